@@ -29,10 +29,11 @@ fn main() {
   let load_position = cgmath::Point3::new(0.0, 512.0, 0.0);
   let load_position = chunk::position::of_world_position(&load_position);
 
+  let thresholds = lod::thresholds(600, 60.0_f32.to_radians(), lod::DEFAULT_MAX_PIXEL_ERROR);
   let mut surroundings_loader = {
     surroundings_loader::new(
       80,
-      lod::THRESHOLDS.iter().map(|&x| x as i32).collect(),
+      thresholds.iter().map(|&x| x as i32).collect(),
     )
   };
   let mut updates = surroundings_loader.updates(load_position.as_pnt());
@@ -60,7 +61,7 @@ fn main() {
     let lod;
     match load_type {
       LoadType::Load => {
-        lod = lod::of_distance(distance as u32);
+        lod = lod::of_distance(distance as u32, &thresholds);
       },
       LoadType::Downgrade => {
         panic!("Downgrading should not happen");
@@ -15,8 +15,16 @@ use std::env;
 fn main() {
   env_logger::init().unwrap();
 
-  let mut args = env::args();
+  let mut args = env::args().peekable();
   args.next().unwrap();
+
+  let mut options = client_lib::run::default_options();
+  if args.peek().map(|s| s == "--capture-every").unwrap_or(false) {
+    args.next();
+    let seconds: f64 = args.next().expect("--capture-every needs a number of seconds").parse().unwrap();
+    options.capture_every_ns = Some((seconds * 1e9) as u64);
+  }
+
   let listen_url = args.next().unwrap_or_else(|| String::from("ipc:///tmp/client.ipc"));
   let server_url = args.next().unwrap_or_else(|| String::from("ipc:///tmp/server.ipc"));
   assert!(args.next().is_none());
@@ -24,5 +32,5 @@ fn main() {
   info!("Sending to {}.", server_url);
   info!("Listening on {}.", listen_url);
 
-  client_lib::run(listen_url.borrow(), server_url.borrow());
+  client_lib::run(listen_url.borrow(), server_url.borrow(), &options);
 }
@@ -27,7 +27,14 @@ pub fn run(listen_url: &str, server_url: &str) {
 
   let server = server::new(&server_url, &listen_url);
 
-  let client = connect_client(&listen_url, &server);
+  let client =
+    match connect_client(&listen_url, &server) {
+      Ok(client) => client,
+      Err(err) => {
+        println!("Couldn't connect: {}", err);
+        return
+      },
+    };
   *client.load_position.lock().unwrap() = Some(cgmath::Point3::new(0.0, 512.0, 0.0));
   let client = &client;
 
@@ -60,8 +67,7 @@ pub fn run(listen_url: &str, server_url: &str) {
           &mut || { server.listen.try() },
           &mut |_| { },
           &mut |_| { },
-          &mut |_| { },
-          &mut |up| { server.talk.tell(&up) },
+          &mut |up| { server.talk.tell(&protocol::ClientToServer::Request(client.secret, up)) },
           &mut |msg| {
             if let client_lib::terrain::Load::Voxels { time_requested: Some(_), .. } = msg {
               *loaded_count.lock().unwrap() += 1;
@@ -81,23 +87,49 @@ pub fn run(listen_url: &str, server_url: &str) {
 
   // View thread returned, so we got a quit event.
   *quit.lock().unwrap() = true;
+  server.talk.tell(&protocol::ClientToServer::Request(client.secret, protocol::Request::Leave(client.id)));
 
   monitor_thread.join();
   update_thread.join();
 }
 
-fn connect_client(listen_url: &str, server: &server::T) -> client::T {
+fn connect_client(listen_url: &str, server: &server::T) -> Result<client::T, protocol::ConnectionError> {
   // TODO: Consider using RPCs to solidify the request-response patterns.
-  server.talk.tell(&protocol::ClientToServer::Init(listen_url.to_owned()));
+  // The dummy client doesn't render anything, but it still loads terrain like a real one would.
+  let polygon_budget = {
+    let budget = client_lib::view::vram_budget::split(client_lib::view::vram_budget::DEFAULT_BYTES);
+    budget.chunks * client_lib::view::terrain_buffers::CHUNK_LENGTH
+  };
+  let capabilities =
+    protocol::Capabilities {
+      max_triangles : std::cmp::min(polygon_budget, std::u32::MAX as usize) as u32,
+      bandwidth     : 8 << 20,
+    };
+  server.talk.tell(
+    &protocol::ClientToServer::Init(protocol::VERSION, listen_url.to_owned(), String::from("dummy"), None, capabilities)
+  );
+  // Pick LODs as if drawing with the default settings, so the dummy loads the same terrain a default client would.
+  let config = client_lib::config::default();
+  let lod_thresholds =
+    client_lib::lod::thresholds(config.window_size.y, config.fov.to_radians(), config.max_pixel_error);
   loop {
     match server.listen.wait() {
-      protocol::ServerToClient::LeaseId(client_id) => {
-        server.talk.tell(&protocol::ClientToServer::AddPlayer(client_id));
+      protocol::ServerToClient::Rejected(err) => {
+        return Err(err)
+      },
+      protocol::ServerToClient::LeaseId(client_id, version, terrain_policy, secret) => {
+        // The server checks this too, but check anyway rather than misread everything that follows.
+        if version != protocol::VERSION {
+          return Err(protocol::ConnectionError::VersionMismatch { server: version, client: protocol::VERSION })
+        }
+        server.talk.tell(&protocol::ClientToServer::Request(secret, protocol::Request::AddPlayer(client_id)));
         let client_id = client_id;
         loop {
           match server.listen.wait() {
             protocol::ServerToClient::PlayerAdded(player_id, position) => {
-              return client::new(client_id, player_id, position);
+              let client =
+                client::new(client_id, secret, player_id, position, terrain_policy, lod_thresholds, polygon_budget);
+              return Ok(client);
             },
             msg => {
               // Ignore other messages in the meantime.
@@ -106,6 +138,10 @@ fn connect_client(listen_url: &str, server: &server::T) -> client::T {
           }
         }
       },
+      protocol::ServerToClient::Queued(position, secret) => {
+        println!("Server is full. Waiting in line to join: number {}", position);
+        server.talk.tell(&protocol::ClientToServer::StillWaiting(secret));
+      },
       msg => {
         // Ignore other messages in the meantime.
         warn!("Ignoring: {:?}", msg);
@@ -0,0 +1,120 @@
+//! How boxed in each terrain vertex is by the voxels right around it, so crevices, valley floors and the corners of
+//! caves get less ambient light. Sky visibility handles the big picture; this only looks one voxel away.
+
+use cgmath::{Point3, Vector3, InnerSpace};
+
+use common::fnv_map;
+use common::voxel;
+
+/// How much of the ambient light a vertex loses when it's completely boxed in.
+const STRENGTH: f32 = 0.7;
+
+/// Directions to every neighboring voxel, diagonals included.
+fn neighbors() -> Vec<Vector3<f32>> {
+  let mut neighbors = Vec::with_capacity(26);
+  for x in -1 .. 2 {
+  for y in -1 .. 2 {
+  for z in -1 .. 2 {
+    if (x, y, z) != (0, 0, 0) {
+      neighbors.push(Vector3::new(x as f32, y as f32, z as f32).normalize());
+    }
+  }}}
+  neighbors
+}
+
+/// How full the voxel containing `point` is, in [0, 1].
+fn solidity(voxels: &voxel::tree::T, point: &Point3<f32>, lg_voxel_size: i16, voxel_size: f32) -> f32 {
+  let bounds =
+    voxel::bounds::new(
+      (point.x / voxel_size).floor() as i32,
+      (point.y / voxel_size).floor() as i32,
+      (point.z / voxel_size).floor() as i32,
+      lg_voxel_size,
+    );
+  match voxels.get(&bounds) {
+    None | Some(&voxel::Volume(voxel::Material::Empty)) => 0.0,
+    Some(&voxel::Volume(_)) => 1.0,
+    // The surface goes through it, so it's partly full.
+    Some(&voxel::Surface(_)) => 0.5,
+  }
+}
+
+/// Ambient occlusion for the vertices of one mesh. Vertices are shared between polygons, so results are cached.
+pub struct T<'a> {
+  voxels        : &'a voxel::tree::T,
+  lg_voxel_size : i16,
+  voxel_size    : f32,
+  neighbors     : Vec<Vector3<f32>>,
+  cache         : fnv_map::T<(u32, u32, u32), f32>,
+}
+
+/// Estimate ambient occlusion against `voxels`, for a mesh made of voxels `2^lg_voxel_size` wide.
+pub fn new<'a>(voxels: &'a voxel::tree::T, lg_voxel_size: i16) -> T<'a> {
+  T {
+    voxels        : voxels,
+    lg_voxel_size : lg_voxel_size,
+    voxel_size    : (1 << lg_voxel_size) as f32,
+    neighbors     : neighbors(),
+    cache         : fnv_map::new(),
+  }
+}
+
+impl<'a> T<'a> {
+  /// The fraction, in [0, 1], of a vertex's ambient light that's blocked by the voxels around it. Neighbors are
+  /// weighted by how directly the vertex faces them; ones behind the surface don't count.
+  pub fn occlusion(&mut self, position: &Point3<f32>, normal: &Vector3<f32>) -> f32 {
+    let key = (position.x.to_bits(), position.y.to_bits(), position.z.to_bits());
+    if let Some(&occlusion) = self.cache.get(&key) {
+      return occlusion
+    }
+
+    // Look from half a voxel off the surface, so the voxel the vertex is in doesn't count against it.
+    let origin = *position + *normal * (0.5 * self.voxel_size);
+    let mut blocked = 0.0;
+    let mut total = 0.0;
+    for direction in &self.neighbors {
+      let weight = direction.dot(*normal);
+      if weight <= 0.0 {
+        continue
+      }
+      let sample = origin + *direction * self.voxel_size;
+      blocked += weight * solidity(self.voxels, &sample, self.lg_voxel_size, self.voxel_size);
+      total += weight;
+    }
+
+    let occlusion = if total > 0.0 { STRENGTH * blocked / total } else { 0.0 };
+    self.cache.insert(key, occlusion);
+    occlusion
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use cgmath::{Point3, Vector3};
+
+  use common::voxel;
+
+  use super::*;
+
+  #[test]
+  fn test_occlusion() {
+    let mut voxels = voxel::tree::new();
+    // A floor at y = 0, with a wall along x = 2.
+    for x in -4 .. 4 {
+    for z in -4 .. 4 {
+      voxels.get_mut_or_create(&voxel::bounds::new(x, -1, z, 0)).data = Some(voxel::Volume(voxel::Material::Stone));
+      for y in 0 .. 4 {
+        let material = if x == 2 { voxel::Material::Stone } else { voxel::Material::Empty };
+        voxels.get_mut_or_create(&voxel::bounds::new(x, y, z, 0)).data = Some(voxel::Volume(material));
+      }
+    }}
+
+    let mut occlusion = new(&voxels, 0);
+    let up = Vector3::new(0.0, 1.0, 0.0);
+    let open = occlusion.occlusion(&Point3::new(-2.0, 0.0, 0.0), &up);
+    let corner = occlusion.occlusion(&Point3::new(1.9, 0.0, 0.0), &up);
+    assert_eq!(open, 0.0);
+    assert!(corner > open);
+    assert!(corner <= STRENGTH);
+  }
+}
@@ -0,0 +1,86 @@
+//! Smoothing normals where chunks meet. A vertex is shaded with the normal of the voxel it comes from. Where a chunk
+//! meets a neighbor at a different LOD, the two sides are made from different voxels, so their lighting doesn't line
+//! up along the seam. Vertices in a chunk's border voxels take their normal from the coarsest loaded surface voxel
+//! they're in instead. Both sides of a border agree on that voxel. A chunk meshed before its coarser neighbor's voxels
+//! arrived keeps its own normals until it's remeshed.
+
+use cgmath::{Point3, Vector3};
+
+use common::voxel;
+
+use greedy_mesh;
+use lod;
+
+/// Whether a point is in the outermost layer of voxels on either side of a chunk's faces. `low` and `high` are the
+/// chunk's corners, counted in voxels `voxel_size` wide.
+fn on_border(point: &Point3<f32>, low: &Point3<i32>, high: &Point3<i32>, voxel_size: f32) -> bool {
+  let near = |p: f32, low: i32, high: i32| {
+    let i = (p / voxel_size).floor() as i32;
+    i == low - 1 || i == low || i == high - 1 || i == high
+  };
+  near(point.x, low.x, high.x) || near(point.y, low.y, high.y) || near(point.z, low.z, high.z)
+}
+
+/// The normal of the coarsest loaded surface voxel that contains `point` and is bigger than `2^lg_voxel_size`, if
+/// there is one. Voxels bigger than the coarsest LOD's aren't meshed, so they aren't looked at.
+fn coarsest_normal(voxels: &voxel::tree::T, point: &Point3<f32>, lg_voxel_size: i16) -> Option<Vector3<f32>> {
+  let max_lg_size = lod::ALL[lod::COUNT - 1].lg_sample_size();
+  for lg_size in (lg_voxel_size + 1 .. max_lg_size + 1).rev() {
+    let size = (lg_size as f32).exp2();
+    let bounds =
+      voxel::bounds::new(
+        (point.x / size).floor() as i32,
+        (point.y / size).floor() as i32,
+        (point.z / size).floor() as i32,
+        lg_size,
+      );
+    if let Some(&voxel::Surface(ref surface)) = voxels.get(&bounds) {
+      return Some(surface.normal.to_float_normal())
+    }
+  }
+  None
+}
+
+/// Reshade the border vertices of a chunk's polygons, made of voxels `2^lg_voxel_size` wide. `low` and `high` are the
+/// chunk's corners, counted in those voxels.
+pub fn smooth(
+  voxels        : &voxel::tree::T,
+  polygons      : &mut [greedy_mesh::Polygon],
+  low           : &Point3<i32>,
+  high          : &Point3<i32>,
+  lg_voxel_size : i16,
+) {
+  let voxel_size = (lg_voxel_size as f32).exp2();
+  let smooth_vertex = |vertex: &Point3<f32>, normal: &mut Vector3<f32>| {
+    if !on_border(vertex, low, high, voxel_size) {
+      return
+    }
+    if let Some(coarse) = coarsest_normal(voxels, vertex, lg_voxel_size) {
+      *normal = coarse;
+    }
+  };
+  for polygon in polygons {
+    smooth_vertex(&polygon.vertices.v1, &mut polygon.normals.v1);
+    smooth_vertex(&polygon.vertices.v2, &mut polygon.normals.v2);
+    smooth_vertex(&polygon.vertices.v3, &mut polygon.normals.v3);
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use cgmath::Point3;
+
+  use super::*;
+
+  #[test]
+  fn test_on_border() {
+    let (low, high) = (Point3::new(0, 0, 0), Point3::new(4, 4, 4));
+    // Voxels are 2 wide, so the chunk's 8 wide; its own outer voxels and the ring around it are on the border.
+    assert!(on_border(&Point3::new(-1.5, 4.0, 4.0), &low, &high, 2.0));
+    assert!(on_border(&Point3::new(1.0, 4.0, 4.0), &low, &high, 2.0));
+    assert!(on_border(&Point3::new(4.0, 4.0, 7.5), &low, &high, 2.0));
+    assert!(on_border(&Point3::new(4.0, 9.0, 4.0), &low, &high, 2.0));
+    assert!(!on_border(&Point3::new(4.0, 4.0, 4.0), &low, &high, 2.0));
+    assert!(!on_border(&Point3::new(2.5, 5.5, 3.0), &low, &high, 2.0));
+  }
+}
@@ -1,16 +1,21 @@
 //! Main Playform client state code.
 
 use cgmath::Point3;
+use collision::Aabb3;
 use num;
 use rand;
 use rand::{Rng, SeedableRng};
 use std::sync::Mutex;
 
+use common::fnv_map;
 use common::id_allocator;
 use common::protocol;
 use common::surroundings_loader;
 
+use chunk;
 use lod;
+use server_clock;
+use snapshots;
 use terrain;
 use view;
 
@@ -21,12 +26,18 @@ const MAX_LOAD_DISTANCE: u32 = 80;
 pub struct T {
   #[allow(missing_docs)]
   pub id                       : protocol::ClientId,
+  /// What to send with every request, so the server knows it's from us.
+  pub secret                   : protocol::Secret,
   /// id for the player in vram
   pub player_id                : view::entity::id::Player,
   /// position of the player in world coordinates
   pub player_position          : Mutex<Point3<f32>>,
   /// the location where we last played a footstep sound
   pub last_footstep            : Mutex<Point3<f32>>,
+  /// The point on the bottom of the player's bounds, under its center.
+  pub player_feet              : Mutex<Point3<f32>>,
+  /// Where the player's feet were when they last left a footprint, if they've touched the ground since teleporting.
+  pub last_footprint           : Mutex<Option<Point3<f32>>>,
   /// world position to center terrain loading around
   pub load_position            : Mutex<Option<Point3<f32>>>,
   #[allow(missing_docs)]
@@ -37,21 +48,48 @@ pub struct T {
   pub surroundings_loader      : Mutex<surroundings_loader::T>,
   #[allow(missing_docs)]
   pub max_load_distance        : u32,
+  /// The most detailed LOD the server lets us load.
+  pub lod_floor                : lod::T,
+  /// The distances at which chunks switch LOD, to keep their screen-space error within the configured budget.
+  pub lod_thresholds           : lod::Thresholds,
   #[allow(missing_docs)]
   pub terrain                  : Mutex<terrain::T>,
   /// The number of terrain requests that are outstanding,
   pub pending_terrain_requests : Mutex<u32>,
   #[allow(missing_docs)]
   pub rng                      : Mutex<rand::XorShiftRng>,
+  /// How server timestamps map to local time.
+  pub server_clock             : Mutex<server_clock::T>,
+  /// Recent positions of other players, to draw them smoothly.
+  pub player_snapshots         : Mutex<snapshots::T<view::entity::id::Player>>,
+  /// Recent positions of mobs, to draw them smoothly.
+  pub mob_snapshots            : Mutex<snapshots::T<view::entity::id::Mob>>,
+  /// Players and mobs being carried by something else, with their last known bounds and rotation. These are drawn
+  /// wherever their parent is drawn, instead of from snapshots, so they don't drift apart.
+  pub attachments              : Mutex<fnv_map::T<protocol::EntityRef, (protocol::Attachment, Aabb3<f32>, f32)>>,
+  /// When (in local ns) the server said it would shut down, if it has.
+  pub server_shutdown          : Mutex<Option<u64>>,
+  /// The player's health, out of `protocol::MAX_HEALTH`.
+  pub health                   : Mutex<u32>,
+  /// The shape and radius of what the player digs out. The server is told whenever these change.
+  pub brush                    : Mutex<(protocol::BrushShape, f32)>,
+  /// The first chunk ids of meshes the view evicted to stay within its VRAM budget, which the terrain should forget
+  /// it loaded.
+  pub evicted_meshes           : Mutex<Vec<view::entity::id::Terrain>>,
+  /// Where each beacon is, so the update thread can check which ones terrain hides.
+  pub beacons                  : Mutex<fnv_map::T<view::entity::id::Beacon, Point3<f32>>>,
+  /// Whether the view's asked for the loaded terrain to be reshaded, e.g. after reloading shaders. The update thread
+  /// passes this on to the terrain, so the view never has to lock it.
+  pub reshade_requested        : Mutex<bool>,
 }
 
-fn load_distance(mut polygon_budget: i32) -> u32 {
+fn load_distance(mut polygon_budget: i32, thresholds: &lod::Thresholds) -> u32 {
   // TODO: This should try to account for VRAM not used on a per-poly basis.
 
   let mut load_distance = 0;
   let mut prev_threshold = 0;
   let mut prev_square = 0;
-  for (i, &threshold) in lod::THRESHOLDS.iter().enumerate() {
+  for (i, &threshold) in thresholds.iter().enumerate() {
     let quality = lod::T(i as u32).edge_samples() as i32;
     let polygons_per_chunk = quality * quality * 4;
     for i in num::iter::range_inclusive(prev_threshold, threshold) {
@@ -90,8 +128,16 @@ fn load_distance(mut polygon_budget: i32) -> u32 {
   load_distance
 }
 
-#[allow(missing_docs)]
-pub fn new(client_id: protocol::ClientId, player_id: view::entity::id::Player, position: Point3<f32>) -> T {
+/// Terrain is loaded as far out as `polygon_budget` polygons reach.
+pub fn new(
+  client_id      : protocol::ClientId,
+  secret         : protocol::Secret,
+  player_id      : view::entity::id::Player,
+  position       : Point3<f32>,
+  terrain_policy : protocol::TerrainPolicy,
+  lod_thresholds : lod::Thresholds,
+  polygon_budget : usize,
+) -> T {
   let mut rng: rand::XorShiftRng = rand::SeedableRng::from_seed([1, 2, 3, 4]);
   let s1 = rng.next_u32();
   let s2 = rng.next_u32();
@@ -99,7 +145,7 @@ pub fn new(client_id: protocol::ClientId, player_id: view::entity::id::Player, p
   let s4 = rng.next_u32();
   rng.reseed([s1, s2, s3, s4]);
 
-  let mut load_distance = load_distance(view::terrain_buffers::POLYGON_BUDGET as i32);
+  let mut load_distance = load_distance(polygon_budget as i32, &lod_thresholds);
 
   if load_distance > MAX_LOAD_DISTANCE {
     info!("load_distance {} capped at {}", load_distance, MAX_LOAD_DISTANCE);
@@ -108,25 +154,51 @@ pub fn new(client_id: protocol::ClientId, player_id: view::entity::id::Player, p
     info!("load_distance {}", load_distance);
   }
 
+  // There's no point loading terrain the server will just tell us to unload.
+  let view_distance = (terrain_policy.view_distance / chunk::WIDTH as f32) as u32;
+  if load_distance > view_distance {
+    info!("load_distance {} capped at the server's view distance {}", load_distance, view_distance);
+    load_distance = view_distance;
+  }
+  info!("Server simulates within {} of players", terrain_policy.simulation_distance);
+
+  let lod_floor = lod::finest_with_lg_sample_size(terrain_policy.min_lg_voxel_size);
+  info!("Server allows voxels down to 2^{}; lod_floor {:?}", terrain_policy.min_lg_voxel_size, lod_floor);
+
   let surroundings_loader = {
     surroundings_loader::new(
       load_distance,
-      lod::THRESHOLDS.iter().map(|&x| x as i32).collect(),
+      lod_thresholds.iter().map(|&x| x as i32).collect(),
     )
   };
 
   T {
     id                       : client_id,
+    secret                   : secret,
     player_id                : player_id,
     player_position          : Mutex::new(position),
     last_footstep            : Mutex::new(position),
+    player_feet              : Mutex::new(position),
+    last_footprint           : Mutex::new(None),
     load_position            : Mutex::new(None),
     terrain_allocator        : Mutex::new(id_allocator::new()),
     grass_allocator          : Mutex::new(id_allocator::new()),
     surroundings_loader      : Mutex::new(surroundings_loader),
     max_load_distance        : load_distance,
-    terrain                  : Mutex::new(terrain::new(load_distance as u32)),
+    lod_floor                : lod_floor,
+    lod_thresholds           : lod_thresholds,
+    terrain                  : Mutex::new(terrain::new(load_distance as u32, lod_floor, lod_thresholds)),
     pending_terrain_requests : Mutex::new(0),
     rng                      : Mutex::new(rng),
+    server_clock             : Mutex::new(server_clock::new()),
+    player_snapshots         : Mutex::new(snapshots::new()),
+    mob_snapshots            : Mutex::new(snapshots::new()),
+    attachments              : Mutex::new(fnv_map::new()),
+    server_shutdown          : Mutex::new(None),
+    health                   : Mutex::new(protocol::MAX_HEALTH),
+    brush                    : Mutex::new((protocol::BrushShape::Sphere, protocol::DEFAULT_BRUSH_RADIUS)),
+    evicted_meshes           : Mutex::new(Vec::new()),
+    beacons                  : Mutex::new(fnv_map::new()),
+    reshade_requested        : Mutex::new(false),
   }
 }
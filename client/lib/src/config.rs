@@ -0,0 +1,289 @@
+//! Client settings: which keys do what, mouse sensitivity, field of view, camera smoothing, vsync, window size and
+//! mode, how long footprints last, how much terrain detail to keep, whether snow settles on grass, how far away
+//! labels can be seen and how much VRAM terrain can use.
+//! Settings are read from a file with one `<setting> <value>` per line, e.g. `key.jump Space` or `fov 75`, and
+//! written back on exit. Missing settings get their defaults; blank lines and lines starting with `#` are ignored.
+
+use cgmath::Vector2;
+use sdl2::keyboard::Keycode;
+use std;
+use std::io::Write;
+
+use common::fnv_map;
+use common::line_file;
+
+use lod;
+use platform;
+use view::vram_budget;
+
+/// Where settings are kept.
+pub const PATH: &'static str = "client.conf";
+
+/// Things keys can be bound to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[allow(missing_docs)]
+pub enum Action {
+  MoveLeft,
+  MoveRight,
+  MoveForward,
+  MoveBack,
+  Jump,
+  TurnLeft,
+  TurnRight,
+  LookUp,
+  LookDown,
+  ToggleHud,
+  ToggleSunControl,
+  ReloadShaders,
+  LessGrass,
+  MoreGrass,
+  PinLoadPosition,
+  CycleBrushShape,
+  ShrinkBrush,
+  GrowBrush,
+  PlaceRock,
+  Throw,
+  Screenshot,
+  Sprint,
+  NarrowFov,
+  WidenFov,
+  ToggleCameraSmoothing,
+  CycleWindowMode,
+  ToggleWireframe,
+  ToggleMeshBounds,
+  TogglePhysicsBounds,
+  ToggleGrass,
+  ToggleFrameTimes,
+  ToggleViewQueue,
+}
+
+/// Every action, with its name in the settings file and the key it's bound to by default.
+const ACTIONS: [(Action, &'static str, Keycode); 32] = [
+  (Action::MoveLeft              , "move_left"               , Keycode::A),
+  (Action::MoveRight             , "move_right"              , Keycode::D),
+  (Action::MoveForward           , "move_forward"            , Keycode::W),
+  (Action::MoveBack              , "move_back"               , Keycode::S),
+  (Action::Jump                  , "jump"                    , Keycode::Space),
+  (Action::TurnLeft              , "turn_left"               , Keycode::Left),
+  (Action::TurnRight             , "turn_right"              , Keycode::Right),
+  (Action::LookUp                , "look_up"                 , Keycode::Up),
+  (Action::LookDown              , "look_down"               , Keycode::Down),
+  (Action::ToggleHud             , "toggle_hud"              , Keycode::H),
+  (Action::ToggleSunControl      , "toggle_sun_control"      , Keycode::M),
+  (Action::ReloadShaders         , "reload_shaders"          , Keycode::R),
+  (Action::LessGrass             , "less_grass"              , Keycode::LeftBracket),
+  (Action::MoreGrass             , "more_grass"              , Keycode::RightBracket),
+  (Action::PinLoadPosition       , "pin_load_position"       , Keycode::P),
+  (Action::CycleBrushShape       , "cycle_brush_shape"       , Keycode::B),
+  (Action::ShrinkBrush           , "shrink_brush"            , Keycode::Minus),
+  (Action::GrowBrush             , "grow_brush"              , Keycode::Equals),
+  (Action::PlaceRock             , "place_rock"              , Keycode::G),
+  (Action::Throw                 , "throw"                   , Keycode::T),
+  (Action::Screenshot            , "screenshot"              , Keycode::F12),
+  (Action::Sprint                , "sprint"                  , Keycode::LShift),
+  (Action::NarrowFov             , "narrow_fov"              , Keycode::Comma),
+  (Action::WidenFov              , "widen_fov"               , Keycode::Period),
+  (Action::ToggleCameraSmoothing , "toggle_camera_smoothing" , Keycode::C),
+  (Action::CycleWindowMode       , "cycle_window_mode"       , Keycode::F11),
+  (Action::ToggleWireframe       , "toggle_wireframe"        , Keycode::F2),
+  (Action::ToggleMeshBounds      , "toggle_mesh_bounds"      , Keycode::F3),
+  (Action::TogglePhysicsBounds   , "toggle_physics_bounds"   , Keycode::F4),
+  (Action::ToggleGrass           , "toggle_grass"            , Keycode::F5),
+  (Action::ToggleFrameTimes      , "toggle_frame_times"      , Keycode::F6),
+  (Action::ToggleViewQueue       , "toggle_view_queue"       , Keycode::F7),
+];
+
+/// Client settings.
+pub struct T {
+  keys                  : fnv_map::T<Keycode, Action>,
+  /// Scales how far the camera turns when the mouse moves.
+  pub mouse_sensitivity : f32,
+  /// Vertical field of view, in degrees.
+  pub fov               : f32,
+  /// Roughly how many seconds the camera takes to catch up with the player. 0 turns smoothing off.
+  pub camera_smoothing  : f32,
+  /// How many degrees wider the field of view gets while sprinting.
+  pub sprint_fov_kick   : f32,
+  #[allow(missing_docs)]
+  pub vsync             : bool,
+  /// Width and height of the window, in points (which are bigger than pixels on high-DPI displays).
+  pub window_size       : Vector2<u32>,
+  /// Whether the window starts windowed, borderless or fullscreen.
+  pub window_mode       : platform::WindowMode,
+  /// How many seconds footprints take to fade away. 0 turns them off.
+  pub footprint_fade    : f32,
+  /// How far (in pixels) terrain can be drawn from where it'd be at full detail. Smaller keeps more detail further out.
+  pub max_pixel_error   : f32,
+  /// Whether grass turns white while it's snowing.
+  pub snow_cover        : bool,
+  /// How far away (in world units) beacon labels can be seen.
+  pub nameplate_distance : f32,
+  /// How many megabytes of VRAM terrain and grass can use between them.
+  pub vram_budget_mb     : u32,
+}
+
+#[allow(missing_docs)]
+pub fn default() -> T {
+  let mut keys = fnv_map::new();
+  for &(action, _, key) in ACTIONS.iter() {
+    keys.insert(key, action);
+  }
+  T {
+    keys               : keys,
+    mouse_sensitivity  : 1.0,
+    fov                : 60.0,
+    camera_smoothing   : 0.1,
+    sprint_fov_kick    : 8.0,
+    vsync              : true,
+    window_size        : Vector2::new(800, 600),
+    window_mode        : platform::WindowMode::Windowed,
+    footprint_fade     : 20.0,
+    max_pixel_error    : lod::DEFAULT_MAX_PIXEL_ERROR,
+    snow_cover         : true,
+    nameplate_distance : 512.0,
+    vram_budget_mb     : (vram_budget::DEFAULT_BYTES / 1_000_000) as u32,
+  }
+}
+
+fn parse<X: std::str::FromStr>(value: Option<&str>, setting: &str) -> Result<X, String> {
+  match value {
+    None => Err(format!("Missing value for {}", setting)),
+    Some(s) => s.parse().map_err(|_| format!("Invalid value for {}: {:?}", setting, s)),
+  }
+}
+
+impl T {
+  /// The action bound to a key, if there is one.
+  pub fn action(&self, key: Keycode) -> Option<Action> {
+    self.keys.get(&key).cloned()
+  }
+
+  /// Bind `key` to `action`, in place of whatever key it was bound to before.
+  pub fn bind(&mut self, key: Keycode, action: Action) {
+    self.keys.retain(|_, &mut a| a != action);
+    self.keys.insert(key, action);
+  }
+
+  fn key(&self, action: Action) -> Option<Keycode> {
+    self.keys.iter().find(|&(_, &a)| a == action).map(|(&key, _)| key)
+  }
+
+  /// Apply one line of a settings file.
+  fn apply_line(&mut self, line: &str) -> Result<(), String> {
+    let mut words = line.split_whitespace();
+    let setting = words.next().unwrap();
+    if setting.starts_with("key.") {
+      let name = &setting["key.".len() ..];
+      let action =
+        match ACTIONS.iter().find(|&&(_, n, _)| n == name) {
+          None => return Err(format!("Unknown action: {:?}", name)),
+          Some(&(action, _, _)) => action,
+        };
+      // Key names can have spaces in them (e.g. "Left Shift").
+      let key: Vec<&str> = words.by_ref().collect();
+      let key = key.join(" ");
+      match Keycode::from_name(&key) {
+        None => return Err(format!("Unknown key: {:?}", key)),
+        Some(key) => self.bind(key, action),
+      }
+      return Ok(())
+    }
+
+    match setting {
+      "mouse_sensitivity" => self.mouse_sensitivity = try!(parse(words.next(), setting)),
+      "fov" => self.fov = try!(parse(words.next(), setting)),
+      "camera_smoothing" => self.camera_smoothing = try!(parse(words.next(), setting)),
+      "sprint_fov_kick" => self.sprint_fov_kick = try!(parse(words.next(), setting)),
+      "vsync" => self.vsync = try!(parse(words.next(), setting)),
+      "footprint_fade" => self.footprint_fade = try!(parse(words.next(), setting)),
+      "max_pixel_error" => self.max_pixel_error = try!(parse(words.next(), setting)),
+      "snow_cover" => self.snow_cover = try!(parse(words.next(), setting)),
+      "nameplate_distance" => self.nameplate_distance = try!(parse(words.next(), setting)),
+      "vram_budget_mb" => self.vram_budget_mb = try!(parse(words.next(), setting)),
+      "window_mode" => self.window_mode = try!(parse(words.next(), setting)),
+      "window_size" => {
+        let w = try!(parse(words.next(), setting));
+        let h = try!(parse(words.next(), setting));
+        self.window_size = Vector2::new(w, h);
+      },
+      _ => return Err(format!("Unknown setting: {:?}", setting)),
+    }
+    match words.next() {
+      None => Ok(()),
+      Some(word) => Err(format!("Unexpected value for {}: {:?}", setting, word)),
+    }
+  }
+
+  /// Write these settings out, in the format `load` reads.
+  pub fn save(&self, path: &std::path::Path) -> std::io::Result<()> {
+    let mut file = try!(std::fs::File::create(path));
+    try!(writeln!(file, "# Client settings. This file is rewritten when the client exits."));
+    try!(writeln!(file, "mouse_sensitivity {}", self.mouse_sensitivity));
+    try!(writeln!(file, "fov {}", self.fov));
+    try!(writeln!(file, "camera_smoothing {}", self.camera_smoothing));
+    try!(writeln!(file, "sprint_fov_kick {}", self.sprint_fov_kick));
+    try!(writeln!(file, "vsync {}", self.vsync));
+    try!(writeln!(file, "window_size {} {}", self.window_size.x, self.window_size.y));
+    try!(writeln!(file, "window_mode {}", self.window_mode.name()));
+    try!(writeln!(file, "footprint_fade {}", self.footprint_fade));
+    try!(writeln!(file, "max_pixel_error {}", self.max_pixel_error));
+    try!(writeln!(file, "snow_cover {}", self.snow_cover));
+    try!(writeln!(file, "nameplate_distance {}", self.nameplate_distance));
+    try!(writeln!(file, "vram_budget_mb {}", self.vram_budget_mb));
+    for &(action, name, _) in ACTIONS.iter() {
+      if let Some(key) = self.key(action) {
+        try!(writeln!(file, "key.{} {}", name, key.name()));
+      }
+    }
+    Ok(())
+  }
+}
+
+/// Load settings from a `line_file`. Anything it doesn't set, or everything if it isn't there, keeps its default.
+pub fn load(path: &std::path::Path) -> T {
+  let mut config = default();
+  line_file::load(path, "settings", |line| config.apply_line(line));
+  config
+}
+
+#[cfg(test)]
+mod test {
+  use sdl2::keyboard::Keycode;
+
+  use platform;
+
+  use super::*;
+
+  #[test]
+  fn test_apply_line() {
+    let mut config = default();
+    config.apply_line("fov 75").unwrap();
+    assert_eq!(config.fov, 75.0);
+    config.apply_line("camera_smoothing 0").unwrap();
+    assert_eq!(config.camera_smoothing, 0.0);
+    config.apply_line("window_size 1024 768").unwrap();
+    assert_eq!((config.window_size.x, config.window_size.y), (1024, 768));
+    config.apply_line("footprint_fade 0").unwrap();
+    assert_eq!(config.footprint_fade, 0.0);
+    config.apply_line("max_pixel_error 2.5").unwrap();
+    assert_eq!(config.max_pixel_error, 2.5);
+    config.apply_line("snow_cover false").unwrap();
+    assert!(!config.snow_cover);
+    config.apply_line("nameplate_distance 128").unwrap();
+    assert_eq!(config.nameplate_distance, 128.0);
+    config.apply_line("vram_budget_mb 512").unwrap();
+    assert_eq!(config.vram_budget_mb, 512);
+    config.apply_line("window_mode borderless").unwrap();
+    assert_eq!(config.window_mode, platform::WindowMode::Borderless);
+    config.apply_line("key.jump Left Shift").unwrap();
+    assert_eq!(config.action(Keycode::LShift), Some(Action::Jump));
+    // The old key is freed up.
+    assert_eq!(config.action(Keycode::Space), None);
+
+    assert!(config.apply_line("fov wide").is_err());
+    assert!(config.apply_line("window_size 1024").is_err());
+    assert!(config.apply_line("window_mode maximized").is_err());
+    assert!(config.apply_line("key.fly F").is_err());
+    assert!(config.apply_line("vsync true please").is_err());
+  }
+}
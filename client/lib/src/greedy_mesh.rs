@@ -0,0 +1,272 @@
+//! Merge flat, horizontal runs of terrain polygons into fewer, larger ones.
+//! Dual contouring a flat plain produces a regular grid of identical quads (two triangles each). This finds those
+//! grid cells and greedily covers them with as few rectangles as possible.
+
+use cgmath::{Point3, Vector3};
+use std;
+
+use common::fnv_map;
+use common::fnv_set;
+use common::voxel;
+
+use terrain_mesh::{Triangle, tri};
+
+/// A triangle from the isosurface extractor.
+#[derive(Debug, Clone, Copy)]
+pub struct Polygon {
+  #[allow(missing_docs)]
+  pub vertices : Triangle<Point3<f32>>,
+  #[allow(missing_docs)]
+  pub normals  : Triangle<Vector3<f32>>,
+  #[allow(missing_docs)]
+  pub material : voxel::Material,
+}
+
+// Normals closer to vertical than this count as vertical.
+const NORMAL_EPSILON: f32 = 1e-4;
+// Cell offsets are compared after quantizing to this fraction of a cell.
+const OFFSET_STEPS: f32 = 1024.0;
+
+// Everything a set of cells must share to be merged together.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct Plane {
+  y        : u32,
+  facing_up: bool,
+  material : i32,
+  width    : u32,
+  depth    : u32,
+  x_offset : i32,
+  z_offset : i32,
+}
+
+// A grid cell: its plane, and x and z indices within that plane.
+type Half = (Plane, i64, i64);
+
+fn vertical_facing(n: &Vector3<f32>) -> Option<bool> {
+  if n.x.abs() < NORMAL_EPSILON && n.z.abs() < NORMAL_EPSILON && n.y != 0.0 {
+    Some(n.y > 0.0)
+  } else {
+    None
+  }
+}
+
+/// Identify the grid cell that a flat right triangle is half of, and the corner it leaves out.
+fn classify(polygon: &Polygon) -> Option<(Half, usize)> {
+  let v = [polygon.vertices.v1, polygon.vertices.v2, polygon.vertices.v3];
+  if v[0].y != v[1].y || v[0].y != v[2].y {
+    return None
+  }
+  let facing_up = match vertical_facing(&polygon.normals.v1) { None => return None, Some(up) => up };
+  for n in &[polygon.normals.v2, polygon.normals.v3] {
+    if vertical_facing(n) != Some(facing_up) {
+      return None
+    }
+  }
+
+  let min_x = v.iter().fold(std::f32::INFINITY, |m, p| m.min(p.x));
+  let max_x = v.iter().fold(std::f32::NEG_INFINITY, |m, p| m.max(p.x));
+  let min_z = v.iter().fold(std::f32::INFINITY, |m, p| m.min(p.z));
+  let max_z = v.iter().fold(std::f32::NEG_INFINITY, |m, p| m.max(p.z));
+  let width = max_x - min_x;
+  let depth = max_z - min_z;
+  if width <= 0.0 || depth <= 0.0 {
+    return None
+  }
+
+  // Every vertex has to be a corner of the bounding rectangle. Corners are numbered (min, min), (max, min),
+  // (max, max), (min, max), so opposite corners differ by 2.
+  let mut corners = [false; 4];
+  for p in &v {
+    let corner =
+      match (p.x == min_x, p.x == max_x, p.z == min_z, p.z == max_z) {
+        (true, _, true, _) => 0,
+        (_, true, true, _) => 1,
+        (_, true, _, true) => 2,
+        (true, _, _, true) => 3,
+        _ => return None,
+      };
+    corners[corner] = true;
+  }
+  let missing =
+    match corners.iter().position(|&c| !c) {
+      None => return None,
+      Some(missing) => missing,
+    };
+
+  let ix = (min_x / width).floor();
+  let iz = (min_z / depth).floor();
+  let plane =
+    Plane {
+      y         : v[0].y.to_bits(),
+      facing_up : facing_up,
+      material  : polygon.material as i32,
+      width     : width.to_bits(),
+      depth     : depth.to_bits(),
+      x_offset  : ((min_x / width - ix) * OFFSET_STEPS).round() as i32,
+      z_offset  : ((min_z / depth - iz) * OFFSET_STEPS).round() as i32,
+    };
+  Some(((plane, ix as i64, iz as i64), missing))
+}
+
+/// Two triangles covering the rectangle [x0, x1] * [z0, z1], wound to face up or down.
+fn rectangle(
+  template : &Polygon,
+  y        : f32,
+  facing_up: bool,
+  x0: f32, x1: f32,
+  z0: f32, z1: f32,
+) -> [Polygon; 2] {
+  let a = Point3::new(x0, y, z0);
+  let b = Point3::new(x0, y, z1);
+  let c = Point3::new(x1, y, z1);
+  let d = Point3::new(x1, y, z0);
+  let n = template.normals.v1;
+  let normals = tri(n, n, n);
+  let polygon = |v1, v2, v3| {
+    let vertices = if facing_up { tri(v1, v2, v3) } else { tri(v1, v3, v2) };
+    Polygon {
+      vertices : vertices,
+      normals  : normals,
+      material : template.material,
+    }
+  };
+  [polygon(a, b, c), polygon(a, c, d)]
+}
+
+/// Merge coplanar horizontal grid cells among `polygons`. Polygons for which `mergeable` returns false (e.g. because
+/// other things are placed per polygon) are left alone, as is anything that isn't part of a flat grid.
+/// Merged rectangles can leave T-junctions with the geometry around them.
+pub fn merge_flat<Mergeable>(polygons: Vec<Polygon>, mut mergeable: Mergeable) -> Vec<Polygon> where
+  Mergeable: FnMut(voxel::Material) -> bool,
+{
+  let mut r = Vec::with_capacity(polygons.len());
+
+  // Pair up triangles into full cells.
+  let mut halves: fnv_map::T<Half, (usize, Polygon)> = fnv_map::new();
+  let mut cells: fnv_map::T<Plane, fnv_set::T<(i64, i64)>> = fnv_map::new();
+  let mut templates: fnv_map::T<Plane, Polygon> = fnv_map::new();
+  for polygon in polygons {
+    let classified = if mergeable(polygon.material) { classify(&polygon) } else { None };
+    let (half, missing) =
+      match classified {
+        None => {
+          r.push(polygon);
+          continue
+        },
+        Some(x) => x,
+      };
+    let complete =
+      match halves.get(&half) {
+        Some(&(other_missing, _)) => other_missing == (missing + 2) % 4,
+        None => false,
+      };
+    if complete {
+      halves.remove(&half);
+      let (plane, ix, iz) = half;
+      cells.entry(plane).or_insert_with(fnv_set::new).insert((ix, iz));
+      templates.entry(plane).or_insert(polygon);
+    } else if let Some((_, unpaired)) = halves.insert(half, (missing, polygon)) {
+      r.push(unpaired);
+    }
+  }
+  r.extend(halves.into_iter().map(|(_, (_, polygon))| polygon));
+
+  // Greedily cover each plane's cells with rectangles: grow along x, then along z as long as whole rows fit.
+  for (plane, mut cells) in cells {
+    let template = templates[&plane];
+    let width = f32::from_bits(plane.width);
+    let depth = f32::from_bits(plane.depth);
+    let x_offset = plane.x_offset as f32 / OFFSET_STEPS;
+    let z_offset = plane.z_offset as f32 / OFFSET_STEPS;
+    let y = f32::from_bits(plane.y);
+
+    let mut sorted: Vec<(i64, i64)> = cells.iter().map(|&(x, z)| (z, x)).collect();
+    sorted.sort();
+    for (z0, x0) in sorted {
+      if !cells.contains(&(x0, z0)) {
+        continue
+      }
+      let mut x1 = x0 + 1;
+      while cells.contains(&(x1, z0)) {
+        x1 += 1;
+      }
+      let mut z1 = z0 + 1;
+      while (x0 .. x1).all(|x| cells.contains(&(x, z1))) {
+        z1 += 1;
+      }
+      for z in z0 .. z1 {
+        for x in x0 .. x1 {
+          cells.remove(&(x, z));
+        }
+      }
+
+      let rect =
+        rectangle(
+          &template,
+          y,
+          plane.facing_up,
+          (x0 as f32 + x_offset) * width, (x1 as f32 + x_offset) * width,
+          (z0 as f32 + z_offset) * depth, (z1 as f32 + z_offset) * depth,
+        );
+      r.extend_from_slice(&rect);
+    }
+  }
+
+  r
+}
+
+#[cfg(test)]
+mod test {
+  use cgmath::{Point3, Vector3};
+
+  use common::voxel;
+
+  use terrain_mesh::tri;
+
+  use super::*;
+
+  fn flat_grid(n: i32) -> Vec<Polygon> {
+    let up = Vector3::new(0.0, 1.0, 0.0);
+    let mut polygons = Vec::new();
+    for x in 0 .. n {
+    for z in 0 .. n {
+      let (x, z) = (x as f32 + 0.5, z as f32 + 0.5);
+      let a = Point3::new(x, 3.0, z);
+      let b = Point3::new(x, 3.0, z + 1.0);
+      let c = Point3::new(x + 1.0, 3.0, z + 1.0);
+      let d = Point3::new(x + 1.0, 3.0, z);
+      for &(v1, v2, v3) in &[(a, b, c), (a, c, d)] {
+        polygons.push(Polygon {
+          vertices : tri(v1, v2, v3),
+          normals  : tri(up, up, up),
+          material : voxel::Material::Stone,
+        });
+      }
+    }}
+    polygons
+  }
+
+  fn area(polygons: &[Polygon]) -> f32 {
+    polygons.iter().map(|p| {
+      let v = &p.vertices;
+      let cross = (v.v2 - v.v1).cross(v.v3 - v.v1);
+      assert!(cross.y > 0.0);
+      cross.y / 2.0
+    }).sum()
+  }
+
+  #[test]
+  fn test_flat_grid_becomes_one_rectangle() {
+    let polygons = flat_grid(8);
+    let merged = merge_flat(polygons, |_| true);
+    assert_eq!(merged.len(), 2);
+    assert_eq!(area(&merged), 64.0);
+  }
+
+  #[test]
+  fn test_unmergeable_is_untouched() {
+    let polygons = flat_grid(4);
+    let merged = merge_flat(polygons, |_| false);
+    assert_eq!(merged.len(), 32);
+  }
+}
@@ -1,23 +1,324 @@
-//! HUD initialization code.
+//! The HUD: the cursor, meters and notifications drawn over the world. It's laid out afresh every frame with
+//! `hud_layout`, from state the view keeps here and state the client keeps.
 
-use cgmath::Point2;
+use cgmath::{Point2, Point3, Vector2};
+use std::cmp;
+use std::collections::VecDeque;
 
 use common::color::Color4;
+use common::protocol;
 
+use client;
+use hud_layout;
+use hud_layout::{Anchor, Direction, Widget};
 use vertex::{ColoredVertex};
 use view;
 
+/// The most vertices the HUD can have (enough for 700 squares). Anything past this isn't drawn.
+pub const MAX_VERTICES: usize = 700 * 6;
+/// How long the cursor stays red after the server refuses an edit.
+const EDIT_DENIED_FLASH_NS: u64 = 1_000_000_000;
+/// How long notifications stay on the screen.
+const NOTIFICATION_NS: u64 = 6_000_000_000;
+/// The most notifications shown at once; older ones make way for newer ones.
+const MAX_NOTIFICATIONS: usize = 3;
+/// Notifications longer than this many characters are cut short.
+const NOTIFICATION_CHARS: usize = 40;
+/// How tall text in the HUD is.
+const TEXT_HEIGHT: f32 = 0.04;
+/// Digits are drawn as seven-segment displays, one square per segment.
+pub const VERTICES_PER_DIGIT: usize = 7 * 6;
+
+/// Which segments (a through g, clockwise from the top, then the middle) are lit for each digit.
+const SEGMENTS: [u8; 10] = [
+  0b0111111, 0b0000110, 0b1011011, 0b1001111, 0b1100110,
+  0b1101101, 0b1111101, 0b0000111, 0b1111111, 0b1101111,
+];
+
+/// Which segments are lit for a character. Lowercase and uppercase letters look the same, and characters that can't be
+/// made out on a seven-segment display (e.g. M, W, X) are blank.
+fn glyph(c: char) -> u8 {
+  match c.to_ascii_uppercase() {
+    '0' ... '9' => SEGMENTS[c as usize - '0' as usize],
+    'A' => 0b1110111,
+    'B' => 0b1111100,
+    'C' => 0b0111001,
+    'D' => 0b1011110,
+    'E' => 0b1111001,
+    'F' => 0b1110001,
+    'G' => 0b0111101,
+    'H' => 0b1110110,
+    'I' => 0b0110000,
+    'J' => 0b0011110,
+    'L' => 0b0111000,
+    'N' => 0b1010100,
+    'O' => 0b0111111,
+    'P' => 0b1110011,
+    'Q' => 0b1100111,
+    'R' => 0b1010000,
+    'S' => 0b1101101,
+    'T' => 0b1111000,
+    'U' | 'V' => 0b0111110,
+    'Y' => 0b1101110,
+    'Z' => 0b1011011,
+    '-' => 0b1000000,
+    '_' => 0b0001000,
+    _ => 0,
+  }
+}
+
+/// The corners of each segment (a through g) of a seven-segment character `height` tall, relative to its bottom-left.
+fn segments(height: f32) -> [(Point2<f32>, Point2<f32>); 7] {
+  let (w, h, t) = (height / 2.0, height / 2.0, height / 8.0);
+  [
+    // a, b, c, d, e, f, g
+    (Point2 { x: 0.0, y: 2.0 * h - t }, Point2 { x: w, y: 2.0 * h }),
+    (Point2 { x: w - t, y: h }, Point2 { x: w, y: 2.0 * h }),
+    (Point2 { x: w - t, y: 0.0 }, Point2 { x: w, y: h }),
+    (Point2 { x: 0.0, y: 0.0 }, Point2 { x: w, y: t }),
+    (Point2 { x: 0.0, y: 0.0 }, Point2 { x: t, y: h }),
+    (Point2 { x: 0.0, y: h }, Point2 { x: t, y: 2.0 * h }),
+    (Point2 { x: 0.0, y: h - t / 2.0 }, Point2 { x: w, y: h + t / 2.0 }),
+  ]
+}
+
+/// Vertices for a seven-segment digit whose bottom-left corner is at `low`.
+fn digit(vertices: &mut Vec<ColoredVertex>, low: Point2<f32>, height: f32, digit: Option<u32>, color: Color4<f32>) {
+  let lit = digit.map(|d| SEGMENTS[d as usize]).unwrap_or(0);
+  for (i, &(min, max)) in segments(height).iter().enumerate() {
+    let (min, max) =
+      if lit & (1 << i) == 0 {
+        // Degenerate squares, so nothing gets drawn.
+        (low, low)
+      } else {
+        (Point2 { x: low.x + min.x, y: low.y + min.y }, Point2 { x: low.x + max.x, y: low.y + max.y })
+      };
+    vertices.extend(ColoredVertex::square(min, max, color).iter().cloned());
+  }
+}
+
+/// Vertices for a number, centered at `center`, in a field `max_digits` wide. The field's always the same number of
+/// vertices, so it can be updated in place; `None` leaves it blank.
+pub fn number(
+  vertices: &mut Vec<ColoredVertex>,
+  max_digits: usize,
+  n: Option<u32>,
+  center: Point2<f32>,
+  height: f32,
+  color: Color4<f32>,
+) {
+  let mut digits = Vec::with_capacity(max_digits);
+  if let Some(n) = n {
+    // Numbers too long for the field show as all nines.
+    let mut n = cmp::min(n, 10u32.pow(max_digits as u32) - 1);
+    loop {
+      digits.push(n % 10);
+      n /= 10;
+      if n == 0 {
+        break
+      }
+    }
+  }
+  digits.reverse();
+
+  let advance = height * 0.75;
+  let left = center.x - advance * digits.len() as f32 / 2.0;
+  for i in 0 .. max_digits {
+    let low = Point2 { x: left + advance * i as f32, y: center.y - height / 2.0 };
+    digit(vertices, low, height, digits.get(i).cloned(), color);
+  }
+}
+
+/// Vertices for a line of seven-segment text whose bottom-left corner is at `low`. Only lit segments get vertices.
+pub fn text(vertices: &mut Vec<ColoredVertex>, text: &str, low: Point2<f32>, height: f32, color: Color4<f32>) {
+  let advance = height * 0.75;
+  for (i, c) in text.chars().enumerate() {
+    let lit = glyph(c);
+    let x = low.x + advance * i as f32;
+    for (j, &(min, max)) in segments(height).iter().enumerate() {
+      if lit & (1 << j) != 0 {
+        let min = Point2 { x: x + min.x, y: low.y + min.y };
+        let max = Point2 { x: x + max.x, y: low.y + max.y };
+        vertices.extend(ColoredVertex::square(min, max, color).iter().cloned());
+      }
+    }
+  }
+}
+
+/// How wide a line of `chars` seven-segment characters `height` tall is.
+pub fn text_width(chars: usize, height: f32) -> f32 {
+  if chars == 0 {
+    0.0
+  } else {
+    // Characters are half as wide as they're tall, with a quarter of their height between them.
+    height * (0.75 * chars as f32 - 0.25)
+  }
+}
+
+/// The HUD state the view keeps.
+pub struct T {
+  mining_progress   : Option<f32>,
+  /// When the cursor should stop showing that an edit was refused, if it's showing that.
+  edit_denied_until : Option<u64>,
+  /// Notifications on the screen, oldest first, with when (in local ns) they go away.
+  notifications     : VecDeque<(String, u64)>,
+  /// How many vertices are in the buffer. Ones past what the HUD needs now are blanked.
+  uploaded          : usize,
+}
+
+#[allow(missing_docs)]
+pub fn new() -> T {
+  T {
+    mining_progress   : None,
+    edit_denied_until : None,
+    notifications     : VecDeque::new(),
+    uploaded          : 0,
+  }
+}
+
+fn backing_color() -> Color4<f32> {
+  Color4::of_rgba(0.0, 0.0, 0.0, 0.5)
+}
+
+fn text_color() -> Color4<f32> {
+  Color4::of_rgba(0.9, 0.9, 0.9, 0.9)
+}
+
+fn cursor_color(denied: bool) -> Color4<f32> {
+  if denied {
+    Color4::of_rgba(0.8, 0.1, 0.1, 0.9)
+  } else {
+    Color4::of_rgba(0.0, 0.0, 0.0, 0.75)
+  }
+}
+
+fn brush_name(shape: protocol::BrushShape) -> &'static str {
+  match shape {
+    protocol::BrushShape::Sphere => "sphere",
+    protocol::BrushShape::Cube => "cube",
+    protocol::BrushShape::Cylinder => "cylinder",
+  }
+}
+
+/// Lay out the HUD for a player with `health` digging with `brush`.
+fn layout(hud: &T, health: u32, brush: (protocol::BrushShape, f32), now: u64) -> hud_layout::T {
+  let mut layout = hud_layout::new();
+  let zero = Vector2::new(0.0, 0.0);
+
+  let denied = hud.edit_denied_until.map(|until| now < until).unwrap_or(false);
+  layout.add(Anchor::Center, zero, Widget::Panel(Vector2::new(0.04, 0.04), cursor_color(denied)));
+
+  // Mining progress goes just below the cursor.
+  if let Some(progress) = hud.mining_progress {
+    layout.add(
+      Anchor::Center,
+      Vector2::new(0.0, -0.055),
+      hud_layout::bar(Vector2::new(0.2, 0.01), progress, Color4::of_rgba(0.9, 0.8, 0.6, 0.9), backing_color()),
+    );
+  }
+
+  layout.add(
+    Anchor::BottomLeft,
+    zero,
+    hud_layout::framed(0.015, backing_color(), hud_layout::stack(Direction::Across, 0.02, vec!(
+      hud_layout::label("hp", TEXT_HEIGHT, text_color()),
+      hud_layout::bar(
+        Vector2::new(0.4, TEXT_HEIGHT * 0.75),
+        health as f32 / protocol::MAX_HEALTH as f32,
+        Color4::of_rgba(0.8, 0.1, 0.1, 0.9),
+        Color4::of_rgba(0.2, 0.0, 0.0, 0.6),
+      ),
+      hud_layout::label(&health.to_string(), TEXT_HEIGHT, text_color()),
+    ))),
+  );
+
+  // The brush shapes are a hotbar, with the brush's radius at the end.
+  let (shape, radius) = brush;
+  let mut slots: Vec<Widget> =
+    [protocol::BrushShape::Sphere, protocol::BrushShape::Cube, protocol::BrushShape::Cylinder].iter()
+    .map(|&s| {
+      let backing = if s == shape { Color4::of_rgba(0.9, 0.8, 0.6, 0.6) } else { backing_color() };
+      hud_layout::framed(0.015, backing, hud_layout::label(brush_name(s), TEXT_HEIGHT, text_color()))
+    })
+    .collect();
+  slots.push(
+    hud_layout::framed(0.015, backing_color(), hud_layout::label(&format!("r{}", radius), TEXT_HEIGHT, text_color()))
+  );
+  layout.add(Anchor::Bottom, zero, hud_layout::stack(Direction::Across, 0.01, slots));
+
+  if !hud.notifications.is_empty() {
+    let notifications =
+      hud.notifications.iter()
+      .map(|&(ref text, _)| {
+        let text: String = text.chars().take(NOTIFICATION_CHARS).collect();
+        hud_layout::framed(0.015, backing_color(), hud_layout::label(&text, TEXT_HEIGHT, text_color()))
+      })
+      .collect();
+    layout.add(Anchor::Top, zero, hud_layout::stack(Direction::Down, 0.01, notifications));
+  }
+
+  layout
+}
+
 /// Add HUD data into `view`.
 pub fn make_hud<'a, 'b:'a>(view: &'a mut view::T<'b>) {
-  let cursor_color = Color4::of_rgba(0.0, 0.0, 0.0, 0.75);
-
-  let triangles: Vec<_> =
-    ColoredVertex::square(
-      Point2 { x: -0.02, y: -0.02 },
-      Point2 { x:  0.02, y:  0.02 },
-      cursor_color
-    ).iter().cloned().collect();
+  let triangles = view.frame_arena.hud_vertices.get();
+  // Degenerate triangles, so nothing gets drawn until the HUD's first laid out.
+  triangles.resize(MAX_VERTICES, blank_vertex());
 
   view.hud_triangles.bind(&mut view.gl);
-  view.hud_triangles.push(&mut view.gl, triangles.as_ref());
+  view.hud_triangles.push(&mut view.gl, triangles);
+  view.hud.uploaded = MAX_VERTICES;
+}
+
+fn blank_vertex() -> ColoredVertex {
+  ColoredVertex { position: Point3::new(0.0, 0.0, 0.0), color: Color4::of_rgba(0.0, 0.0, 0.0, 0.0) }
+}
+
+/// Show how far through digging something the player is, or hide the progress bar.
+pub fn set_mining_progress<'a, 'b:'a>(view: &'a mut view::T<'b>, progress: Option<f32>) {
+  view.hud.mining_progress = progress;
+}
+
+/// Turn the cursor red for a moment, to show the server refused an edit.
+pub fn flash_edit_denied<'a, 'b:'a>(view: &'a mut view::T<'b>, now: u64) {
+  view.hud.edit_denied_until = Some(now + EDIT_DENIED_FLASH_NS);
+}
+
+/// Show a notification at the top of the screen for a few seconds.
+pub fn notify<'a, 'b:'a>(view: &'a mut view::T<'b>, text: String, now: u64) {
+  let notifications = &mut view.hud.notifications;
+  if notifications.len() >= MAX_NOTIFICATIONS {
+    notifications.pop_front();
+  }
+  notifications.push_back((text, now + NOTIFICATION_NS));
+}
+
+/// Lay the HUD out for this frame, and load it into its buffer.
+pub fn update<'a, 'b:'a>(view: &'a mut view::T<'b>, client: &client::T, now: u64) {
+  if view.hud.edit_denied_until.map(|until| now >= until).unwrap_or(false) {
+    view.hud.edit_denied_until = None;
+  }
+  while view.hud.notifications.front().map(|&(_, until)| now >= until).unwrap_or(false) {
+    view.hud.notifications.pop_front();
+  }
+
+  let health = *client.health.lock().unwrap();
+  let brush = *client.brush.lock().unwrap();
+  let layout = layout(&view.hud, health, brush, now);
+
+  let aspect = view.window_size.x as f32 / view.window_size.y as f32;
+  let triangles = view.frame_arena.hud_vertices.get();
+  layout.build(aspect, triangles);
+  if triangles.len() > MAX_VERTICES {
+    warn!("HUD needs {} vertices, but only has room for {}", triangles.len(), MAX_VERTICES);
+    triangles.truncate(MAX_VERTICES);
+  }
+  // Blank out whatever was drawn past the end of this frame's HUD last time.
+  let len = triangles.len();
+  triangles.resize(cmp::max(len, view.hud.uploaded), blank_vertex());
+  view.hud.uploaded = len;
+
+  view.hud_triangles.buffer.byte_buffer.bind(&mut view.gl);
+  view.hud_triangles.buffer.update(&mut view.gl, 0, triangles);
 }
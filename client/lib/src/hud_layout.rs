@@ -0,0 +1,323 @@
+//! A small layout layer for the HUD. Widgets (panels, labels, progress bars) are nested in stacks and anchored to the
+//! edges or the center of the screen, and the whole HUD is turned into one batch of colored triangles each frame.
+//! Screen coordinates go from -aspect to aspect across, and from -1 to 1 up.
+
+use cgmath::{Point2, Vector2};
+
+use common::color::Color4;
+
+use hud;
+use vertex::ColoredVertex;
+
+/// How far anchored widgets are kept from the edges of the screen.
+const MARGIN: f32 = 0.03;
+
+/// Where on the screen a widget's attached.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(missing_docs)]
+pub enum Anchor {
+  TopLeft,
+  Top,
+  TopRight,
+  Left,
+  Center,
+  Right,
+  BottomLeft,
+  Bottom,
+  BottomRight,
+}
+
+impl Anchor {
+  /// How far across and up the screen the anchor is, from 0 to 1. The same point of the widget sits on it.
+  fn fraction(self) -> Vector2<f32> {
+    let (x, y) =
+      match self {
+        Anchor::TopLeft     => (0.0, 1.0),
+        Anchor::Top         => (0.5, 1.0),
+        Anchor::TopRight    => (1.0, 1.0),
+        Anchor::Left        => (0.0, 0.5),
+        Anchor::Center      => (0.5, 0.5),
+        Anchor::Right       => (1.0, 0.5),
+        Anchor::BottomLeft  => (0.0, 0.0),
+        Anchor::Bottom      => (0.5, 0.0),
+        Anchor::BottomRight => (1.0, 0.0),
+      };
+    Vector2::new(x, y)
+  }
+}
+
+/// Which way a stack's children go.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+  /// Left to right.
+  Across,
+  /// Top to bottom.
+  Down,
+}
+
+#[allow(missing_docs)]
+#[derive(Debug, Clone, PartialEq)]
+pub enum Widget {
+  /// A plain rectangle.
+  Panel(Vector2<f32>, Color4<f32>),
+  /// A line of seven-segment text. Characters that can't be shown that way are left blank.
+  Label {
+    text   : String,
+    height : f32,
+    color  : Color4<f32>,
+  },
+  /// A bar filled from the left; `progress` is in [0, 1].
+  Bar {
+    size     : Vector2<f32>,
+    progress : f32,
+    fill     : Color4<f32>,
+    empty    : Color4<f32>,
+  },
+  /// Widgets in a row or a column, `spacing` apart. Smaller ones are centered in it.
+  Stack {
+    direction : Direction,
+    spacing   : f32,
+    children  : Vec<Widget>,
+  },
+  /// A widget on a backing panel, with `padding` around it.
+  Framed {
+    padding : f32,
+    color   : Color4<f32>,
+    child   : Box<Widget>,
+  },
+}
+
+/// A line of seven-segment text.
+pub fn label(text: &str, height: f32, color: Color4<f32>) -> Widget {
+  Widget::Label { text: text.to_owned(), height: height, color: color }
+}
+
+/// A progress bar.
+pub fn bar(size: Vector2<f32>, progress: f32, fill: Color4<f32>, empty: Color4<f32>) -> Widget {
+  Widget::Bar { size: size, progress: progress, fill: fill, empty: empty }
+}
+
+/// A row or column of widgets.
+pub fn stack(direction: Direction, spacing: f32, children: Vec<Widget>) -> Widget {
+  Widget::Stack { direction: direction, spacing: spacing, children: children }
+}
+
+/// A widget on a backing panel.
+pub fn framed(padding: f32, color: Color4<f32>, child: Widget) -> Widget {
+  Widget::Framed { padding: padding, color: color, child: Box::new(child) }
+}
+
+impl Widget {
+  /// How much of the screen the widget takes up.
+  pub fn size(&self) -> Vector2<f32> {
+    match *self {
+      Widget::Panel(size, _) => size,
+      Widget::Label { ref text, height, .. } => Vector2::new(hud::text_width(text.chars().count(), height), height),
+      Widget::Bar { size, .. } => size,
+      Widget::Stack { direction, spacing, ref children } => {
+        let mut size = Vector2::new(0.0, 0.0);
+        for child in children {
+          let child = child.size();
+          match direction {
+            Direction::Across => {
+              size.x += child.x;
+              size.y = f32::max(size.y, child.y);
+            },
+            Direction::Down => {
+              size.x = f32::max(size.x, child.x);
+              size.y += child.y;
+            },
+          }
+        }
+        let gaps = spacing * children.len().saturating_sub(1) as f32;
+        match direction {
+          Direction::Across => size.x += gaps,
+          Direction::Down => size.y += gaps,
+        }
+        size
+      },
+      Widget::Framed { padding, ref child, .. } => child.size() + Vector2::new(2.0 * padding, 2.0 * padding),
+    }
+  }
+
+  /// Push the widget's triangles, with its bottom-left corner at `low`.
+  fn draw(&self, low: Point2<f32>, vertices: &mut Vec<ColoredVertex>) {
+    match *self {
+      Widget::Panel(size, color) => {
+        vertices.extend(ColoredVertex::square(low, low + size, color).iter().cloned());
+      },
+      Widget::Label { ref text, height, color } => {
+        hud::text(vertices, text, low, height, color);
+      },
+      Widget::Bar { size, progress, fill, empty } => {
+        let progress = f32::max(0.0, f32::min(1.0, progress));
+        let high = low + size;
+        let filled = Point2::new(low.x + size.x * progress, high.y);
+        if progress > 0.0 {
+          vertices.extend(ColoredVertex::square(low, filled, fill).iter().cloned());
+        }
+        if progress < 1.0 {
+          vertices.extend(ColoredVertex::square(Point2::new(filled.x, low.y), high, empty).iter().cloned());
+        }
+      },
+      Widget::Stack { direction, spacing, ref children } => {
+        let size = self.size();
+        // The first child goes at the left, or at the top.
+        let mut next =
+          match direction {
+            Direction::Across => low.x,
+            Direction::Down => low.y + size.y,
+          };
+        for child in children {
+          let child_size = child.size();
+          let child_low =
+            match direction {
+              Direction::Across => {
+                let child_low = Point2::new(next, low.y + (size.y - child_size.y) / 2.0);
+                next += child_size.x + spacing;
+                child_low
+              },
+              Direction::Down => {
+                next -= child_size.y;
+                let child_low = Point2::new(low.x + (size.x - child_size.x) / 2.0, next);
+                next -= spacing;
+                child_low
+              },
+            };
+          child.draw(child_low, vertices);
+        }
+      },
+      Widget::Framed { padding, color, ref child } => {
+        vertices.extend(ColoredVertex::square(low, low + self.size(), color).iter().cloned());
+        child.draw(low + Vector2::new(padding, padding), vertices);
+      },
+    }
+  }
+}
+
+/// A HUD layout: widgets, each anchored somewhere on the screen. Later widgets are drawn over earlier ones.
+pub struct T {
+  widgets : Vec<(Anchor, Vector2<f32>, Widget)>,
+}
+
+#[allow(missing_docs)]
+pub fn new() -> T {
+  T {
+    widgets : Vec::new(),
+  }
+}
+
+impl T {
+  /// Attach a widget to the screen at `anchor`, moved by `offset`.
+  pub fn add(&mut self, anchor: Anchor, offset: Vector2<f32>, widget: Widget) {
+    self.widgets.push((anchor, offset, widget));
+  }
+
+  /// Push the triangles for the whole layout, on a screen `aspect` times as wide as it is tall.
+  pub fn build(&self, aspect: f32, vertices: &mut Vec<ColoredVertex>) {
+    for &(anchor, offset, ref widget) in &self.widgets {
+      let fraction = anchor.fraction();
+      let size = widget.size();
+      // Anchors at the edges are kept a margin in from them.
+      let point =
+        Point2::new(
+          -aspect + 2.0 * aspect * fraction.x + MARGIN * (1.0 - 2.0 * fraction.x),
+          -1.0 + 2.0 * fraction.y + MARGIN * (1.0 - 2.0 * fraction.y),
+        );
+      let low = point - Vector2::new(size.x * fraction.x, size.y * fraction.y) + offset;
+      widget.draw(low, vertices);
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use std;
+  use cgmath::{Point2, Vector2};
+
+  use common::color::Color4;
+
+  use super::*;
+
+  fn white() -> Color4<f32> {
+    Color4::of_rgba(1.0, 1.0, 1.0, 1.0)
+  }
+
+  fn bounds(vertices: &[ColoredVertex]) -> (Point2<f32>, Point2<f32>) {
+    let mut min = Point2::new(std::f32::INFINITY, std::f32::INFINITY);
+    let mut max = Point2::new(-std::f32::INFINITY, -std::f32::INFINITY);
+    for v in vertices {
+      min.x = f32::min(min.x, v.position.x);
+      min.y = f32::min(min.y, v.position.y);
+      max.x = f32::max(max.x, v.position.x);
+      max.y = f32::max(max.y, v.position.y);
+    }
+    (min, max)
+  }
+
+  fn close(a: Point2<f32>, b: Point2<f32>) -> bool {
+    (a.x - b.x).abs() < 1e-5 && (a.y - b.y).abs() < 1e-5
+  }
+
+  fn close_bounds(vertices: &[ColoredVertex], min: Point2<f32>, max: Point2<f32>) -> bool {
+    let (actual_min, actual_max) = bounds(vertices);
+    close(actual_min, min) && close(actual_max, max)
+  }
+
+  fn close_size(widget: &Widget, x: f32, y: f32) -> bool {
+    let size = widget.size();
+    close(Point2::new(size.x, size.y), Point2::new(x, y))
+  }
+
+  #[test]
+  fn test_anchors() {
+    let panel = Widget::Panel(Vector2::new(0.2, 0.1), white());
+
+    let mut layout = new();
+    layout.add(Anchor::Center, Vector2::new(0.0, 0.0), panel.clone());
+    let mut vertices = Vec::new();
+    layout.build(1.5, &mut vertices);
+    assert!(close_bounds(&vertices, Point2::new(-0.1, -0.05), Point2::new(0.1, 0.05)));
+
+    let mut layout = new();
+    layout.add(Anchor::TopRight, Vector2::new(0.0, -0.1), panel);
+    let mut vertices = Vec::new();
+    layout.build(1.5, &mut vertices);
+    assert!(close_bounds(&vertices, Point2::new(1.3 - MARGIN, 0.8 - MARGIN), Point2::new(1.5 - MARGIN, 0.9 - MARGIN)));
+  }
+
+  #[test]
+  fn test_stacks() {
+    let column =
+      stack(Direction::Down, 0.1, vec!(
+        Widget::Panel(Vector2::new(0.4, 0.2), white()),
+        Widget::Panel(Vector2::new(0.2, 0.2), white()),
+      ));
+    assert!(close_size(&column, 0.4, 0.5));
+    let row = stack(Direction::Across, 0.0, vec!(column.clone(), Widget::Panel(Vector2::new(0.1, 0.1), white())));
+    assert!(close_size(&row, 0.5, 0.5));
+    assert!(close_size(&framed(0.05, white(), row), 0.6, 0.6));
+
+    // The narrower panel goes under the wider one, centered.
+    let mut vertices = Vec::new();
+    column.draw(Point2::new(0.0, 0.0), &mut vertices);
+    assert_eq!(vertices.len(), 12);
+    assert!(close_bounds(&vertices[.. 6], Point2::new(0.0, 0.3), Point2::new(0.4, 0.5)));
+    assert!(close_bounds(&vertices[6 ..], Point2::new(0.1, 0.0), Point2::new(0.3, 0.2)));
+  }
+
+  #[test]
+  fn test_bar() {
+    let empty = Color4::of_rgba(0.0, 0.0, 0.0, 1.0);
+    let mut vertices = Vec::new();
+    bar(Vector2::new(1.0, 0.1), 0.25, white(), empty).draw(Point2::new(0.0, 0.0), &mut vertices);
+    assert_eq!(vertices.len(), 12);
+    assert!(close_bounds(&vertices[.. 6], Point2::new(0.0, 0.0), Point2::new(0.25, 0.1)));
+    assert_eq!(vertices[6].color, empty);
+
+    // Full and empty bars are one rectangle.
+    let mut vertices = Vec::new();
+    bar(Vector2::new(1.0, 0.1), 2.0, white(), empty).draw(Point2::new(0.0, 0.0), &mut vertices);
+    assert_eq!(vertices.len(), 6);
+  }
+}
@@ -0,0 +1,74 @@
+//! Keys bound to slash commands, which are sent to the server to run as the player.
+//! Bindings are read from a file with one `<key> /<command>` per line, e.g. `6 /sphere me stone 3`.
+//! Blank lines and lines starting with `#` are ignored.
+
+use sdl2::keyboard::Keycode;
+use std;
+
+use common::fnv_map;
+use common::line_file;
+
+/// Bindings from keys to commands.
+pub struct T {
+  commands: fnv_map::T<Keycode, String>,
+}
+
+#[allow(missing_docs)]
+pub fn empty() -> T {
+  T {
+    commands: fnv_map::new(),
+  }
+}
+
+/// Parse one binding from a bindings file.
+fn parse_line(line: &str) -> Result<(Keycode, String), String> {
+  let mut split = line.splitn(2, char::is_whitespace);
+  let key = split.next().unwrap();
+  let command = split.next().unwrap_or("").trim();
+  let key =
+    match Keycode::from_name(key) {
+      None => return Err(format!("Unknown key: {:?}", key)),
+      Some(key) => key,
+    };
+  if !command.starts_with('/') || command.len() == 1 {
+    return Err(format!("Expected a /command after {:?}", key))
+  }
+  Ok((key, command[1..].to_owned()))
+}
+
+/// Load bindings from a `line_file`, if there is one.
+pub fn load(path: &std::path::Path) -> T {
+  let mut binds = empty();
+  {
+    let commands = &mut binds.commands;
+    line_file::load(path, "key bindings", |line| {
+      let (key, command) = try!(parse_line(line));
+      info!("Binding {:?} to /{}", key, command);
+      commands.insert(key, command);
+      Ok(())
+    });
+  }
+  binds
+}
+
+impl T {
+  /// The command bound to a key, without its leading slash.
+  pub fn command(&self, key: Keycode) -> Option<&str> {
+    self.commands.get(&key).map(|command| command.as_str())
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use sdl2::keyboard::Keycode;
+
+  use super::parse_line;
+
+  #[test]
+  fn test_parse_line() {
+    assert_eq!(parse_line("5   /sphere me stone 3 ").unwrap(), (Keycode::Num5, String::from("sphere me stone 3")));
+    assert!(parse_line("5 spawn me").is_err());
+    assert!(parse_line("5 /").is_err());
+    assert!(parse_line("NotAKey /spawn me").is_err());
+  }
+}
@@ -1,6 +1,7 @@
 //! Structs for keeping track of terrain level of detail.
 
 use num;
+use std;
 
 use chunk;
 
@@ -28,8 +29,13 @@ const LG_SAMPLE_SIZE: [i16; COUNT] = [
 /// max LOD where grass will display
 pub const MAX_GRASS_LOD: T = T(3);
 
-/// The distances at which LOD switches.
-pub const THRESHOLDS: [u32; COUNT-1] = [1, 15, 31, 47];
+/// The chunk distances at which LOD switches: chunks further than `thresholds[i]` away are drawn at LOD `i + 1` or
+/// coarser.
+pub type Thresholds = [u32; COUNT-1];
+
+/// How far (in pixels) the terrain's surface can be drawn from where it'd be at full detail, unless configured
+/// otherwise.
+pub const DEFAULT_MAX_PIXEL_ERROR: f32 = 8.0;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 /// A strongly-typed index into various LOD-indexed arrays.
@@ -52,17 +58,84 @@ impl T {
   pub fn edge_samples(self) -> u16 {
     1 << self.lg_edge_samples()
   }
+
+  /// Roughly how far (in world units) a surface drawn at this LOD can be from where it'd be at full detail: half the
+  /// width of its voxels, beyond the most detailed LOD's.
+  pub fn geometric_error(self) -> f32 {
+    let width = |lod: T| 2.0_f32.powi(lod.lg_sample_size() as i32);
+    (width(self) - width(ALL[0])) / 2.0
+  }
+}
+
+/// The LOD thresholds that keep the terrain's geometric error under `max_pixel_error` pixels on screen, when it's drawn
+/// `screen_height` pixels tall with a vertical field of view of `fov` radians. Taller screens and narrower fields of
+/// view magnify errors, so they keep detail further out.
+pub fn thresholds(screen_height: u32, fov: f32, max_pixel_error: f32) -> Thresholds {
+  // How many pixels tall something one unit tall looks from one unit away.
+  let pixels_per_unit = screen_height as f32 / (2.0 * (fov / 2.0).tan());
+  let mut thresholds = [0; COUNT-1];
+  let mut prev = 0;
+  for (i, threshold) in thresholds.iter_mut().enumerate() {
+    let min_distance = ALL[i + 1].geometric_error() * pixels_per_unit / max_pixel_error;
+    // Chunks `d` away can have points within `d - 1` chunk widths of the viewer.
+    let chunks = (min_distance / chunk::WIDTH as f32).floor() as u32 + 1;
+    // LODs with the same error still get a ring each, so none of them go unused.
+    *threshold = std::cmp::max(chunks, prev + 1);
+    prev = *threshold;
+  }
+  thresholds
+}
+
+/// The most detailed LOD whose voxels are at least `2^lg_size` wide, or the least detailed LOD if there isn't one.
+pub fn finest_with_lg_sample_size(lg_size: i16) -> T {
+  for &lod in &ALL {
+    if lod.lg_sample_size() >= lg_size {
+      return lod
+    }
+  }
+  ALL[COUNT - 1]
+}
+
+/// The LOD for a certain chunk distance away, but no more detailed than `floor`.
+pub fn of_distance_with_floor(distance: u32, floor: T, thresholds: &Thresholds) -> T {
+  std::cmp::max(of_distance(distance, thresholds), floor)
 }
 
 /// determine the LOD for a certain chunk distance away
-pub fn of_distance(distance: u32) -> T {
+pub fn of_distance(distance: u32, thresholds: &Thresholds) -> T {
   let mut lod = 0;
   while
-    lod < THRESHOLDS.len()
-    && THRESHOLDS[lod] < distance
+    lod < thresholds.len()
+    && thresholds[lod] < distance
   {
     lod += 1;
   }
   T(num::traits::FromPrimitive::from_usize(lod).unwrap())
 }
 
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_thresholds() {
+    let fov = 60.0_f32.to_radians();
+    let t = thresholds(600, fov, DEFAULT_MAX_PIXEL_ERROR);
+    assert_eq!(t, [5, 13, 14, 29]);
+    assert_eq!(of_distance(5, &t), T(0));
+    assert_eq!(of_distance(14, &t), T(2));
+    assert_eq!(of_distance(100, &t), T(4));
+
+    // Bigger screens and narrower fields of view keep detail further out.
+    let bigger = thresholds(1200, fov, DEFAULT_MAX_PIXEL_ERROR);
+    let narrower = thresholds(600, fov / 2.0, DEFAULT_MAX_PIXEL_ERROR);
+    for i in 0 .. COUNT - 1 {
+      assert!(bigger[i] > t[i]);
+      assert!(narrower[i] > t[i]);
+    }
+
+    // A huge error budget still uses every LOD.
+    assert_eq!(thresholds(600, fov, 1e6), [1, 2, 3, 4]);
+  }
+}
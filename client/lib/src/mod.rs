@@ -26,19 +26,29 @@ extern crate time;
 extern crate voxel_data;
 extern crate yaglw;
 
+pub mod ambient_occlusion;
 pub mod audio;
 pub mod audio_loader;
 pub mod audio_thread;
+pub mod border_normals;
 pub mod chunk;
 pub mod chunk_stats;
+pub mod greedy_mesh;
 pub mod client;
+pub mod config;
 pub mod hud;
+pub mod hud_layout;
+pub mod keybinds;
 pub mod lod;
+pub mod platform;
 pub mod process_event;
 pub mod record_book;
 pub mod run;
 pub mod server;
+pub mod server_clock;
 pub mod server_update;
+pub mod sky_occlusion;
+pub mod snapshots;
 pub mod terrain;
 pub mod terrain_mesh;
 pub mod update_thread;
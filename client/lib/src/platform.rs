@@ -0,0 +1,174 @@
+//! The window, and the events that come out of it. On high-DPI displays, the window's size on screen (in points) and
+//! the size of what's drawn in it (in pixels) differ. Rendering, the HUD and screenshots go by pixels. The mouse goes
+//! by points.
+
+use cgmath::Vector2;
+use gl;
+use sdl2;
+use sdl2::event::Event;
+use sdl2::video;
+use std;
+
+/// How the window sits on the screen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WindowMode {
+  /// A normal window, with a title bar.
+  Windowed,
+  /// A window with no decorations that covers the screen, without changing the display's resolution.
+  Borderless,
+  /// Exclusive fullscreen, at the display's resolution closest to the window's size.
+  Fullscreen,
+}
+
+impl WindowMode {
+  /// What the mode's called in the settings file.
+  pub fn name(self) -> &'static str {
+    match self {
+      WindowMode::Windowed => "windowed",
+      WindowMode::Borderless => "borderless",
+      WindowMode::Fullscreen => "fullscreen",
+    }
+  }
+
+  /// The mode to switch to after this one.
+  pub fn next(self) -> WindowMode {
+    match self {
+      WindowMode::Windowed => WindowMode::Borderless,
+      WindowMode::Borderless => WindowMode::Fullscreen,
+      WindowMode::Fullscreen => WindowMode::Windowed,
+    }
+  }
+
+  fn fullscreen_type(self) -> video::FullscreenType {
+    match self {
+      WindowMode::Windowed => video::FullscreenType::Off,
+      WindowMode::Borderless => video::FullscreenType::Desktop,
+      WindowMode::Fullscreen => video::FullscreenType::True,
+    }
+  }
+}
+
+impl std::str::FromStr for WindowMode {
+  type Err = ();
+  fn from_str(s: &str) -> Result<WindowMode, ()> {
+    match s {
+      "windowed" => Ok(WindowMode::Windowed),
+      "borderless" => Ok(WindowMode::Borderless),
+      "fullscreen" => Ok(WindowMode::Fullscreen),
+      _ => Err(()),
+    }
+  }
+}
+
+/// A window with an OpenGL context, and the SDL state behind it.
+pub struct T {
+  sdl         : sdl2::Sdl,
+  _video      : sdl2::VideoSubsystem,
+  events      : sdl2::EventSubsystem,
+  event_pump  : sdl2::EventPump,
+  window      : video::Window,
+  _gl_context : video::GLContext,
+  mode        : WindowMode,
+}
+
+/// Open a window `size` points big, in some mode, with a core OpenGL context of version `gl_version`. The context is
+/// made current and OpenGL's functions are loaded.
+pub fn new(title: &str, size: Vector2<u32>, mode: WindowMode, vsync: bool, gl_version: (u8, u8)) -> T {
+  let sdl = sdl2::init().unwrap();
+  let events = sdl.event().unwrap();
+  let video = sdl.video().unwrap();
+
+  {
+    let gl_attr = video.gl_attr();
+    gl_attr.set_context_profile(video::GLProfile::Core);
+    gl_attr.set_context_version(gl_version.0, gl_version.1);
+  }
+
+  let window = video.window(title, size.x, size.y).opengl().allow_highdpi().resizable().build().unwrap();
+
+  {
+    let gl_attr = video.gl_attr();
+    assert_eq!(gl_attr.context_profile(), video::GLProfile::Core);
+    assert_eq!(gl_attr.context_version(), gl_version);
+  }
+
+  let event_pump = sdl.event_pump().unwrap();
+  let gl_context = window.gl_create_context().unwrap();
+
+  // Load the OpenGL function pointers.
+  gl::load_with(|s| video.gl_get_proc_address(s) as *const _ );
+
+  if !video.gl_set_swap_interval(if vsync { 1 } else { 0 }) {
+    warn!("Couldn't set vsync to {}", vsync);
+  }
+
+  let mut platform =
+    T {
+      sdl         : sdl,
+      _video      : video,
+      events      : events,
+      event_pump  : event_pump,
+      window      : window,
+      _gl_context : gl_context,
+      mode        : WindowMode::Windowed,
+    };
+  platform.set_mode(mode);
+  platform
+}
+
+impl T {
+  /// How big what's drawn in the window is, in pixels.
+  pub fn pixel_size(&self) -> Vector2<i32> {
+    let (w, h) = self.window.drawable_size();
+    Vector2::new(w as i32, h as i32)
+  }
+
+  /// How big the window is on screen, in points.
+  pub fn point_size(&self) -> Vector2<i32> {
+    let (w, h) = self.window.size();
+    Vector2::new(w as i32, h as i32)
+  }
+
+  #[allow(missing_docs)]
+  pub fn mode(&self) -> WindowMode {
+    self.mode
+  }
+
+  /// Switch the window to another mode. If it can't be, it stays how it was.
+  pub fn set_mode(&mut self, mode: WindowMode) {
+    match self.window.set_fullscreen(mode.fullscreen_type()) {
+      Ok(()) => {
+        info!("Window mode: {}", mode.name());
+        self.mode = mode;
+      },
+      Err(err) => warn!("Couldn't switch the window to {}: {}", mode.name(), err),
+    }
+  }
+
+  /// Lock the mouse to the window and hide it, so mouse motion only turns the camera.
+  pub fn capture_mouse(&self) {
+    self.sdl.mouse().set_relative_mouse_mode(true);
+  }
+
+  /// Every event that's come in since the last time this was called.
+  pub fn poll_events(&mut self) -> Vec<Event> {
+    self.event_pump.pump_events();
+    let events = self.events.peek_events(1 << 6);
+    self.events.flush_events(0, std::u32::MAX);
+    events
+  }
+
+  /// Move the mouse back to the middle of the window, if the window has the mouse.
+  pub fn recenter_mouse(&self) {
+    if self.window.window_flags() & (::sdl2::sys::video::SDL_WindowFlags::SDL_WINDOW_MOUSE_FOCUS as u32) != 0 {
+      // Warping goes by points, not pixels.
+      let size = self.point_size();
+      self.sdl.mouse().warp_mouse_in_window(&self.window, size.x / 2, size.y / 2);
+    }
+  }
+
+  /// Show what's been drawn.
+  pub fn swap(&self) {
+    self.window.gl_swap_window();
+  }
+}
@@ -2,58 +2,84 @@
 
 use cgmath::{Vector2, Vector3};
 use sdl2::event::Event;
-use sdl2::keyboard::Keycode;
 use sdl2::mouse::MouseButton;
 use std::f32::consts::PI;
 use stopwatch;
+use time;
 
 use common::entity;
 use common::protocol;
 
 use client;
+use config;
+use config::Action;
+use keybinds;
+use platform;
 use view;
+use view::debug_overlay::Overlay;
 
 #[allow(missing_docs)]
 pub fn process_event<UpdateServer>(
   update_server: &mut UpdateServer,
   view: &mut view::T,
+  platform: &mut platform::T,
   client: &client::T,
+  config: &config::T,
+  binds: &keybinds::T,
   event: Event,
-) where UpdateServer: FnMut(protocol::ClientToServer)
+) where UpdateServer: FnMut(protocol::Request)
 {
   match event {
     Event::KeyDown{keycode, repeat, ..} => {
       keycode.map(|keycode| {
         if !repeat {
-          key_press(update_server, view, client, keycode);
+          match binds.command(keycode) {
+            Some(command) => update_server(protocol::Request::Command(client.player_id, command.to_owned())),
+            None => {
+              config.action(keycode).map(|action| key_press(update_server, view, platform, client, action));
+            },
+          }
         }
       });
     },
     Event::KeyUp{keycode, repeat, ..} => {
       keycode.map(|keycode| {
-        if !repeat {
-          key_release(client.player_id, update_server, keycode);
+        if !repeat && binds.command(keycode).is_none() {
+          config.action(keycode).map(|action| key_release(client.player_id, update_server, view, action));
         }
       });
     },
     Event::MouseMotion{xrel, yrel, ..} => {
-      mouse_move(client.player_id, update_server, view, xrel, yrel);
+      mouse_move(client.player_id, update_server, view, config.mouse_sensitivity, xrel, yrel);
     },
     Event::MouseButtonDown{mouse_btn, ..} => {
-      mouse_press(client.player_id, update_server, mouse_btn);
+      mouse_press(client.player_id, update_server, mouse_btn, aim(view, client));
+    },
+    Event::MouseButtonUp{mouse_btn, ..} => {
+      mouse_release(client.player_id, update_server, mouse_btn);
     },
     _ => {},
   }
 }
 
+/// Where the player is aiming right now, as far as this client can tell.
+fn aim(view: &view::T, client: &client::T) -> protocol::Aim {
+  protocol::Aim {
+    position  : view.camera.position,
+    direction : view.camera.forward(),
+    time_ns   : client.server_clock.lock().unwrap().to_server(time::precise_time_ns()),
+  }
+}
+
 fn key_press<UpdateServer>(
   update_server: &mut UpdateServer,
   view: &mut view::T,
+  platform: &mut platform::T,
   client: &client::T,
-  key: Keycode,
-) where UpdateServer: FnMut(protocol::ClientToServer)
+  action: Action,
+) where UpdateServer: FnMut(protocol::Request)
 {
-  use common::protocol::ClientToServer::*;
+  use common::protocol::Request::*;
 
   let lr = |update_server: &mut UpdateServer, view: &mut view::T, k| {
     match view.input_mode {
@@ -81,53 +107,130 @@ fn key_press<UpdateServer>(
     }
   };
 
+  let set_brush = |update_server: &mut UpdateServer, change: &Fn(protocol::BrushShape, f32) -> (protocol::BrushShape, f32)| {
+    let mut brush = client.brush.lock().unwrap();
+    let (shape, radius) = change(brush.0, brush.1);
+    let radius = f32::max(protocol::MIN_BRUSH_RADIUS, f32::min(protocol::MAX_BRUSH_RADIUS, radius));
+    *brush = (shape, radius);
+    info!("Brush: {:?} of radius {}", shape, radius);
+    update_server(SetBrush(client.player_id, shape, radius));
+  };
+
   stopwatch::time("event.key_press", || {
-    match key {
-      Keycode::A => {
+    match action {
+      Action::MoveLeft => {
         update_server(Walk(client.player_id, Vector3::new(-1.0, 0.0, 0.0)));
       },
-      Keycode::D => {
+      Action::MoveRight => {
         update_server(Walk(client.player_id, Vector3::new(1.0, 0.0, 0.0)));
       },
-      Keycode::Space => {
+      Action::Jump => {
         update_server(StartJump(client.player_id));
       },
-      Keycode::W => {
+      Action::MoveForward => {
         update_server(Walk(client.player_id, Vector3::new(0.0, 0.0, -1.0)));
       },
-      Keycode::S => {
+      Action::MoveBack => {
         update_server(Walk(client.player_id, Vector3::new(0.0, 0.0, 1.0)));
       },
-      Keycode::Left => {
+      Action::TurnLeft => {
         lr(update_server, view, 1.0);
       },
-      Keycode::Right => {
+      Action::TurnRight => {
         lr(update_server, view, -1.0);
       },
-      Keycode::Up => {
+      Action::LookUp => {
         ud(update_server, view, 1.0);
       },
-      Keycode::Down => {
+      Action::LookDown => {
         ud(update_server, view, -1.0);
       },
-      Keycode::H => {
+      Action::ToggleHud => {
         view.show_hud = !view.show_hud;
       },
-      Keycode::M => {
+      Action::ToggleSunControl => {
         view.input_mode =
           match view.input_mode {
             view::InputMode::Camera => view::InputMode::Sun,
             view::InputMode::Sun => view::InputMode::Camera,
           };
       },
-      Keycode::P => {
+      Action::ReloadShaders => {
+        view.reload_shaders();
+        *client.reshade_requested.lock().unwrap() = true;
+      },
+      Action::LessGrass => {
+        let density = view.grass_density - 0.1;
+        view.set_grass_density(density);
+      },
+      Action::MoreGrass => {
+        let density = view.grass_density + 0.1;
+        view.set_grass_density(density);
+      },
+      Action::CycleBrushShape => {
+        set_brush(update_server, &|shape, radius| (shape.next(), radius));
+      },
+      Action::ShrinkBrush => {
+        set_brush(update_server, &|shape, radius| (shape, radius - 1.0));
+      },
+      Action::GrowBrush => {
+        set_brush(update_server, &|shape, radius| (shape, radius + 1.0));
+      },
+      Action::PlaceRock => {
+        update_server(AddRock(client.player_id, aim(view, client)));
+      },
+      Action::Throw => {
+        update_server(Throw(client.player_id, aim(view, client)));
+      },
+      Action::Screenshot => {
+        view.capture_requested = true;
+      },
+      Action::Sprint => {
+        update_server(Sprint(client.player_id, true));
+        view.camera_motion.sprinting = true;
+      },
+      Action::NarrowFov => {
+        let fov = view.camera_motion.fov_degrees() - 5.0;
+        view.camera_motion.set_fov(fov);
+      },
+      Action::WidenFov => {
+        let fov = view.camera_motion.fov_degrees() + 5.0;
+        view.camera_motion.set_fov(fov);
+      },
+      Action::CycleWindowMode => {
+        let mode = platform.mode().next();
+        platform.set_mode(mode);
+      },
+      Action::ToggleWireframe => {
+        view.debug.toggle(Overlay::Wireframe);
+      },
+      Action::ToggleMeshBounds => {
+        view.debug.toggle(Overlay::MeshBounds);
+      },
+      Action::TogglePhysicsBounds => {
+        let enabled = view.debug.toggle(Overlay::PhysicsBounds);
+        update_server(WatchPhysics(client.id, enabled));
+      },
+      Action::ToggleGrass => {
+        view.debug.toggle(Overlay::HideGrass);
+      },
+      Action::ToggleFrameTimes => {
+        view.debug.toggle(Overlay::FrameTimes);
+      },
+      Action::ToggleViewQueue => {
+        view.debug.toggle(Overlay::ViewQueue);
+      },
+      Action::ToggleCameraSmoothing => {
+        view.camera_motion.smooth = !view.camera_motion.smooth;
+        info!("Camera smoothing: {}", view.camera_motion.smooth);
+      },
+      Action::PinLoadPosition => {
         let mut load_position = client.load_position.lock().unwrap();
         match *load_position {
           None => *load_position = Some(*client.player_position.lock().unwrap()),
           Some(_) => *load_position = None,
         }
       },
-      _ => {},
     }
   })
 }
@@ -136,18 +239,37 @@ fn mouse_press<UpdateServer>(
   player_id: entity::id::Player,
   update_server: &mut UpdateServer,
   mouse_btn: MouseButton,
-) where UpdateServer: FnMut(protocol::ClientToServer)
+  aim: protocol::Aim,
+) where UpdateServer: FnMut(protocol::Request)
 {
   stopwatch::time("event.mouse_press", || {
     match mouse_btn {
       MouseButton::Left => {
         update_server(
-          protocol::ClientToServer::Add(player_id)
+          protocol::Request::Add(player_id, aim)
         );
       },
       MouseButton::Right => {
         update_server(
-          protocol::ClientToServer::Remove(player_id)
+          protocol::Request::StartRemove(player_id, aim)
+        );
+      },
+      _ => {},
+    }
+  })
+}
+
+fn mouse_release<UpdateServer>(
+  player_id: entity::id::Player,
+  update_server: &mut UpdateServer,
+  mouse_btn: MouseButton,
+) where UpdateServer: FnMut(protocol::Request)
+{
+  stopwatch::time("event.mouse_release", || {
+    match mouse_btn {
+      MouseButton::Right => {
+        update_server(
+          protocol::Request::StopRemove(player_id)
         );
       },
       _ => {},
@@ -158,47 +280,54 @@ fn mouse_press<UpdateServer>(
 fn key_release<UpdateServer>(
   player_id: entity::id::Player,
   update_server: &mut UpdateServer,
-  key: Keycode,
-) where UpdateServer: FnMut(protocol::ClientToServer)
+  view: &mut view::T,
+  action: Action,
+) where UpdateServer: FnMut(protocol::Request)
 {
   stopwatch::time("event.key_release", || {
-    match key {
+    match action {
       // accelerations are negated from those in key_press.
-      Keycode::A => {
-        update_server(protocol::ClientToServer::Walk(player_id, Vector3::new(1.0, 0.0, 0.0)));
+      Action::MoveLeft => {
+        update_server(protocol::Request::Walk(player_id, Vector3::new(1.0, 0.0, 0.0)));
+      },
+      Action::MoveRight => {
+        update_server(protocol::Request::Walk(player_id, Vector3::new(-1.0, 0.0, 0.0)));
       },
-      Keycode::D => {
-        update_server(protocol::ClientToServer::Walk(player_id, Vector3::new(-1.0, 0.0, 0.0)));
+      Action::Jump => {
+        update_server(protocol::Request::StopJump(player_id));
       },
-      Keycode::Space => {
-        update_server(protocol::ClientToServer::StopJump(player_id));
+      Action::MoveForward => {
+        update_server(protocol::Request::Walk(player_id, Vector3::new(0.0, 0.0, 1.0)));
       },
-      Keycode::W => {
-        update_server(protocol::ClientToServer::Walk(player_id, Vector3::new(0.0, 0.0, 1.0)));
+      Action::MoveBack => {
+        update_server(protocol::Request::Walk(player_id, Vector3::new(0.0, 0.0, -1.0)));
       },
-      Keycode::S => {
-        update_server(protocol::ClientToServer::Walk(player_id, Vector3::new(0.0, 0.0, -1.0)));
+      Action::Sprint => {
+        update_server(protocol::Request::Sprint(player_id, false));
+        view.camera_motion.sprinting = false;
       },
       _ => {}
     }
   })
 }
 
-// x and y are relative to last position.
+// x and y are relative to last position, in points rather than pixels, so turning doesn't speed up on high-DPI
+// displays.
 fn mouse_move<UpdateServer>(
   player_id: entity::id::Player,
   update_server: &mut UpdateServer,
   view: &mut view::T,
+  sensitivity: f32,
   dx: i32, dy: i32,
-) where UpdateServer: FnMut(protocol::ClientToServer)
+) where UpdateServer: FnMut(protocol::Request)
 {
   stopwatch::time("event.mouse_move", || {
     let d = Vector2::new(dx, dy);
     // To-radians coefficient. Numbers closer to zero dull the mouse movement more.
-    let to_radians = Vector2::new(-1.0 / 1000.0, -1.0 / 1600.0);
+    let to_radians = Vector2::new(-sensitivity / 1000.0, -sensitivity / 1600.0);
     let r = Vector2::new(d.x as f32 * to_radians.x, d.y as f32 * to_radians.y);
 
-    update_server(protocol::ClientToServer::RotatePlayer(player_id, r));
+    update_server(protocol::Request::RotatePlayer(player_id, r));
     view.camera.rotate_lateral(r.x);
     view.camera.rotate_vertical(r.y);
   })
@@ -11,16 +11,46 @@ use common::protocol;
 use audio_loader;
 use audio_thread;
 use client;
+use config;
+use lod;
 use record_book;
 use server;
 use terrain;
 use update_thread::update_thread;
+use view;
 use view::thread::view_thread;
 
+/// How many bytes per second we tell the server we can receive.
+const BANDWIDTH: u32 = 8 << 20;
+
+/// Settings for a run of the client that don't belong in `client.conf`.
+pub struct Options {
+  /// Save a screenshot this often, e.g. to compare terrain generation across changes.
+  pub capture_every_ns: Option<u64>,
+}
+
+#[allow(missing_docs)]
+pub fn default_options() -> Options {
+  Options {
+    capture_every_ns: None,
+  }
+}
+
+/// The account to play as: `$PLAYFORM_ACCOUNT`, or else the user's login name.
+fn account() -> String {
+  std::env::var("PLAYFORM_ACCOUNT")
+    .or_else(|_| std::env::var("USER"))
+    .unwrap_or_else(|_| String::from("player"))
+}
+
+/// The token to connect with, from `$PLAYFORM_TOKEN`. Servers that need one play as the token's account instead.
+fn token() -> Option<String> {
+  std::env::var("PLAYFORM_TOKEN").ok()
+}
+
 #[allow(missing_docs)]
-pub fn run(listen_url: &str, server_url: &str) {
-  let view_updates0 = Mutex::new(std::collections::VecDeque::new());
-  let view_updates1 = Mutex::new(std::collections::VecDeque::new());
+pub fn run(listen_url: &str, server_url: &str, options: &Options) {
+  let view_updates = view::update_queue::new(view::update_queue::DEFAULT_CAPACITY);
   let audio_updates = Mutex::new(std::collections::VecDeque::new());
 
   let quit = Mutex::new(false);
@@ -28,7 +58,25 @@ pub fn run(listen_url: &str, server_url: &str) {
 
   let server = server::new(&server_url, &listen_url);
 
-  let client = connect_client(&listen_url, &server);
+  let config_path = std::path::Path::new(config::PATH);
+  let config = config::load(config_path);
+  // The window's opened before connecting, so LODs can be picked for how many pixels it really draws (which differs
+  // from its size in points on high-DPI displays).
+  let platform = view::thread::open_window(&config);
+  let lod_thresholds =
+    lod::thresholds(platform.pixel_size().y as u32, config.fov.to_radians(), config.max_pixel_error);
+  info!("LOD thresholds {:?}", lod_thresholds);
+  let budget = view::vram_budget::split(config.vram_budget_mb as usize * 1_000_000);
+  let polygon_budget = budget.chunks * view::terrain_buffers::CHUNK_LENGTH;
+
+  let client =
+    match connect_client(&listen_url, &server, lod_thresholds, polygon_budget) {
+      Ok(client) => client,
+      Err(err) => {
+        println!("Couldn't connect: {}", err);
+        return
+      },
+    };
   let client = &client;
 
   {
@@ -38,8 +86,7 @@ pub fn run(listen_url: &str, server_url: &str) {
           while !*quit.lock().unwrap() {
             info!("Outstanding terrain requests: {}", *client.pending_terrain_requests.lock().unwrap());
             info!("Outstanding voxel updates: {}", client.terrain.lock().unwrap().queued_update_count());
-            info!("Outstanding view0 updates: {}", view_updates0.lock().unwrap().len());
-            info!("Outstanding view1 updates: {}", view_updates1.lock().unwrap().len());
+            info!("view updates: {:?}", view_updates.stats());
             std::thread::sleep(std::time::Duration::from_secs(1));
           }
         })
@@ -64,8 +111,7 @@ pub fn run(listen_url: &str, server_url: &str) {
 
     let update_thread = {
       let client = &client;
-      let view_updates0 = &view_updates0;
-      let view_updates1 = &view_updates1;
+      let view_updates = &view_updates;
       let audio_updates = &audio_updates;
       let server = server.clone();
       unsafe {
@@ -74,13 +120,14 @@ pub fn run(listen_url: &str, server_url: &str) {
             quit,
             client,
             &mut || { server.listen.try() },
-            &mut |up| { view_updates0.lock().unwrap().push_back(up) },
-            &mut |up| { view_updates1.lock().unwrap().push_back(up) },
+            &mut |up| { view_updates.push(up) },
             &mut |up| { audio_updates.lock().unwrap().push_back(up) },
-  	        &mut |up| { server.talk.tell(&up) },
+  	        &mut |up| { server.talk.tell(&protocol::ClientToServer::Request(client.secret, up)) },
             &mut |msg| {
               match msg {
                 terrain::Load::Voxels { time_requested: None, .. } => {},
+                terrain::Load::Unload { .. } => {},
+                terrain::Load::Reshade => {},
                 terrain::Load::Voxels { time_requested: Some(_), .. } => {
                   *client.pending_terrain_requests.lock().unwrap() -= 1;
                 }
@@ -114,10 +161,13 @@ pub fn run(listen_url: &str, server_url: &str) {
       let client = &client;
       let server = server.clone();
       view_thread(
+        platform,
         client,
-        &mut || { view_updates0.lock().unwrap().pop_front() },
-        &mut || { view_updates1.lock().unwrap().pop_front() },
-        &mut |server_update| { server.talk.tell(&server_update) },
+        &config,
+        &mut || { view_updates.pop() },
+        &mut || { view_updates.stats() },
+        &mut |server_update| { server.talk.tell(&protocol::ClientToServer::Request(client.secret, server_update)) },
+        options.capture_every_ns,
       );
 
       stopwatch::clone().print();
@@ -125,6 +175,13 @@ pub fn run(listen_url: &str, server_url: &str) {
 
     // View thread returned, so we got a quit event.
     *quit.lock().unwrap() = true;
+    if let Err(err) = config.save(config_path) {
+      warn!("Couldn't save settings to {:?}: {:?}", config_path, err);
+    }
+    // Free up our slot on the server.
+    server.talk.tell(&protocol::ClientToServer::Request(client.secret, protocol::Request::Leave(client.id)));
+    // Don't leave the update thread blocked on a full view queue.
+    view_updates.close();
 
     audio_thread.join();
     monitor_thread.join();
@@ -135,18 +192,39 @@ pub fn run(listen_url: &str, server_url: &str) {
   }
 }
 
-fn connect_client(listen_url: &str, server: &server::T) -> client::T {
+fn connect_client(
+  listen_url: &str,
+  server: &server::T,
+  lod_thresholds: lod::Thresholds,
+  polygon_budget: usize,
+) -> Result<client::T, protocol::ConnectionError> {
   // TODO: Consider using RPCs to solidify the request-response patterns.
-  server.talk.tell(&protocol::ClientToServer::Init(listen_url.to_owned()));
+  let capabilities =
+    protocol::Capabilities {
+      max_triangles : std::cmp::min(polygon_budget, std::u32::MAX as usize) as u32,
+      bandwidth     : BANDWIDTH,
+    };
+  server.talk.tell(
+    &protocol::ClientToServer::Init(protocol::VERSION, listen_url.to_owned(), account(), token(), capabilities)
+  );
   loop {
     match server.listen.wait() {
-      protocol::ServerToClient::LeaseId(client_id) => {
-        server.talk.tell(&protocol::ClientToServer::AddPlayer(client_id));
+      protocol::ServerToClient::Rejected(err) => {
+        return Err(err)
+      },
+      protocol::ServerToClient::LeaseId(client_id, version, terrain_policy, secret) => {
+        // The server checks this too, but check anyway rather than misread everything that follows.
+        if version != protocol::VERSION {
+          return Err(protocol::ConnectionError::VersionMismatch { server: version, client: protocol::VERSION })
+        }
+        server.talk.tell(&protocol::ClientToServer::Request(secret, protocol::Request::AddPlayer(client_id)));
         let client_id = client_id;
         loop {
           match server.listen.wait() {
             protocol::ServerToClient::PlayerAdded(player_id, position) => {
-              return client::new(client_id, player_id, position);
+              return Ok(
+                client::new(client_id, secret, player_id, position, terrain_policy, lod_thresholds, polygon_budget)
+              );
             },
             msg => {
               // Ignore other messages in the meantime.
@@ -155,6 +233,10 @@ fn connect_client(listen_url: &str, server: &server::T) -> client::T {
           }
         }
       },
+      protocol::ServerToClient::Queued(position, secret) => {
+        println!("Server is full. Waiting in line to join: number {}", position);
+        server.talk.tell(&protocol::ClientToServer::StillWaiting(secret));
+      },
       msg => {
         // Ignore other messages in the meantime.
         warn!("Ignoring: {:?}", msg);
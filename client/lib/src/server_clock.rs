@@ -0,0 +1,52 @@
+//! Estimate of how the server's clock relates to ours, so timestamped server state can be placed on our timeline.
+
+/// Tracks the offset between server and local time.
+pub struct T {
+  // Local time minus server time, in nanoseconds. This is the smallest difference we've seen between a message's
+  // server timestamp and when we got it, so it includes the fastest one-way trip we've seen.
+  offset_ns: Option<i64>,
+}
+
+#[allow(missing_docs)]
+pub fn new() -> T {
+  T {
+    offset_ns: None,
+  }
+}
+
+impl T {
+  /// Record that a message stamped `server_ns` by the server arrived at local time `local_ns`.
+  pub fn observe(&mut self, server_ns: u64, local_ns: u64) {
+    let offset = local_ns as i64 - server_ns as i64;
+    self.offset_ns =
+      match self.offset_ns {
+        None => Some(offset),
+        Some(prev) => Some(if offset < prev { offset } else { prev }),
+      };
+  }
+
+  /// Convert a server timestamp to local time. Before anything's been observed, this assumes the clocks agree.
+  pub fn to_local(&self, server_ns: u64) -> u64 {
+    (server_ns as i64 + self.offset_ns.unwrap_or(0)) as u64
+  }
+
+  /// Convert a local time to server time; the inverse of `to_local`.
+  pub fn to_server(&self, local_ns: u64) -> u64 {
+    (local_ns as i64 - self.offset_ns.unwrap_or(0)) as u64
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_fastest_trip_wins() {
+    let mut clock = new();
+    clock.observe(1000, 5300);
+    clock.observe(2000, 6100);
+    clock.observe(3000, 7900);
+    assert_eq!(clock.to_local(4000), 8100);
+    assert_eq!(clock.to_server(8100), 4000);
+  }
+}
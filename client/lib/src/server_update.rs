@@ -1,13 +1,14 @@
 #![allow(missing_docs)]
 
 use cgmath;
-use cgmath::{Point3, EuclideanSpace, InnerSpace, ElementWise};
+use cgmath::{Point3, Vector2, Matrix3, EuclideanSpace, InnerSpace, ElementWise};
 use collision::{Aabb3};
 use rand::Rng;
 use stopwatch;
 use time;
 
 use common::color::Color4;
+use common::math;
 use common::protocol;
 
 use audio_loader;
@@ -21,6 +22,9 @@ const TRIANGLES_PER_BOX: u32 = 12;
 const VERTICES_PER_TRIANGLE: u32 = 3;
 const TRIANGLE_VERTICES_PER_BOX: u32 = TRIANGLES_PER_BOX * VERTICES_PER_TRIANGLE;
 
+/// How far the player walks between footprints.
+const FOOTPRINT_STRIDE: f32 = 1.2;
+
 /// dispatch a server message among the subsystems
 pub fn apply_server_update<UpdateView, UpdateAudio, UpdateServer, EnqueueTerrainLoad>(
   client               : &client::T,
@@ -32,16 +36,22 @@ pub fn apply_server_update<UpdateView, UpdateAudio, UpdateServer, EnqueueTerrain
 ) where
   UpdateView         : FnMut(view::update::T),
   UpdateAudio        : FnMut(audio_thread::Message),
-  UpdateServer       : FnMut(protocol::ClientToServer),
+  UpdateServer       : FnMut(protocol::Request),
   EnqueueTerrainLoad : FnMut(terrain::Load),
 {
   stopwatch::time("apply_server_update", move || {
     match update {
-      protocol::ServerToClient::LeaseId(_) => {
+      protocol::ServerToClient::LeaseId(..) => {
         warn!("Client ID has already been leased.");
       },
+      protocol::ServerToClient::Rejected(err) => {
+        warn!("Unexpected rejection after being admitted: {}", err);
+      },
+      protocol::ServerToClient::Queued(position, _) => {
+        warn!("Unexpected Queued event after being admitted: {}", position);
+      },
       protocol::ServerToClient::Ping => {
-        update_server(protocol::ClientToServer::Ping(client.id));
+        update_server(protocol::Request::Ping(client.id));
       },
       protocol::ServerToClient::PlayerAdded(id, _) => {
         warn!("Unexpected PlayerAdded event: {:?}.", id);
@@ -62,17 +72,87 @@ pub fn apply_server_update<UpdateView, UpdateAudio, UpdateServer, EnqueueTerrain
         let position = Point3::from_vec(position);
 
         *client.player_position.lock().unwrap() = position;
+        *client.player_feet.lock().unwrap() =
+          Point3::new((bounds.min.x + bounds.max.x) / 2.0, bounds.min.y, (bounds.min.z + bounds.max.z) / 2.0);
+        update_view(view::update::MoveCamera(position));
+        draw_attached(client, protocol::EntityRef::Player(player_id), &bounds, update_view);
+      },
+      protocol::ServerToClient::Teleported(player_id, position) => {
+        if player_id != client.player_id {
+          warn!("Teleported event for another client's player: {:?}", player_id);
+          return
+        }
+        info!("Teleported to {:?}", position);
+        // Recenter terrain loading (which follows the player) and don't count the jump as a footstep.
+        *client.player_position.lock().unwrap() = position;
+        *client.last_footstep.lock().unwrap() = position;
+        *client.last_footprint.lock().unwrap() = None;
         update_view(view::update::MoveCamera(position));
       },
-      protocol::ServerToClient::UpdateMob(id, bounds) => {
-        let mesh = to_triangles(&bounds, &Color4::of_rgba(1.0, 0.0, 0.0, 1.0));
+      protocol::ServerToClient::UpdatePlayerEntity(player_id, bounds, rotation, attachment) => {
+        let entity = protocol::EntityRef::Player(player_id);
+        match attachment {
+          None => {
+            client.attachments.lock().unwrap().remove(&entity);
+            // These get drawn by [update_remote_entities].
+            client.player_snapshots.lock().unwrap().push(player_id, time::precise_time_ns(), bounds, rotation.x);
+          },
+          Some(attachment) => {
+            client.player_snapshots.lock().unwrap().remove(player_id);
+            attach(client, entity, attachment, bounds, rotation.x, update_view);
+          },
+        }
+      },
+      protocol::ServerToClient::PlayerRemoved(player_id) => {
+        client.player_snapshots.lock().unwrap().remove(player_id);
+        client.attachments.lock().unwrap().remove(&protocol::EntityRef::Player(player_id));
+        update_view(view::update::RemovePlayer(player_id));
+      },
+      protocol::ServerToClient::UpdateMob(id, bounds, attachment) => {
+        let entity = protocol::EntityRef::Mob(id);
+        match attachment {
+          None => {
+            client.attachments.lock().unwrap().remove(&entity);
+            client.mob_snapshots.lock().unwrap().push(id, time::precise_time_ns(), bounds, 0.0);
+          },
+          Some(attachment) => {
+            client.mob_snapshots.lock().unwrap().remove(id);
+            attach(client, entity, attachment, bounds, 0.0, update_view);
+          },
+        }
+      },
+      protocol::ServerToClient::UpdateProjectile(id, bounds) => {
+        let mesh = to_triangles(&bounds, &Color4::of_rgba(0.4, 0.4, 0.4, 1.0));
         update_view(view::update::UpdateMob(id, mesh));
       },
-      protocol::ServerToClient::UpdateSun(fraction) => {
+      protocol::ServerToClient::RemoveProjectile(id) => {
+        update_view(view::update::RemoveMob(id));
+      },
+      protocol::ServerToClient::UpdateBeacon(id, position) => {
+        client.beacons.lock().unwrap().insert(id, position);
+        update_view(view::update::UpdateBeacon(id, position));
+      },
+      protocol::ServerToClient::RemoveBeacon(id) => {
+        client.beacons.lock().unwrap().remove(&id);
+        update_view(view::update::RemoveBeacon(id));
+      },
+      protocol::ServerToClient::PhysicsBounds(bounds) => {
+        update_view(view::update::SetPhysicsBounds(bounds));
+      },
+      protocol::ServerToClient::UpdateWeather(weather) => {
+        update_view(view::update::SetWeather(weather));
+      },
+      protocol::ServerToClient::UpdateSun(sun) => {
+        let time_ns = {
+          let mut server_clock = client.server_clock.lock().unwrap();
+          server_clock.observe(sun.time_ns, time::precise_time_ns());
+          server_clock.to_local(sun.time_ns)
+        };
         update_view(view::update::SetSun(
-          view::light::Sun {
-            progression: fraction,
-            rotation: 0.0,
+          view::light::SunClock {
+            phase    : sun.phase as f64,
+            time_ns  : time_ns,
+            cycle_ns : sun.cycle_ns,
           }
         ));
       },
@@ -95,6 +175,46 @@ pub fn apply_server_update<UpdateView, UpdateAudio, UpdateServer, EnqueueTerrain
           }
         );
       },
+      protocol::ServerToClient::UnloadBlocks(voxels) => {
+        enqueue_terrain_load(terrain::Load::Unload { voxels: voxels });
+      },
+      protocol::ServerToClient::MiningProgress(progress) => {
+        update_view(view::update::SetMiningProgress(progress));
+      },
+      protocol::ServerToClient::CommandFailed(command, err) => {
+        warn!("/{} failed: {}", command, err);
+        update_view(view::update::Notify(err));
+      },
+      protocol::ServerToClient::CommandOutput(command, output) => {
+        info!("/{}: {}", command, output);
+        update_view(view::update::Notify(output));
+      },
+      protocol::ServerToClient::EditDenied(reason) => {
+        warn!("Edit denied: {}", reason);
+        update_view(view::update::EditDenied);
+        update_view(view::update::Notify(reason));
+      },
+      protocol::ServerToClient::Announcement(text) => {
+        info!("Server: {}", text);
+        update_view(view::update::Notify(text));
+      },
+      protocol::ServerToClient::HealthUpdate(health) => {
+        if health == 0 {
+          info!("You died");
+        } else {
+          debug!("Health {}/{}", health, protocol::MAX_HEALTH);
+        }
+        *client.health.lock().unwrap() = health;
+      },
+      protocol::ServerToClient::Kicked(reason) => {
+        warn!("Disconnected by the server: {}", reason);
+        *client.server_shutdown.lock().unwrap() = Some(time::precise_time_ns());
+      },
+      protocol::ServerToClient::ShuttingDown(reason, eta_ns) => {
+        warn!("Server shutting down in {}s: {}", eta_ns / 1_000_000_000, reason);
+        update_view(view::update::Notify(format!("Shutting down in {}s", eta_ns / 1_000_000_000)));
+        *client.server_shutdown.lock().unwrap() = Some(time::precise_time_ns() + eta_ns);
+      },
       protocol::ServerToClient::Collision(collision_type) => {
         if let protocol::Collision::PlayerTerrain(..) = collision_type {
           let player_position = *client.player_position.lock().unwrap();
@@ -104,12 +224,127 @@ pub fn apply_server_update<UpdateView, UpdateAudio, UpdateServer, EnqueueTerrain
             let idx = client.rng.lock().unwrap().gen_range(1, 17 + 1);
             update_audio(audio_thread::Message::PlayOneShot(audio_loader::SoundId::Footstep(idx)));
           }
+          leave_footprint(client, update_view);
         }
       }
     }
   })
 }
 
+/// Leave a footprint if the player's walked far enough along the ground since the last one.
+fn leave_footprint<UpdateView>(client: &client::T, update_view: &mut UpdateView) where
+  UpdateView : FnMut(view::update::T),
+{
+  let feet = *client.player_feet.lock().unwrap();
+  let mut last_footprint = client.last_footprint.lock().unwrap();
+  let last =
+    match *last_footprint {
+      None => {
+        *last_footprint = Some(feet);
+        return
+      },
+      Some(last) => last,
+    };
+  let step = Vector2::new(feet.x - last.x, feet.z - last.z);
+  if step.magnitude() >= FOOTPRINT_STRIDE {
+    *last_footprint = Some(feet);
+    update_view(view::update::Footprint(feet, step.normalize()));
+  }
+}
+
+/// Send the view interpolated meshes for other players and mobs, as of local time `now_ns`.
+pub fn update_remote_entities<UpdateView>(
+  client      : &client::T,
+  now_ns      : u64,
+  update_view : &mut UpdateView,
+) where
+  UpdateView : FnMut(view::update::T),
+{
+  // Parents are drawn before anything attached to them, so they're never a frame apart.
+  let mut moved = Vec::new();
+  client.player_snapshots.lock().unwrap().sample(now_ns, |player_id, bounds, rotation| {
+    draw_entity(protocol::EntityRef::Player(player_id), bounds, rotation, update_view);
+    moved.push((protocol::EntityRef::Player(player_id), *bounds));
+  });
+  client.mob_snapshots.lock().unwrap().sample(now_ns, |id, bounds, _| {
+    draw_entity(protocol::EntityRef::Mob(id), bounds, 0.0, update_view);
+    moved.push((protocol::EntityRef::Mob(id), *bounds));
+  });
+  for (entity, bounds) in moved {
+    draw_attached(client, entity, &bounds, update_view);
+  }
+}
+
+/// Send the view a mesh for some other client's player or a mob.
+fn draw_entity<UpdateView>(
+  entity      : protocol::EntityRef,
+  bounds      : &Aabb3<f32>,
+  rotation    : f32,
+  update_view : &mut UpdateView,
+) where
+  UpdateView : FnMut(view::update::T),
+{
+  match entity {
+    protocol::EntityRef::Player(player_id) => {
+      let mut mesh = to_triangles(bounds, &Color4::of_rgba(0.0, 0.8, 0.2, 1.0));
+      rotate_lateral(&mut mesh, bounds, rotation);
+      update_view(view::update::UpdatePlayer(player_id, mesh));
+    },
+    protocol::EntityRef::Mob(id) => {
+      let mesh = to_triangles(bounds, &Color4::of_rgba(1.0, 0.0, 0.0, 1.0));
+      update_view(view::update::UpdateMob(id, mesh));
+    },
+  }
+}
+
+/// Record that an entity is attached to another, and draw it where the server says it is for now.
+fn attach<UpdateView>(
+  client      : &client::T,
+  entity      : protocol::EntityRef,
+  attachment  : protocol::Attachment,
+  bounds      : Aabb3<f32>,
+  rotation    : f32,
+  update_view : &mut UpdateView,
+) where
+  UpdateView : FnMut(view::update::T),
+{
+  client.attachments.lock().unwrap().insert(entity, (attachment, bounds, rotation));
+  draw_entity(entity, &bounds, rotation, update_view);
+  draw_attached(client, entity, &bounds, update_view);
+}
+
+/// Draw everything attached to `parent` (and everything attached to those, and so on) relative to `parent_bounds`.
+fn draw_attached<UpdateView>(
+  client        : &client::T,
+  parent        : protocol::EntityRef,
+  parent_bounds : &Aabb3<f32>,
+  update_view   : &mut UpdateView,
+) where
+  UpdateView : FnMut(view::update::T),
+{
+  let children: Vec<(protocol::EntityRef, protocol::Attachment, Aabb3<f32>, f32)> =
+    client.attachments.lock().unwrap().iter()
+    .filter(|&(_, &(attachment, _, _))| attachment.parent == parent)
+    .map(|(&child, &(attachment, bounds, rotation))| (child, attachment, bounds, rotation))
+    .collect();
+  let parent_center = math::center(parent_bounds);
+  for (child, attachment, bounds, rotation) in children {
+    let shift = parent_center + attachment.offset - math::center(&bounds);
+    let bounds = Aabb3::new(bounds.min + shift, bounds.max + shift);
+    draw_entity(child, &bounds, rotation, update_view);
+    draw_attached(client, child, &bounds, update_view);
+  }
+}
+
+/// Rotate a mesh around the vertical axis through the center of its bounds.
+fn rotate_lateral(vertices: &mut [ColoredVertex], bounds: &Aabb3<f32>, angle: f32) {
+  let center = math::center(bounds);
+  let rotation = Matrix3::from_angle_y(cgmath::Rad(angle));
+  for vertex in vertices {
+    vertex.position = center + rotation * (vertex.position - center);
+  }
+}
+
 fn to_triangles(
   bounds: &Aabb3<f32>,
   c: &Color4<f32>,
@@ -0,0 +1,83 @@
+//! How much of the sky each terrain vertex can see, so ambient light can fade out in caves and under overhangs.
+//! This is estimated by casting a few rays up into the sky from the vertex and counting how many get out.
+
+use cgmath::{Point3, Vector3, InnerSpace};
+use collision::{Ray3};
+use std;
+
+use common::fnv_map;
+use common::voxel;
+
+/// Anything further than this along a ray doesn't block the sky.
+const SKY_DISTANCE: f32 = 64.0;
+
+/// Directions to look for the sky in: straight up, and a ring at 45 degrees.
+fn rays() -> [Vector3<f32>; 5] {
+  let d = std::f32::consts::FRAC_1_SQRT_2;
+  [
+    Vector3::new(0.0, 1.0, 0.0),
+    Vector3::new(d, d, 0.0),
+    Vector3::new(-d, d, 0.0),
+    Vector3::new(0.0, d, d),
+    Vector3::new(0.0, d, -d),
+  ]
+}
+
+/// Sky visibility for the vertices of one mesh. Vertices are shared between polygons, so results are cached.
+pub struct T<'a> {
+  voxels     : &'a voxel::tree::T,
+  voxel_size : f32,
+  cache      : fnv_map::T<(u32, u32, u32), f32>,
+}
+
+/// Estimate sky visibility against `voxels`, for a mesh made of voxels `2^lg_voxel_size` wide.
+pub fn new<'a>(voxels: &'a voxel::tree::T, lg_voxel_size: i16) -> T<'a> {
+  T {
+    voxels     : voxels,
+    voxel_size : (1 << lg_voxel_size) as f32,
+    cache      : fnv_map::new(),
+  }
+}
+
+impl<'a> T<'a> {
+  /// The fraction, in [0, 1], of the sky that's visible from a vertex.
+  pub fn visibility(&mut self, position: &Point3<f32>, normal: &Vector3<f32>) -> f32 {
+    let key = (position.x.to_bits(), position.y.to_bits(), position.z.to_bits());
+    if let Some(&visibility) = self.cache.get(&key) {
+      return visibility
+    }
+
+    // Start a little way off the surface, and ignore anything right next to the start, so the surface doesn't
+    // shadow itself.
+    let origin = *position + *normal * self.voxel_size;
+    let min_distance = 1.5 * self.voxel_size;
+    let rays = rays();
+    let mut open = 0;
+    for direction in &rays {
+      let blocked =
+        self.voxels.cast_ray(
+          &Ray3::new(origin, *direction),
+          &mut |bounds, voxel| {
+            match voxel {
+              &voxel::Volume(voxel::Material::Empty) => None,
+              _ => {
+                let distance = (bounds.center() - origin).magnitude();
+                if distance < min_distance {
+                  None
+                } else {
+                  Some(distance <= SKY_DISTANCE)
+                }
+              },
+            }
+          },
+        );
+      if blocked != Some(true) {
+        open += 1;
+      }
+    }
+
+    let visibility = open as f32 / rays.len() as f32;
+    self.cache.insert(key, visibility);
+    visibility
+  }
+}
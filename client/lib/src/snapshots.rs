@@ -0,0 +1,189 @@
+//! Buffer timestamped position updates for remote entities, and play them back smoothly a little in the past.
+//! Updates arrive at irregular intervals, so rendering them as they come in looks jittery. Rendering slightly
+//! behind means there's usually an update on either side of the render time to interpolate between.
+
+use cgmath::{Point3};
+use collision::{Aabb3};
+use std;
+use std::collections::VecDeque;
+use std::hash::Hash;
+
+use common::fnv_map;
+
+/// How far in the past to render remote entities.
+pub const INTERPOLATION_DELAY_NS: u64 = 100_000_000;
+/// When updates are late, extrapolate from the last known motion for at most this long, then stop.
+pub const MAX_EXTRAPOLATION_NS: u64 = 200_000_000;
+
+/// An entity's state as of some local time.
+#[derive(Debug, Clone, Copy)]
+pub struct Snapshot {
+  #[allow(missing_docs)]
+  pub time_ns  : u64,
+  #[allow(missing_docs)]
+  pub bounds   : Aabb3<f32>,
+  /// Lateral rotation, in radians.
+  pub rotation : f32,
+}
+
+struct Entity {
+  // Ordered by time.
+  snapshots : VecDeque<Snapshot>,
+  // The last (bounds, rotation) handed out by `sample`.
+  last      : Option<(Aabb3<f32>, f32)>,
+}
+
+/// Snapshot buffers for a set of entities.
+pub struct T<Id> {
+  entities: fnv_map::T<Id, Entity>,
+}
+
+#[allow(missing_docs)]
+pub fn new<Id: Eq + Hash>() -> T<Id> {
+  T {
+    entities: fnv_map::new(),
+  }
+}
+
+fn lerp_point(x: &Point3<f32>, y: &Point3<f32>, t: f32) -> Point3<f32> {
+  *x + (*y - *x) * t
+}
+
+fn lerp_angle(x: f32, y: f32, t: f32) -> f32 {
+  // Go the short way around.
+  let two_pi = 2.0 * std::f32::consts::PI;
+  let mut d = (y - x) % two_pi;
+  if d > std::f32::consts::PI {
+    d -= two_pi;
+  } else if d < -std::f32::consts::PI {
+    d += two_pi;
+  }
+  x + d * t
+}
+
+/// Interpolate (or, for `t` outside [0, 1], extrapolate) between two snapshots.
+fn blend(s0: &Snapshot, s1: &Snapshot, t: f32) -> (Aabb3<f32>, f32) {
+  let bounds =
+    Aabb3::new(
+      lerp_point(&s0.bounds.min, &s1.bounds.min, t),
+      lerp_point(&s0.bounds.max, &s1.bounds.max, t),
+    );
+  (bounds, lerp_angle(s0.rotation, s1.rotation, t))
+}
+
+fn state_at(snapshots: &VecDeque<Snapshot>, time_ns: u64) -> (Aabb3<f32>, f32) {
+  let first = snapshots[0];
+  if time_ns <= first.time_ns || snapshots.len() == 1 {
+    return (first.bounds, first.rotation)
+  }
+
+  for i in 1 .. snapshots.len() {
+    let s1 = snapshots[i];
+    if time_ns <= s1.time_ns {
+      let s0 = snapshots[i - 1];
+      let t = (time_ns - s0.time_ns) as f32 / (s1.time_ns - s0.time_ns) as f32;
+      return blend(&s0, &s1, t)
+    }
+  }
+
+  // We're past the last update: keep going the way it was going, for a little while.
+  let s0 = snapshots[snapshots.len() - 2];
+  let s1 = snapshots[snapshots.len() - 1];
+  let overshoot = std::cmp::min(time_ns - s1.time_ns, MAX_EXTRAPOLATION_NS);
+  let t = 1.0 + overshoot as f32 / (s1.time_ns - s0.time_ns) as f32;
+  blend(&s0, &s1, t)
+}
+
+impl<Id: Eq + Hash + Copy> T<Id> {
+  /// Record an entity's state as of local time `time_ns`. Updates that arrive out of order are dropped.
+  pub fn push(&mut self, id: Id, time_ns: u64, bounds: Aabb3<f32>, rotation: f32) {
+    let entity =
+      self.entities.entry(id).or_insert_with(|| {
+        Entity {
+          snapshots : VecDeque::new(),
+          last      : None,
+        }
+      });
+    if let Some(back) = entity.snapshots.back() {
+      if time_ns <= back.time_ns {
+        return
+      }
+    }
+    entity.snapshots.push_back(
+      Snapshot {
+        time_ns  : time_ns,
+        bounds   : bounds,
+        rotation : rotation,
+      }
+    );
+  }
+
+  /// Forget an entity.
+  pub fn remove(&mut self, id: Id) {
+    self.entities.remove(&id);
+  }
+
+  /// Find where every entity should be drawn at local time `now_ns`, and call `f` for each one whose position or
+  /// rotation has changed since the last call.
+  pub fn sample<F>(&mut self, now_ns: u64, mut f: F) where
+    F: FnMut(Id, &Aabb3<f32>, f32),
+  {
+    let render_ns = now_ns.saturating_sub(INTERPOLATION_DELAY_NS);
+    for (&id, entity) in self.entities.iter_mut() {
+      // Drop snapshots we've rendered past, keeping one before the render time to interpolate from, or two if
+      // we're past all of them so we can extrapolate.
+      while entity.snapshots.len() > 2 && entity.snapshots[1].time_ns <= render_ns {
+        entity.snapshots.pop_front();
+      }
+
+      let (bounds, rotation) = state_at(&entity.snapshots, render_ns);
+      let changed =
+        match entity.last {
+          None => true,
+          Some((last_bounds, last_rotation)) =>
+            last_bounds.min != bounds.min || last_bounds.max != bounds.max || last_rotation != rotation,
+        };
+      if changed {
+        entity.last = Some((bounds, rotation));
+        f(id, &bounds, rotation);
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use cgmath::Point3;
+  use collision::Aabb3;
+
+  use super::*;
+
+  fn at(x: f32) -> Aabb3<f32> {
+    Aabb3::new(Point3::new(x, 0.0, 0.0), Point3::new(x + 1.0, 1.0, 1.0))
+  }
+
+  fn sample_x(snapshots: &mut T<u32>, now_ns: u64) -> Option<f32> {
+    let mut r = None;
+    snapshots.sample(now_ns, |_, bounds, _| r = Some(bounds.min.x));
+    r
+  }
+
+  #[test]
+  fn test_interpolates_in_the_past() {
+    let mut snapshots = new();
+    snapshots.push(0, 1_000_000_000, at(0.0), 0.0);
+    snapshots.push(0, 1_100_000_000, at(10.0), 0.0);
+    assert_eq!(sample_x(&mut snapshots, 1_150_000_000), Some(5.0));
+    // Nothing moved.
+    assert_eq!(sample_x(&mut snapshots, 1_150_000_000), None);
+  }
+
+  #[test]
+  fn test_extrapolation_is_capped() {
+    let mut snapshots = new();
+    snapshots.push(0, 1_000_000_000, at(0.0), 0.0);
+    snapshots.push(0, 1_100_000_000, at(1.0), 0.0);
+    let x = sample_x(&mut snapshots, 1_100_000_000 + INTERPOLATION_DELAY_NS + 10 * MAX_EXTRAPOLATION_NS).unwrap();
+    assert!((x - 3.0).abs() < 0.001);
+  }
+}
@@ -1,9 +1,10 @@
 //! Keep track of terrain load state, and store voxels cached from the server.
 
 use cgmath;
+use cgmath::InnerSpace;
 use collision;
-use rand;
 use std;
+use stopwatch;
 use time;
 
 use common::{fnv_set, fnv_map};
@@ -27,6 +28,12 @@ pub enum Load {
     /// these voxels because they were updated.
     time_requested : Option<u64>,
   },
+  /// The server says we're done with these voxels.
+  Unload {
+    voxels : Vec<voxel::bounds::T>,
+  },
+  /// Lighting or materials have changed; recompute the shading of every loaded chunk from the cached voxels.
+  Reshade,
 }
 
 #[allow(missing_docs)]
@@ -38,21 +45,50 @@ pub struct T {
   /// The voxels we have cached from the server.
   voxels              : voxel::tree::T,
   max_load_distance   : u32,
+  /// The most detailed LOD we're allowed to load.
+  lod_floor           : lod::T,
+  lod_thresholds      : lod::Thresholds,
   queue               : std::collections::VecDeque<Load>,
 }
 
 #[allow(missing_docs)]
-pub fn new(max_load_distance: u32) -> T {
+pub fn new(max_load_distance: u32, lod_floor: lod::T, lod_thresholds: lod::Thresholds) -> T {
   T {
     loaded_chunks       : fnv_map::new(),
     chunk_voxels_loaded : fnv_map::new(),
     voxels              : voxel::tree::new(),
     max_load_distance   : max_load_distance,
+    lod_floor           : lod_floor,
+    lod_thresholds      : lod_thresholds,
     queue               : std::collections::VecDeque::new(),
   }
 }
 
 impl T {
+  /// Whether the cached voxels leave a clear view from `from` to `to`. Voxels we don't have don't block anything.
+  pub fn line_of_sight(&self, from: &cgmath::Point3<f32>, to: &cgmath::Point3<f32>) -> bool {
+    let offset = *to - *from;
+    let distance = offset.magnitude();
+    if distance <= 0.0 {
+      return true
+    }
+    let blocked =
+      self.voxels.cast_ray(
+        &collision::Ray3::new(*from, offset / distance),
+        &mut |bounds, voxel| {
+          match voxel {
+            &voxel::Volume(voxel::Material::Empty) => None,
+            // Only voxels entirely in front of `to` get in the way.
+            _ => {
+              let far_side = (bounds.center() - *from).magnitude() + (bounds.lg_size as f32).exp2() / 2.0;
+              Some(far_side < distance)
+            },
+          }
+        },
+      );
+    blocked != Some(true)
+  }
+
   /// return the LOD at which a chunk is loaded
   pub fn load_state(&self, chunk_position: &chunk::position::T) -> Option<lod::T> {
     self.loaded_chunks
@@ -88,17 +124,15 @@ impl T {
   }
 
   /// Iterate through some enqueued voxel loads and load any updated chunks.
-  pub fn tick<Rng, UpdateView>(
+  pub fn tick<UpdateView>(
     &mut self,
     terrain_allocator : &std::sync::Mutex<id_allocator::T<view::entity::id::Terrain>>,
     grass_allocator   : &std::sync::Mutex<id_allocator::T<view::entity::id::Grass>>,
-    rng               : &mut Rng,
     chunk_stats       : &mut chunk_stats::T,
     update_view       : &mut UpdateView,
     player_position   : &cgmath::Point3<f32>,
   ) where
     UpdateView : FnMut(view::update::T),
-    Rng        : rand::Rng,
   {
     let start = time::precise_time_ns();
     while let Some(msg) = self.queue.pop_front() {
@@ -107,7 +141,6 @@ impl T {
           self.load_voxels(
             terrain_allocator,
             grass_allocator,
-            rng,
             chunk_stats,
             update_view,
             player_position,
@@ -115,6 +148,12 @@ impl T {
             time_requested,
           );
         },
+        Load::Unload { voxels } => {
+          self.unload_voxels(update_view, voxels);
+        },
+        Load::Reshade => {
+          self.reshade(update_view);
+        },
       }
 
       if time::precise_time_ns() - start >= 1_000_000 {
@@ -124,22 +163,20 @@ impl T {
   }
 
   #[inline(never)]
-  fn force_load_chunk<Rng, UpdateView>(
+  fn force_load_chunk<UpdateView>(
     &mut self,
     terrain_allocator : &std::sync::Mutex<id_allocator::T<view::entity::id::Terrain>>,
     grass_allocator   : &std::sync::Mutex<id_allocator::T<view::entity::id::Grass>>,
-    rng               : &mut Rng,
     chunk_stats       : &mut chunk_stats::T,
     update_view       : &mut UpdateView,
     chunk_position    : &chunk::position::T,
     lod               : lod::T,
   ) where
     UpdateView : FnMut(view::update::T),
-    Rng        : rand::Rng,
   {
     debug!("generate {:?} at {:?}", chunk_position, lod);
     let mesh_chunk: view::chunked_terrain::T =
-      terrain_mesh::generate(&self.voxels, chunk_stats, &chunk_position, lod, terrain_allocator, grass_allocator, rng);
+      terrain_mesh::generate(&self.voxels, chunk_stats, &chunk_position, lod, terrain_allocator, grass_allocator);
 
     let mut updates = Vec::new();
 
@@ -168,20 +205,68 @@ impl T {
     update_view(view::update::Atomic(updates));
   }
 
+  /// Recompute the shading of every loaded chunk, without regenerating or re-uploading its geometry.
+  /// Chunks are always regenerated when their voxels change, so every loaded chunk's voxels are still cached.
+  pub fn reshade<UpdateView>(&mut self, update_view: &mut UpdateView) where
+    UpdateView : FnMut(view::update::T),
+  {
+    stopwatch::time("terrain.reshade", || {
+      debug!("Reshading {} chunks", self.loaded_chunks.len());
+      for (chunk_position, &(ref ids, lod)) in &self.loaded_chunks {
+        if ids.chunk_ids.is_empty() {
+          continue
+        }
+        let shading = terrain_mesh::shade(&self.voxels, chunk_position, lod, &ids.chunk_ids);
+        update_view(view::update::ReshadeMesh(Box::new(shading)));
+      }
+    })
+  }
+
+  /// Stand in for a chunk that isn't loaded at all, by loading it at the most detailed LOD coarser than `lod` whose
+  /// voxels we already have. When the voxels for the LOD it should be at arrive, the placeholder is swapped out in the
+  /// same `Atomic` update that loads its replacement.
+  fn load_placeholder<UpdateView>(
+    &mut self,
+    terrain_allocator : &std::sync::Mutex<id_allocator::T<view::entity::id::Terrain>>,
+    grass_allocator   : &std::sync::Mutex<id_allocator::T<view::entity::id::Grass>>,
+    chunk_stats       : &mut chunk_stats::T,
+    update_view       : &mut UpdateView,
+    chunk_position    : &chunk::position::T,
+    lod               : lod::T,
+  ) where
+    UpdateView : FnMut(view::update::T),
+  {
+    for coarser in lod.0 + 1 .. lod::COUNT as u32 {
+      let coarser = lod::T(coarser);
+      if coarser.lg_sample_size() == lod.lg_sample_size() || !self.all_voxels_loaded(*chunk_position, coarser) {
+        continue
+      }
+      debug!("Placeholder for {:?} at {:?} until {:?} arrives", chunk_position, coarser, lod);
+      self.force_load_chunk(
+        terrain_allocator,
+        grass_allocator,
+        chunk_stats,
+        update_view,
+        chunk_position,
+        coarser,
+      );
+      return
+    }
+  }
+
   /// try to load a chunk into VRAM.
   /// if some voxels are missing, returns an Err of all the voxels that need to be fetched from the server.
-  pub fn load_chunk<Rng, UpdateView>(
+  /// If the chunk isn't loaded at any LOD yet, a coarser version of it is loaded in the meantime (if we can).
+  pub fn load_chunk<UpdateView>(
     &mut self,
     terrain_allocator : &std::sync::Mutex<id_allocator::T<view::entity::id::Terrain>>,
     grass_allocator   : &std::sync::Mutex<id_allocator::T<view::entity::id::Grass>>,
-    rng               : &mut Rng,
     chunk_stats       : &mut chunk_stats::T,
     update_view       : &mut UpdateView,
     chunk_position    : &chunk::position::T,
     lod               : lod::T,
   ) -> Result<(), Vec<voxel::bounds::T>> where
     UpdateView : FnMut(view::update::T),
-    Rng        : rand::Rng,
   {
     let all_voxels_loaded =
       self.all_voxels_loaded(
@@ -192,7 +277,6 @@ impl T {
       self.force_load_chunk(
         terrain_allocator,
         grass_allocator,
-        rng,
         chunk_stats,
         update_view,
         chunk_position,
@@ -200,6 +284,17 @@ impl T {
       );
       Ok(())
     } else {
+      if self.load_state(chunk_position).is_none() {
+        self.load_placeholder(
+          terrain_allocator,
+          grass_allocator,
+          chunk_stats,
+          update_view,
+          chunk_position,
+          lod,
+        );
+      }
+
       let voxel_size = 1 << lod.lg_sample_size();
       let voxels =
         terrain_mesh::voxels_in(
@@ -287,7 +382,14 @@ impl T {
         continue;
       }
 
-      let lod = lod::of_distance(distance as u32);
+      let lod = lod::of_distance_with_floor(distance as u32, self.lod_floor, &self.lod_thresholds);
+      // An edit to the voxels a chunk is currently drawn from has to show up right away, even if the chunk is due to
+      // be replaced at another LOD. Otherwise its old surface, and the grass and trees on it, linger until then.
+      let lod =
+        match self.load_state(&chunk_position) {
+          Some(loaded_lod) if !new_voxel_loaded && loaded_lod.lg_sample_size() == bounds.lg_size => loaded_lod,
+          _ => lod,
+        };
       let lg_size = lod.lg_sample_size();
       if lg_size != bounds.lg_size {
         debug!(
@@ -305,11 +407,10 @@ impl T {
   }
 
   #[inline(never)]
-  fn load_voxels<Rng, UpdateView>(
+  fn load_voxels<UpdateView>(
     &mut self,
     terrain_allocator : &std::sync::Mutex<id_allocator::T<view::entity::id::Terrain>>,
     grass_allocator   : &std::sync::Mutex<id_allocator::T<view::entity::id::Grass>>,
-    rng               : &mut Rng,
     chunk_stats       : &mut chunk_stats::T,
     update_view       : &mut UpdateView,
     player_position   : &cgmath::Point3<f32>,
@@ -317,7 +418,6 @@ impl T {
     time_requested    : Option<u64>,
   ) where
     UpdateView : FnMut(view::update::T),
-    Rng        : rand::Rng,
   {
     let mut update_chunks = fnv_set::new();
     let response_time = time::precise_time_ns();
@@ -337,7 +437,6 @@ impl T {
         self.load_chunk(
           terrain_allocator,
           grass_allocator,
-          rng,
           chunk_stats,
           update_view,
           &chunk,
@@ -362,6 +461,49 @@ impl T {
     }
   }
 
+  /// Drop some voxels from the cache, along with any chunks that were built from them.
+  fn unload_voxels<UpdateView>(
+    &mut self,
+    update_view : &mut UpdateView,
+    voxels      : Vec<voxel::bounds::T>,
+  ) where
+    UpdateView : FnMut(view::update::T),
+  {
+    for bounds in voxels {
+      {
+        let node = self.voxels.get_mut_or_create(&bounds);
+        if node.data.take().is_none() {
+          continue
+        }
+      }
+
+      for chunk_position in updated_chunk_positions(&bounds) {
+        for lod in 0..lod::COUNT as u32 {
+          let lod = lod::T(lod);
+          if lod.lg_sample_size() != bounds.lg_size {
+            continue
+          }
+
+          let now_empty =
+            match self.chunk_voxels_loaded.get_mut(&(chunk_position, lod)) {
+              None => false,
+              Some(count) => {
+                *count -= 1;
+                *count == 0
+              },
+            };
+          if now_empty {
+            self.chunk_voxels_loaded.remove(&(chunk_position, lod));
+          }
+
+          if self.load_state(&chunk_position) == Some(lod) {
+            self.unload(update_view, &chunk_position);
+          }
+        }
+      }
+    }
+  }
+
   /// unload a chunk
   pub fn unload<UpdateView>(
     &mut self,
@@ -377,6 +519,21 @@ impl T {
       },
     }
   }
+
+  /// Forget the chunks the view evicted, identified by the first chunk ids of their meshes, so they get loaded again
+  /// when they're next wanted. Chunks that have been reloaded as a different mesh since are kept. Returns the positions
+  /// of the chunks that were forgotten.
+  pub fn forget_evicted(&mut self, first_chunk_ids: &[view::entity::id::Terrain]) -> Vec<chunk::position::T> {
+    let mut forgotten = Vec::new();
+    self.loaded_chunks.retain(|chunk_position, &mut (ref ids, _)| {
+      let evicted = ids.chunk_ids.first().map_or(false, |id| first_chunk_ids.contains(id));
+      if evicted {
+        forgotten.push(*chunk_position);
+      }
+      !evicted
+    });
+    forgotten
+  }
 }
 
 #[inline(never)]
@@ -4,7 +4,6 @@ use cgmath::{Point3, Vector3};
 use collision::{Aabb, Aabb3};
 use isosurface_extraction::dual_contouring;
 use num::iter::range_inclusive;
-use rand;
 use std::sync::Mutex;
 use stopwatch;
 
@@ -12,9 +11,13 @@ use common::id_allocator;
 use common::voxel;
 // TODO: Move the server-only parts to the server, like BLOCK_WIDTH and sample_info.
 
+use ambient_occlusion;
+use border_normals;
 use chunk;
 use chunk_stats;
+use greedy_mesh;
 use lod;
+use sky_occlusion;
 
 use view;
 use view::chunked_terrain;
@@ -114,102 +117,174 @@ mod voxel_storage {
   }
 }
 
+/// Polygons whose normals are further than this (the cosine of the angle) from straight up are too steep for grass.
+const MIN_GRASS_NORMAL_Y: f32 = 0.6;
+/// How many grass subtextures there are to pick from.
+const GRASS_TEXTURES: u32 = 9;
+
+/// Whether polygons of some material could have grass on them.
+fn grows_grass(material: voxel::Material, lod: lod::T) -> bool {
+  material == voxel::Material::Terrain && lod <= lod::MAX_GRASS_LOD
+}
+
+/// Whether a polygon has grass on it: it's grassy ground that isn't too steep.
+fn has_grass(polygon: &greedy_mesh::Polygon, lod: lod::T) -> bool {
+  let n = &polygon.normals;
+  grows_grass(polygon.material, lod) && (n.v1.y + n.v2.y + n.v3.y) / 3.0 >= MIN_GRASS_NORMAL_Y
+}
+
+/// A hash of where a polygon is, so the grass on it looks the same every time it's loaded.
+fn position_hash(vertices: &Triangle<Point3<f32>>) -> u32 {
+  let (v1, v2, v3) = (&vertices.v1, &vertices.v2, &vertices.v3);
+  // Quantize, so tiny differences in the extracted vertices don't change the hash.
+  let quantize = |x: f32| (x * 4.0 / 3.0).round() as i32 as u32;
+  let x = quantize(v1.x + v2.x + v3.x);
+  let y = quantize(v1.y + v2.y + v3.y);
+  let z = quantize(v1.z + v2.z + v3.z);
+  let mut h = x.wrapping_mul(73856093) ^ y.wrapping_mul(19349663) ^ z.wrapping_mul(83492791);
+  h ^= h >> 15;
+  h = h.wrapping_mul(2246822519);
+  h ^= h >> 13;
+  h
+}
+
+/// Extract the polygons of a chunk's mesh. This is deterministic, so the same voxels always give the same polygons
+/// in the same order.
+fn polygons(
+  voxels         : &voxel::tree::T,
+  chunk_position : &chunk::position::T,
+  lod            : lod::T,
+) -> Vec<greedy_mesh::Polygon> {
+  let lg_edge_samples = lod.lg_edge_samples();
+  let lg_sample_size = lod.lg_sample_size();
+
+  let low = *chunk_position.as_pnt();
+  let high = low + (&Vector3::new(1, 1, 1));
+  let low =
+    Point3::new(
+      low.x << lg_edge_samples,
+      low.y << lg_edge_samples,
+      low.z << lg_edge_samples,
+    );
+  let high =
+    Point3::new(
+      high.x << lg_edge_samples,
+      high.y << lg_edge_samples,
+      high.z << lg_edge_samples,
+    );
+
+  trace!("low {:?}", low);
+  trace!("high {:?}", high);
+
+  let mut polygons = Vec::new();
+  {
+    let mut edges = |direction, low_x, high_x, low_y, high_y, low_z, high_z| {
+      for x in range_inclusive(low_x, high_x) {
+      for y in range_inclusive(low_y, high_y) {
+      for z in range_inclusive(low_z, high_z) {
+        trace!("edge: {:?} {:?}", direction, Point3::new(x, y, z));
+        let edge =
+          dual_contouring::edge::T {
+            low_corner: Point3::new(x, y, z),
+            direction: direction,
+            lg_size: lg_sample_size,
+          };
+
+        let _ =
+          dual_contouring::edge::extract(
+            &mut voxel_storage::T { voxels: voxels },
+            &edge,
+            &mut |polygon: dual_contouring::polygon::T<voxel::Material>| {
+              polygons.push(
+                greedy_mesh::Polygon {
+                  vertices : tri(polygon.vertices[0], polygon.vertices[1], polygon.vertices[2]),
+                  normals  : tri(polygon.normals[0], polygon.normals[1], polygon.normals[2]),
+                  material : polygon.material,
+                }
+              );
+            }
+          );
+      }}}
+    };
+
+    edges(
+      dual_contouring::edge::Direction::X,
+      low.x, high.x - 1,
+      low.y, high.y - 1,
+      low.z, high.z - 1,
+    );
+    edges(
+      dual_contouring::edge::Direction::Y,
+      low.x, high.x - 1,
+      low.y, high.y - 1,
+      low.z, high.z - 1,
+    );
+    edges(
+      dual_contouring::edge::Direction::Z,
+      low.x, high.x - 1,
+      low.y, high.y - 1,
+      low.z, high.z - 1,
+    );
+  }
+
+  border_normals::smooth(voxels, &mut polygons, &low, &high, lg_sample_size);
+
+  // Grass is placed per polygon, so polygons with grass on them can't be merged without thinning it out.
+  stopwatch::time("terrain_mesh::merge_flat", || {
+    greedy_mesh::merge_flat(polygons, |material| !grows_grass(material, lod))
+  })
+}
+
+fn sky_visibility(sky: &mut sky_occlusion::T, polygon: &greedy_mesh::Polygon) -> Triangle<f32> {
+  stopwatch::time("terrain_mesh::sky_occlusion", || {
+    let (v, n) = (&polygon.vertices, &polygon.normals);
+    tri(sky.visibility(&v.v1, &n.v1), sky.visibility(&v.v2, &n.v2), sky.visibility(&v.v3, &n.v3))
+  })
+}
+
+fn occlusion(ao: &mut ambient_occlusion::T, polygon: &greedy_mesh::Polygon) -> Triangle<f32> {
+  stopwatch::time("terrain_mesh::ambient_occlusion", || {
+    let (v, n) = (&polygon.vertices, &polygon.normals);
+    tri(ao.occlusion(&v.v1, &n.v1), ao.occlusion(&v.v2, &n.v2), ao.occlusion(&v.v3, &n.v3))
+  })
+}
+
 #[allow(missing_docs)]
-pub fn generate<Rng: rand::Rng>(
+pub fn generate(
   voxels          : &voxel::tree::T,
   chunk_stats     : &mut chunk_stats::T,
   chunk_position  : &chunk::position::T,
   lod             : lod::T,
   chunk_allocator : &Mutex<id_allocator::T<view::entity::id::Terrain>>,
   grass_allocator : &Mutex<id_allocator::T<view::entity::id::Grass>>,
-  rng             : &mut Rng,
 ) -> view::chunked_terrain::T
 {
   stopwatch::time("terrain_mesh::generate", || {
-    let lg_edge_samples = lod.lg_edge_samples();
-    let lg_sample_size = lod.lg_sample_size();
-
     let mut chunked_terrain = chunked_terrain::empty();
 
-    let low = *chunk_position.as_pnt();
-    let high = low + (&Vector3::new(1, 1, 1));
-    let low =
-      Point3::new(
-        low.x << lg_edge_samples,
-        low.y << lg_edge_samples,
-        low.z << lg_edge_samples,
-      );
-    let high =
-      Point3::new(
-        high.x << lg_edge_samples,
-        high.y << lg_edge_samples,
-        high.z << lg_edge_samples,
-      );
-
-    trace!("low {:?}", low);
-    trace!("high {:?}", high);
-
-    {
-      let mut edges = |direction, low_x, high_x, low_y, high_y, low_z, high_z| {
-        for x in range_inclusive(low_x, high_x) {
-        for y in range_inclusive(low_y, high_y) {
-        for z in range_inclusive(low_z, high_z) {
-          trace!("edge: {:?} {:?}", direction, Point3::new(x, y, z));
-          let edge =
-            dual_contouring::edge::T {
-              low_corner: Point3::new(x, y, z),
-              direction: direction,
-              lg_size: lg_sample_size,
-            };
-
-          let _ =
-            dual_contouring::edge::extract(
-              &mut voxel_storage::T { voxels: voxels },
-              &edge,
-              &mut |polygon: dual_contouring::polygon::T<voxel::Material>| {
-                let vertices = tri(polygon.vertices[0], polygon.vertices[1], polygon.vertices[2]);
-                let normals = tri(polygon.normals[0], polygon.normals[1], polygon.normals[2]);
-                let material = polygon.material as i32;
-
-                let grass =
-                  if polygon.material == voxel::Material::Terrain && lod <= lod::MAX_GRASS_LOD {
-                    Some(chunked_terrain::PushGrass {
-                      tex_id : rng.gen_range(0, 9),
-                      id     : grass_allocator.lock().unwrap().allocate(),
-                    })
-                  } else {
-                    None
-                  };
-
-                chunked_terrain.push(
-                  &mut *chunk_allocator.lock().unwrap(),
-                  vertices,
-                  normals,
-                  material,
-                  grass,
-                );
-              }
-            );
-        }}}
-      };
-
-      edges(
-        dual_contouring::edge::Direction::X,
-        low.x, high.x - 1,
-        low.y, high.y - 1,
-        low.z, high.z - 1,
-      );
-      edges(
-        dual_contouring::edge::Direction::Y,
-        low.x, high.x - 1,
-        low.y, high.y - 1,
-        low.z, high.z - 1,
-      );
-      edges(
-        dual_contouring::edge::Direction::Z,
-        low.x, high.x - 1,
-        low.y, high.y - 1,
-        low.z, high.z - 1,
+    let mut sky = sky_occlusion::new(voxels, lod.lg_sample_size());
+    let mut ao = ambient_occlusion::new(voxels, lod.lg_sample_size());
+    for polygon in polygons(voxels, chunk_position, lod) {
+      let sky_visibility = sky_visibility(&mut sky, &polygon);
+      let occlusion = occlusion(&mut ao, &polygon);
+      let grass =
+        if has_grass(&polygon, lod) {
+          Some(chunked_terrain::PushGrass {
+            tex_id : position_hash(&polygon.vertices) % GRASS_TEXTURES,
+            id     : grass_allocator.lock().unwrap().allocate(),
+          })
+        } else {
+          None
+        };
+
+      chunked_terrain.push(
+        &mut *chunk_allocator.lock().unwrap(),
+        polygon.vertices,
+        polygon.normals,
+        polygon.material as i32,
+        sky_visibility,
+        occlusion,
+        grass,
       );
     }
 
@@ -218,6 +293,28 @@ pub fn generate<Rng: rand::Rng>(
   })
 }
 
+/// Recompute the shading of a chunk that was loaded as the vram chunks `chunk_ids`, from the same voxels it was
+/// generated from. Its geometry isn't touched.
+pub fn shade(
+  voxels         : &voxel::tree::T,
+  chunk_position : &chunk::position::T,
+  lod            : lod::T,
+  chunk_ids      : &[view::entity::id::Terrain],
+) -> chunked_terrain::Shading
+{
+  stopwatch::time("terrain_mesh::shade", || {
+    let mut shading = chunked_terrain::shading(chunk_ids);
+    let mut sky = sky_occlusion::new(voxels, lod.lg_sample_size());
+    let mut ao = ambient_occlusion::new(voxels, lod.lg_sample_size());
+    for (i, polygon) in polygons(voxels, chunk_position, lod).iter().enumerate() {
+      let sky_visibility = sky_visibility(&mut sky, polygon);
+      let occlusion = occlusion(&mut ao, polygon);
+      shading.set(i, polygon.material as i32, sky_visibility, occlusion);
+    }
+    shading
+  })
+}
+
 /// All the information required to construct a grass tuft in vram
 #[derive(Debug, Clone)]
 pub struct Grass {
@@ -1,9 +1,12 @@
 //! The main thread that processes updates from the client and the server and dispatches updates to other systems.
 
+use cgmath::Point3;
+use std;
 use std::sync::Mutex;
 use stopwatch;
 use time;
 
+use common::interval_timer::IntervalTimer;
 use common::protocol;
 use common::surroundings_loader;
 use common::surroundings_loader::LoadType;
@@ -13,31 +16,37 @@ use chunk;
 use chunk_stats;
 use client;
 use lod;
-use server_update::apply_server_update;
+use server_update::{apply_server_update, update_remote_entities};
 use terrain;
 use view;
 
 const MAX_OUTSTANDING_TERRAIN_REQUESTS: u32 = 1;
 
 #[allow(missing_docs)]
-pub fn update_thread<RecvServer, UpdateView0, UpdateView1, UpdateAudio, UpdateServer, EnqueueTerrainLoad>(
+pub fn update_thread<RecvServer, UpdateView, UpdateAudio, UpdateServer, EnqueueTerrainLoad>(
   quit                 : &Mutex<bool>,
   client               : &client::T,
   recv_server          : &mut RecvServer,
-  update_view0         : &mut UpdateView0,
-  update_view1         : &mut UpdateView1,
+  update_view          : &mut UpdateView,
   update_audio         : &mut UpdateAudio,
   update_server        : &mut UpdateServer,
   enqueue_terrain_load : &mut EnqueueTerrainLoad,
 ) where
   RecvServer         : FnMut() -> Option<protocol::ServerToClient>,
-  UpdateView0        : FnMut(view::update::T),
-  UpdateView1        : FnMut(view::update::T),
+  UpdateView         : FnMut(view::update::T),
   UpdateAudio        : FnMut(audio_thread::Message),
-  UpdateServer       : FnMut(protocol::ClientToServer),
+  UpdateServer       : FnMut(protocol::Request),
   EnqueueTerrainLoad : FnMut(terrain::Load),
 {
   let mut chunk_stats = chunk_stats::new();
+  // Interpolate remote entities about as often as the view renders.
+  let mut remote_entity_timer =
+    IntervalTimer::new(1_000_000_000 / view::thread::FRAMES_PER_SECOND, time::precise_time_ns());
+  // View updates are made with the terrain or the player's position locked, and the view thread takes those locks
+  // too, so pushing one then could wait on a full queue that the view can't get around to draining. Instead they're
+  // held here, and pushed after each step, once its locks are released.
+  let mut view_updates = Vec::new();
+  let mut occlusion_timer = IntervalTimer::new(view::beacons::OCCLUSION_CHECK_NS, time::precise_time_ns());
 
   'update_loop: loop {
     let should_quit = *quit.lock().unwrap();
@@ -46,15 +55,53 @@ pub fn update_thread<RecvServer, UpdateView0, UpdateView1, UpdateAudio, UpdateSe
     } else {
       stopwatch::time("update_iteration", || {
         stopwatch::time("process_server_updates", || {
-          process_server_updates(client, recv_server, update_view0, update_audio, update_server, enqueue_terrain_load);
+          process_server_updates(
+            client,
+            recv_server,
+            &mut |up| view_updates.push(up),
+            update_audio,
+            update_server,
+            enqueue_terrain_load,
+          );
+        });
+        push_view_updates(&mut view_updates, update_view);
+
+        stopwatch::time("update_remote_entities", || {
+          let now = time::precise_time_ns();
+          if remote_entity_timer.update(now) > 0 {
+            update_remote_entities(client, now, &mut |up| view_updates.push(up));
+          }
         });
+        push_view_updates(&mut view_updates, update_view);
 
         stopwatch::time("update_surroundings", || {
-          update_surroundings(client, &mut chunk_stats, update_view1, update_server);
+          update_surroundings(client, &mut chunk_stats, &mut |up| view_updates.push(up), update_server);
         });
+        push_view_updates(&mut view_updates, update_view);
+
+        let reshade = std::mem::replace(&mut *client.reshade_requested.lock().unwrap(), false);
+        if reshade {
+          client.terrain.lock().unwrap().enqueue(terrain::Load::Reshade);
+        }
 
         stopwatch::time("process_voxel_updates", || {
-          process_voxel_updates(client, &mut chunk_stats, update_view1);
+          process_voxel_updates(client, &mut chunk_stats, &mut |up| view_updates.push(up));
+        });
+        push_view_updates(&mut view_updates, update_view);
+
+        if occlusion_timer.update(time::precise_time_ns()) > 0 {
+          stopwatch::time("check_beacon_occlusion", || {
+            update_view(check_beacon_occlusion(client));
+          });
+        }
+
+        stopwatch::time("forget_evicted_meshes", || {
+          let evicted = std::mem::replace(&mut *client.evicted_meshes.lock().unwrap(), Vec::new());
+          if !evicted.is_empty() {
+            let forgotten = client.terrain.lock().unwrap().forget_evicted(&evicted);
+            // The surroundings loader thinks these are still loaded, so it has to be told to load them again.
+            client.surroundings_loader.lock().unwrap().reload(forgotten.iter().map(|position| *position.as_pnt()));
+          }
         });
       })
     }
@@ -64,6 +111,28 @@ pub fn update_thread<RecvServer, UpdateView0, UpdateView1, UpdateAudio, UpdateSe
   chunk_stats.output_to("vram_chunk_loads.out");
 }
 
+/// Check which beacons terrain hides from the player. This is done here rather than on the view thread, so rendering
+/// never waits on the terrain lock.
+fn check_beacon_occlusion(client: &client::T) -> view::update::T {
+  let beacons: Vec<_> = client.beacons.lock().unwrap().iter().map(|(&id, &position)| (id, position)).collect();
+  let eye = *client.player_position.lock().unwrap();
+  let terrain = client.terrain.lock().unwrap();
+  let line_of_sight = |from: &Point3<f32>, to: &Point3<f32>| terrain.line_of_sight(from, to);
+  let occluded =
+    beacons.into_iter()
+    .map(|(id, position)| (id, view::beacons::is_occluded(&eye, &position, &line_of_sight)))
+    .collect();
+  view::update::SetBeaconsOccluded(occluded)
+}
+
+fn push_view_updates<UpdateView>(updates: &mut Vec<view::update::T>, update_view: &mut UpdateView) where
+  UpdateView: FnMut(view::update::T),
+{
+  for up in updates.drain(..) {
+    update_view(up);
+  }
+}
+
 #[inline(never)]
 fn update_surroundings<UpdateView, UpdateServer>(
   client        : &client::T,
@@ -72,7 +141,7 @@ fn update_surroundings<UpdateView, UpdateServer>(
   update_server : &mut UpdateServer,
 ) where
   UpdateView   : FnMut(view::update::T),
-  UpdateServer : FnMut(protocol::ClientToServer),
+  UpdateServer : FnMut(protocol::Request),
 {
   let start = time::precise_time_ns();
   let mut i = 0;
@@ -109,7 +178,7 @@ fn update_surroundings<UpdateView, UpdateServer>(
       LoadType::Load => {
         stopwatch::time("update_thread.load_chunk", || {
           trace!("Loading distance {}", distance);
-          let new_lod = lod::of_distance(distance as u32);
+          let new_lod = lod::of_distance_with_floor(distance as u32, client.lod_floor, &client.lod_thresholds);
           let load_state = client.terrain.lock().unwrap().load_state(&chunk_position);
           if load_state == Some(new_lod) {
             debug!("Not re-loading {:?} at {:?}", chunk_position, new_lod);
@@ -120,7 +189,7 @@ fn update_surroundings<UpdateView, UpdateServer>(
       },
       LoadType::Downgrade => {
         stopwatch::time("update_thread.update_chunk", || {
-          let new_lod = lod::of_distance(distance as u32);
+          let new_lod = lod::of_distance_with_floor(distance as u32, client.lod_floor, &client.lod_thresholds);
           let load_state = client.terrain.lock().unwrap().load_state(&chunk_position);
           let is_downgrade = load_state.map(|lod| new_lod < lod) == Some(true);
           if is_downgrade {
@@ -155,16 +224,14 @@ fn load_or_request_chunk<UpdateServer, UpdateView>(
   chunk_position : &chunk::position::T,
   lod            : lod::T,
 ) where
-  UpdateServer: FnMut(protocol::ClientToServer),
+  UpdateServer: FnMut(protocol::Request),
   UpdateView: FnMut(view::update::T),
 {
   let mut terrain = client.terrain.lock().unwrap();
-  let rng = &mut *client.rng.lock().unwrap();
   let r =
     terrain.load_chunk(
       &client.terrain_allocator,
       &client.grass_allocator,
-      &mut *rng,
       chunk_stats,
       update_view,
       chunk_position,
@@ -174,7 +241,7 @@ fn load_or_request_chunk<UpdateServer, UpdateView>(
     Ok(()) => {},
     Err(voxels) => {
       update_server(
-        protocol::ClientToServer::RequestVoxels {
+        protocol::Request::RequestVoxels {
           time_requested_ns : time::precise_time_ns(),
           client_id       : client.id,
           voxels          : voxels,
@@ -194,11 +261,9 @@ fn process_voxel_updates<UpdateView>(
   UpdateView: FnMut(view::update::T),
 {
   let terrain = &mut *client.terrain.lock().unwrap();
-  let rng = &mut *client.rng.lock().unwrap();
   terrain.tick(
     &client.terrain_allocator,
     &client.grass_allocator,
-    rng,
     chunk_stats,
     update_view,
     &*client.player_position.lock().unwrap(),
@@ -217,7 +282,7 @@ fn process_server_updates<RecvServer, UpdateView, UpdateAudio, UpdateServer, Enq
   RecvServer         : FnMut() -> Option<protocol::ServerToClient>,
   UpdateView         : FnMut(view::update::T),
   UpdateAudio        : FnMut(audio_thread::Message),
-  UpdateServer       : FnMut(protocol::ClientToServer),
+  UpdateServer       : FnMut(protocol::Request),
   EnqueueTerrainLoad : FnMut(terrain::Load),
 {
   let start = time::precise_time_ns();
@@ -0,0 +1,254 @@
+//! Beacons are drawn as tall shafts of light, with their distance labelled in the HUD.
+//! Shafts are drawn without depth testing, so beacons can be found from behind hills and underground. Labels fade out
+//! with distance, and are hidden while terrain completely blocks the view of their beacon.
+
+use cgmath::{Point2, Point3, Vector4, InnerSpace};
+use std::collections::hash_map::Entry;
+use yaglw::vertex_buffer::{GLArray, GLBuffer, VertexAttribData};
+use yaglw::vertex_buffer::{DrawMode, GLType};
+use yaglw::gl_context::GLContext;
+
+use common::color::Color4;
+use common::entity;
+use common::fnv_map;
+use common::protocol;
+
+use hud;
+use vertex::ColoredVertex;
+use view;
+
+/// A shaft is two crossed vertical quads.
+const VERTICES_PER_SHAFT: usize = 12;
+/// Each label has room for this many digits.
+const LABEL_DIGITS: usize = 5;
+const VERTICES_PER_LABEL: usize = LABEL_DIGITS * hud::VERTICES_PER_DIGIT;
+
+/// How far above and below a beacon its shaft reaches.
+const SHAFT_HEIGHT: f32 = 256.0;
+const SHAFT_WIDTH: f32 = 1.0;
+/// Labels are drawn this far above the beacon itself.
+const LABEL_HEIGHT: f32 = 4.0;
+/// The height of a label's digits, as a fraction of the screen height.
+const DIGIT_HEIGHT: f32 = 0.04;
+/// Labels fade out over this last fraction of the distance they're drawn to.
+const FADE_FRACTION: f32 = 0.25;
+/// How often to check which labels are hidden behind terrain.
+pub const OCCLUSION_CHECK_NS: u64 = 250_000_000;
+
+/// Whether terrain hides a beacon at `position` from `eye`. `line_of_sight` says whether there's a clear view between
+/// two points. A beacon is hidden only if neither it nor its label can be seen.
+pub fn is_occluded<LineOfSight>(
+  eye: &Point3<f32>,
+  position: &Point3<f32>,
+  mut line_of_sight: LineOfSight,
+) -> bool where
+  LineOfSight: FnMut(&Point3<f32>, &Point3<f32>) -> bool,
+{
+  let label = Point3::new(position.x, position.y + LABEL_HEIGHT, position.z);
+  !line_of_sight(eye, position) && !line_of_sight(eye, &label)
+}
+
+fn shaft_color() -> Color4<f32> {
+  Color4::of_rgba(1.0, 0.9, 0.3, 0.35)
+}
+
+fn label_color() -> Color4<f32> {
+  Color4::of_rgba(1.0, 0.9, 0.3, 0.9)
+}
+
+#[allow(missing_docs)]
+pub struct T<'a> {
+  id_to_index          : fnv_map::T<entity::id::Beacon, usize>,
+  index_to_id          : Vec<entity::id::Beacon>,
+  positions            : Vec<Point3<f32>>,
+  /// Whether terrain was in the way of each beacon, as of the last check.
+  occluded             : Vec<bool>,
+  /// How far away labels can be seen.
+  max_distance         : f32,
+
+  shafts               : GLArray<'a, ColoredVertex>,
+  labels               : GLArray<'a, ColoredVertex>,
+}
+
+fn vertex_array<'a, 'b>(
+  gl: &'b mut GLContext,
+  shader: &view::shaders::color::T<'a>,
+  capacity: usize,
+) -> GLArray<'a, ColoredVertex> where
+  'a: 'b,
+{
+  let buffer = GLBuffer::new(gl, capacity);
+  GLArray::new(
+    gl,
+    &shader.shader,
+    &[
+      VertexAttribData { name: "position", size: 3, unit: GLType::Float, divisor: 0 },
+      VertexAttribData { name: "in_color", size: 4, unit: GLType::Float, divisor: 0 },
+    ],
+    DrawMode::Triangles,
+    buffer,
+  )
+}
+
+/// `world_shader` draws the shafts, and `hud_shader` draws the labels. Labels further than `max_distance` away aren't
+/// drawn.
+pub fn new<'a, 'b>(
+  gl: &'b mut GLContext,
+  world_shader: &view::shaders::color::T<'a>,
+  hud_shader: &view::shaders::color::T<'a>,
+  max_distance: f32,
+) -> T<'a> where
+  'a: 'b,
+{
+  T {
+    id_to_index          : fnv_map::new(),
+    index_to_id          : Vec::new(),
+    positions            : Vec::new(),
+    occluded             : Vec::new(),
+    max_distance         : max_distance,
+
+    shafts               : vertex_array(gl, world_shader, protocol::MAX_BEACONS * VERTICES_PER_SHAFT),
+    labels               : vertex_array(gl, hud_shader, protocol::MAX_BEACONS * VERTICES_PER_LABEL),
+  }
+}
+
+/// How opaque a label `distance` away is, in [0, 1].
+fn fade(distance: f32, max_distance: f32) -> f32 {
+  let fade_distance = FADE_FRACTION * max_distance;
+  if fade_distance <= 0.0 {
+    return if distance < max_distance { 1.0 } else { 0.0 }
+  }
+  ((max_distance - distance) / fade_distance).max(0.0).min(1.0)
+}
+
+fn shaft(position: &Point3<f32>) -> [ColoredVertex; VERTICES_PER_SHAFT] {
+  let (low, high) = (position.y - SHAFT_HEIGHT, position.y + SHAFT_HEIGHT);
+  let w = SHAFT_WIDTH / 2.0;
+  let c = shaft_color();
+  let vtx = |x, y, z| {
+    ColoredVertex { position: Point3::new(x, y, z), color: c }
+  };
+  let (x, z) = (position.x, position.z);
+  [
+    vtx(x - w, low, z), vtx(x + w, high, z), vtx(x - w, high, z),
+    vtx(x - w, low, z), vtx(x + w, low, z), vtx(x + w, high, z),
+    vtx(x, low, z - w), vtx(x, high, z + w), vtx(x, high, z - w),
+    vtx(x, low, z - w), vtx(x, low, z + w), vtx(x, high, z + w),
+  ]
+}
+
+impl<'a> T<'a> {
+  /// Add a beacon, or move it if it's already there.
+  pub fn insert(&mut self, gl: &mut GLContext, id: entity::id::Beacon, position: Point3<f32>) {
+    let vertices = shaft(&position);
+    self.shafts.buffer.byte_buffer.bind(gl);
+    match self.id_to_index.entry(id) {
+      Entry::Vacant(entry) => {
+        entry.insert(self.index_to_id.len());
+        self.index_to_id.push(id);
+        self.positions.push(position);
+        self.occluded.push(false);
+        assert!(self.shafts.push(gl, &vertices));
+
+        // Every beacon gets a label slot; it's filled in when the labels are drawn.
+        let mut label = Vec::with_capacity(VERTICES_PER_LABEL);
+        hud::number(&mut label, LABEL_DIGITS, None, Point2::new(0.0, 0.0), DIGIT_HEIGHT, label_color());
+        self.labels.buffer.byte_buffer.bind(gl);
+        assert!(self.labels.push(gl, &label));
+      },
+      Entry::Occupied(entry) => {
+        let idx = *entry.get();
+        self.positions[idx] = position;
+        self.shafts.buffer.update(gl, idx * VERTICES_PER_SHAFT, &vertices);
+      },
+    }
+  }
+
+  /// Remove a beacon, if it's there.
+  pub fn remove(&mut self, gl: &mut GLContext, id: entity::id::Beacon) {
+    let idx =
+      match self.id_to_index.remove(&id) {
+        None => return,
+        Some(idx) => idx,
+      };
+    let swapped_id = self.index_to_id[self.index_to_id.len() - 1];
+    self.index_to_id.swap_remove(idx);
+    self.positions.swap_remove(idx);
+    self.occluded.swap_remove(idx);
+    if id != swapped_id {
+      self.id_to_index.insert(swapped_id, idx);
+    }
+
+    self.shafts.buffer.byte_buffer.bind(gl);
+    self.shafts.buffer.swap_remove(gl, idx * VERTICES_PER_SHAFT, VERTICES_PER_SHAFT);
+    self.labels.buffer.byte_buffer.bind(gl);
+    self.labels.buffer.swap_remove(gl, idx * VERTICES_PER_LABEL, VERTICES_PER_LABEL);
+  }
+
+  /// Draw the shafts. This should be done with depth testing off, and the world shader bound.
+  pub fn draw_shafts(&self, gl: &mut GLContext) {
+    self.shafts.bind(gl);
+    self.shafts.draw(gl);
+  }
+
+  /// Set whether terrain hides a beacon, if it's there.
+  pub fn set_occluded(&mut self, id: entity::id::Beacon, occluded: bool) {
+    if let Some(&idx) = self.id_to_index.get(&id) {
+      self.occluded[idx] = occluded;
+    }
+  }
+
+  /// Relabel every beacon with its distance from `camera`, and draw the labels. This should be done with the HUD
+  /// shader bound. `vertices` is scratch space.
+  pub fn draw_labels(
+    &mut self,
+    gl: &mut GLContext,
+    camera: &view::camera::T,
+    aspect: f32,
+    vertices: &mut Vec<ColoredVertex>,
+  ) {
+    if self.positions.is_empty() {
+      return
+    }
+
+    let projection = camera.projection_matrix();
+    for (position, &occluded) in self.positions.iter().zip(self.occluded.iter()) {
+      let distance = (*position - camera.position).magnitude();
+      let clip = projection * Vector4::new(position.x, position.y + LABEL_HEIGHT, position.z, 1.0);
+      let mut color = label_color();
+      color.a *= fade(distance, self.max_distance);
+      // Labels that can't be seen are left empty.
+      let visible = clip.w > 0.0 && !occluded && color.a > 0.0;
+      let center = Point2::new(aspect * clip.x / clip.w, clip.y / clip.w);
+      hud::number(
+        vertices,
+        LABEL_DIGITS,
+        if visible { Some(distance.round() as u32) } else { None },
+        center,
+        DIGIT_HEIGHT,
+        color,
+      );
+    }
+
+    self.labels.buffer.byte_buffer.bind(gl);
+    self.labels.buffer.update(gl, 0, vertices);
+
+    self.labels.bind(gl);
+    self.labels.draw(gl);
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_fade() {
+    assert_eq!(fade(0.0, 100.0), 1.0);
+    assert_eq!(fade(75.0, 100.0), 1.0);
+    assert_eq!(fade(87.5, 100.0), 0.5);
+    assert_eq!(fade(100.0, 100.0), 0.0);
+    assert_eq!(fade(150.0, 100.0), 0.0);
+    assert_eq!(fade(0.0, 0.0), 0.0);
+  }
+}
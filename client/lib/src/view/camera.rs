@@ -95,6 +95,16 @@ impl T {
     let axis = axis * (&Vector3::new(1.0, 0.0, 0.0));
     self.rotate(&axis, r);
   }
+
+  /// The direction the camera is facing.
+  pub fn forward(&self) -> Vector3<f32> {
+    let y_axis = Vector3::new(0.0, 1.0, 0.0);
+    let right = Matrix3::from_axis_angle(y_axis, cgmath::Rad(self.lateral_rotation)) * Vector3::new(1.0, 0.0, 0.0);
+    let transform =
+      Matrix3::from_axis_angle(right, cgmath::Rad(self.vertical_rotation))
+        * Matrix3::from_axis_angle(y_axis, cgmath::Rad(self.lateral_rotation));
+    transform * Vector3::new(0.0, 0.0, -1.0)
+  }
 }
 
 /// Set a shader's projection matrix to match that of a camera.
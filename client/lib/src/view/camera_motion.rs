@@ -0,0 +1,176 @@
+//! Where the camera is and how wide it sees, as opposed to where the server last said the player was.
+//! Position updates arrive unevenly, so the camera follows them on a critically damped spring, which catches up as
+//! quickly as it can without overshooting. The field of view widens a little while sprinting.
+
+use cgmath::{Point3, Vector3, InnerSpace};
+
+/// Positions further than this from the camera (e.g. after a respawn) are jumped to instead of followed.
+const SNAP_DISTANCE: f32 = 8.0;
+/// Roughly how long the sprint kick takes to come in or go away, in seconds.
+const KICK_SECONDS: f32 = 0.15;
+/// The narrowest field of view, in degrees.
+pub const MIN_FOV: f32 = 30.0;
+/// The widest field of view, in degrees.
+pub const MAX_FOV: f32 = 110.0;
+
+#[allow(missing_docs)]
+pub struct T {
+  /// Where the player is, as of the last update from the server.
+  target         : Option<Point3<f32>>,
+  position       : Point3<f32>,
+  velocity       : Vector3<f32>,
+  /// Roughly how long (in seconds) the camera takes to catch up with the player.
+  smoothing      : f32,
+  /// Whether the camera follows the player smoothly, rather than jumping to each update.
+  pub smooth     : bool,
+  /// The vertical field of view when not sprinting, in radians.
+  fov            : f32,
+  /// How much wider (in radians) the field of view gets while sprinting.
+  sprint_kick    : f32,
+  pub sprinting  : bool,
+  /// How much of the sprint kick is in effect right now.
+  kick           : f32,
+  last_update_ns : Option<u64>,
+}
+
+/// `fov` is the vertical field of view and `sprint_kick` is how much wider it gets while sprinting, both in radians.
+/// The camera takes about `smoothing` seconds to catch up with the player; 0 makes it keep up exactly.
+pub fn new(fov: f32, smoothing: f32, sprint_kick: f32) -> T {
+  T {
+    target         : None,
+    position       : Point3::new(0.0, 0.0, 0.0),
+    velocity       : Vector3::new(0.0, 0.0, 0.0),
+    smoothing      : smoothing.max(0.0),
+    smooth         : smoothing > 0.0,
+    fov            : fov,
+    sprint_kick    : sprint_kick,
+    sprinting      : false,
+    kick           : 0.0,
+    last_update_ns : None,
+  }
+}
+
+/// Move `position` (going at `velocity`) `seconds` closer to `target`, along a critically damped spring that takes
+/// about `smoothing` seconds to settle. Returns the new position and velocity.
+fn follow(
+  position: Point3<f32>,
+  velocity: Vector3<f32>,
+  target: Point3<f32>,
+  smoothing: f32,
+  seconds: f32,
+) -> (Point3<f32>, Vector3<f32>) {
+  let omega = 2.0 / smoothing;
+  let x = omega * seconds;
+  // A cheap, stable approximation of e^-x.
+  let decay = 1.0 / (1.0 + x + 0.48 * x * x + 0.235 * x * x * x);
+  let offset = position - target;
+  let change = (velocity + offset * omega) * seconds;
+  let velocity = (velocity - change * omega) * decay;
+  let position = target + (offset + change) * decay;
+  (position, velocity)
+}
+
+impl T {
+  /// The player's been moved to `position`.
+  pub fn move_to(&mut self, position: Point3<f32>) {
+    if self.target.is_none() || (position - self.position).magnitude() > SNAP_DISTANCE {
+      self.position = position;
+      self.velocity = Vector3::new(0.0, 0.0, 0.0);
+    }
+    self.target = Some(position);
+  }
+
+  /// Move the camera on to local time `now`. Returns where it should be, if the player's position is known yet.
+  pub fn update(&mut self, now: u64) -> Option<Point3<f32>> {
+    let seconds =
+      match self.last_update_ns {
+        None => 0.0,
+        Some(last) => now.saturating_sub(last) as f32 / 1e9,
+      };
+    self.last_update_ns = Some(now);
+
+    let kick = if self.sprinting { self.sprint_kick } else { 0.0 };
+    self.kick += (kick - self.kick) * (1.0 - (-seconds / KICK_SECONDS).exp());
+
+    let target =
+      match self.target {
+        None => return None,
+        Some(target) => target,
+      };
+    if self.smooth && self.smoothing > 0.0 {
+      let (position, velocity) = follow(self.position, self.velocity, target, self.smoothing, seconds);
+      self.position = position;
+      self.velocity = velocity;
+    } else {
+      self.position = target;
+      self.velocity = Vector3::new(0.0, 0.0, 0.0);
+    }
+    Some(self.position)
+  }
+
+  /// The vertical field of view right now, in radians.
+  pub fn current_fov(&self) -> f32 {
+    self.fov + self.kick
+  }
+
+  /// Change the vertical field of view (when not sprinting), in degrees. It's kept within [MIN_FOV, MAX_FOV].
+  pub fn set_fov(&mut self, degrees: f32) {
+    let degrees = degrees.max(MIN_FOV).min(MAX_FOV);
+    info!("Field of view: {} degrees", degrees);
+    self.fov = degrees.to_radians();
+  }
+
+  /// The vertical field of view when not sprinting, in degrees.
+  pub fn fov_degrees(&self) -> f32 {
+    self.fov.to_degrees()
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use cgmath::{Point3, Vector3, InnerSpace};
+
+  use super::*;
+
+  #[test]
+  fn test_follow() {
+    let target = Point3::new(1.0, 0.0, 0.0);
+    let mut position = Point3::new(0.0, 0.0, 0.0);
+    let mut velocity = Vector3::new(0.0, 0.0, 0.0);
+    for _ in 0 .. 30 {
+      let (p, v) = follow(position, velocity, target, 0.1, 1.0 / 30.0);
+      // Critically damped: it never passes the target.
+      assert!(p.x >= position.x && p.x <= target.x);
+      position = p;
+      velocity = v;
+    }
+    assert!((target - position).magnitude() < 1e-2);
+  }
+
+  #[test]
+  fn test_snap() {
+    let mut motion = new(1.0, 0.1, 0.1);
+    assert_eq!(motion.update(0), None);
+    motion.move_to(Point3::new(0.0, 0.0, 0.0));
+    assert_eq!(motion.update(0), Some(Point3::new(0.0, 0.0, 0.0)));
+    // Small moves are followed.
+    motion.move_to(Point3::new(1.0, 0.0, 0.0));
+    let p = motion.update(10_000_000).unwrap();
+    assert!(p.x > 0.0 && p.x < 1.0);
+    // Big ones are jumped to.
+    motion.move_to(Point3::new(100.0, 0.0, 0.0));
+    assert_eq!(motion.update(20_000_000), Some(Point3::new(100.0, 0.0, 0.0)));
+  }
+
+  #[test]
+  fn test_sprint_kick() {
+    let mut motion = new(1.0, 0.0, 0.2);
+    motion.sprinting = true;
+    motion.update(0);
+    motion.update(1_000_000_000);
+    assert!((motion.current_fov() - 1.2).abs() < 1e-3);
+    motion.sprinting = false;
+    motion.update(2_000_000_000);
+    assert!((motion.current_fov() - 1.0).abs() < 1e-3);
+  }
+}
@@ -2,6 +2,7 @@
 
 use gl::types::*;
 use cgmath::{Point3, Vector3};
+use collision::Aabb3;
 use terrain_mesh;
 
 use common::id_allocator;
@@ -51,6 +52,10 @@ pub struct T {
   pub normals: Vec<terrain_buffers::Chunk<terrain_mesh::Triangle<Vector3<f32>>>>,
   /// Material IDs for each triangle.
   pub materials: Vec<terrain_buffers::Chunk<i32>>,
+  /// How much of the sky each vertex can see, in [0, 1].
+  pub sky_visibility: Vec<terrain_buffers::Chunk<terrain_mesh::Triangle<f32>>>,
+  /// How much of each vertex's ambient light is blocked by the voxels right around it, in [0, 1].
+  pub occlusion: Vec<terrain_buffers::Chunk<terrain_mesh::Triangle<f32>>>,
   /// per-chunk ids
   pub ids: Vec<entity::id::Terrain>,
   pub grass : Grass,
@@ -82,6 +87,27 @@ impl T {
     self.ids.len()
   }
 
+  /// The smallest box around every vertex pushed, if any have been.
+  pub fn bounds(&self) -> Option<Aabb3<f32>> {
+    let mut bounds: Option<Aabb3<f32>> = None;
+    for i in 0 .. self.polygon_count() {
+      let triangle = &self.vertex_coordinates[i / terrain_buffers::CHUNK_LENGTH].0[i % terrain_buffers::CHUNK_LENGTH];
+      for v in &[triangle.v1, triangle.v2, triangle.v3] {
+        bounds =
+          Some(match bounds {
+            None => Aabb3::new(*v, *v),
+            Some(b) => {
+              Aabb3::new(
+                Point3::new(b.min.x.min(v.x), b.min.y.min(v.y), b.min.z.min(v.z)),
+                Point3::new(b.max.x.max(v.x), b.max.y.max(v.y), b.max.z.max(v.z)),
+              )
+            },
+          });
+      }
+    }
+    bounds
+  }
+
   /// is there nothing to be loaded in this chunk?
   pub fn is_empty(&self) -> bool {
     self.chunk_count() == 0
@@ -94,6 +120,8 @@ impl T {
     vertices     : terrain_mesh::Triangle<Point3<GLfloat>>,
     normals      : terrain_mesh::Triangle<Vector3<GLfloat>>,
     material     : GLint,
+    sky          : terrain_mesh::Triangle<GLfloat>,
+    occlusion    : terrain_mesh::Triangle<GLfloat>,
     grass        : Option<PushGrass>,
   ) {
     // After this block executes, then it is unconditionally true that we write to the last chunk in every `Vec` at this index.
@@ -105,6 +133,8 @@ impl T {
       let zero = Vector3::new(0.0, 0.0, 0.0);
       self.normals.push(terrain_buffers::Chunk([terrain_mesh::tri(zero, zero, zero); terrain_buffers::CHUNK_LENGTH]));
       self.materials.push(terrain_buffers::Chunk([0; terrain_buffers::CHUNK_LENGTH]));
+      self.sky_visibility.push(terrain_buffers::Chunk([terrain_mesh::tri(0.0, 0.0, 0.0); terrain_buffers::CHUNK_LENGTH]));
+      self.occlusion.push(terrain_buffers::Chunk([terrain_mesh::tri(0.0, 0.0, 0.0); terrain_buffers::CHUNK_LENGTH]));
       let id = id_allocator.allocate();
       self.ids.push(id);
     }
@@ -114,6 +144,8 @@ impl T {
     self.vertex_coordinates.last_mut().unwrap().0[self.next_idx_inside_chunks] = vertices;
     self.normals.last_mut().unwrap().0[self.next_idx_inside_chunks] = normals;
     self.materials.last_mut().unwrap().0[self.next_idx_inside_chunks] = material;
+    self.sky_visibility.last_mut().unwrap().0[self.next_idx_inside_chunks] = sky;
+    self.occlusion.last_mut().unwrap().0[self.next_idx_inside_chunks] = occlusion;
 
     grass.map(|grass| {
       self.grass.polygon_chunk_ids.push(chunk_id);
@@ -132,8 +164,54 @@ pub fn empty() -> T {
     vertex_coordinates     : Vec::new(),
     normals                : Vec::new(),
     materials              : Vec::new(),
+    sky_visibility         : Vec::new(),
+    occlusion              : Vec::new(),
     ids                    : Vec::new(),
     grass                  : Grass::empty(),
     next_idx_inside_chunks : 0
   }
 }
+
+/// New shading for a mesh that's already loaded, laid out in the same vram chunks as the mesh itself.
+pub struct Shading {
+  /// The vram chunks the mesh was loaded as.
+  pub ids            : Vec<entity::id::Terrain>,
+  #[allow(missing_docs)]
+  pub materials      : Vec<terrain_buffers::Chunk<i32>>,
+  #[allow(missing_docs)]
+  pub sky_visibility : Vec<terrain_buffers::Chunk<terrain_mesh::Triangle<f32>>>,
+  #[allow(missing_docs)]
+  pub occlusion      : Vec<terrain_buffers::Chunk<terrain_mesh::Triangle<f32>>>,
+}
+
+/// Blank shading for the vram chunks `ids`.
+pub fn shading(ids: &[entity::id::Terrain]) -> Shading {
+  Shading {
+    ids            : ids.to_vec(),
+    materials      : ids.iter().map(|_| terrain_buffers::Chunk([0; terrain_buffers::CHUNK_LENGTH])).collect(),
+    sky_visibility :
+      ids.iter()
+      .map(|_| terrain_buffers::Chunk([terrain_mesh::tri(0.0, 0.0, 0.0); terrain_buffers::CHUNK_LENGTH]))
+      .collect(),
+    occlusion      :
+      ids.iter()
+      .map(|_| terrain_buffers::Chunk([terrain_mesh::tri(0.0, 0.0, 0.0); terrain_buffers::CHUNK_LENGTH]))
+      .collect(),
+  }
+}
+
+impl Shading {
+  /// Shade the `i`th polygon of the mesh, in the order they were pushed.
+  pub fn set(
+    &mut self,
+    i: usize,
+    material: GLint,
+    sky: terrain_mesh::Triangle<GLfloat>,
+    occlusion: terrain_mesh::Triangle<GLfloat>,
+  ) {
+    let (chunk, offset) = (i / terrain_buffers::CHUNK_LENGTH, i % terrain_buffers::CHUNK_LENGTH);
+    self.materials[chunk].0[offset] = material;
+    self.sky_visibility[chunk].0[offset] = sky;
+    self.occlusion[chunk].0[offset] = occlusion;
+  }
+}
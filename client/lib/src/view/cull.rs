@@ -0,0 +1,83 @@
+//! Distance culling for entity meshes. Entities beyond their class's draw distance aren't drawn at all,
+//! and fade out as they approach it.
+
+use cgmath::{Point3, EuclideanSpace, InnerSpace};
+use gl;
+
+use vertex::ColoredVertex;
+
+/// How far away a class of entities is drawn.
+#[derive(Debug, Clone, Copy)]
+pub struct Range {
+  /// Entities further than this aren't drawn.
+  pub max_distance  : f32,
+  /// Entities fade out over this distance, up to `max_distance`.
+  pub fade_distance : f32,
+}
+
+/// Draw distance for mobs.
+pub const MOBS: Range =
+  Range {
+    max_distance  : 128.0,
+    fade_distance : 16.0,
+  };
+
+/// Draw distance for players, which should be visible from further away than smaller entities.
+pub const PLAYERS: Range =
+  Range {
+    max_distance  : 512.0,
+    fade_distance : 32.0,
+  };
+
+impl Range {
+  #[allow(missing_docs)]
+  pub fn fade_start(&self) -> f32 {
+    self.max_distance - self.fade_distance
+  }
+}
+
+/// The center of a mesh's bounding box.
+pub fn center(vertices: &[ColoredVertex]) -> Point3<f32> {
+  let mut low = vertices[0].position;
+  let mut high = vertices[0].position;
+  for v in vertices {
+    low.x = f32::min(low.x, v.position.x);
+    low.y = f32::min(low.y, v.position.y);
+    low.z = f32::min(low.z, v.position.z);
+    high.x = f32::max(high.x, v.position.x);
+    high.y = f32::max(high.y, v.position.y);
+    high.z = f32::max(high.z, v.position.z);
+  }
+  Point3::from_vec((low.to_vec() + high.to_vec()) / 2.0)
+}
+
+/// Draw the in-range meshes from the currently-bound vertex array, where mesh `i` has center `centers[i]`.
+/// Each contiguous run of visible meshes takes one draw call.
+pub fn draw_visible(
+  centers           : &[Point3<f32>],
+  vertices_per_mesh : usize,
+  eye               : &Point3<f32>,
+  range             : &Range,
+) {
+  let max_distance2 = range.max_distance * range.max_distance;
+  let draw = |first: usize, end: usize| {
+    if first < end {
+      unsafe {
+        gl::DrawArrays(
+          gl::TRIANGLES,
+          (first * vertices_per_mesh) as i32,
+          ((end - first) * vertices_per_mesh) as i32,
+        );
+      }
+    }
+  };
+
+  let mut run_start = 0;
+  for (i, center) in centers.iter().enumerate() {
+    if (*center - *eye).magnitude2() > max_distance2 {
+      draw(run_start, i);
+      run_start = i + 1;
+    }
+  }
+  draw(run_start, centers.len());
+}
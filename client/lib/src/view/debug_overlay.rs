@@ -0,0 +1,426 @@
+//! Debug overlays, each toggled on its own: terrain drawn as wireframe, boxes around the terrain meshes that are
+//! loaded, boxes around what the server's physics has near the player, grass hidden, a graph of recent frame
+//! times, and a graph of how full the queue of view updates has been. Boxes are drawn as lines over everything else,
+//! so they can be seen through terrain.
+
+use cgmath::Point3;
+use collision::Aabb3;
+use std::collections::VecDeque;
+use yaglw::vertex_buffer::{GLArray, GLBuffer, VertexAttribData};
+use yaglw::vertex_buffer::{DrawMode, GLType};
+use yaglw::gl_context::GLContext;
+
+use common::color::Color4;
+use common::fnv_map;
+
+use vertex::ColoredVertex;
+use view;
+
+use super::entity;
+use super::thread::FRAMES_PER_SECOND;
+use super::update_queue;
+
+/// A box is drawn as its 12 edges.
+const VERTICES_PER_BOX: usize = 24;
+/// The most boxes drawn at once. Past this, the rest aren't drawn.
+const MAX_BOXES: usize = 4096;
+
+/// How many frames the graph shows.
+const GRAPH_FRAMES: usize = 120;
+const GRAPH_WIDTH: f32 = 0.6;
+const GRAPH_HEIGHT: f32 = 0.3;
+/// How far the graphs are from the bottom corners of the screen.
+const GRAPH_MARGIN: f32 = 0.05;
+/// The time one frame should take. The graph's as tall as two of these.
+const FRAME_BUDGET_NS: u64 = 1_000_000_000 / FRAMES_PER_SECOND;
+/// A bar per frame, and a line across the graph.
+const GRAPH_VERTICES: usize = 6 * (GRAPH_FRAMES + 1);
+
+#[allow(missing_docs)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Overlay {
+  /// Draw terrain as wireframe.
+  Wireframe,
+  /// Draw a box around each loaded terrain mesh.
+  MeshBounds,
+  /// Draw the bounds of everything the server's physics has near the player. These are streamed from the server
+  /// while this is on.
+  PhysicsBounds,
+  HideGrass,
+  FrameTimes,
+  /// Graph how many mesh loads and unloads were waiting to be applied each frame.
+  ViewQueue,
+}
+
+const OVERLAY_COUNT: usize = 6;
+
+impl Overlay {
+  #[allow(missing_docs)]
+  pub fn name(self) -> &'static str {
+    match self {
+      Overlay::Wireframe => "wireframe",
+      Overlay::MeshBounds => "mesh bounds",
+      Overlay::PhysicsBounds => "physics bounds",
+      Overlay::HideGrass => "hide grass",
+      Overlay::FrameTimes => "frame times",
+      Overlay::ViewQueue => "view queue",
+    }
+  }
+}
+
+fn mesh_color() -> Color4<f32> {
+  Color4::of_rgba(0.2, 0.6, 1.0, 0.6)
+}
+
+fn physics_color() -> Color4<f32> {
+  Color4::of_rgba(1.0, 0.4, 0.1, 0.8)
+}
+
+fn budget_color() -> Color4<f32> {
+  Color4::of_rgba(0.9, 0.9, 0.9, 0.6)
+}
+
+fn blank_vertex() -> ColoredVertex {
+  ColoredVertex { position: Point3::new(0.0, 0.0, 0.0), color: Color4::of_rgba(0.0, 0.0, 0.0, 0.0) }
+}
+
+fn queue_color(stalled: bool) -> Color4<f32> {
+  if stalled {
+    Color4::of_rgba(0.9, 0.2, 0.1, 0.8)
+  } else {
+    Color4::of_rgba(0.2, 0.6, 1.0, 0.8)
+  }
+}
+
+fn bar_color(frame_ns: u64) -> Color4<f32> {
+  if frame_ns <= FRAME_BUDGET_NS {
+    Color4::of_rgba(0.2, 0.8, 0.2, 0.8)
+  } else {
+    Color4::of_rgba(0.9, 0.2, 0.1, 0.8)
+  }
+}
+
+#[allow(missing_docs)]
+pub struct T<'a> {
+  enabled        : [bool; OVERLAY_COUNT],
+  /// The bounds of each loaded terrain mesh, by its first vram chunk.
+  mesh_bounds    : fnv_map::T<entity::id::Terrain, Aabb3<f32>>,
+  physics_bounds : Vec<Aabb3<f32>>,
+  /// Whether the boxes have changed since `lines` was last filled.
+  dirty          : bool,
+  lines          : GLArray<'a, ColoredVertex>,
+
+  /// How long recent frames took, oldest first.
+  frame_times    : VecDeque<u64>,
+  last_frame_ns  : Option<u64>,
+  graph          : GLArray<'a, ColoredVertex>,
+
+  /// The view update queue's depth each recent frame, oldest first, and whether anything had to wait to push to it.
+  queue_depths   : VecDeque<(usize, bool)>,
+  queue_stats    : Option<update_queue::Stats>,
+  queue_graph    : GLArray<'a, ColoredVertex>,
+}
+
+/// `world_shader` draws the boxes, and `hud_shader` draws the graph.
+pub fn new<'a, 'b>(
+  gl: &'b mut GLContext,
+  world_shader: &view::shaders::color::T<'a>,
+  hud_shader: &view::shaders::color::T<'a>,
+) -> T<'a> where
+  'a: 'b,
+{
+  let attribs =
+    [
+      VertexAttribData { name: "position", size: 3, unit: GLType::Float, divisor: 0 },
+      VertexAttribData { name: "in_color", size: 4, unit: GLType::Float, divisor: 0 },
+    ];
+  let lines = {
+    let buffer = GLBuffer::new(gl, MAX_BOXES * VERTICES_PER_BOX);
+    GLArray::new(gl, &world_shader.shader, &attribs, DrawMode::Lines, buffer)
+  };
+  let mut graph = {
+    let buffer = GLBuffer::new(gl, GRAPH_VERTICES);
+    GLArray::new(gl, &hud_shader.shader, &attribs, DrawMode::Triangles, buffer)
+  };
+  graph.buffer.byte_buffer.bind(gl);
+  assert!(graph.push(gl, &[blank_vertex(); GRAPH_VERTICES]));
+  let mut queue_graph = {
+    let buffer = GLBuffer::new(gl, GRAPH_VERTICES);
+    GLArray::new(gl, &hud_shader.shader, &attribs, DrawMode::Triangles, buffer)
+  };
+  queue_graph.buffer.byte_buffer.bind(gl);
+  assert!(queue_graph.push(gl, &[blank_vertex(); GRAPH_VERTICES]));
+
+  T {
+    enabled        : [false; OVERLAY_COUNT],
+    mesh_bounds    : fnv_map::new(),
+    physics_bounds : Vec::new(),
+    dirty          : false,
+    lines          : lines,
+
+    frame_times    : VecDeque::with_capacity(GRAPH_FRAMES),
+    last_frame_ns  : None,
+    graph          : graph,
+
+    queue_depths   : VecDeque::with_capacity(GRAPH_FRAMES),
+    queue_stats    : None,
+    queue_graph    : queue_graph,
+  }
+}
+
+/// The edges of a box, as pairs of vertices.
+fn box_lines(vertices: &mut Vec<ColoredVertex>, bounds: &Aabb3<f32>, color: Color4<f32>) {
+  let (low, high) = (bounds.min, bounds.max);
+  let corner = |i: usize| {
+    Point3::new(
+      if i & 1 == 0 { low.x } else { high.x },
+      if i & 2 == 0 { low.y } else { high.y },
+      if i & 4 == 0 { low.z } else { high.z },
+    )
+  };
+  // Corners whose indices differ in exactly one bit share an edge.
+  for i in 0 .. 8 {
+    for &bit in &[1, 2, 4] {
+      if i & bit == 0 {
+        vertices.push(ColoredVertex { position: corner(i), color: color });
+        vertices.push(ColoredVertex { position: corner(i | bit), color: color });
+      }
+    }
+  }
+}
+
+fn quad(vertices: &mut Vec<ColoredVertex>, low: (f32, f32), high: (f32, f32), color: Color4<f32>) {
+  let vtx = |x, y| ColoredVertex { position: Point3::new(x, y, 0.0), color: color };
+  vertices.extend_from_slice(&[
+    vtx(low.0, low.1), vtx(high.0, low.1), vtx(high.0, high.1),
+    vtx(low.0, low.1), vtx(high.0, high.1), vtx(low.0, high.1),
+  ]);
+}
+
+/// The height of a bar for a queue `depth` deep, out of `capacity`.
+fn queue_height(depth: usize, capacity: usize) -> f32 {
+  GRAPH_HEIGHT * (depth as f32 / capacity.max(1) as f32).min(1.0)
+}
+
+/// The height of a bar for a frame that took `frame_ns`.
+fn bar_height(frame_ns: u64) -> f32 {
+  GRAPH_HEIGHT * (frame_ns as f32 / (2 * FRAME_BUDGET_NS) as f32).min(1.0)
+}
+
+impl<'a> T<'a> {
+  #[allow(missing_docs)]
+  pub fn is_enabled(&self, overlay: Overlay) -> bool {
+    self.enabled[overlay as usize]
+  }
+
+  /// Turn an overlay on if it's off, or off if it's on. Returns whether it's on now.
+  pub fn toggle(&mut self, overlay: Overlay) -> bool {
+    let enabled = !self.enabled[overlay as usize];
+    self.enabled[overlay as usize] = enabled;
+    info!("Debug overlay {}: {}", overlay.name(), if enabled { "on" } else { "off" });
+    match overlay {
+      Overlay::MeshBounds => self.dirty = true,
+      Overlay::PhysicsBounds => {
+        self.physics_bounds.clear();
+        self.dirty = true;
+      },
+      Overlay::FrameTimes => {
+        self.frame_times.clear();
+        self.last_frame_ns = None;
+      },
+      Overlay::ViewQueue => {
+        self.queue_depths.clear();
+        self.queue_stats = None;
+      },
+      Overlay::Wireframe | Overlay::HideGrass => {},
+    }
+    enabled
+  }
+
+  /// A terrain mesh has been loaded, as the vram chunks `ids`.
+  pub fn mesh_loaded(&mut self, ids: &[entity::id::Terrain], bounds: Option<Aabb3<f32>>) {
+    if let (Some(&id), Some(bounds)) = (ids.first(), bounds) {
+      self.mesh_bounds.insert(id, bounds);
+      if self.is_enabled(Overlay::MeshBounds) {
+        self.dirty = true;
+      }
+    }
+  }
+
+  /// A terrain mesh made of the vram chunks `ids` has been unloaded.
+  pub fn mesh_unloaded(&mut self, ids: &[entity::id::Terrain]) {
+    if let Some(id) = ids.first() {
+      if self.mesh_bounds.remove(id).is_some() && self.is_enabled(Overlay::MeshBounds) {
+        self.dirty = true;
+      }
+    }
+  }
+
+  /// The server's sent the physics bounds around the player. They're dropped if they arrive after the overlay's off.
+  pub fn set_physics_bounds(&mut self, bounds: Vec<Aabb3<f32>>) {
+    if self.is_enabled(Overlay::PhysicsBounds) {
+      self.physics_bounds = bounds;
+      self.dirty = true;
+    }
+  }
+
+  /// A frame's been drawn at local time `now_ns`.
+  pub fn frame_drawn(&mut self, now_ns: u64) {
+    if !self.is_enabled(Overlay::FrameTimes) {
+      return
+    }
+    if let Some(last) = self.last_frame_ns {
+      if self.frame_times.len() >= GRAPH_FRAMES {
+        self.frame_times.pop_front();
+      }
+      self.frame_times.push_back(now_ns - last);
+    }
+    self.last_frame_ns = Some(now_ns);
+  }
+
+  /// The view update queue's stats, as of this frame.
+  pub fn queue_sampled(&mut self, stats: update_queue::Stats) {
+    if !self.is_enabled(Overlay::ViewQueue) {
+      return
+    }
+    let stalled = self.queue_stats.map_or(false, |last| stats.stalls > last.stalls);
+    if self.queue_depths.len() >= GRAPH_FRAMES {
+      self.queue_depths.pop_front();
+    }
+    self.queue_depths.push_back((stats.depth, stalled));
+    self.queue_stats = Some(stats);
+  }
+
+  /// Refill the boxes, if they've changed. `vertices` is scratch space.
+  fn update_lines(&mut self, gl: &mut GLContext, vertices: &mut Vec<ColoredVertex>) {
+    if !self.dirty {
+      return
+    }
+    self.dirty = false;
+
+    if self.is_enabled(Overlay::MeshBounds) {
+      for bounds in self.mesh_bounds.values() {
+        box_lines(vertices, bounds, mesh_color());
+      }
+    }
+    if self.is_enabled(Overlay::PhysicsBounds) {
+      for bounds in &self.physics_bounds {
+        box_lines(vertices, bounds, physics_color());
+      }
+    }
+    if vertices.len() > MAX_BOXES * VERTICES_PER_BOX {
+      warn!("{} debug boxes, but only room for {}", vertices.len() / VERTICES_PER_BOX, MAX_BOXES);
+      vertices.truncate(MAX_BOXES * VERTICES_PER_BOX);
+    }
+
+    self.lines.buffer.byte_buffer.bind(gl);
+    self.lines.buffer.byte_buffer.length = 0;
+    assert!(self.lines.push(gl, vertices));
+  }
+
+  /// Draw the boxes, if any are on. This should be done with the world shader bound, and depth testing off.
+  /// `vertices` is scratch space.
+  pub fn draw_lines(&mut self, gl: &mut GLContext, vertices: &mut Vec<ColoredVertex>) {
+    if !self.is_enabled(Overlay::MeshBounds) && !self.is_enabled(Overlay::PhysicsBounds) {
+      return
+    }
+    self.update_lines(gl, vertices);
+    self.lines.bind(gl);
+    self.lines.draw(gl);
+  }
+
+  /// Draw the frame time graph in the bottom right corner, if it's on. This should be done with the HUD shader bound.
+  /// `vertices` is scratch space.
+  pub fn draw_graph(&mut self, gl: &mut GLContext, aspect: f32, vertices: &mut Vec<ColoredVertex>) {
+    if !self.is_enabled(Overlay::FrameTimes) {
+      return
+    }
+
+    let (right, bottom) = (aspect - GRAPH_MARGIN, -1.0 + GRAPH_MARGIN);
+    let left = right - GRAPH_WIDTH;
+    let bar_width = GRAPH_WIDTH / GRAPH_FRAMES as f32;
+    // The newest frame is on the right.
+    let first = GRAPH_FRAMES - self.frame_times.len();
+    for (i, &frame_ns) in self.frame_times.iter().enumerate() {
+      let x = left + (first + i) as f32 * bar_width;
+      quad(vertices, (x, bottom), (x + bar_width, bottom + bar_height(frame_ns)), bar_color(frame_ns));
+    }
+    let budget = bottom + bar_height(FRAME_BUDGET_NS);
+    quad(vertices, (left, budget - 0.002), (right, budget + 0.002), budget_color());
+    // Leave the rest of the bars blank.
+    vertices.resize(GRAPH_VERTICES, blank_vertex());
+
+    self.graph.buffer.byte_buffer.bind(gl);
+    self.graph.buffer.update(gl, 0, vertices);
+    self.graph.bind(gl);
+    self.graph.draw(gl);
+  }
+
+  /// Draw the view queue graph in the bottom left corner, if it's on. Bars are as tall as the queue was full, red
+  /// where something had to wait to push to it, and the line is the fullest it's ever been. This should be done with
+  /// the HUD shader bound. `vertices` is scratch space.
+  pub fn draw_queue_graph(&mut self, gl: &mut GLContext, aspect: f32, vertices: &mut Vec<ColoredVertex>) {
+    let stats =
+      match self.queue_stats {
+        Some(stats) if self.is_enabled(Overlay::ViewQueue) => stats,
+        _ => return,
+      };
+
+    let (left, bottom) = (-aspect + GRAPH_MARGIN, -1.0 + GRAPH_MARGIN);
+    let right = left + GRAPH_WIDTH;
+    let bar_width = GRAPH_WIDTH / GRAPH_FRAMES as f32;
+    let first = GRAPH_FRAMES - self.queue_depths.len();
+    for (i, &(depth, stalled)) in self.queue_depths.iter().enumerate() {
+      let x = left + (first + i) as f32 * bar_width;
+      quad(vertices, (x, bottom), (x + bar_width, bottom + queue_height(depth, stats.capacity)), queue_color(stalled));
+    }
+    let watermark = bottom + queue_height(stats.high_watermark, stats.capacity);
+    quad(vertices, (left, watermark - 0.002), (right, watermark + 0.002), budget_color());
+    vertices.resize(GRAPH_VERTICES, blank_vertex());
+
+    self.queue_graph.buffer.byte_buffer.bind(gl);
+    self.queue_graph.buffer.update(gl, 0, vertices);
+    self.queue_graph.bind(gl);
+    self.queue_graph.draw(gl);
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use cgmath::Point3;
+  use collision::Aabb3;
+
+  use common::color::Color4;
+
+  use super::*;
+
+  #[test]
+  fn test_box_lines() {
+    let mut vertices = Vec::new();
+    let bounds = Aabb3::new(Point3::new(0.0, 0.0, 0.0), Point3::new(1.0, 2.0, 3.0));
+    box_lines(&mut vertices, &bounds, Color4::of_rgba(1.0, 1.0, 1.0, 1.0));
+    assert_eq!(vertices.len(), VERTICES_PER_BOX);
+    for edge in vertices.chunks(2) {
+      let d = edge[1].position - edge[0].position;
+      // Each edge runs along one axis, the whole length of the box.
+      let along: Vec<f32> = [d.x, d.y, d.z].iter().cloned().filter(|&x| x != 0.0).collect();
+      assert_eq!(along.len(), 1);
+      assert!(along[0] > 0.0);
+    }
+  }
+
+  #[test]
+  fn test_queue_height() {
+    assert_eq!(queue_height(0, 8), 0.0);
+    assert_eq!(queue_height(4, 8), GRAPH_HEIGHT / 2.0);
+    assert_eq!(queue_height(9, 8), GRAPH_HEIGHT);
+    assert_eq!(queue_height(0, 0), 0.0);
+  }
+
+  #[test]
+  fn test_bar_height() {
+    assert_eq!(bar_height(0), 0.0);
+    assert_eq!(bar_height(FRAME_BUDGET_NS), GRAPH_HEIGHT / 2.0);
+    assert_eq!(bar_height(10 * FRAME_BUDGET_NS), GRAPH_HEIGHT);
+  }
+}
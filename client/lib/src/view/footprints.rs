@@ -0,0 +1,135 @@
+//! Footprints the player leaves on the ground as they walk. Each one is a flat quad just above the ground that fades
+//! out over a configurable time. They're kept in a fixed-size ring, so the oldest are overwritten on long walks.
+
+use cgmath::{Point3, Vector2, Vector3};
+use yaglw::vertex_buffer::{GLArray, GLBuffer, VertexAttribData};
+use yaglw::vertex_buffer::{DrawMode, GLType};
+use yaglw::gl_context::GLContext;
+
+use common::color::Color4;
+
+use vertex::ColoredVertex;
+use view;
+
+const MAX_FOOTPRINTS: usize = 128;
+const VERTICES_PER_FOOTPRINT: usize = 6;
+
+const LENGTH: f32 = 0.5;
+const WIDTH: f32 = 0.25;
+/// How far left and right feet land from the middle of the path.
+const SPREAD: f32 = 0.2;
+/// Footprints float this far above the ground, so they don't flicker into it.
+const LIFT: f32 = 0.02;
+const OPACITY: f32 = 0.6;
+
+#[derive(Debug, Clone, Copy)]
+struct Footprint {
+  position  : Point3<f32>,
+  /// The unit direction of travel, in the xz plane.
+  heading   : Vector2<f32>,
+  placed_ns : u64,
+}
+
+#[allow(missing_docs)]
+pub struct T<'a> {
+  /// Oldest first, once the ring's wrapped around.
+  footprints : Vec<Footprint>,
+  /// Where the next footprint goes, once the ring is full.
+  next       : usize,
+  left_foot  : bool,
+  fade_ns    : u64,
+  vertices   : GLArray<'a, ColoredVertex>,
+}
+
+/// Footprints fade out over `fade_ns`; 0 means none are left at all.
+pub fn new<'a, 'b>(gl: &'b mut GLContext, shader: &view::shaders::color::T<'a>, fade_ns: u64) -> T<'a> where
+  'a: 'b,
+{
+  let buffer = GLBuffer::new(gl, MAX_FOOTPRINTS * VERTICES_PER_FOOTPRINT);
+  T {
+    footprints : Vec::with_capacity(MAX_FOOTPRINTS),
+    next       : 0,
+    left_foot  : false,
+    fade_ns    : fade_ns,
+    vertices   :
+      GLArray::new(
+        gl,
+        &shader.shader,
+        &[
+          VertexAttribData { name: "position", size: 3, unit: GLType::Float, divisor: 0 },
+          VertexAttribData { name: "in_color", size: 4, unit: GLType::Float, divisor: 0 },
+        ],
+        DrawMode::Triangles,
+        buffer,
+      ),
+  }
+}
+
+fn quad(footprint: &Footprint, alpha: f32) -> [ColoredVertex; VERTICES_PER_FOOTPRINT] {
+  let forward = Vector3::new(footprint.heading.x, 0.0, footprint.heading.y) * (LENGTH / 2.0);
+  let side = Vector3::new(-footprint.heading.y, 0.0, footprint.heading.x) * (WIDTH / 2.0);
+  let center = footprint.position + Vector3::new(0.0, LIFT, 0.0);
+  let color = Color4::of_rgba(0.2, 0.15, 0.1, OPACITY * alpha);
+  let vtx = |p| ColoredVertex { position: p, color: color };
+  let (back_left, back_right) = (center - forward - side, center - forward + side);
+  let (front_left, front_right) = (center + forward - side, center + forward + side);
+  [
+    vtx(back_left), vtx(front_right), vtx(front_left),
+    vtx(back_left), vtx(back_right), vtx(front_right),
+  ]
+}
+
+impl<'a> T<'a> {
+  /// Leave a footprint at `position` on the ground, facing along `heading` (a unit vector in the xz plane).
+  /// Footprints alternate between left and right feet.
+  pub fn push(&mut self, gl: &mut GLContext, position: Point3<f32>, heading: Vector2<f32>, now: u64) {
+    if self.fade_ns == 0 {
+      return
+    }
+
+    self.left_foot = !self.left_foot;
+    let side = if self.left_foot { -SPREAD } else { SPREAD };
+    let footprint =
+      Footprint {
+        position  : position + Vector3::new(-heading.y, 0.0, heading.x) * side,
+        heading   : heading,
+        placed_ns : now,
+      };
+
+    let vertices = quad(&footprint, 1.0);
+    self.vertices.buffer.byte_buffer.bind(gl);
+    if self.footprints.len() < MAX_FOOTPRINTS {
+      self.footprints.push(footprint);
+      assert!(self.vertices.push(gl, &vertices));
+    } else {
+      self.footprints[self.next] = footprint;
+      self.vertices.buffer.update(gl, self.next * VERTICES_PER_FOOTPRINT, &vertices);
+      self.next = (self.next + 1) % MAX_FOOTPRINTS;
+    }
+  }
+
+  /// Fade footprints out as of `now`. `vertices` is scratch space.
+  pub fn update(&mut self, gl: &mut GLContext, now: u64, vertices: &mut Vec<ColoredVertex>) {
+    if self.footprints.is_empty() {
+      return
+    }
+
+    for footprint in &self.footprints {
+      let age = now.saturating_sub(footprint.placed_ns);
+      let alpha = 1.0 - f32::min(1.0, age as f32 / self.fade_ns as f32);
+      vertices.extend(quad(footprint, alpha).iter().cloned());
+    }
+
+    self.vertices.buffer.byte_buffer.bind(gl);
+    self.vertices.buffer.update(gl, 0, vertices);
+  }
+
+  /// Draw the footprints. This should be done with the world shader bound, and face culling off.
+  pub fn draw(&self, gl: &mut GLContext) {
+    if self.footprints.is_empty() {
+      return
+    }
+    self.vertices.bind(gl);
+    self.vertices.draw(gl);
+  }
+}
@@ -0,0 +1,97 @@
+//! Scratch space for transient per-frame view data, so hot paths reuse the same allocations every frame.
+
+use std;
+
+use vertex::ColoredVertex;
+
+use super::entity;
+use super::grass_buffers;
+
+/// A reusable buffer. Its contents only live until the next time it's borrowed, or the end of the frame.
+pub struct Buffer<T> {
+  data           : Vec<T>,
+  high_watermark : usize,
+}
+
+fn buffer<T>() -> Buffer<T> {
+  Buffer {
+    data           : Vec::new(),
+    high_watermark : 0,
+  }
+}
+
+impl<T> Buffer<T> {
+  /// Borrow the (emptied) buffer.
+  pub fn get(&mut self) -> &mut Vec<T> {
+    self.reset();
+    &mut self.data
+  }
+
+  fn reset(&mut self) {
+    self.high_watermark = std::cmp::max(self.high_watermark, self.data.len());
+    self.data.clear();
+  }
+}
+
+/// Allocation statistics for a buffer, for debugging output.
+#[derive(Debug, Clone, Copy)]
+pub struct Stats {
+  /// The most elements that have been in the buffer at once.
+  pub high_watermark : usize,
+  /// The number of elements the buffer has allocated space for.
+  pub capacity       : usize,
+}
+
+impl<T> Buffer<T> {
+  #[allow(missing_docs)]
+  pub fn stats(&self) -> Stats {
+    Stats {
+      high_watermark : std::cmp::max(self.high_watermark, self.data.len()),
+      capacity       : self.data.capacity(),
+    }
+  }
+}
+
+#[allow(missing_docs)]
+pub struct T {
+  pub grass_entries      : Buffer<grass_buffers::Entry>,
+  pub grass_ids          : Buffer<entity::id::Grass>,
+  pub hud_vertices       : Buffer<ColoredVertex>,
+  pub footprint_vertices : Buffer<ColoredVertex>,
+  pub weather_vertices   : Buffer<ColoredVertex>,
+  pub debug_vertices     : Buffer<ColoredVertex>,
+}
+
+#[allow(missing_docs)]
+pub fn new() -> T {
+  T {
+    grass_entries      : buffer(),
+    grass_ids          : buffer(),
+    hud_vertices       : buffer(),
+    footprint_vertices : buffer(),
+    weather_vertices   : buffer(),
+    debug_vertices     : buffer(),
+  }
+}
+
+impl T {
+  /// Empty every buffer. This should be called once per frame.
+  pub fn reset(&mut self) {
+    self.grass_entries.reset();
+    self.grass_ids.reset();
+    self.hud_vertices.reset();
+    self.footprint_vertices.reset();
+    self.weather_vertices.reset();
+    self.debug_vertices.reset();
+  }
+
+  /// Log allocation statistics for every buffer.
+  pub fn log_stats(&self) {
+    info!("grass_entries arena: {:?}", self.grass_entries.stats());
+    info!("grass_ids arena: {:?}", self.grass_ids.stats());
+    info!("hud_vertices arena: {:?}", self.hud_vertices.stats());
+    info!("footprint_vertices arena: {:?}", self.footprint_vertices.stats());
+    info!("weather_vertices arena: {:?}", self.weather_vertices.stats());
+    info!("debug_vertices arena: {:?}", self.debug_vertices.stats());
+  }
+}
@@ -5,19 +5,23 @@ use cgmath;
 use cgmath::{Point3, EuclideanSpace};
 use gl;
 use std;
-use std::f32;
 use yaglw;
 use yaglw::gl_context::GLContext;
 
 use common::fnv_map;
+use common::index;
 
 use super::entity;
 use super::terrain_buffers;
+use super::upload_ring;
 
 // VRAM bytes
-const BYTE_BUDGET: usize = 64_000_000;
 const TUFT_COST: usize = 8;
-const TUFT_BUDGET: usize = BYTE_BUDGET / TUFT_COST;
+
+/// Each tuft is drawn as this many instances of a single quad, rotated evenly around the vertical axis.
+/// This has to match `QUADS_PER_TUFT` in the grass billboard vertex shader.
+const QUADS_PER_TUFT: u32 = 3;
+const VERTICES_PER_QUAD: usize = 6;
 
 #[derive(Debug, Clone)]
 #[repr(C)]
@@ -29,13 +33,120 @@ pub struct Entry {
   pub tex_id      : u32,
 }
 
-/// Struct for loading/unloading/maintaining terrain data in VRAM.
-pub struct T<'a> {
+/// Where each tuft is in the VRAM buffer, and which terrain polygon it sits on.
+/// This is kept apart from the GL buffers, so it can be tested without a GPU.
+pub struct Index {
   id_to_index: fnv_map::T<entity::id::Grass, usize>,
   index_to_id: Vec<entity::id::Grass>,
 
   to_polygon_idx: fnv_map::T<entity::id::Grass, u32>,
   of_polygon_idx: fnv_map::T<u32, entity::id::Grass>,
+}
+
+impl Index {
+  #[allow(missing_docs)]
+  pub fn new() -> Index {
+    Index {
+      id_to_index: fnv_map::new(),
+      index_to_id: Vec::new(),
+
+      to_polygon_idx: fnv_map::new(),
+      of_polygon_idx: fnv_map::new(),
+    }
+  }
+
+  #[allow(missing_docs)]
+  pub fn contains(&self, id: entity::id::Grass) -> bool {
+    self.id_to_index.contains_key(&id)
+  }
+
+  /// The ids of every tuft, in VRAM order.
+  pub fn ids(&self) -> &[entity::id::Grass] {
+    &self.index_to_id
+  }
+
+  #[allow(missing_docs)]
+  pub fn len(&self) -> usize {
+    self.index_to_id.len()
+  }
+
+  /// Record tufts appended to the VRAM buffer.
+  pub fn push(&mut self, grass: &[Entry], grass_ids: &[entity::id::Grass]) {
+    assert!(grass.len() == grass_ids.len());
+
+    for id in grass_ids {
+      let previous = self.id_to_index.insert(*id, self.index_to_id.len());
+      assert!(previous.is_none());
+      self.index_to_id.push(*id);
+    }
+
+    for (id, grass) in grass_ids.iter().zip(grass.iter()) {
+      debug!("Insert {:?} {:?}", *id, grass.polygon_idx);
+      let previous = self.to_polygon_idx.insert(*id, grass.polygon_idx);
+      assert!(previous.is_none());
+      let previous = self.of_polygon_idx.insert(grass.polygon_idx, *id);
+      assert!(previous.is_none());
+    }
+  }
+
+  /// Forget a tuft. The VRAM buffer should swap-remove the returned index to match.
+  // Note: `id` must be present.
+  pub fn swap_remove(&mut self, id: entity::id::Grass) -> usize {
+    let idx = (*self).id_to_index[&id];
+    let swapped_id = self.index_to_id[self.index_to_id.len() - 1];
+    self.index_to_id.swap_remove(idx);
+    self.id_to_index.remove(&id);
+
+    if id != swapped_id {
+      self.id_to_index.insert(swapped_id, idx);
+    }
+
+    let polygon_idx = self.to_polygon_idx.remove(&id).unwrap();
+    self.of_polygon_idx.remove(&polygon_idx).unwrap();
+    debug!("Swap-remove {:?} {:?} with {:?}", id, polygon_idx, swapped_id);
+    idx
+  }
+
+  /// Whether any tuft sits on a polygon in a terrain chunk.
+  pub fn has_grass_on(&self, chunk: terrain_buffers::ChunkIndex) -> bool {
+    index::all().any(|i| self.of_polygon_idx.contains_key(&chunk.subindex(i).to_u32()))
+  }
+
+  /// Move the grass on every polygon of one terrain chunk onto the same polygons of another, to follow the chunk when
+  /// it's moved in VRAM. `moved` is called with the VRAM index and new polygon index of each tuft that moves, so the
+  /// VRAM entries can be updated to match. There must be no grass on the chunk being moved onto.
+  pub fn move_chunk<Moved>(
+    &mut self,
+    from  : terrain_buffers::ChunkIndex,
+    to    : terrain_buffers::ChunkIndex,
+    mut moved : Moved,
+  ) where
+    Moved: FnMut(usize, u32),
+  {
+    if from.to_u32() == to.to_u32() {
+      return
+    }
+    assert!(!self.has_grass_on(to), "Moving grass onto chunk {:?}, which still has grass", to);
+
+    for i in index::all() {
+      let polygon_idx = from.subindex(i).to_u32();
+      let new_index = to.subindex(i).to_u32();
+      let grass_id =
+        match self.of_polygon_idx.remove(&polygon_idx) {
+          None => continue,
+          Some(id) => id,
+        };
+      debug!("Update {:?} {:?} to {:?}", grass_id, polygon_idx, new_index);
+      self.of_polygon_idx.insert(new_index, grass_id);
+      self.to_polygon_idx.insert(grass_id, new_index);
+      moved(self.id_to_index[&grass_id], new_index);
+    }
+  }
+}
+
+/// Struct for loading/unloading/maintaining terrain data in VRAM.
+pub struct T<'a> {
+  index: Index,
 
   gl_array: yaglw::vertex_buffer::ArrayHandle<'a>,
   _instance_vertices: yaglw::vertex_buffer::GLBuffer<'a, Vertex>,
@@ -43,67 +154,60 @@ pub struct T<'a> {
 }
 
 struct Vertex {
-  /// The position of this vertex in the world.
+  /// The position of this vertex in the unrotated quad.
   pub world_position    : cgmath::Point3<f32>,
 
   /// The position of this vertex on a texture. The range of valid values
   /// in each dimension is [0, 1].
   pub texture_position  : cgmath::Vector2<f32>,
+}
 
-  /// The translation of the entire model (applied after scaling effects)
-  pub model_translation : cgmath::Vector3<f32>,
+/// How many grass tufts fit in some number of bytes of VRAM.
+pub fn tuft_budget(bytes: usize) -> usize {
+  bytes / TUFT_COST
 }
 
-#[allow(missing_docs)]
+/// Buffers with room for `tuft_budget` tufts.
 pub fn new<'a, 'b:'a>(
   gl: &'a mut GLContext,
   shader: &yaglw::shader::Shader<'a>,
+  tuft_budget: usize,
 ) -> T<'b>
 {
   use yaglw::vertex_buffer;
 
   let gl_array = vertex_buffer::ArrayHandle::new(gl);
-  let mut instance_vertices = vertex_buffer::GLBuffer::new(gl, 18);
-  let per_tuft = vertex_buffer::GLBuffer::new(gl, TUFT_BUDGET);
+  let mut instance_vertices = vertex_buffer::GLBuffer::new(gl, VERTICES_PER_QUAD);
+  let per_tuft = vertex_buffer::GLBuffer::new(gl, tuft_budget);
 
-  let mut vertices = Vec::new();
+  // The shader rotates this quad (and its offset from the tuft's root) for each instance.
+  let mut vertices = Vec::with_capacity(VERTICES_PER_QUAD);
   {
     let normal = cgmath::Vector3::new(0.0, 1.0, 0.0);
-    let mut quad = |t: &cgmath::Matrix3<f32>| {
-      let mut tri = |mt, p0, t0, p1, t1, p2, t2| {
-        let vert = |p, t| {
-          Vertex {
-            world_position: p,
-            model_translation: mt,
-            texture_position: t,
-          }
-        };
-        vertices.push(vert(p0, t0));
-        vertices.push(vert(p1, t1));
-        vertices.push(vert(p2, t2));
+    let mut tri = |p0, t0, p1, t1, p2, t2| {
+      let vert = |p, t| {
+        Vertex {
+          world_position: p,
+          texture_position: t,
+        }
       };
-
-      let v = *t * cgmath::Vector3::new(0.0, 0.0, -1.0);
-      let model_translation = *t * cgmath::Vector3::new(1.0, 0.0, 0.0) / 2.0;
-
-      tri(
-        model_translation,
-        Point3::from_vec(-v/2.0)          , cgmath::Vector2::new(0.0 , 0.0) ,
-        Point3::from_vec( v/2.0)          , cgmath::Vector2::new(1.0 , 0.0) ,
-        Point3::from_vec( v/2.0 + normal) , cgmath::Vector2::new(1.0 , 1.0) ,
-      );
-      tri(
-        model_translation,
-        Point3::from_vec(-v/2.0)          , cgmath::Vector2::new(0.0 , 0.0) ,
-        Point3::from_vec( v/2.0 + normal) , cgmath::Vector2::new(1.0 , 1.0) ,
-        Point3::from_vec(-v/2.0 + normal) , cgmath::Vector2::new(0.0 , 1.0) ,
-      );
+      vertices.push(vert(p0, t0));
+      vertices.push(vert(p1, t1));
+      vertices.push(vert(p2, t2));
     };
 
-    use cgmath::SquareMatrix;
-    quad(&cgmath::Matrix3::from_value(1.0));
-    quad(&cgmath::Matrix3::from_axis_angle(normal, cgmath::Rad(f32::consts::FRAC_PI_3)));
-    quad(&cgmath::Matrix3::from_axis_angle(normal, cgmath::Rad(2.0 * f32::consts::FRAC_PI_3)));
+    let v = cgmath::Vector3::new(0.0, 0.0, -1.0);
+
+    tri(
+      Point3::from_vec(-v/2.0)          , cgmath::Vector2::new(0.0 , 0.0) ,
+      Point3::from_vec( v/2.0)          , cgmath::Vector2::new(1.0 , 0.0) ,
+      Point3::from_vec( v/2.0 + normal) , cgmath::Vector2::new(1.0 , 1.0) ,
+    );
+    tri(
+      Point3::from_vec(-v/2.0)          , cgmath::Vector2::new(0.0 , 0.0) ,
+      Point3::from_vec( v/2.0 + normal) , cgmath::Vector2::new(1.0 , 1.0) ,
+      Point3::from_vec(-v/2.0 + normal) , cgmath::Vector2::new(0.0 , 1.0) ,
+    );
   }
 
   unsafe {
@@ -127,12 +231,6 @@ pub fn new<'a, 'b:'a>(
           unit: vertex_buffer::GLType::Float,
           divisor: 0,
         },
-        vertex_buffer::VertexAttribData {
-          name: "model_translation",
-          size: 3,
-          unit: vertex_buffer::GLType::Float,
-          divisor: 0,
-        },
       ],
       gl,
       shader,
@@ -147,13 +245,13 @@ pub fn new<'a, 'b:'a>(
           name: "polygon_id",
           size: 1,
           unit: vertex_buffer::GLType::Int,
-          divisor: 1,
+          divisor: QUADS_PER_TUFT,
         },
         vertex_buffer::VertexAttribData {
           name: "tex_id",
           size: 1,
           unit: vertex_buffer::GLType::UInt,
-          divisor: 1,
+          divisor: QUADS_PER_TUFT,
         },
       ],
       gl,
@@ -162,11 +260,7 @@ pub fn new<'a, 'b:'a>(
   assert!(attrib_span == std::mem::size_of::<Entry>() as u32);
 
   T {
-    id_to_index: fnv_map::new(),
-    index_to_id: Vec::new(),
-
-    to_polygon_idx: fnv_map::new(),
-    of_polygon_idx: fnv_map::new(),
+    index: Index::new(),
 
     gl_array: gl_array,
     _instance_vertices: instance_vertices,
@@ -174,92 +268,153 @@ pub fn new<'a, 'b:'a>(
   }
 }
 
+/// Whether a grass tuft is drawn at some density in [0, 1]. This is a deterministic function of the id, and any tuft
+/// drawn at some density is also drawn at every higher density.
+pub fn is_drawn_at(id: entity::id::Grass, density: f32) -> bool {
+  // Scatter ids (which are allocated sequentially) over [0, 1).
+  let hash = id.to_u32().wrapping_mul(2654435761);
+  (hash as f64 / (1u64 << 32) as f64) < density as f64
+}
+
 impl<'a> T<'a> {
+  #[allow(missing_docs)]
+  pub fn contains(&self, id: entity::id::Grass) -> bool {
+    self.index.contains(id)
+  }
+
+  /// The ids of every tuft in VRAM.
+  pub fn ids(&self) -> &[entity::id::Grass] {
+    self.index.ids()
+  }
+
+  /// How many tufts are in VRAM.
+  pub fn len(&self) -> usize {
+    self.index.len()
+  }
+
   /// Add a series of entites into VRAM.
   pub fn push(
     &mut self,
-    gl: &mut GLContext,
+    uploads: &mut upload_ring::T,
     grass: &[Entry],
     grass_ids: &[entity::id::Grass],
   ) {
     assert!(grass.len() == grass_ids.len());
 
-    self.per_tuft.byte_buffer.bind(gl);
-    let success: bool = self.per_tuft.push(gl, grass);
+    let success: bool = uploads.push(&mut self.per_tuft, grass);
     if !success {
       panic!("Ran out of VRAM for grass");
     }
 
-    for id in grass_ids {
-      let previous = self.id_to_index.insert(*id, self.index_to_id.len());
-      assert!(previous.is_none());
-      self.index_to_id.push(*id);
-    }
-
-    for (id, grass) in grass_ids.iter().zip(grass.iter()) {
-      debug!("Insert {:?} {:?}", *id, grass.polygon_idx);
-      let previous = self.to_polygon_idx.insert(*id, grass.polygon_idx);
-      assert!(previous.is_none());
-      let previous = self.of_polygon_idx.insert(grass.polygon_idx, *id);
-      assert!(previous.is_none());
-    }
+    self.index.push(grass, grass_ids);
   }
 
   // TODO: Make this take many ids as a parameter, to reduce `bind`s.
   // Note: `id` must be present in the buffers.
   /// Remove some entity from VRAM.
   pub fn swap_remove(&mut self, gl: &mut GLContext, id: entity::id::Grass) {
-    let idx = (*self).id_to_index[&id];
-    let swapped_id = self.index_to_id[self.index_to_id.len() - 1];
-    self.index_to_id.swap_remove(idx);
-    self.id_to_index.remove(&id);
-
-    if id != swapped_id {
-      self.id_to_index.insert(swapped_id, idx);
-    }
-
+    let idx = self.index.swap_remove(id);
     self.per_tuft.byte_buffer.bind(gl);
     self.per_tuft.swap_remove(gl, idx, 1);
-
-    let polygon_idx = self.to_polygon_idx.remove(&id).unwrap();
-    self.of_polygon_idx.remove(&polygon_idx).unwrap();
-    debug!("Swap-remove {:?} {:?} with {:?}", id, polygon_idx, swapped_id);
   }
 
-  /// Update the index of the underlying polygon that a grass tuft is associated with.
-  pub fn update_polygon_index(
+  /// Swap-remove a chunk from the terrain buffers, and move the grass on the chunk that takes its place along with it.
+  /// Any grass on the removed chunk has to have been removed already.
+  pub fn swap_remove_chunk(
     &mut self,
-    gl          : &mut GLContext,
-    polygon_idx : terrain_buffers::PolygonIndex,
-    new_index   : terrain_buffers::PolygonIndex,
+    gl       : &mut GLContext,
+    terrain  : &mut terrain_buffers::T,
+    chunk_id : entity::id::Terrain,
   ) {
-    let grass_id =
-      match self.of_polygon_idx.remove(&polygon_idx.to_u32()) {
+    let chunk_idx = terrain.lookup_opengl_index(chunk_id).unwrap();
+    assert!(!self.index.has_grass_on(chunk_idx), "Removing chunk {:?}, which still has grass", chunk_id);
+
+    let (idx, swapped_idx) =
+      match terrain.swap_remove(gl, chunk_id) {
         None => return,
-        Some(id) => id,
+        Some(moved) => moved,
       };
-    debug!("Update {:?} {:?} to {:?}", grass_id, polygon_idx, new_index);
-    self.of_polygon_idx.insert(new_index.to_u32(), grass_id);
-    self.to_polygon_idx.insert(grass_id, new_index.to_u32());
-    let entry_idx = self.id_to_index[&grass_id];
-    // update the underlying byte buffer directly and only touch the polygon
-    // index field.
+
     self.per_tuft.byte_buffer.bind(gl);
-    unsafe {
-      self.per_tuft.byte_buffer.update(
-        gl,
-        std::mem::size_of::<Entry>() * entry_idx,
-        &new_index as *const _ as *const u8,
-        std::mem::size_of::<u32>(),
-      );
-    }
+    let per_tuft = &mut self.per_tuft;
+    self.index.move_chunk(swapped_idx, idx, |entry_idx, new_index| {
+      // update the underlying byte buffer directly and only touch the polygon
+      // index field.
+      unsafe {
+        per_tuft.byte_buffer.update(
+          gl,
+          std::mem::size_of::<Entry>() * entry_idx,
+          &new_index as *const u32 as *const u8,
+          std::mem::size_of::<u32>(),
+        );
+      }
+    });
   }
 
   #[allow(missing_docs)]
   pub fn draw(&self, _gl: &mut GLContext) {
     unsafe {
       gl::BindVertexArray(self.gl_array.gl_id);
-      gl::DrawArraysInstanced(gl::TRIANGLES, 0, 18, self.index_to_id.len() as i32);
+      gl::DrawArraysInstanced(
+        gl::TRIANGLES,
+        0,
+        VERTICES_PER_QUAD as i32,
+        (QUADS_PER_TUFT as usize * self.index.len()) as i32,
+      );
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use common::id_allocator;
+  use common::index;
+
+  use super::*;
+  use super::super::entity;
+
+  fn ids(n: usize) -> Vec<entity::id::Grass> {
+    let mut allocator = id_allocator::new();
+    (0 .. n).map(|_| allocator.allocate()).collect()
+  }
+
+  fn entry(chunk: u32, offset: u32) -> Entry {
+    Entry {
+      polygon_idx : chunk * terrain_buffers::CHUNK_LENGTH as u32 + offset,
+      tex_id      : 0,
     }
   }
+
+  #[test]
+  fn test_move_chunk() {
+    let last = terrain_buffers::CHUNK_LENGTH as u32 - 1;
+    let mut grass = Index::new();
+    let ids = ids(4);
+    // Grass on the first and last polygons of chunks 0 and 3.
+    grass.push(&[entry(0, 0), entry(0, last), entry(3, 0), entry(3, last)], &ids);
+
+    // Chunk 0 is removed, and chunk 3 is swapped into its place.
+    grass.swap_remove(ids[0]);
+    grass.swap_remove(ids[1]);
+    assert!(!grass.has_grass_on(index::of_u32(0)));
+
+    let mut moved = Vec::new();
+    grass.move_chunk(index::of_u32(3), index::of_u32(0), |idx, polygon_idx| moved.push((idx, polygon_idx)));
+    moved.sort();
+    // The swap-removes left ids[3] at index 0 and ids[2] at index 1.
+    assert_eq!(moved, vec!((0, last), (1, 0)));
+    assert!(grass.has_grass_on(index::of_u32(0)));
+    assert!(!grass.has_grass_on(index::of_u32(3)));
+    // The grass on the last polygon of chunk 0 isn't mistaken for grass on chunk 1.
+    assert!(!grass.has_grass_on(index::of_u32(1)));
+  }
+
+  #[test]
+  #[should_panic]
+  fn test_move_chunk_onto_grass() {
+    let mut grass = Index::new();
+    let ids = ids(2);
+    grass.push(&[entry(0, 5), entry(1, 5)], &ids);
+    grass.move_chunk(index::of_u32(1), index::of_u32(0), |_, _| {});
+  }
 }
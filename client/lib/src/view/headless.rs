@@ -0,0 +1,187 @@
+//! A stand-in for the view's terrain and grass VRAM buffers, kept in RAM, so the bookkeeping in `update` can be tested
+//! without a GPU. Recorded mesh loads and unloads are replayed into it, and its "VRAM" is checked after every one.
+
+use cgmath::{Point3, Vector3};
+
+use common::fnv_map;
+use common::id_allocator;
+
+use terrain_mesh;
+
+use super::chunked_terrain;
+use super::entity;
+use super::grass_buffers;
+use super::terrain_buffers;
+use super::update;
+
+struct T {
+  chunks         : terrain_buffers::Index,
+  grass          : grass_buffers::Index,
+  /// Which chunk is in each slot of the chunk buffers.
+  vram_chunks    : Vec<entity::id::Terrain>,
+  /// The per-tuft buffer.
+  vram_grass     : Vec<grass_buffers::Entry>,
+  /// The chunk and polygon offset each loaded tuft was put on, according to its mesh.
+  grass_polygons : fnv_map::T<entity::id::Grass, (entity::id::Terrain, u32)>,
+}
+
+fn new() -> T {
+  T {
+    chunks         : terrain_buffers::Index::new(),
+    grass          : grass_buffers::Index::new(),
+    vram_chunks    : Vec::new(),
+    vram_grass     : Vec::new(),
+    grass_polygons : fnv_map::new(),
+  }
+}
+
+impl update::MeshBuffers for T {
+  fn lookup_chunk(&self, id: entity::id::Terrain) -> Option<terrain_buffers::ChunkIndex> {
+    self.chunks.lookup_opengl_index(id)
+  }
+
+  fn push_chunk(&mut self, mesh: &chunked_terrain::T, i: usize) {
+    self.chunks.push(mesh.ids[i]);
+    self.vram_chunks.push(mesh.ids[i]);
+  }
+
+  fn swap_remove_chunk(&mut self, id: entity::id::Terrain) {
+    assert!(!self.grass.has_grass_on(self.chunks.lookup_opengl_index(id).unwrap()));
+    let (idx, moved) = self.chunks.swap_remove(id);
+    self.vram_chunks.swap_remove(idx);
+    if let Some((idx, swapped_idx)) = moved {
+      let vram_grass = &mut self.vram_grass;
+      self.grass.move_chunk(swapped_idx, idx, |entry_idx, polygon_idx| {
+        vram_grass[entry_idx].polygon_idx = polygon_idx;
+      });
+    }
+  }
+
+  fn contains_grass(&self, id: entity::id::Grass) -> bool {
+    self.grass.contains(id)
+  }
+
+  fn push_grass(&mut self, grass: &[grass_buffers::Entry], ids: &[entity::id::Grass]) {
+    self.grass.push(grass, ids);
+    self.vram_grass.extend_from_slice(grass);
+  }
+
+  fn swap_remove_grass(&mut self, id: entity::id::Grass) {
+    let idx = self.grass.swap_remove(id);
+    self.vram_grass.swap_remove(idx);
+    self.grass_polygons.remove(&id);
+  }
+}
+
+impl T {
+  fn load(&mut self, mesh: &chunked_terrain::T, grass_density: f32) {
+    let mut grass_entries = Vec::new();
+    let mut grass_ids = Vec::new();
+    update::load_mesh(self, mesh, grass_density, &mut grass_entries, &mut grass_ids);
+    for i in 0 .. mesh.grass.len() {
+      if self.grass.contains(mesh.grass.ids[i]) {
+        self.grass_polygons.insert(
+          mesh.grass.ids[i],
+          (mesh.grass.polygon_chunk_ids[i], mesh.grass.polygon_offsets[i].to_u32()),
+        );
+      }
+    }
+    self.check();
+  }
+
+  fn unload(&mut self, mesh: &chunked_terrain::T) {
+    let ids =
+      terrain_mesh::Ids {
+        chunk_ids : mesh.ids.clone(),
+        grass_ids : mesh.grass.ids.clone(),
+      };
+    update::unload_mesh(self, ids);
+    self.check();
+  }
+
+  /// Check that the "VRAM" matches the bookkeeping, and every tuft still points at the polygon it was put on.
+  fn check(&self) {
+    assert_eq!(self.vram_chunks.len(), self.chunks.len());
+    for (i, &id) in self.vram_chunks.iter().enumerate() {
+      assert_eq!(self.chunks.lookup_opengl_index(id).map(|idx| idx.to_u32()), Some(i as u32));
+    }
+
+    assert_eq!(self.vram_grass.len(), self.grass.len());
+    assert_eq!(self.grass_polygons.len(), self.grass.len());
+    let chunk_length = terrain_buffers::CHUNK_LENGTH as u32;
+    for (entry, id) in self.vram_grass.iter().zip(self.grass.ids().iter()) {
+      let (chunk_id, offset) = self.grass_polygons[id];
+      let chunk_idx = entry.polygon_idx / chunk_length;
+      assert!((chunk_idx as usize) < self.vram_chunks.len(), "{:?} points past the loaded chunks", id);
+      assert_eq!(self.vram_chunks[chunk_idx as usize], chunk_id, "{:?} is on the wrong chunk", id);
+      assert_eq!(entry.polygon_idx % chunk_length, offset, "{:?} is on the wrong polygon", id);
+    }
+  }
+}
+
+/// A mesh of `polygons` flat polygons, with grass on every `grass_every`th one.
+fn mesh(
+  terrain_ids : &mut id_allocator::T<entity::id::Terrain>,
+  grass_ids   : &mut id_allocator::T<entity::id::Grass>,
+  polygons    : usize,
+  grass_every : usize,
+) -> chunked_terrain::T {
+  let mut mesh = chunked_terrain::empty();
+  let p = Point3::new(0.0, 0.0, 0.0);
+  let n = Vector3::new(0.0, 1.0, 0.0);
+  for i in 0 .. polygons {
+    let grass =
+      if i % grass_every == 0 {
+        Some(chunked_terrain::PushGrass { tex_id: 0, id: grass_ids.allocate() })
+      } else {
+        None
+      };
+    mesh.push(
+      terrain_ids,
+      terrain_mesh::tri(p, p, p),
+      terrain_mesh::tri(n, n, n),
+      0,
+      terrain_mesh::tri(1.0, 1.0, 1.0),
+      terrain_mesh::tri(0.0, 0.0, 0.0),
+      grass,
+    );
+  }
+  mesh
+}
+
+fn replay(grass_density: f32) {
+  let mut terrain_ids = id_allocator::new();
+  let mut grass_ids = id_allocator::new();
+  let chunk_length = terrain_buffers::CHUNK_LENGTH;
+
+  let a = mesh(&mut terrain_ids, &mut grass_ids, 3 * chunk_length - 5, 1);
+  let b = mesh(&mut terrain_ids, &mut grass_ids, 2 * chunk_length, 3);
+  let c = mesh(&mut terrain_ids, &mut grass_ids, chunk_length + 1, 2);
+  let d = mesh(&mut terrain_ids, &mut grass_ids, 4 * chunk_length, 1);
+
+  let mut vram = new();
+  vram.load(&a, grass_density);
+  vram.load(&b, grass_density);
+  vram.load(&c, grass_density);
+  // The last chunks get swapped into `a`'s place, dragging their grass along.
+  vram.unload(&a);
+  vram.load(&d, grass_density);
+  vram.unload(&c);
+  vram.load(&a, grass_density);
+  vram.unload(&d);
+  vram.unload(&b);
+  vram.unload(&a);
+
+  assert_eq!(vram.chunks.len(), 0);
+  assert_eq!(vram.grass.len(), 0);
+}
+
+#[test]
+fn test_swap_remove_cascade() {
+  replay(1.0);
+}
+
+#[test]
+fn test_swap_remove_cascade_sparse_grass() {
+  replay(0.5);
+}
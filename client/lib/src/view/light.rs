@@ -52,6 +52,28 @@ impl Sun {
   }
 }
 
+#[derive(Debug, Clone, Copy)]
+/// The sun's motion, so it can be moved smoothly every frame rather than jumping on every server update.
+pub struct SunClock {
+  /// The portion of the sun's cycle completed at `time_ns`, in [0, 1). This advances linearly in time.
+  pub phase    : f64,
+  /// The local time, in nanoseconds, at which the sun was at `phase`.
+  pub time_ns  : u64,
+  /// How long a full cycle takes, in nanoseconds.
+  pub cycle_ns : u64,
+}
+
+impl SunClock {
+  /// Extrapolate `Sun::progression` to some local time.
+  pub fn progression_at(&self, now_ns: u64) -> f32 {
+    let elapsed = now_ns as i64 - self.time_ns as i64;
+    let phase = self.phase + elapsed as f64 / self.cycle_ns as f64;
+    let phase = phase - phase.floor();
+    // Longer day, shorter night.
+    (phase * phase) as f32
+  }
+}
+
 /// Sets the `sun` struct in some shader.
 pub fn set_sun(shader: &mut Shader, gl: &mut GLContext, sun: &Sun) {
   let sun_direction_uniform = shader.get_uniform_location("sun.direction");
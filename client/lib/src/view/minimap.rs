@@ -0,0 +1,293 @@
+//! A top-down map of the terrain around the player, drawn in a corner of the HUD with an arrow for the player.
+//! Each texel is a column of the world, colored by the material at the top of the column and shaded by how high that
+//! is relative to the player. Columns are summarized from terrain meshes as they're loaded and unloaded, and the
+//! texture is redrawn when they change or the player moves into another column.
+
+use cgmath::{Point3, Vector2, Vector3};
+use gl;
+use std;
+use yaglw::gl_context::GLContext;
+use yaglw::texture::{Texture2D, TextureUnit};
+use yaglw::vertex_buffer::{GLArray, GLBuffer, VertexAttribData};
+use yaglw::vertex_buffer::{DrawMode, GLType};
+
+use common::color::Color4;
+use common::fnv_map;
+
+use vertex::{ColoredVertex, TextureVertex};
+use view;
+
+use super::chunked_terrain;
+use super::entity;
+use super::terrain_buffers;
+
+/// The map is this many columns across.
+const TEXELS: usize = 64;
+/// The width of a column, in world units.
+const COLUMN_WIDTH: f32 = 4.0;
+/// The width of the map, as a fraction of the screen height.
+const SIZE: f32 = 0.5;
+/// The gap between the map and the corner of the screen.
+const MARGIN: f32 = 0.05;
+/// Columns this far above or below the player are drawn brightest or darkest.
+const SHADE_RANGE: f32 = 64.0;
+const ARROW_LENGTH: f32 = 0.04;
+
+type Column = (i32, i32);
+
+fn column_of(x: f32, z: f32) -> Column {
+  ((x / COLUMN_WIDTH).floor() as i32, (z / COLUMN_WIDTH).floor() as i32)
+}
+
+/// The top of a column within one terrain chunk.
+#[derive(Debug, Clone, Copy)]
+struct Top {
+  chunk    : entity::id::Terrain,
+  height   : f32,
+  material : i32,
+}
+
+#[allow(missing_docs)]
+pub struct T<'a> {
+  /// The columns each loaded terrain chunk has a top in.
+  chunk_columns : fnv_map::T<entity::id::Terrain, Vec<Column>>,
+  /// The tops of each column within every loaded chunk that reaches into it.
+  columns       : fnv_map::T<Column, Vec<Top>>,
+  /// The column the texture was last drawn around, unless it's out of date.
+  drawn_around  : Option<Column>,
+  pixels        : Vec<u8>,
+  texture       : Texture2D<'a>,
+  quad          : GLArray<'a, TextureVertex>,
+  arrow         : GLArray<'a, ColoredVertex>,
+}
+
+/// `texture_shader` draws the map, and `hud_shader` draws the player's arrow.
+pub fn new<'a, 'b>(
+  gl: &'b mut GLContext,
+  texture_shader: &view::shaders::texture::T<'a>,
+  hud_shader: &view::shaders::color::T<'a>,
+) -> T<'a> where
+  'a: 'b,
+{
+  let texture = Texture2D::new(gl);
+  unsafe {
+    gl::BindTexture(gl::TEXTURE_2D, texture.handle.gl_id);
+    gl::TexImage2D(
+      gl::TEXTURE_2D, 0, gl::RGBA as i32, TEXELS as i32, TEXELS as i32, 0, gl::RGBA, gl::UNSIGNED_BYTE,
+      std::ptr::null(),
+    );
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as i32);
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST as i32);
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as i32);
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as i32);
+  }
+
+  let quad = {
+    let buffer = GLBuffer::new(gl, 6);
+    let mut quad =
+      GLArray::new(
+        gl,
+        &texture_shader.shader,
+        &[
+          VertexAttribData { name: "position", size: 3, unit: GLType::Float, divisor: 0 },
+          VertexAttribData { name: "texture_position", size: 2, unit: GLType::Float, divisor: 0 },
+        ],
+        DrawMode::Triangles,
+        buffer,
+      );
+    let v = TextureVertex { world_position: Point3::new(0.0, 0.0, 0.0), texture_position: Vector2::new(0.0, 0.0) };
+    quad.buffer.byte_buffer.bind(gl);
+    assert!(quad.push(gl, &[v; 6]));
+    quad
+  };
+
+  let arrow = {
+    let buffer = GLBuffer::new(gl, 3);
+    let mut arrow =
+      GLArray::new(
+        gl,
+        &hud_shader.shader,
+        &[
+          VertexAttribData { name: "position", size: 3, unit: GLType::Float, divisor: 0 },
+          VertexAttribData { name: "in_color", size: 4, unit: GLType::Float, divisor: 0 },
+        ],
+        DrawMode::Triangles,
+        buffer,
+      );
+    let v = ColoredVertex { position: Point3::new(0.0, 0.0, 0.0), color: arrow_color() };
+    arrow.buffer.byte_buffer.bind(gl);
+    assert!(arrow.push(gl, &[v; 3]));
+    arrow
+  };
+
+  T {
+    chunk_columns : fnv_map::new(),
+    columns       : fnv_map::new(),
+    drawn_around  : None,
+    pixels        : vec!(0; TEXELS * TEXELS * 4),
+    texture       : texture,
+    quad          : quad,
+    arrow         : arrow,
+  }
+}
+
+fn arrow_color() -> Color4<f32> {
+  Color4::of_rgba(1.0, 0.2, 0.2, 1.0)
+}
+
+/// The color of a terrain material, as numbered in the terrain shader.
+fn material_color(material: i32) -> [f32; 3] {
+  match material {
+    1 => [0.3, 0.5, 0.1],
+    2 => [0.4, 0.25, 0.1],
+    3 => [0.1, 0.35, 0.05],
+    4 => [0.5, 0.5, 0.5],
+    5 => [0.1, 0.1, 0.1],
+    6 => [0.08, 0.04, 0.12],
+    _ => [0.5, 0.0, 0.5],
+  }
+}
+
+impl<'a> T<'a> {
+  /// Add the columns a newly-loaded terrain mesh reaches into.
+  pub fn load(&mut self, mesh: &chunked_terrain::T) {
+    let polygons = mesh.polygon_count();
+    for i in 0 .. mesh.chunk_count() {
+      let id = mesh.ids[i];
+      let len = std::cmp::min(terrain_buffers::CHUNK_LENGTH, polygons - i * terrain_buffers::CHUNK_LENGTH);
+      let mut tops: fnv_map::T<Column, Top> = fnv_map::new();
+      for j in 0 .. len {
+        let triangle = &mesh.vertex_coordinates[i].0[j];
+        let (v1, v2, v3) = (triangle.v1, triangle.v2, triangle.v3);
+        let column = column_of((v1.x + v2.x + v3.x) / 3.0, (v1.z + v2.z + v3.z) / 3.0);
+        let top = Top { chunk: id, height: f32::max(v1.y, f32::max(v2.y, v3.y)), material: mesh.materials[i].0[j] };
+        let entry = tops.entry(column).or_insert(top);
+        if top.height > entry.height {
+          *entry = top;
+        }
+      }
+
+      self.chunk_columns.insert(id, tops.keys().cloned().collect());
+      for (column, top) in tops {
+        self.columns.entry(column).or_insert_with(Vec::new).push(top);
+      }
+    }
+    self.drawn_around = None;
+  }
+
+  /// Forget the columns of unloaded terrain chunks.
+  pub fn unload(&mut self, chunk_ids: &[entity::id::Terrain]) {
+    for id in chunk_ids {
+      let columns =
+        match self.chunk_columns.remove(id) {
+          None => continue,
+          Some(columns) => columns,
+        };
+      for column in columns {
+        let now_empty = {
+          let tops = self.columns.get_mut(&column).unwrap();
+          tops.retain(|top| top.chunk != *id);
+          tops.is_empty()
+        };
+        if now_empty {
+          self.columns.remove(&column);
+        }
+      }
+    }
+    self.drawn_around = None;
+  }
+
+  /// Redraw the map around the player, if it's out of date.
+  pub fn update(&mut self, player: &Point3<f32>) {
+    let center = column_of(player.x, player.z);
+    if self.drawn_around == Some(center) {
+      return
+    }
+
+    let half = (TEXELS / 2) as i32;
+    for v in 0 .. TEXELS {
+    for u in 0 .. TEXELS {
+      // Up on the map is -z.
+      let column = (center.0 - half + u as i32, center.1 + half - 1 - v as i32);
+      let top =
+        self.columns.get(&column)
+        .and_then(|tops| tops.iter().fold(None, |highest: Option<&Top>, top| {
+          match highest {
+            Some(highest) if highest.height >= top.height => Some(highest),
+            _ => Some(top),
+          }
+        }));
+      let rgba =
+        match top {
+          None => [0, 0, 0, 100],
+          Some(top) => {
+            let shade = f32::max(0.25, f32::min(1.25, 0.75 + (top.height - player.y) / SHADE_RANGE / 2.0));
+            let color = material_color(top.material);
+            let channel = |c: f32| (f32::min(1.0, c * shade) * 255.0) as u8;
+            [channel(color[0]), channel(color[1]), channel(color[2]), 220]
+          },
+        };
+      let i = 4 * (v * TEXELS + u);
+      self.pixels[i .. i + 4].copy_from_slice(&rgba);
+    }}
+
+    unsafe {
+      gl::BindTexture(gl::TEXTURE_2D, self.texture.handle.gl_id);
+      gl::TexSubImage2D(
+        gl::TEXTURE_2D, 0, 0, 0, TEXELS as i32, TEXELS as i32, gl::RGBA, gl::UNSIGNED_BYTE,
+        self.pixels.as_ptr() as *const _,
+      );
+    }
+    self.drawn_around = Some(center);
+  }
+
+  /// Draw the map in the top right corner of the screen, with an arrow in the middle pointing along `forward`.
+  /// This should be done with depth testing off. It leaves the HUD shader bound.
+  pub fn draw(
+    &mut self,
+    gl: &mut GLContext,
+    shaders: &mut view::shaders::T,
+    texture_unit: &TextureUnit,
+    aspect: f32,
+    forward: &Vector3<f32>,
+  ) {
+    let (right, top) = (aspect - MARGIN, 1.0 - MARGIN);
+    let (left, bottom) = (right - SIZE, top - SIZE);
+    let vtx = |x, y, u, v| {
+      TextureVertex { world_position: Point3::new(x, y, 0.0), texture_position: Vector2::new(u, v) }
+    };
+    let quad = [
+      vtx(left, bottom, 0.0, 0.0), vtx(right, top, 1.0, 1.0), vtx(left, top, 0.0, 1.0),
+      vtx(left, bottom, 0.0, 0.0), vtx(right, bottom, 1.0, 0.0), vtx(right, top, 1.0, 1.0),
+    ];
+    self.quad.buffer.byte_buffer.bind(gl);
+    self.quad.buffer.update(gl, 0, &quad);
+
+    shaders.texture_shader.shader.use_shader(gl);
+    unsafe {
+      let alpha_threshold = shaders.texture_shader.shader.get_uniform_location("alpha_threshold");
+      gl::Uniform1f(alpha_threshold, 0.0);
+      gl::ActiveTexture(texture_unit.gl_id());
+      gl::BindTexture(gl::TEXTURE_2D, self.texture.handle.gl_id);
+    }
+    self.quad.bind(gl);
+    self.quad.draw(gl);
+
+    // Up on the map is -z.
+    let heading = Vector2::new(forward.x, -forward.z);
+    let length = (heading.x * heading.x + heading.y * heading.y).sqrt();
+    let heading = if length > 0.0 { heading / length } else { Vector2::new(0.0, 1.0) };
+    let side = Vector2::new(heading.y, -heading.x) * (ARROW_LENGTH / 3.0);
+    let center = Vector2::new((left + right) / 2.0, (bottom + top) / 2.0);
+    let tip = center + heading * (ARROW_LENGTH / 2.0);
+    let back = center - heading * (ARROW_LENGTH / 2.0);
+    let vtx = |p: Vector2<f32>| ColoredVertex { position: Point3::new(p.x, p.y, 0.0), color: arrow_color() };
+    let arrow = [vtx(tip), vtx(back + side), vtx(back - side)];
+    self.arrow.buffer.byte_buffer.bind(gl);
+    self.arrow.buffer.update(gl, 0, &arrow);
+
+    shaders.hud_color_shader.shader.use_shader(gl);
+    self.arrow.bind(gl);
+    self.arrow.draw(gl);
+  }
+}
@@ -1,5 +1,6 @@
 //! Data structures and functions to load/unload/maintain mob data in VRAM.
 
+use cgmath::Point3;
 use std::collections::hash_map::Entry;
 use yaglw::vertex_buffer::{GLArray, GLBuffer, VertexAttribData};
 use yaglw::vertex_buffer::{DrawMode, GLType};
@@ -9,6 +10,7 @@ use common::fnv_map;
 
 use vertex::ColoredVertex;
 use view;
+use view::cull;
 
 /// Number of vertices in a mob mesh.
 pub const VERTICES_PER_MOB: usize = 36;
@@ -17,6 +19,8 @@ pub const VERTICES_PER_MOB: usize = 36;
 pub struct T<'a> {
   id_to_index: fnv_map::T<view::entity::id::Mob, usize>,
   index_to_id: Vec<view::entity::id::Mob>,
+  // The center of each mob, for culling.
+  centers: Vec<Point3<f32>>,
 
   triangles: GLArray<'a, ColoredVertex>,
 }
@@ -32,6 +36,7 @@ pub fn new<'a, 'b>(
   T {
     id_to_index: fnv_map::new(),
     index_to_id: Vec::new(),
+    centers: Vec::new(),
 
     triangles: GLArray::new(
       gl,
@@ -59,6 +64,7 @@ impl<'a> T<'a> {
       Entry::Vacant(entry) => {
         entry.insert(self.index_to_id.len());
         self.index_to_id.push(id);
+        self.centers.push(cull::center(triangles));
 
         self.triangles.buffer.byte_buffer.bind(gl);
         self.triangles.push(gl, triangles);
@@ -66,6 +72,7 @@ impl<'a> T<'a> {
       },
       Entry::Occupied(entry) => {
         let idx = *entry.get();
+        self.centers[idx] = cull::center(triangles);
         self.triangles.buffer.byte_buffer.bind(gl);
         self.triangles.buffer.update(gl, idx * VERTICES_PER_MOB, triangles);
         false
@@ -73,10 +80,28 @@ impl<'a> T<'a> {
     }
   }
 
-  /// Draw all the mobs.
+  /// Remove a mob from VRAM, if it's there.
+  pub fn remove(&mut self, gl: &mut GLContext, id: view::entity::id::Mob) {
+    let idx =
+      match self.id_to_index.remove(&id) {
+        None => return,
+        Some(idx) => idx,
+      };
+    let swapped_id = self.index_to_id[self.index_to_id.len() - 1];
+    self.index_to_id.swap_remove(idx);
+    self.centers.swap_remove(idx);
+    if id != swapped_id {
+      self.id_to_index.insert(swapped_id, idx);
+    }
+
+    self.triangles.buffer.byte_buffer.bind(gl);
+    self.triangles.buffer.swap_remove(gl, idx * VERTICES_PER_MOB, VERTICES_PER_MOB);
+  }
+
+  /// Draw the mobs within draw distance of `eye`.
   /// N.B. This does not bind any shaders.
-  pub fn draw(&self, gl: &mut GLContext) {
+  pub fn draw(&self, gl: &mut GLContext, eye: &Point3<f32>) {
     self.triangles.bind(gl);
-    self.triangles.draw(gl);
+    cull::draw_visible(&self.centers, VERTICES_PER_MOB, eye, &cull::MOBS);
   }
 }
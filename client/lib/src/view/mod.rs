@@ -1,17 +1,31 @@
 //! The state associated with perceiving the world state.
 
+pub mod beacons;
 mod camera;
+mod camera_motion;
 pub mod chunked_terrain;
+mod cull;
+pub mod debug_overlay;
+mod footprints;
+mod frame_arena;
 mod grass_buffers;
+mod minimap;
+#[cfg(test)]
+mod headless;
 pub mod entity;
 pub mod light;
 mod mob_buffers;
 mod player_buffers;
 mod render;
+mod screenshot;
 pub mod shaders;
 pub mod terrain_buffers;
 pub mod thread;
 pub mod update;
+pub mod update_queue;
+mod upload_ring;
+pub mod vram_budget;
+mod weather;
 
 pub use self::render::render;
 
@@ -27,13 +41,9 @@ use yaglw::vertex_buffer::{GLArray, GLBuffer, GLType, DrawMode, VertexAttribData
 use yaglw::texture::{TextureUnit};
 
 use common::id_allocator;
+use hud;
 use vertex::{ColoredVertex};
 
-/// FOV in radians
-pub const FOV: f32 = std::f32::consts::FRAC_PI_3;
-
-const VERTICES_PER_TRIANGLE: usize = 3;
-
 #[allow(missing_docs)]
 pub enum InputMode {
   Camera,
@@ -55,23 +65,49 @@ pub struct T<'a> {
   pub terrain_buffers: terrain_buffers::T<'a>,
   /// The OpenGL buffers for grass render data
   pub grass_buffers: grass_buffers::T<'a>,
+  /// What's in the terrain and grass buffers, and what to evict when they're full.
+  pub vram_budget: vram_budget::T,
   /// The OpenGL texture to sample for grass
   pub grass_texture: yaglw::texture::Texture2D<'a>,
   /// OpenGL buffers for mob render data
   pub mob_buffers: mob_buffers::T<'a>,
   /// OpenGL buffers for player render data
   pub player_buffers: player_buffers::T<'a>,
-  /// Hud triangles for non-text.
+  /// Beacon shafts, and their HUD labels.
+  pub beacons: beacons::T<'a>,
+  /// Footprints the player's left behind them.
+  pub footprints: footprints::T<'a>,
+  /// Rain or snow falling around the camera.
+  pub weather: weather::T<'a>,
+  /// A map of the terrain around the player, in the HUD.
+  pub minimap: minimap::T<'a>,
+  /// Debugging aids, which are all off to start with.
+  pub debug: debug_overlay::T<'a>,
+  /// The HUD's triangles, laid out afresh each frame.
   pub hud_triangles: GLArray<'a, ColoredVertex>,
+  /// What the HUD's showing.
+  pub hud: hud::T,
+  /// Scratch space for transient per-frame allocations.
+  pub frame_arena: frame_arena::T,
+  /// Staging memory for streaming buffer uploads.
+  pub upload_ring: upload_ring::T,
 
   #[allow(missing_docs)]
   pub sun: light::Sun,
+  /// The sun's motion, according to the server.
+  pub sun_clock: Option<light::SunClock>,
   #[allow(missing_docs)]
   pub camera: camera::T,
-  #[allow(missing_docs)]
+  /// How the camera follows the player, and how wide it sees.
+  pub camera_motion: camera_motion::T,
+  /// How big the window is, in pixels.
   pub window_size: cgmath::Vector2<i32>,
   /// Whether to render HUD elements
   pub show_hud: bool,
+  /// The fraction of grass tufts to draw, in [0, 1].
+  pub grass_density: f32,
+  /// Whether to save a screenshot of the next frame that's drawn.
+  pub capture_requested: bool,
 
   /// Whether to render HUD elements
   pub input_mode: InputMode,
@@ -114,46 +150,104 @@ fn load_grass_texture<'a, 'b:'a>(
   Ok(grass_texture)
 }
 
-#[allow(missing_docs)]
-pub fn new<'a>(
-  mut gl: GLContext,
-  window_size: cgmath::Vector2<i32>,
-) -> T<'a> {
+/// Point the shaders' non-per-frame uniforms at the buffers and texture units they read from.
+/// This needs to be redone whenever the shaders are recompiled. Returns the texture unit for misc use.
+fn bind_shader_inputs<'a>(
+  gl: &mut GLContext,
+  shaders: &mut shaders::T<'a>,
+  terrain_buffers: &terrain_buffers::T<'a>,
+) -> TextureUnit {
+  // A fresh allocator hands out the same units every time.
   let mut texture_unit_alloc = id_allocator::new();
 
-  let mut shaders = shaders::new(&mut gl, window_size);
-
-  let terrain_buffers = terrain_buffers::new(&mut gl);
   terrain_buffers.bind_vertex_positions(
-    &mut gl,
+    gl,
     &mut texture_unit_alloc,
     &mut shaders.terrain_shader.shader,
   );
   terrain_buffers.bind_normals(
-    &mut gl,
+    gl,
     &mut texture_unit_alloc,
     &mut shaders.terrain_shader.shader,
   );
   terrain_buffers.bind_materials(
-    &mut gl,
+    gl,
+    &mut texture_unit_alloc,
+    &mut shaders.terrain_shader.shader,
+  );
+  terrain_buffers.bind_sky_visibility(
+    gl,
+    &mut texture_unit_alloc,
+    &mut shaders.terrain_shader.shader,
+  );
+  terrain_buffers.bind_occlusion(
+    gl,
     &mut texture_unit_alloc,
     &mut shaders.terrain_shader.shader,
   );
   terrain_buffers.bind_vertex_positions(
-    &mut gl,
+    gl,
     &mut texture_unit_alloc,
     &mut shaders.grass_billboard.shader,
   );
   terrain_buffers.bind_normals(
-    &mut gl,
+    gl,
     &mut texture_unit_alloc,
     &mut shaders.grass_billboard.shader,
   );
 
+  let misc_texture_unit = texture_unit_alloc.allocate();
+
+  unsafe {
+    gl::ActiveTexture(misc_texture_unit.gl_id());
+  }
+
+  let texture_in =
+    shaders.texture_shader.shader.get_uniform_location("texture_in");
+  shaders.texture_shader.shader.use_shader(gl);
+  unsafe {
+    gl::Uniform1i(texture_in, misc_texture_unit.glsl_id as GLint);
+  }
+
+  let texture_in =
+    shaders.grass_billboard.shader.get_uniform_location("texture_in");
+  shaders.grass_billboard.shader.use_shader(gl);
+  unsafe {
+    gl::Uniform1i(texture_in, misc_texture_unit.glsl_id as GLint);
+  }
+
+  misc_texture_unit
+}
+
+/// `fov` is the vertical field of view, in radians. Footprints fade out over `footprint_fade_ns`. Snow only settles on
+/// grass if `snow_cover` is set. Beacon labels can be seen up to `nameplate_distance` away. The camera takes about
+/// `camera_smoothing` seconds to catch up with the player, and the field of view widens by `sprint_fov_kick` radians
+/// while sprinting. Terrain and its grass get `vram_budget_bytes` of VRAM between them.
+pub fn new<'a>(
+  mut gl: GLContext,
+  window_size: cgmath::Vector2<i32>,
+  fov: f32,
+  camera_smoothing: f32,
+  sprint_fov_kick: f32,
+  footprint_fade_ns: u64,
+  snow_cover: bool,
+  nameplate_distance: f32,
+  vram_budget_bytes: usize,
+) -> T<'a> {
+  let mut shaders = shaders::new(&mut gl, window_size);
+
+  let budget = vram_budget::split(vram_budget_bytes);
+  let terrain_buffers = terrain_buffers::new(&mut gl, budget.chunks);
+
   let mob_buffers = mob_buffers::new(&mut gl, &shaders.mob_shader);
   let player_buffers = player_buffers::new(&mut gl, &shaders.mob_shader);
+  let beacons = beacons::new(&mut gl, &shaders.mob_shader, &shaders.hud_color_shader, nameplate_distance);
+  let footprints = footprints::new(&mut gl, &shaders.mob_shader, footprint_fade_ns);
+  let weather = weather::new(&mut gl, &shaders.mob_shader, snow_cover);
+  let minimap = minimap::new(&mut gl, &shaders.texture_shader, &shaders.hud_color_shader);
+  let debug = debug_overlay::new(&mut gl, &shaders.mob_shader, &shaders.hud_color_shader);
 
-  let buffer = GLBuffer::new(&mut gl, 16 * VERTICES_PER_TRIANGLE);
+  let buffer = GLBuffer::new(&mut gl, hud::MAX_VERTICES);
   let hud_triangles = {
     GLArray::new(
       &mut gl,
@@ -167,7 +261,7 @@ pub fn new<'a>(
     )
   };
 
-  let misc_texture_unit = texture_unit_alloc.allocate();
+  let misc_texture_unit = bind_shader_inputs(&mut gl, &mut shaders, &terrain_buffers);
 
   unsafe {
     gl::FrontFace(gl::CCW);
@@ -185,25 +279,7 @@ pub fn new<'a>(
     gl::ClearDepth(1.0);
   }
 
-  unsafe {
-    gl::ActiveTexture(misc_texture_unit.gl_id());
-  }
-
-  let texture_in =
-    shaders.texture_shader.shader.get_uniform_location("texture_in");
-  shaders.texture_shader.shader.use_shader(&mut gl);
-  unsafe {
-    gl::Uniform1i(texture_in, misc_texture_unit.glsl_id as GLint);
-  }
-
-  let texture_in =
-    shaders.grass_billboard.shader.get_uniform_location("texture_in");
-  shaders.grass_billboard.shader.use_shader(&mut gl);
-  unsafe {
-    gl::Uniform1i(texture_in, misc_texture_unit.glsl_id as GLint);
-  }
-
-  let grass_buffers = grass_buffers::new(&mut gl, &shaders.grass_billboard.shader);
+  let grass_buffers = grass_buffers::new(&mut gl, &shaders.grass_billboard.shader, budget.tufts);
   let grass_texture = load_grass_texture(&mut gl).unwrap();
 
   let empty_gl_array = yaglw::vertex_buffer::ArrayHandle::new(&gl);
@@ -217,10 +293,19 @@ pub fn new<'a>(
 
     terrain_buffers: terrain_buffers,
     grass_buffers: grass_buffers,
+    vram_budget: vram_budget::new(budget),
     grass_texture: grass_texture,
     mob_buffers: mob_buffers,
     player_buffers: player_buffers,
+    beacons: beacons,
+    footprints: footprints,
+    weather: weather,
+    minimap: minimap,
+    debug: debug,
     hud_triangles: hud_triangles,
+    hud: hud::new(),
+    frame_arena: frame_arena::new(),
+    upload_ring: upload_ring::new(upload_ring::DEFAULT_SIZE),
 
     empty_gl_array: empty_gl_array,
     misc_texture_unit: misc_texture_unit,
@@ -228,7 +313,7 @@ pub fn new<'a>(
     window_size: window_size,
 
     camera: {
-      let fovy = cgmath::Rad(FOV);
+      let fovy = cgmath::Rad(fov);
       let aspect = window_size.x as f32 / window_size.y as f32;
       let mut camera = camera::unit();
       // Initialize the projection matrix.
@@ -237,17 +322,78 @@ pub fn new<'a>(
       camera.rotate_lateral(std::f32::consts::PI / 2.0);
       camera
     },
+    camera_motion: camera_motion::new(fov, camera_smoothing, sprint_fov_kick),
 
     sun:
       light::Sun {
         progression: 0.0,
         rotation: 0.0,
       },
+    sun_clock: None,
 
     show_hud: true,
+    grass_density: 1.0,
+    capture_requested: false,
     input_mode: InputMode::Camera,
 
     near_clip: near_clip,
     far_clip: far_clip,
   }
 }
+
+impl<'a> T<'a> {
+  /// Recompile the shaders from source, e.g. after editing them. Shaders that fail to compile keep their old
+  /// programs, and the compile errors get logged.
+  pub fn reload_shaders(&mut self) {
+    self.shaders.reload(&mut self.gl, self.window_size);
+    bind_shader_inputs(&mut self.gl, &mut self.shaders, &self.terrain_buffers);
+  }
+
+  /// Draw into a window that's changed size, `window_size` pixels big. The perspective camera follows along on its
+  /// next update.
+  pub fn resize(&mut self, window_size: cgmath::Vector2<i32>) {
+    self.window_size = window_size;
+    unsafe {
+      gl::Viewport(0, 0, window_size.x, window_size.y);
+    }
+    self.shaders.set_window_size(&mut self.gl, window_size);
+  }
+
+  /// Change the fraction of grass tufts to draw. Lowering it takes effect immediately; raising it only brings grass
+  /// back as terrain is (re)loaded.
+  pub fn set_grass_density(&mut self, density: f32) {
+    let density = density.max(0.0).min(1.0);
+    if density < self.grass_density {
+      let removed: Vec<entity::id::Grass> =
+        self.grass_buffers.ids().iter()
+        .cloned()
+        .filter(|&id| !grass_buffers::is_drawn_at(id, density))
+        .collect();
+      for id in removed {
+        self.grass_buffers.swap_remove(&mut self.gl, id);
+      }
+    }
+    info!("Grass density: {}", density);
+    self.grass_density = density;
+  }
+
+  /// Move the camera along after the player, and widen or narrow its field of view, as of local time `now_ns`.
+  pub fn update_camera(&mut self, now_ns: u64) {
+    if let Some(position) = self.camera_motion.update(now_ns) {
+      self.camera.translate_to(position);
+    }
+    let fovy = cgmath::Rad(self.camera_motion.current_fov());
+    let aspect = self.window_size.x as f32 / self.window_size.y as f32;
+    self.camera.fov = cgmath::perspective(fovy, aspect, self.near_clip, self.far_clip);
+  }
+
+  /// Move the sun to where it should be at local time `now_ns`, unless it's being controlled manually.
+  pub fn update_sun(&mut self, now_ns: u64) {
+    if let InputMode::Sun = self.input_mode {
+      return
+    }
+    if let Some(clock) = self.sun_clock {
+      self.sun.progression = clock.progression_at(now_ns);
+    }
+  }
+}
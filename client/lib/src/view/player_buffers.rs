@@ -1,5 +1,6 @@
 //! Data structures and functions to load/unload/maintain mob data in VRAM.
 
+use cgmath::Point3;
 use std::collections::hash_map::Entry;
 use yaglw::vertex_buffer::{GLArray, GLBuffer, VertexAttribData};
 use yaglw::vertex_buffer::{DrawMode, GLType};
@@ -10,6 +11,7 @@ use common::fnv_map;
 
 use vertex::ColoredVertex;
 use view;
+use view::cull;
 
 /// Number of vertices in a player mesh.
 pub const VERTICES_PER_PLAYER: usize = 36;
@@ -18,6 +20,8 @@ pub const VERTICES_PER_PLAYER: usize = 36;
 pub struct T<'a> {
   id_to_index: fnv_map::T<entity::id::Player, usize>,
   index_to_id: Vec<entity::id::Player>,
+  // The center of each player, for culling.
+  centers: Vec<Point3<f32>>,
 
   triangles: GLArray<'a, ColoredVertex>,
 }
@@ -33,6 +37,7 @@ pub fn new<'a, 'b>(
   T {
     id_to_index: fnv_map::new(),
     index_to_id: Vec::new(),
+    centers: Vec::new(),
 
     triangles: GLArray::new(
       gl,
@@ -60,6 +65,7 @@ impl<'a> T<'a> {
       Entry::Vacant(entry) => {
         entry.insert(self.index_to_id.len());
         self.index_to_id.push(id);
+        self.centers.push(cull::center(triangles));
 
         self.triangles.buffer.byte_buffer.bind(gl);
         assert!(self.triangles.push(gl, triangles));
@@ -67,6 +73,7 @@ impl<'a> T<'a> {
       },
       Entry::Occupied(entry) => {
         let idx = *entry.get();
+        self.centers[idx] = cull::center(triangles);
         self.triangles.buffer.byte_buffer.bind(gl);
         self.triangles.buffer.update(gl, idx * VERTICES_PER_PLAYER, triangles);
         false
@@ -74,10 +81,28 @@ impl<'a> T<'a> {
     }
   }
 
-  /// Draw all the mobs.
+  /// Remove a player from VRAM, if it's there.
+  pub fn remove(&mut self, gl: &mut GLContext, id: entity::id::Player) {
+    let idx =
+      match self.id_to_index.remove(&id) {
+        None => return,
+        Some(idx) => idx,
+      };
+    let swapped_id = self.index_to_id[self.index_to_id.len() - 1];
+    self.index_to_id.swap_remove(idx);
+    self.centers.swap_remove(idx);
+    if id != swapped_id {
+      self.id_to_index.insert(swapped_id, idx);
+    }
+
+    self.triangles.buffer.byte_buffer.bind(gl);
+    self.triangles.buffer.swap_remove(gl, idx * VERTICES_PER_PLAYER, VERTICES_PER_PLAYER);
+  }
+
+  /// Draw the players within draw distance of `eye`.
   /// N.B. This does not bind any shaders.
-  pub fn draw(&self, gl: &mut GLContext) {
+  pub fn draw(&self, gl: &mut GLContext, eye: &Point3<f32>) {
     self.triangles.bind(gl);
-    self.triangles.draw(gl);
+    cull::draw_visible(&self.centers, VERTICES_PER_PLAYER, eye, &cull::PLAYERS);
   }
 }
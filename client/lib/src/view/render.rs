@@ -7,6 +7,8 @@ use yaglw;
 
 use view;
 use view::camera::{set_camera};
+use view::cull;
+use view::debug_overlay::Overlay;
 use view::light::{set_sun, set_ambient_light};
 
 fn set_eye_position(shader: &mut yaglw::shader::Shader, camera: &view::camera::T) {
@@ -26,6 +28,22 @@ fn set_clip(shader: &mut yaglw::shader::Shader, near: f32, far: f32) {
   }
 }
 
+fn set_fade(shader: &mut yaglw::shader::Shader, range: &cull::Range) {
+  unsafe {
+    let uniform = shader.get_uniform_location("fade_start");
+    gl::Uniform1f(uniform, range.fade_start());
+    let uniform = shader.get_uniform_location("fade_end");
+    gl::Uniform1f(uniform, range.max_distance);
+  }
+}
+
+fn set_snow_cover(shader: &mut yaglw::shader::Shader, snow_cover: f32) {
+  unsafe {
+    let uniform = shader.get_uniform_location("snow_cover");
+    gl::Uniform1f(uniform, snow_cover);
+  }
+}
+
 fn draw_backdrop(
   rndr: &mut view::T,
 ) {
@@ -71,6 +89,7 @@ fn draw_grass_billboards(
   set_clip(&mut rndr.shaders.grass_billboard.shader, rndr.near_clip, rndr.far_clip);
   set_eye_position(&mut rndr.shaders.grass_billboard.shader, &rndr.camera);
   set_sun(&mut rndr.shaders.grass_billboard.shader, &mut rndr.gl, &rndr.sun);
+  set_snow_cover(&mut rndr.shaders.grass_billboard.shader, rndr.weather.snow_cover);
   let alpha_threshold_uniform =
     rndr.shaders.grass_billboard.shader.get_uniform_location("alpha_threshold");
   unsafe {
@@ -82,6 +101,50 @@ fn draw_grass_billboards(
   rndr.grass_buffers.draw(&mut rndr.gl);
 }
 
+/// Rain and snow go after everything solid, since they're see-through.
+fn draw_weather(
+  rndr: &mut view::T,
+) {
+  // The camera and fade are still set from drawing players.
+  rndr.shaders.mob_shader.shader.use_shader(&mut rndr.gl);
+  rndr.weather.draw(&mut rndr.gl);
+}
+
+/// Beacons are drawn over everything else in the world, so they can be seen through terrain.
+fn draw_beacon_shafts(
+  rndr: &mut view::T,
+) {
+  rndr.shaders.mob_shader.shader.use_shader(&mut rndr.gl);
+  set_camera(&mut rndr.shaders.mob_shader.shader, &mut rndr.gl, &rndr.camera);
+  set_clip(&mut rndr.shaders.mob_shader.shader, rndr.near_clip, rndr.far_clip);
+  // Don't fade them out with distance.
+  unsafe {
+    let uniform = rndr.shaders.mob_shader.shader.get_uniform_location("fade_end");
+    gl::Uniform1f(uniform, 0.0);
+    gl::Disable(gl::DEPTH_TEST);
+    gl::Disable(gl::CULL_FACE);
+  }
+  rndr.beacons.draw_shafts(&mut rndr.gl);
+  unsafe {
+    gl::Enable(gl::DEPTH_TEST);
+  }
+}
+
+/// Debug boxes are drawn over everything in the world, like beacon shafts.
+fn draw_debug_boxes(
+  rndr: &mut view::T,
+) {
+  // The camera and clip planes are still set from drawing beacons.
+  rndr.shaders.mob_shader.shader.use_shader(&mut rndr.gl);
+  unsafe {
+    gl::Disable(gl::DEPTH_TEST);
+  }
+  rndr.debug.draw_lines(&mut rndr.gl, rndr.frame_arena.debug_vertices.get());
+  unsafe {
+    gl::Enable(gl::DEPTH_TEST);
+  }
+}
+
 #[allow(missing_docs)]
 pub fn render(
   rndr: &mut view::T,
@@ -101,19 +164,60 @@ pub fn render(
   set_clip(&mut rndr.shaders.terrain_shader.shader, rndr.near_clip, rndr.far_clip);
   set_eye_position(&mut rndr.shaders.terrain_shader.shader, &rndr.camera);
   set_sun(&mut rndr.shaders.terrain_shader.shader, &mut rndr.gl, &rndr.sun);
+  set_snow_cover(&mut rndr.shaders.terrain_shader.shader, rndr.weather.snow_cover);
+  let wireframe = rndr.debug.is_enabled(Overlay::Wireframe);
+  if wireframe {
+    unsafe {
+      gl::PolygonMode(gl::FRONT_AND_BACK, gl::LINE);
+    }
+  }
   rndr.terrain_buffers.draw(&mut rndr.gl);
+  if wireframe {
+    unsafe {
+      gl::PolygonMode(gl::FRONT_AND_BACK, gl::FILL);
+    }
+  }
 
   rndr.shaders.mob_shader.shader.use_shader(&mut rndr.gl);
   set_camera(&mut rndr.shaders.mob_shader.shader, &mut rndr.gl, &rndr.camera);
   set_clip(&mut rndr.shaders.mob_shader.shader, rndr.near_clip, rndr.far_clip);
-  rndr.mob_buffers.draw(&mut rndr.gl);
-  rndr.player_buffers.draw(&mut rndr.gl);
+  set_eye_position(&mut rndr.shaders.mob_shader.shader, &rndr.camera);
+  set_fade(&mut rndr.shaders.mob_shader.shader, &cull::MOBS);
+  rndr.mob_buffers.draw(&mut rndr.gl, &rndr.camera.position);
+  set_fade(&mut rndr.shaders.mob_shader.shader, &cull::PLAYERS);
+  rndr.player_buffers.draw(&mut rndr.gl, &rndr.camera.position);
+  unsafe {
+    gl::Disable(gl::CULL_FACE);
+  }
+  rndr.footprints.draw(&mut rndr.gl);
 
-  draw_grass_billboards(rndr);
+  if !rndr.debug.is_enabled(Overlay::HideGrass) {
+    draw_grass_billboards(rndr);
+  }
+
+  draw_weather(rndr);
+
+  draw_beacon_shafts(rndr);
+
+  draw_debug_boxes(rndr);
 
   if rndr.show_hud {
+    let aspect = rndr.window_size.x as f32 / rndr.window_size.y as f32;
+    unsafe {
+      gl::Disable(gl::DEPTH_TEST);
+    }
+    // Without depth testing, widgets are drawn over whatever they were laid out on top of.
     rndr.shaders.hud_color_shader.shader.use_shader(&mut rndr.gl);
     rndr.hud_triangles.bind(&mut rndr.gl);
     rndr.hud_triangles.draw(&mut rndr.gl);
+
+    let forward = rndr.camera.forward();
+    rndr.minimap.draw(&mut rndr.gl, &mut rndr.shaders, &rndr.misc_texture_unit, aspect, &forward);
+    rndr.beacons.draw_labels(&mut rndr.gl, &rndr.camera, aspect, rndr.frame_arena.hud_vertices.get());
+    rndr.debug.draw_graph(&mut rndr.gl, aspect, rndr.frame_arena.debug_vertices.get());
+    rndr.debug.draw_queue_graph(&mut rndr.gl, aspect, rndr.frame_arena.debug_vertices.get());
+    unsafe {
+      gl::Enable(gl::DEPTH_TEST);
+    }
   }
 }
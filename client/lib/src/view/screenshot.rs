@@ -0,0 +1,78 @@
+//! Saving what's on screen to PNGs, named for when they were taken. Captures read back the frame that was just drawn,
+//! so they have to happen after rendering and before the buffers are swapped.
+
+use cgmath::Vector2;
+use gl;
+use image;
+use std;
+use time;
+
+/// Screenshots are saved in this directory, which is created if it isn't there.
+pub const DIR: &'static str = "screenshots";
+
+/// The name of a screenshot taken at `tm`. Names sort in the order they were taken.
+fn file_name(tm: &time::Tm) -> String {
+  format!(
+    "playform-{:04}{:02}{:02}-{:02}{:02}{:02}-{:03}.png",
+    tm.tm_year + 1900, tm.tm_mon + 1, tm.tm_mday, tm.tm_hour, tm.tm_min, tm.tm_sec, tm.tm_nsec / 1_000_000,
+  )
+}
+
+/// OpenGL reads the bottom row first; images start at the top.
+fn flip_rows(pixels: &mut [u8], row_bytes: usize) {
+  let rows = pixels.len() / row_bytes;
+  for y in 0 .. rows / 2 {
+    let (top, bottom) = (y * row_bytes, (rows - 1 - y) * row_bytes);
+    for x in 0 .. row_bytes {
+      pixels.swap(top + x, bottom + x);
+    }
+  }
+}
+
+/// Save the frame that was just drawn to a window of size `window_size`. Returns where it was saved.
+pub fn capture(window_size: Vector2<i32>) -> Result<std::path::PathBuf, String> {
+  let (w, h) = (window_size.x as usize, window_size.y as usize);
+  let mut pixels = vec!(0; w * h * 4);
+  unsafe {
+    gl::ReadBuffer(gl::BACK);
+    gl::PixelStorei(gl::PACK_ALIGNMENT, 1);
+    gl::ReadPixels(
+      0, 0, w as i32, h as i32, gl::RGBA, gl::UNSIGNED_BYTE,
+      pixels.as_mut_ptr() as *mut _,
+    );
+  }
+  flip_rows(&mut pixels, w * 4);
+  // Blending leaves alpha all over the place, but what's on screen is opaque.
+  for i in 0 .. w * h {
+    pixels[4 * i + 3] = 255;
+  }
+
+  let dir = std::path::Path::new(DIR);
+  try!(std::fs::create_dir_all(dir).map_err(|err| format!("Couldn't create {:?}: {:?}", dir, err)));
+  let path = dir.join(file_name(&time::now()));
+  try!(
+    image::save_buffer(&path, &pixels, w as u32, h as u32, image::ColorType::RGBA(8))
+    .map_err(|err| format!("Couldn't write {:?}: {:?}", path, err))
+  );
+  Ok(path)
+}
+
+#[cfg(test)]
+mod test {
+  use time;
+
+  use super::*;
+
+  #[test]
+  fn test_file_name() {
+    let tm = time::at_utc(time::Timespec::new(1_500_000_000, 123_456_789));
+    assert_eq!(file_name(&tm), "playform-20170714-024000-123.png");
+  }
+
+  #[test]
+  fn test_flip_rows() {
+    let mut pixels = vec!(1, 1, 2, 2, 3, 3);
+    flip_rows(&mut pixels, 2);
+    assert_eq!(pixels, vec!(3, 3, 2, 2, 1, 1));
+  }
+}
@@ -9,16 +9,14 @@ pub mod texture;
 use cgmath;
 use cgmath::{Vector2};
 use gl;
+use gl::types::*;
 use std;
 use yaglw::gl_context::GLContext;
 use yaglw;
 
 use view::camera;
 
-/// Load a shader from a filename prefix.
-/// e.g. shader_from_prefix("foo") will load a vertex shader from shaders/foo.vs.glsl and fragment shader
-/// from shaders/foo.fs.glsl.
-pub fn shader_from_prefix<'a, 'b:'a>(gl: &'a GLContext, prefix: &'static str) -> yaglw::shader::Shader<'b> {
+fn read_shader_sources(prefix: &str) -> Vec<(GLenum, String)> {
   let read_preprocessed_shader =
     |name| {
       String::from_utf8(
@@ -36,14 +34,110 @@ pub fn shader_from_prefix<'a, 'b:'a>(gl: &'a GLContext, prefix: &'static str) ->
   debug!("{}", vs);
   debug!("loaded {} fragment shader:", prefix);
   debug!("{}", fs);
-  let components =
-    vec!(
-      (gl::VERTEX_SHADER, vs),
-      (gl::FRAGMENT_SHADER, fs),
-    );
+  vec!(
+    (gl::VERTEX_SHADER, vs),
+    (gl::FRAGMENT_SHADER, fs),
+  )
+}
+
+fn info_log(
+  id: GLuint,
+  get_iv: unsafe fn(GLuint, GLenum, *mut GLint),
+  get_log: unsafe fn(GLuint, GLsizei, *mut GLsizei, *mut GLchar),
+) -> String {
+  unsafe {
+    let mut len = 0;
+    get_iv(id, gl::INFO_LOG_LENGTH, &mut len);
+    let mut buf = vec!(0u8; len as usize);
+    get_log(id, len, std::ptr::null_mut(), buf.as_mut_ptr() as *mut GLchar);
+    String::from_utf8_lossy(&buf).trim_right_matches('\0').to_owned()
+  }
+}
+
+/// Compile and link a throwaway program from some shader sources, returning the GLSL diagnostics if that fails.
+fn check_program(components: &[(GLenum, String)]) -> Result<(), String> {
+  unsafe {
+    let program = gl::CreateProgram();
+    let mut shaders = Vec::new();
+    let mut r = Ok(());
+
+    for &(kind, ref source) in components {
+      let shader = gl::CreateShader(kind);
+      shaders.push(shader);
+      let source = std::ffi::CString::new(source.as_bytes()).unwrap();
+      gl::ShaderSource(shader, 1, &source.as_ptr(), std::ptr::null());
+      gl::CompileShader(shader);
+
+      let mut status = gl::FALSE as GLint;
+      gl::GetShaderiv(shader, gl::COMPILE_STATUS, &mut status);
+      if status != gl::TRUE as GLint {
+        r = Err(info_log(shader, gl::GetShaderiv, gl::GetShaderInfoLog));
+        break
+      }
+      gl::AttachShader(program, shader);
+    }
+
+    if r.is_ok() {
+      gl::LinkProgram(program);
+      let mut status = gl::FALSE as GLint;
+      gl::GetProgramiv(program, gl::LINK_STATUS, &mut status);
+      if status != gl::TRUE as GLint {
+        r = Err(info_log(program, gl::GetProgramiv, gl::GetProgramInfoLog));
+      }
+    }
+
+    for shader in shaders {
+      gl::DeleteShader(shader);
+    }
+    gl::DeleteProgram(program);
+
+    r
+  }
+}
+
+/// Load a shader from a filename prefix.
+/// e.g. shader_from_prefix("foo") will load a vertex shader from shaders/foo.vs.glsl and fragment shader
+/// from shaders/foo.fs.glsl.
+pub fn shader_from_prefix<'a, 'b:'a>(gl: &'a GLContext, prefix: &'static str) -> yaglw::shader::Shader<'b> {
+  let components = read_shader_sources(prefix);
   yaglw::shader::Shader::new(gl, components.into_iter())
 }
 
+/// Like `shader_from_prefix`, but returns the GLSL diagnostics instead of panicking if the shader is broken.
+pub fn try_shader_from_prefix<'a, 'b:'a>(
+  gl: &'a GLContext,
+  prefix: &'static str,
+) -> Result<yaglw::shader::Shader<'b>, String> {
+  let components = read_shader_sources(prefix);
+  try!(check_program(&components));
+  Ok(yaglw::shader::Shader::new(gl, components.into_iter()))
+}
+
+// Recompile a shader in place. On failure, the old program is kept.
+fn reload_shader<'a>(gl: &GLContext, shader: &mut yaglw::shader::Shader<'a>, prefix: &'static str) {
+  match try_shader_from_prefix(gl, prefix) {
+    Ok(new_shader) => {
+      info!("Reloaded {} shader", prefix);
+      *shader = new_shader;
+    },
+    Err(log) => {
+      warn!("Error reloading {} shader; keeping the old one:\n{}", prefix, log);
+    },
+  }
+}
+
+fn set_hud_camera(shader: &mut yaglw::shader::Shader, gl: &mut GLContext, window_size: Vector2<i32>) {
+  let hud_camera = {
+    let mut c = camera::unit();
+    let dx = window_size.x as f32 / window_size.y as f32;
+    let dy = 1.0;
+    c.fov = cgmath::ortho(-dx, dx, -dy, dy, -1.0, 1.0);
+    c
+  };
+
+  camera::set_camera(shader, gl, &hud_camera);
+}
+
 /// The game's custom shader structs.
 pub struct T<'a> {
   #[allow(missing_docs)]
@@ -65,23 +159,13 @@ pub fn new<'a, 'b>(gl: &'b mut GLContext, window_size: Vector2<i32>) -> T<'a> wh
   let terrain_shader       = self::terrain::new(gl);
   let mob_shader           = self::color::new(gl);
   let mut hud_color_shader = self::color::new(gl);
-  let texture_shader       = self::texture::new(gl);
+  let mut texture_shader   = self::texture::new(gl);
   let grass_billboard      = self::grass_billboard::new(gl);
   let sky                  = self::sky::new(gl);
 
-  let hud_camera = {
-    let mut c = camera::unit();
-    let dx = window_size.x as f32 / window_size.y as f32;
-    let dy = 1.0;
-    c.fov = cgmath::ortho(-dx, dx, -dy, dy, -1.0, 1.0);
-    c
-  };
-
-  camera::set_camera(
-    &mut hud_color_shader.shader,
-    gl,
-    &hud_camera,
-  );
+  set_hud_camera(&mut hud_color_shader.shader, gl, window_size);
+  // The texture shader only draws HUD elements.
+  set_hud_camera(&mut texture_shader.shader, gl, window_size);
 
   match gl.get_error() {
     gl::NO_ERROR => {},
@@ -97,3 +181,26 @@ pub fn new<'a, 'b>(gl: &'b mut GLContext, window_size: Vector2<i32>) -> T<'a> wh
     sky: sky,
   }
 }
+
+impl<'a> T<'a> {
+  /// Recompile every shader from source, keeping the old program for any that fail to compile.
+  /// Uniforms that aren't set every frame need to be rebound afterwards.
+  /// The vertex shaders pin their attributes' locations with `layout(location = ...)`, so existing vertex arrays keep
+  /// pointing at the right attributes as long as those qualifiers are left alone.
+  pub fn reload(&mut self, gl: &mut GLContext, window_size: Vector2<i32>) {
+    reload_shader(gl, &mut self.mob_shader.shader, "color");
+    reload_shader(gl, &mut self.terrain_shader.shader, "terrain");
+    reload_shader(gl, &mut self.texture_shader.shader, "texture");
+    reload_shader(gl, &mut self.grass_billboard.shader, "grass_billboard");
+    reload_shader(gl, &mut self.hud_color_shader.shader, "color");
+    reload_shader(gl, &mut self.sky.shader, "sky");
+
+    self.set_window_size(gl, window_size);
+  }
+
+  /// Stretch the HUD across a window that's changed size, `window_size` pixels big.
+  pub fn set_window_size(&mut self, gl: &mut GLContext, window_size: Vector2<i32>) {
+    set_hud_camera(&mut self.hud_color_shader.shader, gl, window_size);
+    set_hud_camera(&mut self.texture_shader.shader, gl, window_size);
+  }
+}
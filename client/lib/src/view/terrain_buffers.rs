@@ -16,22 +16,28 @@ use common::id_allocator;
 use terrain_mesh::Triangle;
 
 use super::entity;
+use super::upload_ring;
 
 #[cfg(test)]
 use std::mem;
 
 const VERTICES_PER_TRIANGLE: usize = 3;
 
-/// Maximum number of bytes to be used in VRAM
-pub const BYTE_BUDGET: usize = 64_000_000;
 const POLYGON_COST: usize = 100;
-/// Maximum number of polygons to be used in VRAM
-pub const POLYGON_BUDGET: usize = BYTE_BUDGET / POLYGON_COST;
 
 /// Number of elements in a chunk in vram.
 pub const CHUNK_LENGTH: usize = 1 << 5;
-/// The number of polygons loaded contiguously into VRAM.
-const CHUNK_BUDGET: usize = POLYGON_BUDGET / CHUNK_LENGTH;
+
+/// How many polygons fit in some number of bytes of VRAM.
+pub fn polygon_budget(bytes: usize) -> usize {
+  bytes / POLYGON_COST
+}
+
+/// How many chunks fit in some number of bytes of VRAM.
+pub fn chunk_budget(bytes: usize) -> usize {
+  polygon_budget(bytes) / CHUNK_LENGTH
+}
+
 /// Instead of storing individual vertices, normals, etc. in VRAM, store them in chunks.
 /// This makes it much faster to unload things.
 pub struct Chunk<V>(pub [V; CHUNK_LENGTH]);
@@ -43,20 +49,83 @@ impl<V> Chunk<V> {
   }
 }
 
-/// Struct for loading/unloading/maintaining terrain data in VRAM.
-pub struct T<'a> {
+/// Where each chunk is in the VRAM buffers. This is kept apart from the GL buffers, so it can be tested without a GPU.
+pub struct Index {
   id_to_index: fnv_map::T<entity::id::Terrain, usize>,
   index_to_id: Vec<entity::id::Terrain>,
+}
+
+impl Index {
+  #[allow(missing_docs)]
+  pub fn new() -> Index {
+    Index {
+      id_to_index: fnv_map::new(),
+      index_to_id: Vec::new(),
+    }
+  }
+
+  #[allow(missing_docs)]
+  pub fn len(&self) -> usize {
+    self.index_to_id.len()
+  }
+
+  /// Lookup the OpenGL index for an entity.
+  pub fn lookup_opengl_index(
+    &self,
+    id: entity::id::Terrain,
+  ) -> Option<ChunkIndex> {
+    self.id_to_index.get(&id).map(|&x| x as u32).map(|i| index::of_u32(i))
+  }
+
+  /// Record a chunk appended to the VRAM buffers.
+  pub fn push(&mut self, chunk_id: entity::id::Terrain) {
+    debug!("Insert {:?}", chunk_id);
+    let previous = self.id_to_index.insert(chunk_id, self.index_to_id.len());
+    assert!(previous.is_none());
+    self.index_to_id.push(chunk_id);
+    assert_eq!(self.id_to_index.len(), self.index_to_id.len());
+  }
+
+  /// Forget a chunk. The VRAM buffers should swap-remove the first index returned to match.
+  /// Also returns the index the chunk was at and the index of the chunk swapped into its place, if any.
+  pub fn swap_remove(
+    &mut self,
+    id: entity::id::Terrain,
+  ) -> (usize, Option<(ChunkIndex, ChunkIndex)>)
+  {
+    let idx = self.id_to_index[&id];
+    let swapped_idx = self.index_to_id.len() - 1;
+    let swapped_id = self.index_to_id[swapped_idx];
+    self.index_to_id.swap_remove(idx);
+    self.id_to_index.remove(&id);
+
+    debug!("Swap-remove {:?} {:?} with {:?} {:?}", id, idx, swapped_id, swapped_idx);
+
+    let r =
+      if id == swapped_id {
+        None
+      } else {
+        self.id_to_index.insert(swapped_id, idx);
+        Some((index::of_u32(idx as u32), index::of_u32(swapped_idx as u32)))
+      };
+    (idx, r)
+  }
+}
+
+/// Struct for loading/unloading/maintaining terrain data in VRAM.
+pub struct T<'a> {
+  index: Index,
 
   // TODO: Use yaglw's ArrayHandle.
   empty_array: GLuint,
-  length: u32,
 
   // Per-triangle buffers
 
   vertex_positions: BufferTexture<'a, Chunk<Triangle<Point3<GLfloat>>>>,
   normals: BufferTexture<'a, Chunk<Triangle<Vector3<GLfloat>>>>,
   materials: BufferTexture<'a, Chunk<GLint>>,
+  sky_visibility: BufferTexture<'a, Chunk<Triangle<GLfloat>>>,
+  occlusion: BufferTexture<'a, Chunk<Triangle<GLfloat>>>,
 }
 
 /// Phantom type for this buffer.
@@ -80,34 +149,67 @@ fn correct_size() {
   assert!(mem::size_of::<Vector3<GLfloat>>() == 3 * mem::size_of::<GLfloat>());
 }
 
-#[allow(missing_docs)]
+#[test]
+fn swap_remove_remaps() {
+  use common::id_allocator;
+
+  let mut allocator = id_allocator::new();
+  let ids: Vec<entity::id::Terrain> = (0 .. 3).map(|_| allocator.allocate()).collect();
+  let mut chunks = Index::new();
+  for &id in &ids {
+    chunks.push(id);
+  }
+  let moved = |r: Option<(ChunkIndex, ChunkIndex)>| r.map(|(idx, swapped_idx)| (idx.to_u32(), swapped_idx.to_u32()));
+
+  // The last chunk moves into the removed chunk's slot.
+  let (idx, r) = chunks.swap_remove(ids[0]);
+  assert_eq!((idx, moved(r)), (0, Some((0, 2))));
+  assert_eq!(chunks.lookup_opengl_index(ids[2]).map(|idx| idx.to_u32()), Some(0));
+
+  // Removing the last chunk doesn't move anything.
+  let (idx, r) = chunks.swap_remove(ids[1]);
+  assert_eq!((idx, moved(r)), (1, None));
+
+  // Neither does removing the only chunk.
+  let (idx, r) = chunks.swap_remove(ids[2]);
+  assert_eq!((idx, moved(r)), (0, None));
+  assert_eq!(chunks.len(), 0);
+}
+
+/// Buffers with room for `chunk_budget` chunks.
 pub fn new<'a, 'b>(
   gl: &'b mut GLContext,
+  chunk_budget: usize,
 ) -> T<'a> where
   'a: 'b,
 {
   T {
-    id_to_index: fnv_map::new(),
-    index_to_id: Vec::new(),
+    index: Index::new(),
     empty_array: unsafe {
       let mut empty_array = 0;
       gl::GenVertexArrays(1, &mut empty_array);
       empty_array
     },
-    length: 0,
-    vertex_positions: BufferTexture::new(gl, gl::R32F, CHUNK_BUDGET),
-    normals: BufferTexture::new(gl, gl::R32F, CHUNK_BUDGET),
-    materials: BufferTexture::new(gl, gl::R32UI, CHUNK_BUDGET),
+    vertex_positions: BufferTexture::new(gl, gl::R32F, chunk_budget),
+    normals: BufferTexture::new(gl, gl::R32F, chunk_budget),
+    materials: BufferTexture::new(gl, gl::R32UI, chunk_budget),
+    sky_visibility: BufferTexture::new(gl, gl::R32F, chunk_budget),
+    occlusion: BufferTexture::new(gl, gl::R32F, chunk_budget),
   }
 }
 
 impl<'a> T<'a> {
+  /// How many chunks are loaded.
+  pub fn len(&self) -> usize {
+    self.index.len()
+  }
+
   /// Lookup the OpenGL index for an entity.
   pub fn lookup_opengl_index(
     &self,
     id: entity::id::Terrain,
   ) -> Option<ChunkIndex> {
-    self.id_to_index.get(&id).map(|&x| x as u32).map(|i| index::of_u32(i))
+    self.index.lookup_opengl_index(id)
   }
 
   fn bind(
@@ -161,39 +263,93 @@ impl<'a> T<'a> {
     self.bind(texture_unit_alloc, shader, "materials", self.materials.handle.gl_id);
   }
 
+  #[allow(missing_docs)]
+  pub fn bind_sky_visibility(
+    &self,
+    gl: &mut GLContext,
+    texture_unit_alloc: &mut id_allocator::T<TextureUnit>,
+    shader: &mut yaglw::shader::Shader,
+  ) {
+    shader.use_shader(gl);
+    self.bind(texture_unit_alloc, shader, "sky_visibility", self.sky_visibility.handle.gl_id);
+  }
+
+  #[allow(missing_docs)]
+  pub fn bind_occlusion(
+    &self,
+    gl: &mut GLContext,
+    texture_unit_alloc: &mut id_allocator::T<TextureUnit>,
+    shader: &mut yaglw::shader::Shader,
+  ) {
+    shader.use_shader(gl);
+    self.bind(texture_unit_alloc, shader, "occlusion", self.occlusion.handle.gl_id);
+  }
+
   /// Add a series of entites into VRAM.
   pub fn push(
     &mut self,
-    gl        : &mut GLContext,
+    uploads   : &mut upload_ring::T,
     chunk_id  : entity::id::Terrain,
     vertices  : &Chunk<Triangle<Point3<GLfloat>>>,
     normals   : &Chunk<Triangle<Vector3<GLfloat>>>,
     materials : &Chunk<GLint>,
+    sky       : &Chunk<Triangle<GLfloat>>,
+    occlusion : &Chunk<Triangle<GLfloat>>,
   ) {
-    debug!("Insert {:?}", chunk_id);
-
     let vertices  = unsafe { std::slice::from_raw_parts(vertices.as_ptr()  as *const _, 1) };
     let normals   = unsafe { std::slice::from_raw_parts(normals.as_ptr()   as *const _, 1) };
     let materials = unsafe { std::slice::from_raw_parts(materials.as_ptr() as *const _, 1) };
+    let sky       = unsafe { std::slice::from_raw_parts(sky.as_ptr()       as *const _, 1) };
+    let occlusion = unsafe { std::slice::from_raw_parts(occlusion.as_ptr() as *const _, 1) };
 
-    self.vertex_positions.buffer.byte_buffer.bind(gl);
-    let success = self.vertex_positions.buffer.push(gl, vertices);
+    let success = uploads.push(&mut self.vertex_positions.buffer, vertices);
     assert!(success);
 
-    self.normals.buffer.byte_buffer.bind(gl);
-    let success = self.normals.buffer.push(gl, normals);
+    let success = uploads.push(&mut self.normals.buffer, normals);
     assert!(success);
 
-    let previous = self.id_to_index.insert(chunk_id, self.index_to_id.len());
-    assert!(previous.is_none());
-    self.index_to_id.push(chunk_id);
-    assert_eq!(self.id_to_index.len(), self.index_to_id.len());
+    self.index.push(chunk_id);
 
-    self.materials.buffer.byte_buffer.bind(gl);
-    let success = self.materials.buffer.push(gl, materials);
+    let success = uploads.push(&mut self.materials.buffer, materials);
+    assert!(success);
+
+    let success = uploads.push(&mut self.sky_visibility.buffer, sky);
+    assert!(success);
+
+    let success = uploads.push(&mut self.occlusion.buffer, occlusion);
     assert!(success);
+  }
+
+  /// Overwrite the materials, sky visibility and occlusion of a chunk in VRAM, leaving its geometry alone.
+  /// Returns false if the chunk isn't loaded.
+  pub fn reshade(
+    &mut self,
+    gl        : &mut GLContext,
+    chunk_id  : entity::id::Terrain,
+    materials : &Chunk<GLint>,
+    sky       : &Chunk<Triangle<GLfloat>>,
+    occlusion : &Chunk<Triangle<GLfloat>>,
+  ) -> bool {
+    let idx =
+      match self.index.lookup_opengl_index(chunk_id) {
+        None => return false,
+        Some(idx) => idx.to_u32() as usize,
+      };
 
-    self.length += 1;
+    let materials = unsafe { std::slice::from_raw_parts(materials.as_ptr() as *const _, 1) };
+    let sky       = unsafe { std::slice::from_raw_parts(sky.as_ptr()       as *const _, 1) };
+    let occlusion = unsafe { std::slice::from_raw_parts(occlusion.as_ptr() as *const _, 1) };
+
+    self.materials.buffer.byte_buffer.bind(gl);
+    self.materials.buffer.update(gl, idx, materials);
+
+    self.sky_visibility.buffer.byte_buffer.bind(gl);
+    self.sky_visibility.buffer.update(gl, idx, sky);
+
+    self.occlusion.buffer.byte_buffer.bind(gl);
+    self.occlusion.buffer.update(gl, idx, occlusion);
+
+    true
   }
 
   /// Remove some entity from VRAM.
@@ -204,23 +360,7 @@ impl<'a> T<'a> {
     id: entity::id::Terrain,
   ) -> Option<(ChunkIndex, ChunkIndex)>
   {
-    let idx = self.id_to_index[&id];
-    let swapped_idx = self.index_to_id.len() - 1;
-    let swapped_id = self.index_to_id[swapped_idx];
-    self.index_to_id.swap_remove(idx);
-    self.id_to_index.remove(&id);
-
-    debug!("Swap-remove {:?} {:?} with {:?} {:?}", id, idx, swapped_id, swapped_idx);
-
-    let r =
-      if id == swapped_id {
-        None
-      } else {
-        self.id_to_index.insert(swapped_id, idx);
-        Some((index::of_u32(idx as u32), index::of_u32(swapped_idx as u32)))
-      };
-
-    self.length -= 1;
+    let (idx, r) = self.index.swap_remove(id);
 
     self.vertex_positions.buffer.byte_buffer.bind(gl);
     self.vertex_positions.buffer.swap_remove(gl, idx, 1);
@@ -231,6 +371,12 @@ impl<'a> T<'a> {
     self.materials.buffer.byte_buffer.bind(gl);
     self.materials.buffer.swap_remove(gl, idx, 1);
 
+    self.sky_visibility.buffer.byte_buffer.bind(gl);
+    self.sky_visibility.buffer.swap_remove(gl, idx, 1);
+
+    self.occlusion.buffer.byte_buffer.bind(gl);
+    self.occlusion.buffer.swap_remove(gl, idx, 1);
+
     r
   }
 
@@ -238,7 +384,7 @@ impl<'a> T<'a> {
   pub fn draw(&self, _gl: &mut GLContext) {
     unsafe {
       gl::BindVertexArray(self.empty_array);
-      gl::DrawArrays(gl::TRIANGLES, 0, (self.length * CHUNK_LENGTH as u32 * VERTICES_PER_TRIANGLE as u32) as GLint);
+      gl::DrawArrays(gl::TRIANGLES, 0, (self.index.len() as u32 * CHUNK_LENGTH as u32 * VERTICES_PER_TRIANGLE as u32) as GLint);
     }
   }
 }
@@ -1,10 +1,6 @@
 //! This module defines the main function for the view/render/event thread.
 
-use cgmath::{Vector2};
-use gl;
-use sdl2;
 use sdl2::event::{Event, WindowEvent};
-use sdl2::video;
 use std;
 use stopwatch;
 use time;
@@ -14,11 +10,16 @@ use common::interval_timer::IntervalTimer;
 use common::protocol;
 
 use client;
+use config;
+use hud;
 use hud::make_hud;
+use keybinds;
+use platform;
 use process_event::process_event;
 use view;
 
 use super::update;
+use super::update_queue;
 
 #[allow(missing_docs)]
 pub const FRAMES_PER_SECOND: u64 = 30;
@@ -28,66 +29,68 @@ pub const GL_MAJOR_VERSION: u8 = 3;
 #[allow(missing_docs)]
 pub const GL_MINOR_VERSION: u8 = 3;
 
+/// Open the window the view thread draws in, as the settings say. This has to happen on the thread that draws.
+pub fn open_window(config: &config::T) -> platform::T {
+  platform::new(
+    "Playform",
+    config.window_size,
+    config.window_mode,
+    config.vsync,
+    (GL_MAJOR_VERSION, GL_MINOR_VERSION),
+  )
+}
+
 enum ViewIteration {
   Quit,
   Continue,
 }
 
 #[allow(missing_docs)]
-pub fn view_thread<Recv0, Recv1, UpdateServer>(
+/// `platform` is the window to draw in, opened (see `open_window`) on this thread.
+/// If `capture_every_ns` is given, a screenshot is saved that often as well as whenever one's asked for.
+/// `queue_stats` describes the queue `recv` takes updates from, for the debug overlay.
+pub fn view_thread<Recv, QueueStats, UpdateServer>(
+  mut platform: platform::T,
   client: &client::T,
-  recv0: &mut Recv0,
-  recv1: &mut Recv1,
+  config: &config::T,
+  recv: &mut Recv,
+  queue_stats: &mut QueueStats,
   update_server: &mut UpdateServer,
+  capture_every_ns: Option<u64>,
 ) where
-  Recv0: FnMut() -> Option<update::T>,
-  Recv1: FnMut() -> Option<update::T>,
-  UpdateServer: FnMut(protocol::ClientToServer),
+  Recv: FnMut() -> Option<update::T>,
+  QueueStats: FnMut() -> update_queue::Stats,
+  UpdateServer: FnMut(protocol::Request),
 {
-  let sdl = sdl2::init().unwrap();
-  let sdl_event = sdl.event().unwrap();
-  let video = sdl.video().unwrap();
-  let gl_attr = video.gl_attr();
-
-  gl_attr.set_context_profile(video::GLProfile::Core);
-  gl_attr.set_context_version(GL_MAJOR_VERSION, GL_MINOR_VERSION);
-
-  // Open the window as fullscreen at the current resolution.
-  let mut window =
-    video.window(
-      "Playform",
-      800, 600,
-    );
-  let window = window.opengl();
-  let window = window.build().unwrap();
-
-  assert_eq!(gl_attr.context_profile(), video::GLProfile::Core);
-  assert_eq!(gl_attr.context_version(), (GL_MAJOR_VERSION, GL_MINOR_VERSION));
-
-  let mut event_pump = sdl.event_pump().unwrap();
-
-  let _sdl_gl_context = window.gl_create_context().unwrap();
-
-  // Load the OpenGL function pointers.
-  gl::load_with(|s| video.gl_get_proc_address(s) as *const _ );
-
   let gl = unsafe {
     GLContext::new()
   };
 
   gl.print_stats();
 
-  let window_size = {
-    let (w, h) = window.size();
-    Vector2::new(w as i32, h as i32)
-  };
-
-  let mut view = view::new(gl, window_size);
+  let window_size = platform.pixel_size();
+  info!("Drawing {:?} pixels in a window of {:?} points", window_size, platform.point_size());
+
+  let footprint_fade_ns = (f32::max(0.0, config.footprint_fade) * 1e9) as u64;
+  let mut view =
+    view::new(
+      gl,
+      window_size,
+      config.fov.to_radians(),
+      f32::max(0.0, config.camera_smoothing),
+      config.sprint_fov_kick.to_radians(),
+      footprint_fade_ns,
+      config.snow_cover,
+      config.nameplate_distance,
+      config.vram_budget_mb as usize * 1_000_000,
+    );
 
-  sdl.mouse().set_relative_mouse_mode(true);
+  platform.capture_mouse();
 
   make_hud(&mut view);
 
+  let binds = keybinds::load(std::path::Path::new("keybinds.conf"));
+
   let render_interval = {
     let nanoseconds_per_second = 1000000000;
     nanoseconds_per_second / FRAMES_PER_SECOND
@@ -97,6 +100,7 @@ pub fn view_thread<Recv0, Recv1, UpdateServer>(
     let now = time::precise_time_ns();
     render_timer = IntervalTimer::new(render_interval, now);
   }
+  let mut capture_timer = capture_every_ns.map(|every| IntervalTimer::new(every, time::precise_time_ns() + every));
 
   let mut last_update = time::precise_time_ns();
 
@@ -109,10 +113,15 @@ pub fn view_thread<Recv0, Recv1, UpdateServer>(
         }
         last_update = now;
 
-        event_pump.pump_events();
-        let events: Vec<Event> = sdl_event.peek_events(1 << 6);
-        sdl_event.flush_events(0, std::u32::MAX);
-        for event in events {
+        if let Some(shutdown) = *client.server_shutdown.lock().unwrap() {
+          if now >= shutdown {
+            info!("Server has shut down");
+            return ViewIteration::Quit
+          }
+        }
+
+        // SDL turns SIGINT and SIGTERM into quit events, so those shut down the same way closing the window does.
+        for event in platform.poll_events() {
           match event {
             Event::Quit{..} => return ViewIteration::Quit,
             Event::AppTerminating{..} => return ViewIteration::Quit,
@@ -121,23 +130,29 @@ pub fn view_thread<Recv0, Recv1, UpdateServer>(
               process_event(
                 update_server,
                 &mut view,
+                &mut platform,
                 &client,
+                config,
+                &binds,
                 event,
               );
             },
           }
         }
 
-        if window.window_flags() & (::sdl2::sys::video::SDL_WindowFlags::SDL_WINDOW_MOUSE_FOCUS as u32) != 0 {
-          sdl.mouse().warp_mouse_in_window(&window, window_size.x / 2, window_size.y / 2);
+        platform.recenter_mouse();
+
+        // The window can be resized, switched in or out of fullscreen, or moved to a display with a different DPI.
+        let window_size = platform.pixel_size();
+        if window_size != view.window_size {
+          info!("Drawing {:?} pixels in a window of {:?} points", window_size, platform.point_size());
+          view.resize(window_size);
         }
 
         stopwatch::time("apply_updates", || {
           let start = time::precise_time_ns();
           loop {
-            if let Some(update) = recv0() {
-              update::apply_client_to_view(&mut view, update);
-            } else if let Some(update) = recv1() {
+            if let Some(update) = recv() {
               update::apply_client_to_view(&mut view, update);
             } else {
               info!("Out of view updates");
@@ -150,13 +165,50 @@ pub fn view_thread<Recv0, Recv1, UpdateServer>(
           }
         });
 
+        view.debug.queue_sampled(queue_stats());
+
+        view.frame_arena.reset();
+        view.update_camera(time::precise_time_ns());
+        hud::update(&mut view, client, time::precise_time_ns());
+        view.minimap.update(&view.camera.position);
+        view.footprints.update(&mut view.gl, time::precise_time_ns(), view.frame_arena.footprint_vertices.get());
+        let forward = view.camera.forward();
+        view.weather.update(
+          &mut view.gl,
+          &view.camera.position,
+          &forward,
+          time::precise_time_ns(),
+          view.frame_arena.weather_vertices.get(),
+        );
+
+        if let Some(ref mut capture_timer) = capture_timer {
+          if capture_timer.update(time::precise_time_ns()) > 0 {
+            view.capture_requested = true;
+          }
+        }
+
         let renders = render_timer.update(time::precise_time_ns());
         if renders > 0 {
           stopwatch::time("render", || {
+            view.update_sun(time::precise_time_ns());
             view::render::render(&mut view);
+            if view.capture_requested {
+              view.capture_requested = false;
+              match view::screenshot::capture(view.window_size) {
+                Ok(path) => info!("Saved a screenshot to {:?}", path),
+                Err(err) => warn!("Couldn't save a screenshot: {}", err),
+              }
+            }
             // swap buffers
-            window.gl_swap_window();
+            platform.swap();
+            view.debug.frame_drawn(time::precise_time_ns());
           });
+          view.vram_budget.saw(&view.camera.position, &forward, time::precise_time_ns());
+        }
+
+        let evicted = view.vram_budget.take_evicted();
+        if !evicted.is_empty() {
+          client.evicted_meshes.lock().unwrap().extend(evicted);
         }
 
         ViewIteration::Continue
@@ -168,5 +220,10 @@ pub fn view_thread<Recv0, Recv1, UpdateServer>(
     }
   }
 
+  view.frame_arena.log_stats();
+  info!("Upload ring: {:?}", view.upload_ring.stats());
+
+  // Free GL resources while the context is still around.
+  drop(view);
   debug!("view exiting.");
 }
@@ -1,114 +1,317 @@
 //! Define the updates passed from the client to the view.
 
-use cgmath::Point3;
+use cgmath::{Point3, Vector2};
+use collision::Aabb3;
 use stopwatch;
+use time;
+use yaglw::gl_context::GLContext;
 
+use common::protocol;
+
+use hud;
 use terrain_mesh;
 use vertex::ColoredVertex;
 use view;
 
-use common::index;
 
 use super::chunked_terrain;
 use super::entity;
+use super::grass_buffers;
 use super::light;
 use super::mob_buffers::VERTICES_PER_MOB;
 use super::player_buffers::VERTICES_PER_PLAYER;
+use super::terrain_buffers;
+use super::upload_ring;
 
 /// Messages from the client to the view.
 pub enum T {
-  /// Set the camera location.
+  /// The player has moved; the camera follows.
   MoveCamera(Point3<f32>),
 
   /// Update a player mesh.
   UpdatePlayer(entity::id::Player, [ColoredVertex; VERTICES_PER_PLAYER]),
+  /// Remove a player mesh.
+  RemovePlayer(entity::id::Player),
   /// Update a mob mesh.
   UpdateMob(entity::id::Mob, [ColoredVertex; VERTICES_PER_MOB]),
+  /// Remove a mob mesh.
+  RemoveMob(entity::id::Mob),
+  /// Add or move a beacon.
+  UpdateBeacon(entity::id::Beacon, Point3<f32>),
+  /// Remove a beacon.
+  RemoveBeacon(entity::id::Beacon),
+  /// Which beacons terrain hides from the player, as of the last check.
+  SetBeaconsOccluded(Vec<(entity::id::Beacon, bool)>),
 
-  /// Update the sun.
-  SetSun(light::Sun),
+  /// Update the sun's motion.
+  SetSun(light::SunClock),
+  /// Start raining or snowing, or stop.
+  SetWeather(protocol::Weather),
+  /// Show how far through digging the player is, or hide the progress.
+  SetMiningProgress(Option<f32>),
+  /// Show that the server refused an edit.
+  EditDenied,
+  /// Show a message from the server for a few seconds.
+  Notify(String),
+  /// Leave a footprint on the ground at a point, facing along a unit vector in the xz plane.
+  Footprint(Point3<f32>, Vector2<f32>),
+  /// The bounds of what the server's physics has around the player, for the debug overlay.
+  SetPhysicsBounds(Vec<Aabb3<f32>>),
 
   /// Add a terrain chunk to the view.
   LoadMesh (Box<chunked_terrain::T>),
   /// Remove a terrain entity.
   UnloadMesh(terrain_mesh::Ids),
+  /// Replace the shading of a terrain mesh that's already loaded.
+  ReshadeMesh(Box<chunked_terrain::Shading>),
   /// Treat a series of updates as an atomic operation.
   Atomic(Vec<T>),
 }
 
 pub use self::T::*;
 
+/// The buffers terrain meshes are loaded into. The view keeps these in VRAM; tests keep them in RAM, so the
+/// bookkeeping between terrain chunks and the grass on them can be checked without a GPU.
+pub trait MeshBuffers {
+  /// Where a terrain chunk is in the buffers, if it's loaded.
+  fn lookup_chunk(&self, id: entity::id::Terrain) -> Option<terrain_buffers::ChunkIndex>;
+  /// Load one of a mesh's chunks.
+  fn push_chunk(&mut self, mesh: &chunked_terrain::T, i: usize);
+  /// Unload a chunk, and move the grass on the chunk that takes its place along with it.
+  /// Any grass on the unloaded chunk has to have been removed already.
+  fn swap_remove_chunk(&mut self, id: entity::id::Terrain);
+
+  #[allow(missing_docs)]
+  fn contains_grass(&self, id: entity::id::Grass) -> bool;
+  #[allow(missing_docs)]
+  fn push_grass(&mut self, grass: &[grass_buffers::Entry], ids: &[entity::id::Grass]);
+  #[allow(missing_docs)]
+  fn swap_remove_grass(&mut self, id: entity::id::Grass);
+}
+
+/// Load a mesh's chunks, and the grass on them that's drawn at `grass_density`.
+/// `grass_entries` and `grass_ids` are scratch space.
+pub fn load_mesh<Buffers: MeshBuffers>(
+  buffers       : &mut Buffers,
+  mesh          : &chunked_terrain::T,
+  grass_density : f32,
+  grass_entries : &mut Vec<grass_buffers::Entry>,
+  grass_ids     : &mut Vec<entity::id::Grass>,
+) {
+  for i in 0 .. mesh.chunk_count() {
+    buffers.push_chunk(mesh, i);
+  }
+  grass_entries.reserve(mesh.grass.len());
+  grass_ids.reserve(mesh.grass.len());
+  for i in 0 .. mesh.grass.len() {
+    let id = mesh.grass.ids[i];
+    if !grass_buffers::is_drawn_at(id, grass_density) {
+      continue
+    }
+    grass_ids.push(id);
+    let chunk_id = mesh.grass.polygon_chunk_ids[i];
+    let polygon_offset = mesh.grass.polygon_offsets[i];
+    let chunk_idx = buffers.lookup_chunk(chunk_id).unwrap();
+    let polygon_idx = chunk_idx.subindex(polygon_offset);
+    grass_entries.push(
+      grass_buffers::Entry {
+        polygon_idx : polygon_idx.to_u32(),
+        tex_id      : mesh.grass.tex_ids[i],
+      }
+    );
+  }
+  buffers.push_grass(grass_entries, grass_ids);
+}
+
+/// Unload a mesh's chunks and grass.
+pub fn unload_mesh<Buffers: MeshBuffers>(buffers: &mut Buffers, ids: terrain_mesh::Ids) {
+  let terrain_mesh::Ids { chunk_ids, grass_ids } = ids;
+  // Grass has to be removed before the chunks it's on.
+  for id in grass_ids {
+    // Tufts filtered out by the grass density were never added.
+    if buffers.contains_grass(id) {
+      buffers.swap_remove_grass(id);
+    }
+  }
+  for chunk_id in chunk_ids {
+    buffers.swap_remove_chunk(chunk_id);
+  }
+}
+
+/// The view's VRAM buffers.
+struct Vram<'v, 'a: 'v> {
+  gl              : &'v mut GLContext,
+  upload_ring     : &'v mut upload_ring::T,
+  terrain_buffers : &'v mut terrain_buffers::T<'a>,
+  grass_buffers   : &'v mut grass_buffers::T<'a>,
+}
+
+impl<'v, 'a: 'v> MeshBuffers for Vram<'v, 'a> {
+  fn lookup_chunk(&self, id: entity::id::Terrain) -> Option<terrain_buffers::ChunkIndex> {
+    self.terrain_buffers.lookup_opengl_index(id)
+  }
+
+  fn push_chunk(&mut self, mesh: &chunked_terrain::T, i: usize) {
+    self.terrain_buffers.push(
+      self.upload_ring,
+      mesh.ids[i],
+      &mesh.vertex_coordinates[i],
+      &mesh.normals[i],
+      &mesh.materials[i],
+      &mesh.sky_visibility[i],
+      &mesh.occlusion[i],
+    );
+  }
+
+  fn swap_remove_chunk(&mut self, id: entity::id::Terrain) {
+    self.grass_buffers.swap_remove_chunk(self.gl, self.terrain_buffers, id);
+  }
+
+  fn contains_grass(&self, id: entity::id::Grass) -> bool {
+    self.grass_buffers.contains(id)
+  }
+
+  fn push_grass(&mut self, grass: &[grass_buffers::Entry], ids: &[entity::id::Grass]) {
+    self.grass_buffers.push(self.upload_ring, grass, ids);
+  }
+
+  fn swap_remove_grass(&mut self, id: entity::id::Grass) {
+    self.grass_buffers.swap_remove(self.gl, id);
+  }
+}
+
+/// Evict terrain meshes until there's room for `chunks` more chunks and `tufts` more grass tufts.
+fn make_room(view: &mut view::T, chunks: usize, tufts: usize) {
+  while !view.vram_budget.has_room(view.terrain_buffers.len() + chunks, view.grass_buffers.len() + tufts) {
+    let ids =
+      match view.vram_budget.evict(&view.camera.position) {
+        None => break,
+        Some(ids) => ids,
+      };
+    debug!("Evicting mesh {:?}", ids.chunk_ids.first());
+    view.minimap.unload(&ids.chunk_ids);
+    view.debug.mesh_unloaded(&ids.chunk_ids);
+    let mut vram =
+      Vram {
+        gl              : &mut view.gl,
+        upload_ring     : &mut view.upload_ring,
+        terrain_buffers : &mut view.terrain_buffers,
+        grass_buffers   : &mut view.grass_buffers,
+      };
+    unload_mesh(&mut vram, ids);
+  }
+}
+
 #[allow(missing_docs)]
 pub fn apply_client_to_view(view: &mut view::T, up: T) {
   match up {
     T::MoveCamera(position) => {
-      view.camera.translate_to(position);
+      view.camera_motion.move_to(position);
     },
     T::UpdateMob(id, triangles) => {
       view.mob_buffers.insert(&mut view.gl, id, &triangles);
     },
+    T::RemoveMob(id) => {
+      view.mob_buffers.remove(&mut view.gl, id);
+    },
     T::UpdatePlayer(id, triangles) => {
       view.player_buffers.insert(&mut view.gl, id, &triangles);
     },
-    T::SetSun(sun) => {
-      match view.input_mode {
-        view::InputMode::Sun => {},
-        _ => {
-          view.sun = sun;
-        },
+    T::RemovePlayer(id) => {
+      view.player_buffers.remove(&mut view.gl, id);
+    },
+    T::UpdateBeacon(id, position) => {
+      view.beacons.insert(&mut view.gl, id, position);
+    },
+    T::RemoveBeacon(id) => {
+      view.beacons.remove(&mut view.gl, id);
+    },
+    T::SetBeaconsOccluded(occluded) => {
+      for (id, occluded) in occluded {
+        view.beacons.set_occluded(id, occluded);
       }
     },
+    T::SetSun(clock) => {
+      view.sun_clock = Some(clock);
+    },
+    T::SetWeather(weather) => {
+      view.weather.weather = weather;
+    },
+    T::SetMiningProgress(progress) => {
+      hud::set_mining_progress(view, progress);
+    },
+    T::EditDenied => {
+      hud::flash_edit_denied(view, time::precise_time_ns());
+    },
+    T::Notify(text) => {
+      hud::notify(view, text, time::precise_time_ns());
+    },
+    T::Footprint(position, heading) => {
+      view.footprints.push(&mut view.gl, position, heading, time::precise_time_ns());
+    },
+    T::SetPhysicsBounds(bounds) => {
+      view.debug.set_physics_bounds(bounds);
+    },
     T::LoadMesh(mesh) => {
       stopwatch::time("add_chunk", move || {
-        let mesh = *mesh;
-        for i in 0 .. mesh.chunk_count() {
-          view.terrain_buffers.push(
-            &mut view.gl,
-            mesh.ids[i],
-            &mesh.vertex_coordinates[i],
-            &mesh.normals[i],
-            &mesh.materials[i],
-          );
-        }
-        let mut grass_entries = Vec::with_capacity(mesh.grass.len());
-        for i in 0 .. mesh.grass.len() {
-          let chunk_id = mesh.grass.polygon_chunk_ids[i];
-          let polygon_offset = mesh.grass.polygon_offsets[i];
-          let chunk_idx = view.terrain_buffers.lookup_opengl_index(chunk_id).unwrap();
-          let polygon_idx = chunk_idx.subindex(polygon_offset);
-          grass_entries.push(
-            view::grass_buffers::Entry {
-              polygon_idx : polygon_idx.to_u32(),
-              tex_id      : mesh.grass.tex_ids[i],
-            }
-          );
-        }
-        view.grass_buffers.push(
-          &mut view.gl,
-          grass_entries.as_ref(),
-          mesh.grass.ids.as_ref(),
+        make_room(view, mesh.chunk_count(), mesh.grass.ids.len());
+        let ids =
+          terrain_mesh::Ids {
+            chunk_ids : mesh.ids.clone(),
+            grass_ids : mesh.grass.ids.clone(),
+          };
+        view.vram_budget.loaded(ids, mesh.bounds(), time::precise_time_ns());
+        view.minimap.load(&mesh);
+        view.debug.mesh_loaded(&mesh.ids, mesh.bounds());
+        let mut vram =
+          Vram {
+            gl              : &mut view.gl,
+            upload_ring     : &mut view.upload_ring,
+            terrain_buffers : &mut view.terrain_buffers,
+            grass_buffers   : &mut view.grass_buffers,
+          };
+        load_mesh(
+          &mut vram,
+          &mesh,
+          view.grass_density,
+          view.frame_arena.grass_entries.get(),
+          view.frame_arena.grass_ids.get(),
         );
       })
     },
-    T::UnloadMesh(terrain_mesh::Ids { chunk_ids, grass_ids }) => {
-      // Removing grass needs to happen before the calls to [update_polygon_index], or we will remove the wrong things.
-      for id in grass_ids {
-        view.grass_buffers.swap_remove(&mut view.gl, id);
+    T::UnloadMesh(ids) => {
+      if !view.vram_budget.unloaded(&ids) {
+        debug!("Not unloading evicted mesh {:?}", ids.chunk_ids.first());
+        return
       }
-      for chunk_id in chunk_ids {
-        match view.terrain_buffers.swap_remove(&mut view.gl, chunk_id) {
-          None => {},
-          Some((idx, swapped_idx)) => {
-            for i in index::all() {
-              view.grass_buffers.update_polygon_index(
-                &mut view.gl,
-                swapped_idx.subindex(i),
-                idx.subindex(i),
-              );
-            }
+      view.minimap.unload(&ids.chunk_ids);
+      view.debug.mesh_unloaded(&ids.chunk_ids);
+      let mut vram =
+        Vram {
+          gl              : &mut view.gl,
+          upload_ring     : &mut view.upload_ring,
+          terrain_buffers : &mut view.terrain_buffers,
+          grass_buffers   : &mut view.grass_buffers,
+        };
+      unload_mesh(&mut vram, ids);
+    },
+    T::ReshadeMesh(shading) => {
+      stopwatch::time("reshade_chunk", move || {
+        for (i, &id) in shading.ids.iter().enumerate() {
+          // The mesh may have been unloaded since it was shaded.
+          let reshaded =
+            view.terrain_buffers.reshade(
+              &mut view.gl,
+              id,
+              &shading.materials[i],
+              &shading.sky_visibility[i],
+              &shading.occlusion[i],
+            );
+          if !reshaded {
+            debug!("Not reshading unloaded chunk {:?}", id);
           }
         }
-      }
+      })
     },
     T::Atomic(updates) => {
       for up in updates {
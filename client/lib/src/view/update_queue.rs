@@ -0,0 +1,285 @@
+//! A bounded queue of view updates, which coalesces updates that would be redundant by the time the view
+//! gets around to applying them.
+//! Updates come out in two priorities: cheap, latency-sensitive updates (camera, sun, players and mobs, including
+//! their removal) always come out before mesh loads and unloads, no matter how many of those are queued.
+
+use cgmath::Point3;
+use std::collections::VecDeque;
+use std::sync::{Condvar, Mutex};
+
+use common::fnv_map;
+
+use vertex::ColoredVertex;
+
+use super::entity;
+use super::light;
+use super::mob_buffers::VERTICES_PER_MOB;
+use super::player_buffers::VERTICES_PER_PLAYER;
+use super::update;
+
+/// The default maximum number of queued updates before producers have to wait.
+pub const DEFAULT_CAPACITY: usize = 1 << 10;
+
+/// Queue statistics, for debugging output.
+#[derive(Debug, Clone, Copy)]
+pub struct Stats {
+  /// The number of low-priority updates currently queued.
+  pub depth          : usize,
+  /// The largest `depth` has ever been.
+  pub high_watermark : usize,
+  /// The number of updates dropped because a newer update made them redundant.
+  pub coalesced      : u64,
+  /// The number of times a producer had to wait for space in the queue.
+  pub stalls         : u64,
+  /// How many low-priority updates fit before producers have to wait.
+  pub capacity       : usize,
+}
+
+struct Contents {
+  // High priority updates, where only the newest one (per entity) matters. `None` entities are being removed.
+  camera        : Option<Point3<f32>>,
+  sun           : Option<light::SunClock>,
+  players       : fnv_map::T<entity::id::Player, Option<[ColoredVertex; VERTICES_PER_PLAYER]>>,
+  mobs          : fnv_map::T<entity::id::Mob, Option<[ColoredVertex; VERTICES_PER_MOB]>>,
+  // Low priority updates, in order. `None` entries have been cancelled by later updates.
+  queue         : VecDeque<Option<update::T>>,
+  // The sequence number of the front of `queue`.
+  front         : u64,
+  // The sequence number of the queued update that loads each vram chunk.
+  pending_loads : fnv_map::T<entity::id::Terrain, u64>,
+  closed        : bool,
+  stats         : Stats,
+}
+
+/// A bounded queue of view updates. This can be shared between threads.
+pub struct T {
+  capacity : usize,
+  contents : Mutex<Contents>,
+  not_full : Condvar,
+}
+
+#[allow(missing_docs)]
+pub fn new(capacity: usize) -> T {
+  T {
+    capacity : capacity,
+    contents :
+      Mutex::new(Contents {
+        camera        : None,
+        sun           : None,
+        players       : fnv_map::new(),
+        mobs          : fnv_map::new(),
+        queue         : VecDeque::new(),
+        front         : 0,
+        pending_loads : fnv_map::new(),
+        closed        : false,
+        stats         :
+          Stats {
+            depth          : 0,
+            high_watermark : 0,
+            coalesced      : 0,
+            stalls         : 0,
+            capacity       : capacity,
+          },
+      }),
+    not_full : Condvar::new(),
+  }
+}
+
+// Collect the ids of the vram chunks loaded by an update.
+fn loaded_chunks(up: &update::T, ids: &mut Vec<entity::id::Terrain>) {
+  match *up {
+    update::LoadMesh(ref mesh) => {
+      ids.extend(mesh.ids.first().cloned());
+    },
+    update::Atomic(ref updates) => {
+      for up in updates {
+        loaded_chunks(up, ids);
+      }
+    },
+    _ => {},
+  }
+}
+
+// Remove the load of a given vram chunk from an update. Returns whatever's left.
+fn without_load(up: update::T, id: entity::id::Terrain) -> Option<update::T> {
+  match up {
+    update::LoadMesh(ref mesh) if mesh.ids.first() == Some(&id) => None,
+    update::Atomic(updates) => {
+      let updates: Vec<_> = updates.into_iter().filter_map(|up| without_load(up, id)).collect();
+      if updates.is_empty() {
+        None
+      } else {
+        Some(update::Atomic(updates))
+      }
+    },
+    up => Some(up),
+  }
+}
+
+impl Contents {
+  // If `up` unloads a mesh whose load is still queued, drop both. Returns whatever's left of `up`.
+  fn cancel_loads(&mut self, up: update::T) -> Option<update::T> {
+    match up {
+      update::UnloadMesh(ids) => {
+        let seq = ids.chunk_ids.first().and_then(|id| self.pending_loads.remove(id));
+        match seq {
+          None => Some(update::UnloadMesh(ids)),
+          Some(seq) => {
+            let i = (seq - self.front) as usize;
+            let rest = self.queue[i].take().and_then(|up| without_load(up, ids.chunk_ids[0]));
+            if rest.is_none() {
+              self.stats.depth -= 1;
+            }
+            self.queue[i] = rest;
+            self.stats.coalesced += 2;
+            None
+          },
+        }
+      },
+      update::Atomic(updates) => {
+        let updates: Vec<_> = updates.into_iter().filter_map(|up| self.cancel_loads(up)).collect();
+        if updates.is_empty() {
+          None
+        } else {
+          Some(update::Atomic(updates))
+        }
+      },
+      up => Some(up),
+    }
+  }
+
+  fn push_back(&mut self, up: update::T) {
+    let up =
+      match self.cancel_loads(up) {
+        None => return,
+        Some(up) => up,
+      };
+
+    let seq = self.front + self.queue.len() as u64;
+    let mut ids = Vec::new();
+    loaded_chunks(&up, &mut ids);
+    for id in ids {
+      self.pending_loads.insert(id, seq);
+    }
+
+    self.queue.push_back(Some(up));
+    self.stats.depth += 1;
+    if self.stats.depth > self.stats.high_watermark {
+      self.stats.high_watermark = self.stats.depth;
+    }
+  }
+
+  fn pop_front(&mut self) -> Option<update::T> {
+    if let Some(position) = self.camera.take() {
+      return Some(update::MoveCamera(position))
+    }
+    if let Some(sun) = self.sun.take() {
+      return Some(update::SetSun(sun))
+    }
+    if let Some(id) = self.players.keys().next().cloned() {
+      match self.players.remove(&id).unwrap() {
+        None => return Some(update::RemovePlayer(id)),
+        Some(mesh) => return Some(update::UpdatePlayer(id, mesh)),
+      }
+    }
+    if let Some(id) = self.mobs.keys().next().cloned() {
+      match self.mobs.remove(&id).unwrap() {
+        None => return Some(update::RemoveMob(id)),
+        Some(mesh) => return Some(update::UpdateMob(id, mesh)),
+      }
+    }
+
+    while let Some(up) = self.queue.pop_front() {
+      self.front += 1;
+      let up =
+        match up {
+          None => continue,
+          Some(up) => up,
+        };
+
+      let mut ids = Vec::new();
+      loaded_chunks(&up, &mut ids);
+      for id in ids {
+        self.pending_loads.remove(&id);
+      }
+
+      self.stats.depth -= 1;
+      return Some(up)
+    }
+
+    None
+  }
+}
+
+impl T {
+  /// Enqueue an update, blocking while the queue is full.
+  pub fn push(&self, up: update::T) {
+    let mut contents = self.contents.lock().unwrap();
+    match up {
+      update::MoveCamera(position) => {
+        if contents.camera.is_some() {
+          contents.stats.coalesced += 1;
+        }
+        contents.camera = Some(position);
+      },
+      update::SetSun(sun) => {
+        if contents.sun.is_some() {
+          contents.stats.coalesced += 1;
+        }
+        contents.sun = Some(sun);
+      },
+      update::UpdatePlayer(id, mesh) => {
+        if contents.players.insert(id, Some(mesh)).is_some() {
+          contents.stats.coalesced += 1;
+        }
+      },
+      // Don't draw a mesh for an entity that's going away. An older one might have been drawn already, so the removal
+      // still goes through.
+      update::RemovePlayer(id) => {
+        if contents.players.insert(id, None).is_some() {
+          contents.stats.coalesced += 1;
+        }
+      },
+      update::UpdateMob(id, mesh) => {
+        if contents.mobs.insert(id, Some(mesh)).is_some() {
+          contents.stats.coalesced += 1;
+        }
+      },
+      update::RemoveMob(id) => {
+        if contents.mobs.insert(id, None).is_some() {
+          contents.stats.coalesced += 1;
+        }
+      },
+      up => {
+        while !contents.closed && contents.stats.depth >= self.capacity {
+          contents.stats.stalls += 1;
+          contents = self.not_full.wait(contents).unwrap();
+        }
+        if contents.closed {
+          return
+        }
+        contents.push_back(up);
+      },
+    }
+  }
+
+  /// Dequeue an update, if there are any.
+  pub fn pop(&self) -> Option<update::T> {
+    let r = self.contents.lock().unwrap().pop_front();
+    if r.is_some() {
+      self.not_full.notify_one();
+    }
+    r
+  }
+
+  /// Stop accepting updates, and wake up any blocked producers.
+  pub fn close(&self) {
+    self.contents.lock().unwrap().closed = true;
+    self.not_full.notify_all();
+  }
+
+  #[allow(missing_docs)]
+  pub fn stats(&self) -> Stats {
+    self.contents.lock().unwrap().stats
+  }
+}
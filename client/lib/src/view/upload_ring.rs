@@ -0,0 +1,173 @@
+//! Streaming uploads into VRAM buffers that the GPU may still be reading from.
+//! Writing straight into such a buffer (`glBufferSubData`) can stall until the GPU is done with it. Instead, data
+//! is written into an unsynchronized mapping of a ring of staging memory, and copied into place on the GPU.
+//! Fences on each segment of the ring keep us from overwriting staging data before the GPU has copied it out.
+
+use gl;
+use gl::types::*;
+use std;
+use yaglw::vertex_buffer::GLBuffer;
+
+/// The default size of the staging ring, in bytes.
+pub const DEFAULT_SIZE: usize = 4 << 20;
+const SEGMENTS: usize = 4;
+
+/// Upload statistics, for debugging output.
+#[derive(Debug, Clone, Copy)]
+pub struct Stats {
+  /// Bytes uploaded through the ring.
+  pub bytes       : u64,
+  /// The number of times we had to wait for the GPU to finish with a segment of the ring.
+  pub stalls      : u64,
+  /// Uploads too big for the ring, which went through `glBufferSubData` instead.
+  pub oversized   : u64,
+}
+
+/// A ring of staging memory for uploads.
+pub struct T {
+  buffer : GLuint,
+  size   : usize,
+  // Where the next upload goes in the ring.
+  head   : usize,
+  // A fence for each segment the GPU may still be copying out of, or null.
+  fences : [GLsync; SEGMENTS],
+  stats  : Stats,
+}
+
+#[allow(missing_docs)]
+pub fn new(size: usize) -> T {
+  let buffer =
+    unsafe {
+      let mut buffer = 0;
+      gl::GenBuffers(1, &mut buffer);
+      gl::BindBuffer(gl::COPY_READ_BUFFER, buffer);
+      gl::BufferData(gl::COPY_READ_BUFFER, size as GLsizeiptr, std::ptr::null(), gl::STREAM_DRAW);
+      buffer
+    };
+  T {
+    buffer : buffer,
+    size   : size,
+    head   : 0,
+    fences : [std::ptr::null(); SEGMENTS],
+    stats  :
+      Stats {
+        bytes     : 0,
+        stalls    : 0,
+        oversized : 0,
+      },
+  }
+}
+
+impl T {
+  fn segment_of(&self, byte: usize) -> usize {
+    byte * SEGMENTS / self.size
+  }
+
+  // Mark a segment as in use by the GPU, up to the commands issued so far.
+  unsafe fn retire(&mut self, segment: usize) {
+    if !self.fences[segment].is_null() {
+      gl::DeleteSync(self.fences[segment]);
+    }
+    self.fences[segment] = gl::FenceSync(gl::SYNC_GPU_COMMANDS_COMPLETE, 0);
+  }
+
+  // Wait until the GPU is done with a segment.
+  unsafe fn wait(&mut self, segment: usize) {
+    let fence = self.fences[segment];
+    if fence.is_null() {
+      return
+    }
+    if gl::ClientWaitSync(fence, 0, 0) == gl::TIMEOUT_EXPIRED {
+      self.stats.stalls += 1;
+      gl::ClientWaitSync(fence, gl::SYNC_FLUSH_COMMANDS_BIT, std::u64::MAX);
+    }
+    gl::DeleteSync(fence);
+    self.fences[segment] = std::ptr::null();
+  }
+
+  /// Copy `len` bytes from `data` into the GL buffer `dest`, at byte `offset`.
+  /// This rebinds `COPY_READ_BUFFER` and `COPY_WRITE_BUFFER`.
+  pub unsafe fn upload(&mut self, dest: GLuint, offset: usize, data: *const u8, len: usize) {
+    gl::BindBuffer(gl::COPY_WRITE_BUFFER, dest);
+    if len > self.size / SEGMENTS {
+      self.stats.oversized += 1;
+      gl::BufferSubData(gl::COPY_WRITE_BUFFER, offset as GLintptr, len as GLsizeiptr, data as *const _);
+      return
+    }
+    if len == 0 {
+      return
+    }
+
+    if self.head + len > self.size {
+      let segment = self.segment_of(self.head);
+      self.retire(segment);
+      self.head = 0;
+    }
+
+    let first = self.segment_of(self.head);
+    let last = self.segment_of(self.head + len - 1);
+    for segment in first .. last + 1 {
+      self.wait(segment);
+    }
+
+    gl::BindBuffer(gl::COPY_READ_BUFFER, self.buffer);
+    let ptr =
+      gl::MapBufferRange(
+        gl::COPY_READ_BUFFER,
+        self.head as GLintptr,
+        len as GLsizeiptr,
+        gl::MAP_WRITE_BIT | gl::MAP_UNSYNCHRONIZED_BIT | gl::MAP_INVALIDATE_RANGE_BIT,
+      );
+    assert!(!ptr.is_null(), "Failed to map upload ring");
+    std::ptr::copy_nonoverlapping(data, ptr as *mut u8, len);
+    gl::UnmapBuffer(gl::COPY_READ_BUFFER);
+    gl::CopyBufferSubData(
+      gl::COPY_READ_BUFFER,
+      gl::COPY_WRITE_BUFFER,
+      self.head as GLintptr,
+      offset as GLintptr,
+      len as GLsizeiptr,
+    );
+    self.stats.bytes += len as u64;
+
+    // Fence off every segment we've moved past.
+    let new_head = self.head + len;
+    let end = if new_head == self.size { SEGMENTS } else { self.segment_of(new_head) };
+    for segment in first .. end {
+      self.retire(segment);
+    }
+    self.head = if new_head == self.size { 0 } else { new_head };
+  }
+
+  /// Append data to the end of a buffer. Returns false if it doesn't fit.
+  pub fn push<V>(&mut self, buffer: &mut GLBuffer<V>, data: &[V]) -> bool {
+    let len = std::mem::size_of::<V>() * data.len();
+    let byte_buffer = &mut buffer.byte_buffer;
+    if byte_buffer.length + len > byte_buffer.capacity {
+      return false
+    }
+    unsafe {
+      self.upload(byte_buffer.handle.gl_id, byte_buffer.length, data.as_ptr() as *const u8, len);
+    }
+    byte_buffer.length += len;
+    true
+  }
+
+  #[allow(missing_docs)]
+  pub fn stats(&self) -> Stats {
+    self.stats
+  }
+}
+
+impl Drop for T {
+  fn drop(&mut self) {
+    unsafe {
+      for fence in &self.fences {
+        if !fence.is_null() {
+          gl::DeleteSync(*fence);
+        }
+      }
+      gl::DeleteBuffers(1, &self.buffer);
+    }
+  }
+}
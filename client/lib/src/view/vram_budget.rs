@@ -0,0 +1,183 @@
+//! Keep terrain within the VRAM it's been given. Before a mesh is loaded into buffers without room for it, the meshes
+//! that have gone unseen the longest (and of those, the furthest away) are evicted. The logic thread is told which
+//! meshes went, so it can load them again if the player comes back for them.
+
+use cgmath::{Point3, Vector3, InnerSpace};
+use collision::Aabb3;
+use std;
+
+use common::fnv_map;
+use common::math;
+
+use terrain_mesh;
+
+use super::entity;
+use super::grass_buffers;
+use super::terrain_buffers;
+
+/// The default VRAM budget for terrain and its grass, together.
+pub const DEFAULT_BYTES: usize = 128_000_000;
+
+/// How much fits in the terrain and grass buffers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Budget {
+  /// Terrain chunks, of `terrain_buffers::CHUNK_LENGTH` polygons each.
+  pub chunks : usize,
+  /// Grass tufts.
+  pub tufts  : usize,
+}
+
+/// Split a VRAM budget evenly between terrain and grass.
+pub fn split(bytes: usize) -> Budget {
+  Budget {
+    chunks : terrain_buffers::chunk_budget(bytes / 2),
+    tufts  : grass_buffers::tuft_budget(bytes / 2),
+  }
+}
+
+struct Mesh {
+  ids          : terrain_mesh::Ids,
+  center       : Point3<f32>,
+  /// Half the diagonal of the mesh's bounds.
+  radius       : f32,
+  last_seen_ns : u64,
+}
+
+#[allow(missing_docs)]
+pub struct T {
+  pub budget : Budget,
+  /// Every loaded mesh, by its first chunk's id.
+  meshes     : fnv_map::T<entity::id::Terrain, Mesh>,
+  /// The first chunk ids of meshes evicted since `take_evicted` was last called.
+  evicted    : Vec<entity::id::Terrain>,
+}
+
+#[allow(missing_docs)]
+pub fn new(budget: Budget) -> T {
+  T {
+    budget  : budget,
+    meshes  : fnv_map::new(),
+    evicted : Vec::new(),
+  }
+}
+
+impl T {
+  /// Whether this many chunks and tufts fit in the budget.
+  pub fn has_room(&self, chunks: usize, tufts: usize) -> bool {
+    chunks <= self.budget.chunks && tufts <= self.budget.tufts
+  }
+
+  /// Keep track of a mesh that's being loaded. It counts as just seen.
+  pub fn loaded(&mut self, ids: terrain_mesh::Ids, bounds: Option<Aabb3<f32>>, now_ns: u64) {
+    let first =
+      match ids.chunk_ids.first() {
+        None => return,
+        Some(&id) => id,
+      };
+    let (center, radius) =
+      match bounds {
+        None => (Point3::new(0.0, 0.0, 0.0), 0.0),
+        Some(bounds) => {
+          (math::center(&bounds), (bounds.max - bounds.min).magnitude() / 2.0)
+        },
+      };
+    self.meshes.insert(
+      first,
+      Mesh {
+        ids          : ids,
+        center       : center,
+        radius       : radius,
+        last_seen_ns : now_ns,
+      },
+    );
+  }
+
+  /// Stop keeping track of a mesh that's being unloaded. Returns false if it's already been evicted, in which case
+  /// there's nothing left to unload.
+  pub fn unloaded(&mut self, ids: &terrain_mesh::Ids) -> bool {
+    match ids.chunk_ids.first() {
+      None => true,
+      Some(id) => self.meshes.remove(id).is_some(),
+    }
+  }
+
+  /// Mark the meshes in front of a camera at `eye`, facing along `forward`, as seen.
+  pub fn saw(&mut self, eye: &Point3<f32>, forward: &Vector3<f32>, now_ns: u64) {
+    for mesh in self.meshes.values_mut() {
+      if (mesh.center - *eye).dot(*forward) >= -mesh.radius {
+        mesh.last_seen_ns = now_ns;
+      }
+    }
+  }
+
+  /// Pick a mesh to evict, if there are any: whichever's gone unseen the longest, breaking ties by distance from
+  /// `eye`. The caller has to unload the mesh that's returned.
+  pub fn evict(&mut self, eye: &Point3<f32>) -> Option<terrain_mesh::Ids> {
+    let mut victim: Option<(entity::id::Terrain, u64, f32)> = None;
+    for (&id, mesh) in &self.meshes {
+      let distance = (mesh.center - *eye).magnitude2();
+      let worse =
+        match victim {
+          None => true,
+          Some((_, seen, d)) => mesh.last_seen_ns < seen || (mesh.last_seen_ns == seen && distance > d),
+        };
+      if worse {
+        victim = Some((id, mesh.last_seen_ns, distance));
+      }
+    }
+    victim.map(|(id, _, _)| {
+      self.evicted.push(id);
+      self.meshes.remove(&id).unwrap().ids
+    })
+  }
+
+  /// The first chunk ids of the meshes evicted since this was last called.
+  pub fn take_evicted(&mut self) -> Vec<entity::id::Terrain> {
+    std::mem::replace(&mut self.evicted, Vec::new())
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use cgmath::{Point3, Vector3};
+  use collision::Aabb3;
+
+  use common::id_allocator;
+
+  use terrain_mesh;
+
+  use super::*;
+
+  #[test]
+  fn test_evict() {
+    let mut allocator = id_allocator::new();
+    let mut mesh = |budget: &mut T, x: f32| {
+      let ids = terrain_mesh::Ids { chunk_ids: vec!(allocator.allocate()), grass_ids: Vec::new() };
+      let bounds = Aabb3::new(Point3::new(x, 0.0, 0.0), Point3::new(x + 1.0, 1.0, 1.0));
+      budget.loaded(ids.clone(), Some(bounds), 0);
+      ids.chunk_ids[0]
+    };
+
+    let mut budget = new(Budget { chunks: 2, tufts: 0 });
+    assert!(budget.has_room(2, 0));
+    assert!(!budget.has_room(3, 0));
+    let behind = mesh(&mut budget, -10.0);
+    let near = mesh(&mut budget, 5.0);
+    let far = mesh(&mut budget, 50.0);
+
+    // Looking along +x, the mesh behind goes unseen, then the one further away goes first.
+    let eye = Point3::new(0.0, 0.0, 0.0);
+    budget.saw(&eye, &Vector3::new(1.0, 0.0, 0.0), 1);
+    assert_eq!(budget.evict(&eye).unwrap().chunk_ids, vec!(behind));
+    assert_eq!(budget.evict(&eye).unwrap().chunk_ids, vec!(far));
+    assert_eq!(budget.take_evicted(), vec!(behind, far));
+    assert!(budget.take_evicted().is_empty());
+
+    // Unloading an evicted mesh is a no-op.
+    let evicted = terrain_mesh::Ids { chunk_ids: vec!(far), grass_ids: Vec::new() };
+    assert!(!budget.unloaded(&evicted));
+    let loaded = terrain_mesh::Ids { chunk_ids: vec!(near), grass_ids: Vec::new() };
+    assert!(budget.unloaded(&loaded));
+    assert!(budget.evict(&eye).is_none());
+  }
+}
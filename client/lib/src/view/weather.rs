@@ -0,0 +1,246 @@
+//! Rain and snow, drawn as particles falling through a box that follows the camera around, and the snow that builds up
+//! on grass while it's snowing.
+//! Particles stay put in the world as the camera moves; one that leaves the box comes back in on the other side.
+
+use cgmath::{Point3, Vector3, InnerSpace};
+use rand;
+use rand::Rng;
+use yaglw::vertex_buffer::{GLArray, GLBuffer, VertexAttribData};
+use yaglw::vertex_buffer::{DrawMode, GLType};
+use yaglw::gl_context::GLContext;
+
+use common::color::Color4;
+use common::protocol;
+
+use vertex::ColoredVertex;
+use view;
+
+const MAX_PARTICLES: usize = 2048;
+const VERTICES_PER_PARTICLE: usize = 6;
+/// The width of the cube of particles around the camera.
+const BOX_WIDTH: f32 = 32.0;
+
+/// How fast rain falls, in world units per second.
+const RAIN_SPEED: f32 = 14.0;
+const RAIN_WIDTH: f32 = 0.02;
+const RAIN_LENGTH: f32 = 0.6;
+/// How fast snow falls, in world units per second.
+const SNOW_SPEED: f32 = 1.2;
+const SNOW_SIZE: f32 = 0.08;
+/// How far snowflakes drift from side to side as they fall.
+const SNOW_SWAY: f32 = 0.4;
+
+/// How long full-intensity snow takes to cover grass completely, in seconds.
+const SNOW_COVER_SECONDS: f32 = 120.0;
+/// How long complete snow cover takes to melt once it stops snowing, in seconds.
+const MELT_SECONDS: f32 = 240.0;
+
+#[allow(missing_docs)]
+pub struct T<'a> {
+  /// What's falling, as of the last update from the server.
+  pub weather    : protocol::Weather,
+  /// How much snow has settled on grass, in [0, 1].
+  pub snow_cover : f32,
+  /// Whether snow settles on grass at all.
+  snow_settles   : bool,
+  /// Where each particle would be if nothing had fallen, anywhere in a `BOX_WIDTH` cube.
+  particles      : Vec<Point3<f32>>,
+  /// How far particles have fallen so far.
+  fallen         : f64,
+  last_update_ns : Option<u64>,
+  /// Whether `vertices` has a full set of particles in it.
+  uploaded       : bool,
+  vertices       : GLArray<'a, ColoredVertex>,
+}
+
+/// Snow only settles on grass if `snow_settles` is set.
+pub fn new<'a, 'b>(gl: &'b mut GLContext, shader: &view::shaders::color::T<'a>, snow_settles: bool) -> T<'a> where
+  'a: 'b,
+{
+  let mut rng: rand::XorShiftRng = rand::SeedableRng::from_seed([5, 6, 7, 8]);
+  let particles =
+    (0 .. MAX_PARTICLES)
+    .map(|_| {
+      let mut coord = || rng.gen_range(0.0, BOX_WIDTH);
+      Point3::new(coord(), coord(), coord())
+    })
+    .collect();
+
+  let buffer = GLBuffer::new(gl, MAX_PARTICLES * VERTICES_PER_PARTICLE);
+  T {
+    weather        :
+      protocol::Weather {
+        precipitation : protocol::Precipitation::None,
+        intensity     : 0.0,
+      },
+    snow_cover     : 0.0,
+    snow_settles   : snow_settles,
+    particles      : particles,
+    fallen         : 0.0,
+    last_update_ns : None,
+    uploaded       : false,
+    vertices       :
+      GLArray::new(
+        gl,
+        &shader.shader,
+        &[
+          VertexAttribData { name: "position", size: 3, unit: GLType::Float, divisor: 0 },
+          VertexAttribData { name: "in_color", size: 4, unit: GLType::Float, divisor: 0 },
+        ],
+        DrawMode::Triangles,
+        buffer,
+      ),
+  }
+}
+
+/// How much snow cover there is `seconds` after there was `cover`, if the weather was `weather` the whole time.
+fn next_snow_cover(cover: f32, weather: &protocol::Weather, seconds: f32) -> f32 {
+  let cover =
+    match weather.precipitation {
+      protocol::Precipitation::Snow => cover + weather.intensity * seconds / SNOW_COVER_SECONDS,
+      protocol::Precipitation::None | protocol::Precipitation::Rain => cover - seconds / MELT_SECONDS,
+    };
+  cover.max(0.0).min(1.0)
+}
+
+/// Wrap `x` into [low, low + BOX_WIDTH).
+fn wrap(x: f32, low: f32) -> f32 {
+  let r = (x - low) % BOX_WIDTH;
+  if r < 0.0 {
+    low + r + BOX_WIDTH
+  } else {
+    low + r
+  }
+}
+
+/// A quad centered on `center`, extending `side` to either side and `up` above and below.
+fn quad(
+  center: Point3<f32>,
+  side: Vector3<f32>,
+  up: Vector3<f32>,
+  color: Color4<f32>,
+) -> [ColoredVertex; VERTICES_PER_PARTICLE] {
+  let vtx = |p| ColoredVertex { position: p, color: color };
+  let (bottom_left, bottom_right) = (center - side - up, center + side - up);
+  let (top_left, top_right) = (center - side + up, center + side + up);
+  [
+    vtx(bottom_left), vtx(bottom_right), vtx(top_right),
+    vtx(bottom_left), vtx(top_right), vtx(top_left),
+  ]
+}
+
+impl<'a> T<'a> {
+  /// Move the particles on to `now`, around a camera at `eye` looking along `forward`, and let snow settle or melt.
+  /// `vertices` is scratch space.
+  pub fn update(
+    &mut self,
+    gl: &mut GLContext,
+    eye: &Point3<f32>,
+    forward: &Vector3<f32>,
+    now: u64,
+    vertices: &mut Vec<ColoredVertex>,
+  ) {
+    let seconds =
+      match self.last_update_ns {
+        None => 0.0,
+        Some(last) => now.saturating_sub(last) as f32 / 1e9,
+      };
+    self.last_update_ns = Some(now);
+
+    if self.snow_settles {
+      self.snow_cover = next_snow_cover(self.snow_cover, &self.weather, seconds);
+    }
+
+    let (speed, color) =
+      match self.weather.precipitation {
+        protocol::Precipitation::None => return,
+        protocol::Precipitation::Rain => (RAIN_SPEED, Color4::of_rgba(0.6, 0.65, 0.75, 0.5)),
+        protocol::Precipitation::Snow => (SNOW_SPEED, Color4::of_rgba(1.0, 1.0, 1.0, 0.9)),
+      };
+    self.fallen += (speed * seconds) as f64;
+
+    let mut right = forward.cross(Vector3::new(0.0, 1.0, 0.0));
+    if right.magnitude() < 1e-3 {
+      // Looking straight up or down.
+      right = Vector3::new(1.0, 0.0, 0.0);
+    }
+    let right = right.normalize();
+    let (side, up) =
+      match self.weather.precipitation {
+        protocol::Precipitation::Snow => {
+          // Flakes face the camera.
+          let up = right.cross(*forward).normalize();
+          (right * SNOW_SIZE, up * SNOW_SIZE)
+        },
+        _ => (right * RAIN_WIDTH, Vector3::new(0.0, RAIN_LENGTH / 2.0, 0.0)),
+      };
+    let sway =
+      match self.weather.precipitation {
+        protocol::Precipitation::Snow => SNOW_SWAY,
+        _ => 0.0,
+      };
+
+    let low = *eye + Vector3::new(-BOX_WIDTH / 2.0, -BOX_WIDTH / 2.0, -BOX_WIDTH / 2.0);
+    let fallen = (self.fallen % BOX_WIDTH as f64) as f32;
+    let drawn = (MAX_PARTICLES as f32 * self.weather.intensity.max(0.0).min(1.0)) as usize;
+    let zero = Vector3::new(0.0, 0.0, 0.0);
+    for (i, particle) in self.particles.iter().enumerate() {
+      if i >= drawn {
+        // Degenerate quads, so the buffer stays the same size.
+        vertices.extend(quad(*eye, zero, zero, color).iter().cloned());
+        continue
+      }
+      let phase = (self.fallen * 0.5) as f32 + particle.z;
+      let position =
+        Point3::new(
+          wrap(particle.x + sway * phase.sin(), low.x),
+          wrap(particle.y - fallen, low.y),
+          wrap(particle.z + sway * phase.cos(), low.z),
+        );
+      vertices.extend(quad(position, side, up, color).iter().cloned());
+    }
+
+    self.vertices.buffer.byte_buffer.bind(gl);
+    if self.uploaded {
+      self.vertices.buffer.update(gl, 0, vertices);
+    } else {
+      assert!(self.vertices.push(gl, vertices));
+      self.uploaded = true;
+    }
+  }
+
+  /// Draw the rain or snow. This should be done with the world shader bound, and face culling off.
+  pub fn draw(&self, gl: &mut GLContext) {
+    if !self.uploaded || self.weather.precipitation == protocol::Precipitation::None {
+      return
+    }
+    self.vertices.bind(gl);
+    self.vertices.draw(gl);
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use common::protocol;
+
+  use super::*;
+
+  #[test]
+  fn test_wrap() {
+    assert_eq!(wrap(5.0, 0.0), 5.0);
+    assert_eq!(wrap(BOX_WIDTH + 5.0, 0.0), 5.0);
+    assert_eq!(wrap(-5.0, 0.0), BOX_WIDTH - 5.0);
+    assert_eq!(wrap(1.0, -10.0), 1.0);
+  }
+
+  #[test]
+  fn test_snow_cover() {
+    let snow = protocol::Weather { precipitation: protocol::Precipitation::Snow, intensity: 1.0 };
+    let clear = protocol::Weather { precipitation: protocol::Precipitation::None, intensity: 0.0 };
+    let cover = next_snow_cover(0.0, &snow, SNOW_COVER_SECONDS / 2.0);
+    assert_eq!(cover, 0.5);
+    assert_eq!(next_snow_cover(cover, &snow, SNOW_COVER_SECONDS), 1.0);
+    assert_eq!(next_snow_cover(cover, &clear, MELT_SECONDS / 4.0), 0.25);
+    assert_eq!(next_snow_cover(cover, &clear, MELT_SECONDS), 0.0);
+  }
+}
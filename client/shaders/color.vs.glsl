@@ -6,12 +6,21 @@ uniform mat4 projection_matrix;
 uniform float near_clip;
 uniform float far_clip;
 
-in vec3 position;
-in vec4 in_color;
+// Fade out with distance from the eye, between fade_start and fade_end.
+// Fading is disabled when fade_end is 0.
+uniform vec3 eye_position;
+uniform float fade_start;
+uniform float fade_end;
+
+layout(location = 0) in vec3 position;
+layout(location = 1) in vec4 in_color;
 
 out vec4 color;
 
 void main() {
   gl_Position = adjust_depth_precision(near_clip, far_clip, projection_matrix * vec4(position, 1.0));
   color = in_color;
+  if (fade_end > 0) {
+    color.a *= 1 - smoothstep(fade_start, fade_end, distance(position, eye_position));
+  }
 }
@@ -13,6 +13,8 @@ uniform vec3 eye_position;
 
 uniform sampler2D texture_in;
 uniform float alpha_threshold;
+// How much snow has settled on grass, in [0, 1].
+uniform float snow_cover;
 
 in vec2 vs_texture_position;
 in vec3 vs_normal;
@@ -31,6 +33,7 @@ void main() {
   if (c.a < alpha_threshold) {
     discard;
   }
+  c.rgb = mix(c.rgb, vec3(0.92, 0.94, 1), 0.8 * snow_cover);
   vec3 world_position = vec3(gl_FragCoord.xy * gl_FragCoord.w, gl_FragCoord.w);
   vec4 fog_color = vec4(sun.intensity, 1);
   frag_color =
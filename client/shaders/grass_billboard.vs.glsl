@@ -12,11 +12,14 @@ uniform float time_ms;
 uniform samplerBuffer positions;
 uniform samplerBuffer normals;
 
-in vec2 texture_position;
-in vec3 vertex_position;
-in vec3 model_translation;
-in int polygon_id;
-in uint tex_id;
+// Each tuft is drawn as this many instances of the same quad, rotated evenly around the vertical axis.
+// The per-tuft attributes advance once per tuft. This has to match `QUADS_PER_TUFT` in grass_buffers.rs.
+const int QUADS_PER_TUFT = 3;
+
+layout(location = 0) in vec2 texture_position;
+layout(location = 1) in vec3 vertex_position;
+layout(location = 2) in int polygon_id;
+layout(location = 3) in uint tex_id;
 
 out vec2 vs_texture_position;
 out vec3 vs_normal;
@@ -67,6 +70,18 @@ void main() {
   vs_texture_position = texture_position;
   vs_tex_id = float(tex_id);
 
+  float quad_angle = float(gl_InstanceID % QUADS_PER_TUFT) * 3.14159265 / float(QUADS_PER_TUFT);
+  float quad_sin = sin(quad_angle);
+  float quad_cos = cos(quad_angle);
+  mat3 quad_rotation =
+    mat3(
+      vec3(quad_cos, 0, -quad_sin),
+      vec3(0, 1, 0),
+      vec3(quad_sin, 0, quad_cos)
+    );
+  vec3 quad_position = quad_rotation * vertex_position;
+  vec3 model_translation = quad_rotation * vec3(0.5, 0, 0);
+
   // Put the grass tuft in the middle of the underlying terrain polygon.
   int position_id = polygon_id * 3 * 3;
   mat3 vertices =
@@ -137,7 +152,7 @@ void main() {
   mat4 shear_mat = shear * noise_shear;
   mat4 to_world_mat = translation * rotation * model_translation_mat;
 
-  vec4 scaled = scale * vec4(vertex_position, 1);
+  vec4 scaled = scale * vec4(quad_position, 1);
   vec4 sheared = shear_mat * scaled;
   float length_ratio = length(vec3(scaled)) / length(vec3(sheared));
   sheared *= vec4(vec3(length_ratio), 1);
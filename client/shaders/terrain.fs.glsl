@@ -7,12 +7,16 @@ uniform struct Sun {
 
 uniform vec3 ambient_light;
 uniform vec3 eye_position;
+// How much snow has settled on grass, in [0, 1].
+uniform float snow_cover;
 
 uniform samplerBuffer positions;
 
 in vec3 world_position;
 in vec3 vs_normal;
 flat in int material;
+in float vs_sky_visibility;
+in float vs_occlusion;
 
 out vec4 frag_color;
 
@@ -66,6 +70,9 @@ void main() {
       1.5;
     grassiness = clamp(grassiness, 0, 1);
     base_color = vec4(mix(dirt(world_position), grass(world_position), grassiness), 1);
+    // Snow only settles on ground that faces up.
+    float snowiness = snow_cover * clamp(2 * dot(normal, vec3(0, 1, 0)) - 0.6, 0, 1);
+    base_color = mix(base_color, vec4(0.92, 0.94, 1, 1), snowiness);
   } else if (material == 2) {
     base_color = vec4(bark(world_position), 1);
   } else if (material == 3) {
@@ -76,6 +83,9 @@ void main() {
   } else if (material == 5) {
     base_color = vec4(0, 0, 0, 1);
     shininess = 40;
+  } else if (material == 6) {
+    base_color = vec4(0.08, 0.04, 0.12, 1);
+    shininess = 80;
   } else {
     base_color = vec4(0.5, 0, 0.5, 0.5);
     shininess = 1;
@@ -87,7 +97,8 @@ void main() {
       sun.direction,
       sun.intensity,
       normalize(world_position - eye_position),
-      ambient_light,
+      // Ambient light comes from the sky, so enclosed places don't get much, and crevices get even less.
+      ambient_light * vs_sky_visibility * (1 - vs_occlusion),
       base_color,
       shininess,
       normal,
@@ -9,10 +9,16 @@ uniform mat4 projection_matrix;
 uniform samplerBuffer positions;
 uniform samplerBuffer normals;
 uniform isamplerBuffer materials;
+// How much of the sky each vertex can see, in [0, 1].
+uniform samplerBuffer sky_visibility;
+// How much of each vertex's ambient light is blocked by the voxels right around it, in [0, 1].
+uniform samplerBuffer occlusion;
 
 out vec3 world_position;
 out vec3 vs_normal;
 flat out int material;
+out float vs_sky_visibility;
+out float vs_occlusion;
 
 void main() {
   // Mutiply by 3 because there are 3 components for each normal vector.
@@ -30,5 +36,8 @@ void main() {
 
   material = texelFetch(materials, face_id).r;
 
+  vs_sky_visibility = texelFetch(sky_visibility, gl_VertexID).r;
+  vs_occlusion = texelFetch(occlusion, gl_VertexID).r;
+
   gl_Position = adjust_depth_precision(near_clip, far_clip, projection_matrix * vec4(world_position, 1.0));
 }
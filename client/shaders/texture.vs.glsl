@@ -2,8 +2,8 @@
 
 uniform mat4 projection_matrix;
 
-in vec3 position;
-in vec2 texture_position;
+layout(location = 0) in vec3 position;
+layout(location = 1) in vec2 texture_position;
 
 out vec2 tex_position;
 
@@ -1,33 +1,27 @@
 //! Data structures and functions shared between server and client.
+//! The actual code lives in the `playform-protocol` and `playform-util` crates; this re-exports both.
 
 #![deny(missing_docs)]
 #![deny(warnings)]
 
-extern crate cgmath;
-extern crate collision;
-extern crate fnv;
-extern crate isosurface_extraction;
-extern crate log;
-extern crate nanomsg;
-extern crate num;
-extern crate serde;
-#[macro_use]
-extern crate serde_derive;
-extern crate stopwatch;
-extern crate time;
-extern crate voxel_data;
+extern crate protocol as wire;
+extern crate util;
 
-pub mod closure_series;
-pub mod color;
-pub mod cube_shell;
-pub mod entity;
-pub mod fnv_map;
-pub mod fnv_set;
-pub mod id_allocator;
-pub mod index;
-pub mod interval_timer;
-pub mod protocol;
-pub mod range_abs;
-pub mod socket;
-pub mod surroundings_loader;
-pub mod voxel;
+pub use util::closure_series;
+pub use util::color;
+pub use util::cube_shell;
+pub use util::fnv_map;
+pub use util::fnv_set;
+pub use util::id_allocator;
+pub use util::index;
+pub use util::interval_timer;
+pub use util::line_file;
+pub use util::math;
+pub use util::range_abs;
+pub use util::surroundings_loader;
+
+pub use wire::entity;
+pub use wire::protocol;
+#[cfg(feature = "socket")]
+pub use wire::socket;
+pub use wire::voxel;
@@ -7,6 +7,9 @@ mod types {
 
   #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
   pub struct Mob;
+
+  #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+  pub struct Beacon;
 }
 
 #[allow(missing_docs)]
@@ -49,4 +52,5 @@ pub mod id {
 
   pub type Player = T<super::types::Player>;
   pub type Mob = T<super::types::Mob>;
+  pub type Beacon = T<super::types::Beacon>;
 }
@@ -0,0 +1,21 @@
+//! The wire contract between server and client: messages, entity ids and block formats.
+
+#![deny(missing_docs)]
+#![deny(warnings)]
+
+extern crate cgmath;
+extern crate collision;
+#[cfg(feature = "isosurface")]
+extern crate isosurface_extraction;
+#[cfg(feature = "socket")]
+extern crate nanomsg;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate voxel_data;
+
+pub mod entity;
+pub mod protocol;
+#[cfg(feature = "socket")]
+pub mod socket;
+pub mod voxel;
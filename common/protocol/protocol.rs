@@ -0,0 +1,344 @@
+//! Defines the messages passed between client and server.
+
+use cgmath::{Vector2, Vector3, Point3};
+use collision::{Aabb3};
+use std::default::Default;
+use std::fmt;
+use std::ops::Add;
+
+use entity;
+use voxel;
+
+/// The version of these messages. Bump this whenever they change, so clients and servers built from different
+/// revisions refuse to talk to each other instead of misreading each other's messages.
+pub const VERSION: u32 = 10;
+
+/// A secret the server hands a client along with its id (or its place in line). Everything the client sends after its
+/// `Init` has to carry it, so no one else can act on the client's behalf by using its id.
+pub type Secret = u64;
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Serialize, Deserialize)]
+/// Unique client ID.
+pub struct ClientId(u32);
+
+impl Default for ClientId {
+  fn default() -> ClientId {
+    ClientId(0)
+  }
+}
+
+impl Add<u32> for ClientId {
+  type Output = ClientId;
+
+  fn add(self, rhs: u32) -> ClientId {
+    let ClientId(i) = self;
+    ClientId(i + rhs)
+  }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+/// What a client can handle, declared when it connects.
+pub struct Capabilities {
+  /// The most terrain triangles the client can have loaded at once.
+  pub max_triangles : u32,
+  /// How many bytes per second the client can receive.
+  pub bandwidth     : u32,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+/// Limits the server puts on a client's terrain, based on its `Capabilities`.
+pub struct TerrainPolicy {
+  /// Base-2 log of the width of the smallest voxels the client should request.
+  /// Requests for smaller voxels get the voxels of this size that contain them instead.
+  pub min_lg_voxel_size   : i16,
+  /// Terrain further than this (in world units, along any axis) from the client's player gets unloaded.
+  pub view_distance       : f32,
+  /// Mobs further than this (in world units, along any axis) from every player are frozen in place.
+  pub simulation_distance : f32,
+}
+
+/// How much health players have when they (re)spawn.
+pub const MAX_HEALTH: u32 = 100;
+
+/// The radius of a player's dig brush until they pick another.
+pub const DEFAULT_BRUSH_RADIUS: f32 = 8.0;
+/// The smallest radius a player's dig brush can have.
+pub const MIN_BRUSH_RADIUS: f32 = 1.0;
+/// The largest radius a player's dig brush can have.
+pub const MAX_BRUSH_RADIUS: f32 = 16.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+/// The shape dug out by a player's dig brush.
+pub enum BrushShape {
+  /// A ball of the brush's radius.
+  Sphere,
+  /// An axis-aligned cube, with sides twice the brush's radius.
+  Cube,
+  /// An upright cylinder of the brush's radius, as tall as it is wide.
+  Cylinder,
+}
+
+impl BrushShape {
+  /// The shape after this one, cycling back around to the first.
+  pub fn next(self) -> BrushShape {
+    match self {
+      BrushShape::Sphere => BrushShape::Cube,
+      BrushShape::Cube => BrushShape::Cylinder,
+      BrushShape::Cylinder => BrushShape::Sphere,
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+/// Where a player was aiming when they acted, as their client saw it. The server checks this against where it thinks
+/// the player was, so edits land where the player aimed even when the server has since moved on.
+pub struct Aim {
+  /// Where the camera was.
+  pub position  : Point3<f32>,
+  /// The direction the camera was facing.
+  pub direction : Vector3<f32>,
+  /// When the player aimed, estimated in server time (ns).
+  pub time_ns   : u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Messages the client sends to the server.
+pub enum ClientToServer {
+  /// Notify the server that the client exists, and provide its protocol `VERSION`, a "return address", the account
+  /// its players belong to, its token (if it has one), and what it can handle. This has to stay the first message,
+  /// starting with the version and address, to match `Handshake`.
+  Init(u32, String, String, Option<String>, Capabilities),
+  /// Anything else, along with the `Secret` the client was given in its `LeaseId`. Requests without the secret of the
+  /// client they're from (or whose player they're about) are dropped.
+  Request(Secret, Request),
+  /// Answer a `Queued` with the secret it came with, to say the client is still waiting. Clients that stop answering
+  /// are dropped from the line instead of being admitted.
+  StillWaiting(Secret),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+/// What a client can ask of the server once it's been given an id.
+pub enum Request {
+  /// Ping
+  Ping(ClientId),
+  /// Ask the server to create a new player.
+  AddPlayer(ClientId),
+  /// Add a vector the player's acceleration.
+  Walk(entity::id::Player, Vector3<f32>),
+  /// Rotate the player by some amount.
+  RotatePlayer(entity::id::Player, Vector2<f32>),
+  /// [Try to] start a jump for the player.
+  StartJump(entity::id::Player),
+  /// [Try to] stop a jump for the player.
+  StopJump(entity::id::Player),
+  /// Start (true) or stop (false) sprinting.
+  Sprint(entity::id::Player, bool),
+  /// Ask the server to send a block of terrain.
+  RequestVoxels {
+    /// The time, in nanoseconds, when the voxels were requested.
+    time_requested_ns : u64,
+    /// The ID of the requesting client.
+    client_id       : ClientId,
+    /// The bounds of the voxels to fetch.
+    voxels          : Vec<voxel::bounds::T>,
+  },
+  /// Brush-add where the player aimed.
+  Add(entity::id::Player, Aim),
+  /// Put a boulder where the player aimed.
+  AddRock(entity::id::Player, Aim),
+  /// Throw a rock the way the player aimed. It knocks a small hole in whatever terrain it hits.
+  Throw(entity::id::Player, Aim),
+  /// Start digging where the player aimed, and carry on where they're looking. Voxels are removed once they've been
+  /// dug for long enough.
+  StartRemove(entity::id::Player, Aim),
+  /// Stop digging.
+  StopRemove(entity::id::Player),
+  /// Set the shape and radius of what the player digs out.
+  /// The radius is clamped to [`MIN_BRUSH_RADIUS`, `MAX_BRUSH_RADIUS`].
+  SetBrush(entity::id::Player, BrushShape, f32),
+  /// Run an admin command (e.g. `spawn me`) on behalf of a player, who can refer to themselves as `me`.
+  Command(entity::id::Player, String),
+  /// Start (true) or stop (false) sending the client `PhysicsBounds`, to debug with.
+  WatchPhysics(ClientId, bool),
+  /// The client is going away; its players should be removed.
+  Leave(ClientId),
+}
+
+/// Why a block is being sent to a client.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum VoxelReason {
+  /// The client asked for it.
+  Requested {
+    /// The time, in nanoseconds, when the voxels were requested.
+    at: u64,
+  },
+  /// The block has been updated.
+  Updated,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+/// Where the sun was at some point in server time. Clients extrapolate from this between updates.
+pub struct Sun {
+  /// The portion of the sun's cycle completed, in [0, 1). This advances linearly in time.
+  pub phase    : f32,
+  /// The server time, in nanoseconds, at which the sun was at `phase`.
+  pub time_ns  : u64,
+  /// How long a full cycle takes, in nanoseconds.
+  pub cycle_ns : u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[allow(missing_docs)]
+pub enum Precipitation {
+  None,
+  Rain,
+  Snow,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+/// What's falling from the sky, and how hard.
+pub struct Weather {
+  #[allow(missing_docs)]
+  pub precipitation : Precipitation,
+  /// How heavily it's raining or snowing, in [0, 1].
+  pub intensity     : f32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Collision events. First ID is "collider", rest of IDs are collidee(s).
+#[allow(missing_docs)]
+pub enum Collision {
+  PlayerTerrain(entity::id::Player),
+  PlayerMisc(entity::id::Player),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+/// A player or mob, for messages that can be about either.
+#[allow(missing_docs)]
+pub enum EntityRef {
+  Player(entity::id::Player),
+  Mob(entity::id::Mob),
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+/// An entity that's carried along by another one, e.g. a player riding a mob.
+pub struct Attachment {
+  /// The entity doing the carrying.
+  pub parent : EntityRef,
+  /// Where the attached entity's center is, relative to its parent's center.
+  pub offset : Vector3<f32>,
+}
+
+/// The most beacons there can be in the world at once.
+pub const MAX_BEACONS: usize = 32;
+
+/// The most boxes in one `PhysicsBounds`.
+pub const MAX_PHYSICS_BOUNDS: usize = 1024;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+/// The start of a `ClientToServer::Init`, which every version of the protocol agrees on. When a client's `Init`
+/// can't be decoded, this still can, so the server knows where to tell it why.
+pub enum Handshake {
+  #[allow(missing_docs)]
+  Init(u32, String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+/// Why a server wouldn't let a client connect.
+pub enum ConnectionError {
+  /// The client and server speak different versions of the protocol.
+  VersionMismatch {
+    #[allow(missing_docs)]
+    server : u32,
+    #[allow(missing_docs)]
+    client : u32,
+  },
+  /// The server needs a token to connect, and the client didn't have one it accepts.
+  Unauthorized,
+}
+
+impl fmt::Display for ConnectionError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match *self {
+      ConnectionError::VersionMismatch { server, client } =>
+        write!(f, "the server speaks protocol version {}, but this client speaks version {}", server, client),
+      ConnectionError::Unauthorized =>
+        write!(f, "the server didn't accept this client's token"),
+    }
+  }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Messages the server sends to the client.
+pub enum ServerToClient {
+  /// The client can't connect. This has to stay the first message, so clients of any version can understand it.
+  Rejected(ConnectionError),
+  /// Provide the client a unique id to tag its messages, the server's protocol `VERSION`, the limits on the terrain it
+  /// gets, and the secret to send its requests with.
+  LeaseId(ClientId, u32, TerrainPolicy, Secret),
+  /// The server is full. The client has been put in line to join, at this position (starting from 1), and should
+  /// answer with `StillWaiting` and this secret. It's sent a `LeaseId` once it's admitted.
+  Queued(u32, Secret),
+  /// Ping
+  Ping,
+
+  /// Complete an AddPlayer request.
+  PlayerAdded(entity::id::Player, Point3<f32>),
+
+  /// The client's own player was moved somewhere else entirely (the new position is its center).
+  /// Terrain around the old position won't be kept up to date.
+  Teleported(entity::id::Player, Point3<f32>),
+  /// Update the position of the client's own player.
+  UpdatePlayer(entity::id::Player, Aabb3<f32>),
+  /// Update the position and orientation of some other client's player, and what it's attached to, if anything.
+  /// The orientation is (lateral, vertical) rotation, in radians.
+  UpdatePlayerEntity(entity::id::Player, Aabb3<f32>, Vector2<f32>, Option<Attachment>),
+  /// Some other client's player has left.
+  PlayerRemoved(entity::id::Player),
+  /// Update the client's view of a mob with a given mesh, and what it's attached to, if anything.
+  UpdateMob(entity::id::Mob, Aabb3<f32>, Option<Attachment>),
+  /// Where the sun is, as of some server time.
+  UpdateSun(Sun),
+  /// The weather has changed.
+  UpdateWeather(Weather),
+  /// A beacon has been placed at (or moved to) this position.
+  UpdateBeacon(entity::id::Beacon, Point3<f32>),
+  /// A beacon has been taken down.
+  RemoveBeacon(entity::id::Beacon),
+  /// A thrown projectile has moved. Projectiles share ids with mobs.
+  UpdateProjectile(entity::id::Mob, Aabb3<f32>),
+  /// A thrown projectile has landed, or been dropped.
+  RemoveProjectile(entity::id::Mob),
+
+  /// Provide a block of terrain to a client.
+  Voxels {
+    /// The voxels requested, and their associated bounds.
+    voxels : Vec<(voxel::bounds::T, voxel::T)>,
+    /// The reason the voxels are being sent.
+    reason : VoxelReason,
+  },
+  /// The client has moved far away from these blocks; it should drop them.
+  UnloadBlocks(Vec<voxel::bounds::T>),
+  /// A collision happened.
+  Collision(Collision),
+  /// How far through digging the voxel it's looking at the client's player is, in [0, 1),
+  /// or `None` if it isn't digging anything.
+  MiningProgress(Option<f32>),
+  /// The server is going away in this many nanoseconds, for the given reason.
+  ShuttingDown(String, u64),
+  /// The client has been disconnected, for some reason.
+  Kicked(String),
+  /// A `Command` (the first string) failed, with some error.
+  CommandFailed(String, String),
+  /// A `Command` (the first string) answered with something, e.g. the result of a query.
+  CommandOutput(String, String),
+  /// An edit the client's player tried to make wasn't allowed, for some reason.
+  EditDenied(String),
+  /// The client's player's health has changed. At 0, the player has died and is about to respawn.
+  HealthUpdate(u32),
+  /// Something the server wants every player to know.
+  Announcement(String),
+  /// The bounds of everything the server's physics has around the client's player, while it's watching them. At most
+  /// `MAX_PHYSICS_BOUNDS` are sent.
+  PhysicsBounds(Vec<Aabb3<f32>>),
+}
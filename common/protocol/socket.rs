@@ -19,14 +19,21 @@ fn as_millis(duration: Duration) -> isize {
 impl SendSocket {
   #[allow(missing_docs)]
   pub fn new(url: &str, timeout: Option<Duration>) -> SendSocket {
-    let mut socket = Socket::new(Protocol::Push).unwrap();
-    timeout.map(|timeout| socket.set_receive_timeout(as_millis(timeout)).unwrap());
-    let endpoint = socket.connect(url).unwrap();
+    SendSocket::connect(url, timeout).unwrap()
+  }
 
-    SendSocket {
+  /// Like `new`, but an error (e.g. from a malformed URL) is returned instead of panicking.
+  pub fn connect(url: &str, timeout: Option<Duration>) -> std::result::Result<SendSocket, Error> {
+    let mut socket = try!(Socket::new(Protocol::Push));
+    if let Some(timeout) = timeout {
+      try!(socket.set_receive_timeout(as_millis(timeout)));
+    }
+    let endpoint = try!(socket.connect(url));
+
+    Ok(SendSocket {
       socket: socket,
       endpoint: endpoint,
-    }
+    })
   }
 
   /// Block until we can send this socket a message.
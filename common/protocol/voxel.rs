@@ -1,6 +1,8 @@
 //! Voxel implementation for terrain
 
+#[cfg(feature = "isosurface")]
 use isosurface_extraction;
+use std;
 use voxel_data;
 
 pub use voxel_data::bounds;
@@ -21,6 +23,35 @@ pub enum Material {
   Leaves = 3,
   Stone = 4,
   Marble = 5,
+  Obsidian = 6,
+}
+
+impl Material {
+  /// How long it takes to dig through a voxel of this material, in seconds.
+  pub fn hardness(&self) -> f32 {
+    match *self {
+      Material::Empty    => 0.0,
+      Material::Leaves   => 0.25,
+      Material::Terrain  => 0.5,
+      Material::Bark     => 1.0,
+      Material::Stone    => 2.0,
+      Material::Marble   => 3.0,
+      Material::Obsidian => 4.0,
+    }
+  }
+
+  /// How powerful an explosion it takes to destroy a voxel of this material.
+  pub fn blast_resistance(&self) -> f32 {
+    match *self {
+      Material::Empty    => 0.0,
+      Material::Leaves   => 0.5,
+      Material::Terrain  => 1.0,
+      Material::Bark     => 2.0,
+      Material::Stone    => 4.0,
+      Material::Marble   => 6.0,
+      Material::Obsidian => std::f32::INFINITY,
+    }
+  }
 }
 
 #[allow(missing_docs)]
@@ -51,6 +82,7 @@ pub mod mosaic {
   pub use voxel_data::mosaic::*;
 }
 
+#[cfg(feature = "isosurface")]
 impl isosurface_extraction::dual_contouring::material::T for Material {
   fn is_opaque(&self) -> bool {
     *self != Material::Empty
@@ -0,0 +1,57 @@
+//! Files with one entry per line, like the server's roles and hooks, and the client's settings and key bindings.
+//! Blank lines and lines starting with `#` aren't entries.
+
+use std;
+use std::io::Read;
+
+/// Pass each entry in the file at `path` to `parse`, in order. `what` says what the file holds, for the log.
+/// A file that can't be read has no entries. Entries `parse` rejects are logged by line number and skipped; the lines
+/// themselves aren't logged, since some files (like the server's tokens) are secret.
+pub fn load<Parse>(path: &std::path::Path, what: &str, mut parse: Parse) where
+  Parse: FnMut(&str) -> Result<(), String>,
+{
+  let mut contents = String::new();
+  if let Err(err) = std::fs::File::open(path).and_then(|mut file| file.read_to_string(&mut contents)) {
+    info!("Not loading {} from {:?}: {:?}", what, path, err);
+    return
+  }
+  for (i, line) in contents.lines().enumerate() {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+      continue
+    }
+    if let Err(err) = parse(line) {
+      warn!("{:?} line {}: {}", path, i + 1, err);
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use std;
+  use std::io::Write;
+
+  use super::*;
+
+  #[test]
+  fn test_load() {
+    let path = std::env::temp_dir().join("playform_line_file_test");
+    {
+      let mut file = std::fs::File::create(&path).unwrap();
+      write!(file, "first\n\n  # comment\n  second  \nbad\nthird").unwrap();
+    }
+
+    let mut entries = Vec::new();
+    load(&path, "entries", |line| {
+      if line == "bad" {
+        return Err(String::from("Bad entry"))
+      }
+      entries.push(line.to_owned());
+      Ok(())
+    });
+    assert_eq!(entries, vec!("first", "second", "third"));
+
+    std::fs::remove_file(&path).unwrap();
+    load(&path, "entries", |_| panic!("A missing file has no entries"));
+  }
+}
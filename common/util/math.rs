@@ -0,0 +1,23 @@
+//! Geometry helpers for the cgmath and collision types used on both sides of the protocol.
+
+use cgmath::{Point3, EuclideanSpace};
+use collision::Aabb3;
+
+/// The middle of a bounding box.
+pub fn center(bounds: &Aabb3<f32>) -> Point3<f32> {
+  Point3::from_vec((bounds.min.to_vec() + bounds.max.to_vec()) / 2.0)
+}
+
+#[cfg(test)]
+mod test {
+  use cgmath::Point3;
+  use collision::Aabb3;
+
+  use super::*;
+
+  #[test]
+  fn test_center() {
+    let bounds = Aabb3::new(Point3::new(-1.0, 2.0, 0.0), Point3::new(3.0, 4.0, 1.0));
+    assert_eq!(center(&bounds), Point3::new(1.0, 3.0, 0.5));
+  }
+}
@@ -0,0 +1,25 @@
+//! General-purpose helpers shared between server and client, which have nothing to do with the wire protocol.
+
+#![deny(missing_docs)]
+#![deny(warnings)]
+
+extern crate cgmath;
+extern crate collision;
+extern crate fnv;
+#[macro_use]
+extern crate log;
+extern crate num;
+extern crate stopwatch;
+
+pub mod closure_series;
+pub mod color;
+pub mod cube_shell;
+pub mod fnv_map;
+pub mod fnv_set;
+pub mod id_allocator;
+pub mod index;
+pub mod interval_timer;
+pub mod line_file;
+pub mod math;
+pub mod range_abs;
+pub mod surroundings_loader;
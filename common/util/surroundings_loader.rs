@@ -0,0 +1,219 @@
+//! This data structure emits messages to maintain its surrounding blocks in a desired
+//! loaded state (e.g. to keep player surroundings loaded, or to keep unloaded blocks
+//! solid near the player).
+
+use cgmath::Point3;
+use std::cmp::{max, min};
+use std::collections::VecDeque;
+use stopwatch;
+
+use cube_shell::{cube_diff, cube_shell};
+
+/// The center has to move up (or down) this many times in a row before the loader starts looking ahead vertically.
+const SUSTAINED_VERTICAL_MOVES: i32 = 2;
+/// How many blocks ahead to load first, once the center's been moving vertically.
+const VERTICAL_LOOKAHEAD: i32 = 4;
+
+fn surroundings_iter(center: Point3<i32>, max_distance: i32) -> Box<Iterator<Item=Point3<i32>> + Send> {
+  Box::new((0 .. max_distance).flat_map(move |radius| cube_shell(&center, radius)))
+}
+
+#[allow(missing_docs)]
+/// The type of message emitted by `T`. This stream of messages maintains
+/// an owner's desired surroundings.
+pub enum LoadType {
+  Load,
+  Unload,
+  /// Load only if already loaded
+  Downgrade,
+}
+
+/// Iteratively load points cube-shaped layers around the some point.
+/// That point can be updated with calls to `update`.
+/// What "load" exactly means depends on the closures provided.
+pub struct T {
+  last_position: Option<Point3<i32>>,
+
+  max_load_distance: u32,
+  to_load: Option<Box<Iterator<Item=Point3<i32>> + Send>>,
+
+  to_recheck: VecDeque<Point3<i32>>,
+  /// Blocks to load before any others, e.g. the column ahead of a player digging straight down.
+  to_prioritize: VecDeque<Point3<i32>>,
+  /// Blocks that were loaded, but have been dropped since, and need loading again if they're still in range.
+  to_reload: VecDeque<Point3<i32>>,
+  /// How many moves in a row the center has made up (positive) or down (negative).
+  vertical_moves: i32,
+  // The distances to the switches between LODs.
+  lod_thresholds: Vec<i32>,
+}
+
+#[allow(missing_docs)]
+pub fn new(
+  max_load_distance: u32,
+  lod_thresholds: Vec<i32>,
+) -> T {
+  T {
+    last_position: None,
+
+    to_load: None,
+    max_load_distance: max_load_distance,
+
+    to_recheck: VecDeque::new(),
+    to_prioritize: VecDeque::new(),
+    to_reload: VecDeque::new(),
+    vertical_moves: 0,
+    lod_thresholds: lod_thresholds,
+  }
+}
+
+impl T {
+  /// Update the center point around which we load, and load some more blocks.
+  pub fn updates(&mut self, position: &Point3<i32>) -> Updates {
+    let position_changed = self.last_position != Some(*position);
+    if position_changed {
+      stopwatch::time("surroundings_loader.extend", || {
+        self.to_load = Some(surroundings_iter((*position).clone(), self.max_load_distance as i32));
+        self.last_position.map(|last_position| {
+          self.track_vertical_moves(position.y - last_position.y);
+          for &distance in &self.lod_thresholds {
+            self.to_recheck.extend(
+              cube_diff(&last_position, &position, distance).into_iter()
+            );
+          }
+          self.to_recheck.extend(
+            cube_diff(&last_position, &position, self.max_load_distance as i32).into_iter()
+          );
+        });
+
+        self.last_position = Some(*position);
+        self.prioritize_column(position);
+      })
+    }
+
+    Updates {
+      loader: self,
+      position: *position,
+    }
+  }
+
+  /// Load some blocks again, e.g. after they've been evicted to save memory. Ones that are out of range by the time
+  /// they come up are skipped.
+  pub fn reload<Blocks: IntoIterator<Item=Point3<i32>>>(&mut self, blocks: Blocks) {
+    self.to_reload.extend(blocks);
+  }
+
+  fn track_vertical_moves(&mut self, dy: i32) {
+    self.vertical_moves =
+      if dy > 0 {
+        max(self.vertical_moves, 0) + 1
+      } else if dy < 0 {
+        min(self.vertical_moves, 0) - 1
+      } else {
+        0
+      };
+  }
+
+  /// When the center's kept moving up or down, load the column of blocks ahead of it (and the ones around that)
+  /// first. Otherwise the loader fills in whole shells around the center, and someone digging straight down or
+  /// building straight up can outrun it.
+  fn prioritize_column(&mut self, position: &Point3<i32>) {
+    self.to_prioritize.clear();
+    if self.vertical_moves.abs() < SUSTAINED_VERTICAL_MOVES {
+      return
+    }
+    let direction = self.vertical_moves.signum();
+    let lookahead = min(VERTICAL_LOOKAHEAD, self.max_load_distance as i32 - 1);
+    for distance in 1 .. lookahead + 1 {
+      for dx in -1 .. 2 {
+      for dz in -1 .. 2 {
+        self.to_prioritize.push_back(Point3::new(position.x + dx, position.y + direction * distance, position.z + dz));
+      }}
+    }
+  }
+}
+
+/// Iterator for the updates from a T.
+pub struct Updates<'a> {
+  loader: &'a mut T,
+  position: Point3<i32>,
+}
+
+/// Find the minimum cube shell radius it would take from one point to intersect the other.
+pub fn distance_between(p1: &Point3<i32>, p2: &Point3<i32>) -> u32 {
+  let dx = (p1.x - p2.x).abs();
+  let dy = (p1.y - p2.y).abs();
+  let dz = (p1.z - p2.z).abs();
+  max(max(dx, dy), dz) as u32
+}
+
+impl<'a> Updates<'a> {
+  fn next_reload(&mut self) -> Option<Point3<i32>> {
+    while let Some(position) = self.loader.to_reload.pop_front() {
+      if distance_between(&self.position, &position) < self.loader.max_load_distance {
+        return Some(position)
+      }
+    }
+    None
+  }
+}
+
+impl<'a> Iterator for Updates<'a> {
+  type Item = (Point3<i32>, LoadType);
+
+  fn next(&mut self) -> Option<Self::Item> {
+    stopwatch::time("surroundings_loader.next", || {
+      if let Some(position) = self.loader.to_recheck.pop_front() {
+        let distance = distance_between(&self.position, &position);
+        if distance > self.loader.max_load_distance {
+          Some((position, LoadType::Unload))
+        } else {
+          Some((position, LoadType::Downgrade))
+        }
+      } else if let Some(position) = self.loader.to_prioritize.pop_front() {
+        Some((position, LoadType::Load))
+      } else if let Some(position) = self.next_reload() {
+        Some((position, LoadType::Load))
+      } else {
+        self.loader.to_load.as_mut().unwrap().next()
+          .map(|position| (position, LoadType::Load))
+      }
+    })
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use cgmath::Point3;
+
+  use super::*;
+
+  fn first_load(loader: &mut T, position: &Point3<i32>) -> Option<Point3<i32>> {
+    loader.updates(position)
+      .filter(|&(_, ref load_type)| match *load_type { LoadType::Load => true, _ => false })
+      .map(|(position, _)| position)
+      .next()
+  }
+
+  #[test]
+  fn test_sustained_descent_loads_below_first() {
+    let mut loader = new(8, vec!());
+    assert_eq!(first_load(&mut loader, &Point3::new(0, 0, 0)), Some(Point3::new(0, 0, 0)));
+    // One step down could just be a ledge.
+    assert_eq!(first_load(&mut loader, &Point3::new(0, -1, 0)), Some(Point3::new(0, -1, 0)));
+    assert_eq!(first_load(&mut loader, &Point3::new(0, -2, 0)), Some(Point3::new(-1, -3, -1)));
+    // Walking off sideways stops it.
+    assert_eq!(first_load(&mut loader, &Point3::new(1, -2, 0)), Some(Point3::new(1, -2, 0)));
+  }
+
+  #[test]
+  fn test_reload_in_range() {
+    let mut loader = new(8, vec!());
+    let center = Point3::new(0, 0, 0);
+    // Everything's loaded, so there's nothing more to do until something's dropped.
+    for _ in loader.updates(&center) {}
+    loader.reload(vec!(Point3::new(20, 0, 0), Point3::new(3, 1, 2)));
+    assert_eq!(first_load(&mut loader, &center), Some(Point3::new(3, 1, 2)));
+    assert_eq!(first_load(&mut loader, &center), None);
+  }
+}
@@ -3,54 +3,172 @@
 #![deny(missing_docs)]
 #![deny(warnings)]
 
+extern crate ctrlc;
 extern crate env_logger;
 extern crate nanomsg;
 #[macro_use]
 extern crate log;
-extern crate thread_scoped;
 
 extern crate server_lib;
 
 use std::borrow::Borrow;
+use std::collections::VecDeque;
 use std::env;
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex};
+
+/// How many blocks wide the `--bench-terrain` grid is, if it isn't given.
+const DEFAULT_BENCH_WIDTH: u32 = 16;
 
 fn main() {
   env_logger::init().unwrap();
 
-  let mut args = env::args();
+  let mut args = env::args().peekable();
   args.next().unwrap();
+
+  if args.peek().map(|s| s == "--bench-terrain").unwrap_or(false) {
+    args.next();
+    let width = args.next().map(|s| s.parse().unwrap()).unwrap_or(DEFAULT_BENCH_WIDTH);
+    assert!(args.next().is_none());
+    server_lib::bench_terrain::run(width);
+    return
+  }
+
+  if args.peek().map(|s| s == "--migrate-terrain").unwrap_or(false) {
+    args.next();
+    let from = args.next().unwrap_or_else(|| String::from("default.terrain"));
+    let to = args.next().unwrap_or_else(|| from.clone());
+    assert!(args.next().is_none());
+    if let Err(err) = server_lib::migrate_terrain::run(std::path::Path::new(&from), std::path::Path::new(&to)) {
+      println!("{}", err);
+    }
+    return
+  }
+
+  if args.peek().map(|s| s == "--check-replay").unwrap_or(false) {
+    args.next();
+    let log = args.next().expect("--check-replay needs a replay log");
+    let hashes = args.next();
+    assert!(args.next().is_none());
+    let result = server_lib::replay::check(std::path::Path::new(&log), hashes.as_ref().map(std::path::Path::new));
+    if let Err(err) = result {
+      println!("{}", err);
+      std::process::exit(1);
+    }
+    return
+  }
+
+  let mut world = server_lib::world::default();
+  if args.peek().map(|s| s == "--heightmap").unwrap_or(false) {
+    args.next();
+    let path = args.next().expect("--heightmap needs an image path");
+    world.base =
+      server_lib::world::Base::Image {
+        path           : std::path::PathBuf::from(path),
+        vertical_scale : server_lib::world::DEFAULT_VERTICAL_SCALE,
+      };
+  }
+  if args.peek().map(|s| s == "--heightmap-scale").unwrap_or(false) {
+    args.next();
+    let scale = args.next().expect("--heightmap-scale needs a height").parse().unwrap();
+    match world.base {
+      server_lib::world::Base::Image { ref mut vertical_scale, .. } => *vertical_scale = scale,
+      server_lib::world::Base::Noise => panic!("--heightmap-scale only applies with --heightmap"),
+    }
+  }
+
+  let mut distances = server_lib::server::default_distances();
+  if args.peek().map(|s| s == "--view-distance").unwrap_or(false) {
+    args.next();
+    distances.view = args.next().expect("--view-distance needs a distance").parse().unwrap();
+  }
+  if args.peek().map(|s| s == "--simulation-distance").unwrap_or(false) {
+    args.next();
+    distances.simulation = args.next().expect("--simulation-distance needs a distance").parse().unwrap();
+  }
+
+  let mut replay_path = None;
+  if args.peek().map(|s| s == "--record-replay").unwrap_or(false) {
+    args.next();
+    replay_path = Some(std::path::PathBuf::from(args.next().expect("--record-replay needs a path")));
+  }
+
+  let mut autosave = Some(server_lib::autosave::default_config());
+  if args.peek().map(|s| s == "--autosave").unwrap_or(false) {
+    args.next();
+    let minutes: u64 = args.next().expect("--autosave needs a number of minutes").parse().unwrap();
+    let keep = args.next().expect("--autosave needs a number of snapshots to keep").parse().unwrap();
+    autosave =
+      if minutes == 0 || keep == 0 {
+        None
+      } else {
+        Some(server_lib::autosave::Config {
+          interval_ns : minutes * 60 * 1_000_000_000,
+          keep        : keep,
+        })
+      };
+  }
+
   let listen_url = args.next().unwrap_or_else(|| String::from("ipc:///tmp/server.ipc"));
+  let max_players: Option<usize> = args.next().map(|s| s.parse().unwrap());
   assert!(args.next().is_none());
 
   info!("Listening on {}.", listen_url);
 
-  let quit_signal = Mutex::new(false);
+  let quit_signal = Arc::new(Mutex::new(false));
+  let commands = Arc::new(Mutex::new(VecDeque::new()));
+  if let Some(max_players) = max_players {
+    commands.lock().unwrap().push_back(server_lib::command::T::MaxPlayers(max_players));
+  }
 
-  let _quit_thread =
-    unsafe {
-      let quit_signal = &quit_signal;
-      thread_scoped::scoped(move || {
-        wait_for_quit();
-        *quit_signal.lock().unwrap() = true;
-        // Close all sockets.
-        nanomsg::Socket::terminate();
-      })
-    };
+  {
+    let quit_signal = quit_signal.clone();
+    ctrlc::set_handler(move || {
+      println!("Interrupted");
+      *quit_signal.lock().unwrap() = true;
+    }).unwrap();
+  }
 
-  server_lib::run(listen_url.borrow(), &quit_signal);
+  // This isn't joined: it may be blocked on stdin when we quit some other way.
+  {
+    let quit_signal = quit_signal.clone();
+    let commands = commands.clone();
+    std::thread::spawn(move || {
+      read_commands(&commands);
+      *quit_signal.lock().unwrap() = true;
+    });
+  }
+
+  server_lib::run(
+    listen_url.borrow(),
+    &world,
+    distances,
+    replay_path.as_ref().map(|path| path.as_path()),
+    autosave,
+    &commands,
+    &quit_signal,
+  );
+
+  // Close all sockets.
+  nanomsg::Socket::terminate();
 }
 
-fn wait_for_quit() {
+/// Read commands from stdin until we're told to quit.
+fn read_commands(commands: &Mutex<VecDeque<server_lib::command::T>>) {
   loop {
     let mut line = String::new();
-    std::io::stdin().read_line(&mut line).unwrap();
+    if std::io::stdin().read_line(&mut line).unwrap() == 0 {
+      // stdin was closed.
+      return
+    }
 
     if line == "quit\n" {
       println!("Quitting");
       return
-    } else {
-      println!("Unrecognized command: {:?}", line);
+    }
+
+    match server_lib::command::parse(&line) {
+      Ok(command) => commands.lock().unwrap().push_back(command),
+      Err(err) => println!("{}", err),
     }
   }
 }
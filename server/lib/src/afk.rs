@@ -0,0 +1,286 @@
+//! Clients that haven't sent any input in a while are away from the keyboard (AFK). Their players stop keeping mobs
+//! and hibernating regions around them awake, can be parked at the spawn point until they come back, and are
+//! disconnected if they stay away for too long.
+
+use cgmath::Point3;
+
+use common::fnv_map;
+use common::protocol;
+use common::protocol::EntityRef;
+
+use entity;
+use join_queue;
+use player;
+use server;
+
+const MINUTE_NS: u64 = 60_000_000_000;
+
+/// How AFK clients are handled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Settings {
+  /// How long (in ns) a client goes without input before it's away.
+  pub away_after_ns       : u64,
+  /// How long (in ns) a client goes without input before it's disconnected, if it ever is.
+  pub disconnect_after_ns : Option<u64>,
+  /// Whether away players are moved to the spawn point, out of harm's way, until they're back.
+  pub park                : bool,
+}
+
+#[allow(missing_docs)]
+pub fn default_settings() -> Settings {
+  Settings {
+    away_after_ns       : 5 * MINUTE_NS,
+    disconnect_after_ns : Some(30 * MINUTE_NS),
+    park                : false,
+  }
+}
+
+#[allow(missing_docs)]
+pub struct T {
+  pub settings : Settings,
+  /// Where parked players were (the bottom corners of their bounds), to put them back when they return.
+  parked       : fnv_map::T<entity::id::Player, Point3<f32>>,
+}
+
+#[allow(missing_docs)]
+pub fn new() -> T {
+  T {
+    settings : default_settings(),
+    parked   : fnv_map::new(),
+  }
+}
+
+impl T {
+  /// Forget a player that's left.
+  pub fn forget(&mut self, player_id: entity::id::Player) {
+    self.parked.remove(&player_id);
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Status {
+  Active,
+  Away,
+  Expired,
+}
+
+fn status(settings: &Settings, idle_ns: u64) -> Status {
+  match settings.disconnect_after_ns {
+    Some(after) if idle_ns >= after => Status::Expired,
+    _ if idle_ns >= settings.away_after_ns => Status::Away,
+    _ => Status::Active,
+  }
+}
+
+/// The player a message is input from, if it's something a person at the keyboard did.
+pub fn input_from(message: &protocol::Request) -> Option<entity::id::Player> {
+  match *message {
+    protocol::Request::Walk(player_id, _) |
+    protocol::Request::RotatePlayer(player_id, _) |
+    protocol::Request::StartJump(player_id) |
+    protocol::Request::StopJump(player_id) |
+    protocol::Request::Sprint(player_id, _) |
+    protocol::Request::Add(player_id, _) |
+    protocol::Request::AddRock(player_id, _) |
+    protocol::Request::Throw(player_id, _) |
+    protocol::Request::StartRemove(player_id, _) |
+    protocol::Request::StopRemove(player_id) |
+    protocol::Request::SetBrush(player_id, _, _) |
+    protocol::Request::Command(player_id, _) => Some(player_id),
+    protocol::Request::Ping(_) |
+    protocol::Request::AddPlayer(_) |
+    protocol::Request::RequestVoxels { .. } |
+    protocol::Request::WatchPhysics(..) |
+    protocol::Request::Leave(_) => None,
+  }
+}
+
+fn players_of(server: &server::T, client_id: protocol::ClientId) -> Vec<entity::id::Player> {
+  server.players.lock().unwrap().values()
+    .filter(|player| player.client_id == client_id)
+    .map(|player| player.entity_id)
+    .collect()
+}
+
+/// Note some input from a player's client, at local time `now_ns`. If the client was away, it's back.
+pub fn input(server: &server::T, player_id: entity::id::Player, now_ns: u64) {
+  let client_id =
+    match server.components.controller(EntityRef::Player(player_id)) {
+      None => return,
+      Some(client_id) => client_id,
+    };
+  {
+    let mut clients = server.clients.lock().unwrap();
+    let client =
+      match clients.get_mut(&client_id) {
+        None => return,
+        Some(client) => client,
+      };
+    client.last_input_ns = now_ns;
+    if !client.afk {
+      return
+    }
+    client.afk = false;
+  }
+  info!("Client {:?} is back", client_id);
+
+  for player_id in players_of(server, client_id) {
+    server.components.away.lock().unwrap().remove(&EntityRef::Player(player_id));
+    let parked = server.afk.lock().unwrap().parked.remove(&player_id);
+    if let Some(min) = parked {
+      if let Some(player) = server.players.lock().unwrap().get_mut(&player_id) {
+        player.teleport(server, min);
+      }
+    }
+  }
+}
+
+fn away(server: &server::T, client_id: protocol::ClientId, settings: &Settings) {
+  info!("Client {:?} is away", client_id);
+  let message =
+    match settings.disconnect_after_ns {
+      None => String::from("You're away from the keyboard."),
+      Some(after) => {
+        format!(
+          "You're away from the keyboard. You'll be disconnected in {} minutes unless you do something.",
+          (after - settings.away_after_ns) / MINUTE_NS,
+        )
+      },
+    };
+  if let Some(client) = server.clients.lock().unwrap().get_mut(&client_id) {
+    client.send(protocol::ServerToClient::Announcement(message));
+  }
+
+  let mut players = server.players.lock().unwrap();
+  for player in players.values_mut().filter(|player| player.client_id == client_id) {
+    let entity = EntityRef::Player(player.entity_id);
+    server.components.away.lock().unwrap().insert(entity);
+    if !settings.park {
+      continue
+    }
+    let min =
+      match server.components.bounds.lock().unwrap().get(entity) {
+        None => continue,
+        Some(bounds) => bounds.min,
+      };
+    server.afk.lock().unwrap().parked.insert(player.entity_id, min);
+    player.teleport(server, player::SPAWN_POINT);
+  }
+}
+
+/// Mark clients that have gone quiet as away, and disconnect those that have been quiet for too long.
+pub fn tick(server: &server::T, now_ns: u64) {
+  let settings = server.afk.lock().unwrap().settings;
+
+  let mut newly_away = Vec::new();
+  let mut expired = Vec::new();
+  for (&client_id, client) in server.clients.lock().unwrap().iter_mut() {
+    match status(&settings, now_ns.saturating_sub(client.last_input_ns)) {
+      Status::Active => {},
+      Status::Away => {
+        if !client.afk {
+          client.afk = true;
+          newly_away.push(client_id);
+        }
+      },
+      Status::Expired => expired.push(client_id),
+    }
+  }
+
+  for client_id in newly_away {
+    away(server, client_id, &settings);
+  }
+
+  for client_id in expired {
+    info!("Disconnecting client {:?} for being away too long", client_id);
+    if let Some(client) = server.clients.lock().unwrap().get_mut(&client_id) {
+      client.send(protocol::ServerToClient::Kicked(String::from(
+        "You were away for a while, so you've been disconnected. Come back any time!"
+      )));
+    }
+    join_queue::leave(server, client_id);
+  }
+}
+
+/// Parse the arguments of an `afk <minutes> [<disconnect minutes>|never] [park]` command.
+pub fn parse<'a, Words>(words: &mut Words) -> Result<Settings, String> where
+  Words: Iterator<Item=&'a str>,
+{
+  let minutes = |s: &str| {
+    s.parse::<u64>().map(|m| m * MINUTE_NS).map_err(|_| format!("Invalid minutes: {:?}", s))
+  };
+  let away_after_ns =
+    match words.next() {
+      None => return Err(String::from("Missing minutes")),
+      Some(s) => try!(minutes(s)),
+    };
+  let mut settings =
+    Settings {
+      away_after_ns       : away_after_ns,
+      disconnect_after_ns : default_settings().disconnect_after_ns,
+      park                : false,
+    };
+  let mut word = words.next();
+  match word {
+    Some("never") => {
+      settings.disconnect_after_ns = None;
+      word = words.next();
+    },
+    Some(s) if s != "park" => {
+      settings.disconnect_after_ns = Some(try!(minutes(s)));
+      word = words.next();
+    },
+    _ => {},
+  }
+  match word {
+    None => {},
+    Some("park") => settings.park = true,
+    Some(s) => return Err(format!("Unexpected argument: {:?}", s)),
+  }
+  if let Some(after) = settings.disconnect_after_ns {
+    if after <= settings.away_after_ns {
+      return Err(String::from("Clients have to be away before they're disconnected"))
+    }
+  }
+  Ok(settings)
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_status() {
+    let settings = default_settings();
+    assert_eq!(status(&settings, 0), Status::Active);
+    assert_eq!(status(&settings, 5 * MINUTE_NS), Status::Away);
+    assert_eq!(status(&settings, 30 * MINUTE_NS), Status::Expired);
+    let settings = Settings { disconnect_after_ns: None, .. settings };
+    assert_eq!(status(&settings, 1000 * MINUTE_NS), Status::Away);
+  }
+
+  #[test]
+  fn test_parse() {
+    let parse_str = |s: &str| parse(&mut s.split_whitespace());
+    assert_eq!(
+      parse_str("10"),
+      Ok(Settings { away_after_ns: 10 * MINUTE_NS, disconnect_after_ns: Some(30 * MINUTE_NS), park: false }),
+    );
+    assert_eq!(
+      parse_str("2 never park"),
+      Ok(Settings { away_after_ns: 2 * MINUTE_NS, disconnect_after_ns: None, park: true }),
+    );
+    assert_eq!(
+      parse_str("2 15"),
+      Ok(Settings { away_after_ns: 2 * MINUTE_NS, disconnect_after_ns: Some(15 * MINUTE_NS), park: false }),
+    );
+    assert_eq!(
+      parse_str("2 park"),
+      Ok(Settings { away_after_ns: 2 * MINUTE_NS, disconnect_after_ns: Some(30 * MINUTE_NS), park: true }),
+    );
+    assert!(parse_str("").is_err());
+    assert!(parse_str("soon").is_err());
+    assert!(parse_str("10 5").is_err());
+    assert!(parse_str("2 15 stay").is_err());
+  }
+}
@@ -0,0 +1,179 @@
+//! Entities carried along by other entities, e.g. a player riding a mob, or a mob carried over a player's head.
+//! Attached entities don't move under their own power. After everything else has moved, each one is put back at
+//! its offset from its parent, parents before their children, so whole chains move together.
+
+use cgmath::{Vector2, Vector3};
+use collision::Aabb3;
+
+use common::fnv_map;
+use common::math;
+use common::protocol;
+use common::protocol::EntityRef;
+
+use entity;
+use server;
+
+#[allow(missing_docs)]
+pub struct T {
+  parents: fnv_map::T<EntityRef, protocol::Attachment>,
+}
+
+#[allow(missing_docs)]
+pub fn new() -> T {
+  T {
+    parents: fnv_map::new(),
+  }
+}
+
+impl T {
+  /// What an entity is attached to, if anything.
+  pub fn get(&self, child: EntityRef) -> Option<protocol::Attachment> {
+    self.parents.get(&child).cloned()
+  }
+
+  /// Carry `child` along with `parent`, with its center kept at `offset` from the parent's. An entity can only be
+  /// attached to one thing at a time, and can't end up carrying itself.
+  pub fn attach(&mut self, child: EntityRef, parent: EntityRef, offset: Vector3<f32>) -> Result<(), String> {
+    let mut ancestor = Some(parent);
+    while let Some(a) = ancestor {
+      if a == child {
+        return Err(format!("{:?} can't carry itself", child))
+      }
+      ancestor = self.parents.get(&a).map(|attachment| attachment.parent);
+    }
+    if self.parents.contains_key(&child) {
+      return Err(format!("{:?} is already attached to something", child))
+    }
+    self.parents.insert(child, protocol::Attachment { parent: parent, offset: offset });
+    Ok(())
+  }
+
+  /// Let an entity move on its own again. Returns what it was attached to.
+  pub fn detach(&mut self, child: EntityRef) -> Option<protocol::Attachment> {
+    self.parents.remove(&child)
+  }
+
+  /// Detach an entity from its parent, and everything attached to it, e.g. when it leaves the world.
+  pub fn detach_all(&mut self, entity: EntityRef) {
+    self.parents.remove(&entity);
+    self.parents.retain(|_, attachment| attachment.parent != entity);
+  }
+
+  /// Every attachment, with parents' attachments before their children's.
+  fn in_order(&self) -> Vec<(EntityRef, protocol::Attachment)> {
+    let depth = |mut entity: EntityRef| {
+      let mut depth = 0;
+      while let Some(attachment) = self.parents.get(&entity) {
+        entity = attachment.parent;
+        depth += 1;
+      }
+      depth
+    };
+    let mut attachments: Vec<(u32, EntityRef, protocol::Attachment)> =
+      self.parents.iter().map(|(&child, &attachment)| (depth(child), child, attachment)).collect();
+    attachments.sort_by_key(|&(depth, _, _)| depth);
+    attachments.into_iter().map(|(_, child, attachment)| (child, attachment)).collect()
+  }
+}
+
+fn physics_id(server: &server::T, entity: EntityRef) -> Option<entity::id::Misc> {
+  match entity {
+    EntityRef::Player(id) => server.players.lock().unwrap().get(&id).map(|player| player.physics_id),
+    EntityRef::Mob(id) => server.mobs.lock().unwrap().get(&id).map(|mob| mob.physics_id),
+  }
+}
+
+/// Move every attached entity to its parent, and tell clients where they ended up. This replaces the usual position
+/// updates for attached entities, which aren't simulated.
+pub fn propagate(server: &server::T) {
+  let attachments = server.attachments.lock().unwrap().in_order();
+  for (child, attachment) in attachments {
+    let (parent_id, child_id) =
+      match (physics_id(server, attachment.parent), physics_id(server, child)) {
+        (Some(parent_id), Some(child_id)) => (parent_id, child_id),
+        _ => {
+          // One of them has left the world without being detached.
+          server.attachments.lock().unwrap().detach(child);
+          continue
+        },
+      };
+
+    let bounds = {
+      let mut physics = server.physics.lock().unwrap();
+      let parent_center = math::center(physics.get_bounds(parent_id).unwrap());
+      let old = *physics.get_bounds(child_id).unwrap();
+      let shift = (parent_center + attachment.offset) - math::center(&old);
+      let bounds = Aabb3::new(old.min + shift, old.max + shift);
+      // Reinsert rather than translate, so the child can overlap its parent (or terrain) without getting stuck.
+      let is_capsule = match child { EntityRef::Player(_) => true, EntityRef::Mob(_) => false };
+      physics.remove_misc(child_id);
+      if is_capsule {
+        physics.insert_misc_capsule(child_id, &bounds);
+      } else {
+        physics.insert_misc(child_id, &bounds);
+      }
+      bounds
+    };
+
+    match child {
+      EntityRef::Player(id) => {
+        let (owner, rotation) = {
+          let mut players = server.players.lock().unwrap();
+          let player = players.get_mut(&id).unwrap();
+          player.position = math::center(&bounds);
+          player.speed = Vector3::new(0.0, 0.0, 0.0);
+          (player.client_id, Vector2::new(player.lateral_rotation, player.vertical_rotation))
+        };
+        server.components.moved(child, &bounds, Vector3::new(0.0, 0.0, 0.0));
+        for (&client_id, client) in server.clients.lock().unwrap().iter_mut() {
+          if client_id == owner {
+            client.send(protocol::ServerToClient::UpdatePlayer(id, bounds));
+          } else {
+            client.send(protocol::ServerToClient::UpdatePlayerEntity(id, bounds, rotation, Some(attachment)));
+          }
+        }
+      },
+      EntityRef::Mob(id) => {
+        {
+          let mut mobs = server.mobs.lock().unwrap();
+          let mob = mobs.get_mut(&id).unwrap();
+          mob.position = math::center(&bounds);
+          mob.speed = Vector3::new(0.0, 0.0, 0.0);
+        }
+        server.components.moved(child, &bounds, Vector3::new(0.0, 0.0, 0.0));
+        for (_, client) in server.clients.lock().unwrap().iter_mut() {
+          client.send(protocol::ServerToClient::UpdateMob(id, bounds, Some(attachment)));
+        }
+      },
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use cgmath::Vector3;
+
+  use common::entity;
+  use common::protocol::EntityRef;
+
+  use super::*;
+
+  #[test]
+  fn test_attach_rejects_cycles() {
+    let player: entity::id::Player = Default::default();
+    let mob: entity::id::Mob = Default::default();
+    let (player, mob, other_mob) = (EntityRef::Player(player), EntityRef::Mob(mob), EntityRef::Mob(mob + 1));
+    let up = Vector3::new(0.0, 2.0, 0.0);
+
+    let mut attachments = new();
+    attachments.attach(player, mob, up).unwrap();
+    assert!(attachments.attach(mob, player, up).is_err());
+    assert!(attachments.attach(player, other_mob, up).is_err());
+    attachments.attach(other_mob, player, up).unwrap();
+    assert_eq!(attachments.in_order().iter().map(|&(child, _)| child).collect::<Vec<_>>(), vec!(player, other_mob));
+
+    attachments.detach_all(player);
+    assert!(attachments.get(player).is_none());
+    assert!(attachments.get(other_mob).is_none());
+  }
+}
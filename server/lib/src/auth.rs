@@ -0,0 +1,107 @@
+//! Tokens that clients connect with. Tokens are kept in a file with one `<token> <account> <role>` per line. A client
+//! that connects with a token plays as the token's account, with the token's role, whatever account it claims to be.
+//! If there aren't any tokens, the server is open: anybody can connect as any account, and gets that account's role
+//! from the roles file.
+
+use std;
+
+use common::fnv_map;
+use common::line_file;
+use common::protocol;
+
+use roles;
+
+/// Who a token lets a client play as.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Grant {
+  #[allow(missing_docs)]
+  pub account : String,
+  #[allow(missing_docs)]
+  pub role    : roles::Role,
+}
+
+#[allow(missing_docs)]
+pub struct T {
+  tokens : fnv_map::T<String, Grant>,
+}
+
+impl T {
+  /// Whether clients need a token to connect.
+  pub fn required(&self) -> bool {
+    !self.tokens.is_empty()
+  }
+
+  /// Who a client connecting with `token` plays as, or why it can't connect. `None` means the server's open, and the
+  /// client can be whoever it says.
+  pub fn check(&self, token: Option<&str>) -> Result<Option<Grant>, protocol::ConnectionError> {
+    if !self.required() {
+      return Ok(None)
+    }
+    token
+      .and_then(|token| self.tokens.get(token))
+      .map(|grant| Some(grant.clone()))
+      .ok_or(protocol::ConnectionError::Unauthorized)
+  }
+}
+
+fn parse_line(line: &str) -> Result<(String, Grant), String> {
+  let words: Vec<&str> = line.split_whitespace().collect();
+  if words.len() != 3 {
+    return Err(format!("Expected <token> <account> <role>, got {} words", words.len()))
+  }
+  let grant =
+    Grant {
+      account : words[1].to_owned(),
+      role    : try!(roles::parse(words[2])),
+    };
+  Ok((words[0].to_owned(), grant))
+}
+
+/// Load tokens from a `line_file`. Without any, the server's open.
+pub fn load(path: &std::path::Path) -> T {
+  let mut tokens = fnv_map::new();
+  line_file::load(path, "tokens", |line| {
+    let (token, grant) = try!(parse_line(line));
+    tokens.insert(token, grant);
+    Ok(())
+  });
+
+  if tokens.is_empty() {
+    warn!("No tokens in {:?}; anybody who can reach the server can connect", path);
+  } else {
+    info!("Clients need one of the {} tokens in {:?} to connect", tokens.len(), path);
+  }
+
+  T {
+    tokens : tokens,
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use common::fnv_map;
+  use common::protocol;
+
+  use roles;
+
+  use super::*;
+
+  #[test]
+  fn test_check() {
+    let open = T { tokens: fnv_map::new() };
+    assert_eq!(open.check(None), Ok(None));
+    assert_eq!(open.check(Some("anything")), Ok(None));
+
+    let (token, grant) = parse_line("s3cret alice spectator").unwrap();
+    assert_eq!(grant, Grant { account: String::from("alice"), role: roles::Role::Guest });
+    let mut tokens = fnv_map::new();
+    tokens.insert(token, grant.clone());
+    let closed = T { tokens: tokens };
+    assert_eq!(closed.check(Some("s3cret")), Ok(Some(grant)));
+    assert_eq!(closed.check(Some("guess")), Err(protocol::ConnectionError::Unauthorized));
+    assert_eq!(closed.check(None), Err(protocol::ConnectionError::Unauthorized));
+
+    assert!(parse_line("s3cret alice").is_err());
+    assert!(parse_line("s3cret alice wizard").is_err());
+  }
+}
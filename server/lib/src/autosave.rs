@@ -0,0 +1,231 @@
+//! Saving the terrain every so often while the server runs, so a crash only loses the edits since the last save.
+//! Snapshots are taken on the thread that applies terrain edits, between edits and once no brush is partway through
+//! being applied, so every edit is either all in a snapshot or not in it at all. Each one is written out on a thread
+//! of its own, next to a checksum of its contents, and only the newest few are kept.
+//! A snapshot is a terrain file like any other: to restore one, copy it over `default.terrain`.
+
+use std;
+use std::sync::mpsc;
+use time;
+
+use replay;
+use terrain;
+use terrain_file;
+
+/// Where snapshots are kept.
+pub const DIRECTORY: &str = "autosaves";
+/// The default `Config::interval_ns`.
+pub const DEFAULT_INTERVAL_NS: u64 = 10 * 60 * 1_000_000_000;
+/// The default `Config::keep`.
+pub const DEFAULT_KEEP: usize = 5;
+
+const PREFIX: &str = "autosave-";
+const EXTENSION: &str = ".terrain";
+const CHECKSUM_EXTENSION: &str = ".checksum";
+
+/// How often to save, and how much to keep.
+#[derive(Debug, Clone, Copy)]
+pub struct Config {
+  /// How long after one snapshot to take the next.
+  pub interval_ns : u64,
+  /// How many snapshots to keep. Older ones are deleted once a new one's written.
+  pub keep        : usize,
+}
+
+#[allow(missing_docs)]
+pub fn default_config() -> Config {
+  Config {
+    interval_ns : DEFAULT_INTERVAL_NS,
+    keep        : DEFAULT_KEEP,
+  }
+}
+
+#[allow(missing_docs)]
+pub struct T {
+  config  : Config,
+  dir     : std::path::PathBuf,
+  /// When (in local ns) the next snapshot is due.
+  next_ns : u64,
+  /// Hears how the snapshot being written went, if one is.
+  writing : Option<mpsc::Receiver<Result<std::path::PathBuf, String>>>,
+}
+
+/// Start saving into `dir`, with the first snapshot due an interval after `now_ns`.
+pub fn new(config: Config, dir: std::path::PathBuf, now_ns: u64) -> T {
+  T {
+    config  : config,
+    dir     : dir,
+    next_ns : now_ns + config.interval_ns,
+    writing : None,
+  }
+}
+
+fn checksum_path(path: &std::path::Path) -> std::path::PathBuf {
+  let mut name = path.as_os_str().to_owned();
+  name.push(CHECKSUM_EXTENSION);
+  std::path::PathBuf::from(name)
+}
+
+fn checksum(bytes: &[u8]) -> String {
+  format!("{:016x}\n", replay::fnv(replay::FNV_OFFSET, bytes))
+}
+
+/// Write a snapshot to `dir` under `name`, then delete all but the newest `keep` snapshots there. The snapshot is
+/// written under a temporary name and moved into place once it and its checksum are both written, so a crash partway
+/// through never leaves a snapshot that looks whole. Returns where the snapshot went.
+fn write(dir: &std::path::Path, name: &str, bytes: &[u8], keep: usize) -> Result<std::path::PathBuf, String> {
+  try!(std::fs::create_dir_all(dir).map_err(|err| format!("Error creating {:?}: {:?}", dir, err)));
+  let path = dir.join(format!("{}{}{}", PREFIX, name, EXTENSION));
+  let partial = dir.join(format!("{}{}{}.partial", PREFIX, name, EXTENSION));
+  try!(write_file(&partial, bytes));
+  try!(write_file(&checksum_path(&path), checksum(bytes).as_bytes()));
+  try!(std::fs::rename(&partial, &path).map_err(|err| format!("Error renaming {:?}: {:?}", partial, err)));
+  try!(rotate(dir, keep));
+  Ok(path)
+}
+
+fn write_file(path: &std::path::Path, bytes: &[u8]) -> Result<(), String> {
+  use std::io::Write;
+  let mut file = try!(std::fs::File::create(path).map_err(|err| format!("Error creating {:?}: {:?}", path, err)));
+  file.write_all(bytes).map_err(|err| format!("Error writing {:?}: {:?}", path, err))
+}
+
+fn read_file(path: &std::path::Path) -> Result<Vec<u8>, String> {
+  use std::io::Read;
+  let mut bytes = Vec::new();
+  try!(
+    std::fs::File::open(path)
+    .and_then(|mut file| file.read_to_end(&mut bytes))
+    .map_err(|err| format!("Error reading {:?}: {:?}", path, err))
+  );
+  Ok(bytes)
+}
+
+/// The snapshots in `dir`, oldest first.
+fn snapshots(dir: &std::path::Path) -> Result<Vec<std::path::PathBuf>, String> {
+  let entries = try!(std::fs::read_dir(dir).map_err(|err| format!("Error reading {:?}: {:?}", dir, err)));
+  let mut snapshots = Vec::new();
+  for entry in entries {
+    let entry = try!(entry.map_err(|err| format!("Error reading {:?}: {:?}", dir, err)));
+    let is_snapshot =
+      entry.file_name().to_str().map_or(false, |name| name.starts_with(PREFIX) && name.ends_with(EXTENSION));
+    if is_snapshot {
+      snapshots.push(entry.path());
+    }
+  }
+  // Names are zero-padded times, so they sort oldest first.
+  snapshots.sort();
+  Ok(snapshots)
+}
+
+/// Delete all but the newest `keep` snapshots in `dir`, along with their checksums.
+fn rotate(dir: &std::path::Path, keep: usize) -> Result<(), String> {
+  let snapshots = try!(snapshots(dir));
+  let stale = snapshots.len().saturating_sub(keep);
+  for path in &snapshots[.. stale] {
+    info!("Deleting old autosave {:?}", path);
+    try!(std::fs::remove_file(path).map_err(|err| format!("Error deleting {:?}: {:?}", path, err)));
+    // An old snapshot without a checksum is still worth getting rid of.
+    let _ = std::fs::remove_file(checksum_path(path));
+  }
+  Ok(())
+}
+
+/// Check a snapshot against its checksum.
+pub fn verify(path: &std::path::Path) -> Result<(), String> {
+  let bytes = try!(read_file(path));
+  let expected = try!(read_file(&checksum_path(path)));
+  if checksum(&bytes).as_bytes() == &expected[..] {
+    Ok(())
+  } else {
+    Err(format!("{:?} doesn't match its checksum", path))
+  }
+}
+
+impl T {
+  /// Whether it's time for another snapshot. It isn't while the last one's still being written.
+  pub fn is_due(&mut self, now_ns: u64) -> bool {
+    if now_ns < self.next_ns {
+      return false
+    }
+    self.poll();
+    self.writing.is_none()
+  }
+
+  /// Take a snapshot of `terrain` and start writing it out. The terrain's only locked while it's serialized.
+  pub fn save(&mut self, terrain: &terrain::T, now_ns: u64) {
+    self.next_ns = now_ns + self.config.interval_ns;
+    let bytes =
+      match terrain_file::snapshot(terrain) {
+        Ok(bytes) => bytes,
+        Err(err) => {
+          warn!("Error taking an autosave snapshot: {}", err);
+          return
+        },
+      };
+    let name = format!("{:012}", time::get_time().sec);
+    let dir = self.dir.clone();
+    let keep = self.config.keep;
+    let (send, recv) = mpsc::channel();
+    std::thread::spawn(move || {
+      let _ = send.send(write(&dir, &name, &bytes, keep));
+    });
+    self.writing = Some(recv);
+  }
+
+  /// Wait for the snapshot being written, if there is one.
+  pub fn finish(&mut self) {
+    if let Some(recv) = self.writing.take() {
+      report(recv.recv().unwrap_or_else(|_| Err(String::from("Autosave thread panicked"))));
+    }
+  }
+
+  /// Check whether the snapshot being written is done.
+  fn poll(&mut self) {
+    let result =
+      match self.writing {
+        None => return,
+        Some(ref recv) =>
+          match recv.try_recv() {
+            Ok(result) => result,
+            Err(mpsc::TryRecvError::Empty) => return,
+            Err(mpsc::TryRecvError::Disconnected) => Err(String::from("Autosave thread panicked")),
+          },
+      };
+    self.writing = None;
+    report(result);
+  }
+}
+
+fn report(result: Result<std::path::PathBuf, String>) {
+  match result {
+    Ok(path) => info!("Autosaved terrain to {:?}", path),
+    Err(err) => warn!("Error autosaving terrain: {}", err),
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use std;
+
+  use super::*;
+
+  #[test]
+  fn test_write() {
+    let dir = std::env::temp_dir().join("playform_autosave_test");
+    let _ = std::fs::remove_dir_all(&dir);
+
+    let mut paths = Vec::new();
+    for i in 0 .. 4 {
+      paths.push(write(&dir, &format!("{:012}", i), &[i as u8; 16], 2).unwrap());
+    }
+    assert_eq!(snapshots(&dir).unwrap(), &paths[2 ..]);
+    assert!(!checksum_path(&paths[0]).exists());
+    assert!(verify(&paths[3]).is_ok());
+
+    write_file(&paths[3], &[0; 16]).unwrap();
+    assert!(verify(&paths[3]).is_err());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+}
@@ -0,0 +1,69 @@
+//! Beacons mark meeting points in the world. Every client is kept up to date on where they all are.
+
+use cgmath::Point3;
+
+use common::entity;
+use common::fnv_map;
+use common::id_allocator;
+use common::protocol;
+
+use server;
+use server::Client;
+
+#[allow(missing_docs)]
+pub struct T {
+  positions : fnv_map::T<entity::id::Beacon, Point3<f32>>,
+  allocator : id_allocator::T<entity::id::Beacon>,
+}
+
+#[allow(missing_docs)]
+pub fn new() -> T {
+  T {
+    positions : fnv_map::new(),
+    allocator : id_allocator::new(),
+  }
+}
+
+fn broadcast(server: &server::T, msg: protocol::ServerToClient) {
+  for (_, client) in server.clients.lock().unwrap().iter_mut() {
+    client.send(msg.clone());
+  }
+}
+
+/// Put a beacon at `position`, and tell every client about it.
+pub fn place(server: &server::T, position: Point3<f32>) -> Result<entity::id::Beacon, String> {
+  let id = {
+    let mut beacons = server.beacons.lock().unwrap();
+    if beacons.positions.len() >= protocol::MAX_BEACONS {
+      return Err(format!("There can only be {} beacons at once", protocol::MAX_BEACONS))
+    }
+    let id = beacons.allocator.allocate();
+    beacons.positions.insert(id, position);
+    id
+  };
+  broadcast(server, protocol::ServerToClient::UpdateBeacon(id, position));
+  Ok(id)
+}
+
+/// Take down a beacon (by id), and tell every client it's gone.
+pub fn remove(server: &server::T, id: u32) -> Result<(), String> {
+  let beacon = {
+    let mut beacons = server.beacons.lock().unwrap();
+    let beacon =
+      match beacons.positions.keys().find(|beacon| beacon.to_u32() == id) {
+        None => return Err(format!("No beacon with id {}", id)),
+        Some(&beacon) => beacon,
+      };
+    beacons.positions.remove(&beacon);
+    beacon
+  };
+  broadcast(server, protocol::ServerToClient::RemoveBeacon(beacon));
+  Ok(())
+}
+
+/// Tell a newly-admitted client where all the beacons are.
+pub fn send_all(server: &server::T, client: &mut Client) {
+  for (&id, &position) in &server.beacons.lock().unwrap().positions {
+    client.send(protocol::ServerToClient::UpdateBeacon(id, position));
+  }
+}
@@ -0,0 +1,64 @@
+//! Measure terrain generation throughput without running a server or connecting a client.
+
+use stopwatch;
+use time;
+
+use common::voxel;
+
+use terrain;
+
+/// Width of a block, in voxels. This matches the size of the chunks clients request.
+const BLOCK_WIDTH: i32 = 8;
+/// The voxel sizes to generate at, from most to least detailed.
+const LG_SIZES: [i16; 5] = [0, 1, 2, 3, 4];
+
+/// Generate an `n` x `n` grid of blocks, two blocks tall and straddling `y = 0`, at each LOD.
+/// Print how quickly each LOD generated, then where the time went.
+pub fn run(n: u32) {
+  let n = n as i32;
+  let blocks = n * n * 2;
+  let voxels_per_block = BLOCK_WIDTH * BLOCK_WIDTH * BLOCK_WIDTH;
+
+  println!("Generating {}x{}x2 blocks of {}^3 voxels at each LOD", n, n, BLOCK_WIDTH);
+
+  for &lg_size in &LG_SIZES {
+    // Start from scratch every time, so no LOD gets to reuse another's work.
+    let terrain = terrain::T::new(terrain::Seed::new(0));
+
+    let start = time::precise_time_ns();
+    stopwatch::time("bench_terrain", || {
+      for bx in -n/2 .. n - n/2 {
+        for by in -1 .. 1 {
+          for bz in -n/2 .. n - n/2 {
+            for x in 0 .. BLOCK_WIDTH {
+              for y in 0 .. BLOCK_WIDTH {
+                for z in 0 .. BLOCK_WIDTH {
+                  let bounds =
+                    voxel::bounds::new(
+                      bx * BLOCK_WIDTH + x,
+                      by * BLOCK_WIDTH + y,
+                      bz * BLOCK_WIDTH + z,
+                      lg_size,
+                    );
+                  terrain.load(&bounds);
+                }
+              }
+            }
+          }
+        }
+      }
+    });
+    let seconds = (time::precise_time_ns() - start) as f64 / 1e9;
+
+    println!(
+      "lg_size {}: {} blocks in {:.3}s; {:.1} blocks/s, {:.0} voxels/s",
+      lg_size,
+      blocks,
+      seconds,
+      blocks as f64 / seconds,
+      (blocks * voxels_per_block) as f64 / seconds,
+    );
+  }
+
+  stopwatch::clone().print();
+}
@@ -0,0 +1,80 @@
+//! Explosions dig out a sphere of terrain, except for whatever can withstand them. A voxel's blast resistance is its
+//! material's, plus any reinforcement where it is.
+
+use cgmath::{Point3, InnerSpace};
+use collision::Aabb3;
+
+use common::fnv_set;
+use common::voxel;
+
+use brush;
+use terrain;
+
+/// The voxels of lg_size 0 whose centers are within `radius` of `center`.
+fn voxels_within(center: &Point3<f32>, radius: f32) -> Vec<voxel::bounds::T> {
+  let region = region(center, radius);
+  let mut voxels = Vec::new();
+  for x in region.min.x .. region.max.x {
+  for y in region.min.y .. region.max.y {
+  for z in region.min.z .. region.max.z {
+    let bounds = voxel::bounds::new(x, y, z, 0);
+    if (bounds.center() - center).magnitude() <= radius {
+      voxels.push(bounds);
+    }
+  }}}
+  voxels
+}
+
+/// The region of smallest voxels a sphere can touch.
+pub fn region(center: &Point3<f32>, radius: f32) -> Aabb3<i32> {
+  let low = |x: f32| (x - radius).floor() as i32;
+  let high = |x: f32| (x + radius).ceil() as i32;
+  Aabb3::new(
+    Point3::new(low(center.x), low(center.y), low(center.z)),
+    Point3::new(high(center.x), high(center.y), high(center.z)),
+  )
+}
+
+/// How powerful an explosion it takes to destroy a voxel of lg_size 0.
+pub fn resistance(terrain: &terrain::T, bounds: &voxel::bounds::T) -> f32 {
+  let material =
+    match terrain.load(bounds) {
+      voxel::Volume(material) => material,
+      voxel::Surface(vertex) => vertex.corner,
+    };
+  material.blast_resistance() + terrain.reinforcement.lock().unwrap().resistance(bounds)
+}
+
+/// A brush for an explosion of some radius at `center`. An explosion's power is its radius; voxels that resist at
+/// least that much are left standing.
+pub fn explosion(terrain: &terrain::T, center: &Point3<f32>, radius: f32) -> brush::T {
+  let mut kept = fnv_set::new();
+  // Voxels just outside the radius can still be touched by the brush.
+  for bounds in voxels_within(center, radius + 1.0) {
+    if resistance(terrain, &bounds) >= radius {
+      kept.insert(Point3::new(bounds.x, bounds.y, bounds.z));
+    }
+  }
+  brush::explosion(center, radius, kept)
+}
+
+/// Reinforce the voxels within `radius` of `center` one more level. Returns how many got stronger.
+pub fn reinforce(terrain: &terrain::T, center: &Point3<f32>, radius: f32) -> usize {
+  terrain.reinforcement.lock().unwrap().reinforce(&voxels_within(center, radius))
+}
+
+#[cfg(test)]
+mod test {
+  use cgmath::Point3;
+
+  use super::*;
+
+  #[test]
+  fn test_voxels_within() {
+    assert_eq!(voxels_within(&Point3::new(0.5, 0.5, 0.5), 0.5).len(), 1);
+    // A voxel and its six neighbors.
+    assert_eq!(voxels_within(&Point3::new(0.5, 0.5, 0.5), 1.0).len(), 7);
+    let b = region(&Point3::new(0.5, 0.5, 0.5), 1.0);
+    assert_eq!((b.min, b.max), (Point3::new(-1, -1, -1), Point3::new(2, 2, 2)));
+  }
+}
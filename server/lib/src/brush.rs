@@ -0,0 +1,176 @@
+//! Brushes for editing terrain.
+
+use cgmath::{Point3, Vector3, EuclideanSpace};
+use collision::{Aabb3};
+
+use common::fnv_set;
+use common::protocol;
+use common::voxel;
+
+use voxel_data;
+
+#[allow(missing_docs)]
+pub type T = voxel_data::brush::T<Box<voxel_data::mosaic::T<voxel::Material> + Send>>;
+
+mod cube {
+  use cgmath::{Point3, Vector3};
+
+  use voxel_data::field;
+
+  /// An axis-aligned cube centered at the origin, with sides `2 * radius` long.
+  pub struct T {
+    pub radius: f32,
+  }
+
+  impl field::T for T {
+    fn density(&mut self, p: &Point3<f32>) -> f32 {
+      let d = f32::max(p.x.abs(), f32::max(p.y.abs(), p.z.abs()));
+      self.radius*self.radius - d*d
+    }
+
+    fn normal(&mut self, p: &Point3<f32>) -> Vector3<f32> {
+      // Point out of whichever face is nearest.
+      let (x, y, z) = (p.x.abs(), p.y.abs(), p.z.abs());
+      if x >= y && x >= z {
+        Vector3::new(p.x.signum(), 0.0, 0.0)
+      } else if y >= z {
+        Vector3::new(0.0, p.y.signum(), 0.0)
+      } else {
+        Vector3::new(0.0, 0.0, p.z.signum())
+      }
+    }
+  }
+}
+
+mod cylinder {
+  use cgmath::{Point3, Vector3, InnerSpace};
+
+  use voxel_data::field;
+
+  /// An upright cylinder centered at the origin, `2 * radius` wide and `2 * half_height` tall.
+  pub struct T {
+    pub radius: f32,
+    pub half_height: f32,
+  }
+
+  impl T {
+    fn side_density(&self, p: &Point3<f32>) -> f32 {
+      self.radius*self.radius - (p.x*p.x + p.z*p.z)
+    }
+
+    fn cap_density(&self, p: &Point3<f32>) -> f32 {
+      self.half_height*self.half_height - p.y*p.y
+    }
+  }
+
+  impl field::T for T {
+    fn density(&mut self, p: &Point3<f32>) -> f32 {
+      f32::min(self.side_density(p), self.cap_density(p))
+    }
+
+    fn normal(&mut self, p: &Point3<f32>) -> Vector3<f32> {
+      if self.cap_density(p) < self.side_density(p) {
+        Vector3::new(0.0, p.y.signum(), 0.0)
+      } else {
+        Vector3::new(p.x, 0.0, p.z).normalize()
+      }
+    }
+  }
+}
+
+mod explosion {
+  use cgmath::{Point3, Vector3, EuclideanSpace, InnerSpace};
+
+  use common::fnv_set;
+  use voxel_data::field;
+
+  /// A sphere centered at the origin, with holes in it wherever some of the smallest voxels are kept.
+  pub struct T {
+    pub radius : f32,
+    /// Where the origin is, in the world.
+    pub center : Point3<f32>,
+    /// The low corners of the voxels (of lg_size 0) to leave alone.
+    pub kept   : fnv_set::T<Point3<i32>>,
+  }
+
+  impl field::T for T {
+    fn density(&mut self, p: &Point3<f32>) -> f32 {
+      let world = self.center + p.to_vec();
+      let voxel = Point3::new(world.x.floor() as i32, world.y.floor() as i32, world.z.floor() as i32);
+      if self.kept.contains(&voxel) {
+        -1.0
+      } else {
+        self.radius*self.radius - p.to_vec().magnitude2()
+      }
+    }
+
+    fn normal(&mut self, p: &Point3<f32>) -> Vector3<f32> {
+      p.to_vec().normalize()
+    }
+  }
+}
+
+/// A brush that applies `mosaic`, centered at `center`. Everything in `mosaic` must be within `radius` of the origin
+/// along each axis.
+pub fn of_mosaic<Mosaic>(center: &Point3<f32>, radius: f32, mosaic: Mosaic) -> T
+  where Mosaic: voxel_data::mosaic::T<voxel::Material> + Send + 'static,
+{
+  let mosaic =
+    voxel_data::mosaic::translation::T {
+      translation: center.to_vec(),
+      mosaic: mosaic,
+    };
+  let r = radius + 1.0;
+  voxel_data::brush::T {
+    bounds:
+      Aabb3::new(
+        {
+          let low = mosaic.translation + (&-Vector3::new(r, r, r));
+          Point3::new(low.x.floor() as i32, low.y.floor() as i32, low.z.floor() as i32)
+        },
+        {
+          let high = mosaic.translation + (&Vector3::new(r, r, r));
+          Point3::new(high.x.ceil() as i32, high.y.ceil() as i32, high.z.ceil() as i32)
+        },
+      ),
+    mosaic: Box::new(mosaic) as Box<voxel_data::mosaic::T<voxel::Material> + Send>,
+    min_lg_size: 0,
+  }
+}
+
+/// A brush that fills `field`, centered at `center`, with some material. Everything in `field` must be within
+/// `radius` of the origin along each axis.
+fn solid<Field>(center: &Point3<f32>, radius: f32, field: Field, material: voxel::Material) -> T
+  where Field: voxel_data::field::T + Send + 'static,
+{
+  of_mosaic(center, radius, voxel_data::mosaic::solid::T { field: field, material: material })
+}
+
+/// A brush that fills a sphere with some material (which may be `Empty`, to dig it out).
+pub fn sphere(center: &Point3<f32>, radius: f32, material: voxel::Material) -> T {
+  solid(center, radius, voxel_data::field::sphere::T { radius: radius }, material)
+}
+
+/// A brush that digs out a sphere, except for the voxels of lg_size 0 whose low corners are in `kept`.
+pub fn explosion(center: &Point3<f32>, radius: f32, kept: fnv_set::T<Point3<i32>>) -> T {
+  let field = explosion::T { radius: radius, center: *center, kept: kept };
+  solid(center, radius, field, voxel::Material::Empty)
+}
+
+/// A brush that fills a shape of some radius around `center` with some material.
+pub fn of_shape(center: &Point3<f32>, shape: protocol::BrushShape, radius: f32, material: voxel::Material) -> T {
+  match shape {
+    protocol::BrushShape::Sphere => sphere(center, radius, material),
+    protocol::BrushShape::Cube => solid(center, radius, cube::T { radius: radius }, material),
+    protocol::BrushShape::Cylinder => {
+      solid(center, radius, cylinder::T { radius: radius, half_height: radius }, material)
+    },
+  }
+}
+
+/// A brush that fills a flat disc, `radius` wide and `thickness` thick, with some material.
+pub fn pad(center: &Point3<f32>, radius: f32, thickness: f32, material: voxel::Material) -> T {
+  let half_height = thickness / 2.0;
+  let field = cylinder::T { radius: radius, half_height: half_height };
+  solid(center, f32::max(radius, half_height), field, material)
+}
@@ -0,0 +1,52 @@
+//! Tokens for calling off work that's been queued but not done yet. Whoever queued the work keeps a clone of the
+//! token and cancels it once the work's no longer wanted; whoever does the work checks it along the way.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+#[allow(missing_docs)]
+#[derive(Debug, Clone)]
+pub struct T {
+  cancelled: Arc<AtomicBool>,
+}
+
+#[allow(missing_docs)]
+pub fn new() -> T {
+  T {
+    cancelled: Arc::new(AtomicBool::new(false)),
+  }
+}
+
+impl T {
+  /// Call off the work this token was handed out with, and everything else sharing it.
+  pub fn cancel(&self) {
+    self.cancelled.store(true, Ordering::Relaxed);
+  }
+
+  #[allow(missing_docs)]
+  pub fn is_cancelled(&self) -> bool {
+    self.cancelled.load(Ordering::Relaxed)
+  }
+
+  /// Whether two tokens are clones of each other.
+  pub fn same(&self, other: &T) -> bool {
+    Arc::ptr_eq(&self.cancelled, &other.cancelled)
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_cancel() {
+    let token = new();
+    let clone = token.clone();
+    let other = new();
+    assert!(token.same(&clone));
+    assert!(!token.same(&other));
+    clone.cancel();
+    assert!(token.is_cancelled());
+    assert!(!other.is_cancelled());
+  }
+}
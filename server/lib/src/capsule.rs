@@ -0,0 +1,105 @@
+//! Vertical capsule colliders, fitted inside an entity's bounding box.
+//! The rounded ends let capsules slide over small lips and around corners that an AABB would catch on.
+
+use collision::{Aabb3};
+
+/// A vertical capsule: every point within `radius` of the segment from `(x, low_y, z)` to `(x, high_y, z)`.
+#[derive(Debug, Clone, Copy)]
+pub struct T {
+  pub x      : f32,
+  pub z      : f32,
+  pub low_y  : f32,
+  pub high_y : f32,
+  pub radius : f32,
+}
+
+/// The largest vertical capsule that fits inside some bounds.
+pub fn of_bounds(bounds: &Aabb3<f32>) -> T {
+  let radius =
+    f32::min(
+      (bounds.max.x - bounds.min.x) / 2.0,
+      f32::min(
+        (bounds.max.z - bounds.min.z) / 2.0,
+        (bounds.max.y - bounds.min.y) / 2.0,
+      ),
+    );
+  T {
+    x      : (bounds.min.x + bounds.max.x) / 2.0,
+    z      : (bounds.min.z + bounds.max.z) / 2.0,
+    low_y  : bounds.min.y + radius,
+    high_y : bounds.max.y - radius,
+    radius : radius,
+  }
+}
+
+// Distance from a value to the interval [low, high].
+fn distance_to_interval(v: f32, low: f32, high: f32) -> f32 {
+  if v < low {
+    low - v
+  } else if v > high {
+    v - high
+  } else {
+    0.0
+  }
+}
+
+impl T {
+  /// Does this capsule overlap a box (e.g. a voxel)? Touching doesn't count.
+  pub fn intersects(&self, aabb: &Aabb3<f32>) -> bool {
+    // The segment is axis-aligned, so the closest points can be found one axis at a time.
+    let dx = distance_to_interval(self.x, aabb.min.x, aabb.max.x);
+    let dz = distance_to_interval(self.z, aabb.min.z, aabb.max.z);
+    let dy =
+      if self.high_y < aabb.min.y {
+        aabb.min.y - self.high_y
+      } else if self.low_y > aabb.max.y {
+        self.low_y - aabb.max.y
+      } else {
+        0.0
+      };
+    dx * dx + dy * dy + dz * dz < self.radius * self.radius
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use cgmath::Point3;
+  use collision::Aabb3;
+
+  use super::*;
+
+  fn player() -> T {
+    of_bounds(&Aabb3::new(Point3::new(0.0, 0.0, 0.0), Point3::new(1.0, 2.0, 1.0)))
+  }
+
+  fn voxel(x: f32, y: f32, z: f32) -> Aabb3<f32> {
+    Aabb3::new(Point3::new(x, y, z), Point3::new(x + 1.0, y + 1.0, z + 1.0))
+  }
+
+  #[test]
+  fn test_fitted() {
+    let c = player();
+    assert_eq!(c.radius, 0.5);
+    assert_eq!((c.x, c.z), (0.5, 0.5));
+    assert_eq!((c.low_y, c.high_y), (0.5, 1.5));
+  }
+
+  #[test]
+  fn test_side_overlap() {
+    assert!(player().intersects(&voxel(0.75, 0.5, 0.0)));
+    assert!(!player().intersects(&voxel(1.0, 0.5, 0.0)));
+  }
+
+  #[test]
+  // A box that would catch the corner of the player's AABB misses the rounded capsule.
+  fn test_corner_miss() {
+    assert!(!player().intersects(&voxel(0.9, -0.9, 0.9)));
+    assert!(!player().intersects(&voxel(-0.9, 1.9, -0.9)));
+  }
+
+  #[test]
+  fn test_below() {
+    assert!(player().intersects(&voxel(0.0, -0.9, 0.0)));
+    assert!(!player().intersects(&voxel(0.0, -1.0, 0.0)));
+  }
+}
@@ -1,240 +1,578 @@
-use cgmath::{Point3, Vector3, EuclideanSpace};
-use collision::{Aabb3};
+use cgmath::Vector3;
+use collision::Ray3;
 use rand;
-use rand::distributions::IndependentSample;
 use std::convert::AsRef;
 use std::f32::consts::PI;
-use std::ops::DerefMut;
 use std::time::Duration;
 use stopwatch;
+use time;
 
+use common::fnv_set;
+use common::math;
 use common::protocol;
+use common::protocol::EntityRef;
 use common::socket::SendSocket;
 use common::voxel;
 
+use afk;
+use command;
+use edit_permissions;
 use entity;
+use error;
+use hooks;
+use join_queue;
+use mining;
 use player;
+use projectile;
+use rate_limit;
+use roles;
 use server;
 use server::Client;
-use terrain;
-use voxel_data;
+use terrain_policy;
 use update_gaia;
 use update_gaia::LoadDestination;
+use webhooks;
 
-fn center(bounds: &Aabb3<f32>) -> Point3<f32> {
-  (bounds.min + bounds.max.to_vec()) * 0.5
+/// Find the voxel a player is looking at, and its material.
+pub fn cast(
+  server: &server::T,
+  player_id: entity::id::Player,
+) -> Option<(voxel::bounds::T, voxel::Material)> {
+  let ray;
+  {
+    let players = server.players.lock().unwrap();
+    let player =
+      match players.get(&player_id) {
+        None => return None,
+        Some(player) => player,
+      };
+    ray = player.forward_ray();
+  }
+
+  cast_ray(server, &ray)
 }
 
-fn cast(
+/// Find the voxel a player aimed at, and its material. If the aim can't be trusted, this is what the player's looking
+/// at now.
+pub fn cast_aimed(
   server: &server::T,
   player_id: entity::id::Player,
-) -> Option<voxel::bounds::T> {
+  aim: &protocol::Aim,
+) -> Option<(voxel::bounds::T, voxel::Material)> {
   let ray;
   {
     let players = server.players.lock().unwrap();
-    let player = players.get(&player_id).unwrap();
-    ray = player.forward_ray();
+    let player =
+      match players.get(&player_id) {
+        None => return None,
+        Some(player) => player,
+      };
+    ray = player.aim_ray(aim);
   }
 
-  server.terrain_loader.terrain.voxels.lock().unwrap().cast_ray(
-    &ray,
-    &mut |bounds, voxel| {
-      match voxel {
-        &voxel::Volume(voxel::Material::Empty) => None,
-        _ => Some(bounds),
+  cast_ray(server, &ray)
+}
+
+/// What a voxel's solid part is made of. A surface voxel only records the material at its low corner, which is
+/// `Empty` whenever that corner's on the outside of the surface; then it's made of whatever's at one of its other
+/// corners, which are the low corners of its neighbors.
+fn solid_material(voxels: &voxel::tree::T, bounds: &voxel::bounds::T, voxel: &voxel::T) -> voxel::Material {
+  let corner =
+    match *voxel {
+      voxel::Volume(material) => return material,
+      voxel::Surface(ref vertex) => vertex.corner,
+    };
+  if corner != voxel::Material::Empty {
+    return corner
+  }
+  for &(dx, dy, dz) in &[(1, 0, 0), (0, 1, 0), (0, 0, 1), (1, 1, 0), (1, 0, 1), (0, 1, 1), (1, 1, 1)] {
+    let neighbor = voxel::bounds::new(bounds.x + dx, bounds.y + dy, bounds.z + dz, bounds.lg_size);
+    let corner =
+      match voxels.get(&neighbor) {
+        None => continue,
+        Some(&voxel::Volume(material)) => material,
+        Some(&voxel::Surface(ref vertex)) => vertex.corner,
+      };
+    if corner != voxel::Material::Empty {
+      return corner
+    }
+  }
+  // None of its neighbors are loaded at the same size; it's most likely the ground.
+  voxel::Material::Terrain
+}
+
+/// Find the first solid voxel along a ray, and its material.
+pub fn cast_ray(server: &server::T, ray: &Ray3<f32>) -> Option<(voxel::bounds::T, voxel::Material)> {
+  let voxels = server.terrain_loader.terrain.voxels.lock().unwrap();
+  let hit =
+    voxels.cast_ray(
+      ray,
+      &mut |bounds, voxel| {
+        match voxel {
+          &voxel::Volume(voxel::Material::Empty) => None,
+          &voxel::Volume(_) | &voxel::Surface(_) => Some((bounds, *voxel)),
+        }
       }
+    );
+  hit.map(|(bounds, voxel)| (bounds, solid_material(&voxels, &bounds, &voxel)))
+}
+
+/// Tell a client that it can't connect, and why.
+pub fn reject(client_url: &str, err: protocol::ConnectionError) {
+  use bincode;
+
+  warn!("Rejecting {}: {}", client_url, err);
+  let msg =
+    match bincode::serialize(&protocol::ServerToClient::Rejected(err), bincode::Infinite) {
+      Err(err) => {
+        warn!("Error rejecting {}: {:?}", client_url, err);
+        return
+      },
+      Ok(msg) => msg,
+    };
+  let mut socket =
+    match SendSocket::connect(client_url, Some(Duration::from_secs(30))) {
+      Err(err) => {
+        warn!("Error rejecting {}: {:?}", client_url, err);
+        return
+      },
+      Ok(socket) => socket,
+    };
+  if let Err(err) = socket.write(msg.as_ref()) {
+    warn!("Error rejecting {}: {:?}", client_url, err);
+  }
+}
+
+/// Charge a client `cost` tokens for an expensive message. Returns whether to go ahead with it. Clients that are over
+/// budget too often are disconnected.
+fn within_budget(server: &server::T, client_id: protocol::ClientId, kind: rate_limit::Kind, cost: usize) -> bool {
+  let budgets = *server.rate_budgets.lock().unwrap();
+  let outcome = {
+    let mut clients = server.clients.lock().unwrap();
+    let client =
+      match clients.get_mut(&client_id) {
+        None => return false,
+        Some(client) => client,
+      };
+    let outcome = client.rate_limits.charge_n(&budgets, kind, cost, time::precise_time_ns());
+    if outcome == rate_limit::Outcome::Kick {
+      client.send(protocol::ServerToClient::Kicked(String::from("Too many requests")));
     }
-  )
+    outcome
+  };
+  match outcome {
+    rate_limit::Outcome::Allow => true,
+    rate_limit::Outcome::Drop => {
+      debug!("Client {:?} is over its {:?} budget", client_id, kind);
+      false
+    },
+    rate_limit::Outcome::Kick => {
+      warn!("Disconnecting client {:?} for going over its {:?} budget too often", client_id, kind);
+      join_queue::leave(server, client_id);
+      false
+    },
+  }
+}
+
+fn player_within_budget(server: &server::T, player_id: entity::id::Player, kind: rate_limit::Kind) -> bool {
+  let client_id =
+    match server.components.controller(EntityRef::Player(player_id)) {
+      None => return false,
+      Some(client_id) => client_id,
+    };
+  within_budget(server, client_id, kind, 1)
+}
+
+/// Guests can't edit terrain at all; they're told so, instead of having their edits go to gaia.
+fn can_edit(server: &server::T, player_id: entity::id::Player) -> bool {
+  if roles::of_player(server, player_id) != roles::Role::Guest {
+    return true
+  }
+  let client_id = server.components.controller(EntityRef::Player(player_id));
+  if let Some(client_id) = client_id {
+    if let Some(client) = server.clients.lock().unwrap().get_mut(&client_id) {
+      client.send(protocol::ServerToClient::EditDenied(String::from("Guests can't edit terrain")));
+    }
+  }
+  false
+}
+
+/// The client a request is on behalf of: the one it names, or the one controlling the player it names.
+pub fn sender_of(server: &server::T, request: &protocol::Request) -> error::Result<protocol::ClientId> {
+  match *request {
+    protocol::Request::Ping(client_id) |
+    protocol::Request::AddPlayer(client_id) |
+    protocol::Request::RequestVoxels { client_id, .. } |
+    protocol::Request::WatchPhysics(client_id, _) |
+    protocol::Request::Leave(client_id) => Ok(client_id),
+    protocol::Request::Walk(player_id, _) |
+    protocol::Request::RotatePlayer(player_id, _) |
+    protocol::Request::StartJump(player_id) |
+    protocol::Request::StopJump(player_id) |
+    protocol::Request::Sprint(player_id, _) |
+    protocol::Request::Add(player_id, _) |
+    protocol::Request::AddRock(player_id, _) |
+    protocol::Request::Throw(player_id, _) |
+    protocol::Request::StartRemove(player_id, _) |
+    protocol::Request::StopRemove(player_id) |
+    protocol::Request::SetBrush(player_id, _, _) |
+    protocol::Request::Command(player_id, _) =>
+      server.components.controller(EntityRef::Player(player_id)).ok_or(error::T::Uncontrolled(player_id)),
+  }
+}
+
+/// Check that a request came with the secret of the client it's on behalf of.
+fn authenticate(server: &server::T, secret: protocol::Secret, request: &protocol::Request) -> error::Result<()> {
+  let client_id = try!(sender_of(server, request));
+  let clients = server.clients.lock().unwrap();
+  let client = try!(clients.get(&client_id).ok_or(error::T::UnknownClient(client_id)));
+  if client.secret == secret {
+    Ok(())
+  } else {
+    Err(error::T::Forged(client_id))
+  }
+}
+
+fn new_secret() -> protocol::Secret {
+  use rand::Rng;
+  match rand::OsRng::new() {
+    Ok(mut rng) => rng.gen(),
+    Err(err) => {
+      warn!("Couldn't get a secret from the OS ({:?}); using the thread RNG", err);
+      rand::random()
+    },
+  }
+}
+
+/// Let a new client in (or put it in line), if it speaks our protocol and has a token we accept (when one's needed).
+fn init(
+  server: &server::T,
+  version: u32,
+  client_url: String,
+  account: String,
+  token: Option<String>,
+  capabilities: protocol::Capabilities,
+) -> error::Result<()> {
+  if version != protocol::VERSION {
+    let err = protocol::ConnectionError::VersionMismatch { server: protocol::VERSION, client: version };
+    reject(&client_url, err);
+    return Ok(())
+  }
+  let (account, token_role) =
+    match server.auth.check(token.as_ref().map(|token| token.as_str())) {
+      Err(err) => {
+        reject(&client_url, err);
+        return Ok(())
+      },
+      Ok(None) => (account, None),
+      Ok(Some(grant)) => {
+        if grant.account != account {
+          info!("{} claimed to be {}, but its token is for {}", client_url, account, grant.account);
+        }
+        (grant.account, Some(grant.role))
+      },
+    };
+  info!("Sending to {} ({}).", client_url, account);
+
+  let terrain_policy = terrain_policy::negotiate(&capabilities, &server.distances);
+  info!("Client can handle {:?}; giving it {:?}", capabilities, terrain_policy);
+
+  let socket =
+    try!(
+      SendSocket::connect(client_url.as_ref(), Some(Duration::from_secs(30)))
+      .map_err(|err| error::T::Connect(client_url.clone(), format!("{:?}", err)))
+    );
+  let client =
+    Client {
+      socket: socket,
+      blocks: fnv_set::new(),
+      last_unload_position: None,
+      terrain_policy: terrain_policy,
+      account: account,
+      token_role: token_role,
+      rate_limits: rate_limit::new(),
+      last_input_ns: time::precise_time_ns(),
+      afk: false,
+      watching_physics: None,
+      failure: None,
+      secret: new_secret(),
+    };
+  join_queue::join(server, client);
+  Ok(())
 }
 
+/// Act on a message from a client. Errors are about the client (e.g. it's already gone, or the message didn't come
+/// with its secret), not the server.
 pub fn apply_client_update<UpdateGaia>(
   server: &server::T,
   update_gaia: &mut UpdateGaia,
   update: protocol::ClientToServer,
-) where
+) -> error::Result<()> where
   UpdateGaia: FnMut(update_gaia::Message),
 {
   stopwatch::time("apply_client_update", move || {
-    match update {
-      protocol::ClientToServer::Init(client_url) => {
-        info!("Sending to {}.", client_url);
+    let request =
+      match update {
+        protocol::ClientToServer::Init(version, client_url, account, token, capabilities) =>
+          return init(server, version, client_url, account, token, capabilities),
+        protocol::ClientToServer::Request(secret, request) => {
+          try!(authenticate(server, secret, &request));
+          request
+        },
+        protocol::ClientToServer::StillWaiting(secret) => {
+          join_queue::still_waiting(server, secret);
+          return Ok(())
+        },
+      };
 
-        let mut client =
-          Client {
-            socket: SendSocket::new(client_url.as_ref(), Some(Duration::from_secs(30))),
-          };
-
-        let client_id = server.client_allocator.lock().unwrap().allocate();
-        client.send(protocol::ServerToClient::LeaseId(client_id));
+    if let Some(player_id) = afk::input_from(&request) {
+      afk::input(server, player_id, time::precise_time_ns());
+    }
 
-        server.clients.lock().unwrap().insert(client_id, client);
+    match request {
+      protocol::Request::Leave(client_id) => {
+        join_queue::leave(server, client_id);
       },
-      protocol::ClientToServer::Ping(client_id) => {
-        server.clients.lock().unwrap()
-          .get_mut(&client_id)
-          .unwrap()
-          .send(protocol::ServerToClient::Ping);
+      protocol::Request::WatchPhysics(client_id, watch) => {
+        let mut clients = server.clients.lock().unwrap();
+        let client = try!(clients.get_mut(&client_id).ok_or(error::T::UnknownClient(client_id)));
+        client.watching_physics = if watch { Some(0) } else { None };
+      },
+      protocol::Request::Ping(client_id) => {
+        if let Some(client) = server.clients.lock().unwrap().get_mut(&client_id) {
+          client.send(protocol::ServerToClient::Ping);
+        }
       },
-      protocol::ClientToServer::AddPlayer(client_id) => {
+      protocol::Request::AddPlayer(client_id) => {
+        if !server.clients.lock().unwrap().contains_key(&client_id) {
+          return Err(error::T::UnknownClient(client_id))
+        }
         let mut player =
           player::new(
             server.player_allocator.lock().unwrap().allocate(),
             server.misc_allocator.lock().unwrap().allocate(),
+            client_id,
             &server.owner_allocator,
           );
 
         // TODO: shift upward until outside terrain
-        let min = Point3::new(0.0, 64.0, 4.0);
-        let max = min + (&Vector3::new(1.0, 2.0, 1.0));
-        let bounds = Aabb3::new(min, max);
-        server.physics.lock().unwrap().insert_misc(player.physics_id, &bounds);
+        let bounds = player::bounds_at(player::SPAWN_POINT);
+        server.physics.lock().unwrap().insert_misc_capsule(player.physics_id, &bounds);
 
-        player.position = center(&bounds);
+        player.position = math::center(&bounds);
         player.rotate_lateral(PI / 2.0);
 
         let id = player.entity_id;
         let pos = player.position;
 
         server.players.lock().unwrap().insert(id, player);
+        server.components.moved(EntityRef::Player(id), &bounds, Vector3::new(0.0, 0.0, 0.0));
+        server.components.controllers.lock().unwrap().insert(EntityRef::Player(id), client_id);
 
-        let mut clients = server.clients.lock().unwrap();
-        let client = clients.get_mut(&client_id).unwrap();
-        client.send(
-          protocol::ServerToClient::PlayerAdded(id, pos)
+        let account = {
+          let weather = server.weather.lock().unwrap().current;
+          let mut clients = server.clients.lock().unwrap();
+          let client = try!(clients.get_mut(&client_id).ok_or(error::T::UnknownClient(client_id)));
+          client.send(
+            protocol::ServerToClient::PlayerAdded(id, pos)
+          );
+          // Anything sent before the player's added doesn't reach the view.
+          client.send(protocol::ServerToClient::UpdateWeather(weather));
+          client.account.clone()
+        };
+
+        webhooks::fire(
+          server,
+          webhooks::Event::Join,
+          &[("player", webhooks::Value::Int(id.to_u32() as i64)), ("account", webhooks::Value::Text(account))],
         );
+        hooks::player_joined(server, update_gaia, id);
       },
-      protocol::ClientToServer::StartJump(player_id) => {
+      protocol::Request::StartJump(player_id) => {
         let mut players = server.players.lock().unwrap();
-        let player = players.get_mut(&player_id).unwrap();
+        // Messages can still be in flight from clients that have been disconnected.
+        let player =
+          match players.get_mut(&player_id) {
+            None => return Ok(()),
+            Some(player) => player,
+          };
         if !player.is_jumping {
           player.is_jumping = true;
           // this 0.3 is duplicated in a few places
           player.accel.y = player.accel.y + 0.3;
         }
       },
-      protocol::ClientToServer::StopJump(player_id) => {
+      protocol::Request::StopJump(player_id) => {
         let mut players = server.players.lock().unwrap();
-        let player = players.get_mut(&player_id).unwrap();
+        let player =
+          match players.get_mut(&player_id) {
+            None => return Ok(()),
+            Some(player) => player,
+          };
         if player.is_jumping {
           player.is_jumping = false;
           // this 0.3 is duplicated in a few places
           player.accel.y = player.accel.y - 0.3;
         }
       },
-      protocol::ClientToServer::Walk(player_id, v) => {
+      protocol::Request::Walk(player_id, v) => {
         let mut players = server.players.lock().unwrap();
-        let player = players.get_mut(&player_id).unwrap();
+        let player =
+          match players.get_mut(&player_id) {
+            None => return Ok(()),
+            Some(player) => player,
+          };
         player.walk(v);
       },
-      protocol::ClientToServer::RotatePlayer(player_id, v) => {
+      protocol::Request::Sprint(player_id, sprinting) => {
+        let mut players = server.players.lock().unwrap();
+        let player =
+          match players.get_mut(&player_id) {
+            None => return Ok(()),
+            Some(player) => player,
+          };
+        player.is_sprinting = sprinting;
+      },
+      protocol::Request::RotatePlayer(player_id, v) => {
         let mut players = server.players.lock().unwrap();
-        let player = players.get_mut(&player_id).unwrap();
+        let player =
+          match players.get_mut(&player_id) {
+            None => return Ok(()),
+            Some(player) => player,
+          };
         player.rotate_lateral(v.x);
         player.rotate_vertical(v.y);
       },
-      protocol::ClientToServer::RequestVoxels { time_requested_ns, client_id, voxels } => {
+      protocol::Request::RequestVoxels { time_requested_ns, client_id, voxels } => {
+        let voxels =
+          match server.clients.lock().unwrap().get(&client_id) {
+            None => return Err(error::T::UnknownClient(client_id)),
+            Some(client) => terrain_policy::clamp_request(&client.terrain_policy, voxels),
+          };
+        // Each voxel is as much work for gaia as the next, however they're batched up.
+        if !within_budget(server, client_id, rate_limit::Kind::Voxels, voxels.len()) {
+          return Ok(())
+        }
         update_gaia(update_gaia::Message::Load(time_requested_ns, voxels, LoadDestination::Client(client_id)));
       },
-      protocol::ClientToServer::Add(player_id) => {
-        let bounds = cast(server, player_id);
-
-        bounds.map(|bounds| {
-          let mut rng = server.rng.lock().unwrap();
-          let rng = rng.deref_mut();
-
-          let trunk_radius =
-            rand::distributions::normal::Normal::new(2.0, 0.5)
-            .ind_sample(rng);
-          let trunk_radius =
-            f64::max(1.0, f64::min(3.0, trunk_radius));
-
-          let trunk_height =
-            rand::distributions::normal::Normal::new(8.0 * trunk_radius, 2.0 * trunk_radius)
-            .ind_sample(rng);
-          let trunk_height =
-            f64::max(4.0 * trunk_radius, f64::min(12.0 * trunk_radius, trunk_height));
-
-          let leaf_radius =
-            rand::distributions::normal::Normal::new(4.0 * trunk_radius, trunk_radius)
-            .ind_sample(rng);
-          let leaf_radius =
-            f64::max(2.0 * trunk_radius, f64::min(6.0 * trunk_radius, leaf_radius));
-
-          let (low, high) = bounds.corners();
-          let mut bottom = (low + high.to_vec()) / 2.0;
-          bottom.y = low.y;
-
-          let trunk_height = trunk_height as f32;
-          let trunk_radius = trunk_radius as f32;
-          let leaf_radius = leaf_radius as f32;
-
-          let tree =
-            voxel_data::mosaic::translation::T {
-              translation: bottom.to_vec(),
-              mosaic: terrain::tree::new(rng, trunk_height, trunk_radius, leaf_radius),
-            };
-
-          let center =
-            bottom + (&Vector3::new(0.0, trunk_height / 2.0, 0.0));
-          let r = trunk_height / 2.0 + leaf_radius + 20.0;
-          let brush =
-            voxel_data::brush::T {
-              bounds:
-                Aabb3::new(
-                  {
-                    let low = center + (&-Vector3::new(r, r, r));
-                    Point3::new(low.x.floor() as i32, low.y.floor() as i32, low.z.floor() as i32)
-                  },
-                  {
-                    let high = center + (&Vector3::new(r, r, r));
-                    Point3::new(high.x.ceil() as i32, high.y.ceil() as i32, high.z.ceil() as i32)
-                  },
-                ),
-              mosaic: Box::new(tree) as Box<voxel_data::mosaic::T<voxel::Material> + Send>,
-              min_lg_size: 0,
-            };
-
-          update_gaia(update_gaia::Message::Brush(brush));
-        });
-      },
-      protocol::ClientToServer::Remove(player_id) => {
-        let bounds = cast(server, player_id);
-
-        bounds.map(|bounds| {
-          debug!("remove bounds {:?}", bounds);
-          let center = bounds.center();
-          let r = 8.0;
-          let sphere =
-            voxel_data::mosaic::solid::T {
-              field: voxel_data::field::translation::T {
-                translation: center.to_vec(),
-                field: voxel_data::field::sphere::T {
-                  radius: r,
-                },
+      protocol::Request::Add(player_id, aim) => {
+        if !player_within_budget(server, player_id, rate_limit::Kind::Edits) || !can_edit(server, player_id) {
+          return Ok(())
+        }
+        // Fitting the tree to the ground means loading the terrain around it, so that's left to gaia.
+        if let Some((bounds, _)) = cast_aimed(server, player_id, &aim) {
+          update_gaia(update_gaia::Message::Plant(edit_permissions::Actor::Player(player_id), bounds));
+        }
+      },
+      protocol::Request::AddRock(player_id, aim) => {
+        if !player_within_budget(server, player_id, rate_limit::Kind::Edits) || !can_edit(server, player_id) {
+          return Ok(())
+        }
+        if let Some((bounds, _)) = cast_aimed(server, player_id, &aim) {
+          update_gaia(update_gaia::Message::PlaceRock(edit_permissions::Actor::Player(player_id), bounds));
+        }
+      },
+      protocol::Request::Throw(player_id, aim) => {
+        if !player_within_budget(server, player_id, rate_limit::Kind::Edits) || !can_edit(server, player_id) {
+          return Ok(())
+        }
+        let (ray, speed) = {
+          let players = server.players.lock().unwrap();
+          match players.get(&player_id) {
+            None => return Ok(()),
+            Some(player) => (player.aim_ray(&aim), player.speed),
+          }
+        };
+        projectile::throw(server, player_id, &ray, &speed);
+      },
+      protocol::Request::StartRemove(player_id, aim) => {
+        if !player_within_budget(server, player_id, rate_limit::Kind::Edits) || !can_edit(server, player_id) {
+          return Ok(())
+        }
+        server.mining.lock().unwrap().entry(player_id).or_insert_with(|| mining::new(Some(aim)));
+      },
+      protocol::Request::StopRemove(player_id) => {
+        if server.mining.lock().unwrap().remove(&player_id).is_none() {
+          return Ok(())
+        }
+        let client_id = try!(server::client_of(server, player_id));
+        try!(server::send_to(server, client_id, protocol::ServerToClient::MiningProgress(None)));
+      },
+      protocol::Request::SetBrush(player_id, shape, radius) => {
+        let mut players = server.players.lock().unwrap();
+        let player =
+          match players.get_mut(&player_id) {
+            None => return Ok(()),
+            Some(player) => player,
+          };
+        player.brush_shape = shape;
+        player.brush_radius = f32::max(protocol::MIN_BRUSH_RADIUS, f32::min(protocol::MAX_BRUSH_RADIUS, radius));
+      },
+      protocol::Request::Command(player_id, line) => {
+        if !player_within_budget(server, player_id, rate_limit::Kind::Commands) {
+          return Ok(())
+        }
+        info!("Player {:?} runs {:?}", player_id, line);
+        let result =
+          command::parse_as(&line, player_id.to_u32())
+          .and_then(|command| {
+            match command {
+              command::T::SetRole(..) => Err(String::from("Only the server console can change roles")),
+              command::T::Players | command::T::Stats => Err(String::from("That's only shown on the server console")),
+              command => {
+                let role = roles::of_player(server, player_id);
+                let needed = command::role_needed(&command);
+                if role < needed {
+                  Err(format!("That command needs the {} role; you're a {}", roles::name(needed), roles::name(role)))
+                } else {
+                  match command {
+                    // Queries are answered to whoever asked, rather than on the console.
+                    command::T::Query(query) => command::run_query(server, &query).map(Some),
+                    command => {
+                      command::apply(server, update_gaia, edit_permissions::Actor::Player(player_id), command)
+                      .map(|()| None)
+                    },
+                  }
+                }
               },
-              material: voxel::Material::Empty,
-            };
-          let r = sphere.field.field.radius + 1.0;
-          let brush =
-            voxel_data::brush::T {
-              bounds:
-                Aabb3::new(
-                  {
-                    let low = sphere.field.translation + (&-Vector3::new(r, r, r));
-                    Point3::new(low.x.floor() as i32, low.y.floor() as i32, low.z.floor() as i32)
-                  },
-                  {
-                    let high = sphere.field.translation + (&Vector3::new(r, r, r));
-                    Point3::new(high.x.ceil() as i32, high.y.ceil() as i32, high.z.ceil() as i32)
-                  },
-                ),
-              mosaic: Box::new(sphere) as Box<voxel_data::mosaic::T<voxel::Material> + Send>,
-              min_lg_size: 0,
-            };
-          let brush: voxel_data::brush::T<Box<voxel_data::mosaic::T<voxel::Material> + Send>> = brush;
-          update_gaia(update_gaia::Message::Brush(brush));
-        });
+            }
+          });
+        let reply =
+          match result {
+            Ok(None) => return Ok(()),
+            Ok(Some(output)) => protocol::ServerToClient::CommandOutput(line, output),
+            Err(err) => protocol::ServerToClient::CommandFailed(line, err),
+          };
+        let client_id = try!(server::client_of(server, player_id));
+        try!(server::send_to(server, client_id, reply));
       },
     };
+    Ok(())
   })
 }
+
+#[cfg(test)]
+mod test {
+  use bincode;
+
+  use super::*;
+
+  #[test]
+  fn test_handshake_decodes_init() {
+    let init =
+      protocol::ClientToServer::Init(
+        protocol::VERSION + 1,
+        String::from("ipc:///tmp/client.ipc"),
+        String::from("alice"),
+        Some(String::from("s3cret")),
+        protocol::Capabilities { max_triangles: 1, bandwidth: 2 },
+      );
+    let bytes = bincode::serialize(&init, bincode::Infinite).unwrap();
+    match bincode::deserialize(&bytes).unwrap() {
+      protocol::Handshake::Init(version, url) => {
+        assert_eq!(version, protocol::VERSION + 1);
+        assert_eq!(url, "ipc:///tmp/client.ipc");
+      },
+    }
+  }
+}
@@ -0,0 +1,1163 @@
+//! Admin commands, e.g. typed into the server console or sent by a client.
+
+use cgmath::{Point3, Vector3, InnerSpace};
+use collision::Aabb3;
+use std;
+use time;
+
+use common::protocol;
+use common::protocol::EntityRef;
+use common::voxel;
+
+use afk;
+use beacon;
+use blast;
+use brush;
+use client_recv_thread::cast;
+use edit_permissions;
+use entity;
+use join_queue;
+use player;
+use portal;
+use rate_limit;
+use roles;
+use server;
+use shutdown;
+use simulate;
+use template;
+use terrain_file;
+use update_gaia;
+use voxel_query;
+use weather;
+
+/// What teleporter pads are made of.
+const PORTAL_MATERIAL: voxel::Material = voxel::Material::Obsidian;
+/// How far away a mob can be from a player and still be ridden or picked up by them.
+const MAX_ATTACH_DISTANCE: f32 = 4.0;
+/// The gap between a player's head and a mob they're carrying.
+const CARRY_CLEARANCE: f32 = 0.5;
+/// The most blocks a claim can reach out from its owner.
+const MAX_CLAIM_RADIUS: u32 = 64;
+/// How far from the spawn point `simulate` grows things, if it isn't told.
+const DEFAULT_SIMULATE_RADIUS: u32 = 128;
+/// Given to kicked clients when the command doesn't say why.
+const DEFAULT_KICK_REASON: &str = "Kicked by an admin";
+/// How hard `weather` makes it rain or snow, if it isn't told.
+const DEFAULT_WEATHER_INTENSITY: f32 = 0.7;
+
+/// A parsed admin command.
+#[derive(Debug, Clone)]
+pub enum T {
+  /// Move a player (by id) so the bottom corner of its bounds is at a point.
+  Teleport(u32, Point3<f32>),
+  /// Send a player (by id) back to the spawn point.
+  Spawn(u32),
+  /// Set the tallest obstacle a player (by id) will automatically step up onto.
+  StepHeight(u32, f32),
+  /// Warn clients, then shut down after some number of seconds. The string is the reason given to clients.
+  Shutdown(u64, String),
+  /// Fill a sphere of some radius with a material, centered where a player (by id) is looking.
+  Sphere(u32, voxel::Material, f32),
+  /// Change how many clients can play at once. Raising it lets waiting clients in right away; lowering it doesn't
+  /// kick anybody out.
+  MaxPlayers(usize),
+  /// Set off an explosion of some radius where a player (by id) is looking.
+  Explode(u32, f32),
+  /// Reinforce the terrain within some radius of where a player (by id) is looking, so it takes a stronger explosion
+  /// to destroy.
+  Reinforce(u32, f32),
+  /// Put a beacon where a player (by id) is looking.
+  Beacon(u32),
+  /// Take down a beacon (by id).
+  RemoveBeacon(u32),
+  /// Put a teleporter pad of some radius where a player (by id) is looking. Players who step onto it are moved so the
+  /// bottom corners of their bounds are at a point.
+  Portal(u32, f32, Point3<f32>),
+  /// Take away a portal (by id). Its pad is left where it is.
+  RemovePortal(u32),
+  /// Claim the blocks within some number of blocks of a player (by id), for that player.
+  Claim(u32, u32),
+  /// Release every claim a player (by id) holds.
+  Unclaim(u32),
+  /// Sit a player (by id) on top of the nearest mob, and have it carry them around.
+  Ride(u32),
+  /// Have a player (by id) carry the nearest mob over their head.
+  Carry(u32),
+  /// Stop a player (by id) riding anything, and put down anything they're carrying.
+  Dismount(u32),
+  /// Change how often each client can send some kind of expensive message.
+  RateLimit(rate_limit::Kind, rate_limit::Budget),
+  /// Change how many times a client can go over budget (within a few seconds) before it's disconnected.
+  KickAfter(u32),
+  /// Change the role of an account (by name).
+  SetRole(String, roles::Role),
+  /// Fast-forward some number of hours of growth within some distance of the spawn point, while nobody's connected.
+  Simulate(f32, u32),
+  /// Print every player's id, account and position.
+  Players,
+  /// Disconnect the client controlling a player (by id). The string is the reason given to the client.
+  Kick(u32, String),
+  /// Move the sun to some hour of a 24-hour day, starting from when it rises.
+  Time(f32),
+  /// Save the terrain now, rather than waiting for the server to stop.
+  Save,
+  /// Print how many clients, players and mobs there are, and how much terrain has been edited.
+  Stats,
+  /// Change the weather.
+  Weather(protocol::Weather),
+  /// Change when clients count as away from the keyboard, whether they're disconnected, and where they're kept.
+  Afk(afk::Settings),
+  /// Ask what the loaded terrain's made of somewhere.
+  Query(voxel_query::T),
+  /// Copy the smallest voxels in a region (including its min corner but not its max) into a template, saved under a
+  /// name.
+  Copy(String, Aabb3<i32>),
+  /// Paste a template (by name) with the middle of its bottom where a player (by id) is looking.
+  Paste(u32, String),
+}
+
+/// Parse a finite number; `NaN` and `inf` parse as floats, but aren't any use as positions or sizes.
+fn parse_f32(s: Option<&str>, name: &str) -> Result<f32, String> {
+  match s {
+    None => Err(format!("Missing {}", name)),
+    Some(s) => {
+      match s.parse::<f32>() {
+        Ok(x) if x.is_finite() => Ok(x),
+        _ => Err(format!("Invalid {}: {:?}", name, s)),
+      }
+    },
+  }
+}
+
+/// Parse an edit's radius. Edits load every voxel they touch before they apply, so big ones would stall the server.
+fn parse_radius(s: Option<&str>) -> Result<f32, String> {
+  let radius = try!(parse_f32(s, "radius"));
+  if radius <= 0.0 {
+    return Err(format!("Radius must be positive: {}", radius))
+  }
+  if radius > protocol::MAX_BRUSH_RADIUS {
+    return Err(format!("Radius can be at most {}: {}", protocol::MAX_BRUSH_RADIUS, radius))
+  }
+  Ok(radius)
+}
+
+// `me` is the player issuing the command, if there is one.
+fn parse_player(s: Option<&str>, me: Option<u32>) -> Result<u32, String> {
+  match (s, me) {
+    (None, _) => Err(String::from("Missing player id")),
+    (Some("me"), Some(me)) => Ok(me),
+    (Some("me"), None) => Err(String::from("There's no \"me\" here")),
+    (Some(s), _) => s.parse().map_err(|_| format!("Invalid player id: {:?}", s)),
+  }
+}
+
+/// Parse a material by name, e.g. `stone`.
+pub fn parse_material(s: Option<&str>) -> Result<voxel::Material, String> {
+  let material =
+    match s {
+      None => return Err(String::from("Missing material")),
+      Some("empty") => voxel::Material::Empty,
+      Some("terrain") => voxel::Material::Terrain,
+      Some("bark") => voxel::Material::Bark,
+      Some("leaves") => voxel::Material::Leaves,
+      Some("stone") => voxel::Material::Stone,
+      Some("marble") => voxel::Material::Marble,
+      Some("obsidian") => voxel::Material::Obsidian,
+      Some(s) => return Err(format!("Invalid material: {:?}", s)),
+    };
+  Ok(material)
+}
+
+/// Parse a command like `teleport <player> <x> <y> <z>`, `spawn <player>`, `step_height <player> <height>`,
+/// `sphere <player> <material> <radius>`, `explode <player> <radius>`, `reinforce <player> <radius>`,
+/// `beacon <player>`, `remove_beacon <beacon>`, `portal <player> <radius> <x> <y> <z>`, `remove_portal <portal>`,
+/// `claim <player> <radius>`, `unclaim <player>`, `ride <player>`, `carry <player>`, `dismount <player>`,
+/// `max_players <count>`,
+/// `rate_limit <voxels|edits|commands> <per second> <burst>`, `kick_after <violations>`,
+/// `role <account> <guest|builder|admin>`, `simulate <hours> [radius]`, `players`, `kick <player> [reason]`,
+/// `time <hour>`, `save`, `stats`, `afk <minutes> [<disconnect minutes>|never] [park]`,
+/// `copy <template> <x> <y> <z> <x> <y> <z>` (between two opposite corner voxels, both included),
+/// `paste <player> <template>`,
+/// `query count material=<material> within r=<radius> of <place>`,
+/// `query find nearest material=<material> to <place> [within r=<radius>]` or `shutdown <seconds> [reason]`.
+/// Places are `player <player>` or `<x>,<y>,<z>`.
+pub fn parse(line: &str) -> Result<T, String> {
+  parse_with_caller(line, None)
+}
+
+/// Parse a command issued by a player, who can refer to themselves as `me` instead of by id.
+pub fn parse_as(line: &str, player_id: u32) -> Result<T, String> {
+  parse_with_caller(line, Some(player_id))
+}
+
+// The value of a `<key>=<value>` word.
+fn parse_setting<'a>(s: Option<&'a str>, key: &str) -> Result<&'a str, String> {
+  let s =
+    match s {
+      None => return Err(format!("Missing {}=", key)),
+      Some(s) => s,
+    };
+  let mut parts = s.splitn(2, '=');
+  match (parts.next(), parts.next()) {
+    (Some(k), Some(value)) if k == key => Ok(value),
+    _ => Err(format!("Expected {}=: {:?}", key, s)),
+  }
+}
+
+fn parse_i32(s: Option<&str>, name: &str) -> Result<i32, String> {
+  match s {
+    None => Err(format!("Missing {}", name)),
+    Some(s) => s.parse().map_err(|_| format!("Invalid {}: {:?}", name, s)),
+  }
+}
+
+fn parse_word(s: Option<&str>, word: &str) -> Result<(), String> {
+  match s {
+    Some(s) if s == word => Ok(()),
+    None => Err(format!("Missing {:?}", word)),
+    Some(s) => Err(format!("Expected {:?}: {:?}", word, s)),
+  }
+}
+
+fn parse_query_radius(s: Option<&str>) -> Result<f32, String> {
+  let radius = try!(parse_f32(Some(try!(parse_setting(s, "r"))), "radius"));
+  if radius <= 0.0 || radius > voxel_query::MAX_RADIUS {
+    return Err(format!("Radius must be more than 0, up to {}: {}", voxel_query::MAX_RADIUS, radius))
+  }
+  Ok(radius)
+}
+
+fn parse_place<'a, Words>(words: &mut Words, me: Option<u32>) -> Result<voxel_query::Place, String> where
+  Words: Iterator<Item=&'a str>,
+{
+  match words.next() {
+    None => Err(String::from("Missing place")),
+    Some("player") => Ok(voxel_query::Place::Player(try!(parse_player(words.next(), me)))),
+    Some(s) => {
+      let coords: Vec<&str> = s.split(',').collect();
+      if coords.len() != 3 {
+        return Err(format!("Invalid place: {:?}", s))
+      }
+      let x = try!(parse_f32(Some(coords[0]), "x"));
+      let y = try!(parse_f32(Some(coords[1]), "y"));
+      let z = try!(parse_f32(Some(coords[2]), "z"));
+      Ok(voxel_query::Place::Point(Point3::new(x, y, z)))
+    },
+  }
+}
+
+fn parse_query<'a, Words>(words: &mut Words, me: Option<u32>) -> Result<voxel_query::T, String> where
+  Words: Iterator<Item=&'a str>,
+{
+  match words.next() {
+    None => Err(String::from("Missing query")),
+    Some("count") => {
+      let material = try!(parse_material(Some(try!(parse_setting(words.next(), "material")))));
+      try!(parse_word(words.next(), "within"));
+      let radius = try!(parse_query_radius(words.next()));
+      try!(parse_word(words.next(), "of"));
+      let place = try!(parse_place(words, me));
+      Ok(voxel_query::T::Count { material: material, place: place, radius: radius })
+    },
+    Some("find") => {
+      try!(parse_word(words.next(), "nearest"));
+      let material = try!(parse_material(Some(try!(parse_setting(words.next(), "material")))));
+      try!(parse_word(words.next(), "to"));
+      let place = try!(parse_place(words, me));
+      let radius =
+        match words.next() {
+          None => voxel_query::DEFAULT_SEARCH_RADIUS,
+          s => {
+            try!(parse_word(s, "within"));
+            try!(parse_query_radius(words.next()))
+          },
+        };
+      Ok(voxel_query::T::FindNearest { material: material, place: place, radius: radius })
+    },
+    Some(s) => Err(format!("Invalid query: {:?}", s)),
+  }
+}
+
+/// The rest of the words, joined by single spaces, or `default` if there aren't any.
+fn rest_or<'a, Words>(words: &mut Words, default: &str) -> String where
+  Words: Iterator<Item=&'a str>,
+{
+  let rest: Vec<&str> = words.collect();
+  if rest.is_empty() {
+    String::from(default)
+  } else {
+    rest.join(" ")
+  }
+}
+
+fn parse_with_caller(line: &str, me: Option<u32>) -> Result<T, String> {
+  let mut words = line.split_whitespace();
+  let command =
+    match words.next() {
+      None => return Err(String::from("Empty command")),
+      Some(command) => command,
+    };
+  let r =
+    match command {
+      "teleport" => {
+        let player = try!(parse_player(words.next(), me));
+        let x = try!(parse_f32(words.next(), "x"));
+        let y = try!(parse_f32(words.next(), "y"));
+        let z = try!(parse_f32(words.next(), "z"));
+        T::Teleport(player, Point3::new(x, y, z))
+      },
+      "spawn" => {
+        let player = try!(parse_player(words.next(), me));
+        T::Spawn(player)
+      },
+      "step_height" => {
+        let player = try!(parse_player(words.next(), me));
+        let height = try!(parse_f32(words.next(), "height"));
+        if height < 0.0 {
+          return Err(format!("Step height can't be negative: {}", height))
+        }
+        T::StepHeight(player, height)
+      },
+      "sphere" => {
+        let player = try!(parse_player(words.next(), me));
+        let material = try!(parse_material(words.next()));
+        let radius = try!(parse_radius(words.next()));
+        T::Sphere(player, material, radius)
+      },
+      "explode" | "reinforce" => {
+        let player = try!(parse_player(words.next(), me));
+        let radius = try!(parse_radius(words.next()));
+        if command == "explode" { T::Explode(player, radius) } else { T::Reinforce(player, radius) }
+      },
+      "beacon" => {
+        let player = try!(parse_player(words.next(), me));
+        T::Beacon(player)
+      },
+      "remove_beacon" => {
+        match words.next() {
+          None => return Err(String::from("Missing beacon id")),
+          Some(s) => T::RemoveBeacon(try!(s.parse().map_err(|_| format!("Invalid beacon id: {:?}", s)))),
+        }
+      },
+      "portal" => {
+        let player = try!(parse_player(words.next(), me));
+        let radius = try!(parse_radius(words.next()));
+        let x = try!(parse_f32(words.next(), "x"));
+        let y = try!(parse_f32(words.next(), "y"));
+        let z = try!(parse_f32(words.next(), "z"));
+        T::Portal(player, radius, Point3::new(x, y, z))
+      },
+      "remove_portal" => {
+        match words.next() {
+          None => return Err(String::from("Missing portal id")),
+          Some(s) => T::RemovePortal(try!(s.parse().map_err(|_| format!("Invalid portal id: {:?}", s)))),
+        }
+      },
+      "claim" => {
+        let player = try!(parse_player(words.next(), me));
+        let radius: u32 =
+          match words.next() {
+            None => return Err(String::from("Missing radius")),
+            Some(s) => try!(s.parse().map_err(|_| format!("Invalid radius: {:?}", s))),
+          };
+        if radius > MAX_CLAIM_RADIUS {
+          return Err(format!("Claims can reach at most {} blocks: {}", MAX_CLAIM_RADIUS, radius))
+        }
+        T::Claim(player, radius)
+      },
+      "unclaim" => {
+        let player = try!(parse_player(words.next(), me));
+        T::Unclaim(player)
+      },
+      "ride" => T::Ride(try!(parse_player(words.next(), me))),
+      "carry" => T::Carry(try!(parse_player(words.next(), me))),
+      "dismount" => T::Dismount(try!(parse_player(words.next(), me))),
+      "rate_limit" => {
+        let kind =
+          match words.next() {
+            None => return Err(String::from("Missing message kind")),
+            Some(s) => try!(rate_limit::parse_kind(s)),
+          };
+        let per_second = try!(parse_f32(words.next(), "rate"));
+        let burst = try!(parse_f32(words.next(), "burst"));
+        if per_second <= 0.0 || burst < 1.0 {
+          return Err(format!("Rate must be positive and burst at least 1: {} {}", per_second, burst))
+        }
+        T::RateLimit(kind, rate_limit::Budget { per_second: per_second, burst: burst })
+      },
+      "kick_after" => {
+        match words.next() {
+          None => return Err(String::from("Missing count")),
+          Some(s) => T::KickAfter(try!(s.parse().map_err(|_| format!("Invalid count: {:?}", s)))),
+        }
+      },
+      "max_players" => {
+        match words.next() {
+          None => return Err(String::from("Missing count")),
+          Some(s) => T::MaxPlayers(try!(s.parse().map_err(|_| format!("Invalid count: {:?}", s)))),
+        }
+      },
+      "role" => {
+        let account =
+          match words.next() {
+            None => return Err(String::from("Missing account")),
+            Some(account) => account.to_owned(),
+          };
+        match words.next() {
+          None => return Err(String::from("Missing role")),
+          Some(s) => T::SetRole(account, try!(roles::parse(s))),
+        }
+      },
+      "simulate" => {
+        let hours = try!(parse_f32(words.next(), "hours"));
+        if hours <= 0.0 {
+          return Err(format!("Hours must be positive: {}", hours))
+        }
+        let radius =
+          match words.next() {
+            None => DEFAULT_SIMULATE_RADIUS,
+            Some(s) => try!(s.parse().map_err(|_| format!("Invalid radius: {:?}", s))),
+          };
+        T::Simulate(hours, radius)
+      },
+      "players" => T::Players,
+      "kick" => {
+        let player = try!(parse_player(words.next(), me));
+        T::Kick(player, rest_or(&mut words, DEFAULT_KICK_REASON))
+      },
+      "time" => {
+        let hour = try!(parse_f32(words.next(), "hour"));
+        if hour < 0.0 || hour >= 24.0 {
+          return Err(format!("Hour must be from 0 up to 24: {}", hour))
+        }
+        T::Time(hour)
+      },
+      "save" => T::Save,
+      "stats" => T::Stats,
+      "afk" => T::Afk(try!(afk::parse(&mut words))),
+      "query" => T::Query(try!(parse_query(&mut words, me))),
+      "copy" => {
+        let name =
+          match words.next() {
+            None => return Err(String::from("Missing template name")),
+            Some(name) => name.to_owned(),
+          };
+        let mut corners = Vec::new();
+        for _ in 0 .. 2 {
+          let x = try!(parse_i32(words.next(), "x"));
+          let y = try!(parse_i32(words.next(), "y"));
+          let z = try!(parse_i32(words.next(), "z"));
+          corners.push(Point3::new(x, y, z));
+        }
+        let (a, b) = (corners[0], corners[1]);
+        let region =
+          Aabb3::new(
+            Point3::new(std::cmp::min(a.x, b.x), std::cmp::min(a.y, b.y), std::cmp::min(a.z, b.z)),
+            Point3::new(std::cmp::max(a.x, b.x) + 1, std::cmp::max(a.y, b.y) + 1, std::cmp::max(a.z, b.z) + 1),
+          );
+        T::Copy(name, region)
+      },
+      "paste" => {
+        let player = try!(parse_player(words.next(), me));
+        match words.next() {
+          None => return Err(String::from("Missing template name")),
+          Some(name) => T::Paste(player, name.to_owned()),
+        }
+      },
+      "weather" => {
+        let precipitation =
+          match words.next() {
+            None => return Err(String::from("Missing weather")),
+            Some("clear") => protocol::Precipitation::None,
+            Some("rain") => protocol::Precipitation::Rain,
+            Some("snow") => protocol::Precipitation::Snow,
+            Some(s) => return Err(format!("Invalid weather: {:?}", s)),
+          };
+        if precipitation == protocol::Precipitation::None {
+          T::Weather(weather::CLEAR)
+        } else {
+          let intensity =
+            match words.next() {
+              None => DEFAULT_WEATHER_INTENSITY,
+              s => try!(parse_f32(s, "intensity")),
+            };
+          if intensity <= 0.0 || intensity > 1.0 {
+            return Err(format!("Intensity must be more than 0, up to 1: {}", intensity))
+          }
+          T::Weather(protocol::Weather { precipitation: precipitation, intensity: intensity })
+        }
+      },
+      "shutdown" => {
+        let seconds: u64 =
+          match words.next() {
+            None => return Err(String::from("Missing seconds")),
+            Some(s) => try!(s.parse().map_err(|_| format!("Invalid seconds: {:?}", s))),
+          };
+        T::Shutdown(seconds, rest_or(&mut words, "Server is shutting down"))
+      },
+      _ => return Err(format!("Unrecognized command: {:?}", command)),
+    };
+  match words.next() {
+    None => Ok(r),
+    Some(word) => Err(format!("Unexpected argument: {:?}", word)),
+  }
+}
+
+/// The least trusted role a player needs to run a command. Changing roles is left to the server console.
+pub fn role_needed(command: &T) -> roles::Role {
+  match *command {
+    T::Sphere(..) | T::Explode(..) | T::Reinforce(..) | T::Beacon(..) | T::Portal(..) | T::Claim(..) | T::Unclaim(..) |
+    T::Ride(..) | T::Carry(..) | T::Dismount(..) | T::Paste(..) => {
+      roles::Role::Builder
+    },
+    T::Teleport(..) | T::Spawn(..) | T::StepHeight(..) | T::RemoveBeacon(..) | T::RemovePortal(..) | T::Shutdown(..) |
+    T::MaxPlayers(..) | T::RateLimit(..) | T::KickAfter(..) | T::SetRole(..) | T::Simulate(..) | T::Players |
+    T::Kick(..) | T::Time(..) | T::Save | T::Stats | T::Weather(..) | T::Afk(..) | T::Query(..) | T::Copy(..) => {
+      roles::Role::Admin
+    },
+  }
+}
+
+/// Run a command against the server, on behalf of `actor`.
+pub fn apply<UpdateGaia>(
+  server: &server::T,
+  update_gaia: &mut UpdateGaia,
+  actor: edit_permissions::Actor,
+  command: T,
+) -> Result<(), String> where
+  UpdateGaia: FnMut(update_gaia::Message),
+{
+  let player_id =
+    match command {
+      T::Teleport(player_id, _) | T::Spawn(player_id) | T::StepHeight(player_id, _) | T::Claim(player_id, _) |
+      T::Unclaim(player_id) => player_id,
+      T::Sphere(player_id, material, radius) => {
+        return place_sphere(server, update_gaia, actor, player_id, material, radius)
+      },
+      T::Explode(player_id, radius) => {
+        return explode(server, update_gaia, actor, player_id, radius)
+      },
+      T::Reinforce(player_id, radius) => {
+        return reinforce(server, actor, player_id, radius)
+      },
+      T::Beacon(player_id) => {
+        return place_beacon(server, player_id)
+      },
+      T::Ride(player_id) => {
+        return attach_nearest_mob(server, player_id, true)
+      },
+      T::Carry(player_id) => {
+        return attach_nearest_mob(server, player_id, false)
+      },
+      T::Dismount(player_id) => {
+        return dismount(server, player_id)
+      },
+      T::RemoveBeacon(id) => {
+        try!(beacon::remove(server, id));
+        info!("Took down beacon {}", id);
+        return Ok(())
+      },
+      T::Portal(player_id, radius, destination) => {
+        return place_portal(server, update_gaia, actor, player_id, radius, destination)
+      },
+      T::RemovePortal(id) => {
+        try!(server.portals.lock().unwrap().remove(id));
+        info!("Took away portal {}", id);
+        return Ok(())
+      },
+      T::Shutdown(seconds, reason) => {
+        shutdown::begin(server, reason, seconds * 1_000_000_000);
+        return Ok(())
+      },
+      T::MaxPlayers(count) => {
+        *server.max_players.lock().unwrap() = count;
+        info!("Up to {} players can play at once", count);
+        join_queue::admit_waiting(server);
+        return Ok(())
+      },
+      T::RateLimit(kind, budget) => {
+        server.rate_budgets.lock().unwrap().set(kind, budget);
+        info!("Clients can send {:?} at {:?}", kind, budget);
+        return Ok(())
+      },
+      T::KickAfter(count) => {
+        server.rate_budgets.lock().unwrap().kick_after = count;
+        info!("Clients are disconnected after going over budget {} times", count);
+        return Ok(())
+      },
+      T::Simulate(hours, radius) => {
+        return simulate::run(server, update_gaia, hours, radius)
+      },
+      T::Players => {
+        print_players(server);
+        return Ok(())
+      },
+      T::Kick(player_id, reason) => {
+        return kick(server, player_id, reason)
+      },
+      T::Time(hour) => {
+        set_time(server, hour);
+        return Ok(())
+      },
+      T::Save => {
+        try!(terrain_file::save(&server.terrain_loader.terrain, std::path::Path::new(terrain_file::DEFAULT_PATH)));
+        info!("Saved terrain to {}", terrain_file::DEFAULT_PATH);
+        return Ok(())
+      },
+      T::Stats => {
+        print_stats(server);
+        return Ok(())
+      },
+      T::Weather(w) => {
+        weather::set(server, w, time::precise_time_ns());
+        info!("Set the weather to {:?}", w);
+        return Ok(())
+      },
+      T::Afk(settings) => {
+        server.afk.lock().unwrap().settings = settings;
+        info!("Away from the keyboard: {:?}", settings);
+        return Ok(())
+      },
+      T::Query(query) => {
+        println!("{}", try!(run_query(server, &query)));
+        return Ok(())
+      },
+      T::Copy(name, region) => {
+        let template = try!(template::copy(&server.terrain_loader.terrain, &region));
+        try!(template.save(&name));
+        info!("Copied {:?} into template {:?}", region, name);
+        return Ok(())
+      },
+      T::Paste(player_id, name) => {
+        return paste(server, update_gaia, actor, player_id, &name)
+      },
+      T::SetRole(account, role) => {
+        try!(
+          server.roles.lock().unwrap().set(&account, role)
+          .map_err(|err| format!("Couldn't save roles: {:?}", err))
+        );
+        info!("{} is now a {}", account, roles::name(role));
+        return Ok(())
+      },
+    };
+
+  let mut players = server.players.lock().unwrap();
+  let player =
+    match players.values_mut().find(|player| player.entity_id.to_u32() == player_id) {
+      None => return Err(format!("No player with id {}", player_id)),
+      Some(player) => player,
+    };
+
+  match command {
+    T::Teleport(_, destination) => {
+      player.teleport(server, destination);
+      info!("Teleported player {} to {:?}", player_id, destination);
+    },
+    T::Spawn(_) => {
+      player.teleport(server, player::SPAWN_POINT);
+      info!("Sent player {} back to spawn", player_id);
+    },
+    T::StepHeight(_, height) => {
+      let height = f32::min(height, player::MAX_STEP_HEIGHT);
+      player.step_height = height;
+      info!("Set player {} step height to {}", player_id, height);
+    },
+    T::Claim(_, radius) => {
+      let w = edit_permissions::BLOCK_WIDTH as f32;
+      let block = Point3::new(player.position.x / w, player.position.y / w, player.position.z / w);
+      let block = Point3::new(block.x.floor() as i32, block.y.floor() as i32, block.z.floor() as i32);
+      let r = radius as i32;
+      let blocks =
+        Aabb3::new(
+          Point3::new(block.x - r, block.y - r, block.z - r),
+          Point3::new(block.x + r + 1, block.y + r + 1, block.z + r + 1),
+        );
+      try!(server.edit_permissions.lock().unwrap().claims.claim(player.entity_id, &blocks));
+      info!("Player {} claimed blocks {:?}", player_id, blocks);
+    },
+    T::Unclaim(_) => {
+      server.edit_permissions.lock().unwrap().claims.release(player.entity_id);
+      info!("Released player {}'s claims", player_id);
+    },
+    T::Shutdown(..) | T::Sphere(..) | T::Explode(..) | T::Reinforce(..) | T::MaxPlayers(..) | T::Beacon(..) |
+    T::RemoveBeacon(..) | T::Portal(..) | T::RemovePortal(..) | T::SetRole(..) | T::Ride(..) | T::Carry(..) | T::Dismount(..) | T::RateLimit(..) | T::KickAfter(..) | T::Simulate(..) |
+    T::Players | T::Kick(..) | T::Time(..) | T::Save | T::Stats | T::Weather(..) | T::Afk(..) | T::Query(..) |
+    T::Copy(..) | T::Paste(..) => {
+      unreachable!()
+    },
+  }
+  Ok(())
+}
+
+fn place_sphere<UpdateGaia>(
+  server: &server::T,
+  update_gaia: &mut UpdateGaia,
+  actor: edit_permissions::Actor,
+  player_id: u32,
+  material: voxel::Material,
+  radius: f32,
+) -> Result<(), String> where
+  UpdateGaia: FnMut(update_gaia::Message),
+{
+  let entity_id =
+    match server.players.lock().unwrap().values().find(|player| player.entity_id.to_u32() == player_id) {
+      None => return Err(format!("No player with id {}", player_id)),
+      Some(player) => player.entity_id,
+    };
+  let center =
+    match cast(server, entity_id) {
+      None => return Err(format!("Player {} isn't looking at anything", player_id)),
+      Some((bounds, _)) => bounds.center(),
+    };
+  update_gaia(update_gaia::Message::Brush(actor, vec!(material), brush::sphere(&center, radius, material)));
+  info!("Requested a {:?} sphere of radius {} at {:?}", material, radius, center);
+  Ok(())
+}
+
+/// Where a player (by id) is looking.
+fn target(server: &server::T, player_id: u32) -> Result<Point3<f32>, String> {
+  let entity_id = try!(player_entity(server, player_id));
+  match cast(server, entity_id) {
+    None => Err(format!("Player {} isn't looking at anything", player_id)),
+    Some((bounds, _)) => Ok(bounds.center()),
+  }
+}
+
+fn explode<UpdateGaia>(
+  server: &server::T,
+  update_gaia: &mut UpdateGaia,
+  actor: edit_permissions::Actor,
+  player_id: u32,
+  radius: f32,
+) -> Result<(), String> where
+  UpdateGaia: FnMut(update_gaia::Message),
+{
+  let center = try!(target(server, player_id));
+  let brush = blast::explosion(&server.terrain_loader.terrain, &center, radius);
+  update_gaia(update_gaia::Message::Brush(actor, vec!(voxel::Material::Empty), brush));
+  info!("Set off an explosion of radius {} at {:?}", radius, center);
+  Ok(())
+}
+
+fn paste<UpdateGaia>(
+  server: &server::T,
+  update_gaia: &mut UpdateGaia,
+  actor: edit_permissions::Actor,
+  player_id: u32,
+  name: &str,
+) -> Result<(), String> where
+  UpdateGaia: FnMut(update_gaia::Message),
+{
+  let at = try!(target(server, player_id));
+  let template = try!(template::load(name));
+  let low = template::low_corner(&template.size, &at);
+  update_gaia(update_gaia::Message::Paste(actor, template, low));
+  info!("Requested a paste of template {:?} at {:?}", name, low);
+  Ok(())
+}
+
+fn reinforce(server: &server::T, actor: edit_permissions::Actor, player_id: u32, radius: f32) -> Result<(), String> {
+  let center = try!(target(server, player_id));
+  try!(
+    server.edit_permissions.lock().unwrap().decide(
+      &edit_permissions::Edit {
+        actor     : actor,
+        bounds    : &blast::region(&center, radius),
+        materials : &[],
+      }
+    )
+  );
+  let reinforced = blast::reinforce(&server.terrain_loader.terrain, &center, radius);
+  info!("Reinforced {} voxels within {} of {:?}", reinforced, radius, center);
+  Ok(())
+}
+
+fn place_beacon(server: &server::T, player_id: u32) -> Result<(), String> {
+  let entity_id =
+    match server.players.lock().unwrap().values().find(|player| player.entity_id.to_u32() == player_id) {
+      None => return Err(format!("No player with id {}", player_id)),
+      Some(player) => player.entity_id,
+    };
+  let position =
+    match cast(server, entity_id) {
+      None => return Err(format!("Player {} isn't looking at anything", player_id)),
+      Some((bounds, _)) => bounds.center(),
+    };
+  let id = try!(beacon::place(server, position));
+  info!("Placed beacon {} at {:?}", id.to_u32(), position);
+  Ok(())
+}
+
+fn place_portal<UpdateGaia>(
+  server: &server::T,
+  update_gaia: &mut UpdateGaia,
+  actor: edit_permissions::Actor,
+  player_id: u32,
+  radius: f32,
+  destination: Point3<f32>,
+) -> Result<(), String> where
+  UpdateGaia: FnMut(update_gaia::Message),
+{
+  let center = try!(target(server, player_id));
+  let pad = brush::pad(&center, radius, portal::PAD_THICKNESS, PORTAL_MATERIAL);
+  // Gaia would refuse the pad anyway, but by then the portal would already be there.
+  try!(
+    server.edit_permissions.lock().unwrap().decide(
+      &edit_permissions::Edit {
+        actor     : actor,
+        bounds    : &pad.bounds,
+        materials : &[PORTAL_MATERIAL],
+      }
+    )
+  );
+  update_gaia(update_gaia::Message::Brush(actor, vec!(PORTAL_MATERIAL), pad));
+  let portal = portal::Portal { region: portal::region(&center, radius), destination: destination };
+  let id = server.portals.lock().unwrap().add(portal);
+  info!("Placed portal {} at {:?}, to {:?}", id, center, destination);
+  Ok(())
+}
+
+/// Answer a query about the terrain.
+pub fn run_query(server: &server::T, query: &voxel_query::T) -> Result<String, String> {
+  let terrain = &server.terrain_loader.terrain;
+  match *query {
+    voxel_query::T::Count { material, place, radius } => {
+      let center = try!(place_position(server, place));
+      let counted = voxel_query::count(terrain, material, &center, radius);
+      let mut r = format!("{} {:?} voxels within {} of {:?}", counted.matching, material, radius, place);
+      if counted.unloaded > 0 {
+        r.push_str(&format!(" ({} more weren't loaded)", counted.unloaded));
+      }
+      Ok(r)
+    },
+    voxel_query::T::FindNearest { material, place, radius } => {
+      let center = try!(place_position(server, place));
+      match voxel_query::find_nearest(terrain, material, &center, radius) {
+        None => Ok(format!("No {:?} loaded within {} of {:?}", material, radius, place)),
+        Some((bounds, distance)) => {
+          Ok(format!("Nearest {:?} is at ({}, {}, {}), {:.1} away", material, bounds.x, bounds.y, bounds.z, distance))
+        },
+      }
+    },
+  }
+}
+
+fn place_position(server: &server::T, place: voxel_query::Place) -> Result<Point3<f32>, String> {
+  match place {
+    voxel_query::Place::Point(position) => Ok(position),
+    voxel_query::Place::Player(player_id) => {
+      match server.players.lock().unwrap().values().find(|player| player.entity_id.to_u32() == player_id) {
+        None => Err(format!("No player with id {}", player_id)),
+        Some(player) => Ok(player.position),
+      }
+    },
+  }
+}
+
+fn player_entity(server: &server::T, player_id: u32) -> Result<entity::id::Player, String> {
+  match server.players.lock().unwrap().values().find(|player| player.entity_id.to_u32() == player_id) {
+    None => Err(format!("No player with id {}", player_id)),
+    Some(player) => Ok(player.entity_id),
+  }
+}
+
+fn print_players(server: &server::T) {
+  let mut players: Vec<(u32, protocol::ClientId, Point3<f32>)> =
+    server.players.lock().unwrap().values()
+    .map(|player| (player.entity_id.to_u32(), player.client_id, player.position))
+    .collect();
+  players.sort_by_key(|&(id, _, _)| id);
+  println!("{} players", players.len());
+  let clients = server.clients.lock().unwrap();
+  for (id, client_id, position) in players {
+    let client = clients.get(&client_id);
+    let account = client.map(|client| client.account.as_str()).unwrap_or("?");
+    let away = if client.map(|client| client.afk).unwrap_or(false) { ", away" } else { "" };
+    println!("  {} ({}{}) at ({:.1}, {:.1}, {:.1})", id, account, away, position.x, position.y, position.z);
+  }
+}
+
+fn kick(server: &server::T, player_id: u32, reason: String) -> Result<(), String> {
+  let entity_id = try!(player_entity(server, player_id));
+  let client_id =
+    match server.components.controller(EntityRef::Player(entity_id)) {
+      None => return Err(format!("Player {} isn't controlled by anybody", player_id)),
+      Some(client_id) => client_id,
+    };
+  if let Some(client) = server.clients.lock().unwrap().get_mut(&client_id) {
+    client.send(protocol::ServerToClient::Kicked(reason.clone()));
+  }
+  join_queue::leave(server, client_id);
+  info!("Kicked player {}'s client: {}", player_id, reason);
+  Ok(())
+}
+
+fn set_time(server: &server::T, hour: f32) {
+  let sun = {
+    let mut sun = server.sun.lock().unwrap();
+    sun.set_phase(hour / 24.0);
+    sun.state()
+  };
+  for (_, client) in server.clients.lock().unwrap().iter_mut() {
+    client.send(protocol::ServerToClient::UpdateSun(sun));
+  }
+  info!("Moved the sun to hour {}", hour);
+}
+
+fn print_stats(server: &server::T) {
+  let players = server.players.lock().unwrap().len();
+  let mobs = server.mobs.lock().unwrap().len();
+  let clients = server.clients.lock().unwrap().len();
+  let waiting = server.join_queue.lock().unwrap().len();
+  let max_players = *server.max_players.lock().unwrap();
+  let edited = server.terrain_loader.terrain.edits.lock().unwrap().blocks.len();
+  let coalesced = server.terrain_loader.pending_loads.coalesced();
+  let portals = server.portals.lock().unwrap().len();
+  println!("{}/{} clients playing, {} waiting", clients, max_players, waiting);
+  println!("{} players, {} mobs, {} portals", players, mobs, portals);
+  println!("{} blocks of terrain edited", edited);
+  println!("{} block loads shared with a load already in progress", coalesced);
+}
+
+fn half_height(bounds: &Aabb3<f32>) -> f32 {
+  (bounds.max.y - bounds.min.y) / 2.0
+}
+
+/// Attach a player to the nearest mob within `MAX_ATTACH_DISTANCE` (`riding`), or the mob to the player.
+fn attach_nearest_mob(server: &server::T, player_id: u32, riding: bool) -> Result<(), String> {
+  let entity_id = try!(player_entity(server, player_id));
+  let (position, player_physics) = {
+    let players = server.players.lock().unwrap();
+    let player = &players[&entity_id];
+    (player.position, player.physics_id)
+  };
+  let nearest =
+    server.mobs.lock().unwrap().values()
+    .map(|mob| ((mob.position - position).magnitude(), mob.entity_id, mob.physics_id))
+    .filter(|&(distance, _, _)| distance <= MAX_ATTACH_DISTANCE)
+    .fold(None, |nearest: Option<(f32, entity::id::Mob, entity::id::Misc)>, mob| {
+      match nearest {
+        Some(nearest) if nearest.0 <= mob.0 => Some(nearest),
+        _ => Some(mob),
+      }
+    });
+  let (mob_id, mob_physics) =
+    match nearest {
+      None => return Err(format!("There are no mobs within {} of player {}", MAX_ATTACH_DISTANCE, player_id)),
+      Some((_, mob_id, mob_physics)) => (mob_id, mob_physics),
+    };
+
+  let height = {
+    let physics = server.physics.lock().unwrap();
+    half_height(physics.get_bounds(player_physics).unwrap()) + half_height(physics.get_bounds(mob_physics).unwrap())
+  };
+  let (player, mob) = (EntityRef::Player(entity_id), EntityRef::Mob(mob_id));
+  let mut attachments = server.attachments.lock().unwrap();
+  if riding {
+    try!(attachments.attach(player, mob, Vector3::new(0.0, height, 0.0)));
+    info!("Player {} is riding mob {}", player_id, mob_id.to_u32());
+  } else {
+    try!(attachments.attach(mob, player, Vector3::new(0.0, height + CARRY_CLEARANCE, 0.0)));
+    info!("Player {} is carrying mob {}", player_id, mob_id.to_u32());
+  }
+  Ok(())
+}
+
+fn dismount(server: &server::T, player_id: u32) -> Result<(), String> {
+  let entity_id = try!(player_entity(server, player_id));
+  server.attachments.lock().unwrap().detach_all(EntityRef::Player(entity_id));
+  info!("Player {} isn't riding or carrying anything", player_id);
+  Ok(())
+}
+
+#[cfg(test)]
+mod test {
+  use cgmath::Point3;
+  use collision::Aabb3;
+
+  use common::protocol;
+  use common::voxel;
+
+  use rate_limit;
+  use roles;
+  use voxel_query;
+  use weather;
+
+  use super::*;
+
+  #[test]
+  fn test_parse() {
+    match parse("teleport 3 1 -2.5 100\n") {
+      Ok(T::Teleport(3, p)) => assert_eq!(p, Point3::new(1.0, -2.5, 100.0)),
+      r => panic!("{:?}", r),
+    }
+    match parse("  spawn 0") {
+      Ok(T::Spawn(0)) => {},
+      r => panic!("{:?}", r),
+    }
+    match parse("step_height 2 0.5") {
+      Ok(T::StepHeight(2, h)) => assert_eq!(h, 0.5),
+      r => panic!("{:?}", r),
+    }
+    match parse_as("sphere me stone 3", 7) {
+      Ok(T::Sphere(7, voxel::Material::Stone, r)) => assert_eq!(r, 3.0),
+      r => panic!("{:?}", r),
+    }
+    match parse_as("explode me 4", 2) {
+      Ok(T::Explode(2, r)) => assert_eq!(r, 4.0),
+      r => panic!("{:?}", r),
+    }
+    match parse("reinforce 1 2.5") {
+      Ok(T::Reinforce(1, r)) => assert_eq!(r, 2.5),
+      r => panic!("{:?}", r),
+    }
+    match parse_as("beacon me", 2) {
+      Ok(T::Beacon(2)) => {},
+      r => panic!("{:?}", r),
+    }
+    match parse("remove_beacon 5") {
+      Ok(T::RemoveBeacon(5)) => {},
+      r => panic!("{:?}", r),
+    }
+    match parse_as("portal me 1.5 10 64 -3", 2) {
+      Ok(T::Portal(2, r, p)) => {
+        assert_eq!(r, 1.5);
+        assert_eq!(p, Point3::new(10.0, 64.0, -3.0));
+      },
+      r => panic!("{:?}", r),
+    }
+    assert!(parse("portal 1 0 10 64 -3").is_err());
+    assert!(parse("portal 1 2 10 64").is_err());
+    match parse("remove_portal 3") {
+      Ok(T::RemovePortal(3)) => {},
+      r => panic!("{:?}", r),
+    }
+    match parse("max_players 4") {
+      Ok(T::MaxPlayers(4)) => {},
+      r => panic!("{:?}", r),
+    }
+    match parse_as("claim me 2", 4) {
+      Ok(T::Claim(4, 2)) => {},
+      r => panic!("{:?}", r),
+    }
+    match parse_as("ride me", 3) {
+      Ok(T::Ride(3)) => {},
+      r => panic!("{:?}", r),
+    }
+    match parse("rate_limit edits 2.5 5") {
+      Ok(T::RateLimit(rate_limit::Kind::Edits, budget)) => assert_eq!((budget.per_second, budget.burst), (2.5, 5.0)),
+      r => panic!("{:?}", r),
+    }
+    match parse("role alice admin") {
+      Ok(T::SetRole(ref account, roles::Role::Admin)) => assert_eq!(account, "alice"),
+      r => panic!("{:?}", r),
+    }
+    match parse("simulate 12.5") {
+      Ok(T::Simulate(h, DEFAULT_SIMULATE_RADIUS)) => assert_eq!(h, 12.5),
+      r => panic!("{:?}", r),
+    }
+    match parse("simulate 1 64") {
+      Ok(T::Simulate(h, 64)) => assert_eq!(h, 1.0),
+      r => panic!("{:?}", r),
+    }
+    match parse("kick 2") {
+      Ok(T::Kick(2, ref reason)) => assert_eq!(reason, DEFAULT_KICK_REASON),
+      r => panic!("{:?}", r),
+    }
+    match parse("kick 2 be nice") {
+      Ok(T::Kick(2, ref reason)) => assert_eq!(reason, "be nice"),
+      r => panic!("{:?}", r),
+    }
+    match parse("time 6.5") {
+      Ok(T::Time(h)) => assert_eq!(h, 6.5),
+      r => panic!("{:?}", r),
+    }
+    match parse("weather snow 0.5") {
+      Ok(T::Weather(w)) => {
+        assert_eq!(w, protocol::Weather { precipitation: protocol::Precipitation::Snow, intensity: 0.5 })
+      },
+      r => panic!("{:?}", r),
+    }
+    match parse("weather clear") {
+      Ok(T::Weather(w)) => assert_eq!(w, weather::CLEAR),
+      r => panic!("{:?}", r),
+    }
+    match parse("afk 10 never") {
+      Ok(T::Afk(settings)) => assert_eq!((settings.disconnect_after_ns, settings.park), (None, false)),
+      r => panic!("{:?}", r),
+    }
+    match parse_as("query count material=stone within r=100 of player me", 4) {
+      Ok(T::Query(voxel_query::T::Count { material, place, radius })) => {
+        assert_eq!((material, place, radius), (voxel::Material::Stone, voxel_query::Place::Player(4), 100.0));
+      },
+      r => panic!("{:?}", r),
+    }
+    match parse("query find nearest material=marble to 0,64,-2.5") {
+      Ok(T::Query(voxel_query::T::FindNearest { material, place, radius })) => {
+        assert_eq!(material, voxel::Material::Marble);
+        assert_eq!(place, voxel_query::Place::Point(Point3::new(0.0, 64.0, -2.5)));
+        assert_eq!(radius, voxel_query::DEFAULT_SEARCH_RADIUS);
+      },
+      r => panic!("{:?}", r),
+    }
+    assert!(parse("query find nearest material=marble to 0,64 within r=10").is_err());
+    assert!(parse("query count material=stone within r=1000 of 0,0,0").is_err());
+    assert!(parse("query count stone within r=10 of 0,0,0").is_err());
+    match parse("copy house 4 70 -2 0 64 3") {
+      Ok(T::Copy(ref name, region)) => {
+        assert_eq!(name, "house");
+        assert_eq!(region, Aabb3::new(Point3::new(0, 64, -2), Point3::new(5, 71, 4)));
+      },
+      r => panic!("{:?}", r),
+    }
+    match parse_as("paste me house", 2) {
+      Ok(T::Paste(2, ref name)) => assert_eq!(name, "house"),
+      r => panic!("{:?}", r),
+    }
+    match parse("shutdown 30 back in  five\n") {
+      Ok(T::Shutdown(30, ref reason)) => assert_eq!(reason, "back in five"),
+      r => panic!("{:?}", r),
+    }
+  }
+
+  #[test]
+  fn test_parse_errors() {
+    assert!(parse("").is_err());
+    assert!(parse("fly 1").is_err());
+    assert!(parse("teleport 1 2 3").is_err());
+    assert!(parse("teleport one 2 3 4").is_err());
+    assert!(parse("spawn 1 2").is_err());
+    assert!(parse("shutdown soon").is_err());
+    assert!(parse("weather hail").is_err());
+    assert!(parse("weather rain 2").is_err());
+    assert!(parse("weather clear 1").is_err());
+    assert!(parse("max_players -1").is_err());
+    assert!(parse("spawn me").is_err());
+    assert!(parse("role alice").is_err());
+    assert!(parse("claim 1 -2").is_err());
+    assert!(parse("claim 1 2147483647").is_err());
+    assert!(parse("step_height 2 -0.5").is_err());
+    assert!(parse("step_height 2 NaN").is_err());
+    assert!(parse("step_height 2 inf").is_err());
+    assert!(parse("teleport 3 0 -inf 0").is_err());
+    assert!(parse("rate_limit chat 1 1").is_err());
+    assert!(parse("rate_limit voxels 10 0.5").is_err());
+    assert!(parse("role alice wizard").is_err());
+    assert!(parse("simulate -3").is_err());
+    assert!(parse("simulate 3 far").is_err());
+    assert!(parse("explode 1 0").is_err());
+    assert!(parse("explode 1 10000").is_err());
+    assert!(parse("reinforce 1 10000").is_err());
+    assert!(parse("time 24").is_err());
+    assert!(parse("stats now").is_err());
+    assert!(parse("kick").is_err());
+    assert!(parse("afk").is_err());
+    assert!(parse("copy house 0 0 0 1 1").is_err());
+    assert!(parse("copy house 0 0 0 1 1 1.5").is_err());
+    assert!(parse("paste 1").is_err());
+    assert!(parse_as("sphere me cheese 3", 1).is_err());
+    assert!(parse_as("sphere me stone -1", 1).is_err());
+    assert!(parse_as("sphere me stone 10000", 1).is_err());
+    assert!(parse("portal 1 10000 0 0 0").is_err());
+  }
+}
@@ -0,0 +1,147 @@
+//! Components of players and mobs that other systems read, each behind its own lock. Looking up where a player is or
+//! which client controls it shouldn't need the whole `players` map, which is locked for as long as players are being
+//! simulated.
+//! Players and mobs still own their state; these are copies, updated whenever the owner moves or changes hands.
+//! Component locks are innermost: don't take any other lock while holding one.
+
+use cgmath::{Point3, Vector3};
+use collision::Aabb3;
+use std;
+use std::sync::Mutex;
+
+use common::fnv_map;
+use common::fnv_set;
+use common::protocol;
+use common::protocol::EntityRef;
+
+#[allow(missing_docs)]
+pub struct Store<C> {
+  components: fnv_map::T<EntityRef, C>,
+}
+
+#[allow(missing_docs)]
+pub fn new_store<C>() -> Store<C> {
+  Store {
+    components: fnv_map::new(),
+  }
+}
+
+impl<C> Store<C> {
+  #[allow(missing_docs)]
+  pub fn get(&self, entity: EntityRef) -> Option<&C> {
+    self.components.get(&entity)
+  }
+
+  /// Set an entity's component, replacing any it already had.
+  pub fn insert(&mut self, entity: EntityRef, component: C) {
+    self.components.insert(entity, component);
+  }
+
+  #[allow(missing_docs)]
+  pub fn remove(&mut self, entity: EntityRef) -> Option<C> {
+    self.components.remove(&entity)
+  }
+
+  #[allow(missing_docs)]
+  pub fn iter(&self) -> std::collections::hash_map::Iter<EntityRef, C> {
+    self.components.iter()
+  }
+}
+
+#[allow(missing_docs)]
+pub struct T {
+  /// The centers of entities' bounds.
+  pub positions   : Mutex<Store<Point3<f32>>>,
+  /// Entities' speeds, in world units per step.
+  pub velocities  : Mutex<Store<Vector3<f32>>>,
+  pub bounds      : Mutex<Store<Aabb3<f32>>>,
+  /// The clients controlling players.
+  pub controllers : Mutex<Store<protocol::ClientId>>,
+  /// Players whose clients are away from the keyboard.
+  pub away        : Mutex<fnv_set::T<EntityRef>>,
+}
+
+#[allow(missing_docs)]
+pub fn new() -> T {
+  T {
+    positions   : Mutex::new(new_store()),
+    velocities  : Mutex::new(new_store()),
+    bounds      : Mutex::new(new_store()),
+    controllers : Mutex::new(new_store()),
+    away        : Mutex::new(fnv_set::new()),
+  }
+}
+
+impl T {
+  /// Record where an entity is now, and how fast it's going.
+  pub fn moved(&self, entity: EntityRef, bounds: &Aabb3<f32>, velocity: Vector3<f32>) {
+    let center = bounds.min + (bounds.max - bounds.min) * 0.5;
+    self.positions.lock().unwrap().insert(entity, center);
+    self.velocities.lock().unwrap().insert(entity, velocity);
+    self.bounds.lock().unwrap().insert(entity, *bounds);
+  }
+
+  /// Forget every component of an entity that's left the world.
+  pub fn remove(&self, entity: EntityRef) {
+    self.positions.lock().unwrap().remove(entity);
+    self.velocities.lock().unwrap().remove(entity);
+    self.bounds.lock().unwrap().remove(entity);
+    self.controllers.lock().unwrap().remove(entity);
+    self.away.lock().unwrap().remove(&entity);
+  }
+
+  /// The client controlling a player, if the player's still around.
+  pub fn controller(&self, entity: EntityRef) -> Option<protocol::ClientId> {
+    self.controllers.lock().unwrap().get(entity).cloned()
+  }
+
+  /// Where every player is, except those that are away from the keyboard: they don't keep the world around them
+  /// running.
+  pub fn player_positions(&self) -> Vec<Point3<f32>> {
+    let away = self.away.lock().unwrap().clone();
+    self.positions.lock().unwrap().iter()
+      .filter(|&(entity, _)| match *entity { EntityRef::Player(_) => true, EntityRef::Mob(_) => false })
+      .filter(|&(entity, _)| !away.contains(entity))
+      .map(|(_, position)| *position)
+      .collect()
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use cgmath::{Point3, Vector3};
+  use collision::Aabb3;
+
+  use common::entity;
+  use common::id_allocator;
+  use common::protocol;
+  use common::protocol::EntityRef;
+
+  use super::*;
+
+  #[test]
+  fn test_moved_and_removed() {
+    let mut players: id_allocator::T<entity::id::Player> = id_allocator::new();
+    let mut mobs: id_allocator::T<entity::id::Mob> = id_allocator::new();
+    let player = EntityRef::Player(players.allocate());
+    let mob = EntityRef::Mob(mobs.allocate());
+
+    let components = new();
+    let bounds = Aabb3::new(Point3::new(0.0, 0.0, 0.0), Point3::new(1.0, 2.0, 1.0));
+    components.moved(player, &bounds, Vector3::new(0.0, -0.1, 0.0));
+    components.moved(mob, &bounds, Vector3::new(0.0, 0.0, 0.0));
+    let mut clients: id_allocator::T<protocol::ClientId> = id_allocator::new();
+    let client = clients.allocate();
+    components.controllers.lock().unwrap().insert(player, client);
+
+    assert_eq!(components.player_positions(), vec!(Point3::new(0.5, 1.0, 0.5)));
+    assert_eq!(components.controller(player), Some(client));
+    components.away.lock().unwrap().insert(player);
+    assert_eq!(components.player_positions(), vec!());
+
+    components.remove(player);
+    assert_eq!(components.player_positions(), vec!());
+    assert_eq!(components.controller(player), None);
+    assert_eq!(components.positions.lock().unwrap().iter().count(), 1);
+  }
+}
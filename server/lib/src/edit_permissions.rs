@@ -0,0 +1,272 @@
+//! Deciding whether a terrain edit is allowed. Every brush is checked here before it's applied.
+//! Checks are consulted in order, and the first one with an opinion decides. Overrides (e.g. from scripts or game
+//! modes) go ahead of the built-in claims, world border and game mode checks, so they can allow what those would
+//! deny, or the other way around. An edit nobody objects to is allowed.
+
+use cgmath::Point3;
+use collision::{Aabb3};
+
+use common::voxel;
+
+use entity;
+
+/// Claims are made in whole blocks of this many voxels on a side, the same blocks clients load terrain in.
+pub const BLOCK_WIDTH: i32 = 8;
+
+/// Who's making an edit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Actor {
+  /// The server console.
+  Console,
+  /// A player, by digging, planting or running a command.
+  Player(entity::id::Player),
+}
+
+/// An edit that's about to be made.
+#[derive(Debug, Clone, Copy)]
+pub struct Edit<'a> {
+  #[allow(missing_docs)]
+  pub actor     : Actor,
+  /// The region the brush can touch.
+  pub bounds    : &'a Aabb3<i32>,
+  /// The materials the brush can leave behind (`Empty` for digging).
+  pub materials : &'a [voxel::Material],
+}
+
+/// What a check thinks of an edit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Decision {
+  #[allow(missing_docs)]
+  Allow,
+  /// Refuse the edit, with a reason to give the actor.
+  Deny(String),
+  /// Leave it to the next check.
+  Abstain,
+}
+
+/// Something that gets a say in whether edits happen.
+pub trait Check {
+  #[allow(missing_docs)]
+  fn check(&self, edit: &Edit) -> Decision;
+}
+
+fn overlaps(a: &Aabb3<i32>, b: &Aabb3<i32>) -> bool {
+  a.min.x < b.max.x && b.min.x < a.max.x &&
+  a.min.y < b.max.y && b.min.y < a.max.y &&
+  a.min.z < b.max.z && b.min.z < a.max.z
+}
+
+fn within(inner: &Aabb3<i32>, outer: &Aabb3<i32>) -> bool {
+  outer.min.x <= inner.min.x && inner.max.x <= outer.max.x &&
+  outer.min.y <= inner.min.y && inner.max.y <= outer.max.y &&
+  outer.min.z <= inner.min.z && inner.max.z <= outer.max.z
+}
+
+/// The voxels in a region of blocks. `blocks` is in block coordinates, and includes its `min` but not its `max`.
+pub fn of_blocks(blocks: &Aabb3<i32>) -> Aabb3<i32> {
+  let w = BLOCK_WIDTH;
+  Aabb3::new(
+    Point3::new(blocks.min.x * w, blocks.min.y * w, blocks.min.z * w),
+    Point3::new(blocks.max.x * w, blocks.max.y * w, blocks.max.z * w),
+  )
+}
+
+/// Regions of blocks that only their owner (or the console) can edit.
+pub struct Claims {
+  claims: Vec<(entity::id::Player, Aabb3<i32>)>,
+}
+
+impl Claims {
+  /// Give `owner` sole rights to edit a region of blocks (see `of_blocks`). Regions can't overlap anybody else's.
+  pub fn claim(&mut self, owner: entity::id::Player, blocks: &Aabb3<i32>) -> Result<(), String> {
+    let bounds = of_blocks(blocks);
+    for &(o, ref claimed) in &self.claims {
+      if o != owner && overlaps(claimed, &bounds) {
+        return Err(format!("Part of that area already belongs to player {}", o.to_u32()))
+      }
+    }
+    self.claims.push((owner, bounds));
+    Ok(())
+  }
+
+  /// Drop every claim held by `owner`.
+  pub fn release(&mut self, owner: entity::id::Player) {
+    self.claims.retain(|&(o, _)| o != owner);
+  }
+}
+
+impl Check for Claims {
+  fn check(&self, edit: &Edit) -> Decision {
+    let player =
+      match edit.actor {
+        Actor::Console => return Decision::Abstain,
+        Actor::Player(player) => player,
+      };
+    for &(owner, ref bounds) in &self.claims {
+      if owner != player && overlaps(bounds, edit.bounds) {
+        return Decision::Deny(format!("That area belongs to player {}", owner.to_u32()))
+      }
+    }
+    Decision::Abstain
+  }
+}
+
+/// Players can't edit outside this region.
+pub struct WorldBorder {
+  #[allow(missing_docs)]
+  pub bounds: Aabb3<i32>,
+}
+
+impl Check for WorldBorder {
+  fn check(&self, edit: &Edit) -> Decision {
+    if edit.actor == Actor::Console {
+      return Decision::Abstain
+    }
+    if within(edit.bounds, &self.bounds) {
+      Decision::Abstain
+    } else {
+      Decision::Deny(String::from("That's past the edge of the world"))
+    }
+  }
+}
+
+/// What players are allowed to do to the terrain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GameMode {
+  /// Anything goes.
+  Creative,
+  /// Players can dig and plant trees, but can't conjure up other materials.
+  Survival,
+  /// Players can look, but not touch.
+  Spectator,
+}
+
+impl Check for GameMode {
+  fn check(&self, edit: &Edit) -> Decision {
+    if edit.actor == Actor::Console {
+      return Decision::Abstain
+    }
+    match *self {
+      GameMode::Creative => Decision::Abstain,
+      GameMode::Spectator => Decision::Deny(String::from("Terrain can't be edited in spectator mode")),
+      GameMode::Survival => {
+        let natural =
+          edit.materials.iter().all(|&m| {
+            m == voxel::Material::Empty || m == voxel::Material::Bark || m == voxel::Material::Leaves
+          });
+        if natural {
+          Decision::Abstain
+        } else {
+          Decision::Deny(String::from("Those materials can't be placed in survival mode"))
+        }
+      },
+    }
+  }
+}
+
+/// Every check on terrain edits.
+pub struct T {
+  overrides     : Vec<Box<Check + Send>>,
+  #[allow(missing_docs)]
+  pub claims    : Claims,
+  #[allow(missing_docs)]
+  pub border    : WorldBorder,
+  #[allow(missing_docs)]
+  pub game_mode : GameMode,
+}
+
+/// Edits are allowed anywhere inside `border`, by anybody, until claims, overrides or a game mode say otherwise.
+pub fn new(border: Aabb3<i32>) -> T {
+  T {
+    overrides : Vec::new(),
+    claims    : Claims { claims: Vec::new() },
+    border    : WorldBorder { bounds: border },
+    game_mode : GameMode::Creative,
+  }
+}
+
+impl T {
+  /// Add a check that's consulted before the built-in ones (and before any overrides added earlier).
+  pub fn push_override(&mut self, check: Box<Check + Send>) {
+    self.overrides.insert(0, check);
+  }
+
+  /// Decide whether an edit can go ahead. `Err` has the reason it can't.
+  pub fn decide(&self, edit: &Edit) -> Result<(), String> {
+    let built_in: [&Check; 3] = [&self.claims, &self.border, &self.game_mode];
+    let checks = self.overrides.iter().map(|check| &**check as &Check).chain(built_in.iter().map(|&check| check));
+    for check in checks {
+      match check.check(edit) {
+        Decision::Abstain => {},
+        Decision::Allow => return Ok(()),
+        Decision::Deny(reason) => return Err(reason),
+      }
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use cgmath::Point3;
+  use collision::Aabb3;
+
+  use common::voxel;
+
+  use entity;
+
+  use super::*;
+
+  fn cube(low: i32, high: i32) -> Aabb3<i32> {
+    Aabb3::new(Point3::new(low, low, low), Point3::new(high, high, high))
+  }
+
+  fn edit<'a>(actor: Actor, bounds: &'a Aabb3<i32>, materials: &'a [voxel::Material]) -> Edit<'a> {
+    Edit {
+      actor     : actor,
+      bounds    : bounds,
+      materials : materials,
+    }
+  }
+
+  struct AllowEverything;
+
+  impl Check for AllowEverything {
+    fn check(&self, _: &Edit) -> Decision {
+      Decision::Allow
+    }
+  }
+
+  #[test]
+  fn test_built_in_checks() {
+    let alice_id: entity::id::Player = Default::default();
+    let alice = Actor::Player(alice_id);
+    let bob = Actor::Player(alice_id + 1);
+    let stone = [voxel::Material::Stone];
+
+    let mut permissions = new(cube(-100, 100));
+    permissions.claims.claim(alice_id, &cube(0, 2)).unwrap();
+    assert!(permissions.claims.claim(alice_id + 1, &cube(1, 3)).is_err());
+
+    assert!(permissions.decide(&edit(alice, &cube(5, 6), &stone)).is_ok());
+    assert!(permissions.decide(&edit(bob, &cube(5, 6), &stone)).is_err());
+    assert!(permissions.decide(&edit(bob, &cube(20, 30), &stone)).is_ok());
+    assert!(permissions.decide(&edit(bob, &cube(90, 110), &stone)).is_err());
+    assert!(permissions.decide(&edit(Actor::Console, &cube(5, 200), &stone)).is_ok());
+
+    permissions.game_mode = GameMode::Survival;
+    assert!(permissions.decide(&edit(bob, &cube(20, 30), &stone)).is_err());
+    assert!(permissions.decide(&edit(bob, &cube(20, 30), &[voxel::Material::Empty])).is_ok());
+  }
+
+  #[test]
+  fn test_overrides_go_first() {
+    let bob_id: entity::id::Player = Default::default();
+    let bob = Actor::Player(bob_id);
+    let mut permissions = new(cube(-100, 100));
+    permissions.game_mode = GameMode::Spectator;
+    assert!(permissions.decide(&edit(bob, &cube(0, 1), &[])).is_err());
+    permissions.push_override(Box::new(AllowEverything));
+    assert!(permissions.decide(&edit(bob, &cube(0, 1), &[])).is_ok());
+  }
+}
@@ -0,0 +1,42 @@
+//! Things that can go wrong while dealing with a client. They cost that client its connection, not the server its
+//! life: messages can still be in flight from clients that have left, and clients can vanish without saying goodbye.
+
+use std;
+
+use common::protocol;
+
+use entity;
+
+#[derive(Debug)]
+#[allow(missing_docs)]
+pub enum T {
+  /// A message couldn't be encoded to send.
+  Encode(String),
+  /// A client's URL couldn't be connected to, and why.
+  Connect(String, String),
+  /// A message couldn't be sent to a client, and why.
+  Send(String),
+  UnknownClient(protocol::ClientId),
+  UnknownPlayer(entity::id::Player),
+  /// A player that isn't being controlled by any client.
+  Uncontrolled(entity::id::Player),
+  /// A request that didn't come with the secret of the client it's on behalf of.
+  Forged(protocol::ClientId),
+}
+
+#[allow(missing_docs)]
+pub type Result<X> = std::result::Result<X, T>;
+
+impl std::fmt::Display for T {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    match *self {
+      T::Encode(ref err) => write!(f, "Couldn't encode message: {}", err),
+      T::Connect(ref url, ref err) => write!(f, "Couldn't connect to {}: {}", url, err),
+      T::Send(ref err) => write!(f, "Couldn't send to client: {}", err),
+      T::UnknownClient(client_id) => write!(f, "Unknown client {:?}", client_id),
+      T::UnknownPlayer(player_id) => write!(f, "Unknown player {:?}", player_id),
+      T::Uncontrolled(player_id) => write!(f, "Player {:?} has no client", player_id),
+      T::Forged(client_id) => write!(f, "Request for client {:?} without its secret", client_id),
+    }
+  }
+}
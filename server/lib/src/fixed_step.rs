@@ -0,0 +1,76 @@
+//! Run the simulation in fixed-size steps, however often it gets the chance to run.
+//! Real time is accumulated and spent in whole steps, so movement doesn't depend on how quickly the server loop spins.
+
+/// Simulation steps per second. Per-step constants (player acceleration, friction, jump fuel, ...) are tuned for this.
+pub const STEPS_PER_SECOND: u64 = 30;
+/// The length of one step, in nanoseconds.
+pub const STEP_NS: u64 = 1_000_000_000 / STEPS_PER_SECOND;
+/// The most steps to take at once. If the server falls further behind than this, the rest of the backlog is dropped
+/// (so the world slows down) rather than making the next update even slower.
+pub const MAX_STEPS: u32 = 8;
+
+/// Real time that hasn't been simulated yet.
+pub struct T {
+  last_ns        : u64,
+  accumulated_ns : u64,
+  /// How many steps have been taken so far.
+  steps          : u64,
+}
+
+#[allow(missing_docs)]
+pub fn new(now_ns: u64) -> T {
+  T {
+    last_ns        : now_ns,
+    accumulated_ns : 0,
+    steps          : 0,
+  }
+}
+
+impl T {
+  /// Account for the time up to `now_ns`, and return how many steps to simulate.
+  pub fn advance(&mut self, now_ns: u64) -> u32 {
+    self.accumulated_ns += now_ns.saturating_sub(self.last_ns);
+    self.last_ns = now_ns;
+
+    let steps = self.accumulated_ns / STEP_NS;
+    self.accumulated_ns -= steps * STEP_NS;
+    let steps =
+      if steps > MAX_STEPS as u64 {
+        warn!("Simulation is {} steps behind; dropping {}", steps, steps - MAX_STEPS as u64);
+        MAX_STEPS
+      } else {
+        steps as u32
+      };
+    self.steps += steps as u64;
+    steps
+  }
+
+  /// How many steps have been handed out so far. Replays use this to put client messages in order with the world.
+  pub fn steps(&self) -> u64 {
+    self.steps
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_leftover_time_carries_over() {
+    let mut clock = new(0);
+    assert_eq!(clock.advance(STEP_NS / 2), 0);
+    assert_eq!(clock.advance(STEP_NS), 1);
+    assert_eq!(clock.advance(2 * STEP_NS), 1);
+    assert_eq!(clock.advance(2 * STEP_NS + STEP_NS / 2), 1);
+    assert_eq!(clock.advance(5 * STEP_NS), 2);
+    assert_eq!(clock.steps(), 5);
+  }
+
+  #[test]
+  fn test_backlog_is_capped() {
+    let mut clock = new(0);
+    assert_eq!(clock.advance(100 * STEP_NS), MAX_STEPS);
+    assert_eq!(clock.advance(101 * STEP_NS), 1);
+    assert_eq!(clock.steps(), MAX_STEPS as u64 + 1);
+  }
+}
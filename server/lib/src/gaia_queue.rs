@@ -0,0 +1,144 @@
+//! Queue of updates for gaia, where newer client block requests supersede older ones for the same region. Edits are
+//! kept in order in a queue of their own, and take turns with loads, so a big edit being applied a slice at a time
+//! doesn't starve the loads, and vice versa.
+
+use cgmath::Point3;
+use std::collections::VecDeque;
+
+use common::fnv_map;
+use common::protocol;
+use common::voxel;
+
+use update_gaia;
+use update_gaia::LoadDestination;
+
+// Twice the center of the region covered by a request. A client re-requesting the same region at a different
+// LOD pads it by a different voxel size, but the center stays put.
+type Region = Point3<i32>;
+
+fn region_of(voxels: &[voxel::bounds::T]) -> Option<Region> {
+  let mut iter = voxels.iter();
+  let (mut low, mut high) =
+    match iter.next() {
+      None => return None,
+      Some(bounds) => bounds.corners(),
+    };
+  for bounds in iter {
+    let (l, h) = bounds.corners();
+    low.x = f32::min(low.x, l.x);
+    low.y = f32::min(low.y, l.y);
+    low.z = f32::min(low.z, l.z);
+    high.x = f32::max(high.x, h.x);
+    high.y = f32::max(high.y, h.y);
+    high.z = f32::max(high.z, h.z);
+  }
+  Some(
+    Point3::new(
+      (low.x + high.x).floor() as i32,
+      (low.y + high.y).floor() as i32,
+      (low.z + high.z).floor() as i32,
+    )
+  )
+}
+
+fn client_request(up: &update_gaia::Message) -> Option<(protocol::ClientId, Region)> {
+  match *up {
+    update_gaia::Message::Load(_, ref voxels, LoadDestination::Client(client_id)) => {
+      region_of(voxels).map(|region| (client_id, region))
+    },
+    _ => None,
+  }
+}
+
+fn is_edit(up: &update_gaia::Message) -> bool {
+  match *up {
+    update_gaia::Message::Load(..) => false,
+    update_gaia::Message::Brush(..) |
+    update_gaia::Message::ResumeBrush(..) |
+    update_gaia::Message::Paste(..) |
+    update_gaia::Message::Plant(..) |
+    update_gaia::Message::PlaceRock(..) => true,
+  }
+}
+
+pub struct T {
+  queue     : VecDeque<update_gaia::Message>,
+  // The sequence number of the front of `queue`.
+  front     : u64,
+  // The sequence number of the latest queued request from each client for each region.
+  pending   : fnv_map::T<(protocol::ClientId, Region), u64>,
+  edits     : VecDeque<update_gaia::Message>,
+  // Whether the next pop should come from `edits`, if there are any.
+  edit_turn : bool,
+}
+
+pub fn new() -> T {
+  T {
+    queue     : VecDeque::new(),
+    front     : 0,
+    pending   : fnv_map::new(),
+    edits     : VecDeque::new(),
+    edit_turn : true,
+  }
+}
+
+impl T {
+  pub fn len(&self) -> usize {
+    self.queue.len() + self.edits.len()
+  }
+
+  /// Whether a brush has been partly applied, and the rest of it is waiting its turn.
+  pub fn brush_in_progress(&self) -> bool {
+    match self.edits.front() {
+      Some(&update_gaia::Message::ResumeBrush(..)) => true,
+      _ => false,
+    }
+  }
+
+  /// Put back the rest of an update that was only partly applied. It goes ahead of other edits, so edits still happen
+  /// in the order they were made.
+  pub fn resume(&mut self, up: update_gaia::Message) {
+    self.edits.push_front(up);
+  }
+
+  pub fn push_back(&mut self, up: update_gaia::Message) {
+    if is_edit(&up) {
+      self.edits.push_back(up);
+      return
+    }
+    let seq = self.front + self.queue.len() as u64;
+    if let Some(key) = client_request(&up) {
+      if let Some(old_seq) = self.pending.insert(key, seq) {
+        // Don't generate the stale request, but still answer it (with no voxels), so the client can account for it.
+        let old = &mut self.queue[(old_seq - self.front) as usize];
+        if let update_gaia::Message::Load(_, ref mut voxels, _) = *old {
+          debug!("Superseding a request for {} voxels", voxels.len());
+          voxels.clear();
+        }
+      }
+    }
+    self.queue.push_back(up);
+  }
+
+  pub fn pop_front(&mut self) -> Option<update_gaia::Message> {
+    let edit_turn = self.edit_turn;
+    self.edit_turn = !edit_turn;
+    if edit_turn || self.queue.is_empty() {
+      if let Some(up) = self.edits.pop_front() {
+        return Some(up)
+      }
+    }
+
+    let up = self.queue.pop_front();
+    let seq = self.front;
+    if let Some(ref up) = up {
+      self.front += 1;
+      if let Some(key) = client_request(up) {
+        if self.pending.get(&key) == Some(&seq) {
+          self.pending.remove(&key);
+        }
+      }
+    }
+    up
+  }
+}
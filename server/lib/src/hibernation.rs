@@ -0,0 +1,316 @@
+//! Regions of the world that no player has been near for a while are hibernated: their mobs are written out to disk
+//! and dropped from memory, and read back in when a player comes within the simulation distance again. This keeps a
+//! long-running server's memory bounded by where players are, not by everywhere they've been.
+
+use cgmath::{Point3, Vector3, EuclideanSpace};
+use collision::Aabb3;
+use std;
+use std::io::{Read, Write};
+
+use common::cube_shell::cube_shell;
+use common::fnv_map;
+use common::protocol::EntityRef;
+use common::surroundings_loader;
+use common::voxel;
+
+use entity;
+use init_mobs;
+use mob;
+use server;
+
+/// Width of a hibernation region, in world units.
+const REGION_WIDTH: f32 = 64.0;
+/// How long a region has to go without a player within the simulation distance before it's hibernated.
+const HIBERNATE_AFTER_NS: u64 = 5 * 60 * 1_000_000_000;
+/// How far around them mobs keep terrain loaded (in blocks).
+const MOB_LOAD_DISTANCE: u32 = 8;
+/// position, speed.
+const RECORD_BYTES: usize = 6 * 4;
+
+/// Identifies a region: its position in units of `REGION_WIDTH`.
+type Region = Point3<i32>;
+
+fn region_of(position: &Point3<f32>) -> Region {
+  Point3::new(
+    (position.x / REGION_WIDTH).floor() as i32,
+    (position.y / REGION_WIDTH).floor() as i32,
+    (position.z / REGION_WIDTH).floor() as i32,
+  )
+}
+
+/// Whether a player at `position` is within `distance` (along any axis) of any part of a region.
+fn is_near(region: &Region, position: &Point3<f32>, distance: f32) -> bool {
+  let low = Point3::new(region.x as f32, region.y as f32, region.z as f32) * REGION_WIDTH;
+  let gap = |p: f32, low: f32| f32::max(0.0, f32::max(low - p, p - (low + REGION_WIDTH)));
+  gap(position.x, low.x) <= distance && gap(position.y, low.y) <= distance && gap(position.z, low.z) <= distance
+}
+
+/// The parts of a mob that outlive hibernation. Everything else is rebuilt when it wakes up.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Record {
+  position : Point3<f32>,
+  speed    : Vector3<f32>,
+}
+
+fn encode(records: &[Record]) -> Vec<u8> {
+  let mut bytes = Vec::with_capacity(records.len() * RECORD_BYTES);
+  for record in records {
+    let floats =
+      [record.position.x, record.position.y, record.position.z, record.speed.x, record.speed.y, record.speed.z];
+    for f in &floats {
+      let x = f.to_bits();
+      for i in 0 .. 4 {
+        bytes.push((x >> (8 * i)) as u8);
+      }
+    }
+  }
+  bytes
+}
+
+fn decode(bytes: &[u8]) -> Result<Vec<Record>, String> {
+  if bytes.len() % RECORD_BYTES != 0 {
+    return Err(format!("{} bytes isn't a whole number of records", bytes.len()))
+  }
+  let get = |record: &[u8], i: usize| {
+    let x = (0 .. 4).fold(0, |x, j| x | ((record[4 * i + j] as u32) << (8 * j)));
+    f32::from_bits(x)
+  };
+  let records =
+    bytes.chunks(RECORD_BYTES)
+    .map(|record| {
+      Record {
+        position : Point3::new(get(record, 0), get(record, 1), get(record, 2)),
+        speed    : Vector3::new(get(record, 3), get(record, 4), get(record, 5)),
+      }
+    })
+    .collect();
+  Ok(records)
+}
+
+#[allow(missing_docs)]
+pub struct T {
+  /// Where hibernated regions are written.
+  dir         : std::path::PathBuf,
+  /// When each region with mobs in it last had a player nearby, if it doesn't now.
+  idle_since  : fnv_map::T<Region, u64>,
+  /// The hibernated regions, and the ids of their mobs (in the same order as the records on disk), so clients see the
+  /// same mobs again when they wake up.
+  hibernating : fnv_map::T<Region, Vec<entity::id::Mob>>,
+}
+
+/// Hibernate regions into `dir`. Anything left there from an earlier run is cleared out, since the mobs from that
+/// run are long gone.
+pub fn new(dir: std::path::PathBuf) -> T {
+  if let Err(err) = std::fs::remove_dir_all(&dir) {
+    if err.kind() != std::io::ErrorKind::NotFound {
+      warn!("Couldn't clear out {:?}: {:?}", dir, err);
+    }
+  }
+  T {
+    dir         : dir,
+    idle_since  : fnv_map::new(),
+    hibernating : fnv_map::new(),
+  }
+}
+
+impl T {
+  fn path(&self, region: &Region) -> std::path::PathBuf {
+    self.dir.join(format!("{}_{}_{}", region.x, region.y, region.z))
+  }
+
+  #[allow(missing_docs)]
+  pub fn hibernating_count(&self) -> usize {
+    self.hibernating.len()
+  }
+}
+
+/// Stop keeping terrain loaded around a mob.
+fn release_terrain(server: &server::T, mob: &mob::Mob) {
+  let center = Point3::new(mob.position.x as i32, mob.position.y as i32, mob.position.z as i32);
+  for radius in 0 .. MOB_LOAD_DISTANCE as i32 {
+    for pos in cube_shell(&center, radius) {
+      let pos = voxel::bounds::new(pos.x, pos.y, pos.z, 0);
+      server.terrain_loader.unload(&server.physics, &pos, mob.owner_id);
+    }
+  }
+}
+
+/// Hibernate the mobs in a region. If it's already hibernating (a mob's wandered into it since), they're added to the
+/// ones already there.
+fn hibernate(server: &server::T, hibernation: &mut T, region: Region) -> std::io::Result<()> {
+  let mut mobs = server.mobs.lock().unwrap();
+  let ids: Vec<entity::id::Mob> =
+    mobs.iter()
+    .filter(|&(_, mob)| region_of(&mob.position) == region)
+    .map(|(&id, _)| id)
+    .collect();
+  let records: Vec<Record> =
+    ids.iter()
+    .map(|id| {
+      let mob = &mobs[id];
+      Record { position: mob.position, speed: mob.speed }
+    })
+    .collect();
+
+  try!(std::fs::create_dir_all(&hibernation.dir));
+  // Records are all the same size, so appending keeps them in step with the ids.
+  let already_hibernating = hibernation.hibernating.contains_key(&region);
+  let mut file =
+    try!(
+      std::fs::OpenOptions::new()
+      .write(true)
+      .create(true)
+      .append(already_hibernating)
+      .truncate(!already_hibernating)
+      .open(hibernation.path(&region))
+    );
+  try!(file.write_all(&encode(&records)));
+
+  for id in &ids {
+    let mob = mobs.remove(id).unwrap();
+    release_terrain(server, &mob);
+    server.physics.lock().unwrap().remove_misc(mob.physics_id);
+    server.attachments.lock().unwrap().detach_all(EntityRef::Mob(*id));
+    server.components.remove(EntityRef::Mob(*id));
+  }
+  debug!("Hibernated {} mobs in {:?}", ids.len(), region);
+  hibernation.hibernating.entry(region).or_insert_with(Vec::new).extend(ids);
+  Ok(())
+}
+
+fn wake(server: &server::T, hibernation: &mut T, region: Region) -> Result<(), String> {
+  let path = hibernation.path(&region);
+  let mut bytes = Vec::new();
+  try!(
+    std::fs::File::open(&path)
+    .and_then(|mut file| file.read_to_end(&mut bytes))
+    .map_err(|err| format!("{:?}", err))
+  );
+  let records = try!(decode(&bytes));
+  let ids = hibernation.hibernating.remove(&region).unwrap();
+  if records.len() != ids.len() {
+    return Err(format!("{} records for {} mobs", records.len(), ids.len()))
+  }
+
+  for (id, record) in ids.into_iter().zip(records.into_iter()) {
+    let half_extents = Vector3::new(0.5, 1.0, 0.5);
+    let bounds = Aabb3::new(record.position + -half_extents, record.position + half_extents);
+    let physics_id = server.misc_allocator.lock().unwrap().allocate();
+    let mob =
+      mob::Mob {
+        position            : record.position,
+        speed               : record.speed,
+        behavior            : init_mobs::mob_behavior,
+        entity_id           : id,
+        physics_id          : physics_id,
+        owner_id            : server.owner_allocator.lock().unwrap().allocate(),
+        surroundings_loader : surroundings_loader::new(MOB_LOAD_DISTANCE, Vec::new()),
+      };
+    server.physics.lock().unwrap().insert_misc(physics_id, &bounds);
+    server.components.moved(EntityRef::Mob(id), &bounds, record.speed);
+    server.mobs.lock().unwrap().insert(id, mob);
+  }
+  debug!("Woke up {:?}", region);
+
+  if let Err(err) = std::fs::remove_file(&path) {
+    warn!("Couldn't remove {:?}: {:?}", path, err);
+  }
+  Ok(())
+}
+
+/// Hibernate regions that have been idle for long enough, and wake up any that players have come near.
+pub fn update(server: &server::T, now_ns: u64) {
+  let players = server.components.player_positions();
+  let distance = server.distances.simulation;
+  let is_idle = |region: &Region| !players.iter().any(|p| is_near(region, p, distance));
+
+  let mut hibernation = server.hibernation.lock().unwrap();
+
+  let waking: Vec<Region> = hibernation.hibernating.keys().filter(|&r| !is_idle(r)).cloned().collect();
+  for region in waking {
+    if let Err(err) = wake(server, &mut hibernation, region) {
+      warn!("Couldn't wake up {:?}; its mobs are lost: {}", region, err);
+    }
+  }
+
+  let occupied: Vec<Region> =
+    server.components.positions.lock().unwrap().iter()
+    .filter(|&(entity, _)| match *entity { EntityRef::Mob(_) => true, EntityRef::Player(_) => false })
+    .map(|(_, position)| region_of(position))
+    .collect();
+  let mut idle_since = fnv_map::new();
+  for region in occupied {
+    if is_idle(&region) {
+      let since = hibernation.idle_since.get(&region).cloned().unwrap_or(now_ns);
+      idle_since.insert(region, since);
+    }
+  }
+  hibernation.idle_since = idle_since;
+
+  let due: Vec<Region> =
+    hibernation.idle_since.iter()
+    .filter(|&(_, &since)| now_ns - since >= HIBERNATE_AFTER_NS)
+    .map(|(&region, _)| region)
+    .collect();
+  for region in due {
+    hibernation.idle_since.remove(&region);
+    if let Err(err) = hibernate(server, &mut hibernation, region) {
+      warn!("Couldn't hibernate {:?}: {:?}", region, err);
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use cgmath::{Point3, Vector3};
+  use std;
+
+  use entity;
+  use server;
+
+  use super::*;
+
+  #[test]
+  fn test_encode_decode() {
+    let records =
+      vec!(
+        Record { position: Point3::new(1.5, -64.0, 3.25), speed: Vector3::new(0.0, -0.1, 0.5) },
+        Record { position: Point3::new(-700.0, 2.0, 0.0), speed: Vector3::new(1.0, 2.0, 3.0) },
+      );
+    assert_eq!(decode(&encode(&records)), Ok(records));
+    assert!(decode(&[0; RECORD_BYTES + 1]).is_err());
+  }
+
+  #[test]
+  fn test_is_near() {
+    let region = region_of(&Point3::new(10.0, 10.0, 10.0));
+    assert_eq!(region, Point3::new(0, 0, 0));
+    assert!(is_near(&region, &Point3::new(32.0, 0.0, 32.0), 0.0));
+    assert!(is_near(&region, &Point3::new(-10.0, 0.0, 70.0), 10.0));
+    assert!(!is_near(&region, &Point3::new(-10.0, 0.0, 80.0), 10.0));
+  }
+
+  #[test]
+  // Hibernating a region again keeps the mobs that were already hibernating there.
+  fn test_hibernate_twice() {
+    let dir = std::env::temp_dir().join("playform_hibernation_test");
+    let server = server::new();
+    let mut hibernation = new(dir.clone());
+    let ids: Vec<entity::id::Mob> = server.mobs.lock().unwrap().keys().cloned().collect();
+    assert!(!ids.is_empty());
+
+    let region = Point3::new(100, 100, 100);
+    for id in &ids {
+      server.mobs.lock().unwrap().get_mut(id).unwrap().position = Point3::new(100.5, 100.5, 100.5) * REGION_WIDTH;
+    }
+    hibernate(&server, &mut hibernation, region).unwrap();
+    hibernate(&server, &mut hibernation, region).unwrap();
+    assert_eq!(hibernation.hibernating[&region], ids);
+
+    wake(&server, &mut hibernation, region).unwrap();
+    for id in &ids {
+      assert!(server.mobs.lock().unwrap().contains_key(id));
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+}
@@ -0,0 +1,297 @@
+//! Server operators' hooks: a small script of rules for what happens when players join, on timers, and when terrain
+//! is edited, so behavior can be added to a server without rebuilding it. Scripts have one rule per line, e.g.
+//!
+//! ```text
+//! # Comments start with a hash.
+//! join: say Welcome, player $player!
+//! join: run beacon $player
+//! every 600: say Remember to claim your builds.
+//! every 3600: run simulate 1
+//! edit: deny marble Marble is reserved for the spawn.
+//! edit: deny below -64 The deep is off limits.
+//! ```
+//!
+//! `say` announces something to every client, and `run` runs a command as the console (see `command::parse`), so
+//! scripts can do anything an admin can. `$player` is replaced by the id of the player who set the hook off.
+//! `edit` rules are checked ahead of claims and the game mode, and don't apply to the console.
+
+use std;
+
+use common::line_file;
+use common::protocol;
+use common::voxel;
+
+use command;
+use edit_permissions;
+use edit_permissions::{Actor, Decision};
+use entity;
+use server;
+use update_gaia;
+
+/// Given to players when an `edit` rule doesn't say why.
+const DEFAULT_DENY_REASON: &str = "That edit isn't allowed here";
+
+/// Something a hook does.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Action {
+  /// Announce something to every client.
+  Say(String),
+  /// Run a command, as the console.
+  Run(String),
+}
+
+/// Edits that an `edit` rule refuses.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Deny {
+  /// Edits that can leave some material behind.
+  Material(voxel::Material),
+  /// Edits that reach below some height.
+  Below(i32),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Rule {
+  Join(Action),
+  /// Run an action every so many seconds.
+  Every(u64, Action),
+  /// Refuse some edits, with a reason.
+  Edit(Deny, String),
+}
+
+struct Timer {
+  period_ns : u64,
+  /// When the timer next goes off, once it's been started.
+  next_ns   : Option<u64>,
+  action    : Action,
+}
+
+#[allow(missing_docs)]
+pub struct T {
+  join   : Vec<Action>,
+  timers : Vec<Timer>,
+}
+
+/// The script's `edit` rules, as a check on edits.
+pub struct EditRules {
+  rules: Vec<(Deny, String)>,
+}
+
+impl edit_permissions::Check for EditRules {
+  fn check(&self, edit: &edit_permissions::Edit) -> Decision {
+    if edit.actor == Actor::Console {
+      return Decision::Abstain
+    }
+    for &(deny, ref reason) in &self.rules {
+      let denied =
+        match deny {
+          Deny::Material(material) => edit.materials.contains(&material),
+          Deny::Below(y) => edit.bounds.min.y < y,
+        };
+      if denied {
+        return Decision::Deny(reason.clone())
+      }
+    }
+    Decision::Abstain
+  }
+}
+
+fn parse_action(s: &str) -> Result<Action, String> {
+  let s = s.trim();
+  let (verb, rest) =
+    match s.find(char::is_whitespace) {
+      None => (s, ""),
+      Some(i) => (&s[.. i], s[i ..].trim()),
+    };
+  if rest.is_empty() {
+    return Err(format!("Missing argument for {:?}", verb))
+  }
+  match verb {
+    "say" => Ok(Action::Say(rest.to_owned())),
+    "run" => Ok(Action::Run(rest.to_owned())),
+    verb => Err(format!("Invalid action: {:?}", verb)),
+  }
+}
+
+fn parse_deny(s: &str) -> Result<(Deny, String), String> {
+  let mut words = s.split_whitespace();
+  if words.next() != Some("deny") {
+    return Err(String::from("Edit rules have to start with \"deny\""))
+  }
+  let deny =
+    match words.next() {
+      Some("below") => {
+        let y = try!(words.next().ok_or_else(|| String::from("Missing height")));
+        Deny::Below(try!(y.parse().map_err(|_| format!("Invalid height: {:?}", y))))
+      },
+      material => Deny::Material(try!(command::parse_material(material))),
+    };
+  let reason = words.collect::<Vec<_>>().join(" ");
+  let reason = if reason.is_empty() { String::from(DEFAULT_DENY_REASON) } else { reason };
+  Ok((deny, reason))
+}
+
+fn parse_line(line: &str) -> Result<Rule, String> {
+  let colon = try!(line.find(':').ok_or_else(|| String::from("Missing \":\" after the event")));
+  let (event, rest) = (line[.. colon].trim(), &line[colon + 1 ..]);
+  let mut words = event.split_whitespace();
+  let rule =
+    match words.next() {
+      Some("join") => Rule::Join(try!(parse_action(rest))),
+      Some("every") => {
+        let seconds = try!(words.next().ok_or_else(|| String::from("Missing number of seconds")));
+        let seconds: u64 = try!(seconds.parse().map_err(|_| format!("Invalid number of seconds: {:?}", seconds)));
+        if seconds == 0 {
+          return Err(String::from("Timers have to wait at least a second"))
+        }
+        Rule::Every(seconds, try!(parse_action(rest)))
+      },
+      Some("edit") => {
+        let (deny, reason) = try!(parse_deny(rest));
+        Rule::Edit(deny, reason)
+      },
+      event => return Err(format!("Invalid event: {:?}", event.unwrap_or(""))),
+    };
+  match words.next() {
+    None => Ok(rule),
+    Some(word) => Err(format!("Unexpected argument: {:?}", word)),
+  }
+}
+
+/// Load hooks from a script, which is a `line_file` of rules. The edit rules should be added to `edit_permissions` as
+/// an override.
+pub fn load(path: &std::path::Path) -> (T, EditRules) {
+  let mut hooks = T { join: Vec::new(), timers: Vec::new() };
+  let mut edit_rules = EditRules { rules: Vec::new() };
+
+  line_file::load(path, "hooks", |line| {
+    match try!(parse_line(line)) {
+      Rule::Join(action) => hooks.join.push(action),
+      Rule::Every(seconds, action) => {
+        hooks.timers.push(Timer { period_ns: seconds * 1_000_000_000, next_ns: None, action: action });
+      },
+      Rule::Edit(deny, reason) => edit_rules.rules.push((deny, reason)),
+    }
+    Ok(())
+  });
+
+  (hooks, edit_rules)
+}
+
+impl T {
+  /// The actions of timers that have gone off by `now`. Timers start counting the first time this is called.
+  fn due(&mut self, now: u64) -> Vec<Action> {
+    let mut due = Vec::new();
+    for timer in &mut self.timers {
+      match timer.next_ns {
+        Some(next_ns) if next_ns > now => {},
+        Some(_) => {
+          due.push(timer.action.clone());
+          // Timers that fell behind (e.g. while the server was busy) go off once, not once per missed period.
+          timer.next_ns = Some(now + timer.period_ns);
+        },
+        None => timer.next_ns = Some(now + timer.period_ns),
+      }
+    }
+    due
+  }
+}
+
+fn substitute(s: &str, player: Option<entity::id::Player>) -> String {
+  match player {
+    None => s.to_owned(),
+    Some(player) => s.replace("$player", &player.to_u32().to_string()),
+  }
+}
+
+fn run<UpdateGaia>(
+  server: &server::T,
+  update_gaia: &mut UpdateGaia,
+  action: &Action,
+  player: Option<entity::id::Player>,
+) where
+  UpdateGaia: FnMut(update_gaia::Message),
+{
+  match *action {
+    Action::Say(ref text) => {
+      let text = substitute(text, player);
+      info!("Announcing {:?}", text);
+      for (_, client) in server.clients.lock().unwrap().iter_mut() {
+        client.send(protocol::ServerToClient::Announcement(text.clone()));
+      }
+    },
+    Action::Run(ref line) => {
+      let line = substitute(line, player);
+      info!("Hook runs {:?}", line);
+      let result =
+        command::parse(&line)
+        .and_then(|command| command::apply(server, update_gaia, Actor::Console, command));
+      if let Err(err) = result {
+        warn!("Hook command {:?} failed: {}", line, err);
+      }
+    },
+  }
+}
+
+/// Run the hooks for a player joining.
+pub fn player_joined<UpdateGaia>(server: &server::T, update_gaia: &mut UpdateGaia, player: entity::id::Player) where
+  UpdateGaia: FnMut(update_gaia::Message),
+{
+  let actions = server.hooks.lock().unwrap().join.clone();
+  for action in &actions {
+    run(server, update_gaia, action, Some(player));
+  }
+}
+
+/// Run the hooks for timers that have gone off by `now`.
+pub fn tick<UpdateGaia>(server: &server::T, update_gaia: &mut UpdateGaia, now: u64) where
+  UpdateGaia: FnMut(update_gaia::Message),
+{
+  let actions = server.hooks.lock().unwrap().due(now);
+  for action in &actions {
+    run(server, update_gaia, action, None);
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use common::voxel;
+
+  use super::*;
+
+  #[test]
+  fn test_parse_line() {
+    assert_eq!(
+      parse_line("join: say Welcome, player $player!"),
+      Ok(Rule::Join(Action::Say(String::from("Welcome, player $player!")))),
+    );
+    assert_eq!(
+      parse_line("every 60 : run simulate 1"),
+      Ok(Rule::Every(60, Action::Run(String::from("simulate 1")))),
+    );
+    assert_eq!(
+      parse_line("edit: deny below -64 Too deep"),
+      Ok(Rule::Edit(Deny::Below(-64), String::from("Too deep"))),
+    );
+    assert_eq!(
+      parse_line("edit: deny stone"),
+      Ok(Rule::Edit(Deny::Material(voxel::Material::Stone), String::from(DEFAULT_DENY_REASON))),
+    );
+    assert_eq!(parse_line("join say hi"), Err(String::from("Missing \":\" after the event")));
+    assert!(parse_line("every 0: say hi").is_err());
+    assert!(parse_line("leave: say bye").is_err());
+    assert!(parse_line("join: shout hi").is_err());
+  }
+
+  #[test]
+  fn test_timers() {
+    let mut hooks = T { join: Vec::new(), timers: Vec::new() };
+    let action = Action::Say(String::from("hi"));
+    hooks.timers.push(Timer { period_ns: 10, next_ns: None, action: action.clone() });
+    assert_eq!(hooks.due(100), vec!());
+    assert_eq!(hooks.due(109), vec!());
+    assert_eq!(hooks.due(110), vec!(action.clone()));
+    // A long wait only sets it off once.
+    assert_eq!(hooks.due(200), vec!(action));
+    assert_eq!(hooks.due(205), vec!());
+  }
+}
@@ -1,93 +1,90 @@
-use cgmath::{Point3, EuclideanSpace, InnerSpace, Vector3};
+use cgmath::{Point3, InnerSpace, Vector3};
 use collision::{Aabb3};
 
+use common::math;
+use common::protocol::EntityRef;
 use common::surroundings_loader;
 
-use entity;
 use mob;
 use server;
 
-fn center(bounds: &Aabb3<f32>) -> Point3<f32> {
-  (bounds.min + bounds.max.to_vec()) * 0.5
-}
-
 // TODO: Locking is hard to reason about. Make it saner.
 // The goal should be to prevent coder error causing deadlock.
 
-pub fn init_mobs(
-  server: &server::T,
-) {
-  fn mob_behavior(world: &server::T, mob: &mut mob::Mob) {
-    fn to_player(world: &server::T, mob: &mob::Mob) -> Option<Vector3<f32>> {
-      let mob_posn = center(world.physics.lock().unwrap().get_bounds(mob.physics_id).unwrap());
-
-      let players: Vec<entity::id::Misc> = world.players.lock().unwrap().values().map(|player| player.physics_id).collect();
-      let mut players = players.into_iter();
-
-      players.next().map(|id| {
-        let mut min_v = center(world.physics.lock().unwrap().get_bounds(id).unwrap()) - mob_posn;
-        let mut min_d = min_v.magnitude2();
-        for id in players {
-          let v = center(world.physics.lock().unwrap().get_bounds(id).unwrap()) - mob_posn;
-          let d = v.magnitude2();
-          if d < min_d {
-            min_v = v;
-            min_d = d;
-          }
+/// What new mobs do: play tag with the nearest player.
+pub fn mob_behavior(world: &server::T, mob: &mut mob::Mob) {
+  fn to_player(world: &server::T, mob: &mob::Mob) -> Option<Vector3<f32>> {
+    let mob_posn = math::center(world.physics.lock().unwrap().get_bounds(mob.physics_id).unwrap());
+
+    let mut players = world.components.player_positions().into_iter();
+
+    players.next().map(|position| {
+      let mut min_v = position - mob_posn;
+      let mut min_d = min_v.magnitude2();
+      for position in players {
+        let v = position - mob_posn;
+        let d = v.magnitude2();
+        if d < min_d {
+          min_v = v;
+          min_d = d;
         }
+      }
 
-        min_v
-      })
-    }
+      min_v
+    })
+  }
 
-    {
-      match to_player(world, mob) {
-        None => { mob.behavior = mob_behavior },
-        Some(to_player) => {
-          if to_player.magnitude() < 2.0 {
-            mob.behavior = wait_for_distance;
-          }
-        },
-      }
+  {
+    match to_player(world, mob) {
+      None => { mob.behavior = mob_behavior },
+      Some(to_player) => {
+        if to_player.magnitude() < 2.0 {
+          mob.behavior = wait_for_distance;
+        }
+      },
     }
+  }
 
-    fn wait_for_distance(world: &server::T, mob: &mut mob::Mob) {
-      match to_player(world, mob) {
-        None => { mob.behavior = mob_behavior },
-        Some(to_player) => {
-          if to_player.magnitude() > 8.0 {
-            mob.behavior = follow_player;
-          }
-        },
-      }
+  fn wait_for_distance(world: &server::T, mob: &mut mob::Mob) {
+    match to_player(world, mob) {
+      None => { mob.behavior = mob_behavior },
+      Some(to_player) => {
+        if to_player.magnitude() > 8.0 {
+          mob.behavior = follow_player;
+        }
+      },
     }
+  }
 
-    fn follow_player(world: &server::T, mob: &mut mob::Mob) {
-      match to_player(world, mob) {
-        None => { mob.behavior = mob_behavior },
-        Some(to_player) => {
-          if to_player.magnitude2() < 4.0 {
-            mob.behavior = wait_to_reset;
-            mob.speed = Vector3::new(0.0, 0.0, 0.0);
-          } else {
-            mob.speed = to_player * (0.5);
-          }
-        },
-      }
+  fn follow_player(world: &server::T, mob: &mut mob::Mob) {
+    match to_player(world, mob) {
+      None => { mob.behavior = mob_behavior },
+      Some(to_player) => {
+        if to_player.magnitude2() < 4.0 {
+          mob.behavior = wait_to_reset;
+          mob.speed = Vector3::new(0.0, 0.0, 0.0);
+        } else {
+          mob.speed = to_player * (0.5);
+        }
+      },
     }
+  }
 
-    fn wait_to_reset(world: &server::T, mob: &mut mob::Mob) {
-      match to_player(world, mob) {
-        None => { mob.behavior = mob_behavior },
-        Some(to_player) => {
-          if to_player.magnitude() >= 2.0 {
-            mob.behavior = mob_behavior;
-          }
-        },
-      }
+  fn wait_to_reset(world: &server::T, mob: &mut mob::Mob) {
+    match to_player(world, mob) {
+      None => { mob.behavior = mob_behavior },
+      Some(to_player) => {
+        if to_player.magnitude() >= 2.0 {
+          mob.behavior = mob_behavior;
+        }
+      },
     }
   }
+}
 
+pub fn init_mobs(
+  server: &server::T,
+) {
   add_mob(
     server,
     // TODO: shift upward until outside terrain
@@ -107,7 +104,7 @@ fn add_mob(
 
   let mob =
     mob::Mob {
-      position            : (bounds.min + bounds.max.to_vec()) * 0.5,
+      position            : math::center(&bounds),
       speed               : Vector3::new(0.0, 0.0, 0.0),
       behavior            : behavior,
       entity_id           : entity_id,
@@ -117,5 +114,6 @@ fn add_mob(
     };
 
   server.physics.lock().unwrap().insert_misc(physics_id, &bounds);
+  server.components.moved(EntityRef::Mob(entity_id), &bounds, Vector3::new(0.0, 0.0, 0.0));
   server.mobs.lock().unwrap().insert(entity_id, mob);
 }
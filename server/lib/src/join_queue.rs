@@ -0,0 +1,200 @@
+//! Admitting clients while there's room, and holding them in line when there isn't.
+//! Clients that connect to a full server are told their place in line, reminded of it periodically,
+//! and admitted in the order they arrived as other clients leave. Waiting clients answer each reminder, and ones that
+//! stop answering (e.g. because they quit) are dropped rather than admitted.
+
+use std::collections::VecDeque;
+use time;
+
+use common::interval_timer::IntervalTimer;
+use common::protocol;
+use common::protocol::EntityRef;
+
+use afk;
+use beacon;
+use player;
+use server;
+use server::Client;
+use webhooks;
+
+/// How many clients can play at once, unless the server is told otherwise.
+pub const DEFAULT_MAX_PLAYERS: usize = 16;
+/// How often waiting clients are reminded of their place in line.
+const POSITION_UPDATE_NS: u64 = 5_000_000_000;
+/// How long a waiting client can go without answering before it's dropped from the line.
+const WAITING_TIMEOUT_NS: u64 = 3 * POSITION_UPDATE_NS;
+
+/// Clients waiting for a slot, in the order they'll be admitted.
+pub struct T {
+  waiting      : VecDeque<Client>,
+  update_timer : IntervalTimer,
+}
+
+#[allow(missing_docs)]
+pub fn new() -> T {
+  T {
+    waiting      : VecDeque::new(),
+    update_timer : IntervalTimer::new(POSITION_UPDATE_NS, time::precise_time_ns()),
+  }
+}
+
+impl T {
+  #[allow(missing_docs)]
+  pub fn len(&self) -> usize {
+    self.waiting.len()
+  }
+
+  /// Tell every waiting client its place in line (starting from 1).
+  fn send_positions(&mut self) {
+    for (i, client) in self.waiting.iter_mut().enumerate() {
+      let secret = client.secret;
+      client.send(protocol::ServerToClient::Queued(i as u32 + 1, secret));
+    }
+  }
+
+  /// Drop waiting clients that haven't answered in a while. Returns whether any were dropped.
+  fn drop_unresponsive(&mut self, now_ns: u64) -> bool {
+    let waiting = self.waiting.len();
+    self.waiting.retain(|client| now_ns < client.last_input_ns + WAITING_TIMEOUT_NS);
+    if self.waiting.len() < waiting {
+      info!("Dropping {} waiting clients, which stopped answering", waiting - self.waiting.len());
+      true
+    } else {
+      false
+    }
+  }
+}
+
+/// Give a client an id and let it start playing.
+fn admit(server: &server::T, mut client: Client) {
+  // Time spent waiting in line doesn't count as being away.
+  client.last_input_ns = time::precise_time_ns();
+  let client_id = server.client_allocator.lock().unwrap().allocate();
+  let lease = protocol::ServerToClient::LeaseId(client_id, protocol::VERSION, client.terrain_policy, client.secret);
+  client.send(lease);
+  client.send(protocol::ServerToClient::UpdateSun(server.sun.lock().unwrap().state()));
+  beacon::send_all(server, &mut client);
+
+  server.clients.lock().unwrap().insert(client_id, client);
+}
+
+fn is_full(server: &server::T) -> bool {
+  server.clients.lock().unwrap().len() >= *server.max_players.lock().unwrap()
+}
+
+/// Admit a newly-connected client if there's room, otherwise put it at the back of the line.
+pub fn join(server: &server::T, mut client: Client) {
+  // Holding the queue keeps concurrent joins from overfilling the server or cutting in line.
+  let mut queue = server.join_queue.lock().unwrap();
+  if queue.waiting.is_empty() && !is_full(server) {
+    admit(server, client);
+    return
+  }
+
+  let position = queue.waiting.len() as u32 + 1;
+  info!("Server is full; client is number {} in line", position);
+  let secret = client.secret;
+  client.send(protocol::ServerToClient::Queued(position, secret));
+  queue.waiting.push_back(client);
+}
+
+/// Note that a waiting client (by the secret it was queued with) answered, so it's still there.
+pub fn still_waiting(server: &server::T, secret: protocol::Secret) {
+  let mut queue = server.join_queue.lock().unwrap();
+  match queue.waiting.iter_mut().find(|client| client.secret == secret) {
+    None => warn!("Unknown client is still waiting"),
+    Some(client) => client.last_input_ns = time::precise_time_ns(),
+  }
+}
+
+/// Admit waiting clients, in order, until the server is full.
+pub fn admit_waiting(server: &server::T) {
+  let mut queue = server.join_queue.lock().unwrap();
+  // Dropping clients moves the ones behind them up the line too.
+  let mut moved = queue.drop_unresponsive(time::precise_time_ns());
+  while !queue.waiting.is_empty() && !is_full(server) {
+    let client = queue.waiting.pop_front().unwrap();
+    admit(server, client);
+    moved = true;
+  }
+  if moved {
+    info!("{} clients still waiting", queue.len());
+    queue.send_positions();
+  }
+}
+
+/// Take a client and its players out of the game, and let the next client in line take its place.
+pub fn leave(server: &server::T, client_id: protocol::ClientId) {
+  let account =
+    match server.clients.lock().unwrap().remove(&client_id) {
+      None => {
+        warn!("Unknown client {:?} left", client_id);
+        return
+      },
+      Some(client) => client.account,
+    };
+  info!("Client {:?} left", client_id);
+
+  let players: Vec<player::T> = {
+    let mut players = server.players.lock().unwrap();
+    let ids: Vec<_> =
+      players.iter()
+      .filter(|&(_, player)| player.client_id == client_id)
+      .map(|(&id, _)| id)
+      .collect();
+    ids.iter().map(|id| players.remove(id).unwrap()).collect()
+  };
+  for player in players {
+    let player_id = player.entity_id;
+    server.mining.lock().unwrap().remove(&player_id);
+    server.edit_permissions.lock().unwrap().claims.release(player_id);
+    server.attachments.lock().unwrap().detach_all(EntityRef::Player(player_id));
+    server.components.remove(EntityRef::Player(player_id));
+    server.afk.lock().unwrap().forget(player_id);
+    server.portals.lock().unwrap().forget(player_id);
+    player.remove(server);
+    for (_, client) in server.clients.lock().unwrap().iter_mut() {
+      client.send(protocol::ServerToClient::PlayerRemoved(player_id));
+    }
+    webhooks::fire(
+      server,
+      webhooks::Event::Leave,
+      &[
+        ("player", webhooks::Value::Int(player_id.to_u32() as i64)),
+        ("account", webhooks::Value::Text(account.clone())),
+      ],
+    );
+  }
+
+  admit_waiting(server);
+}
+
+/// Drop clients that couldn't be sent to, whether they're playing or waiting in line.
+pub fn drop_failed(server: &server::T) {
+  let failed: Vec<protocol::ClientId> =
+    server.clients.lock().unwrap().iter()
+    .filter(|&(_, client)| client.failure.is_some())
+    .map(|(&client_id, _)| client_id)
+    .collect();
+  for client_id in failed {
+    warn!("Dropping client {:?}, which couldn't be sent to", client_id);
+    leave(server, client_id);
+  }
+
+  let mut queue = server.join_queue.lock().unwrap();
+  let waiting = queue.waiting.len();
+  queue.waiting.retain(|client| client.failure.is_none());
+  if queue.waiting.len() < waiting {
+    warn!("Dropping {} waiting clients, which couldn't be sent to", waiting - queue.waiting.len());
+    queue.send_positions();
+  }
+}
+
+/// Periodically remind waiting clients of their place in line.
+pub fn tick(server: &server::T, now_ns: u64) {
+  let mut queue = server.join_queue.lock().unwrap();
+  if queue.update_timer.update(now_ns) > 0 {
+    queue.drop_unresponsive(now_ns);
+    queue.send_positions();
+  }
+}
@@ -0,0 +1,43 @@
+//! Carry a saved world over to a new version of the terrain generator. Only the blocks players have edited (and any
+//! reinforcement) are kept; everything else is dropped, so it's regenerated by the new generator as it's loaded.
+
+use std;
+
+use common::fnv_map;
+use common::voxel;
+
+use terrain;
+use terrain_file;
+
+/// Keep the edited blocks of the terrain saved at `from`, and save them to `to` (which can be the same file).
+/// The heightmap cache next to `to` is from the old generator, so it's deleted.
+pub fn run(from: &std::path::Path, to: &std::path::Path) -> Result<(), String> {
+  let old = try!(terrain_file::read(from));
+
+  let mut voxels = voxel::tree::new();
+  let mut compacted = fnv_map::new();
+  let mut kept = 0;
+  for block in &old.edited {
+    for bounds in terrain::edits::voxels_in(block) {
+      if let Some(voxel) = old.voxels.get(&bounds) {
+        voxels.get_mut_or_create(&bounds).data = Some(*voxel);
+        kept += 1;
+      }
+      if let Some(voxel) = old.compacted.get(&bounds) {
+        compacted.insert(bounds, *voxel);
+      }
+    }
+  }
+
+  try!(terrain_file::write(to, &voxels, &compacted, &old.edited, &old.reinforced));
+
+  let heightmap_path = std::path::PathBuf::from(format!("{}.heightmap", to.display()));
+  match std::fs::remove_file(&heightmap_path) {
+    Ok(()) => println!("Deleted the old heightmap cache {:?}", heightmap_path),
+    Err(ref err) if err.kind() == std::io::ErrorKind::NotFound => {},
+    Err(err) => return Err(format!("Error deleting {:?}: {:?}", heightmap_path, err)),
+  }
+
+  println!("Kept {} voxels from {} edited blocks; everything else will be regenerated", kept, old.edited.len());
+  Ok(())
+}
@@ -0,0 +1,183 @@
+//! Digging takes time, depending on the hardness of whatever's being dug.
+
+use time;
+
+use common::protocol;
+use common::voxel;
+
+use brush;
+use client_recv_thread::{cast, cast_aimed};
+use edit_permissions;
+use entity;
+use server;
+use update_gaia;
+
+/// A player's digging.
+pub struct T {
+  /// The voxel being dug, and when we started digging it.
+  target: Option<(voxel::bounds::T, u64)>,
+  /// Where the player aimed when they started digging. This picks the first voxel; after that, digging follows where
+  /// the player is looking.
+  aim: Option<protocol::Aim>,
+}
+
+#[allow(missing_docs)]
+pub fn new(aim: Option<protocol::Aim>) -> T {
+  T {
+    target: None,
+    aim: aim,
+  }
+}
+
+/// A brush that digs out the player's chosen shape around a voxel.
+fn dig_brush(server: &server::T, player_id: entity::id::Player, bounds: &voxel::bounds::T) -> brush::T {
+  debug!("remove bounds {:?}", bounds);
+  let (shape, radius) = {
+    let players = server.players.lock().unwrap();
+    let player = players.get(&player_id).unwrap();
+    (player.brush_shape, player.brush_radius)
+  };
+  brush::of_shape(&bounds.center(), shape, radius, voxel::Material::Empty)
+}
+
+impl T {
+  /// Advance digging by `player_id`. Returns how far through its current voxel it is, if it has one.
+  fn update<RequestBlock>(
+    &mut self,
+    server: &server::T,
+    player_id: entity::id::Player,
+    request_block: &mut RequestBlock,
+  ) -> Option<f32> where
+    RequestBlock: FnMut(update_gaia::Message),
+  {
+    let hit =
+      match self.aim.take() {
+        Some(aim) => cast_aimed(server, player_id, &aim),
+        None => cast(server, player_id),
+      };
+    let (bounds, material) =
+      match hit {
+        None => {
+          self.target = None;
+          return None
+        },
+        Some(hit) => hit,
+      };
+
+    let now = time::precise_time_ns();
+    let started =
+      match self.target {
+        Some((target, started)) if target == bounds => started,
+        _ => {
+          // Looking at something new; start over.
+          self.target = Some((bounds, now));
+          now
+        },
+      };
+
+    let duration_ns = (material.hardness() as f64 * 1e9) as u64;
+    let elapsed = now - started;
+    if elapsed < duration_ns {
+      return Some(elapsed as f32 / duration_ns as f32)
+    }
+
+    request_block(
+      update_gaia::Message::Brush(
+        edit_permissions::Actor::Player(player_id),
+        vec!(voxel::Material::Empty),
+        dig_brush(server, player_id, &bounds),
+      )
+    );
+    // Keep digging whatever's exposed next.
+    self.target = None;
+    Some(0.0)
+  }
+}
+
+/// Advance all digging, and tell the diggers how it's going.
+pub fn update<RequestBlock>(
+  server: &server::T,
+  request_block: &mut RequestBlock,
+) where
+  RequestBlock: FnMut(update_gaia::Message),
+{
+  let mut progress = Vec::new();
+  {
+    let mut mining = server.mining.lock().unwrap();
+    for (&player_id, digging) in mining.iter_mut() {
+      progress.push((player_id, digging.update(server, player_id, request_block)));
+    }
+  }
+
+  let players = server.players.lock().unwrap();
+  let mut clients = server.clients.lock().unwrap();
+  for (player_id, progress) in progress {
+    let client_id =
+      match players.get(&player_id) {
+        None => continue,
+        Some(player) => player.client_id,
+      };
+    if let Some(client) = clients.get_mut(&client_id) {
+      client.send(protocol::ServerToClient::MiningProgress(progress));
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use cgmath::Point3;
+  use std::f32::consts::PI;
+
+  use common::protocol;
+  use common::voxel;
+
+  use brush;
+  use client_recv_thread::cast;
+  use edit_permissions;
+  use player;
+  use server;
+  use update_gaia;
+
+  use super::*;
+
+  #[test]
+  // Surface voxels whose low corner is outside the surface record `Empty` there; they still take time to dig.
+  fn test_digging_a_surface() {
+    let server = server::new();
+    // A ball of stone in the sky, to dig into from underneath.
+    let center = Point3::new(0.0, 200.0, 0.0);
+    update_gaia::update_gaia(
+      &server,
+      update_gaia::Message::Brush(
+        edit_permissions::Actor::Console,
+        vec!(voxel::Material::Stone),
+        brush::sphere(&center, 2.5, voxel::Material::Stone),
+      ),
+    );
+
+    let mut player =
+      player::new(
+        server.player_allocator.lock().unwrap().allocate(),
+        server.misc_allocator.lock().unwrap().allocate(),
+        protocol::ClientId::default(),
+        &server.owner_allocator,
+      );
+    player.position = Point3::new(0.5, 190.0, 0.5);
+    player.rotate_vertical(PI / 2.0);
+    let player_id = player.entity_id;
+    server.players.lock().unwrap().insert(player_id, player);
+
+    let (bounds, material) = cast(&server, player_id).expect("Nothing to dig");
+    match server.terrain_loader.terrain.voxels.lock().unwrap().get(&bounds) {
+      Some(&voxel::Surface(_)) => {},
+      voxel => panic!("Expected to aim at a surface, got {:?}", voxel),
+    }
+    assert_eq!(material, voxel::Material::Stone);
+
+    // Stone takes a while, so the first update only starts digging.
+    let mut digging = new(None);
+    let mut dug = Vec::new();
+    assert_eq!(digging.update(&server, player_id, &mut |up| dug.push(up)), Some(0.0));
+    assert!(dug.is_empty());
+  }
+}
@@ -9,6 +9,7 @@ extern crate collision;
 extern crate common;
 #[macro_use]
 extern crate log;
+extern crate lru_cache;
 extern crate nanomsg;
 extern crate num;
 extern crate rand;
@@ -18,20 +19,62 @@ extern crate thread_scoped;
 extern crate time;
 extern crate voxel_data;
 
+mod afk;
+mod attachment;
+mod auth;
+pub mod autosave;
+mod beacon;
+mod blast;
+pub mod bench_terrain;
+mod brush;
+mod cancel;
+mod capsule;
 mod client_recv_thread;
+pub mod command;
+mod components;
+pub mod edit_permissions;
 mod entity;
+mod error;
+mod fixed_step;
+mod gaia_queue;
+mod hibernation;
+mod hooks;
 mod in_progress_terrain;
 mod init_mobs;
+mod join_queue;
 mod lod;
+pub mod migrate_terrain;
+mod mining;
 mod mob;
+pub mod navgrid;
 mod octree;
+mod pending_loads;
 mod physics;
+mod placement;
 mod player;
+mod portal;
+mod prefetch;
+mod projectile;
+pub mod rate_limit;
+pub mod replay;
+pub mod roles;
 mod run;
 pub mod server;
+mod shutdown;
+mod simulate;
+mod spatial_hash;
 mod sun;
+mod template;
+mod terrain_file;
 mod terrain_loader;
+mod terrain_policy;
+#[cfg(test)]
+mod two_clients_test;
 pub mod update_gaia;
 mod update_world;
+mod voxel_query;
+mod weather;
+mod webhooks;
 
 pub use run::run;
+pub use terrain::world;
@@ -0,0 +1,301 @@
+//! Where mobs can walk, and how to get from one place to another on foot.
+//! The grid's cells are the smallest voxels. A cell is standable if the voxel under it is solid and there's room above
+//! it for a mob. Each standable cell links to its neighbours along x and z that are standable too, as long as the step
+//! up or the drop down is small enough. Cells are analyzed a block (as in `terrain::edits`) at a time, the first time a
+//! path needs them, and edits reanalyze the blocks they touch.
+
+use cgmath::{Point3, Vector3};
+use collision::Aabb3;
+use lru_cache::LruCache;
+use std;
+use std::collections::BinaryHeap;
+
+use common::fnv_map;
+use common::fnv_set;
+use common::voxel;
+
+use server;
+use terrain;
+use terrain::edits;
+
+/// How many cells tall a mob is. Standable cells have at least this many clear cells, starting with themselves.
+pub const HEIGHT: u8 = 2;
+/// The highest a mob can step up to a neighbouring cell.
+pub const STEP_UP: i32 = 1;
+/// The furthest a mob can drop down to a neighbouring cell.
+pub const MAX_DROP: i32 = 2;
+/// The most cells a search looks at before giving up.
+pub const MAX_SEARCH: usize = 1 << 14;
+
+/// Clear cells above the ground past this many don't make any difference.
+const MAX_HEADROOM: u8 = HEIGHT + MAX_DROP as u8;
+/// The most blocks to keep the analyses of.
+const CAPACITY: usize = 1 << 12;
+
+/// The standable cells in a block, and how many clear cells each one has, starting with itself.
+type Block = fnv_map::T<Point3<i32>, u8>;
+
+#[allow(missing_docs)]
+pub struct T {
+  /// Recently-used block analyses, by block.
+  blocks: LruCache<voxel::bounds::T, Block>,
+}
+
+#[allow(missing_docs)]
+pub fn new() -> T {
+  T {
+    blocks: LruCache::new(CAPACITY),
+  }
+}
+
+/// The cell a point (e.g. the bottom middle of a mob) is in.
+pub fn cell_of(point: &Point3<f32>) -> Point3<i32> {
+  Point3::new(point.x.floor() as i32, point.y.floor() as i32, point.z.floor() as i32)
+}
+
+fn block_of(cell: &Point3<i32>) -> voxel::bounds::T {
+  let lg = edits::LG_BLOCK_WIDTH;
+  voxel::bounds::new(cell.x >> lg, cell.y >> lg, cell.z >> lg, lg)
+}
+
+/// Find the standable cells in a region, given which cells are solid. `solid` is asked about cells from just below
+/// the region up to `MAX_HEADROOM` cells above it.
+fn analyze<Solid>(region: &Aabb3<i32>, mut solid: Solid) -> Block where
+  Solid: FnMut(&Point3<i32>) -> bool,
+{
+  let mut block = fnv_map::new();
+  for x in region.min.x .. region.max.x {
+  for z in region.min.z .. region.max.z {
+  for y in region.min.y .. region.max.y {
+    let cell = Point3::new(x, y, z);
+    if solid(&cell) || !solid(&Point3::new(x, y - 1, z)) {
+      continue
+    }
+    let mut headroom = 1;
+    while headroom < MAX_HEADROOM && !solid(&Point3::new(x, y + headroom as i32, z)) {
+      headroom += 1;
+    }
+    if headroom >= HEIGHT {
+      block.insert(cell, headroom);
+    }
+  }}}
+  block
+}
+
+/// Analyze a block of the terrain. Any voxel that isn't empty space is solid, as it is to physics.
+fn analyze_block(terrain: &terrain::T, block: &voxel::bounds::T) -> Block {
+  let region = edits::region(block);
+  let extracted =
+    Aabb3::new(
+      region.min + Vector3::new(0, -1, 0),
+      region.max + Vector3::new(0, MAX_HEADROOM as i32, 0),
+    );
+  let voxels = terrain.extract(&extracted);
+  let size = extracted.max - extracted.min;
+  analyze(&region, |cell| {
+    let offset = *cell - extracted.min;
+    let i = (offset.x * size.y + offset.y) * size.z + offset.z;
+    voxels[i as usize] != voxel::Volume(voxel::Material::Empty)
+  })
+}
+
+/// The cells a mob can walk, step up or drop to from `cell`, which has `headroom` clear cells, and what it costs to
+/// get to each one.
+fn links<Standable>(cell: &Point3<i32>, headroom: u8, standable: &mut Standable) -> Vec<(Point3<i32>, u32)> where
+  Standable: FnMut(&Point3<i32>) -> Option<u8>,
+{
+  let mut links = Vec::new();
+  for &(dx, dz) in &[(1, 0), (-1, 0), (0, 1), (0, -1)] {
+    for dy in -MAX_DROP .. STEP_UP + 1 {
+      let next = Point3::new(cell.x + dx, cell.y + dy, cell.z + dz);
+      let next_headroom =
+        match standable(&next) {
+          None => continue,
+          Some(headroom) => headroom,
+        };
+      // Stepping up needs room overhead before moving over; dropping down needs room over where it lands.
+      let fits =
+        if dy > 0 {
+          headroom as i32 >= HEIGHT as i32 + dy
+        } else {
+          next_headroom as i32 >= HEIGHT as i32 - dy
+        };
+      if fits {
+        links.push((next, if dy > 0 { 3 } else { 2 }));
+      }
+    }
+  }
+  links
+}
+
+/// A* from one standable cell to another, looking at no more than `max_search` cells. Returns every cell along the
+/// way, including both ends.
+fn search<Standable>(
+  from: &Point3<i32>,
+  to: &Point3<i32>,
+  max_search: usize,
+  mut standable: Standable,
+) -> Option<Vec<Point3<i32>>> where
+  Standable: FnMut(&Point3<i32>) -> Option<u8>,
+{
+  if standable(to).is_none() || standable(from).is_none() {
+    return None
+  }
+  // Every link costs at least 2 per cell along x or z.
+  let estimate = |cell: &Point3<i32>| 2 * ((to.x - cell.x).abs() + (to.z - cell.z).abs()) as u32;
+
+  // Each cell reached, with what it cost to get there and the index of the cell it was reached from.
+  let mut reached: Vec<(Point3<i32>, u32, Option<usize>)> = vec!((*from, 0, None));
+  let mut best = fnv_map::new();
+  best.insert(*from, 0);
+  let mut open = BinaryHeap::new();
+  open.push((std::cmp::Reverse(estimate(from)), 0));
+  let mut searched = 0;
+
+  while let Some((_, i)) = open.pop() {
+    let (cell, cost, _) = reached[i];
+    if best.get(&cell).map_or(false, |&b| b < cost) {
+      // It's been reached more cheaply since this was queued.
+      continue
+    }
+    if cell == *to {
+      let mut path = vec!(cell);
+      let mut parent = reached[i].2;
+      while let Some(p) = parent {
+        path.push(reached[p].0);
+        parent = reached[p].2;
+      }
+      path.reverse();
+      return Some(path)
+    }
+    searched += 1;
+    if searched > max_search {
+      return None
+    }
+
+    let headroom = standable(&cell).unwrap();
+    for (next, link_cost) in links(&cell, headroom, &mut standable) {
+      let next_cost = cost + link_cost;
+      if best.get(&next).map_or(false, |&b| b <= next_cost) {
+        continue
+      }
+      best.insert(next, next_cost);
+      reached.push((next, next_cost, Some(i)));
+      open.push((std::cmp::Reverse(next_cost + estimate(&next)), reached.len() - 1));
+    }
+  }
+  None
+}
+
+impl T {
+  /// How many clear cells a cell has (starting with itself), if it's standable.
+  pub fn standable(&mut self, terrain: &terrain::T, cell: &Point3<i32>) -> Option<u8> {
+    let block = block_of(cell);
+    if let Some(cells) = self.blocks.get_mut(&block) {
+      return cells.get(cell).cloned()
+    }
+    let cells = analyze_block(terrain, &block);
+    let headroom = cells.get(cell).cloned();
+    self.blocks.insert(block, cells);
+    headroom
+  }
+
+  /// Reanalyze the blocks that edits changed, along with the blocks above and below them, since cells' footing and
+  /// headroom can reach across. Blocks that haven't been analyzed are left until they're needed.
+  pub fn edited(&mut self, terrain: &terrain::T, blocks: &[voxel::bounds::T]) {
+    let mut stale = fnv_set::new();
+    for block in blocks {
+      for &dy in &[-1, 0, 1] {
+        stale.insert(voxel::bounds::new(block.x, block.y + dy, block.z, block.lg_size));
+      }
+    }
+    for block in stale {
+      if self.blocks.contains_key(&block) {
+        let cells = analyze_block(terrain, &block);
+        self.blocks.insert(block, cells);
+      }
+    }
+  }
+
+  /// Find a path on foot between two standable cells. Returns every cell along the way, including both ends, or
+  /// `None` if there's no path that can be found without looking at more than `MAX_SEARCH` cells.
+  pub fn find_path(
+    &mut self,
+    terrain : &terrain::T,
+    from    : &Point3<i32>,
+    to      : &Point3<i32>,
+  ) -> Option<Vec<Point3<i32>>> {
+    search(from, to, MAX_SEARCH, |cell| self.standable(terrain, cell))
+  }
+}
+
+/// Find a path on foot between two points, e.g. the bottom middles of a mob and where it's going. Each point is
+/// moved down onto the ground, if it's up to `MAX_DROP` cells above it. Returns the cells along the way.
+pub fn find_path(server: &server::T, from: &Point3<f32>, to: &Point3<f32>) -> Option<Vec<Point3<i32>>> {
+  let terrain = &server.terrain_loader.terrain;
+  let mut navgrid = server.navgrid.lock().unwrap();
+  let (from, to) = {
+    let mut ground = |point: &Point3<f32>| {
+      let cell = cell_of(point);
+      (0 .. MAX_DROP + 1)
+        .map(|dy| cell + Vector3::new(0, -dy, 0))
+        .find(|cell| navgrid.standable(terrain, cell).is_some())
+    };
+    (ground(from), ground(to))
+  };
+  match (from, to) {
+    (Some(from), Some(to)) => navgrid.find_path(terrain, &from, &to),
+    _ => None,
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use cgmath::Point3;
+  use collision::Aabb3;
+
+  use common::fnv_map;
+
+  use super::*;
+
+  /// A floor at y = -1, with a wall along x = 2 (from z = -1 up) and a one-cell step at x = -2.
+  fn solid(cell: &Point3<i32>) -> bool {
+    cell.y < 0 ||
+    (cell.x == 2 && cell.z >= -1 && cell.y < 3) ||
+    (cell.x == -2 && cell.y == 0)
+  }
+
+  fn world() -> fnv_map::T<Point3<i32>, u8> {
+    analyze(&Aabb3::new(Point3::new(-4, -2, -4), Point3::new(5, 4, 5)), solid)
+  }
+
+  #[test]
+  fn test_analyze() {
+    let world = world();
+    assert_eq!(world.get(&Point3::new(0, 0, 0)), Some(&MAX_HEADROOM));
+    // On top of the step, and the wall.
+    assert_eq!(world.get(&Point3::new(-2, 1, 0)), Some(&MAX_HEADROOM));
+    assert_eq!(world.get(&Point3::new(2, 3, 0)), Some(&MAX_HEADROOM));
+    // Inside the wall, and in midair.
+    assert_eq!(world.get(&Point3::new(2, 0, 0)), None);
+    assert_eq!(world.get(&Point3::new(0, 1, 0)), None);
+  }
+
+  #[test]
+  fn test_search() {
+    let world = world();
+    let standable = |cell: &Point3<i32>| world.get(cell).cloned();
+
+    // Up onto the step and back down.
+    let path = search(&Point3::new(-3, 0, 0), &Point3::new(-1, 0, 0), 100, &standable).unwrap();
+    assert_eq!(path, vec!(Point3::new(-3, 0, 0), Point3::new(-2, 1, 0), Point3::new(-1, 0, 0)));
+
+    // The wall's too tall to climb, so the way around it is at z = -2.
+    let path = search(&Point3::new(1, 0, 0), &Point3::new(3, 0, 0), 100, &standable).unwrap();
+    assert_eq!(path.len(), 7);
+    assert!(path.contains(&Point3::new(2, 0, -2)));
+
+    assert_eq!(search(&Point3::new(1, 0, 0), &Point3::new(3, 0, 0), 4, &standable), None);
+    assert_eq!(search(&Point3::new(1, 0, 0), &Point3::new(0, 1, 0), 100, &standable), None);
+  }
+}
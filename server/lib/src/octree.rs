@@ -5,7 +5,7 @@ use std::ptr;
 
 pub const MIN_CELL_WIDTH: f32 = 0.1;
 
-fn aabb_overlap(aabb1: &Aabb3<f32>, aabb2: &Aabb3<f32>) -> bool {
+pub fn aabb_overlap(aabb1: &Aabb3<f32>, aabb2: &Aabb3<f32>) -> bool {
   true
   && aabb1.min.x < aabb2.max.x
   && aabb1.min.y < aabb2.max.y
@@ -227,28 +227,41 @@ impl<V: Debug + Copy + Eq + PartialOrd> Octree<V> {
   // this/child trees. Uses equality comparison on V to ignore "same" objects.
   // Returns the value associated with the first object intersected.
   pub fn intersect(&self, bounds: &Aabb3<f32>, self_v: Option<V>) -> Option<(Aabb3<f32>, V)> {
+    self.intersect_where(bounds, self_v, &mut |_| true)
+  }
+
+  /// Like `intersect`, but only count overlapping entries whose bounds also pass `pred`
+  /// (e.g. a finer-grained collision test).
+  pub fn intersect_where<P>(
+    &self,
+    bounds: &Aabb3<f32>,
+    self_v: Option<V>,
+    pred: &mut P,
+  ) -> Option<(Aabb3<f32>, V)> where
+    P: FnMut(&Aabb3<f32>) -> bool,
+  {
     match self.contents {
       OctreeContents::Leaf(ref vs) => {
         vs.iter()
-          .find(|&&(ref bs, ref v)| Some(*v) != self_v && aabb_overlap(bounds, bs))
+          .find(|&&(ref bs, ref v)| Some(*v) != self_v && aabb_overlap(bounds, bs) && pred(bs))
           .map(|&(bounds, v)| (bounds, v))
       },
       OctreeContents::Branch(ref b) => {
         let mid = middle(&self.bounds, self.dimension);
         let (low_bounds, high_bounds) = split(mid, self.dimension, bounds);
-        let high = |high_bounds| {
-          match high_bounds {
+        let low =
+          match low_bounds {
             None => None,
-            Some(bs) => b.high_tree.intersect(&bs, self_v),
-          }
-        };
-        match low_bounds {
-          None => high(high_bounds),
-          Some(bs) =>
-            match b.low_tree.intersect(&bs, self_v) {
-              None => high(high_bounds),
-              r => r,
+            Some(bs) => b.low_tree.intersect_where(&bs, self_v, pred),
+          };
+        match low {
+          None => {
+            match high_bounds {
+              None => None,
+              Some(bs) => b.high_tree.intersect_where(&bs, self_v, pred),
             }
+          },
+          r => r,
         }
       },
     }
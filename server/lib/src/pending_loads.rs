@@ -0,0 +1,205 @@
+//! Blocks that are being loaded right now. Generating a block doesn't hold the voxel tree, so two requests for the same
+//! block (the same position and LOD) can arrive while it's generating; the later ones wait for the first to finish and
+//! share its result, rather than generating it again.
+
+use std::sync::{Arc, Condvar, Mutex};
+
+use common::fnv_map;
+use common::voxel;
+
+enum State {
+  Loading,
+  Loaded(voxel::T),
+  /// The load panicked or was cancelled; whoever's waiting has to load the block themselves.
+  Abandoned,
+}
+
+/// A block being loaded, which waiters get handed once it's done.
+struct Slot {
+  state : Mutex<State>,
+  done  : Condvar,
+}
+
+/// Finishes a load when it's dropped, so waiters are woken (and the block stops counting as loading) even if the load
+/// panics.
+struct Finish<'a> {
+  pending : &'a T,
+  bounds  : voxel::bounds::T,
+  slot    : Arc<Slot>,
+  block   : Option<voxel::T>,
+}
+
+impl<'a> Drop for Finish<'a> {
+  fn drop(&mut self) {
+    self.pending.loads.lock().unwrap().remove(&self.bounds);
+    *self.slot.state.lock().unwrap() =
+      match self.block {
+        Some(block) => State::Loaded(block),
+        None => State::Abandoned,
+      };
+    self.slot.done.notify_all();
+  }
+}
+
+#[allow(missing_docs)]
+pub struct T {
+  loads     : Mutex<fnv_map::T<voxel::bounds::T, Arc<Slot>>>,
+  /// How many requests were answered by somebody else's load.
+  coalesced : Mutex<u64>,
+}
+
+#[allow(missing_docs)]
+pub fn new() -> T {
+  T {
+    loads     : Mutex::new(fnv_map::new()),
+    coalesced : Mutex::new(0),
+  }
+}
+
+impl T {
+  /// Load a block with `load`, unless it's already being loaded, in which case wait for that load and return what it
+  /// found instead. If the load being waited for panics or is cancelled, this loads the block itself. `load` returns
+  /// None if it's cancelled, and so does this.
+  pub fn load<Load>(&self, bounds: &voxel::bounds::T, load: Load) -> Option<voxel::T> where
+    Load: FnOnce() -> Option<voxel::T>,
+  {
+    loop {
+      let (slot, loading) = {
+        let mut loads = self.loads.lock().unwrap();
+        match loads.entry(*bounds) {
+          fnv_map::Entry::Occupied(entry) => (entry.get().clone(), false),
+          fnv_map::Entry::Vacant(entry) => {
+            let slot = Arc::new(Slot { state: Mutex::new(State::Loading), done: Condvar::new() });
+            (entry.insert(slot).clone(), true)
+          },
+        }
+      };
+
+      if loading {
+        let mut finish = Finish { pending: self, bounds: *bounds, slot: slot, block: None };
+        let block = load();
+        finish.block = block;
+        return block
+      }
+
+      debug!("Waiting for {:?}, which is already loading", bounds);
+      *self.coalesced.lock().unwrap() += 1;
+      let mut state = slot.state.lock().unwrap();
+      loop {
+        match *state {
+          State::Loading => {},
+          State::Loaded(block) => return Some(block),
+          State::Abandoned => break,
+        }
+        state = slot.done.wait(state).unwrap();
+      }
+      debug!("Loading {:?} was abandoned elsewhere; trying again", bounds);
+    }
+  }
+
+  /// How many requests have been answered by somebody else's load.
+  pub fn coalesced(&self) -> u64 {
+    *self.coalesced.lock().unwrap()
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use std;
+  use std::sync::Arc;
+  use std::sync::atomic::{AtomicUsize, Ordering};
+  use std::sync::mpsc;
+
+  use common::voxel;
+
+  use super::*;
+
+  #[test]
+  fn test_concurrent_loads_coalesce() {
+    let pending = Arc::new(new());
+    let loads = Arc::new(AtomicUsize::new(0));
+    let bounds = voxel::bounds::new(1, 2, 3, 0);
+    let block = voxel::Volume(voxel::Material::Stone);
+
+    let threads: Vec<_> =
+      (0 .. 4).map(|_| {
+        let pending = pending.clone();
+        let loads = loads.clone();
+        std::thread::spawn(move || {
+          let waiting = pending.clone();
+          pending.load(&bounds, || {
+            loads.fetch_add(1, Ordering::SeqCst);
+            // Don't finish until everyone else is waiting on this load, however late their threads start.
+            while waiting.coalesced() < 3 {
+              std::thread::yield_now();
+            }
+            Some(block)
+          })
+        })
+      })
+      .collect();
+    for thread in threads {
+      assert_eq!(thread.join().unwrap(), Some(block));
+    }
+    assert_eq!(loads.load(Ordering::SeqCst), 1);
+    assert_eq!(pending.coalesced(), 3);
+
+    // Once it's done, the next load goes ahead.
+    pending.load(&bounds, || { loads.fetch_add(1, Ordering::SeqCst); Some(block) });
+    assert_eq!(loads.load(Ordering::SeqCst), 2);
+  }
+
+  #[test]
+  fn test_panicked_loads_are_retried() {
+    let pending = Arc::new(new());
+    let bounds = voxel::bounds::new(1, 2, 3, 0);
+    let block = voxel::Volume(voxel::Material::Stone);
+
+    let (started_send, started) = mpsc::channel();
+    let panicking = {
+      let pending = pending.clone();
+      std::thread::spawn(move || {
+        let waiting = pending.clone();
+        pending.load(&bounds, || {
+          started_send.send(()).unwrap();
+          while waiting.coalesced() < 1 {
+            std::thread::yield_now();
+          }
+          panic!("Failing a load on purpose")
+        })
+      })
+    };
+
+    // This waits on the load that panics, then loads the block itself.
+    started.recv().unwrap();
+    assert_eq!(pending.load(&bounds, || Some(block)), Some(block));
+    assert!(panicking.join().is_err());
+  }
+
+  #[test]
+  fn test_cancelled_loads_are_retried() {
+    let pending = Arc::new(new());
+    let bounds = voxel::bounds::new(1, 2, 3, 0);
+    let block = voxel::Volume(voxel::Material::Stone);
+
+    let (started_send, started) = mpsc::channel();
+    let cancelled = {
+      let pending = pending.clone();
+      std::thread::spawn(move || {
+        let waiting = pending.clone();
+        pending.load(&bounds, || {
+          started_send.send(()).unwrap();
+          while waiting.coalesced() < 1 {
+            std::thread::yield_now();
+          }
+          None
+        })
+      })
+    };
+
+    // Whoever was waiting on the cancelled load doesn't care that it was cancelled, so loads the block itself.
+    started.recv().unwrap();
+    assert_eq!(pending.load(&bounds, || Some(block)), Some(block));
+    assert_eq!(cancelled.join().unwrap(), None);
+  }
+}
@@ -2,15 +2,21 @@ use cgmath::{Vector3};
 use collision::{Aabb3};
 
 use common::fnv_map;
+use common::fnv_set;
 
+use capsule;
 use entity;
 use octree::Octree;
+use spatial_hash;
 
 pub struct T {
-  pub terrain_octree : Octree<entity::id::Terrain>,
+  // Terrain is loaded and unloaded constantly as players move, so it lives in a spatial hash rather than an octree.
+  terrain_hash   : spatial_hash::T<entity::id::Terrain>,
   terrain_bounds : fnv_map::T<entity::id::Terrain, Aabb3<f32>>,
   pub misc_octree    : Octree<entity::id::Misc>,
   misc_bounds    : fnv_map::T<entity::id::Misc, Aabb3<f32>>,
+  // Misc entities that collide with terrain as capsules fitted in their bounds, rather than as boxes.
+  capsules       : fnv_set::T<entity::id::Misc>,
 }
 
 pub enum Collision {
@@ -21,15 +27,16 @@ pub enum Collision {
 impl T {
   pub fn new(world_bounds: Aabb3<f32>) -> T {
     T {
-      terrain_octree : Octree::new(&world_bounds),
+      terrain_hash   : spatial_hash::new(),
       terrain_bounds : fnv_map::new(),
       misc_octree    : Octree::new(&world_bounds),
       misc_bounds    : fnv_map::new(),
+      capsules       : fnv_set::new(),
     }
   }
 
   pub fn insert_terrain(&mut self, id: entity::id::Terrain, bounds: &Aabb3<f32>) {
-    self.terrain_octree.insert(bounds, id);
+    self.terrain_hash.insert(bounds, id);
     self.terrain_bounds.insert(id, *bounds);
   }
 
@@ -38,51 +45,95 @@ impl T {
     self.misc_bounds.insert(id, *bounds);
   }
 
+  /// Insert a misc entity that collides with terrain as a capsule.
+  pub fn insert_misc_capsule(&mut self, id: entity::id::Misc, bounds: &Aabb3<f32>) {
+    self.insert_misc(id, bounds);
+    self.capsules.insert(id);
+  }
+
   pub fn remove_terrain(&mut self, id: entity::id::Terrain) {
-    match self.terrain_bounds.get(&id) {
+    match self.terrain_bounds.remove(&id) {
       None => {},
       Some(bounds) => {
-        self.terrain_octree.remove(bounds, id);
+        self.terrain_hash.remove(&bounds, id);
       },
     }
   }
 
   pub fn remove_misc(&mut self, id: entity::id::Misc) {
-    match self.misc_bounds.get(&id) {
+    match self.misc_bounds.remove(&id) {
       None => {},
       Some(bounds) => {
-        self.misc_octree.remove(bounds, id);
+        self.misc_octree.remove(&bounds, id);
       },
     }
+    self.capsules.remove(&id);
   }
 
   pub fn get_bounds(&self, id: entity::id::Misc) -> Option<&Aabb3<f32>> {
     self.misc_bounds.get(&id)
   }
 
-  pub fn translate_misc(&mut self, id: entity::id::Misc, amount: Vector3<f32>) -> Option<(Aabb3<f32>, Collision)> {
-    let bounds = self.misc_bounds.get_mut(&id).unwrap();
+  /// The bounds of up to `max` entities (terrain first) that overlap `region`. This looks at every entity, so it's
+  /// only for debugging.
+  pub fn bounds_in(&self, region: &Aabb3<f32>, max: usize) -> Vec<Aabb3<f32>> {
+    let overlaps = |b: &&Aabb3<f32>| {
+      b.min.x < region.max.x && region.min.x < b.max.x &&
+      b.min.y < region.max.y && region.min.y < b.max.y &&
+      b.min.z < region.max.z && region.min.z < b.max.z
+    };
+    self.terrain_bounds.values()
+      .chain(self.misc_bounds.values())
+      .filter(overlaps)
+      .take(max)
+      .cloned()
+      .collect()
+  }
+
+  /// Check whether moving a misc entity by some amount would collide with anything, without moving it.
+  pub fn check_translate_misc(&self, id: entity::id::Misc, amount: Vector3<f32>) -> Option<(Aabb3<f32>, Collision)> {
+    let bounds = self.misc_bounds.get(&id).unwrap();
     let new_bounds =
       Aabb3::new(
         bounds.min + amount,
         bounds.max + amount,
       );
-    match self.terrain_octree.intersect(&new_bounds, None) {
+    self.collision(id, &new_bounds)
+  }
+
+  fn collision(&self, id: entity::id::Misc, new_bounds: &Aabb3<f32>) -> Option<(Aabb3<f32>, Collision)> {
+    let terrain_collision =
+      if self.capsules.contains(&id) {
+        let capsule = capsule::of_bounds(new_bounds);
+        self.terrain_hash.intersect_where(new_bounds, None, &mut |bounds| capsule.intersects(bounds))
+      } else {
+        self.terrain_hash.intersect(new_bounds, None)
+      };
+    match terrain_collision {
       Some((bounds, terrain_id)) => {
         Some((bounds, Collision::Terrain(terrain_id)))
       },
       None => {
-        match self.misc_octree.intersect(&new_bounds, Some(id)) {
-          Some((bounds, misc_id)) => {
-            Some ((bounds, Collision::Misc(misc_id)))
-          },
-          None => {
-            self.misc_octree.reinsert(id, bounds, &new_bounds);
-            *bounds = new_bounds;
-            None
-          },
-        }
+        self.misc_octree.intersect(new_bounds, Some(id))
+          .map(|(bounds, misc_id)| (bounds, Collision::Misc(misc_id)))
       },
     }
   }
+
+  pub fn translate_misc(&mut self, id: entity::id::Misc, amount: Vector3<f32>) -> Option<(Aabb3<f32>, Collision)> {
+    let new_bounds = {
+      let bounds = self.misc_bounds.get(&id).unwrap();
+      Aabb3::new(
+        bounds.min + amount,
+        bounds.max + amount,
+      )
+    };
+    let collision = self.collision(id, &new_bounds);
+    if collision.is_none() {
+      let bounds = self.misc_bounds.get_mut(&id).unwrap();
+      self.misc_octree.reinsert(id, bounds, &new_bounds);
+      *bounds = new_bounds;
+    }
+    collision
+  }
 }
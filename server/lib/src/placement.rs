@@ -0,0 +1,124 @@
+//! Growing trees and placing rocks where players aim. Both are fitted to the ground around where they go, which means
+//! loading that terrain, so they're built on the gaia thread rather than wherever they were asked for.
+
+use cgmath::{Point3, Vector3, EuclideanSpace};
+use collision::Aabb3;
+use rand;
+use rand::distributions::IndependentSample;
+
+use common::voxel;
+
+use brush;
+use server;
+use terrain;
+use voxel_data;
+
+/// How many voxels below a tree's base we'll look for ground to root it in.
+const MAX_ROOT_DEPTH: i32 = 8;
+/// The smallest and largest radius of a placed rock.
+const MIN_ROCK_RADIUS: f64 = 1.5;
+const MAX_ROCK_RADIUS: f64 = 6.0;
+
+/// The height of the ground in a voxel's column, at or below that voxel, if there is any nearby.
+fn ground_height(server: &server::T, column: &voxel::bounds::T) -> Option<f32> {
+  for dy in 0 .. MAX_ROOT_DEPTH + 1 {
+    let bounds = voxel::bounds::new(column.x, column.y - dy, column.z, column.lg_size);
+    match server.terrain_loader.load_voxels(&bounds) {
+      voxel::Surface(ref vertex) => {
+        return Some(vertex.surface_vertex.to_world_vertex(&bounds).y)
+      },
+      voxel::Volume(voxel::Material::Empty) => {},
+      voxel::Volume(_) => {
+        return Some(bounds.corners().1.y)
+      },
+    }
+  }
+  None
+}
+
+/// Find where to put the base of a tree planted in a voxel, and how deep its roots need to go so that no part of the
+/// trunk's footprint floats above the ground.
+fn tree_base(server: &server::T, bounds: &voxel::bounds::T, trunk_radius: f32) -> (Point3<f32>, f32) {
+  let (low, high) = bounds.corners();
+  let mut base = (low + high.to_vec()) / 2.0;
+  base.y = ground_height(server, bounds).unwrap_or(low.y);
+
+  let size = high.x - low.x;
+  let reach = (trunk_radius / size).ceil() as i32;
+  let mut lowest = base.y;
+  for &(dx, dz) in &[(-reach, 0), (reach, 0), (0, -reach), (0, reach)] {
+    let column = voxel::bounds::new(bounds.x + dx, bounds.y, bounds.z + dz, bounds.lg_size);
+    ground_height(server, &column).map(|y| {
+      lowest = f32::min(lowest, y);
+    });
+  }
+
+  (base, base.y - lowest)
+}
+
+/// A brush that grows a randomly-sized tree out of the ground at a voxel.
+pub fn tree<Rng: rand::Rng>(server: &server::T, rng: &mut Rng, bounds: &voxel::bounds::T) -> brush::T {
+  let trunk_radius =
+    rand::distributions::normal::Normal::new(2.0, 0.5)
+    .ind_sample(rng);
+  let trunk_radius =
+    f64::max(1.0, f64::min(3.0, trunk_radius));
+
+  let trunk_height =
+    rand::distributions::normal::Normal::new(8.0 * trunk_radius, 2.0 * trunk_radius)
+    .ind_sample(rng);
+  let trunk_height =
+    f64::max(4.0 * trunk_radius, f64::min(12.0 * trunk_radius, trunk_height));
+
+  let leaf_radius =
+    rand::distributions::normal::Normal::new(4.0 * trunk_radius, trunk_radius)
+    .ind_sample(rng);
+  let leaf_radius =
+    f64::max(2.0 * trunk_radius, f64::min(6.0 * trunk_radius, leaf_radius));
+
+  let trunk_height = trunk_height as f32;
+  let trunk_radius = trunk_radius as f32;
+  let leaf_radius = leaf_radius as f32;
+
+  let (bottom, root_depth) = tree_base(server, bounds, trunk_radius);
+
+  let tree =
+    voxel_data::mosaic::translation::T {
+      translation: bottom.to_vec(),
+      mosaic: terrain::tree::new(rng, trunk_height, trunk_radius, leaf_radius, root_depth),
+    };
+
+  let center =
+    bottom + (&Vector3::new(0.0, trunk_height / 2.0, 0.0));
+  let r = trunk_height / 2.0 + f32::max(leaf_radius, root_depth) + 20.0;
+  voxel_data::brush::T {
+    bounds:
+      Aabb3::new(
+        {
+          let low = center + (&-Vector3::new(r, r, r));
+          Point3::new(low.x.floor() as i32, low.y.floor() as i32, low.z.floor() as i32)
+        },
+        {
+          let high = center + (&Vector3::new(r, r, r));
+          Point3::new(high.x.ceil() as i32, high.y.ceil() as i32, high.z.ceil() as i32)
+        },
+      ),
+    mosaic: Box::new(tree) as Box<voxel_data::mosaic::T<voxel::Material> + Send>,
+    min_lg_size: 0,
+  }
+}
+
+/// A brush that half-buries a randomly-sized rock in the ground at a voxel.
+pub fn rock<Rng: rand::Rng>(server: &server::T, rng: &mut Rng, bounds: &voxel::bounds::T) -> brush::T {
+  let radius =
+    rand::distributions::normal::Normal::new(3.0, 1.0)
+    .ind_sample(rng);
+  let radius = f64::max(MIN_ROCK_RADIUS, f64::min(MAX_ROCK_RADIUS, radius)) as f32;
+
+  let (low, high) = bounds.corners();
+  let mut center = (low + high.to_vec()) / 2.0;
+  center.y = ground_height(server, bounds).unwrap_or(low.y);
+
+  let rock = terrain::rock::new(rng, radius);
+  brush::of_mosaic(&center, terrain::rock::reach(radius), rock)
+}
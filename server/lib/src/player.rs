@@ -1,24 +1,58 @@
 use cgmath;
-use cgmath::{Point3, Matrix3, Vector3, ElementWise};
+use cgmath::{Point3, Matrix3, Vector3, ElementWise, InnerSpace, MetricSpace};
 use collision::{Aabb3, Ray3};
+use std::collections::VecDeque;
 use std::f32::consts::PI;
 use std::ops::DerefMut;
 use std::sync::Mutex;
 use stopwatch;
 
+use common::cube_shell::cube_shell;
 use common::id_allocator;
+use common::math;
+use common::protocol;
 use common::surroundings_loader;
 use common::voxel;
 
 use entity;
 use lod;
 use physics;
+use prefetch;
 use server;
 use update_gaia;
 use update_world::load_placeholders;
 
 const MAX_JUMP_FUEL: u32 = 4;
-const MAX_STEP_HEIGHT: f32 = 1.0;
+/// How far around the player (in blocks) terrain is kept loaded.
+const LOAD_DISTANCE: u32 = 8;
+/// Where new players appear: the bottom corner of their bounds.
+pub const SPAWN_POINT: Point3<f32> = Point3 { x: 0.0, y: 64.0, z: 4.0 };
+/// The default value for `T::step_height`.
+pub const DEFAULT_STEP_HEIGHT: f32 = 1.0;
+/// The highest `T::step_height` can be set: as tall as the player.
+pub const MAX_STEP_HEIGHT: f32 = 2.0;
+/// How much climbing a slope slows the player down. Horizontal speed is divided by
+/// `1 + SLOPE_DRAG * rise / run` after every step up.
+const SLOPE_DRAG: f32 = 0.5;
+/// How much faster players walk while sprinting.
+const SPRINT_FACTOR: f32 = 1.6;
+/// Players can land at up to this speed (in world units per step) without getting hurt.
+/// Falling about five blocks gets a player this fast.
+const SAFE_LANDING_SPEED: f32 = 1.0;
+/// Health lost per unit of landing speed over `SAFE_LANDING_SPEED`.
+const FALL_DAMAGE_PER_SPEED: f32 = 40.0;
+/// How long players' recent poses are kept, to check their clients' aim against.
+const POSE_HISTORY_NS: u64 = 1_000_000_000;
+/// How far (in time) a client's aim can be from a recorded pose and still match it. This covers error in the
+/// client's estimate of server time, and the time between simulation steps.
+const AIM_TIME_SLACK_NS: u64 = 150_000_000;
+/// How far a client's camera can be from a recorded position and still match it. The camera sits above the center of
+/// the player's bounds.
+const AIM_POSITION_SLACK: f32 = 2.0;
+/// The cosine of the largest angle between a client's aim and a recorded facing that still match.
+const AIM_DIRECTION_SLACK: f32 = 0.95;
+/// What the player's speed is multiplied by after every step.
+const FRICTION: Vector3<f32> = Vector3 { x: 0.7, y: 0.99, z: 0.7 };
 
 #[derive(Debug, Clone)]
 pub enum Collision {
@@ -38,28 +72,49 @@ pub struct T {
   pub jump_fuel: u32,
   // are we currently trying to jump? (e.g. holding the key).
   pub is_jumping: bool,
+  // is the sprint key held down?
+  pub is_sprinting: bool,
+  // the tallest obstacle we'll automatically step up onto while walking.
+  pub step_height: f32,
   pub entity_id: entity::id::Player,
   pub physics_id: entity::id::Misc,
+  // the client controlling this player.
+  pub client_id: protocol::ClientId,
+  // what digging removes, and how big it is.
+  pub brush_shape: protocol::BrushShape,
+  pub brush_radius: f32,
+  // in [0, protocol::MAX_HEALTH]; the player respawns when this reaches 0.
+  pub health: u32,
+  // whether health has changed since the client was last told.
+  health_changed: bool,
 
   // rotation around the y-axis, in radians
   pub lateral_rotation: f32,
   // "pitch", in radians
   pub vertical_rotation: f32,
 
+  // recent (time, position, facing) samples, oldest first.
+  poses: VecDeque<(u64, Point3<f32>, Vector3<f32>)>,
+
   surroundings_loader: surroundings_loader::T,
   surroundings_owner: lod::OwnerId,
   // Nearby blocks should be made solid if they aren't loaded yet.
   solid_boundary: surroundings_loader::T,
   solid_owner: lod::OwnerId,
+  // Blocks along the path the player's headed on, requested ahead of its surroundings.
+  prefetched: Vec<Point3<i32>>,
+  prefetch_owner: lod::OwnerId,
 }
 
 pub fn new(
   entity_id: entity::id::Player,
   physics_id: entity::id::Misc,
+  client_id: protocol::ClientId,
   owner_allocator: &Mutex<id_allocator::T<lod::OwnerId>>,
 ) -> T {
   let surroundings_owner = owner_allocator.lock().unwrap().allocate();
   let solid_owner = owner_allocator.lock().unwrap().allocate();
+  let prefetch_owner = owner_allocator.lock().unwrap().allocate();
   T {
     position            : Point3::new(0.0, 0.0, 0.0),
     speed               : Vector3::new(0.0, 0.0, 0.0),
@@ -67,21 +122,94 @@ pub fn new(
     walk_accel          : Vector3::new(0.0, 0.0, 0.0),
     jump_fuel           : 0,
     is_jumping          : false,
+    is_sprinting        : false,
+    step_height         : DEFAULT_STEP_HEIGHT,
     entity_id           : entity_id,
     physics_id          : physics_id,
+    client_id           : client_id,
+    brush_shape         : protocol::BrushShape::Sphere,
+    brush_radius        : protocol::DEFAULT_BRUSH_RADIUS,
+    health              : protocol::MAX_HEALTH,
+    health_changed      : false,
     lateral_rotation    : 0.0,
     vertical_rotation   : 0.0,
+    poses               : VecDeque::new(),
 
-    surroundings_loader : surroundings_loader::new(8, Vec::new()),
-    solid_boundary      : surroundings_loader::new(8, Vec::new()),
+    surroundings_loader : surroundings_loader::new(LOAD_DISTANCE, Vec::new()),
+    solid_boundary      : surroundings_loader::new(LOAD_DISTANCE, Vec::new()),
     surroundings_owner  : surroundings_owner,
     solid_owner         : solid_owner,
+    prefetched          : Vec::new(),
+    prefetch_owner      : prefetch_owner,
   }
 }
 
+/// The bounds of a player whose bottom corner is at `min`.
+pub fn bounds_at(min: Point3<f32>) -> Aabb3<f32> {
+  Aabb3::new(min, min + Vector3::new(1.0, 2.0, 1.0))
+}
+
+fn block_position(position: &Point3<f32>) -> Point3<i32> {
+  Point3::new(
+    position.x as i32,
+    position.y as i32,
+    position.z as i32,
+  )
+}
+
 impl T {
+  /// Move the player so the bottom corner of its bounds is at `min`, and tell its client.
+  /// Terrain the player was keeping loaded around its old position is released right away,
+  /// rather than waiting for it to be unloaded incrementally.
+  pub fn teleport(&mut self, server: &server::T, min: Point3<f32>) {
+    self.release_terrain(server);
+
+    let bounds = bounds_at(min);
+    {
+      let mut physics = server.physics.lock().unwrap();
+      physics.remove_misc(self.physics_id);
+      physics.insert_misc_capsule(self.physics_id, &bounds);
+    }
+    self.position = math::center(&bounds);
+    self.speed = Vector3::new(0.0, 0.0, 0.0);
+    server.components.moved(protocol::EntityRef::Player(self.entity_id), &bounds, self.speed);
+
+    let mut clients = server.clients.lock().unwrap();
+    if let Some(client) = clients.get_mut(&self.client_id) {
+      // Check for distant blocks to unload on the next update.
+      client.last_unload_position = None;
+      client.send(protocol::ServerToClient::Teleported(self.entity_id, self.position));
+    }
+  }
+
+  /// Stop keeping terrain loaded around the player's current position.
+  fn release_terrain(&mut self, server: &server::T) {
+    let center = block_position(&self.position);
+    for radius in 0 .. LOAD_DISTANCE as i32 {
+      for pos in cube_shell(&center, radius) {
+        let pos = voxel::bounds::new(pos.x, pos.y, pos.z, 0);
+        server.terrain_loader.unload(&server.physics, &pos, self.surroundings_owner);
+        server.terrain_loader.unload(&server.physics, &pos, self.solid_owner);
+      }
+    }
+    self.surroundings_loader = surroundings_loader::new(LOAD_DISTANCE, Vec::new());
+    self.solid_boundary = surroundings_loader::new(LOAD_DISTANCE, Vec::new());
+    for pos in self.prefetched.drain(..) {
+      server.terrain_loader.unload(&server.physics, &voxel::bounds::new(pos.x, pos.y, pos.z, 0), self.prefetch_owner);
+    }
+  }
+
+  /// Take a player (that's already been removed from `server.players`) out of the world.
+  pub fn remove(mut self, server: &server::T) {
+    self.release_terrain(server);
+    server.physics.lock().unwrap().remove_misc(self.physics_id);
+  }
+
   /// Translates the player by a vector.
-  /// If the player collides with something with a small height jump, the player will shift upward.
+  /// If the player walks into something shorter than `step_height` and there's room above it, the player will
+  /// shift upward onto it.
+  /// Horizontal movement that gets blocked outright kills the player's speed along that axis,
+  /// and movement that steps up is slowed in proportion to the slope climbed.
   /// Returns the actual amount moved by.
   fn translate(
     &mut self,
@@ -119,25 +247,53 @@ impl T {
           let step_height = collision_bounds.max.y - requested_bounds.min.y;
           assert!(step_height > 0.0);
 
-          if step_height > MAX_STEP_HEIGHT {
+          if step_height > self.step_height {
             // Step is too big; we just ran into something.
             break
           }
 
+          if requested_shift.y == 0.0 {
+            // Only step up if there's headroom to rise straight up before moving over the obstacle.
+            let rise = Vector3::new(0.0, shift.y + step_height, 0.0);
+            if physics.check_translate_misc(self.physics_id, rise).is_some() {
+              break
+            }
+          }
+
           shift += Vector3::new(0.0, step_height, 0.0);
         },
       }
     }
 
     let shifted = *physics.get_bounds(self.physics_id).unwrap();
-    self.position += shifted.min - init_bounds.min;
+    let moved = shifted.min - init_bounds.min;
+    self.position += moved;
 
     if collided {
       if requested_shift.y < 0.0 {
         self.jump_fuel = MAX_JUMP_FUEL;
+        self.land(-requested_shift.y);
       }
 
       self.speed.y -= requested_shift.y;
+
+      if requested_shift.y == 0.0 {
+        if moved.y > 0.0 {
+          // We stepped up; walking uphill is slower the steeper it is.
+          let run = requested_shift.x.abs() + requested_shift.z.abs();
+          let drag = 1.0 + SLOPE_DRAG * moved.y / run;
+          self.speed.x /= drag;
+          self.speed.z /= drag;
+        } else {
+          // We walked into a wall. Drop our speed into it so we slide along it instead of sticking.
+          if requested_shift.x != 0.0 {
+            self.speed.x = 0.0;
+          }
+          if requested_shift.z != 0.0 {
+            self.speed.z = 0.0;
+          }
+        }
+      }
     } else {
       if requested_shift.y < 0.0 {
         self.jump_fuel = 0;
@@ -147,20 +303,54 @@ impl T {
     (shifted, collisions)
   }
 
+  /// Hurt the player for landing at `speed`, if it's fast enough to hurt.
+  fn land(&mut self, speed: f32) {
+    if speed <= SAFE_LANDING_SPEED {
+      return
+    }
+    let damage = ((speed - SAFE_LANDING_SPEED) * FALL_DAMAGE_PER_SPEED).ceil() as u32;
+    debug!("{:?} landed at {} and took {} damage", self.entity_id, speed, damage);
+    self.health = self.health.saturating_sub(damage);
+    self.health_changed = true;
+  }
+
+  /// The player's health, if it's changed since this was last called.
+  pub fn take_health_update(&mut self) -> Option<u32> {
+    if self.health_changed {
+      self.health_changed = false;
+      Some(self.health)
+    } else {
+      None
+    }
+  }
 
-  pub fn update<RequestBlock>(
+  /// Put the player back at the spawn point at full health. Returns its new bounds.
+  pub fn respawn(&mut self, server: &server::T) -> Aabb3<f32> {
+    info!("{:?} died; respawning", self.entity_id);
+    self.teleport(server, SPAWN_POINT);
+    // Forget any jump in progress.
+    if self.is_jumping {
+      self.is_jumping = false;
+      self.accel.y = self.accel.y - 0.3;
+    }
+    self.health = protocol::MAX_HEALTH;
+    self.health_changed = true;
+    bounds_at(SPAWN_POINT)
+  }
+
+  /// Load and unload terrain around the player as it moves.
+  pub fn load_surroundings<RequestBlock>(
     &mut self,
     server: &server::T,
     request_block: &mut RequestBlock,
-  ) -> (Aabb3<f32>, Vec<Collision>) where
+  ) where
     RequestBlock: FnMut(update_gaia::Message),
   {
-    let player_position =
-      Point3::new(
-        self.position.x as i32,
-        self.position.y as i32,
-        self.position.z as i32,
-      );
+    let player_position = block_position(&self.position);
+
+    stopwatch::time("update.player.prefetch", || {
+      self.prefetch(server, request_block);
+    });
 
     stopwatch::time("update.player.surroundings", || {
       let owner = self.surroundings_owner;
@@ -199,7 +389,43 @@ impl T {
         )
       }
     });
+  }
+
+  /// Ask for the blocks the player's headed into at full detail, and let go of the ones it's no longer headed into.
+  /// Blocks that are already loaded cost nothing extra.
+  fn prefetch<RequestBlock>(
+    &mut self,
+    server: &server::T,
+    request_block: &mut RequestBlock,
+  ) where
+    RequestBlock: FnMut(update_gaia::Message),
+  {
+    let ahead = prefetch::blocks_ahead(&self.position, &self.speed, &self.accel, &FRICTION, block_position);
+    for pos in &self.prefetched {
+      if !ahead.contains(pos) {
+        let pos = voxel::bounds::new(pos.x, pos.y, pos.z, 0);
+        server.terrain_loader.unload(&server.physics, &pos, self.prefetch_owner);
+      }
+    }
+    for pos in &ahead {
+      if !self.prefetched.contains(pos) {
+        let pos = voxel::bounds::new(pos.x, pos.y, pos.z, 0);
+        server.terrain_loader.load(
+          &server.misc_allocator,
+          &server.physics,
+          &pos,
+          lod::Full,
+          self.prefetch_owner,
+          request_block,
+        );
+      }
+    }
+    self.prefetched = ahead;
+  }
 
+  /// Advance the player's movement by one fixed simulation step.
+  /// Returns the player's new bounds, and what it collided with along the way.
+  pub fn step(&mut self, server: &server::T) -> (Aabb3<f32>, Vec<Collision>) {
     if self.is_jumping {
       if self.jump_fuel > 0 {
         self.jump_fuel -= 1;
@@ -233,10 +459,11 @@ impl T {
     let walk_v =
         Matrix3::from_axis_angle(y_axis, cgmath::Rad(self.lateral_rotation))
         * self.walk_accel;
+    let walk_v = if self.is_sprinting { walk_v * SPRINT_FACTOR } else { walk_v };
     self.speed += walk_v;
     self.speed += self.accel;
     // friction
-    self.speed.mul_assign_element_wise(Vector3::new(0.7, 0.99, 0.7 as f32));
+    self.speed.mul_assign_element_wise(FRICTION);
 
     (new_bounds, collisions)
   }
@@ -287,4 +514,40 @@ impl T {
   pub fn forward_ray(&self) -> Ray3<f32> {
     Ray3::new(self.position, self.forward())
   }
+
+  /// Remember where the player is and which way it's facing at time `now_ns`, and forget poses that are too old to
+  /// check aim against.
+  pub fn record_pose(&mut self, now_ns: u64) {
+    let pose = (now_ns, self.position, self.forward());
+    self.poses.push_back(pose);
+    while self.poses.front().map(|&(t, _, _)| t + POSE_HISTORY_NS < now_ns).unwrap_or(false) {
+      self.poses.pop_front();
+    }
+  }
+
+  /// The ray to act along for a client's aim. If the aim matches where the player recently was and which way it was
+  /// facing, that's the client's ray; otherwise the client is out of sync (or lying), and the player's current ray is
+  /// used instead.
+  pub fn aim_ray(&self, aim: &protocol::Aim) -> Ray3<f32> {
+    let direction_ok = aim.direction.x.is_finite() && aim.direction.y.is_finite() && aim.direction.z.is_finite();
+    if !direction_ok || aim.direction.magnitude2() == 0.0 {
+      debug!("Ignoring degenerate aim {:?}", aim);
+      return self.forward_ray()
+    }
+    let direction = aim.direction.normalize();
+
+    let matches =
+      self.poses.iter().any(|&(t, position, forward)| {
+        let dt = if t > aim.time_ns { t - aim.time_ns } else { aim.time_ns - t };
+        dt <= AIM_TIME_SLACK_NS
+        && position.distance(aim.position) <= AIM_POSITION_SLACK
+        && forward.dot(direction) >= AIM_DIRECTION_SLACK
+      });
+    if matches {
+      Ray3::new(aim.position, direction)
+    } else {
+      debug!("Aim {:?} doesn't match {:?}'s recent poses", aim, self.entity_id);
+      self.forward_ray()
+    }
+  }
 }
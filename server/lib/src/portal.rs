@@ -0,0 +1,156 @@
+//! Portals: teleporter pads that send players who step onto them somewhere else. Each one is a region of the world
+//! (the pad, and the space above it that a player stands in) with a destination. Players are only sent through when
+//! they step into a portal, so arriving on another portal doesn't send them straight back out. The portals lock is
+//! innermost.
+
+use cgmath::{Point3, Vector3};
+use collision::Aabb3;
+
+use common::fnv_map;
+
+use entity;
+
+/// How much space above a pad counts as being in the portal.
+const HEADROOM: f32 = 2.0;
+/// How thick pads are.
+pub const PAD_THICKNESS: f32 = 1.0;
+
+#[allow(missing_docs)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Portal {
+  pub region      : Aabb3<f32>,
+  /// Where the bottom corner of a player's bounds ends up.
+  pub destination : Point3<f32>,
+}
+
+/// The region of a portal whose pad is centered at `center`, `radius` wide (in each direction along x and z).
+pub fn region(center: &Point3<f32>, radius: f32) -> Aabb3<f32> {
+  let half = PAD_THICKNESS / 2.0;
+  Aabb3::new(
+    *center + Vector3::new(-radius, -half, -radius),
+    *center + Vector3::new(radius, half + HEADROOM, radius),
+  )
+}
+
+fn overlaps(a: &Aabb3<f32>, b: &Aabb3<f32>) -> bool {
+  a.min.x < b.max.x && b.min.x < a.max.x &&
+  a.min.y < b.max.y && b.min.y < a.max.y &&
+  a.min.z < b.max.z && b.min.z < a.max.z
+}
+
+#[allow(missing_docs)]
+pub struct T {
+  portals : fnv_map::T<u32, Portal>,
+  next_id : u32,
+  /// The portal each player is standing in, if they're in one.
+  inside  : fnv_map::T<entity::id::Player, u32>,
+}
+
+#[allow(missing_docs)]
+pub fn new() -> T {
+  T {
+    portals : fnv_map::new(),
+    next_id : 0,
+    inside  : fnv_map::new(),
+  }
+}
+
+impl T {
+  /// Add a portal. Returns its id.
+  pub fn add(&mut self, portal: Portal) -> u32 {
+    let id = self.next_id;
+    self.next_id += 1;
+    self.portals.insert(id, portal);
+    id
+  }
+
+  /// Take a portal (by id) away.
+  pub fn remove(&mut self, id: u32) -> Result<Portal, String> {
+    self.inside.retain(|_, &mut portal| portal != id);
+    self.portals.remove(&id).ok_or_else(|| format!("No portal with id {}", id))
+  }
+
+  #[allow(missing_docs)]
+  pub fn len(&self) -> usize {
+    self.portals.len()
+  }
+
+  /// The first portal (by id) that `bounds` is in, if any.
+  fn portal_at(&self, bounds: &Aabb3<f32>) -> Option<u32> {
+    self.portals.iter()
+      .filter(|&(_, portal)| overlaps(&portal.region, bounds))
+      .map(|(&id, _)| id)
+      .min()
+  }
+
+  /// A player has moved to `bounds`. If it's just stepped into a portal, this is where to send it.
+  pub fn moved(&mut self, player_id: entity::id::Player, bounds: &Aabb3<f32>) -> Option<Point3<f32>> {
+    match self.portal_at(bounds) {
+      None => {
+        self.inside.remove(&player_id);
+        None
+      },
+      Some(id) => {
+        if self.inside.insert(player_id, id) == Some(id) {
+          None
+        } else {
+          Some(self.portals[&id].destination)
+        }
+      },
+    }
+  }
+
+  /// A player has been sent to `bounds`. It isn't sent on by any portal it lands in until it steps out and back in.
+  pub fn arrived(&mut self, player_id: entity::id::Player, bounds: &Aabb3<f32>) {
+    match self.portal_at(bounds) {
+      None => self.inside.remove(&player_id),
+      Some(id) => self.inside.insert(player_id, id),
+    };
+  }
+
+  /// Forget a player that's left.
+  pub fn forget(&mut self, player_id: entity::id::Player) {
+    self.inside.remove(&player_id);
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use cgmath::{Point3, Vector3};
+  use collision::Aabb3;
+
+  use entity;
+
+  use super::*;
+
+  fn standing_at(x: f32, y: f32, z: f32) -> Aabb3<f32> {
+    Aabb3::new(Point3::new(x, y, z), Point3::new(x, y, z) + Vector3::new(1.0, 2.0, 1.0))
+  }
+
+  #[test]
+  fn test_round_trip() {
+    let player: entity::id::Player = Default::default();
+    let (a, b) = (Point3::new(0.0, 10.0, 0.0), Point3::new(100.0, 10.0, 0.0));
+    let mut portals = new();
+    let to_b = portals.add(Portal { region: region(&a, 2.0), destination: Point3::new(99.5, 10.5, -0.5) });
+    portals.add(Portal { region: region(&b, 2.0), destination: Point3::new(-0.5, 10.5, -0.5) });
+
+    assert_eq!(portals.moved(player, &standing_at(-10.0, 10.5, -0.5)), None);
+    // Stepping onto a pad sends the player through, once.
+    let destination = portals.moved(player, &standing_at(-0.5, 10.5, -0.5)).unwrap();
+    assert_eq!(destination, Point3::new(99.5, 10.5, -0.5));
+    assert_eq!(portals.moved(player, &standing_at(-0.5, 10.5, -0.5)), None);
+
+    // Arriving on the other pad doesn't send them back until they step off and on again.
+    portals.arrived(player, &standing_at(destination.x, destination.y, destination.z));
+    assert_eq!(portals.moved(player, &standing_at(100.0, 10.5, 0.0)), None);
+    assert_eq!(portals.moved(player, &standing_at(103.0, 10.5, 0.0)), None);
+    assert_eq!(portals.moved(player, &standing_at(100.0, 10.5, 0.0)), Some(Point3::new(-0.5, 10.5, -0.5)));
+
+    // Standing above the headroom, or after the portal's gone, does nothing.
+    assert_eq!(portals.moved(player, &standing_at(-0.5, 13.0, -0.5)), None);
+    portals.remove(to_b).unwrap();
+    assert_eq!(portals.moved(player, &standing_at(-0.5, 10.5, -0.5)), None);
+    assert!(portals.remove(to_b).is_err());
+  }
+}
@@ -0,0 +1,103 @@
+//! Guessing which blocks a moving player is about to need. The surroundings loader works outward from where a player
+//! is, a shell at a time, so a player falling fast can outrun it and drop through terrain that hasn't loaded yet.
+//! Following the player's current speed and acceleration forward a little way says which blocks it's headed into, so
+//! they can be asked for ahead of the rest.
+
+use cgmath::{Point3, Vector3, ElementWise, InnerSpace};
+
+use fixed_step::STEPS_PER_SECOND;
+
+/// How many simulation steps ahead to follow a player.
+const STEPS_AHEAD: u64 = STEPS_PER_SECOND;
+/// How many blocks below where a falling player's path ends are asked for too, in case it keeps falling.
+const FALL_COLUMN_DEPTH: i32 = 4;
+/// The most blocks to ask for ahead of one player.
+pub const MAX_BLOCKS: usize = 64;
+
+/// The blocks a player at `position` is headed through, in the order it'll get to them. Each step, `accel` is added
+/// to the player's speed, which is then multiplied by `friction`. `block_of` is which block a position is in.
+pub fn blocks_ahead<BlockOf>(
+  position : &Point3<f32>,
+  speed    : &Vector3<f32>,
+  accel    : &Vector3<f32>,
+  friction : &Vector3<f32>,
+  block_of : BlockOf,
+) -> Vec<Point3<i32>> where
+  BlockOf: Fn(&Point3<f32>) -> Point3<i32>,
+{
+  let mut blocks: Vec<Point3<i32>> = Vec::new();
+  {
+    let mut push = |block: Point3<i32>| {
+      if blocks.len() < MAX_BLOCKS && !blocks.contains(&block) {
+        blocks.push(block);
+      }
+    };
+
+    let mut position = *position;
+    let mut speed = *speed;
+    push(block_of(&position));
+    for _ in 0 .. STEPS_AHEAD {
+      // Go at most one unit at a time, so fast players don't skip over blocks.
+      let parts = speed.magnitude().ceil().max(1.0);
+      for _ in 0 .. parts as u32 {
+        position += speed / parts;
+        push(block_of(&position));
+      }
+      speed += *accel;
+      speed.mul_assign_element_wise(*friction);
+    }
+
+    if speed.y < 0.0 {
+      let bottom = block_of(&position);
+      for depth in 1 .. FALL_COLUMN_DEPTH + 1 {
+        push(Point3::new(bottom.x, bottom.y - depth, bottom.z));
+      }
+    }
+  }
+  blocks
+}
+
+#[cfg(test)]
+mod test {
+  use cgmath::{Point3, Vector3};
+
+  use super::*;
+
+  fn block_of(position: &Point3<f32>) -> Point3<i32> {
+    Point3::new(position.x.floor() as i32, position.y.floor() as i32, position.z.floor() as i32)
+  }
+
+  #[test]
+  fn test_blocks_ahead() {
+    let friction = Vector3::new(0.7, 0.99, 0.7);
+    let still = Vector3::new(0.0, 0.0, 0.0);
+    assert_eq!(
+      blocks_ahead(&Point3::new(0.5, 0.5, 0.5), &still, &still, &friction, block_of),
+      vec!(Point3::new(0, 0, 0)),
+    );
+
+    // A fast fall asks for the whole column under the player, top down, without gaps.
+    let falling = blocks_ahead(&Point3::new(0.5, 0.5, 0.5), &Vector3::new(0.0, -3.0, 0.0), &still, &friction, block_of);
+    assert_eq!(falling.len(), MAX_BLOCKS);
+    for (i, block) in falling.iter().enumerate() {
+      assert_eq!(*block, Point3::new(0, -(i as i32), 0));
+    }
+
+    // A jump goes up, then the column below where it lands is asked for too.
+    let jumping =
+      blocks_ahead(
+        &Point3::new(0.5, 0.5, 0.5),
+        &Vector3::new(0.0, 1.0, 0.0),
+        &Vector3::new(0.0, -0.1, 0.0),
+        &friction,
+        block_of,
+      );
+    assert_eq!(jumping[0], Point3::new(0, 0, 0));
+    assert_eq!(jumping[1], Point3::new(0, 1, 0));
+    let top = jumping.iter().map(|block| block.y).max().unwrap();
+    let bottom = jumping.iter().map(|block| block.y).min().unwrap();
+    assert!(top >= 4);
+    assert!(bottom < 0);
+    assert!(jumping.iter().all(|block| block.x == 0 && block.z == 0));
+  }
+}
@@ -0,0 +1,231 @@
+//! Things players throw. A projectile flies under gravity until it hits terrain, where it knocks a small hole, or
+//! until it's been flying for too long. Clients draw projectiles with their mobs, so they share the mob ids.
+
+use cgmath::{Point3, Vector3, InnerSpace};
+use collision::{Aabb3, Ray3};
+use std;
+
+use common::protocol;
+use common::voxel;
+
+use brush;
+use client_recv_thread::cast_ray;
+use edit_permissions;
+use entity;
+use fixed_step::STEPS_PER_SECOND;
+use server;
+use update_gaia;
+
+/// Half the width of a projectile.
+const RADIUS: f32 = 0.25;
+/// How fast a projectile leaves its thrower, in world units per step.
+const THROW_SPEED: f32 = 1.5;
+/// How far ahead of its thrower's eye a projectile starts, so it doesn't hit the thrower.
+const LAUNCH_DISTANCE: f32 = 1.0;
+/// Added to a projectile's speed every step.
+const GRAVITY: Vector3<f32> = Vector3 { x: 0.0, y: -0.04, z: 0.0 };
+/// How many steps a projectile can fly before it's dropped.
+const MAX_AGE: u64 = 10 * STEPS_PER_SECOND;
+/// The radius of the hole a projectile knocks in terrain.
+const IMPACT_RADIUS: f32 = 1.0;
+
+#[allow(missing_docs)]
+pub struct T {
+  pub position : Point3<f32>,
+  pub speed    : Vector3<f32>,
+  /// Who threw it. Holes it knocks are their edits.
+  pub thrower  : entity::id::Player,
+  /// How many steps it's been flying.
+  age          : u64,
+}
+
+/// What happened to a projectile over a step.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Flight {
+  Flying,
+  Hit(Point3<f32>),
+  Expired,
+}
+
+impl T {
+  fn step(&mut self, server: &server::T) -> Flight {
+    self.age += 1;
+    if self.age > MAX_AGE {
+      return Flight::Expired
+    }
+    let hit = impact(&self.position, &self.speed, |ray| cast_ray(server, ray).map(|(bounds, _)| bounds));
+    if let Some(point) = hit {
+      return Flight::Hit(point)
+    }
+    self.position += self.speed;
+    self.speed += GRAVITY;
+    Flight::Flying
+  }
+}
+
+/// The bounds of a projectile centered at `position`.
+pub fn bounds(position: &Point3<f32>) -> Aabb3<f32> {
+  let r = Vector3::new(RADIUS, RADIUS, RADIUS);
+  Aabb3::new(*position + -r, *position + r)
+}
+
+/// Throw a projectile along `ray`, on top of the thrower's own speed.
+pub fn throw(server: &server::T, thrower: entity::id::Player, ray: &Ray3<f32>, thrower_speed: &Vector3<f32>) {
+  let id = server.mob_allocator.lock().unwrap().allocate();
+  debug!("{:?} throws {:?}", thrower, id);
+  server.projectiles.lock().unwrap().insert(
+    id,
+    T {
+      position : ray.origin + ray.direction * LAUNCH_DISTANCE,
+      speed    : ray.direction * THROW_SPEED + *thrower_speed,
+      thrower  : thrower,
+      age      : 0,
+    },
+  );
+}
+
+/// Where something moving from `position` by `speed` in one step hits a voxel, if it does. `cast` finds the first
+/// solid voxel along a ray, however far away.
+fn impact<Cast>(position: &Point3<f32>, speed: &Vector3<f32>, mut cast: Cast) -> Option<Point3<f32>> where
+  Cast: FnMut(&Ray3<f32>) -> Option<voxel::bounds::T>,
+{
+  let distance = speed.magnitude();
+  if distance == 0.0 {
+    return None
+  }
+  let direction = *speed / distance;
+  cast(&Ray3::new(*position, direction))
+    .and_then(|bounds| {
+      let (low, high) = bounds.corners();
+      entry_distance(position, &direction, &low, &high)
+    })
+    .and_then(|d| if d <= distance { Some(*position + direction * d) } else { None })
+}
+
+/// How far along a ray it enters a box, if it ever does. A ray that starts inside enters right away.
+fn entry_distance(
+  origin    : &Point3<f32>,
+  direction : &Vector3<f32>,
+  low       : &Point3<f32>,
+  high      : &Point3<f32>,
+) -> Option<f32> {
+  let mut near = 0.0;
+  let mut far = std::f32::INFINITY;
+  for i in 0 .. 3 {
+    if direction[i] == 0.0 {
+      if origin[i] < low[i] || origin[i] > high[i] {
+        return None
+      }
+      continue
+    }
+    let to_low = (low[i] - origin[i]) / direction[i];
+    let to_high = (high[i] - origin[i]) / direction[i];
+    near = f32::max(near, f32::min(to_low, to_high));
+    far = f32::min(far, f32::max(to_low, to_high));
+  }
+  if near <= far {
+    Some(near)
+  } else {
+    None
+  }
+}
+
+/// Fly every projectile `steps` steps, knock holes where they hit terrain, and tell clients where they've got to.
+pub fn update<RequestBlock>(
+  server: &server::T,
+  steps: u32,
+  request_block: &mut RequestBlock,
+) where
+  RequestBlock: FnMut(update_gaia::Message),
+{
+  let mut updates = Vec::new();
+  {
+    let mut projectiles = server.projectiles.lock().unwrap();
+    let mut landed = Vec::new();
+    for (&id, projectile) in projectiles.iter_mut() {
+      let mut flight = Flight::Flying;
+      for _ in 0 .. steps {
+        flight = projectile.step(server);
+        if flight != Flight::Flying {
+          break
+        }
+      }
+      match flight {
+        Flight::Flying => {
+          updates.push(protocol::ServerToClient::UpdateProjectile(id, bounds(&projectile.position)));
+          continue
+        },
+        Flight::Hit(point) => {
+          debug!("{:?} hit terrain at {:?}", id, point);
+          request_block(
+            update_gaia::Message::Brush(
+              edit_permissions::Actor::Player(projectile.thrower),
+              vec!(voxel::Material::Empty),
+              brush::sphere(&point, IMPACT_RADIUS, voxel::Material::Empty),
+            )
+          );
+        },
+        Flight::Expired => {},
+      }
+      landed.push(id);
+      updates.push(protocol::ServerToClient::RemoveProjectile(id));
+    }
+    for id in landed {
+      projectiles.remove(&id);
+    }
+  }
+  if updates.is_empty() {
+    return
+  }
+
+  for (_, client) in server.clients.lock().unwrap().iter_mut() {
+    for update in &updates {
+      client.send(update.clone());
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use cgmath::{Point3, Vector3};
+
+  use common::voxel;
+
+  use super::*;
+
+  #[test]
+  fn test_impact() {
+    // The ground is everything below y = 0.
+    let ground = |ray: &Ray3<f32>| {
+      if ray.direction.y < 0.0 {
+        let t = -ray.origin.y / ray.direction.y;
+        let x = ray.origin.x + ray.direction.x * t;
+        let z = ray.origin.z + ray.direction.z * t;
+        Some(voxel::bounds::new(x.floor() as i32, -1, z.floor() as i32, 0))
+      } else {
+        None
+      }
+    };
+
+    let start = Point3::new(0.5, 2.0, 0.5);
+    assert_eq!(impact(&start, &Vector3::new(0.0, -1.0, 0.0), &ground), None);
+    assert_eq!(impact(&start, &Vector3::new(0.0, 1.0, 0.0), &ground), None);
+    assert_eq!(impact(&start, &Vector3::new(0.0, 0.0, 0.0), &ground), None);
+    assert_eq!(impact(&start, &Vector3::new(0.0, -3.0, 0.0), &ground), Some(Point3::new(0.5, 0.0, 0.5)));
+
+    // Coming in at an angle, it stops where it enters the voxel.
+    let hit = impact(&start, &Vector3::new(2.0, -4.0, 0.0), &ground).unwrap();
+    assert!((hit.x - 1.5).abs() < 1e-5 && hit.y.abs() < 1e-5 && (hit.z - 0.5).abs() < 1e-5);
+  }
+
+  #[test]
+  fn test_entry_distance() {
+    let low = Point3::new(0.0, 0.0, 0.0);
+    let high = Point3::new(1.0, 1.0, 1.0);
+    let x = Vector3::new(1.0, 0.0, 0.0);
+    assert_eq!(entry_distance(&Point3::new(-2.0, 0.5, 0.5), &x, &low, &high), Some(2.0));
+    assert_eq!(entry_distance(&Point3::new(0.5, 0.5, 0.5), &x, &low, &high), Some(0.0));
+    assert_eq!(entry_distance(&Point3::new(2.0, 0.5, 0.5), &x, &low, &high), None);
+    assert_eq!(entry_distance(&Point3::new(-2.0, 2.0, 0.5), &x, &low, &high), None);
+  }
+}
@@ -0,0 +1,213 @@
+//! Per-client limits on how often expensive messages are handled, so one client can't starve gaia for everyone else.
+//! Each kind of message has a token bucket that refills at a steady rate up to some burst size. Most messages cost one
+//! token, but voxel requests cost one per voxel requested. Messages that cost more than is left in their bucket are
+//! dropped (so ones that cost more than the burst size always are), and clients that keep sending them are
+//! disconnected.
+
+/// Expensive kinds of messages, each with their own budget.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+  /// Requests for voxels.
+  Voxels,
+  /// Placing trees and rocks, and starting to dig.
+  Edits,
+  /// Chat commands.
+  Commands,
+}
+
+#[allow(missing_docs)]
+pub fn parse_kind(s: &str) -> Result<Kind, String> {
+  match s {
+    "voxels" => Ok(Kind::Voxels),
+    "edits" => Ok(Kind::Edits),
+    "commands" => Ok(Kind::Commands),
+    s => Err(format!("Invalid message kind: {:?}", s)),
+  }
+}
+
+/// How often a client can send one kind of message.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Budget {
+  /// Tokens per second, in the long run.
+  pub per_second : f32,
+  /// How many tokens can be spent at once, after a quiet spell.
+  pub burst      : f32,
+}
+
+/// The budgets every client gets.
+#[derive(Debug, Clone, Copy)]
+pub struct Budgets {
+  #[allow(missing_docs)]
+  pub voxels     : Budget,
+  #[allow(missing_docs)]
+  pub edits      : Budget,
+  #[allow(missing_docs)]
+  pub commands   : Budget,
+  /// Clients that go over budget this many times within `VIOLATION_WINDOW_NS` are disconnected.
+  pub kick_after : u32,
+}
+
+/// Violations older than this are forgotten.
+const VIOLATION_WINDOW_NS: u64 = 10_000_000_000;
+
+#[allow(missing_docs)]
+pub fn default_budgets() -> Budgets {
+  Budgets {
+    // A chunk's worth of voxels is a few hundred.
+    voxels     : Budget { per_second: 200_000.0, burst: 400_000.0 },
+    edits      : Budget { per_second: 10.0, burst: 20.0 },
+    commands   : Budget { per_second: 5.0, burst: 10.0 },
+    kick_after : 200,
+  }
+}
+
+impl Budgets {
+  #[allow(missing_docs)]
+  pub fn get(&self, kind: Kind) -> Budget {
+    match kind {
+      Kind::Voxels => self.voxels,
+      Kind::Edits => self.edits,
+      Kind::Commands => self.commands,
+    }
+  }
+
+  #[allow(missing_docs)]
+  pub fn set(&mut self, kind: Kind, budget: Budget) {
+    match kind {
+      Kind::Voxels => self.voxels = budget,
+      Kind::Edits => self.edits = budget,
+      Kind::Commands => self.commands = budget,
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+  /// `None` until the first message, when the bucket starts out full.
+  tokens     : Option<f32>,
+  updated_ns : u64,
+}
+
+impl Bucket {
+  /// Take `n` tokens, if there are that many.
+  fn take_n(&mut self, budget: &Budget, n: f32, now_ns: u64) -> bool {
+    let refill = now_ns.saturating_sub(self.updated_ns) as f32 / 1e9 * budget.per_second;
+    let tokens = f32::min(budget.burst, self.tokens.map(|t| t + refill).unwrap_or(budget.burst));
+    self.updated_ns = now_ns;
+    if tokens >= n {
+      self.tokens = Some(tokens - n);
+      true
+    } else {
+      self.tokens = Some(tokens);
+      false
+    }
+  }
+}
+
+/// What to do with a message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+  #[allow(missing_docs)]
+  Allow,
+  /// The client's over budget; ignore the message.
+  Drop,
+  /// The client's been over budget too often; disconnect it.
+  Kick,
+}
+
+/// One client's buckets.
+#[derive(Debug, Clone)]
+pub struct T {
+  voxels          : Bucket,
+  edits           : Bucket,
+  commands        : Bucket,
+  violations      : u32,
+  window_start_ns : u64,
+}
+
+#[allow(missing_docs)]
+pub fn new() -> T {
+  let bucket = Bucket { tokens: None, updated_ns: 0 };
+  T {
+    voxels          : bucket,
+    edits           : bucket,
+    commands        : bucket,
+    violations      : 0,
+    window_start_ns : 0,
+  }
+}
+
+impl T {
+  /// Charge for a message of some kind, arriving at `now_ns`.
+  pub fn charge(&mut self, budgets: &Budgets, kind: Kind, now_ns: u64) -> Outcome {
+    self.charge_n(budgets, kind, 1, now_ns)
+  }
+
+  /// Charge `n` tokens for a message of some kind (e.g. one per voxel it requests), arriving at `now_ns`.
+  pub fn charge_n(&mut self, budgets: &Budgets, kind: Kind, n: usize, now_ns: u64) -> Outcome {
+    let allowed = {
+      let bucket =
+        match kind {
+          Kind::Voxels => &mut self.voxels,
+          Kind::Edits => &mut self.edits,
+          Kind::Commands => &mut self.commands,
+        };
+      bucket.take_n(&budgets.get(kind), n as f32, now_ns)
+    };
+    if allowed {
+      return Outcome::Allow
+    }
+
+    if now_ns.saturating_sub(self.window_start_ns) >= VIOLATION_WINDOW_NS {
+      self.window_start_ns = now_ns;
+      self.violations = 0;
+    }
+    self.violations += 1;
+    if self.violations >= budgets.kick_after {
+      Outcome::Kick
+    } else {
+      Outcome::Drop
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_bursts_then_refills() {
+    let mut budgets = default_budgets();
+    budgets.set(Kind::Edits, Budget { per_second: 2.0, burst: 3.0 });
+    budgets.kick_after = 3;
+
+    let mut limits = new();
+    let start = 1_000_000_000;
+    for _ in 0 .. 3 {
+      assert_eq!(limits.charge(&budgets, Kind::Edits, start), Outcome::Allow);
+    }
+    assert_eq!(limits.charge(&budgets, Kind::Edits, start), Outcome::Drop);
+    // Other kinds have their own budgets.
+    assert_eq!(limits.charge(&budgets, Kind::Commands, start), Outcome::Allow);
+    // Half a second buys one more.
+    assert_eq!(limits.charge(&budgets, Kind::Edits, start + 500_000_000), Outcome::Allow);
+    assert_eq!(limits.charge(&budgets, Kind::Edits, start + 500_000_000), Outcome::Drop);
+    assert_eq!(limits.charge(&budgets, Kind::Edits, start + 500_000_000), Outcome::Kick);
+  }
+
+  #[test]
+  fn test_charges_per_voxel() {
+    let mut budgets = default_budgets();
+    budgets.set(Kind::Voxels, Budget { per_second: 100.0, burst: 100.0 });
+
+    let mut limits = new();
+    let start = 1_000_000_000;
+    assert_eq!(limits.charge_n(&budgets, Kind::Voxels, 60, start), Outcome::Allow);
+    // There aren't 60 left, and a failed charge doesn't use up what there is.
+    assert_eq!(limits.charge_n(&budgets, Kind::Voxels, 60, start), Outcome::Drop);
+    assert_eq!(limits.charge_n(&budgets, Kind::Voxels, 40, start), Outcome::Allow);
+    // More than the burst never fits, however long the client waits.
+    assert_eq!(limits.charge_n(&budgets, Kind::Voxels, 101, start + 10_000_000_000), Outcome::Drop);
+    assert_eq!(limits.charge_n(&budgets, Kind::Voxels, 100, start + 10_000_000_000), Outcome::Allow);
+  }
+}
@@ -0,0 +1,348 @@
+//! Replays: a log of every message clients sent, tagged with the simulation step it arrived in, that can be run again
+//! against a fresh world to check the simulation is deterministic.
+//!
+//! Each replayed step is summarized as a hash per subsystem (players, mobs, terrain). Two replays of the same log - in
+//! one process, or on two builds via a hashes file - should agree on every step; the first step where they don't, and
+//! which subsystems disagree there, is where to start looking.
+//!
+//! Replays start from freshly generated default terrain, and messages are fed in step by step, with terrain edits
+//! applied as soon as they're asked for. Systems driven by the wall clock (mining, weather, hibernation) don't replay
+//! exactly, and admin commands typed into the console aren't recorded.
+
+use bincode;
+use std;
+use std::io::{BufRead, Read, Write};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use common::protocol;
+use common::socket::ReceiveSocket;
+use common::voxel;
+
+use client_recv_thread::{apply_client_update, sender_of};
+use join_queue;
+use server;
+use terrain;
+use update_gaia;
+use update_world::update_world;
+use webhooks;
+
+/// The subsystems that are hashed separately, in the order they're written to hashes files.
+pub const SUBSYSTEMS: [&str; 3] = ["players", "mobs", "terrain"];
+
+/// Appends client messages to a replay log as they arrive.
+pub struct Recorder {
+  file : std::io::BufWriter<std::fs::File>,
+}
+
+/// Start a new replay log at `path`, replacing whatever's there.
+pub fn record_to(path: &std::path::Path) -> Result<Recorder, String> {
+  let file = try!(std::fs::File::create(path).map_err(|err| format!("Error creating {:?}: {:?}", path, err)));
+  Ok(Recorder { file: std::io::BufWriter::new(file) })
+}
+
+impl Recorder {
+  /// Log `message` as having arrived after `step` steps.
+  pub fn push(&mut self, step: u64, message: &protocol::ClientToServer) {
+    if let Err(err) = bincode::serialize_into(&mut self.file, &(step, message), bincode::Infinite) {
+      warn!("Error writing to the replay log: {:?}", err);
+    }
+  }
+}
+
+/// Log a message from a client, if `server` is recording.
+pub fn record(server: &server::T, message: &protocol::ClientToServer) {
+  let step = server.simulation.lock().unwrap().steps();
+  if let Some(ref mut recorder) = *server.replay_log.lock().unwrap() {
+    recorder.push(step, message);
+  }
+}
+
+/// Read a replay log, as (step, message) pairs in the order they arrived.
+pub fn read(path: &std::path::Path) -> Result<Vec<(u64, protocol::ClientToServer)>, String> {
+  let mut bytes = Vec::new();
+  try!(
+    std::fs::File::open(path).and_then(|mut file| file.read_to_end(&mut bytes))
+    .map_err(|err| format!("Error reading {:?}: {:?}", path, err))
+  );
+  let mut cursor = std::io::Cursor::new(bytes);
+  let mut log = Vec::new();
+  while (cursor.position() as usize) < cursor.get_ref().len() {
+    let entry =
+      try!(
+        bincode::deserialize_from(&mut cursor, bincode::Infinite)
+        .map_err(|err| format!("Error reading {:?} entry {}: {:?}", path, log.len(), err))
+      );
+    log.push(entry);
+  }
+  Ok(log)
+}
+
+/// The state of each subsystem after one step.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(missing_docs)]
+pub struct StepHashes {
+  pub step    : u64,
+  pub players : u64,
+  pub mobs    : u64,
+  pub terrain : u64,
+}
+
+impl StepHashes {
+  /// The hashes, in the same order as `SUBSYSTEMS`.
+  fn subsystems(&self) -> [u64; 3] {
+    [self.players, self.mobs, self.terrain]
+  }
+}
+
+/// FNV-1a, so hashes are the same from build to build.
+pub fn fnv(hash: u64, bytes: &[u8]) -> u64 {
+  bytes.iter().fold(hash, |hash, &b| (hash ^ b as u64).wrapping_mul(0x100_0000_01b3))
+}
+
+/// What to start an `fnv` hash from.
+pub const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+
+fn fnv_u32(hash: u64, x: u32) -> u64 {
+  fnv(hash, &[x as u8, (x >> 8) as u8, (x >> 16) as u8, (x >> 24) as u8])
+}
+
+fn fnv_floats(hash: u64, xs: &[f32]) -> u64 {
+  xs.iter().fold(hash, |hash, x| fnv_u32(hash, x.to_bits()))
+}
+
+fn hash_players(server: &server::T) -> u64 {
+  let players = server.players.lock().unwrap();
+  let mut players: Vec<_> = players.values().collect();
+  players.sort_by_key(|player| player.entity_id.to_u32());
+  players.iter().fold(FNV_OFFSET, |hash, player| {
+    let hash = fnv_u32(hash, player.entity_id.to_u32());
+    let hash = fnv_u32(hash, player.health);
+    fnv_floats(hash, &[
+      player.position.x, player.position.y, player.position.z,
+      player.speed.x, player.speed.y, player.speed.z,
+      player.lateral_rotation, player.vertical_rotation,
+    ])
+  })
+}
+
+fn hash_mobs(server: &server::T) -> u64 {
+  let mobs = server.mobs.lock().unwrap();
+  let mut mobs: Vec<_> = mobs.values().collect();
+  mobs.sort_by_key(|mob| mob.entity_id.to_u32());
+  mobs.iter().fold(FNV_OFFSET, |hash, mob| {
+    let hash = fnv_u32(hash, mob.entity_id.to_u32());
+    fnv_floats(hash, &[
+      mob.position.x, mob.position.y, mob.position.z,
+      mob.speed.x, mob.speed.y, mob.speed.z,
+    ])
+  })
+}
+
+/// Generated terrain only depends on the seed, so only the edited blocks are hashed.
+fn hash_terrain(server: &server::T) -> u64 {
+  let terrain = &server.terrain_loader.terrain;
+  let voxels = terrain.voxels.lock().unwrap();
+  let edits = terrain.edits.lock().unwrap();
+  let mut blocks: Vec<_> = edits.blocks.iter().collect();
+  blocks.sort_by_key(|block| (block.lg_size, block.x, block.y, block.z));
+  blocks.iter().fold(FNV_OFFSET, |hash, block| {
+    terrain::edits::voxels_in(block).iter().fold(hash, |hash, bounds| {
+      let voxel: Option<voxel::T> = voxels.get(bounds).cloned();
+      fnv(hash, &bincode::serialize(&(bounds, voxel), bincode::Infinite).unwrap())
+    })
+  })
+}
+
+fn hash_step(server: &server::T, step: u64) -> StepHashes {
+  StepHashes {
+    step    : step,
+    players : hash_players(server),
+    mobs    : hash_mobs(server),
+    terrain : hash_terrain(server),
+  }
+}
+
+/// Replay `log` against a fresh world, and hash the state after every step. `run` keeps the sockets of different
+/// replays in the same process apart.
+pub fn replay(log: &[(u64, protocol::ClientToServer)], run: u32) -> Result<Vec<StepHashes>, String> {
+  let terrain = try!(terrain::T::of_config(&terrain::world::default()));
+  let server = server::of_terrain(terrain, server::default_distances());
+  // Replays shouldn't tell anyone about joins and edits that already happened.
+  *server.webhooks.lock().unwrap() = webhooks::new();
+
+  // Clients' sockets, drained by a thread each, so sends never block.
+  let done = Arc::new(AtomicBool::new(false));
+  let mut sinks = Vec::new();
+
+  let mut gaia = Vec::new();
+  let mut hashes = Vec::new();
+  let last_step = log.last().map(|&(step, _)| step).unwrap_or(0);
+  let mut entries = log.iter().peekable();
+  for step in 0 .. last_step + 1 {
+    while entries.peek().map(|&&(s, _)| s == step).unwrap_or(false) {
+      let mut message = entries.next().unwrap().1.clone();
+      if let protocol::ClientToServer::Init(_, ref mut url, _, _, _) = message {
+        *url = format!("inproc://replay/{}/{}", run, sinks.len());
+        let mut socket = ReceiveSocket::new(url.as_str(), Some(Duration::from_millis(100)));
+        let done = done.clone();
+        sinks.push(std::thread::spawn(move || {
+          while !done.load(Ordering::SeqCst) {
+            socket.read();
+          }
+        }));
+      }
+      if let protocol::ClientToServer::Request(ref mut secret, ref request) = message {
+        // The log has the secrets clients were given when it was recorded; this run's clients were given new ones.
+        if let Ok(client_id) = sender_of(&server, request) {
+          if let Some(client) = server.clients.lock().unwrap().get(&client_id) {
+            *secret = client.secret;
+          }
+        }
+      }
+      if let Err(err) = apply_client_update(&server, &mut |up| gaia.push(up), message) {
+        warn!("Replayed message failed at step {}: {}", step, err);
+      }
+      join_queue::drop_failed(&server);
+      for up in gaia.drain(..) {
+        update_gaia::update_gaia(&server, up);
+      }
+    }
+
+    update_world(&server, 1, &mut |up| gaia.push(up));
+    join_queue::drop_failed(&server);
+    for up in gaia.drain(..) {
+      update_gaia::update_gaia(&server, up);
+    }
+    hashes.push(hash_step(&server, step + 1));
+  }
+
+  server.clients.lock().unwrap().clear();
+  done.store(true, Ordering::SeqCst);
+  for sink in sinks {
+    sink.join().unwrap();
+  }
+  Ok(hashes)
+}
+
+/// The first step where `a` and `b` disagree, and the subsystems that disagree there. If one run is a prefix of the
+/// other, the first step only one of them has is reported with no subsystems.
+pub fn first_divergence(a: &[StepHashes], b: &[StepHashes]) -> Option<(u64, Vec<&'static str>)> {
+  for (a, b) in a.iter().zip(b.iter()) {
+    let differ: Vec<_> =
+      SUBSYSTEMS.iter().zip(a.subsystems().iter().zip(b.subsystems().iter()))
+      .filter(|&(_, (a, b))| a != b)
+      .map(|(&name, _)| name)
+      .collect();
+    if a.step != b.step || !differ.is_empty() {
+      return Some((a.step, differ))
+    }
+  }
+  if a.len() == b.len() {
+    return None
+  }
+  let shorter = if a.len() < b.len() { a } else { b };
+  Some((shorter.last().map(|h| h.step + 1).unwrap_or(1), Vec::new()))
+}
+
+/// Write hashes as text, one `<step> <players> <mobs> <terrain>` line per step, so builds can be compared.
+fn write_hashes<W: Write>(mut w: W, hashes: &[StepHashes]) -> std::io::Result<()> {
+  for h in hashes {
+    try!(writeln!(w, "{} {:016x} {:016x} {:016x}", h.step, h.players, h.mobs, h.terrain));
+  }
+  Ok(())
+}
+
+fn read_hashes<R: BufRead>(r: R) -> Result<Vec<StepHashes>, String> {
+  let mut hashes = Vec::new();
+  for (i, line) in r.lines().enumerate() {
+    let line = try!(line.map_err(|err| format!("{:?}", err)));
+    let words: Vec<_> = line.split_whitespace().collect();
+    let parse = |word: &str| {
+      u64::from_str_radix(word, 16).map_err(|_| format!("line {}: bad hash {:?}", i + 1, word))
+    };
+    if words.len() != 1 + SUBSYSTEMS.len() {
+      return Err(format!("line {}: expected a step and {} hashes", i + 1, SUBSYSTEMS.len()))
+    }
+    hashes.push(StepHashes {
+      step    : try!(words[0].parse().map_err(|_| format!("line {}: bad step {:?}", i + 1, words[0]))),
+      players : try!(parse(words[1])),
+      mobs    : try!(parse(words[2])),
+      terrain : try!(parse(words[3])),
+    });
+  }
+  Ok(hashes)
+}
+
+/// Replay the log at `log_path` and check it comes out the same every time. With a `hashes_path`, the replay is
+/// compared against the hashes there (from another build, say), or they're written there if it doesn't exist yet.
+/// Without one, the log is replayed twice.
+pub fn check(log_path: &std::path::Path, hashes_path: Option<&std::path::Path>) -> Result<(), String> {
+  let log = try!(read(log_path));
+  println!("Replaying {} messages from {:?}", log.len(), log_path);
+  let first = try!(replay(&log, 0));
+
+  let second =
+    match hashes_path {
+      None => try!(replay(&log, 1)),
+      Some(path) if !path.exists() => {
+        try!(
+          std::fs::File::create(path)
+          .and_then(|file| write_hashes(std::io::BufWriter::new(file), &first))
+          .map_err(|err| format!("Error writing {:?}: {:?}", path, err))
+        );
+        println!("Recorded hashes for {} steps in {:?}", first.len(), path);
+        return Ok(())
+      },
+      Some(path) => {
+        let file = try!(std::fs::File::open(path).map_err(|err| format!("Error opening {:?}: {:?}", path, err)));
+        try!(read_hashes(std::io::BufReader::new(file)).map_err(|err| format!("{:?} {}", path, err)))
+      },
+    };
+
+  match first_divergence(&first, &second) {
+    None => {
+      println!("{} steps replayed identically", first.len());
+      Ok(())
+    },
+    Some((step, subsystems)) => {
+      if subsystems.is_empty() {
+        Err(format!("Runs have different lengths; step {} is missing from one", step))
+      } else {
+        Err(format!("Diverged at step {}: {}", step, subsystems.join(", ")))
+      }
+    },
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  fn hashes(step: u64, players: u64, mobs: u64, terrain: u64) -> StepHashes {
+    StepHashes { step: step, players: players, mobs: mobs, terrain: terrain }
+  }
+
+  #[test]
+  fn test_first_divergence() {
+    let a = vec!(hashes(1, 1, 2, 3), hashes(2, 4, 5, 6), hashes(3, 7, 8, 9));
+    assert_eq!(first_divergence(&a, &a), None);
+
+    let mut b = a.clone();
+    b[1].mobs = 0;
+    b[2].terrain = 0;
+    assert_eq!(first_divergence(&a, &b), Some((2, vec!("mobs"))));
+
+    assert_eq!(first_divergence(&a, &a[.. 2]), Some((3, vec!())));
+  }
+
+  #[test]
+  fn test_hashes_file() {
+    let a = vec!(hashes(1, 1, 2, 3), hashes(2, 0xffff_ffff_ffff_ffff, 0, 6));
+    let mut file = Vec::new();
+    write_hashes(&mut file, &a).unwrap();
+    assert_eq!(read_hashes(&file[..]).unwrap(), a);
+    assert!(read_hashes(&b"1 2 3\n"[..]).is_err());
+  }
+}
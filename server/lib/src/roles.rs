@@ -0,0 +1,139 @@
+//! What each account is trusted to do. Roles are kept in a file with one `<account> <role>` per line, which is
+//! rewritten whenever the server console changes somebody's role. Accounts that aren't listed get `DEFAULT`.
+
+use std;
+use std::io::Write;
+
+use common::fnv_map;
+use common::line_file;
+use common::protocol::EntityRef;
+
+use entity;
+use server;
+
+/// How much a player is trusted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Role {
+  /// Can look around, but can't edit terrain or run commands. Also called a spectator.
+  Guest,
+  /// Can edit terrain (outside other players' claims) and run commands that only affect themselves.
+  Builder,
+  /// Can run any command a player can send, including ones that affect other players.
+  Admin,
+}
+
+/// The role of accounts nobody has assigned one to.
+pub const DEFAULT: Role = Role::Builder;
+
+#[allow(missing_docs)]
+pub fn parse(s: &str) -> Result<Role, String> {
+  match s {
+    "guest" | "spectator" => Ok(Role::Guest),
+    "builder" => Ok(Role::Builder),
+    "admin" => Ok(Role::Admin),
+    s => Err(format!("Invalid role: {:?}", s)),
+  }
+}
+
+#[allow(missing_docs)]
+pub fn name(role: Role) -> &'static str {
+  match role {
+    Role::Guest => "guest",
+    Role::Builder => "builder",
+    Role::Admin => "admin",
+  }
+}
+
+#[allow(missing_docs)]
+pub struct T {
+  path  : std::path::PathBuf,
+  roles : fnv_map::T<String, Role>,
+}
+
+impl T {
+  #[allow(missing_docs)]
+  pub fn get(&self, account: &str) -> Role {
+    self.roles.get(account).cloned().unwrap_or(DEFAULT)
+  }
+
+  /// Change an account's role, and write the roles file back out.
+  pub fn set(&mut self, account: &str, role: Role) -> std::io::Result<()> {
+    self.roles.insert(account.to_owned(), role);
+    self.save()
+  }
+
+  fn save(&self) -> std::io::Result<()> {
+    let mut accounts: Vec<(&String, &Role)> = self.roles.iter().collect();
+    accounts.sort();
+    let mut file = try!(std::fs::File::create(&self.path));
+    try!(writeln!(file, "# Player roles. This file is rewritten when roles are changed from the server console."));
+    for (account, &role) in accounts {
+      try!(writeln!(file, "{} {}", account, name(role)));
+    }
+    Ok(())
+  }
+}
+
+fn parse_line(line: &str) -> Result<(String, Role), String> {
+  let mut words = line.split_whitespace();
+  let account = words.next().unwrap();
+  let role =
+    match words.next() {
+      None => return Err(format!("Missing role for {:?}", account)),
+      Some(role) => try!(parse(role)),
+    };
+  match words.next() {
+    None => Ok((account.to_owned(), role)),
+    Some(word) => Err(format!("Unexpected value for {:?}: {:?}", account, word)),
+  }
+}
+
+/// Load roles from a `line_file`. Accounts it doesn't list, or all of them if it isn't there, get `DEFAULT`.
+pub fn load(path: std::path::PathBuf) -> T {
+  let mut roles = fnv_map::new();
+  line_file::load(&path, "roles", |line| {
+    let (account, role) = try!(parse_line(line));
+    roles.insert(account, role);
+    Ok(())
+  });
+
+  T {
+    path  : path,
+    roles : roles,
+  }
+}
+
+/// The role of whoever's controlling a player: its client's token's role if it has one, or else its account's.
+/// Players whose clients are gone are treated as guests.
+pub fn of_player(server: &server::T, player_id: entity::id::Player) -> Role {
+  let client_id =
+    match server.components.controller(EntityRef::Player(player_id)) {
+      None => return Role::Guest,
+      Some(client_id) => client_id,
+    };
+  let account =
+    match server.clients.lock().unwrap().get(&client_id) {
+      None => return Role::Guest,
+      Some(client) => {
+        if let Some(role) = client.token_role {
+          return role
+        }
+        client.account.clone()
+      },
+    };
+  server.roles.lock().unwrap().get(&account)
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_parse_line() {
+    assert_eq!(parse_line("alice admin"), Ok((String::from("alice"), Role::Admin)));
+    assert_eq!(parse_line("carol spectator"), Ok((String::from("carol"), Role::Guest)));
+    assert!(parse_line("bob").is_err());
+    assert!(parse_line("bob wizard").is_err());
+    assert!(parse_line("bob guest please").is_err());
+  }
+}
@@ -1,4 +1,5 @@
 use std;
+use std::collections::VecDeque;
 use std::convert::AsRef;
 use std::sync::Mutex;
 use bincode;
@@ -9,13 +10,23 @@ use voxel_data;
 
 use common;
 use common::closure_series;
+use common::protocol;
 use common::socket::ReceiveSocket;
 
-use client_recv_thread::apply_client_update;
+use autosave;
+use client_recv_thread::{apply_client_update, reject};
+use command;
+use edit_permissions;
+use gaia_queue;
+use join_queue;
+use replay;
 use server;
+use shutdown;
+use terrain_file;
 use update_gaia;
-use update_gaia::update_gaia;
+use update_gaia::{update_gaia, update_gaia_slice};
 use update_world::update_world;
+use webhooks;
 
 mod terrain {
   pub use ::terrain::*;
@@ -23,26 +34,68 @@ mod terrain {
 
 const SAVE_TERRAIN: bool = false;
 
-#[allow(missing_docs)]
-pub fn run(listen_url: &str, quit_signal: &Mutex<bool>) {
-  let gaia_updates = Mutex::new(std::collections::VecDeque::new());
+/// Run the server until `quit_signal` is set or a scheduled shutdown comes due, applying admin commands as they're
+/// pushed onto `commands`. On the way out, clients are told the server is going away, outstanding terrain edits are
+/// applied, and the terrain is saved. If there's a `replay_path`, every message from clients is logged there (see
+/// `replay`). If there's an `autosave` config, snapshots of the terrain are saved as it says (see `autosave`).
+pub fn run(
+  listen_url: &str,
+  world: &terrain::world::WorldConfig,
+  distances: server::Distances,
+  replay_path: Option<&std::path::Path>,
+  autosave: Option<autosave::Config>,
+  commands: &Mutex<VecDeque<command::T>>,
+  quit_signal: &Mutex<bool>,
+) {
+  let terrain =
+    match terrain::T::of_config(world) {
+      Ok(terrain) => terrain,
+      Err(err) => {
+        println!("{}", err);
+        return
+      },
+    };
+
+  let gaia_updates = Mutex::new(gaia_queue::new());
 
   let listen_socket = ReceiveSocket::new(listen_url.as_ref(), None);
   let listen_socket = Mutex::new(listen_socket);
 
-  let server = server::new();
+  let server = server::of_terrain(terrain, distances);
   let server = &server;
 
-  let terrain_path = std::path::Path::new("default.terrain");
+  if let Some(path) = replay_path {
+    match replay::record_to(path) {
+      Ok(recorder) => {
+        println!("Recording a replay to {}", path.display());
+        *server.replay_log.lock().unwrap() = Some(recorder);
+      },
+      Err(err) => println!("{}", err),
+    }
+  }
+
+  let terrain_path = std::path::Path::new(terrain_file::DEFAULT_PATH);
 
   println!("Loading terrain from {}", terrain_path.to_str().unwrap());
   load_terrain(&server.terrain_loader.terrain, &terrain_path);
 
+  // The heightmap summaries are much smaller than the voxels, so they're kept even when the terrain isn't saved.
+  let heightmap_path = std::path::Path::new("default.terrain.heightmap");
+  server.terrain_loader.terrain.load_heightmap(&heightmap_path);
+
+  let autosave =
+    autosave.map(|config| {
+      Mutex::new(autosave::new(config, std::path::PathBuf::from(autosave::DIRECTORY), time::precise_time_ns()))
+    });
+  let autosave = &autosave;
+
+  webhooks::fire(server, webhooks::Event::Start, &[]);
+
   let mut threads = Vec::new();
 
   unsafe {
     threads.push(thread_scoped::scoped(|| {
-      while !*quit_signal.lock().unwrap() {
+      while !*quit_signal.lock().unwrap() && !shutdown::is_due(server, time::precise_time_ns()) {
         info!("Outstanding gaia updates: {}", gaia_updates.lock().unwrap().len());
         std::thread::sleep(std::time::Duration::from_secs(1));
       }
@@ -57,10 +110,20 @@ pub fn run(listen_url: &str, quit_signal: &Mutex<bool>) {
     let listen_socket = &listen_socket;
     threads.push(thread_scoped::scoped(move || {
       closure_series::new(vec!(
-        quit_upon(&quit_signal),
+        quit_upon(&quit_signal, server),
         consider_world_update(&server, |up| { gaia_updates.lock().unwrap().push_back(up) }),
         network_listen(&listen_socket, server, |up| { gaia_updates.lock().unwrap().push_back(up) }),
-        consider_gaia_update(&server, || { gaia_updates.lock().unwrap().pop_front() } ),
+        consider_gaia_update(
+          &server,
+          || { gaia_updates.lock().unwrap().pop_front() },
+          |up| { gaia_updates.lock().unwrap().resume(up) },
+        ),
+        consider_command(
+          &server,
+          || { commands.lock().unwrap().pop_front() },
+          |up| { gaia_updates.lock().unwrap().push_back(up) },
+        ),
+        consider_autosave(&server, autosave, || { gaia_updates.lock().unwrap().brush_in_progress() }),
       ))
       .until_quit();
 
@@ -74,7 +137,7 @@ pub fn run(listen_url: &str, quit_signal: &Mutex<bool>) {
     let listen_socket = &listen_socket;
     threads.push(thread_scoped::scoped(move || {
       closure_series::new(vec!(
-        quit_upon(&quit_signal),
+        quit_upon(&quit_signal, server),
         consider_world_update(&server, |up| { gaia_updates.lock().unwrap().push_back(up) }),
         network_listen(&listen_socket, server, |up| { gaia_updates.lock().unwrap().push_back(up) }),
       ))
@@ -89,6 +152,19 @@ pub fn run(listen_url: &str, quit_signal: &Mutex<bool>) {
     stopwatch.print();
   }
 
+  if !shutdown::is_scheduled(server) {
+    shutdown::begin(server, String::from("Server stopped"), 0);
+  }
+
+  // Apply any terrain edits that were still queued, so they get saved.
+  stopwatch::time("drain_gaia_updates", || {
+    let mut gaia_updates = gaia_updates.lock().unwrap();
+    info!("Applying {} outstanding gaia updates", gaia_updates.len());
+    while let Some(up) = gaia_updates.pop_front() {
+      update_gaia(server, up);
+    }
+  });
+
   info!("Voxel takes {} bytes", std::mem::size_of::<common::voxel::T>());
 
   println!(
@@ -103,12 +179,30 @@ pub fn run(listen_url: &str, quit_signal: &Mutex<bool>) {
     });
   }
 
+  if let Some(ref autosave) = *autosave {
+    autosave.lock().unwrap().finish();
+  }
+
+  if let Err(err) = server.terrain_loader.terrain.save_heightmap(&heightmap_path) {
+    warn!("Error saving heightmap cache: {:?}", err);
+  }
+
+  // Close the connections to clients.
+  server.clients.lock().unwrap().clear();
+
+  // Finish off the replay log.
+  *server.replay_log.lock().unwrap() = None;
+
+  webhooks::fire(server, webhooks::Event::Stop, &[]);
+  // Wait for the webhooks to go out, since nothing's going to be left to send them.
+  server.webhooks.lock().unwrap().finish();
+
   stopwatch::clone().print();
 }
 
-fn quit_upon(signal: &Mutex<bool>) -> closure_series::Closure {
+fn quit_upon<'a>(signal: &'a Mutex<bool>, server: &'a server::T) -> closure_series::Closure<'a> {
   Box::new(move || {
-    if *signal.lock().unwrap() {
+    if *signal.lock().unwrap() || shutdown::is_due(server, time::precise_time_ns()) {
       closure_series::Quit
     } else {
       closure_series::Continue
@@ -123,11 +217,14 @@ fn consider_world_update<'a, ToGaia>(
   ToGaia: FnMut(update_gaia::Message) + 'a,
 {
   Box::new(move || {
-    if server.update_timer.lock().unwrap().update(time::precise_time_ns()) > 0 {
+    let steps = server.simulation.lock().unwrap().advance(time::precise_time_ns());
+    if steps > 0 {
       update_world(
         server,
+        steps,
         &mut to_gaia,
       );
+      join_queue::drop_failed(server);
       closure_series::Restart
     } else {
       closure_series::Continue
@@ -147,24 +244,45 @@ fn network_listen<'a, ToGaia>(
       common::socket::Result::Empty => closure_series::Continue,
       common::socket::Result::Terminating => closure_series::Quit,
       common::socket::Result::Success(up) => {
-        let up = bincode::deserialize(up.as_ref()).unwrap();
-        apply_client_update(server, &mut to_gaia, up);
+        match bincode::deserialize(up.as_ref()) {
+          Ok(up) => {
+            replay::record(server, &up);
+            if let Err(err) = apply_client_update(server, &mut to_gaia, up) {
+              warn!("Dropping client message: {}", err);
+            }
+            join_queue::drop_failed(server);
+          },
+          Err(err) => {
+            // This might be a client from another version of the protocol trying to connect.
+            match bincode::deserialize(up.as_ref()) {
+              Ok(protocol::Handshake::Init(version, client_url)) if version != protocol::VERSION => {
+                let err = protocol::ConnectionError::VersionMismatch { server: protocol::VERSION, client: version };
+                reject(&client_url, err)
+              },
+              _ => warn!("Ignoring a message that doesn't decode: {:?}", err),
+            }
+          },
+        }
         closure_series::Restart
       },
     }
   })
 }
 
-fn consider_gaia_update<'a, Get>(
+fn consider_gaia_update<'a, Get, Resume>(
   server: &'a server::T,
   mut get_update: Get,
+  mut resume: Resume,
 ) -> closure_series::Closure<'a> where
   Get: FnMut() -> Option<update_gaia::Message> + 'a,
+  Resume: FnMut(update_gaia::Message) + 'a,
 {
   Box::new(move || {
     match get_update() {
       Some(up) => {
-        update_gaia(server, up);
+        if let Some(rest) = update_gaia_slice(server, up) {
+          resume(rest);
+        }
         closure_series::Restart
       },
       None => closure_series::Continue,
@@ -172,38 +290,62 @@ fn consider_gaia_update<'a, Get>(
   })
 }
 
-fn load_terrain(terrain: &terrain::T, path: &std::path::Path) {
-  let mut file =
-    match std::fs::File::open(path) {
-      Err(err) => {
-        warn!("Error opening terrain file: {:?}", err);
-        return
-      },
-      Ok(file) => file,
-    };
-  let loaded =
-    bincode::deserialize_from(
-      &mut file,
-      bincode::Infinite,
-    );
-  let loaded =
-    match loaded {
-      Ok(loaded) => loaded,
-      Err(err) => {
-        warn!("Error loading terrain: {:?}", err);
-        return
+fn consider_command<'a, Get, ToGaia>(
+  server: &'a server::T,
+  mut get_command: Get,
+  mut to_gaia: ToGaia,
+) -> closure_series::Closure<'a> where
+  Get: FnMut() -> Option<command::T> + 'a,
+  ToGaia: FnMut(update_gaia::Message) + 'a,
+{
+  Box::new(move || {
+    match get_command() {
+      Some(command) => {
+        if let Err(err) = command::apply(server, &mut to_gaia, edit_permissions::Actor::Console, command) {
+          println!("{}", err);
+        }
+        closure_series::Restart
       },
-    };
-  *terrain.voxels.lock().unwrap() = loaded;
+      None => closure_series::Continue,
+    }
+  })
+}
+
+/// Snapshots are taken here, on the thread that applies gaia updates, so no brush slice is being applied meanwhile.
+fn consider_autosave<'a, BrushInProgress>(
+  server: &'a server::T,
+  autosave: &'a Option<Mutex<autosave::T>>,
+  mut brush_in_progress: BrushInProgress,
+) -> closure_series::Closure<'a> where
+  BrushInProgress: FnMut() -> bool + 'a,
+{
+  Box::new(move || {
+    let autosave =
+      match *autosave {
+        None => return closure_series::Continue,
+        Some(ref autosave) => autosave,
+      };
+    let now = time::precise_time_ns();
+    let mut autosave = autosave.lock().unwrap();
+    // Wait for a brush that's partway through to finish, so it's either all in the snapshot or not in it at all.
+    if !autosave.is_due(now) || brush_in_progress() {
+      return closure_series::Continue
+    }
+    stopwatch::time("autosave", || {
+      autosave.save(&server.terrain_loader.terrain, now);
+    });
+    closure_series::Restart
+  })
+}
+
+fn load_terrain(terrain: &terrain::T, path: &std::path::Path) {
+  if let Err(err) = terrain_file::load(terrain, path) {
+    warn!("Error loading terrain: {}", err);
+  }
 }
 
 fn save_terrain(terrain: &terrain::T, path: &std::path::Path) {
-  let mut file = std::fs::File::create(path).unwrap();
-  bincode::serialize_into(
-    &mut file,
-    &*terrain.voxels.lock().unwrap(),
-    bincode::Infinite,
-  ).unwrap();
+  terrain_file::save(terrain, path).unwrap();
 }
 
 fn tree_ram_usage(tree: &common::voxel::tree::T) -> usize {
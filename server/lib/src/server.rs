@@ -3,43 +3,118 @@
 use cgmath::{Point3};
 use collision::{Aabb3};
 use rand;
+use std;
 use std::sync::Mutex;
 use time;
 
 use common::protocol;
 use common::fnv_map;
+use common::fnv_set;
 use common::id_allocator;
-use common::interval_timer::IntervalTimer;
 use common::socket::SendSocket;
+use common::voxel;
 
+use afk;
+use attachment;
+use auth;
+use beacon;
+use components;
+use edit_permissions;
 use entity;
+use error;
+use fixed_step;
+use hibernation;
+use hooks;
 use init_mobs::init_mobs;
+use join_queue;
 use lod;
+use mining;
 use mob;
+use navgrid;
 use physics;
 use player;
+use portal;
+use projectile;
+use rate_limit;
+use replay;
+use roles;
 use sun::Sun;
+use terrain;
 use terrain_loader;
+use weather;
+use webhooks;
 
-const UPDATES_PER_SECOND: u64 = 30;
 const SUN_TICK_NS: u64 = 1600000;
 
+/// The default `Distances::view`. This should be comfortably beyond the furthest clients will load terrain.
+pub const DEFAULT_VIEW_DISTANCE: f32 = 1024.0;
+/// The default `Distances::simulation`.
+pub const DEFAULT_SIMULATION_DISTANCE: f32 = 128.0;
+
+/// How far around players things happen, in world units along any axis.
+#[derive(Debug, Clone, Copy)]
+pub struct Distances {
+  /// Clients are told to drop blocks further than this from their player.
+  pub view       : f32,
+  /// Mobs only move, and keep terrain loaded around them, within this distance of some player.
+  /// Terrain can be streamed to clients well beyond this.
+  pub simulation : f32,
+}
+
+#[allow(missing_docs)]
+pub fn default_distances() -> Distances {
+  Distances {
+    view       : DEFAULT_VIEW_DISTANCE,
+    simulation : DEFAULT_SIMULATION_DISTANCE,
+  }
+}
+
 /// Client handle
 pub struct Client {
   /// Socket to the client
   pub socket: SendSocket,
+  /// The blocks this client has been sent, and hasn't been told to unload.
+  pub blocks: fnv_set::T<voxel::bounds::T>,
+  /// Where the client's player was when we last checked for blocks to unload.
+  pub last_unload_position: Option<Point3<f32>>,
+  /// Limits on the terrain this client gets.
+  pub terrain_policy: protocol::TerrainPolicy,
+  /// The account the client's players belong to, which decides their `roles::Role`.
+  pub account: String,
+  /// The role the client's token gives it, if it connected with one. This outranks the account's role.
+  pub token_role: Option<roles::Role>,
+  /// How many expensive messages the client has sent lately.
+  pub rate_limits: rate_limit::T,
+  /// When (in local ns) the client last sent any input.
+  pub last_input_ns: u64,
+  /// Whether the client's gone quiet for long enough to be away from the keyboard.
+  pub afk: bool,
+  /// When (in local ns) to next send the client the physics bounds around its player, if it's watching them.
+  pub watching_physics: Option<u64>,
+  /// What went wrong sending to the client, if anything has. Failed clients are dropped by
+  /// `join_queue::drop_failed`.
+  pub failure: Option<error::T>,
+  /// What the client has to send its requests with. It's told this along with its id.
+  pub secret: protocol::Secret,
 }
 
 impl Client {
-  #[allow(missing_docs)]
+  /// Send a message to the client. If that fails, the client's marked as failed, and nothing more is sent to it.
   pub fn send(&mut self, msg: protocol::ServerToClient) {
+    if self.failure.is_some() {
+      return
+    }
+    if let Err(err) = self.try_send(&msg) {
+      warn!("{}", err);
+      self.failure = Some(err);
+    }
+  }
+
+  fn try_send(&mut self, msg: &protocol::ServerToClient) -> error::Result<()> {
     use bincode;
     use bincode::serialize;
-    let msg = serialize(&msg, bincode::Infinite).unwrap();
-    match self.socket.write(msg.as_ref()) {
-      Ok(()) => {},
-      Err(err) => warn!("Error sending to client: {:?}", err),
-    }
+    let msg = try!(serialize(msg, bincode::Infinite).map_err(|err| error::T::Encode(format!("{:?}", err))));
+    self.socket.write(msg.as_ref()).map_err(|err| error::T::Send(format!("{:?}", err)))
   }
 }
 
@@ -48,6 +123,8 @@ impl Client {
 pub struct T {
   pub players           : Mutex<fnv_map::T<entity::id::Player, player::T>>,
   pub mobs              : Mutex<fnv_map::T<entity::id::Mob, mob::Mob>>,
+  /// Copies of players' and mobs' state that can be read without locking them.
+  pub components        : components::T,
 
   pub player_allocator  : Mutex<id_allocator::T<entity::id::Player>>,
   pub mob_allocator     : Mutex<id_allocator::T<entity::id::Mob>>,
@@ -57,18 +134,64 @@ pub struct T {
   pub client_allocator  : Mutex<id_allocator::T<protocol::ClientId>>,
 
   pub physics           : Mutex<physics::T>,
+  /// Consulted before every terrain edit.
+  pub edit_permissions  : Mutex<edit_permissions::T>,
+  /// What the operator's script does when things happen.
+  pub hooks             : Mutex<hooks::T>,
+  pub webhooks          : Mutex<webhooks::T>,
+  #[allow(missing_docs)]
+  pub roles             : Mutex<roles::T>,
+  /// Which tokens clients can connect with.
+  pub auth              : auth::T,
   pub terrain_loader    : terrain_loader::T,
+  /// Where mobs can walk. This is always locked before the terrain's voxels.
+  pub navgrid           : Mutex<navgrid::T>,
   pub rng               : Mutex<rand::StdRng>,
 
   pub clients           : Mutex<fnv_map::T<protocol::ClientId, Client>>,
+  /// The most clients that can play at once. Clients beyond this wait in `join_queue`.
+  pub max_players       : Mutex<usize>,
+  /// How many expensive messages each client can send.
+  pub rate_budgets      : Mutex<rate_limit::Budgets>,
+  pub join_queue        : Mutex<join_queue::T>,
+  /// How clients that are away from the keyboard are handled.
+  pub afk               : Mutex<afk::T>,
+  /// Players that are currently digging.
+  pub mining            : Mutex<fnv_map::T<entity::id::Player, mining::T>>,
+  /// Things players have thrown that are still in the air. They share ids with mobs.
+  pub projectiles       : Mutex<fnv_map::T<entity::id::Mob, projectile::T>>,
+  pub beacons           : Mutex<beacon::T>,
+  /// Teleporter pads, and who's standing on them.
+  pub portals           : Mutex<portal::T>,
+  #[allow(missing_docs)]
+  pub attachments       : Mutex<attachment::T>,
+  pub distances         : Distances,
+  /// Regions whose mobs have been put away until a player comes near.
+  pub hibernation       : Mutex<hibernation::T>,
 
   pub sun               : Mutex<Sun>,
-  pub update_timer      : Mutex<IntervalTimer>,
+  pub weather           : Mutex<weather::T>,
+  /// Time that hasn't been simulated yet.
+  pub simulation        : Mutex<fixed_step::T>,
+  /// When (in local ns) a scheduled shutdown happens, if one is scheduled.
+  pub shutdown_at       : Mutex<Option<u64>>,
+  /// Where client messages are being logged for replaying, if anywhere.
+  pub replay_log        : Mutex<Option<replay::Recorder>>,
 }
 
-#[allow(missing_docs)]
+/// A server with the default terrain.
 pub fn new() -> T {
+  of_terrain(terrain::T::new(terrain::Seed::new(0)), default_distances())
+}
+
+#[allow(missing_docs)]
+pub fn of_terrain(terrain: terrain::T, distances: Distances) -> T {
   let world_width: u32 = 1 << 11;
+  let world_bounds =
+    Aabb3::new(
+      Point3 { x: -(world_width as i32), y: -512, z: -(world_width as i32) },
+      Point3 { x: world_width as i32, y: 512, z: world_width as i32 },
+    );
   let world_width = world_width as f32;
   let physics =
     physics::T::new(
@@ -78,9 +201,14 @@ pub fn new() -> T {
       )
     );
 
+  let (hooks, edit_rules) = hooks::load(std::path::Path::new("default.hooks"));
+  let mut edit_permissions = edit_permissions::new(world_bounds);
+  edit_permissions.push_override(Box::new(edit_rules));
+
   let server = T {
     players           : Mutex::new(fnv_map::new()),
     mobs              : Mutex::new(fnv_map::new()),
+    components        : components::new(),
 
     player_allocator  : Mutex::new(id_allocator::new()),
     mob_allocator     : Mutex::new(id_allocator::new()),
@@ -90,7 +218,13 @@ pub fn new() -> T {
     client_allocator  : Mutex::new(id_allocator::new()),
 
     physics: Mutex::new(physics),
-    terrain_loader: terrain_loader::T::new(),
+    edit_permissions: Mutex::new(edit_permissions),
+    hooks: Mutex::new(hooks),
+    webhooks: Mutex::new(webhooks::load(std::path::Path::new("default.webhooks"))),
+    roles: Mutex::new(roles::load(std::path::PathBuf::from("default.roles"))),
+    auth: auth::load(std::path::Path::new("default.tokens")),
+    terrain_loader: terrain_loader::T::new(terrain),
+    navgrid: Mutex::new(navgrid::new()),
     rng: {
       let seed = [0];
       let seed: &[usize] = &seed;
@@ -98,17 +232,41 @@ pub fn new() -> T {
     },
 
     clients: Mutex::new(fnv_map::new()),
+    max_players: Mutex::new(join_queue::DEFAULT_MAX_PLAYERS),
+    rate_budgets: Mutex::new(rate_limit::default_budgets()),
+    join_queue: Mutex::new(join_queue::new()),
+    afk: Mutex::new(afk::new()),
+    mining: Mutex::new(fnv_map::new()),
+    projectiles: Mutex::new(fnv_map::new()),
+    beacons: Mutex::new(beacon::new()),
+    portals: Mutex::new(portal::new()),
+    attachments: Mutex::new(attachment::new()),
+    distances: distances,
+    hibernation: Mutex::new(hibernation::new(std::path::PathBuf::from("default.hibernation"))),
     sun: Mutex::new(Sun::new(SUN_TICK_NS)),
+    weather: Mutex::new(weather::new()),
 
-    update_timer: {
-      let now = time::precise_time_ns();
-      let nanoseconds_per_second = 1000000000;
-      Mutex::new(
-        IntervalTimer::new(nanoseconds_per_second / UPDATES_PER_SECOND, now)
-      )
-    }
+    simulation: Mutex::new(fixed_step::new(time::precise_time_ns())),
+    shutdown_at: Mutex::new(None),
+    replay_log: Mutex::new(None),
   };
 
   init_mobs(&server);
   server
 }
+
+/// The client controlling a player.
+pub fn client_of(server: &T, player_id: entity::id::Player) -> error::Result<protocol::ClientId> {
+  server.components.controller(protocol::EntityRef::Player(player_id)).ok_or(error::T::Uncontrolled(player_id))
+}
+
+/// Send a message to a client that's playing.
+pub fn send_to(server: &T, client_id: protocol::ClientId, msg: protocol::ServerToClient) -> error::Result<()> {
+  match server.clients.lock().unwrap().get_mut(&client_id) {
+    None => Err(error::T::UnknownClient(client_id)),
+    Some(client) => {
+      client.send(msg);
+      Ok(())
+    },
+  }
+}
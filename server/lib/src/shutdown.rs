@@ -0,0 +1,41 @@
+//! Winding the server down in an orderly way: warn clients, stop taking input, then flush state.
+
+use time;
+
+use common::protocol;
+
+use server;
+
+/// Tell every client the server is going away in `eta_ns`, and schedule the server to stop then.
+/// If a shutdown is already scheduled, the earlier one wins.
+pub fn begin(server: &server::T, reason: String, eta_ns: u64) {
+  let at = time::precise_time_ns() + eta_ns;
+  {
+    let mut shutdown_at = server.shutdown_at.lock().unwrap();
+    if let Some(prev) = *shutdown_at {
+      if prev <= at {
+        info!("Shutdown already scheduled sooner");
+        return
+      }
+    }
+    *shutdown_at = Some(at);
+  }
+
+  info!("Shutting down in {}s: {}", eta_ns / 1_000_000_000, reason);
+  for (_, client) in server.clients.lock().unwrap().iter_mut() {
+    client.send(protocol::ServerToClient::ShuttingDown(reason.clone(), eta_ns));
+  }
+}
+
+/// Whether a scheduled shutdown is due.
+pub fn is_due(server: &server::T, now_ns: u64) -> bool {
+  match *server.shutdown_at.lock().unwrap() {
+    None => false,
+    Some(at) => now_ns >= at,
+  }
+}
+
+/// Whether clients have been warned about a shutdown.
+pub fn is_scheduled(server: &server::T) -> bool {
+  server.shutdown_at.lock().unwrap().is_some()
+}
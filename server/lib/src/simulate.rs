@@ -0,0 +1,68 @@
+//! Fast-forwarding an empty world, so it looks lived-in before anybody plays in it. The sun moves on by the simulated
+//! time, and new trees sprout at random on open ground around the spawn point.
+
+use rand::Rng;
+
+use common::voxel;
+
+use edit_permissions;
+use player;
+use server;
+use update_gaia;
+
+/// How many trees sprout in each simulated hour.
+const TREES_PER_HOUR: f32 = 4.0;
+/// How far above and below the spawn point we look for ground to sprout trees in.
+const SEARCH_HEIGHT: i32 = 128;
+
+/// The highest solid voxel in a column near the spawn point, if it's bare ground (rather than e.g. an existing tree).
+fn bare_ground(server: &server::T, x: i32, z: i32) -> Option<voxel::bounds::T> {
+  let spawn_y = player::SPAWN_POINT.y as i32;
+  for y in (spawn_y - SEARCH_HEIGHT .. spawn_y + SEARCH_HEIGHT).rev() {
+    let bounds = voxel::bounds::new(x, y, z, 0);
+    let material =
+      match server.terrain_loader.load_voxels(&bounds) {
+        voxel::Volume(material) => material,
+        voxel::Surface(ref vertex) => vertex.corner,
+      };
+    match material {
+      voxel::Material::Empty => {},
+      voxel::Material::Terrain => return Some(bounds),
+      _ => return None,
+    }
+  }
+  None
+}
+
+/// Simulate `hours` of growth within `radius` of the spawn point. This only runs while nobody's connected, since the
+/// whole region changes at once.
+pub fn run<UpdateGaia>(server: &server::T, update_gaia: &mut UpdateGaia, hours: f32, radius: u32) -> Result<(), String>
+  where UpdateGaia: FnMut(update_gaia::Message)
+{
+  if !server.clients.lock().unwrap().is_empty() {
+    return Err(String::from("Can't simulate while clients are connected"))
+  }
+
+  server.sun.lock().unwrap().fast_forward((hours * 3600.0 * 1e9) as u64);
+
+  let trees = (hours * TREES_PER_HOUR).round() as u32;
+  let (spawn_x, spawn_z) = (player::SPAWN_POINT.x as i32, player::SPAWN_POINT.z as i32);
+  let r = radius as i32;
+  let mut planted = 0;
+  for _ in 0 .. trees {
+    let (x, z) = {
+      let mut rng = server.rng.lock().unwrap();
+      (spawn_x + rng.gen_range(-r, r + 1), spawn_z + rng.gen_range(-r, r + 1))
+    };
+    let ground =
+      match bare_ground(server, x, z) {
+        None => continue,
+        Some(ground) => ground,
+      };
+    update_gaia(update_gaia::Message::Plant(edit_permissions::Actor::Console, ground));
+    planted += 1;
+  }
+
+  info!("Simulated {} hours within {} of spawn: {} of {} trees sprouted", hours, radius, planted, trees);
+  Ok(())
+}
@@ -0,0 +1,126 @@
+//! A uniform grid of hashed cells, for finding overlapping bounds. Insertion and removal only touch the cells an
+//! entry covers, so churning small entries (e.g. terrain, as blocks load and unload) stays cheap.
+
+use collision::{Aabb3};
+use std::fmt::Debug;
+
+use common::fnv_map;
+
+use octree::aabb_overlap;
+
+/// The width of each cell, in world units.
+pub const CELL_WIDTH: f32 = 4.0;
+
+type Cell = (i32, i32, i32);
+
+pub struct T<V> {
+  cells: fnv_map::T<Cell, Vec<(Aabb3<f32>, V)>>,
+}
+
+pub fn new<V>() -> T<V> {
+  T {
+    cells: fnv_map::new(),
+  }
+}
+
+fn cell_of(x: f32) -> i32 {
+  (x / CELL_WIDTH).floor() as i32
+}
+
+/// Call `f` on every cell that some bounds touch.
+fn for_each_cell<F>(bounds: &Aabb3<f32>, mut f: F) where F: FnMut(Cell) {
+  for x in cell_of(bounds.min.x) .. cell_of(bounds.max.x) + 1 {
+  for y in cell_of(bounds.min.y) .. cell_of(bounds.max.y) + 1 {
+  for z in cell_of(bounds.min.z) .. cell_of(bounds.max.z) + 1 {
+    f((x, y, z));
+  }}}
+}
+
+impl<V: Debug + Copy + Eq> T<V> {
+  pub fn insert(&mut self, bounds: &Aabb3<f32>, v: V) {
+    let cells = &mut self.cells;
+    for_each_cell(bounds, |cell| {
+      cells.entry(cell).or_insert_with(Vec::new).push((*bounds, v));
+    });
+  }
+
+  pub fn remove(&mut self, bounds: &Aabb3<f32>, v: V) {
+    let cells = &mut self.cells;
+    for_each_cell(bounds, |cell| {
+      let now_empty =
+        match cells.get_mut(&cell) {
+          None => panic!("{:?} not found in spatial hash", v),
+          Some(entries) => {
+            let i = entries.iter().position(|&(_, ref x)| *x == v).unwrap();
+            entries.swap_remove(i);
+            entries.is_empty()
+          },
+        };
+      if now_empty {
+        cells.remove(&cell);
+      }
+    });
+  }
+
+  pub fn reinsert(&mut self, v: V, bounds: &Aabb3<f32>, new_bounds: &Aabb3<f32>) {
+    self.remove(bounds, v);
+    self.insert(new_bounds, v);
+  }
+
+  pub fn intersect(&self, bounds: &Aabb3<f32>, self_v: Option<V>) -> Option<(Aabb3<f32>, V)> {
+    self.intersect_where(bounds, self_v, &mut |_| true)
+  }
+
+  /// Like `intersect`, but only count overlapping entries whose bounds also pass `pred`.
+  pub fn intersect_where<P>(
+    &self,
+    bounds: &Aabb3<f32>,
+    self_v: Option<V>,
+    pred: &mut P,
+  ) -> Option<(Aabb3<f32>, V)> where
+    P: FnMut(&Aabb3<f32>) -> bool,
+  {
+    let mut r = None;
+    for_each_cell(bounds, |cell| {
+      if r.is_some() {
+        return
+      }
+      if let Some(entries) = self.cells.get(&cell) {
+        r =
+          entries.iter()
+            .find(|&&(ref bs, ref v)| Some(*v) != self_v && aabb_overlap(bounds, bs) && pred(bs))
+            .cloned();
+      }
+    });
+    r
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use cgmath::Point3;
+  use collision::Aabb3;
+
+  use super::*;
+
+  fn cube(x: f32, y: f32, z: f32, w: f32) -> Aabb3<f32> {
+    Aabb3::new(Point3::new(x, y, z), Point3::new(x + w, y + w, z + w))
+  }
+
+  #[test]
+  fn test_insert_remove() {
+    let mut hash = new();
+    hash.insert(&cube(0.0, 0.0, 0.0, 1.0), 1);
+    // Spans several cells.
+    hash.insert(&cube(3.0, 3.0, 3.0, 2.0), 2);
+    assert_eq!(hash.intersect(&cube(0.5, 0.5, 0.5, 0.1), None).map(|(_, v)| v), Some(1));
+    assert_eq!(hash.intersect(&cube(4.5, 4.5, 4.5, 0.1), None).map(|(_, v)| v), Some(2));
+    assert_eq!(hash.intersect(&cube(4.5, 4.5, 4.5, 0.1), Some(2)), None);
+    assert_eq!(hash.intersect(&cube(1.0, 1.0, 1.0, 1.0), None), None);
+
+    hash.remove(&cube(3.0, 3.0, 3.0, 2.0), 2);
+    assert_eq!(hash.intersect(&cube(4.5, 4.5, 4.5, 0.1), None), None);
+    hash.remove(&cube(0.0, 0.0, 0.0, 1.0), 1);
+    assert!(hash.cells.is_empty());
+  }
+}
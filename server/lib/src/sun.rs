@@ -1,12 +1,18 @@
 use common::interval_timer::IntervalTimer;
+use common::protocol;
 use time;
 use std;
 
+/// How often clients are told where the sun is. They extrapolate in between.
+const SEND_INTERVAL_NS: u64 = 5_000_000_000;
+
 pub struct Sun {
   // The sun as portions of a 65536-degree circle.
   pub position: u16,
   pub timer: IntervalTimer,
   pub print_timer: IntervalTimer,
+  pub send_timer: IntervalTimer,
+  tick_ns: u64,
 }
 
 impl Sun {
@@ -15,27 +21,48 @@ impl Sun {
       position: 0,
       timer: IntervalTimer::new(tick_ns, time::precise_time_ns()),
       print_timer: IntervalTimer::new(2e9 as u64, time::precise_time_ns()),
+      send_timer: IntervalTimer::new(SEND_INTERVAL_NS, time::precise_time_ns()),
+      tick_ns: tick_ns,
     }
   }
 
-  pub fn update(&mut self) -> Option<f32> {
-    let ticks = self.timer.update(time::precise_time_ns());
-
-    if ticks == 0 {
-      return None;
+  /// Where the sun is right now, for sending to clients.
+  pub fn state(&self) -> protocol::Sun {
+    protocol::Sun {
+      phase    : (self.position as f32) / 65536.0,
+      time_ns  : time::precise_time_ns(),
+      cycle_ns : self.tick_ns * 65536,
     }
+  }
+
+  /// Move the sun on by some amount of time, all at once.
+  pub fn fast_forward(&mut self, ns: u64) {
+    let ticks = (ns / self.tick_ns) as u16;
+    self.position = (std::num::Wrapping(self.position) + std::num::Wrapping(ticks)).0;
+  }
 
-    self.position = (std::num::Wrapping(self.position) + std::num::Wrapping(ticks as u16)).0;
+  /// Move the sun to some portion of its cycle, in [0, 1).
+  pub fn set_phase(&mut self, phase: f32) {
+    self.position = (phase * 65536.0) as u16;
+  }
+
+  /// Advance the sun. Returns its state when it's time to update clients.
+  pub fn update(&mut self) -> Option<protocol::Sun> {
+    let now = time::precise_time_ns();
+    let ticks = self.timer.update(now);
+
+    if ticks > 0 {
+      self.position = (std::num::Wrapping(self.position) + std::num::Wrapping(ticks as u16)).0;
+    }
 
-    // Fraction completed of a full cycle.
-    let fraction = (self.position as f32) / 65536.0;
-    // Longer day, shorter night.
-    let fraction = fraction * fraction;
+    if self.print_timer.update(now) > 0 {
+      debug!("Sun is at {:.1}%.", (self.position as f32) / 65536.0 * 100.0);
+    }
 
-    if self.print_timer.update(time::precise_time_ns()) > 0 {
-      debug!("Sun is at {:.1}%.", fraction * 100.0);
+    if self.send_timer.update(now) == 0 {
+      return None;
     }
 
-    Some(fraction)
+    Some(self.state())
   }
 }
@@ -0,0 +1,216 @@
+//! Structure templates: boxes of terrain copied out of the world, saved under a name, and pasted back in elsewhere.
+//! Templates keep the material of each of the smallest voxels in the box. Pasting stamps the whole box, air included,
+//! so the surfaces it leaves are blockier than the ones that were copied.
+
+use bincode;
+use cgmath::{Point3, Vector3};
+use collision::Aabb3;
+use std;
+
+use common::voxel;
+
+use brush;
+use terrain;
+use voxel_data;
+
+/// Where templates are kept, one `<name>.template` file each.
+pub const DIRECTORY: &str = "templates";
+/// The most voxels a template can hold.
+pub const MAX_VOLUME: i32 = 64 * 64 * 64;
+
+#[allow(missing_docs)]
+#[derive(Debug, Clone, PartialEq)]
+pub struct T {
+  /// How many voxels wide, tall and deep the template is.
+  pub size   : Vector3<i32>,
+  /// The smallest voxels in the box, in x, then y, then z order (z varies fastest).
+  pub voxels : Vec<voxel::T>,
+}
+
+/// The material a voxel's mostly made of.
+fn material_of(voxel: &voxel::T) -> voxel::Material {
+  match *voxel {
+    voxel::Volume(material) => material,
+    voxel::Surface(vertex) => vertex.corner,
+  }
+}
+
+/// Copy a region of terrain, including its min corner but not its max.
+pub fn copy(terrain: &terrain::T, region: &Aabb3<i32>) -> Result<T, String> {
+  let size = region.max - region.min;
+  if size.x <= 0 || size.y <= 0 || size.z <= 0 {
+    return Err(format!("Empty region: {:?}", region))
+  }
+  let volume = size.x as i64 * size.y as i64 * size.z as i64;
+  if volume > MAX_VOLUME as i64 {
+    return Err(format!("Region has {} voxels; templates can have at most {}", volume, MAX_VOLUME))
+  }
+  Ok(T {
+    size   : size,
+    voxels : terrain.extract(region),
+  })
+}
+
+/// Template names are used as file names, so they're kept to letters, digits, `-` and `_`.
+fn path(name: &str) -> Result<std::path::PathBuf, String> {
+  let valid = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_';
+  if name.is_empty() || !name.chars().all(valid) {
+    return Err(format!("Invalid template name: {:?}", name))
+  }
+  Ok(std::path::Path::new(DIRECTORY).join(format!("{}.template", name)))
+}
+
+impl T {
+  /// Save this template under a name, in place of any template already saved under it.
+  pub fn save(&self, name: &str) -> Result<(), String> {
+    let path = try!(path(name));
+    try!(std::fs::create_dir_all(DIRECTORY).map_err(|err| format!("Error creating {:?}: {:?}", DIRECTORY, err)));
+    let mut file = try!(std::fs::File::create(&path).map_err(|err| format!("Error creating {:?}: {:?}", path, err)));
+    let size = (self.size.x, self.size.y, self.size.z);
+    bincode::serialize_into(&mut file, &(size, &self.voxels), bincode::Infinite)
+      .map_err(|err| format!("Error writing {:?}: {:?}", path, err))
+  }
+
+  /// Every material in the template, each once.
+  pub fn materials(&self) -> Vec<voxel::Material> {
+    let mut materials = Vec::new();
+    for voxel in &self.voxels {
+      let material = material_of(voxel);
+      if !materials.contains(&material) {
+        materials.push(material);
+      }
+    }
+    materials
+  }
+
+  /// The material of the voxel at some offset from the template's low corner, if it's inside the template.
+  fn material_at(&self, offset: &Point3<i32>) -> Option<voxel::Material> {
+    if offset.x < 0 || offset.y < 0 || offset.z < 0 ||
+       offset.x >= self.size.x || offset.y >= self.size.y || offset.z >= self.size.z {
+      return None
+    }
+    let i = (offset.x * self.size.y + offset.y) * self.size.z + offset.z;
+    Some(material_of(&self.voxels[i as usize]))
+  }
+
+  /// A brush that pastes this template with its low corner at `low`.
+  pub fn brush(self, low: &Point3<i32>) -> brush::T {
+    let bounds = Aabb3::new(*low + Vector3::new(-1, -1, -1), *low + self.size);
+    let translation = Vector3::new(low.x as f32, low.y as f32, low.z as f32);
+    let mosaic =
+      voxel_data::mosaic::translation::T {
+        translation : translation,
+        mosaic      : Stamp { template: self },
+      };
+    voxel_data::brush::T {
+      bounds      : bounds,
+      mosaic      : Box::new(mosaic) as Box<voxel_data::mosaic::T<voxel::Material> + Send>,
+      min_lg_size : 0,
+    }
+  }
+}
+
+/// Load a template that was saved under some name.
+pub fn load(name: &str) -> Result<T, String> {
+  let path = try!(path(name));
+  let mut file = try!(std::fs::File::open(&path).map_err(|err| format!("Error opening {:?}: {:?}", path, err)));
+  let ((x, y, z), voxels): ((i32, i32, i32), Vec<voxel::T>) =
+    try!(
+      bincode::deserialize_from(&mut file, bincode::Infinite)
+      .map_err(|err| format!("Error reading {:?}: {:?}", path, err))
+    );
+  if x <= 0 || y <= 0 || z <= 0 || x as i64 * y as i64 * z as i64 != voxels.len() as i64 {
+    return Err(format!("{:?} has {} voxels, which don't fill {}x{}x{}", path, voxels.len(), x, y, z))
+  }
+  Ok(T {
+    size   : Vector3::new(x, y, z),
+    voxels : voxels,
+  })
+}
+
+/// A template as a mosaic, with its low corner at the origin. It fills its whole box, so the brush overwrites
+/// everything in the box; the material at each point is the one of the template voxel it's in.
+struct Stamp {
+  template: T,
+}
+
+impl Stamp {
+  fn high(&self) -> Point3<f32> {
+    let size = &self.template.size;
+    Point3::new(size.x as f32, size.y as f32, size.z as f32)
+  }
+}
+
+impl voxel_data::field::T for Stamp {
+  fn density(&mut self, p: &Point3<f32>) -> f32 {
+    // How far inside the box the point is, along whichever axis it's nearest a face.
+    let high = self.high();
+    let mut d = std::f32::INFINITY;
+    for i in 0 .. 3 {
+      d = f32::min(d, f32::min(p[i], high[i] - p[i]));
+    }
+    d
+  }
+
+  fn normal(&mut self, p: &Point3<f32>) -> Vector3<f32> {
+    // Point out of whichever face is nearest.
+    let center = self.high() / 2.0;
+    let v = *p - center;
+    let (x, y, z) = (v.x.abs() / center.x, v.y.abs() / center.y, v.z.abs() / center.z);
+    if x >= y && x >= z {
+      Vector3::new(v.x.signum(), 0.0, 0.0)
+    } else if y >= z {
+      Vector3::new(0.0, v.y.signum(), 0.0)
+    } else {
+      Vector3::new(0.0, 0.0, v.z.signum())
+    }
+  }
+}
+
+impl voxel_data::mosaic::T<voxel::Material> for Stamp {
+  fn material(&mut self, p: &Point3<f32>) -> Option<voxel::Material> {
+    let offset = Point3::new(p.x.floor() as i32, p.y.floor() as i32, p.z.floor() as i32);
+    self.template.material_at(&offset)
+  }
+}
+
+/// Where to paste a template so the middle of its bottom is at `target`.
+pub fn low_corner(size: &Vector3<i32>, target: &Point3<f32>) -> Point3<i32> {
+  let target = Point3::new(target.x.floor() as i32, target.y.floor() as i32, target.z.floor() as i32);
+  target - Vector3::new(size.x / 2, 0, size.z / 2)
+}
+
+#[cfg(test)]
+mod test {
+  use cgmath::{Point3, Vector3};
+
+  use common::voxel;
+
+  use super::*;
+
+  #[test]
+  fn test_material_at() {
+    let template =
+      T {
+        size   : Vector3::new(1, 2, 3),
+        voxels :
+          (0 .. 6)
+          .map(|i| voxel::Volume(if i == 4 { voxel::Material::Stone } else { voxel::Material::Empty }))
+          .collect(),
+      };
+    // z varies fastest, so voxel 4 is at y = 1, z = 1.
+    assert_eq!(template.material_at(&Point3::new(0, 1, 1)), Some(voxel::Material::Stone));
+    assert_eq!(template.material_at(&Point3::new(0, 0, 1)), Some(voxel::Material::Empty));
+    assert_eq!(template.material_at(&Point3::new(0, 2, 1)), None);
+    assert_eq!(template.material_at(&Point3::new(-1, 0, 0)), None);
+    assert_eq!(template.materials(), vec!(voxel::Material::Empty, voxel::Material::Stone));
+  }
+
+  #[test]
+  fn test_path() {
+    assert!(path("house_2").is_ok());
+    assert!(path("").is_err());
+    assert!(path("../roles").is_err());
+    assert!(path("a/b").is_err());
+  }
+}
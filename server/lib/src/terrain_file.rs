@@ -0,0 +1,82 @@
+//! Saving terrain to disk, and loading it back. Along with the voxels, the file has everything about them that can't
+//! be regenerated: which voxels were compacted, which blocks were edited, and which voxels were reinforced.
+
+use bincode;
+use std;
+
+use common::fnv_map;
+use common::fnv_set;
+use common::voxel;
+
+use terrain;
+
+/// Where the server keeps its terrain.
+pub const DEFAULT_PATH: &str = "default.terrain";
+
+/// The contents of a terrain file.
+#[allow(missing_docs)]
+pub struct Contents {
+  pub voxels     : voxel::tree::T,
+  pub compacted  : fnv_map::T<voxel::bounds::T, voxel::T>,
+  pub edited     : fnv_set::T<voxel::bounds::T>,
+  pub reinforced : fnv_map::T<voxel::bounds::T, u8>,
+}
+
+/// Read a terrain file.
+pub fn read(path: &std::path::Path) -> Result<Contents, String> {
+  let mut file = try!(std::fs::File::open(path).map_err(|err| format!("Error opening {:?}: {:?}", path, err)));
+  let (voxels, compacted, edited, reinforced) =
+    try!(
+      bincode::deserialize_from(&mut file, bincode::Infinite)
+      .map_err(|err| format!("Error reading {:?}: {:?}", path, err))
+    );
+  Ok(Contents {
+    voxels     : voxels,
+    compacted  : compacted,
+    edited     : edited,
+    reinforced : reinforced,
+  })
+}
+
+/// Write a terrain file.
+pub fn write(
+  path       : &std::path::Path,
+  voxels     : &voxel::tree::T,
+  compacted  : &fnv_map::T<voxel::bounds::T, voxel::T>,
+  edited     : &fnv_set::T<voxel::bounds::T>,
+  reinforced : &fnv_map::T<voxel::bounds::T, u8>,
+) -> Result<(), String> {
+  let mut file = try!(std::fs::File::create(path).map_err(|err| format!("Error creating {:?}: {:?}", path, err)));
+  bincode::serialize_into(&mut file, &(voxels, compacted, edited, reinforced), bincode::Infinite)
+    .map_err(|err| format!("Error writing {:?}: {:?}", path, err))
+}
+
+/// Load a terrain file into `terrain`, in place of what's there.
+pub fn load(terrain: &terrain::T, path: &std::path::Path) -> Result<(), String> {
+  let contents = try!(read(path));
+  *terrain.voxels.lock().unwrap() = contents.voxels;
+  terrain.compaction.lock().unwrap().compacted = contents.compacted;
+  terrain.edits.lock().unwrap().blocks = contents.edited;
+  terrain.reinforcement.lock().unwrap().levels = contents.reinforced;
+  Ok(())
+}
+
+/// The bytes `save` would write for `terrain`, taken with all of it locked, so they can be written out later without
+/// holding anything.
+pub fn snapshot(terrain: &terrain::T) -> Result<Vec<u8>, String> {
+  let voxels = terrain.voxels.lock().unwrap();
+  let compaction = terrain.compaction.lock().unwrap();
+  let edits = terrain.edits.lock().unwrap();
+  let reinforcement = terrain.reinforcement.lock().unwrap();
+  let contents = (&*voxels, &compaction.compacted, &edits.blocks, &reinforcement.levels);
+  bincode::serialize(&contents, bincode::Infinite).map_err(|err| format!("Error serializing terrain: {:?}", err))
+}
+
+/// Save `terrain` to a file.
+pub fn save(terrain: &terrain::T, path: &std::path::Path) -> Result<(), String> {
+  let voxels = terrain.voxels.lock().unwrap();
+  let compaction = terrain.compaction.lock().unwrap();
+  let edits = terrain.edits.lock().unwrap();
+  let reinforcement = terrain.reinforcement.lock().unwrap();
+  write(path, &voxels, &compaction.compacted, &edits.blocks, &reinforcement.levels)
+}
@@ -7,9 +7,11 @@ use common::fnv_map;
 use common::id_allocator;
 use common::voxel;
 
+use cancel;
 use entity;
 use in_progress_terrain;
 use lod;
+use pending_loads;
 use physics;
 use terrain;
 use update_gaia;
@@ -25,18 +27,42 @@ pub struct T {
   pub in_progress_terrain : Mutex<in_progress_terrain::T>,
   pub lod_map             : Mutex<lod::Map>,
   pub loaded              : Mutex<fnv_map::T<voxel::bounds::T, Vec<entity::id::Terrain>>>,
+  /// Blocks being loaded right now, so concurrent requests for them don't load them twice.
+  pub pending_loads       : pending_loads::T,
+  /// Full loads requested from gaia that haven't been inserted yet, by block and owner, so they can be cancelled if
+  /// the owner stops wanting them first. Only locked with `lod_map` locked.
+  requested               : Mutex<fnv_map::T<(voxel::bounds::T, lod::OwnerId), cancel::T>>,
 }
 
 impl T {
-  pub fn new() -> T {
+  pub fn new(terrain: terrain::T) -> T {
     T {
-      terrain             : terrain::T::new(terrain::Seed::new(0)),
+      terrain             : terrain,
       in_progress_terrain : Mutex::new(in_progress_terrain::T::new()),
       lod_map             : Mutex::new(lod::Map::new()),
       loaded              : Mutex::new(fnv_map::new()),
+      pending_loads       : pending_loads::new(),
+      requested           : Mutex::new(fnv_map::new()),
     }
   }
 
+  /// Load a block of voxels, or wait for it if it's already being loaded. Blocks that have been loaded before are
+  /// already stored in the terrain's voxels, so they aren't generated again.
+  pub fn load_voxels(&self, bounds: &voxel::bounds::T) -> voxel::T {
+    self.load_voxels_unless(bounds, &|| false).unwrap()
+  }
+
+  /// Like `load_voxels`, but give up (and return None) if `cancel` is cancelled before the block's done generating.
+  pub fn load_voxels_unless_cancelled(&self, bounds: &voxel::bounds::T, cancel: &cancel::T) -> Option<voxel::T> {
+    self.load_voxels_unless(bounds, &|| cancel.is_cancelled())
+  }
+
+  fn load_voxels_unless<Cancelled>(&self, bounds: &voxel::bounds::T, cancelled: &Cancelled) -> Option<voxel::T> where
+    Cancelled: Fn() -> bool,
+  {
+    self.pending_loads.load(bounds, || self.terrain.load_unless(bounds, cancelled))
+  }
+
   // TODO: Avoid the double-lookup when unload and load the same index.
 
   pub fn load<LoadBlock>(
@@ -53,6 +79,16 @@ impl T {
     let max_lod_changed: bool;
     let mut lod_map = self.lod_map.lock().unwrap();
     let mut in_progress_terrain = self.in_progress_terrain.lock().unwrap();
+    let mut requested = self.requested.lock().unwrap();
+    if let Some(cancel) = requested.remove(&(*position, owner)) {
+      if new_lod == lod::Full {
+        // It's already on its way.
+        requested.insert((*position, owner), cancel);
+        return;
+      }
+      debug!("Cancelling load of {:?} for {:?}", position, owner);
+      cancel.cancel();
+    }
     match lod_map.get(position, owner) {
       Some((Some(prev), lods)) => {
         prev_lod = Some(prev);
@@ -94,13 +130,25 @@ impl T {
       },
       lod::Full => {
         debug!("{:?} requested from gaia", position);
+        let cancel = cancel::new();
+        requested.insert((*position, owner), cancel.clone());
         load_block(
-          update_gaia::Message::Load(time::precise_time_ns(), vec!(*position), LoadDestination::Local(owner))
+          update_gaia::Message::Load(time::precise_time_ns(), vec!(*position), LoadDestination::Local(owner, cancel))
         );
       },
     };
   }
 
+  /// Stop keeping track of a load from gaia, now that it's been inserted. `cancel` is the load's token, in case the
+  /// block's been requested again since.
+  pub fn loaded_block(&self, position: &voxel::bounds::T, owner: lod::OwnerId, cancel: &cancel::T) {
+    let mut requested = self.requested.lock().unwrap();
+    let done = requested.get(&(*position, owner)).map_or(false, |c| c.same(cancel));
+    if done {
+      requested.remove(&(*position, owner));
+    }
+  }
+
   pub fn insert_block(
     block               : &LoadedTerrain,
     position            : &voxel::bounds::T,
@@ -156,9 +204,16 @@ impl T {
     owner    : lod::OwnerId,
   ) {
     let lod_change;
-    match self.lod_map.lock().unwrap().remove(*position, owner) {
-      (_, None) => return,
-      (_, Some(c)) => lod_change = c,
+    {
+      let mut lod_map = self.lod_map.lock().unwrap();
+      if let Some(cancel) = self.requested.lock().unwrap().remove(&(*position, owner)) {
+        debug!("Cancelling load of {:?} for {:?}", position, owner);
+        cancel.cancel();
+      }
+      match lod_map.remove(*position, owner) {
+        (_, None) => return,
+        (_, Some(c)) => lod_change = c,
+      }
     }
 
     lod_change.loaded.map(|loaded_lod| {
@@ -0,0 +1,97 @@
+//! Decide how much terrain detail each client gets, based on what it says it can handle.
+
+use common::fnv_set;
+use common::protocol;
+use common::voxel;
+
+use server;
+
+// Roughly what a client needs to be able to take for full-detail terrain. Each doubling of voxel size cuts both of
+// these by about 4x, since terrain is mostly a surface.
+const FULL_DETAIL_TRIANGLES: u64 = 1 << 20;
+const FULL_DETAIL_BANDWIDTH: u64 = 4 << 20;
+// Don't clamp any further than this, no matter how little the client claims to handle.
+const MAX_MIN_LG_VOXEL_SIZE: i16 = 3;
+
+/// Pick the terrain limits for a client with some capabilities, on a server with some `distances`.
+pub fn negotiate(capabilities: &protocol::Capabilities, distances: &server::Distances) -> protocol::TerrainPolicy {
+  let mut min_lg_voxel_size = 0;
+  while min_lg_voxel_size < MAX_MIN_LG_VOXEL_SIZE {
+    let scale = 1 << (2 * min_lg_voxel_size);
+    let triangles_ok = capabilities.max_triangles as u64 * scale >= FULL_DETAIL_TRIANGLES;
+    let bandwidth_ok = capabilities.bandwidth as u64 * scale >= FULL_DETAIL_BANDWIDTH;
+    if triangles_ok && bandwidth_ok {
+      break
+    }
+    min_lg_voxel_size += 1;
+  }
+  protocol::TerrainPolicy {
+    min_lg_voxel_size   : min_lg_voxel_size,
+    view_distance       : distances.view,
+    simulation_distance : distances.simulation,
+  }
+}
+
+/// Replace any requested voxels finer than a policy allows with the allowed voxels containing them.
+pub fn clamp_request(policy: &protocol::TerrainPolicy, voxels: Vec<voxel::bounds::T>) -> Vec<voxel::bounds::T> {
+  if voxels.iter().all(|bounds| bounds.lg_size >= policy.min_lg_voxel_size) {
+    return voxels
+  }
+
+  let mut seen = fnv_set::new();
+  let mut r = Vec::with_capacity(voxels.len());
+  for bounds in voxels {
+    let bounds =
+      if bounds.lg_size >= policy.min_lg_voxel_size {
+        bounds
+      } else {
+        let shift = policy.min_lg_voxel_size - bounds.lg_size;
+        voxel::bounds::new(bounds.x >> shift, bounds.y >> shift, bounds.z >> shift, policy.min_lg_voxel_size)
+      };
+    if seen.insert(bounds) {
+      r.push(bounds);
+    }
+  }
+  r
+}
+
+#[cfg(test)]
+mod test {
+  use common::protocol;
+  use common::voxel;
+
+  use server;
+
+  use super::*;
+
+  #[test]
+  fn test_negotiate() {
+    let policy = |max_triangles, bandwidth| {
+      let capabilities = protocol::Capabilities { max_triangles: max_triangles, bandwidth: bandwidth };
+      negotiate(&capabilities, &server::default_distances()).min_lg_voxel_size
+    };
+    assert_eq!(policy(1 << 22, 1 << 24), 0);
+    // Either limit can force coarser terrain.
+    assert_eq!(policy(1 << 18, 1 << 24), 1);
+    assert_eq!(policy(1 << 22, 1 << 20), 1);
+    assert_eq!(policy(0, 0), MAX_MIN_LG_VOXEL_SIZE);
+  }
+
+  #[test]
+  fn test_clamp_request() {
+    let policy =
+      protocol::TerrainPolicy {
+        min_lg_voxel_size   : 1,
+        view_distance       : server::DEFAULT_VIEW_DISTANCE,
+        simulation_distance : server::DEFAULT_SIMULATION_DISTANCE,
+      };
+    let voxels =
+      vec!(
+        voxel::bounds::new(-1, 0, 0, 0),
+        voxel::bounds::new(-2, 1, 1, 0),
+        voxel::bounds::new(3, 0, 0, 2),
+      );
+    let clamped = clamp_request(&policy, voxels);
+    assert_eq!(clamped, vec!(voxel::bounds::new(-1, 0, 0, 1), voxel::bounds::new(3, 0, 0, 2)));
+  }
+}
@@ -0,0 +1,262 @@
+//! End-to-end test of edits being broadcast: two clients connect over in-process sockets, one of them builds and then
+//! digs, and both have to end up with the same terrain as the server.
+
+use bincode;
+use cgmath::{Point3, Vector3};
+use std;
+use std::collections::hash_map::DefaultHasher;
+use std::f32::consts::PI;
+use std::hash::Hasher;
+use std::time::Duration;
+use time;
+
+use common::fnv_map;
+use common::protocol;
+use common::socket::ReceiveSocket;
+use common::voxel;
+
+use brush;
+use client_recv_thread::apply_client_update;
+use edit_permissions;
+use entity;
+use error;
+use join_queue;
+use mining;
+use server;
+use update_gaia;
+
+/// A client, as far as the server can tell: a socket it's sent messages on, and the terrain it's been told about.
+struct Client {
+  socket    : ReceiveSocket,
+  id        : protocol::ClientId,
+  secret    : protocol::Secret,
+  player_id : entity::id::Player,
+  voxels    : fnv_map::T<voxel::bounds::T, voxel::T>,
+}
+
+/// The next message the server sent a client that `f` picks out, skipping the rest.
+fn wait_for<R, F>(socket: &mut ReceiveSocket, mut f: F) -> R where
+  F: FnMut(protocol::ServerToClient) -> Option<R>,
+{
+  loop {
+    let bytes = socket.read().expect("Timed out waiting for the server");
+    if let Some(r) = f(bincode::deserialize(&bytes).unwrap()) {
+      return r
+    }
+  }
+}
+
+/// Run messages from a client to the server, and the terrain edits they lead to.
+fn send(server: &server::T, message: protocol::ClientToServer) {
+  let mut to_gaia = Vec::new();
+  apply_client_update(server, &mut |up| to_gaia.push(up), message).unwrap();
+  for up in to_gaia {
+    update_gaia::update_gaia(server, up);
+  }
+}
+
+fn connect(server: &server::T, account: &str) -> Client {
+  let url = format!("inproc://two_clients_test/{}", account);
+  let mut socket = ReceiveSocket::new(&url, Some(Duration::from_secs(10)));
+  let capabilities = protocol::Capabilities { max_triangles: 1 << 22, bandwidth: 1 << 24 };
+  send(server, protocol::ClientToServer::Init(protocol::VERSION, url, account.to_owned(), None, capabilities));
+  let (id, secret) =
+    wait_for(&mut socket, |up| match up {
+      protocol::ServerToClient::LeaseId(id, _, _, secret) => Some((id, secret)),
+      _ => None,
+    });
+
+  send(server, protocol::ClientToServer::Request(secret, protocol::Request::AddPlayer(id)));
+  let player_id =
+    wait_for(&mut socket, |up| match up {
+      protocol::ServerToClient::PlayerAdded(player_id, _) => Some(player_id),
+      _ => None,
+    });
+
+  Client {
+    socket    : socket,
+    id        : id,
+    secret    : secret,
+    player_id : player_id,
+    voxels    : fnv_map::new(),
+  }
+}
+
+impl Client {
+  /// A request as this client would send it, with its secret.
+  fn sign(&self, request: protocol::Request) -> protocol::ClientToServer {
+    protocol::ClientToServer::Request(self.secret, request)
+  }
+
+  /// Wait for the next batch of updated voxels, and apply it. Only voxels that changed should be sent.
+  fn receive_update(&mut self) {
+    let voxels =
+      wait_for(&mut self.socket, |up| match up {
+        protocol::ServerToClient::Voxels { voxels, reason: protocol::VoxelReason::Updated } => Some(voxels),
+        _ => None,
+      });
+    assert!(!voxels.is_empty());
+    for (bounds, voxel) in voxels {
+      assert!(self.voxels.get(&bounds) != Some(&voxel), "{:?} was sent without changing", bounds);
+      self.voxels.insert(bounds, voxel);
+    }
+  }
+}
+
+/// Put a client's player above `target`, looking straight down at it, and aim the way the client would.
+fn aim_down(server: &server::T, client: &Client, target: Point3<f32>) -> protocol::Aim {
+  let now = time::precise_time_ns();
+  let mut players = server.players.lock().unwrap();
+  let player = players.get_mut(&client.player_id).unwrap();
+  player.position = target + Vector3::new(0.0, 8.0, 0.0);
+  player.rotate_vertical(-PI / 2.0);
+  player.record_pose(now);
+  protocol::Aim {
+    position  : player.position,
+    direction : player.forward(),
+    time_ns   : now,
+  }
+}
+
+/// A hash of some voxels, that doesn't depend on the order they're stored in.
+fn world_hash(voxels: &fnv_map::T<voxel::bounds::T, voxel::T>) -> u64 {
+  let mut voxels: Vec<_> = voxels.iter().collect();
+  voxels.sort_by_key(|&(bounds, _)| (bounds.lg_size, bounds.x, bounds.y, bounds.z));
+  let mut hasher = DefaultHasher::new();
+  hasher.write(&bincode::serialize(&voxels, bincode::Infinite).unwrap());
+  hasher.finish()
+}
+
+#[test]
+fn test_edits_reach_every_client() {
+  let server = server::new();
+  let mut alice = connect(&server, "alice");
+  let mut bob = connect(&server, "bob");
+  assert!(alice.id != bob.id);
+
+  let center = Point3::new(3.0, 80.0, 5.0);
+  let edit = |material, radius| {
+    update_gaia::update_gaia(
+      &server,
+      update_gaia::Message::Brush(
+        edit_permissions::Actor::Console,
+        vec!(material),
+        brush::sphere(&center, radius, material),
+      ),
+    );
+  };
+
+  // Build a ball of stone in the sky, then hollow it out.
+  for &(material, radius) in &[(voxel::Material::Stone, 3.0), (voxel::Material::Empty, 1.5)] {
+    edit(material, radius);
+    alice.receive_update();
+    bob.receive_update();
+    assert_eq!(world_hash(&alice.voxels), world_hash(&bob.voxels));
+  }
+
+  // Hollowing it out again doesn't change anything, so nothing's sent: the next update is from putting a pebble inside.
+  edit(voxel::Material::Empty, 1.5);
+  edit(voxel::Material::Stone, 1.0);
+  alice.receive_update();
+  bob.receive_update();
+  assert_eq!(world_hash(&alice.voxels), world_hash(&bob.voxels));
+
+  // Alice plants a tree on top of the ball, the way her client would.
+  let top = center + Vector3::new(0.0, 3.0, 0.0);
+  let aim = aim_down(&server, &alice, top);
+  send(&server, alice.sign(protocol::Request::Add(alice.player_id, aim)));
+  alice.receive_update();
+  bob.receive_update();
+  assert_eq!(world_hash(&alice.voxels), world_hash(&bob.voxels));
+
+  // Then she digs into it, which takes a while to get through the first voxel.
+  let aim = aim_down(&server, &alice, top);
+  send(&server, alice.sign(protocol::Request::StartRemove(alice.player_id, aim)));
+  let deadline = time::precise_time_ns() + 10_000_000_000;
+  let mut to_gaia = Vec::new();
+  while to_gaia.is_empty() {
+    assert!(time::precise_time_ns() < deadline, "Digging never got anywhere");
+    std::thread::sleep(Duration::from_millis(50));
+    mining::update(&server, &mut |up| to_gaia.push(up));
+  }
+  for up in to_gaia {
+    update_gaia::update_gaia(&server, up);
+  }
+  alice.receive_update();
+  bob.receive_update();
+  assert_eq!(world_hash(&alice.voxels), world_hash(&bob.voxels));
+  send(&server, alice.sign(protocol::Request::StopRemove(alice.player_id)));
+
+  // Both clients agree with the server, too. Voxels the server has compacted since are checked against what they were
+  // compacted into.
+  let mut on_server = fnv_map::new();
+  {
+    let voxels = server.terrain_loader.terrain.voxels.lock().unwrap();
+    let compaction = server.terrain_loader.terrain.compaction.lock().unwrap();
+    for bounds in alice.voxels.keys() {
+      let voxel = voxels.get(bounds).cloned().or_else(|| compaction.inherited(bounds));
+      on_server.insert(*bounds, voxel.expect("Voxel missing on the server"));
+    }
+  }
+  assert_eq!(world_hash(&alice.voxels), world_hash(&on_server));
+
+  send(&server, alice.sign(protocol::Request::Leave(alice.id)));
+  send(&server, bob.sign(protocol::Request::Leave(bob.id)));
+}
+
+#[test]
+fn test_failed_clients_are_dropped() {
+  let server = server::new();
+  let carol = connect(&server, "carol");
+  let mut dave = connect(&server, "dave");
+
+  // Messages about clients and players that aren't there are errors, not panics.
+  let stale = apply_client_update(&server, &mut |_| {}, dave.sign(protocol::Request::AddPlayer(dave.id + 100)));
+  match stale {
+    Err(error::T::UnknownClient(_)) => {},
+    r => panic!("Expected an unknown client, got {:?}", r),
+  }
+  let walk = carol.sign(protocol::Request::Walk(carol.player_id + 100, Vector3::new(1.0, 0.0, 0.0)));
+  match apply_client_update(&server, &mut |_| {}, walk) {
+    Err(error::T::Uncontrolled(_)) => {},
+    r => panic!("Expected an uncontrolled player, got {:?}", r),
+  }
+
+  // A client that can't be sent to is dropped, players and all, and everyone else hears about it.
+  server.clients.lock().unwrap().get_mut(&carol.id).unwrap().failure = Some(error::T::Send(String::from("gone")));
+  join_queue::drop_failed(&server);
+  assert!(!server.clients.lock().unwrap().contains_key(&carol.id));
+  assert!(!server.players.lock().unwrap().contains_key(&carol.player_id));
+  let removed =
+    wait_for(&mut dave.socket, |up| match up {
+      protocol::ServerToClient::PlayerRemoved(player_id) => Some(player_id),
+      _ => None,
+    });
+  assert_eq!(removed, carol.player_id);
+
+  send(&server, dave.sign(protocol::Request::Leave(dave.id)));
+}
+
+#[test]
+fn test_requests_need_their_clients_secret() {
+  let server = server::new();
+  let erin = connect(&server, "erin");
+  let frank = connect(&server, "frank");
+
+  // Frank knows Erin's ids, but not her secret, so he can't act for her.
+  let forged = [
+    protocol::Request::Leave(erin.id),
+    protocol::Request::Walk(erin.player_id, Vector3::new(1.0, 0.0, 0.0)),
+    protocol::Request::Command(erin.player_id, String::from("spawn me")),
+  ];
+  for request in forged.iter() {
+    match apply_client_update(&server, &mut |_| {}, frank.sign(request.clone())) {
+      Err(error::T::Forged(client_id)) => assert_eq!(client_id, erin.id),
+      r => panic!("Expected {:?} to be refused, got {:?}", request, r),
+    }
+  }
+  assert!(server.clients.lock().unwrap().contains_key(&erin.id));
+
+  send(&server, erin.sign(protocol::Request::Leave(erin.id)));
+  send(&server, frank.sign(protocol::Request::Leave(frank.id)));
+}
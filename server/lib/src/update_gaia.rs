@@ -1,22 +1,34 @@
 //! Creator of the earth.
 
+use cgmath::{Point3, Vector3};
 use collision::{Aabb3};
+use std;
+use std::ops::DerefMut;
 use stopwatch;
 
-use common;
 use common::protocol;
+use common::protocol::EntityRef;
 use common::voxel;
 
+use brush;
+use cancel;
+use edit_permissions;
 use lod;
+use placement;
 use server;
+use template;
+use terrain;
 use terrain_loader;
-use voxel_data;
+use webhooks;
 
-#[derive(Debug, Clone, Copy)]
+/// How many blocks of a brush to apply at a time. Anything else queued for gaia gets a turn between slices.
+const BLOCKS_PER_SLICE: usize = 64;
+
+#[derive(Debug, Clone)]
 /// What to do with a loaded block
 pub enum LoadDestination {
-  /// The server requested this block. Load it into local state.
-  Local(lod::OwnerId),
+  /// The server requested this block. Load it into local state, unless the request's been cancelled by then.
+  Local(lod::OwnerId, cancel::T),
   /// A client requested this block. Send it to them.
   Client(protocol::ClientId),
   /// Drop the loaded voxels on the floor.
@@ -27,47 +39,210 @@ pub enum LoadDestination {
 pub enum Message {
   /// Load some voxels
   Load(u64, Vec<voxel::bounds::T>, LoadDestination),
-  /// Apply a brush operation
-  Brush(voxel_data::brush::T<Box<voxel_data::mosaic::T<common::voxel::Material> + Send>>),
+  /// Apply a brush operation on somebody's behalf, if they're allowed to. The materials are the ones the brush can
+  /// leave behind.
+  Brush(edit_permissions::Actor, Vec<voxel::Material>, brush::T),
+  /// Apply the next slice of a brush that's already been allowed.
+  ResumeBrush(BrushInProgress),
+  /// Paste a template on somebody's behalf, if they're allowed to, with its low corner at a point.
+  Paste(edit_permissions::Actor, template::T, Point3<i32>),
+  /// Grow a tree out of the ground at a voxel on somebody's behalf, if they're allowed to.
+  Plant(edit_permissions::Actor, voxel::bounds::T),
+  /// Half-bury a rock in the ground at a voxel on somebody's behalf, if they're allowed to.
+  PlaceRock(edit_permissions::Actor, voxel::bounds::T),
 }
 
-// TODO: Consider adding terrain loads to a thread pool instead of having one monolithic separate thread.
-#[allow(missing_docs)]
+/// A brush that's partway through being applied.
+pub struct BrushInProgress {
+  actor   : edit_permissions::Actor,
+  brush   : brush::T,
+  /// The region the whole brush covers. `brush.bounds` gets narrowed to one block at a time.
+  bounds  : Aabb3<i32>,
+  /// The blocks the brush still has to be applied to.
+  blocks  : Vec<voxel::bounds::T>,
+  /// Whether any slice so far has changed anything.
+  changed : bool,
+}
+
+/// Apply an update all at once.
 pub fn update_gaia(
   server: &server::T,
   update: Message,
 ) {
+  let mut update = Some(update);
+  while let Some(up) = update {
+    update = update_gaia_slice(server, up);
+  }
+}
+
+// TODO: Consider adding terrain loads to a thread pool instead of having one monolithic separate thread.
+/// Apply some of an update, and return the rest of it, if there is any. Big brushes are applied `BLOCKS_PER_SLICE`
+/// blocks at a time, so one huge edit doesn't hold up every block load behind it.
+pub fn update_gaia_slice(
+  server: &server::T,
+  update: Message,
+) -> Option<Message> {
   stopwatch::time("update_gaia", move || {
     match update {
       Message::Load(time_requested, voxel_bounds, load_reason) => {
         stopwatch::time("terrain.load", || {
           load(server, time_requested, voxel_bounds, load_reason);
         });
+        None
       },
-      Message::Brush(mut brush) => {
-        let mut updates = Vec::new();
-        server.terrain_loader.terrain.brush(
-          &mut brush,
-          |block, bounds| {
-            trace!("update bounds {:?}", bounds);
-            updates.push((*bounds, *block));
-          },
-        );
-
-        let mut clients = server.clients.lock().unwrap();
-        for (_, client) in clients.iter_mut() {
-          client.send(
-            protocol::ServerToClient::Voxels {
-              voxels : updates.clone(),
-              reason : protocol::VoxelReason::Updated,
+      Message::Brush(actor, materials, brush) => {
+        let decision =
+          server.edit_permissions.lock().unwrap().decide(
+            &edit_permissions::Edit {
+              actor     : actor,
+              bounds    : &brush.bounds,
+              materials : &materials,
             }
           );
+        if let Err(reason) = decision {
+          deny(server, actor, reason);
+          return None
         }
+
+        let bounds = brush.bounds;
+        brush_slice(
+          server,
+          BrushInProgress {
+            actor   : actor,
+            brush   : brush,
+            bounds  : bounds,
+            blocks  : terrain::edits::blocks_in(&bounds),
+            changed : false,
+          },
+        )
       },
-    };
+      Message::ResumeBrush(brushing) => brush_slice(server, brushing),
+      Message::Paste(actor, template, low) => {
+        debug!("Pasting a {:?} template at {:?}", template.size, low);
+        let materials = template.materials();
+        update_gaia_slice(server, Message::Brush(actor, materials, template.brush(&low)))
+      },
+      Message::Plant(actor, bounds) => {
+        let brush = {
+          let mut rng = server.rng.lock().unwrap();
+          placement::tree(server, rng.deref_mut(), &bounds)
+        };
+        update_gaia_slice(server, Message::Brush(actor, vec!(voxel::Material::Bark, voxel::Material::Leaves), brush))
+      },
+      Message::PlaceRock(actor, bounds) => {
+        let brush = {
+          let mut rng = server.rng.lock().unwrap();
+          placement::rock(server, rng.deref_mut(), &bounds)
+        };
+        update_gaia_slice(server, Message::Brush(actor, vec!(voxel::Material::Stone), brush))
+      },
+    }
   })
 }
 
+/// Apply a brush to its next `BLOCKS_PER_SLICE` blocks, and send out whatever changed. Returns the rest of the brush,
+/// if it isn't done.
+fn brush_slice(server: &server::T, mut brushing: BrushInProgress) -> Option<Message> {
+  let first = brushing.blocks.len().saturating_sub(BLOCKS_PER_SLICE);
+  let blocks = brushing.blocks.split_off(first);
+
+  let mut updates = Vec::new();
+  let mut dirty = Vec::new();
+  for block in &blocks {
+    brushing.brush.bounds = clip(block, &brushing.bounds);
+    dirty.extend(
+      server.terrain_loader.terrain.brush(
+        &mut brushing.brush,
+        |voxel, bounds| {
+          trace!("update bounds {:?}", bounds);
+          updates.push((*bounds, *voxel));
+        },
+      )
+    );
+  }
+
+  if !dirty.is_empty() {
+    brushing.changed = true;
+    server.navgrid.lock().unwrap().edited(&server.terrain_loader.terrain, &dirty);
+
+    let mut clients = server.clients.lock().unwrap();
+    for (_, client) in clients.iter_mut() {
+      client.send(
+        protocol::ServerToClient::Voxels {
+          voxels : updates.clone(),
+          reason : protocol::VoxelReason::Updated,
+        }
+      );
+    }
+  }
+
+  if !brushing.blocks.is_empty() {
+    return Some(Message::ResumeBrush(brushing))
+  }
+  if brushing.changed {
+    large_edit(server, brushing.actor, &brushing.bounds);
+  } else {
+    trace!("Brush at {:?} didn't change anything", brushing.bounds);
+  }
+  None
+}
+
+/// The part of `bounds` within a block. Both include their max corners, so the clipped blocks of a region tile it
+/// without overlapping.
+fn clip(block: &voxel::bounds::T, bounds: &Aabb3<i32>) -> Aabb3<i32> {
+  let region = terrain::edits::region(block);
+  let high = region.max + Vector3::new(-1, -1, -1);
+  Aabb3::new(
+    Point3::new(
+      std::cmp::max(region.min.x, bounds.min.x),
+      std::cmp::max(region.min.y, bounds.min.y),
+      std::cmp::max(region.min.z, bounds.min.z),
+    ),
+    Point3::new(
+      std::cmp::min(high.x, bounds.max.x),
+      std::cmp::min(high.y, bounds.max.y),
+      std::cmp::min(high.z, bounds.max.z),
+    ),
+  )
+}
+
+/// Fire the webhook for an edit, if it's a big one.
+fn large_edit(server: &server::T, actor: edit_permissions::Actor, bounds: &Aabb3<i32>) {
+  let size = bounds.max - bounds.min;
+  let volume = size.x as i64 * size.y as i64 * size.z as i64;
+  if volume < webhooks::LARGE_EDIT_VOLUME {
+    return
+  }
+  let mut fields = vec!(
+    ("volume", webhooks::Value::Int(volume)),
+    ("x", webhooks::Value::Int(((bounds.min.x + bounds.max.x) / 2) as i64)),
+    ("y", webhooks::Value::Int(((bounds.min.y + bounds.max.y) / 2) as i64)),
+    ("z", webhooks::Value::Int(((bounds.min.z + bounds.max.z) / 2) as i64)),
+  );
+  if let edit_permissions::Actor::Player(player_id) = actor {
+    fields.push(("player", webhooks::Value::Int(player_id.to_u32() as i64)));
+  }
+  webhooks::fire(server, webhooks::Event::Edit, &fields);
+}
+
+/// Tell whoever tried to make an edit why it didn't happen.
+fn deny(server: &server::T, actor: edit_permissions::Actor, reason: String) {
+  info!("{:?} can't make an edit: {}", actor, reason);
+  let player_id =
+    match actor {
+      edit_permissions::Actor::Console => return,
+      edit_permissions::Actor::Player(player_id) => player_id,
+    };
+  let client_id =
+    match server.components.controller(EntityRef::Player(player_id)) {
+      None => return,
+      Some(client_id) => client_id,
+    };
+  if let Some(client) = server.clients.lock().unwrap().get_mut(&client_id) {
+    client.send(protocol::ServerToClient::EditDenied(reason));
+  }
+}
+
 #[inline(never)]
 fn load(
   server: &server::T,
@@ -75,19 +250,26 @@ fn load(
   voxel_bounds: Vec<voxel::bounds::T>,
   load_reason: LoadDestination,
 ) {
-  // TODO: Just lock `terrain` for the check and then the move;
-  // don't lock for the whole time where we're generating the block.
-  let mut lod_map = server.terrain_loader.lod_map.lock().unwrap();
-  let mut in_progress_terrain = server.terrain_loader.in_progress_terrain.lock().unwrap();
   match load_reason {
     LoadDestination::None => {
       for voxel_bounds in voxel_bounds {
-        server.terrain_loader.terrain.load(&voxel_bounds);
+        server.terrain_loader.load_voxels(&voxel_bounds);
       }
     },
-    LoadDestination::Local(owner) => {
+    LoadDestination::Local(owner, cancel) => {
       for voxel_bounds in voxel_bounds {
-        let block = server.terrain_loader.terrain.load(&voxel_bounds);
+        if cancel.is_cancelled() {
+          debug!("Load of {:?} cancelled before generating", voxel_bounds);
+          continue
+        }
+        let block =
+          match server.terrain_loader.load_voxels_unless_cancelled(&voxel_bounds, &cancel) {
+            None => {
+              debug!("Load of {:?} cancelled while generating", voxel_bounds);
+              continue
+            },
+            Some(block) => block,
+          };
         let bounds =
           match block {
             voxel::Volume(voxel::Material::Empty) => Vec::new(),
@@ -97,8 +279,15 @@ fn load(
               vec!((id, Aabb3::new(low, high)))
             },
           };
-        // TODO: Check that this block isn't stale, i.e. should still be loaded.
-        // Maybe this should just ping the original thread, same as we ping the client.
+        // The block's generated before these are locked, so it might have been unloaded in the meantime;
+        // `insert_block` ignores stale blocks. Loads are only cancelled with `lod_map` locked, so this check holds
+        // until the block's inserted.
+        let mut lod_map = server.terrain_loader.lod_map.lock().unwrap();
+        if cancel.is_cancelled() {
+          debug!("Load of {:?} cancelled after generating", voxel_bounds);
+          continue
+        }
+        let mut in_progress_terrain = server.terrain_loader.in_progress_terrain.lock().unwrap();
         terrain_loader::T::insert_block(
           &terrain_loader::LoadedTerrain { bounds: bounds },
           &voxel_bounds,
@@ -108,23 +297,52 @@ fn load(
           &mut *in_progress_terrain,
           &mut *server.terrain_loader.loaded.lock().unwrap(),
         );
+        server.terrain_loader.loaded_block(&voxel_bounds, owner, &cancel);
       }
     },
     LoadDestination::Client(id) => {
       let mut voxels = Vec::new();
       for voxel_bounds in voxel_bounds {
-        let voxel = server.terrain_loader.terrain.load(&voxel_bounds);
+        let voxel = server.terrain_loader.load_voxels(&voxel_bounds);
         voxels.push((voxel_bounds, voxel));
       }
 
       let mut clients = server.clients.lock().unwrap();
-      let client = clients.get_mut(&id).unwrap();
-      client.send(
-        protocol::ServerToClient::Voxels {
-          voxels : voxels,
-          reason : protocol::VoxelReason::Requested { at: time_requested },
-        }
-      );
+      // The client may have left (or been kicked) while these were queued.
+      if let Some(client) = clients.get_mut(&id) {
+        client.blocks.extend(voxels.iter().map(|&(bounds, _)| bounds));
+        client.send(
+          protocol::ServerToClient::Voxels {
+            voxels : voxels,
+            reason : protocol::VoxelReason::Requested { at: time_requested },
+          }
+        );
+      }
     },
   }
 }
+
+#[cfg(test)]
+mod test {
+  use cgmath::Point3;
+  use collision::Aabb3;
+
+  use terrain;
+
+  use super::*;
+
+  #[test]
+  fn test_clip() {
+    let bounds = Aabb3::new(Point3::new(-3, 5, 7), Point3::new(12, 9, 30));
+    let volume = |b: &Aabb3<i32>| (b.max.x - b.min.x + 1) * (b.max.y - b.min.y + 1) * (b.max.z - b.min.z + 1);
+    let blocks = terrain::edits::blocks_in(&bounds);
+    assert_eq!(blocks.len(), 3 * 2 * 4);
+    let clipped: Vec<_> = blocks.iter().map(|block| clip(block, &bounds)).collect();
+    assert_eq!(clipped.iter().map(&volume).sum::<i32>(), volume(&bounds));
+    for b in &clipped {
+      assert!(b.min.x >= bounds.min.x && b.max.x <= bounds.max.x);
+      assert!(b.min.y >= bounds.min.y && b.max.y <= bounds.max.y);
+      assert!(b.min.z >= bounds.min.z && b.max.z <= bounds.max.z);
+    }
+  }
+}
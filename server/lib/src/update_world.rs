@@ -1,56 +1,131 @@
-use cgmath::{Point3, Vector3};
+use cgmath::{Point3, Vector2, Vector3};
+use collision::Aabb3;
 use std::ops::Neg;
 use stopwatch;
+use time;
 
 use common::protocol;
+use common::protocol::EntityRef;
 use common::surroundings_loader::LoadType;
 use common::voxel;
 
+use afk;
+use attachment;
+use hibernation;
+use hooks;
+use join_queue;
 use lod;
+use mining;
 use mob;
 use player;
+use projectile;
 use server;
 use update_gaia;
+use weather;
+use webhooks;
 
 // TODO: Consider removing the IntervalTimer.
 
+/// How far a player has to move before we look for blocks to unload again.
+const UNLOAD_CHECK_DISTANCE: f32 = 32.0;
+/// How far around its player a client watching physics sees its bounds.
+const PHYSICS_WATCH_DISTANCE: f32 = 16.0;
+/// How often a client watching physics is sent its bounds.
+const PHYSICS_WATCH_INTERVAL_NS: u64 = 250_000_000;
+
+/// Simulate `steps` fixed steps of the world, then tell clients about the result.
 pub fn update_world<RequestBlock>(
   server: &server::T,
+  steps: u32,
   request_block: &mut RequestBlock,
 ) where
   RequestBlock: FnMut(update_gaia::Message),
 {
   stopwatch::time("update_world", || {
     stopwatch::time("update_world.player", || {
-      let mut updates = Vec::new();
+      // Updates for each player's own client.
+      let mut own_updates = Vec::new();
+      // Updates for everybody else.
+      let mut entity_updates = Vec::new();
 
+      let now = time::precise_time_ns();
       for (_, player) in server.players.lock().unwrap().iter_mut() {
-        let (bounds, collisions) = player.update(server, request_block);
-        updates.push(protocol::ServerToClient::UpdatePlayer(player.entity_id, bounds));
-        updates.extend(
-          collisions.into_iter()
-          .map(|c| {
+        player.load_surroundings(server, request_block);
+        if server.attachments.lock().unwrap().get(EntityRef::Player(player.entity_id)).is_some() {
+          // `attachment::propagate` moves it, and tells clients.
+          player.record_pose(now);
+          continue
+        }
+        let (mut bounds, mut collisions) = player.step(server);
+        for _ in 1 .. steps {
+          let (b, c) = player.step(server);
+          bounds = b;
+          collisions.extend(c);
+        }
+        player.record_pose(now);
+        server.components.moved(EntityRef::Player(player.entity_id), &bounds, player.speed);
+        if let Some(health) = player.take_health_update() {
+          own_updates.push((player.client_id, protocol::ServerToClient::HealthUpdate(health)));
+          if health == 0 {
+            let player_id = webhooks::Value::Int(player.entity_id.to_u32() as i64);
+            webhooks::fire(server, webhooks::Event::Death, &[("player", player_id)]);
+            bounds = player.respawn(server);
+            let health = player.take_health_update().unwrap();
+            own_updates.push((player.client_id, protocol::ServerToClient::HealthUpdate(health)));
+          }
+        }
+        let portal = server.portals.lock().unwrap().moved(player.entity_id, &bounds);
+        if let Some(destination) = portal {
+          info!("{:?} went through a portal to {:?}", player.entity_id, destination);
+          // This moves the player's physics, and tells its client to recenter the terrain it has loaded.
+          player.teleport(server, destination);
+          bounds = player::bounds_at(destination);
+          server.portals.lock().unwrap().arrived(player.entity_id, &bounds);
+        }
+        own_updates.push((player.client_id, protocol::ServerToClient::UpdatePlayer(player.entity_id, bounds)));
+        entity_updates.push((
+          player.client_id,
+          protocol::ServerToClient::UpdatePlayerEntity(
+            player.entity_id,
+            bounds,
+            Vector2::new(player.lateral_rotation, player.vertical_rotation),
+            None,
+          ),
+        ));
+        for c in collisions {
+          let c =
             match c {
               player::Collision::Terrain(_) => protocol::Collision::PlayerTerrain(player.entity_id),
               player::Collision::Misc(_)    => protocol::Collision::PlayerMisc(player.entity_id),
-            }
-          })
-          .map(|c| {
-            protocol::ServerToClient::Collision(c)
-          })
-        );
+            };
+          own_updates.push((player.client_id, protocol::ServerToClient::Collision(c)));
+        }
       }
 
       let mut clients = server.clients.lock().unwrap();
-      for (_, client) in &mut *clients {
-        for update in &updates {
-          client.send(update.clone());
+      for (&client_id, client) in &mut *clients {
+        for &(owner, ref update) in &own_updates {
+          if owner == client_id {
+            client.send(update.clone());
+          }
+        }
+        for &(owner, ref update) in &entity_updates {
+          if owner != client_id {
+            client.send(update.clone());
+          }
         }
       }
     });
 
     stopwatch::time("update_world.mobs", || {
+      let player_positions = server.components.player_positions();
       for (_, mob) in server.mobs.lock().unwrap().iter_mut() {
+        let simulated =
+          player_positions.iter().any(|&p| max_norm(mob.position - p) <= server.distances.simulation);
+        if !simulated {
+          continue
+        }
+
         let position =
           Point3::new(
             mob.position.x as i32,
@@ -69,35 +144,154 @@ pub fn update_world<RequestBlock>(
           )
         }
 
-        {
-          let behavior = mob.behavior;
-          (behavior)(server, mob);
+        if server.attachments.lock().unwrap().get(EntityRef::Mob(mob.entity_id)).is_some() {
+          continue
         }
 
-        mob.speed = mob.speed + -Vector3::new(0.0, 0.1, 0.0 as f32);
+        for _ in 0 .. steps {
+          {
+            let behavior = mob.behavior;
+            (behavior)(server, mob);
+          }
 
-        // TODO: This logic is dumb (isolating along components shouldn't be a thing). Change it.
-        let delta_p = mob.speed;
-        if delta_p.x != 0.0 {
-          translate_mob(server, mob, &Vector3::new(delta_p.x, 0.0, 0.0));
-        }
-        if delta_p.y != 0.0 {
-          translate_mob(server, mob, &Vector3::new(0.0, delta_p.y, 0.0));
-        }
-        if delta_p.z != 0.0 {
-          translate_mob(server, mob, &Vector3::new(0.0, 0.0, delta_p.z));
+          mob.speed = mob.speed + -Vector3::new(0.0, 0.1, 0.0 as f32);
+
+          // TODO: This logic is dumb (isolating along components shouldn't be a thing). Change it.
+          let delta_p = mob.speed;
+          if delta_p.x != 0.0 {
+            translate_mob(server, mob, &Vector3::new(delta_p.x, 0.0, 0.0));
+          }
+          if delta_p.y != 0.0 {
+            translate_mob(server, mob, &Vector3::new(0.0, delta_p.y, 0.0));
+          }
+          if delta_p.z != 0.0 {
+            translate_mob(server, mob, &Vector3::new(0.0, 0.0, delta_p.z));
+          }
         }
       }
     });
 
-    server.sun.lock().unwrap().update().map(|fraction| {
+    stopwatch::time("update_world.projectiles", || {
+      projectile::update(server, steps, request_block);
+    });
+
+    stopwatch::time("update_world.attachments", || {
+      attachment::propagate(server);
+    });
+
+    stopwatch::time("update_world.hibernation", || {
+      hibernation::update(server, time::precise_time_ns());
+    });
+
+    stopwatch::time("update_world.mining", || {
+      mining::update(server, request_block);
+    });
+
+    stopwatch::time("update_world.unload_blocks", || {
+      unload_distant_blocks(server);
+    });
+
+    stopwatch::time("update_world.physics_bounds", || {
+      send_physics_bounds(server, time::precise_time_ns());
+    });
+
+    join_queue::tick(server, time::precise_time_ns());
+    afk::tick(server, time::precise_time_ns());
+    hooks::tick(server, request_block, time::precise_time_ns());
+
+    server.sun.lock().unwrap().update().map(|sun| {
       for (_, client) in server.clients.lock().unwrap().iter_mut() {
-        client.send(protocol::ServerToClient::UpdateSun(fraction));
+        client.send(protocol::ServerToClient::UpdateSun(sun));
       }
     });
+    weather::update(server, time::precise_time_ns());
   });
 }
 
+fn max_norm(v: Vector3<f32>) -> f32 {
+  f32::max(v.x.abs(), f32::max(v.y.abs(), v.z.abs()))
+}
+
+/// Send clients that are watching physics the bounds around their players, if it's time to.
+fn send_physics_bounds(server: &server::T, now: u64) {
+  let mut watchers = Vec::new();
+  {
+    let players = server.players.lock().unwrap();
+    let mut clients = server.clients.lock().unwrap();
+    for (_, player) in players.iter() {
+      if let Some(client) = clients.get_mut(&player.client_id) {
+        match client.watching_physics {
+          Some(next) if now >= next => {
+            client.watching_physics = Some(now + PHYSICS_WATCH_INTERVAL_NS);
+            watchers.push((player.client_id, player.position));
+          },
+          _ => {},
+        }
+      }
+    }
+  }
+  if watchers.is_empty() {
+    return
+  }
+
+  let updates: Vec<_> = {
+    let physics = server.physics.lock().unwrap();
+    watchers.into_iter()
+      .map(|(client_id, position)| {
+        let reach = Vector3::new(PHYSICS_WATCH_DISTANCE, PHYSICS_WATCH_DISTANCE, PHYSICS_WATCH_DISTANCE);
+        let region = Aabb3::new(position - reach, position + reach);
+        (client_id, physics.bounds_in(&region, protocol::MAX_PHYSICS_BOUNDS))
+      })
+      .collect()
+  };
+  let mut clients = server.clients.lock().unwrap();
+  for (client_id, bounds) in updates {
+    if let Some(client) = clients.get_mut(&client_id) {
+      client.send(protocol::ServerToClient::PhysicsBounds(bounds));
+    }
+  }
+}
+
+/// Tell clients about blocks they've moved far away from.
+fn unload_distant_blocks(server: &server::T) {
+  let players = server.players.lock().unwrap();
+  let mut clients = server.clients.lock().unwrap();
+  for (_, player) in players.iter() {
+    let client =
+      match clients.get_mut(&player.client_id) {
+        None => continue,
+        Some(client) => client,
+      };
+
+    let position = player.position;
+    if let Some(last_position) = client.last_unload_position {
+      if max_norm(position - last_position) < UNLOAD_CHECK_DISTANCE {
+        continue
+      }
+    }
+    client.last_unload_position = Some(position);
+
+    let far: Vec<voxel::bounds::T> =
+      client.blocks.iter()
+      .filter(|bounds| {
+        let (low, high) = bounds.corners();
+        let center = low + (high - low) / 2.0;
+        max_norm(center - position) > server.distances.view
+      })
+      .cloned()
+      .collect();
+    if far.is_empty() {
+      continue
+    }
+
+    for bounds in &far {
+      client.blocks.remove(bounds);
+    }
+    debug!("Unloading {} blocks from client {:?}", far.len(), player.client_id);
+    client.send(protocol::ServerToClient::UnloadBlocks(far));
+  }
+}
+
 fn translate_mob(
   server: &server::T,
   mob: &mut mob::Mob,
@@ -115,10 +309,11 @@ fn translate_mob(
   }
 
   mob.position += *delta_p;
+  server.components.moved(EntityRef::Mob(mob.entity_id), &bounds, mob.speed);
 
   for (_, client) in server.clients.lock().unwrap().iter_mut() {
     client.send(
-      protocol::ServerToClient::UpdateMob(mob.entity_id, bounds),
+      protocol::ServerToClient::UpdateMob(mob.entity_id, bounds, None),
     );
   }
 }
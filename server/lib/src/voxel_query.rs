@@ -0,0 +1,295 @@
+//! Questions about what the world's made of, for moderation and world design: how much of a material there is around
+//! a place, and where the nearest voxel of it is. Queries look at the smallest voxels, a block at a time. Whole voxels
+//! that are out of range get skipped, and compacted voxels are answered for without looking under them.
+//! Terrain that isn't loaded isn't generated for a query. It's left out, and counting says how much was.
+
+use cgmath::{Point3, InnerSpace};
+
+use common::voxel;
+
+use terrain;
+use terrain::compaction;
+
+/// The furthest a query can reach from where it's centered.
+pub const MAX_RADIUS: f32 = 256.0;
+/// How far `find nearest` looks, unless it's told otherwise.
+pub const DEFAULT_SEARCH_RADIUS: f32 = 64.0;
+
+/// Queries are broken into voxels this big, and the terrain's locked for one at a time.
+const BLOCK_LG_SIZE: i16 = compaction::MAX_LG_SIZE;
+
+/// Where a query is centered.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Place {
+  /// Wherever a player (by id) is.
+  Player(u32),
+  #[allow(missing_docs)]
+  Point(Point3<f32>),
+}
+
+#[allow(missing_docs)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum T {
+  /// How many voxels of a material have their centers within `radius` of a place.
+  Count {
+    material : voxel::Material,
+    place    : Place,
+    radius   : f32,
+  },
+  /// The voxel of a material whose center is nearest a place, up to `radius` away.
+  FindNearest {
+    material : voxel::Material,
+    place    : Place,
+    radius   : f32,
+  },
+}
+
+/// What a count found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Counted {
+  #[allow(missing_docs)]
+  pub matching : u64,
+  /// How many voxels in range weren't loaded, so couldn't be looked at.
+  pub unloaded : u64,
+}
+
+/// The material a voxel's mostly made of.
+fn material_of(voxel: &voxel::T) -> voxel::Material {
+  match *voxel {
+    voxel::Volume(material) => material,
+    voxel::Surface(vertex) => vertex.corner,
+  }
+}
+
+fn children(parent: &voxel::bounds::T) -> Vec<voxel::bounds::T> {
+  let mut children = Vec::with_capacity(8);
+  for &dx in &[0, 1] {
+  for &dy in &[0, 1] {
+  for &dz in &[0, 1] {
+    children.push(voxel::bounds::new(2 * parent.x + dx, 2 * parent.y + dy, 2 * parent.z + dz, parent.lg_size - 1));
+  }}}
+  children
+}
+
+/// The nearest and furthest any part of a voxel is from `point`.
+fn distances(bounds: &voxel::bounds::T, point: &Point3<f32>) -> (f32, f32) {
+  let (low, high) = bounds.corners();
+  let axis = |p: f32, min: f32, max: f32| {
+    (f32::max(0.0, f32::max(min - p, p - max)), f32::max((p - min).abs(), (p - max).abs()))
+  };
+  let (near_x, far_x) = axis(point.x, low.x, high.x);
+  let (near_y, far_y) = axis(point.y, low.y, high.y);
+  let (near_z, far_z) = axis(point.z, low.z, high.z);
+  (
+    (near_x * near_x + near_y * near_y + near_z * near_z).sqrt(),
+    (far_x * far_x + far_y * far_y + far_z * far_z).sqrt(),
+  )
+}
+
+/// The blocks that might have voxels within `radius` of `center`, nearest first.
+fn blocks_around(center: &Point3<f32>, radius: f32) -> Vec<(f32, voxel::bounds::T)> {
+  let low = |x: f32| (x - radius).floor() as i32 >> BLOCK_LG_SIZE;
+  let high = |x: f32| (x + radius).ceil() as i32 >> BLOCK_LG_SIZE;
+  let mut blocks = Vec::new();
+  for x in low(center.x) .. high(center.x) + 1 {
+  for y in low(center.y) .. high(center.y) + 1 {
+  for z in low(center.z) .. high(center.z) + 1 {
+    let block = voxel::bounds::new(x, y, z, BLOCK_LG_SIZE);
+    let (near, _) = distances(&block, center);
+    if near <= radius {
+      blocks.push((near, block));
+    }
+  }}}
+  blocks.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+  blocks
+}
+
+/// One query's view of one block's worth of terrain.
+struct Search<'a> {
+  voxels     : &'a voxel::tree::T,
+  compaction : &'a compaction::T,
+  material   : voxel::Material,
+  center     : Point3<f32>,
+  radius     : f32,
+}
+
+impl<'a> Search<'a> {
+  /// What a voxel of lg_size 0 is, if it's loaded. `uniform` is what a compacted voxel it's under was compacted to.
+  fn load(&self, bounds: &voxel::bounds::T, uniform: Option<voxel::T>) -> Option<voxel::T> {
+    uniform.or_else(|| self.voxels.get(bounds).cloned())
+  }
+
+  /// Count the matching voxels in `bounds`.
+  fn count(&self, bounds: voxel::bounds::T, uniform: Option<voxel::T>, counted: &mut Counted) {
+    let (near, far) = distances(&bounds, &self.center);
+    if near > self.radius {
+      return
+    }
+    let uniform = uniform.or_else(|| self.compaction.compacted.get(&bounds).cloned());
+    if let Some(voxel) = uniform {
+      if material_of(&voxel) != self.material {
+        return
+      }
+      if far <= self.radius {
+        counted.matching += 1 << (3 * bounds.lg_size);
+        return
+      }
+    }
+    if bounds.lg_size > 0 {
+      for child in children(&bounds) {
+        self.count(child, uniform, counted);
+      }
+      return
+    }
+    if (bounds.center() - self.center).magnitude() > self.radius {
+      return
+    }
+    match self.load(&bounds, uniform) {
+      None => counted.unloaded += 1,
+      Some(voxel) => {
+        if material_of(&voxel) == self.material {
+          counted.matching += 1;
+        }
+      },
+    }
+  }
+
+  /// Look for a matching voxel in `bounds` that's nearer than `best`, nearest parts first.
+  fn nearest(&self, bounds: voxel::bounds::T, uniform: Option<voxel::T>, best: &mut Option<(f32, voxel::bounds::T)>) {
+    let limit =
+      match *best {
+        None => self.radius,
+        Some((distance, _)) => distance,
+      };
+    if distances(&bounds, &self.center).0 > limit {
+      return
+    }
+    let uniform = uniform.or_else(|| self.compaction.compacted.get(&bounds).cloned());
+    if let Some(voxel) = uniform {
+      if material_of(&voxel) != self.material {
+        return
+      }
+    }
+    if bounds.lg_size > 0 {
+      let mut children: Vec<(f32, voxel::bounds::T)> =
+        children(&bounds).into_iter()
+        .map(|child| (distances(&child, &self.center).0, child))
+        .collect();
+      children.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+      for (_, child) in children {
+        self.nearest(child, uniform, best);
+      }
+      return
+    }
+    match self.load(&bounds, uniform) {
+      Some(ref voxel) if material_of(voxel) == self.material => {},
+      _ => return,
+    }
+    let distance = (bounds.center() - self.center).magnitude();
+    let nearer =
+      match *best {
+        None => distance <= self.radius,
+        Some((best, _)) => distance < best,
+      };
+    if nearer {
+      *best = Some((distance, bounds));
+    }
+  }
+}
+
+/// Count the loaded voxels of `material` whose centers are within `radius` of `center`.
+pub fn count(terrain: &terrain::T, material: voxel::Material, center: &Point3<f32>, radius: f32) -> Counted {
+  let mut counted = Default::default();
+  for (_, block) in blocks_around(center, radius) {
+    let voxels = terrain.voxels.lock().unwrap();
+    let compaction = terrain.compaction.lock().unwrap();
+    let search =
+      Search {
+        voxels     : &voxels,
+        compaction : &compaction,
+        material   : material,
+        center     : *center,
+        radius     : radius,
+      };
+    search.count(block, None, &mut counted);
+  }
+  counted
+}
+
+/// The loaded voxel (of lg_size 0) of `material` nearest `center`, up to `radius` away, and how far away it is.
+pub fn find_nearest(
+  terrain: &terrain::T,
+  material: voxel::Material,
+  center: &Point3<f32>,
+  radius: f32,
+) -> Option<(voxel::bounds::T, f32)> {
+  let mut best = None;
+  for (near, block) in blocks_around(center, radius) {
+    // Blocks are nearest first, so once one's further than the best so far, the rest are too.
+    if let Some((distance, _)) = best {
+      if near > distance {
+        break
+      }
+    }
+    let voxels = terrain.voxels.lock().unwrap();
+    let compaction = terrain.compaction.lock().unwrap();
+    let search =
+      Search {
+        voxels     : &voxels,
+        compaction : &compaction,
+        material   : material,
+        center     : *center,
+        radius     : radius,
+      };
+    search.nearest(block, None, &mut best);
+  }
+  best.map(|(distance, bounds)| (bounds, distance))
+}
+
+#[cfg(test)]
+mod test {
+  use cgmath::Point3;
+  use collision::Aabb3;
+
+  use common::voxel;
+
+  use terrain;
+
+  use super::*;
+
+  fn set(terrain: &terrain::T, x: i32, y: i32, z: i32, material: voxel::Material) {
+    terrain.voxels.lock().unwrap().get_mut_or_create(&voxel::bounds::new(x, y, z, 0)).data =
+      Some(voxel::Volume(material));
+  }
+
+  #[test]
+  fn test_count_and_find() {
+    let terrain = terrain::T::new(terrain::Seed::new(0));
+    for x in 0 .. 8 {
+    for y in 0 .. 8 {
+    for z in 0 .. 8 {
+      set(&terrain, x, y, z, voxel::Material::Stone);
+    }}}
+    // The block compacts to one stone voxel, so it's counted without looking inside.
+    {
+      let mut voxels = terrain.voxels.lock().unwrap();
+      let region = Aabb3::new(Point3::new(0, 0, 0), Point3::new(8, 8, 8));
+      terrain.compaction.lock().unwrap().compact(&mut voxels, &region);
+    }
+    set(&terrain, 20, 0, 0, voxel::Material::Marble);
+
+    let origin = Point3::new(4.0, 4.0, 4.0);
+    let stone = count(&terrain, voxel::Material::Stone, &origin, 7.0);
+    assert_eq!(stone, Counted { matching: 512, unloaded: stone.unloaded });
+    assert!(stone.unloaded > 0);
+    assert_eq!(count(&terrain, voxel::Material::Stone, &Point3::new(0.0, 0.0, 0.0), 0.9).matching, 1);
+    assert_eq!(count(&terrain, voxel::Material::Marble, &origin, 7.0).matching, 0);
+
+    let (bounds, distance) = find_nearest(&terrain, voxel::Material::Marble, &origin, 20.0).unwrap();
+    assert_eq!((bounds.x, bounds.y, bounds.z, bounds.lg_size), (20, 0, 0, 0));
+    assert!((distance - (bounds.center() - origin).magnitude()).abs() < 1e-5);
+    assert_eq!(find_nearest(&terrain, voxel::Material::Marble, &origin, 10.0), None);
+    let (bounds, _) = find_nearest(&terrain, voxel::Material::Stone, &Point3::new(-3.0, 0.2, 0.2), 10.0).unwrap();
+    assert_eq!((bounds.x, bounds.y, bounds.z), (0, 0, 0));
+  }
+}
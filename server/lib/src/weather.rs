@@ -0,0 +1,119 @@
+//! The weather: spells of clear skies, rain or snow, each lasting a few minutes. Clients are told whenever it changes.
+
+use rand;
+use rand::Rng;
+
+use common::protocol;
+
+use server;
+
+/// The shortest a spell of weather lasts.
+const MIN_SPELL_NS: u64 = 120_000_000_000;
+/// The longest a spell of weather lasts.
+const MAX_SPELL_NS: u64 = 600_000_000_000;
+
+/// Clear skies.
+pub const CLEAR: protocol::Weather =
+  protocol::Weather {
+    precipitation : protocol::Precipitation::None,
+    intensity     : 0.0,
+  };
+
+#[allow(missing_docs)]
+pub struct T {
+  pub current : protocol::Weather,
+  /// When (in local ns) the current spell ends. `None` until the first update.
+  spell_ends  : Option<u64>,
+}
+
+#[allow(missing_docs)]
+pub fn new() -> T {
+  T {
+    current    : CLEAR,
+    spell_ends : None,
+  }
+}
+
+/// Pick a spell of weather. It's clear half the time.
+fn random<R: rand::Rng>(rng: &mut R) -> protocol::Weather {
+  let precipitation =
+    match rng.gen_range(0, 10) {
+      0 ... 4 => protocol::Precipitation::None,
+      5 ... 7 => protocol::Precipitation::Rain,
+      _ => protocol::Precipitation::Snow,
+    };
+  match precipitation {
+    protocol::Precipitation::None => CLEAR,
+    _ =>
+      protocol::Weather {
+        precipitation : precipitation,
+        intensity     : rng.gen_range(0.2, 1.0),
+      },
+  }
+}
+
+fn broadcast(server: &server::T, weather: protocol::Weather) {
+  for (_, client) in server.clients.lock().unwrap().iter_mut() {
+    client.send(protocol::ServerToClient::UpdateWeather(weather));
+  }
+}
+
+/// Start a new spell of weather if the current one is over, and tell every client if it changed.
+pub fn update(server: &server::T, now: u64) {
+  let changed = {
+    let mut weather = server.weather.lock().unwrap();
+    let mut rng = server.rng.lock().unwrap();
+    match weather.spell_ends {
+      None => {
+        weather.spell_ends = Some(now + rng.gen_range(MIN_SPELL_NS, MAX_SPELL_NS));
+        None
+      },
+      Some(spell_ends) if now < spell_ends => None,
+      Some(_) => {
+        let next = random(&mut *rng);
+        weather.spell_ends = Some(now + rng.gen_range(MIN_SPELL_NS, MAX_SPELL_NS));
+        if next == weather.current {
+          None
+        } else {
+          weather.current = next;
+          Some(next)
+        }
+      },
+    }
+  };
+  if let Some(weather) = changed {
+    debug!("Weather is now {:?}", weather);
+    broadcast(server, weather);
+  }
+}
+
+/// Change the weather now, and tell every client. It stays this way for a whole spell.
+pub fn set(server: &server::T, weather: protocol::Weather, now: u64) {
+  {
+    let mut state = server.weather.lock().unwrap();
+    state.current = weather;
+    state.spell_ends = Some(now + MAX_SPELL_NS);
+  }
+  broadcast(server, weather);
+}
+
+#[cfg(test)]
+mod test {
+  use rand;
+
+  use common::protocol;
+
+  use super::*;
+
+  #[test]
+  fn test_random_intensity() {
+    let mut rng: rand::XorShiftRng = rand::SeedableRng::from_seed([1, 2, 3, 4]);
+    for _ in 0 .. 1000 {
+      let weather = random(&mut rng);
+      match weather.precipitation {
+        protocol::Precipitation::None => assert_eq!(weather.intensity, 0.0),
+        _ => assert!(weather.intensity >= 0.2 && weather.intensity < 1.0),
+      }
+    }
+  }
+}
@@ -0,0 +1,284 @@
+//! Webhooks: notable events POSTed as JSON to URLs of the operator's choosing, e.g. a relay into a chat channel.
+//! They're set up in a file with one `<event> <url>` per line, and an event can go to any number of URLs:
+//!
+//! ```text
+//! # Comments start with a hash.
+//! join http://localhost:8080/playform
+//! leave http://localhost:8080/playform
+//! death http://localhost:8080/playform
+//! edit http://localhost:8080/playform
+//! start http://localhost:8080/status
+//! stop http://localhost:8080/status
+//! ```
+//!
+//! Bodies look like `{"event":"join","player":3,"account":"alice"}`. Only plain `http://` URLs work.
+//! Deliveries happen on their own thread, so a slow receiver doesn't hold up the server; failed ones are logged and
+//! dropped. The webhooks lock is innermost: don't take any other lock while holding it.
+
+use std;
+use std::io::{BufRead, Read, Write};
+use std::sync::mpsc;
+
+use common::fnv_map;
+use common::line_file;
+
+use server;
+
+/// How long a delivery can take before it's given up on.
+const TIMEOUT_SECS: u64 = 5;
+/// Edits that can touch at least this many voxels are worth telling someone about.
+pub const LARGE_EDIT_VOLUME: i64 = 16 * 16 * 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[allow(missing_docs)]
+pub enum Event {
+  Start,
+  Stop,
+  Join,
+  Leave,
+  Death,
+  /// An edit of at least `LARGE_EDIT_VOLUME` voxels.
+  Edit,
+}
+
+/// Every event, with its name in the webhooks file and in bodies.
+const EVENTS: [(Event, &str); 6] = [
+  (Event::Start , "start"),
+  (Event::Stop  , "stop"),
+  (Event::Join  , "join"),
+  (Event::Leave , "leave"),
+  (Event::Death , "death"),
+  (Event::Edit  , "edit"),
+];
+
+fn name(event: Event) -> &'static str {
+  EVENTS.iter().find(|&&(e, _)| e == event).unwrap().1
+}
+
+/// A field of an event's body.
+#[derive(Debug, Clone, PartialEq)]
+#[allow(missing_docs)]
+pub enum Value {
+  Int(i64),
+  Text(String),
+}
+
+#[allow(missing_docs)]
+pub struct T {
+  urls   : fnv_map::T<Event, Vec<String>>,
+  /// Deliveries for the sending thread, as (url, body). `None` once the thread's been told to finish.
+  sender : Option<mpsc::Sender<(String, String)>>,
+  thread : Option<std::thread::JoinHandle<()>>,
+}
+
+/// Escape a string for a JSON body, quotes included.
+fn quote(s: &str) -> String {
+  let mut r = String::with_capacity(s.len() + 2);
+  r.push('"');
+  for c in s.chars() {
+    match c {
+      '"' => r.push_str("\\\""),
+      '\\' => r.push_str("\\\\"),
+      '\n' => r.push_str("\\n"),
+      '\r' => r.push_str("\\r"),
+      '\t' => r.push_str("\\t"),
+      c if (c as u32) < 0x20 => r.push_str(&format!("\\u{:04x}", c as u32)),
+      c => r.push(c),
+    }
+  }
+  r.push('"');
+  r
+}
+
+fn body(event: Event, fields: &[(&str, Value)]) -> String {
+  let mut body = format!("{{\"event\":{}", quote(name(event)));
+  for &(key, ref value) in fields {
+    let value =
+      match *value {
+        Value::Int(i) => i.to_string(),
+        Value::Text(ref s) => quote(s),
+      };
+    body.push_str(&format!(",{}:{}", quote(key), value));
+  }
+  body.push('}');
+  body
+}
+
+/// Split an `http://` URL into its host, port and path.
+fn parse_url(url: &str) -> Result<(String, u16, String), String> {
+  if !url.starts_with("http://") {
+    return Err(format!("Only http:// URLs are supported: {:?}", url))
+  }
+  let rest = &url["http://".len() ..];
+  let (authority, path) =
+    match rest.find('/') {
+      None => (rest, "/"),
+      Some(i) => (&rest[.. i], &rest[i ..]),
+    };
+  let (host, port) =
+    match authority.find(':') {
+      None => (authority, 80),
+      Some(i) => {
+        let port = &authority[i + 1 ..];
+        (&authority[.. i], try!(port.parse().map_err(|_| format!("Invalid port: {:?}", port))))
+      },
+    };
+  if host.is_empty() {
+    return Err(format!("Missing host: {:?}", url))
+  }
+  Ok((host.to_owned(), port, path.to_owned()))
+}
+
+fn parse_line(line: &str) -> Result<(Event, String), String> {
+  let mut words = line.split_whitespace();
+  let event = words.next().unwrap();
+  let event =
+    match EVENTS.iter().find(|&&(_, n)| n == event) {
+      None => return Err(format!("Invalid event: {:?}", event)),
+      Some(&(event, _)) => event,
+    };
+  let url = try!(words.next().ok_or_else(|| String::from("Missing URL")));
+  try!(parse_url(url));
+  match words.next() {
+    None => Ok((event, url.to_owned())),
+    Some(word) => Err(format!("Unexpected argument: {:?}", word)),
+  }
+}
+
+fn post(url: &str, body: &str) -> Result<(), String> {
+  let (host, port, path) = try!(parse_url(url));
+  let mut stream =
+    try!(std::net::TcpStream::connect((host.as_str(), port)).map_err(|err| format!("Couldn't connect: {:?}", err)));
+  let timeout = Some(std::time::Duration::from_secs(TIMEOUT_SECS));
+  try!(
+    stream.set_read_timeout(timeout)
+    .and_then(|()| stream.set_write_timeout(timeout))
+    .map_err(|err| format!("Couldn't set timeouts: {:?}", err))
+  );
+  try!(
+    write!(
+      stream,
+      concat!(
+        "POST {} HTTP/1.1\r\n",
+        "Host: {}\r\n",
+        "Content-Type: application/json\r\n",
+        "Content-Length: {}\r\n",
+        "Connection: close\r\n",
+        "\r\n",
+        "{}"
+      ),
+      path, host, body.len(), body,
+    )
+    .map_err(|err| format!("Couldn't send: {:?}", err))
+  );
+
+  let mut status = String::new();
+  try!(
+    std::io::BufReader::new(stream.by_ref()).read_line(&mut status)
+    .map_err(|err| format!("No response: {:?}", err))
+  );
+  // e.g. "HTTP/1.1 204 No Content"
+  match status.split_whitespace().nth(1) {
+    Some(code) if code.starts_with('2') => Ok(()),
+    _ => Err(format!("Refused: {:?}", status.trim())),
+  }
+}
+
+/// No webhooks at all.
+pub fn new() -> T {
+  T { urls: fnv_map::new(), sender: None, thread: None }
+}
+
+/// Load webhooks from a `line_file`, if there is one.
+pub fn load(path: &std::path::Path) -> T {
+  let mut webhooks = new();
+  {
+    let urls = &mut webhooks.urls;
+    line_file::load(path, "webhooks", |line| {
+      let (event, url) = try!(parse_line(line));
+      urls.entry(event).or_insert_with(Vec::new).push(url);
+      Ok(())
+    });
+  }
+
+  if !webhooks.urls.is_empty() {
+    let (sender, receiver) = mpsc::channel::<(String, String)>();
+    webhooks.sender = Some(sender);
+    webhooks.thread =
+      Some(std::thread::spawn(move || {
+        for (url, body) in receiver {
+          if let Err(err) = post(&url, &body) {
+            warn!("Webhook to {} failed: {}", url, err);
+          }
+        }
+      }));
+  }
+
+  webhooks
+}
+
+impl T {
+  /// Queue an event for every URL that wants it.
+  pub fn fire(&self, event: Event, fields: &[(&str, Value)]) {
+    let (urls, sender) =
+      match (self.urls.get(&event), self.sender.as_ref()) {
+        (Some(urls), Some(sender)) => (urls, sender),
+        _ => return,
+      };
+    let body = body(event, fields);
+    for url in urls {
+      if sender.send((url.clone(), body.clone())).is_err() {
+        warn!("Webhook thread is gone; dropping {} event", name(event));
+      }
+    }
+  }
+
+  /// Deliver everything that's been queued, then stop the sending thread. Later events are dropped.
+  pub fn finish(&mut self) {
+    self.sender = None;
+    if let Some(thread) = self.thread.take() {
+      if thread.join().is_err() {
+        warn!("Webhook thread panicked");
+      }
+    }
+  }
+}
+
+/// Fire a webhook event on `server`.
+pub fn fire(server: &server::T, event: Event, fields: &[(&str, Value)]) {
+  server.webhooks.lock().unwrap().fire(event, fields);
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_body() {
+    assert_eq!(body(Event::Start, &[]), "{\"event\":\"start\"}");
+    assert_eq!(
+      body(Event::Join, &[("player", Value::Int(3)), ("account", Value::Text(String::from("al \"ice\"\n")))]),
+      "{\"event\":\"join\",\"player\":3,\"account\":\"al \\\"ice\\\"\\n\"}",
+    );
+  }
+
+  #[test]
+  fn test_parse_url() {
+    assert_eq!(parse_url("http://example.com"), Ok((String::from("example.com"), 80, String::from("/"))));
+    assert_eq!(
+      parse_url("http://localhost:8080/a/b?c=d"),
+      Ok((String::from("localhost"), 8080, String::from("/a/b?c=d"))),
+    );
+    assert!(parse_url("https://example.com/").is_err());
+    assert!(parse_url("http://:80/").is_err());
+    assert!(parse_url("http://localhost:http/").is_err());
+  }
+
+  #[test]
+  fn test_parse_line() {
+    assert_eq!(parse_line("death http://a:1/x"), Ok((Event::Death, String::from("http://a:1/x"))));
+    assert!(parse_line("respawn http://a/").is_err());
+    assert!(parse_line("join").is_err());
+    assert!(parse_line("join http://a/ http://b/").is_err());
+  }
+}
@@ -1,64 +1,182 @@
 //! Grass, hilly biome
 
-use cgmath::{Point3, Vector3, InnerSpace};
-use noise::{Seed, Brownian2, Brownian3, perlin2, perlin3};
+use cgmath::{Point2, Point3, Vector3, InnerSpace};
+use std;
 
 use common::voxel;
 
+use gradient_noise;
+use gradient_noise::{Fractal, Seed};
+use heightmap;
+
+/// Frequency of the noise fields that carve out caves.
+const CAVE_FREQUENCY: f64 = 1.0 / 64.0;
+/// How close to the intersection of the cave noise surfaces a point has to be to get carved out.
+const CAVE_THICKNESS: f32 = 0.08;
+/// Scale cave density into roughly the same units as the heightmap density.
+const CAVE_DENSITY_SCALE: f32 = 64.0;
+/// An upper bound on how much the 3D features can raise the ground above the hills' heightmap:
+/// two octaves of Perlin noise (each within about [-1.04, 1.04]) with amplitudes 1 and 8, scaled by 8.
+const MAX_FEATURE_HEIGHT: f32 = 80.0;
+
+/// Columns are summarized from a grid of this many samples on a side. This should be odd, so the center is sampled.
+const COLUMN_SAMPLES: usize = 3;
+
+const HEIGHT: Fractal =
+  Fractal {
+    octaves     : 5,
+    amplitude   : 1.0,
+    frequency   : 1.0 / 4.0,
+    persistence : 2.0,
+    lacunarity  : 1.0 / 2.0,
+  };
+
+const MOUNTAINS: Fractal =
+  Fractal {
+    octaves     : 3,
+    amplitude   : 1.0,
+    frequency   : 1.0 / 16.0,
+    persistence : 4.0,
+    lacunarity  : 1.0 / 4.0,
+  };
+
+const FEATURES: Fractal =
+  Fractal {
+    octaves     : 2,
+    amplitude   : 1.0,
+    frequency   : 1.0 / 32.0,
+    persistence : 8.0,
+    lacunarity  : 1.0 / 4.0,
+  };
+
 #[allow(missing_docs)]
 pub struct T {
-  height: Brownian2<f64, fn (&Seed, &[f64; 2]) -> f64>,
-  mountains: Brownian2<f64, fn (&Seed, &[f64; 2]) -> f64>,
-  features: Brownian3<f64, fn (&Seed, &[f64; 3]) -> f64>,
-  seed: Seed,
+  noise     : gradient_noise::T,
+  height    : Fractal,
+  mountains : Fractal,
+  features  : Fractal,
 }
 
-#[allow(missing_docs)]
+/// The terrain in full detail.
 pub fn new(seed: Seed) -> T {
-  let perlin2: fn(&Seed, &[f64; 2]) -> f64 = perlin2;
-  let perlin3: fn(&Seed, &[f64; 3]) -> f64 = perlin3;
   T {
-    seed: seed,
-    height:
-      Brownian2::new(perlin2, 5)
-      .frequency(1.0 / 4.0)
-      .persistence(2.0)
-      .lacunarity(1.0 / 2.0)
-    ,
-    mountains:
-      Brownian2::new(perlin2, 3)
-      .frequency(1.0 / 16.0)
-      .persistence(4.0)
-      .lacunarity(1.0 / 4.0)
-    ,
-    features:
-      Brownian3::new(perlin3, 2)
-      .frequency(1.0 / 32.0)
-      .persistence(8.0)
-      .lacunarity(1.0 / 4.0)
-    ,
+    noise     : gradient_noise::new(seed),
+    height    : HEIGHT,
+    mountains : MOUNTAINS,
+    features  : FEATURES,
+  }
+}
+
+/// The terrain as detailed as voxels with lg_size `lg_size` can show. The noise is the same as `new`'s, less the
+/// octaves that are too fine to matter, so it's cheaper to generate big voxels from.
+pub fn at_lg_size(seed: Seed, lg_size: i16) -> T {
+  T {
+    noise     : gradient_noise::new(seed),
+    height    : HEIGHT.at_lg_size(lg_size),
+    mountains : MOUNTAINS.at_lg_size(lg_size),
+    features  : FEATURES.at_lg_size(lg_size),
   }
 }
 
 impl T {
+  /// Density of the cave field: negative inside a cave, positive elsewhere.
+  /// Caves are "worms" that run along the intersection of the zero surfaces of two 3D noise fields.
+  fn cave_density(&self, p: &Point3<f32>) -> f32 {
+    let sample = |offset: f64| {
+      self.noise.perlin3(
+        [
+          (p.x as f64) * CAVE_FREQUENCY + offset,
+          // Squash vertically, so tunnels tend to run sideways rather than straight down.
+          (p.y as f64) * CAVE_FREQUENCY * 2.0,
+          (p.z as f64) * CAVE_FREQUENCY - offset,
+        ],
+      )
+    };
+
+    let worm = (sample(0.0).abs() + sample(512.0).abs()) as f32;
+    (worm - CAVE_THICKNESS) * CAVE_DENSITY_SCALE
+  }
+
+  /// The heights of the hills and the mountains at a point.
+  fn heights(&self, x: f32, z: f32) -> (f32, f32) {
+    let height = self.noise.fractal2(&self.height, [x as f64, z as f64]);
+    let mountain_height = 16.0 * self.noise.fractal2(&self.mountains, [x as f64 - 32.0, z as f64 - 10.0]) - 32.0;
+    (height as f32, mountain_height as f32)
+  }
+
+  /// The heights of the hills and the mountains at `hills.len()` points along x, `stride` apart starting from
+  /// `(x0, z0)`. These are the same as `heights` at each point, but the noise is sampled a row at a time.
+  pub fn heights_for_row(&self, x0: f32, z0: f32, stride: f32, hills: &mut [f32], mountains: &mut [f32]) {
+    assert!(hills.len() == mountains.len());
+    let count = hills.len();
+    let xs: Vec<f64> = (0 .. count).map(|i| (x0 + i as f32 * stride) as f64).collect();
+    let mut row = vec!(0.0; count);
+
+    self.noise.fractal2_row(&self.height, &xs, z0 as f64, &mut row);
+    for (height, &h) in hills.iter_mut().zip(row.iter()) {
+      *height = h as f32;
+    }
+
+    let xs: Vec<f64> = xs.iter().map(|&x| x - 32.0).collect();
+    self.noise.fractal2_row(&self.mountains, &xs, z0 as f64 - 10.0, &mut row);
+    for (height, &h) in mountains.iter_mut().zip(row.iter()) {
+      *height = (16.0 * h - 32.0) as f32;
+    }
+  }
+
+  /// Summarize the heightmaps over the column between two corners, from a grid of samples that includes the corners
+  /// and the center.
+  pub fn column(&self, low: Point2<f32>, high: Point2<f32>) -> heightmap::Column {
+    let stride = (high - low) / (COLUMN_SAMPLES - 1) as f32;
+    let mut hills = [0.0; COLUMN_SAMPLES];
+    let mut mountains = [0.0; COLUMN_SAMPLES];
+
+    let mut min = std::f32::INFINITY;
+    let mut max = std::f32::NEG_INFINITY;
+    let (mut height, mut mountain_height) = (0.0, 0.0);
+    for row in 0 .. COLUMN_SAMPLES {
+      let z = low.y + row as f32 * stride.y;
+      self.heights_for_row(low.x, z, stride.x, &mut hills, &mut mountains);
+      for (&h, &m) in hills.iter().zip(mountains.iter()) {
+        min = f32::min(min, f32::max(h, m));
+        max = f32::max(max, f32::max(h + MAX_FEATURE_HEIGHT, m));
+      }
+      if row == COLUMN_SAMPLES / 2 {
+        height = hills[COLUMN_SAMPLES / 2];
+        mountain_height = mountains[COLUMN_SAMPLES / 2];
+      }
+    }
+
+    heightmap::Column {
+      min   : min,
+      max   : max,
+      biome :
+        if mountain_height > height {
+          heightmap::Biome::Mountains
+        } else {
+          heightmap::Biome::Hills
+        },
+    }
+  }
+
   fn mat_density(&self, p: &Point3<f32>) -> (f32, voxel::Material) {
-    let height = self.height.apply(&self.seed, &[p.x as f64, p.z as f64]);
-    let height = height as f32;
+    let (height, mountain_height) = self.heights(p.x, p.z);
     let heightmap_density = height - p.y;
-
-    let mountain_height = 16.0 * self.mountains.apply(&self.seed, &[p.x as f64 - 32.0, p.z as f64 - 10.0]) - 32.0;
-    let mountain_height = mountain_height as f32;
     let mountain_heightmap_density = mountain_height - p.y;
 
-    let feature_density = self.features.apply(&self.seed, &[p.x as f64, p.y as f64, p.z as f64]) * 8.0;
+    let feature_density = self.noise.fractal3(&self.features, [p.x as f64, p.y as f64, p.z as f64]) * 8.0;
     let feature_density = feature_density as f32;
     let d = feature_density + heightmap_density;
 
-    if mountain_heightmap_density > d {
-      (mountain_heightmap_density, voxel::Material::Stone)
-    } else {
-      (d, voxel::Material::Terrain)
-    }
+    let (d, material) =
+      if mountain_heightmap_density > d {
+        (mountain_heightmap_density, voxel::Material::Stone)
+      } else {
+        (d, voxel::Material::Terrain)
+      };
+
+    // Carve the caves out of whatever's there. Where they break the surface, we get arches and overhangs.
+    (f32::min(d, self.cave_density(p)), material)
   }
 }
 
@@ -106,3 +224,51 @@ impl voxel::mosaic::T<voxel::Material> for T {
     )
   }
 }
+
+#[cfg(test)]
+mod test {
+  use cgmath::Point3;
+
+  use common::voxel;
+
+  use gradient_noise::Seed;
+
+  use super::*;
+
+  // Like the gradient noise's own golden values, these were computed independently of this implementation; if they
+  // change, every world changes.
+
+  #[test]
+  fn test_heights() {
+    let d0 = new(Seed::new(0));
+    let d1 = new(Seed::new(1234));
+    assert_eq!(d0.heights(0.0, 0.0), (0.0, 38.77633));
+    assert_eq!(d1.heights(0.0, 0.0), (0.0, -47.089172));
+    assert_eq!(d0.heights(13.5, -7.25), (-6.4592147, 12.170248));
+    assert_eq!(d1.heights(13.5, -7.25), (10.106309, -33.36923));
+    assert_eq!(d0.heights(-200.0, 310.0), (-7.8806224, -13.277315));
+    assert_eq!(d1.heights(-200.0, 310.0), (-2.8287687, 2.4142601));
+    assert_eq!(d0.heights(1000.0, -1000.0), (-7.411258, -45.788597));
+    assert_eq!(d1.heights(1000.0, -1000.0), (-1.0762285, 52.011787));
+  }
+
+  #[test]
+  fn test_voxel_field() {
+    let mut d0 = new(Seed::new(0));
+    let mut d1 = new(Seed::new(1234));
+    let check = |d: &mut T, p: Point3<f32>, density: f32, material: voxel::Material| {
+      assert_eq!(d.mat_density(&p).0, density);
+      assert_eq!(voxel::mosaic::T::material(d, &p), Some(material));
+    };
+    check(&mut d0, Point3::new(13.5, 2.0, -7.25), 10.170248, voxel::Material::Stone);
+    check(&mut d1, Point3::new(13.5, 2.0, -7.25), 12.586816, voxel::Material::Terrain);
+    check(&mut d0, Point3::new(-200.0, -20.0, 310.0), 8.041666, voxel::Material::Terrain);
+    check(&mut d1, Point3::new(-200.0, -20.0, 310.0), 28.60215, voxel::Material::Terrain);
+    check(&mut d0, Point3::new(1000.0, -60.0, -1000.0), -1.7550509, voxel::Material::Empty);
+    check(&mut d1, Point3::new(1000.0, -60.0, -1000.0), 9.0658865, voxel::Material::Stone);
+    check(&mut d0, Point3::new(5.0, 30.0, 5.0), -3.4387875, voxel::Material::Empty);
+    check(&mut d1, Point3::new(5.0, 30.0, 5.0), -20.795769, voxel::Material::Empty);
+    check(&mut d0, Point3::new(64.0, -100.0, 32.0), 36.035763, voxel::Material::Terrain);
+    check(&mut d1, Point3::new(64.0, -100.0, 32.0), 33.338985, voxel::Material::Terrain);
+  }
+}
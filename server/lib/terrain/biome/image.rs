@@ -0,0 +1,154 @@
+//! Terrain built on a heightmap read from a grayscale image.
+
+use cgmath::{Point2, Point3, Vector3};
+use image;
+use std;
+use std::sync::Arc;
+
+use common::voxel;
+
+use heightmap;
+use heightmap::HeightMap;
+
+/// A heightmap with one sample per pixel of an image, centered on the origin at one pixel per world unit.
+/// Outside the image, the edge pixels are stretched out forever.
+pub struct ImageHeightMap {
+  width   : u32,
+  depth   : u32,
+  /// Row-major, so image rows run along x and image columns along z.
+  heights : Vec<f32>,
+}
+
+/// Read a heightmap from an image. Black is at height 0, and white is at `vertical_scale`.
+pub fn load(path: &std::path::Path, vertical_scale: f32) -> Result<ImageHeightMap, String> {
+  let image =
+    try!(image::open(path).map_err(|err| format!("Couldn't load heightmap from {:?}: {}", path, err)));
+  let image = image.to_luma();
+  let (width, depth) = image.dimensions();
+  if width == 0 || depth == 0 {
+    return Err(format!("Heightmap {:?} is empty", path))
+  }
+
+  info!("Loaded {}x{} heightmap from {:?}", width, depth, path);
+  Ok(ImageHeightMap {
+    width   : width,
+    depth   : depth,
+    heights : image.pixels().map(|p| p.data[0] as f32 / 255.0 * vertical_scale).collect(),
+  })
+}
+
+impl ImageHeightMap {
+  /// The height at a pixel, clamped into the image.
+  fn pixel(&self, x: i64, z: i64) -> f32 {
+    let x = std::cmp::max(0, std::cmp::min(self.width as i64 - 1, x)) as usize;
+    let z = std::cmp::max(0, std::cmp::min(self.depth as i64 - 1, z)) as usize;
+    self.heights[z * self.width as usize + x]
+  }
+
+  /// Convert world x and z into (fractional) pixel coordinates.
+  fn to_pixel(&self, x: f32, z: f32) -> (f32, f32) {
+    (x + self.width as f32 / 2.0, z + self.depth as f32 / 2.0)
+  }
+
+  /// Summarize the heightmap over the column between two corners.
+  pub fn column(&self, low: Point2<f32>, high: Point2<f32>) -> heightmap::Column {
+    let (low_x, low_z) = self.to_pixel(low.x, low.y);
+    let (high_x, high_z) = self.to_pixel(high.x, high.y);
+
+    // Bilinear sampling never leaves the range of the pixels around the sample, so the pixels touching the column
+    // bound it exactly.
+    let mut min = std::f32::INFINITY;
+    let mut max = std::f32::NEG_INFINITY;
+    for z in low_z.floor() as i64 .. high_z.ceil() as i64 + 1 {
+      for x in low_x.floor() as i64 .. high_x.ceil() as i64 + 1 {
+        let height = self.pixel(x, z);
+        min = f32::min(min, height);
+        max = f32::max(max, height);
+      }
+    }
+
+    heightmap::Column {
+      min   : min,
+      max   : max,
+      biome : heightmap::Biome::Hills,
+    }
+  }
+}
+
+impl HeightMap for ImageHeightMap {
+  fn height_at(&self, x: f32, z: f32) -> f32 {
+    let (x, z) = self.to_pixel(x, z);
+    let (x0, z0) = (x.floor(), z.floor());
+    let (tx, tz) = (x - x0, z - z0);
+    let (x0, z0) = (x0 as i64, z0 as i64);
+
+    let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+    lerp(
+      lerp(self.pixel(x0, z0), self.pixel(x0 + 1, z0), tx),
+      lerp(self.pixel(x0, z0 + 1), self.pixel(x0 + 1, z0 + 1), tx),
+      tz,
+    )
+  }
+}
+
+/// Solid ground up to the heightmap, and sky above it.
+pub struct T {
+  height_map: Arc<ImageHeightMap>,
+}
+
+#[allow(missing_docs)]
+pub fn new(height_map: Arc<ImageHeightMap>) -> T {
+  T {
+    height_map: height_map,
+  }
+}
+
+impl voxel::field::T for T {
+  fn density(&mut self, p: &Point3<f32>) -> f32 {
+    self.height_map.height_at(p.x, p.z) - p.y
+  }
+
+  fn normal(&mut self, p: &Point3<f32>) -> Vector3<f32> {
+    self.height_map.normal_at(p.x, p.z)
+  }
+}
+
+impl voxel::mosaic::T<voxel::Material> for T {
+  fn material(&mut self, p: &Point3<f32>) -> Option<voxel::Material> {
+    Some(
+      if voxel::field::T::density(self, p) >= 0.0 {
+        voxel::Material::Terrain
+      } else {
+        voxel::Material::Empty
+      }
+    )
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use cgmath::Point2;
+
+  use heightmap::HeightMap;
+
+  use super::*;
+
+  #[test]
+  fn test_bilinear() {
+    // 2x2, so pixel (0, 0) is at world (-1, -1).
+    let height_map =
+      ImageHeightMap {
+        width   : 2,
+        depth   : 2,
+        heights : vec!(0.0, 4.0, 8.0, 12.0),
+      };
+    assert_eq!(height_map.height_at(-1.0, -1.0), 0.0);
+    assert_eq!(height_map.height_at(-0.5, -1.0), 2.0);
+    assert_eq!(height_map.height_at(-0.5, -0.5), 6.0);
+    // Off the edge, the edge pixels carry on.
+    assert_eq!(height_map.height_at(10.0, 10.0), 12.0);
+
+    let column = height_map.column(Point2::new(-1.0, -1.0), Point2::new(-0.5, -0.5));
+    assert_eq!((column.min, column.max), (0.0, 12.0));
+  }
+}
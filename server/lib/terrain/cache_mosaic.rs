@@ -2,6 +2,7 @@ use cgmath;
 use fnv;
 use lru_cache;
 use std;
+use stopwatch;
 
 use common::voxel;
 
@@ -57,7 +58,7 @@ impl<Material> voxel::field::T for T<Material> {
     get_or_init(
       &mut self.density,
       Key(*p),
-      || voxel::field::T::density(mosaic, p),
+      || stopwatch::time("terrain.field.density", || voxel::field::T::density(mosaic, p)),
     )
   }
 
@@ -66,7 +67,7 @@ impl<Material> voxel::field::T for T<Material> {
     get_or_init(
       &mut self.normal,
       Key(*p),
-      || voxel::field::T::normal(mosaic, p),
+      || stopwatch::time("terrain.field.normal", || voxel::field::T::normal(mosaic, p)),
     )
   }
 }
@@ -77,7 +78,7 @@ impl<Material> voxel::mosaic::T<Material> for T<Material> where Material: Clone
     get_or_init(
       &mut self.mosaic_density,
       Key(*p),
-      || voxel::mosaic::T::density(mosaic, p),
+      || stopwatch::time("terrain.mosaic.density", || voxel::mosaic::T::density(mosaic, p)),
     )
   }
 
@@ -86,7 +87,7 @@ impl<Material> voxel::mosaic::T<Material> for T<Material> where Material: Clone
     get_or_init(
       &mut self.mosaic_material,
       Key(*p),
-      || voxel::mosaic::T::material(mosaic, p),
+      || stopwatch::time("terrain.mosaic.material", || voxel::mosaic::T::material(mosaic, p)),
     )
   }
 }
@@ -0,0 +1,153 @@
+//! Collapsing edited voxels back down. Brushes split voxels into their eight children to edit them, and the children
+//! stick around even once they're all the same volume (e.g. after a whole region's been dug out). Compaction turns
+//! those branches back into single voxels.
+//! The terrain generator doesn't know about edits, so voxels under a compacted one can't be regenerated from it.
+//! Compacted voxels are remembered, and missing voxels under them are copied from them instead.
+
+use collision::Aabb3;
+
+use common::fnv_map;
+use common::voxel;
+use voxel_data;
+
+/// The largest voxels that get compacted. Brushes don't generate voxels bigger than this, so nothing above it gets
+/// split by edits.
+pub const MAX_LG_SIZE: i16 = 3;
+
+#[allow(missing_docs)]
+pub struct T {
+  /// Every compacted voxel (that isn't under a bigger compacted voxel), and the volume it was compacted to.
+  pub compacted: fnv_map::T<voxel::bounds::T, voxel::T>,
+}
+
+#[allow(missing_docs)]
+pub fn new() -> T {
+  T {
+    compacted: fnv_map::new(),
+  }
+}
+
+fn children(parent: &voxel::bounds::T) -> Vec<voxel::bounds::T> {
+  let mut children = Vec::with_capacity(8);
+  for &dx in &[0, 1] {
+  for &dy in &[0, 1] {
+  for &dz in &[0, 1] {
+    children.push(voxel::bounds::new(2 * parent.x + dx, 2 * parent.y + dy, 2 * parent.z + dz, parent.lg_size - 1));
+  }}}
+  children
+}
+
+impl T {
+  /// What a voxel has to be because it's under a compacted voxel, if it is.
+  pub fn inherited(&self, bounds: &voxel::bounds::T) -> Option<voxel::T> {
+    if self.compacted.is_empty() {
+      return None
+    }
+    for lg_size in bounds.lg_size + 1 .. MAX_LG_SIZE + 1 {
+      let shift = lg_size - bounds.lg_size;
+      let ancestor = voxel::bounds::new(bounds.x >> shift, bounds.y >> shift, bounds.z >> shift, lg_size);
+      if let Some(voxel) = self.compacted.get(&ancestor) {
+        return Some(*voxel)
+      }
+    }
+    None
+  }
+
+  /// Whether a voxel is completely known, i.e. none of it would have to be generated: either it's as small as voxels
+  /// get, or it's been compacted.
+  fn is_complete(&self, bounds: &voxel::bounds::T) -> bool {
+    bounds.lg_size == 0 || self.compacted.contains_key(bounds)
+  }
+
+  /// The volume to collapse a branch into, if all its children are complete and the same volume.
+  fn uniform_children(&self, voxels: &mut voxel::tree::T, parent: &voxel::bounds::T) -> Option<voxel::T> {
+    let mut uniform = None;
+    for child in children(parent) {
+      let material =
+        match voxels.get(&child) {
+          Some(&voxel::Volume(material)) => material,
+          _ => return None,
+        };
+      match uniform {
+        None => uniform = Some(material),
+        Some(m) if m == material => {},
+        Some(_) => return None,
+      }
+      if !self.is_complete(&child) {
+        return None
+      }
+      // The child exists, so this doesn't create anything.
+      match voxels.get_mut_or_create(&child).next {
+        voxel_data::tree::Inner::Empty => {},
+        voxel_data::tree::Inner::Branches(_) => return None,
+      }
+    }
+    uniform.map(voxel::Volume)
+  }
+
+  /// Collapse every branch (up to `MAX_LG_SIZE`) within a region whose children are all the same volume, smallest
+  /// first so collapses can cascade upward. `region` is in the coordinates of the smallest voxels.
+  /// Returns how many branches were collapsed.
+  pub fn compact(&mut self, voxels: &mut voxel::tree::T, region: &Aabb3<i32>) -> usize {
+    let mut collapsed = 0;
+    for lg_size in 1 .. MAX_LG_SIZE + 1 {
+      let (low, high) = (region.min, region.max);
+      for x in low.x >> lg_size .. (high.x >> lg_size) + 1 {
+      for y in low.y >> lg_size .. (high.y >> lg_size) + 1 {
+      for z in low.z >> lg_size .. (high.z >> lg_size) + 1 {
+        let parent = voxel::bounds::new(x, y, z, lg_size);
+        let voxel =
+          match self.uniform_children(voxels, &parent) {
+            None => continue,
+            Some(voxel) => voxel,
+          };
+        {
+          let node = voxels.get_mut_or_create(&parent);
+          node.next = voxel_data::tree::Inner::Empty;
+          node.data = Some(voxel);
+        }
+        for child in children(&parent) {
+          self.compacted.remove(&child);
+        }
+        self.compacted.insert(parent, voxel);
+        collapsed += 1;
+      }}}
+    }
+    collapsed
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use cgmath::Point3;
+  use collision::Aabb3;
+
+  use common::voxel;
+
+  use super::*;
+
+  #[test]
+  fn test_compact_cascades() {
+    let mut voxels = voxel::tree::new();
+    let empty = voxel::Volume(voxel::Material::Empty);
+    for x in 0 .. 4 {
+    for y in 0 .. 4 {
+    for z in 0 .. 4 {
+      // One corner of the block is still solid.
+      let voxel = if (x, y, z) == (3, 3, 3) { voxel::Volume(voxel::Material::Stone) } else { empty };
+      voxels.get_mut_or_create(&voxel::bounds::new(x, y, z, 0)).data = Some(voxel);
+    }}}
+
+    let mut compaction = new();
+    let region = Aabb3::new(Point3::new(0, 0, 0), Point3::new(4, 4, 4));
+    assert_eq!(compaction.compact(&mut voxels, &region), 7);
+    assert_eq!(compaction.inherited(&voxel::bounds::new(1, 0, 1, 0)), Some(empty));
+    assert_eq!(compaction.inherited(&voxel::bounds::new(3, 3, 3, 0)), None);
+
+    voxels.get_mut_or_create(&voxel::bounds::new(3, 3, 3, 0)).data = Some(empty);
+    // The uncollapsed branch collapses, and so does its parent now.
+    assert_eq!(compaction.compact(&mut voxels, &region), 2);
+    assert_eq!(compaction.compacted.len(), 1);
+    assert_eq!(compaction.inherited(&voxel::bounds::new(3, 3, 3, 0)), Some(empty));
+  }
+}
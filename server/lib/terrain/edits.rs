@@ -0,0 +1,133 @@
+//! Which blocks of terrain have been edited. Everything else can be regenerated, so this is what has to be kept when
+//! the generator changes. Blocks are the size of the largest voxels that get compacted, so each one is a single voxel
+//! at `compaction::MAX_LG_SIZE`.
+
+use cgmath::{Point3, Vector3};
+use collision::Aabb3;
+
+use common::fnv_set;
+use common::voxel;
+
+use compaction;
+
+/// The lg_size of a block, in voxels.
+pub const LG_BLOCK_WIDTH: i16 = compaction::MAX_LG_SIZE;
+
+#[allow(missing_docs)]
+pub struct T {
+  /// Every edited block, as a voxel at `LG_BLOCK_WIDTH`.
+  pub blocks: fnv_set::T<voxel::bounds::T>,
+  /// How many edits have been recorded. Something that looked at the terrain earlier can compare this to tell whether
+  /// it's been edited since.
+  generation: u64,
+}
+
+#[allow(missing_docs)]
+pub fn new() -> T {
+  T {
+    blocks     : fnv_set::new(),
+    generation : 0,
+  }
+}
+
+impl T {
+  /// Record an edit to a region, in the coordinates of the smallest voxels.
+  pub fn record(&mut self, region: &Aabb3<i32>) {
+    self.record_blocks(blocks_in(region));
+  }
+
+  /// Record an edit to some blocks.
+  pub fn record_blocks<Blocks>(&mut self, blocks: Blocks) where
+    Blocks: IntoIterator<Item=voxel::bounds::T>,
+  {
+    self.blocks.extend(blocks);
+    self.generation += 1;
+  }
+
+  #[allow(missing_docs)]
+  pub fn generation(&self) -> u64 {
+    self.generation
+  }
+}
+
+/// Every block a region touches. The region is in the coordinates of the smallest voxels, and includes its max corner.
+pub fn blocks_in(region: &Aabb3<i32>) -> Vec<voxel::bounds::T> {
+  let (low, high) = (region.min, region.max);
+  let mut blocks = Vec::new();
+  for x in low.x >> LG_BLOCK_WIDTH .. (high.x >> LG_BLOCK_WIDTH) + 1 {
+  for y in low.y >> LG_BLOCK_WIDTH .. (high.y >> LG_BLOCK_WIDTH) + 1 {
+  for z in low.z >> LG_BLOCK_WIDTH .. (high.z >> LG_BLOCK_WIDTH) + 1 {
+    blocks.push(voxel::bounds::new(x, y, z, LG_BLOCK_WIDTH));
+  }}}
+  blocks
+}
+
+/// The blocks a voxel is in. Voxels bigger than a block cover several.
+pub fn blocks_of(voxel: &voxel::bounds::T) -> Vec<voxel::bounds::T> {
+  if voxel.lg_size <= LG_BLOCK_WIDTH {
+    let shift = LG_BLOCK_WIDTH - voxel.lg_size;
+    return vec!(voxel::bounds::new(voxel.x >> shift, voxel.y >> shift, voxel.z >> shift, LG_BLOCK_WIDTH))
+  }
+  let low = Point3::new(voxel.x << voxel.lg_size, voxel.y << voxel.lg_size, voxel.z << voxel.lg_size);
+  let width = (1 << voxel.lg_size) - 1;
+  blocks_in(&Aabb3::new(low, low + Vector3::new(width, width, width)))
+}
+
+/// The region a block covers, in the coordinates of the smallest voxels. The max corner is just outside it.
+pub fn region(block: &voxel::bounds::T) -> Aabb3<i32> {
+  assert!(block.lg_size == LG_BLOCK_WIDTH);
+  let low = Point3::new(block.x << LG_BLOCK_WIDTH, block.y << LG_BLOCK_WIDTH, block.z << LG_BLOCK_WIDTH);
+  let width = 1 << LG_BLOCK_WIDTH;
+  Aabb3::new(low, low + Vector3::new(width, width, width))
+}
+
+/// Every voxel in a block that could have been edited, from the smallest up to the block itself.
+pub fn voxels_in(block: &voxel::bounds::T) -> Vec<voxel::bounds::T> {
+  assert!(block.lg_size == LG_BLOCK_WIDTH);
+  let mut voxels = Vec::new();
+  for lg_size in 0 .. LG_BLOCK_WIDTH + 1 {
+    let shift = LG_BLOCK_WIDTH - lg_size;
+    let width = 1 << shift;
+    for x in 0 .. width {
+    for y in 0 .. width {
+    for z in 0 .. width {
+      voxels.push(voxel::bounds::new((block.x << shift) + x, (block.y << shift) + y, (block.z << shift) + z, lg_size));
+    }}}
+  }
+  voxels
+}
+
+#[cfg(test)]
+mod test {
+  use cgmath::Point3;
+  use collision::Aabb3;
+
+  use common::voxel;
+
+  use super::*;
+
+  #[test]
+  fn test_record() {
+    let mut edits = new();
+    edits.record(&Aabb3::new(Point3::new(-1, 0, 7), Point3::new(3, 2, 9)));
+    let mut blocks: Vec<_> = edits.blocks.iter().map(|b| (b.x, b.y, b.z)).collect();
+    blocks.sort();
+    assert_eq!(blocks, vec!((-1, 0, 0), (-1, 0, 1), (0, 0, 0), (0, 0, 1)));
+
+    let voxels = voxels_in(&voxel::bounds::new(-1, 0, 1, LG_BLOCK_WIDTH));
+    assert_eq!(voxels.len(), 512 + 64 + 8 + 1);
+    assert!(voxels.contains(&voxel::bounds::new(-8, 7, 15, 0)));
+    assert!(!voxels.contains(&voxel::bounds::new(0, 0, 8, 0)));
+  }
+
+  #[test]
+  fn test_blocks_of() {
+    assert_eq!(blocks_of(&voxel::bounds::new(-1, 9, 7, 0)), vec!(voxel::bounds::new(-1, 1, 0, LG_BLOCK_WIDTH)));
+    assert_eq!(blocks_of(&voxel::bounds::new(1, 0, 0, 2)), vec!(voxel::bounds::new(0, 0, 0, LG_BLOCK_WIDTH)));
+    let big = blocks_of(&voxel::bounds::new(-1, 0, 0, LG_BLOCK_WIDTH + 1));
+    assert_eq!(big.len(), 8);
+    assert!(big.contains(&voxel::bounds::new(-2, 1, 1, LG_BLOCK_WIDTH)));
+    let region = region(&voxel::bounds::new(-1, 0, 1, LG_BLOCK_WIDTH));
+    assert_eq!((region.min, region.max), (Point3::new(-8, 0, 8), Point3::new(0, 8, 16)));
+  }
+}
@@ -0,0 +1,350 @@
+//! Gradient noise that gives bit-identical results on every platform.
+//! Everything is driven by integer hashing and a fixed permutation table, so the only floating-point
+//! operations are basic arithmetic and `floor`, which IEEE 754 pins down exactly.
+
+/// Seed for world generation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Seed(u32);
+
+impl Seed {
+  #[allow(missing_docs)]
+  pub fn new(seed: u32) -> Seed {
+    Seed(seed)
+  }
+
+  #[allow(missing_docs)]
+  pub fn to_u32(self) -> u32 {
+    self.0
+  }
+}
+
+const GRADIENTS_2: [[f64; 2]; 8] = [
+  [ 1.0,  0.0], [-1.0,  0.0], [ 0.0,  1.0], [ 0.0, -1.0],
+  [ 1.0,  1.0], [-1.0,  1.0], [ 1.0, -1.0], [-1.0, -1.0],
+];
+
+const GRADIENTS_3: [[f64; 3]; 12] = [
+  [ 1.0,  1.0,  0.0], [-1.0,  1.0,  0.0], [ 1.0, -1.0,  0.0], [-1.0, -1.0,  0.0],
+  [ 1.0,  0.0,  1.0], [-1.0,  0.0,  1.0], [ 1.0,  0.0, -1.0], [-1.0,  0.0, -1.0],
+  [ 0.0,  1.0,  1.0], [ 0.0, -1.0,  1.0], [ 0.0,  1.0, -1.0], [ 0.0, -1.0, -1.0],
+];
+
+/// How many points the row functions work on at once. Each step of the noise is done for a whole batch before the
+/// next, so the arithmetic can be vectorized; only the permutation lookups are done a point at a time.
+const BATCH: usize = 8;
+
+/// Octaves whose wavelength is less than this many voxels across are left out of coarse voxels; they'd be aliased
+/// away anyway.
+const MIN_WAVELENGTH_IN_VOXELS: f64 = 2.0;
+
+/// Parameters for summing several octaves of noise.
+#[derive(Debug, Clone, Copy)]
+pub struct Fractal {
+  #[allow(missing_docs)]
+  pub octaves     : u32,
+  /// Amplitude of the first octave.
+  pub amplitude   : f64,
+  /// Frequency of the first octave.
+  pub frequency   : f64,
+  /// Amplitude multiplier from each octave to the next.
+  pub persistence : f64,
+  /// Frequency multiplier from each octave to the next.
+  pub lacunarity  : f64,
+}
+
+impl Fractal {
+  /// The same noise, without the octaves that are too fine to show up in voxels with lg_size `lg_size`. Octaves that
+  /// are kept are exactly as they were, so voxels fine enough to need every octave get identical noise.
+  /// At least one octave is always kept.
+  pub fn at_lg_size(&self, lg_size: i16) -> Fractal {
+    let min_wavelength = MIN_WAVELENGTH_IN_VOXELS * (2.0 as f64).powi(lg_size as i32);
+    let mut coarse: Option<Fractal> = None;
+    let mut octave = Fractal { octaves: 1, ..*self };
+    let mut coarsest = octave;
+    for _ in 0 .. self.octaves {
+      // Wavelengths only go one way, so the octaves that are kept are all in a row.
+      if 1.0 / octave.frequency >= min_wavelength {
+        coarse = Some(match coarse { None => octave, Some(c) => Fractal { octaves: c.octaves + 1, ..c } });
+      }
+      if octave.frequency < coarsest.frequency {
+        coarsest = octave;
+      }
+      octave.amplitude *= self.persistence;
+      octave.frequency *= self.lacunarity;
+    }
+    // If even the coarsest octave is too fine, keep it anyway rather than flatten everything.
+    coarse.unwrap_or(coarsest)
+  }
+}
+
+/// A seeded noise generator.
+pub struct T {
+  perm: [u8; 256],
+}
+
+fn hash(mut x: u32) -> u32 {
+  x = (x ^ 61) ^ (x >> 16);
+  x = x.wrapping_mul(9);
+  x = x ^ (x >> 4);
+  x = x.wrapping_mul(0x27d4eb2d);
+  x = x ^ (x >> 15);
+  x
+}
+
+#[allow(missing_docs)]
+pub fn new(seed: Seed) -> T {
+  let mut perm = [0; 256];
+  for (i, p) in perm.iter_mut().enumerate() {
+    *p = i as u8;
+  }
+
+  // Fisher-Yates shuffle, driven by hashing the seed.
+  let mut state = seed.0;
+  for i in (1 .. 256).rev() {
+    state = hash(state.wrapping_add(i as u32));
+    let j = (state % (i as u32 + 1)) as usize;
+    perm.swap(i, j);
+  }
+
+  T {
+    perm: perm,
+  }
+}
+
+fn fade(t: f64) -> f64 {
+  t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+fn lerp(t: f64, a: f64, b: f64) -> f64 {
+  a + t * (b - a)
+}
+
+impl T {
+  fn perm(&self, i: i32) -> i32 {
+    self.perm[(i & 255) as usize] as i32
+  }
+
+  fn hash2(&self, x: i32, y: i32) -> usize {
+    self.perm(x + self.perm(y)) as usize
+  }
+
+  fn hash3(&self, x: i32, y: i32, z: i32) -> usize {
+    self.perm(x + self.perm(y + self.perm(z))) as usize
+  }
+
+  /// 2D Perlin noise, roughly in [-1, 1].
+  pub fn perlin2(&self, p: [f64; 2]) -> f64 {
+    let x0 = p[0].floor();
+    let y0 = p[1].floor();
+    let (xi, yi) = (x0 as i32, y0 as i32);
+    let (xf, yf) = (p[0] - x0, p[1] - y0);
+
+    let corner = |dx: i32, dy: i32| {
+      let g = GRADIENTS_2[self.hash2(xi + dx, yi + dy) % 8];
+      g[0] * (xf - dx as f64) + g[1] * (yf - dy as f64)
+    };
+
+    let u = fade(xf);
+    let v = fade(yf);
+    lerp(
+      v,
+      lerp(u, corner(0, 0), corner(1, 0)),
+      lerp(u, corner(0, 1), corner(1, 1)),
+    )
+  }
+
+  /// `perlin2` at up to `BATCH` points along a row, `[x, y]` for each `x` in `xs`.
+  fn perlin2_batch(&self, xs: &[f64], y: f64, out: &mut [f64]) {
+    let n = xs.len();
+    assert!(n <= BATCH && out.len() == n);
+
+    let y0 = y.floor();
+    let yi = y0 as i32;
+    let yf = y - y0;
+    let v = fade(yf);
+
+    let mut xi = [0; BATCH];
+    let mut xf = [0.0; BATCH];
+    let mut u = [0.0; BATCH];
+    for i in 0 .. n {
+      let x0 = xs[i].floor();
+      xi[i] = x0 as i32;
+      xf[i] = xs[i] - x0;
+      u[i] = fade(xf[i]);
+    }
+
+    let mut gradients = [[0; 4]; BATCH];
+    for i in 0 .. n {
+      gradients[i] = [
+        self.hash2(xi[i], yi) % 8,
+        self.hash2(xi[i] + 1, yi) % 8,
+        self.hash2(xi[i], yi + 1) % 8,
+        self.hash2(xi[i] + 1, yi + 1) % 8,
+      ];
+    }
+
+    for i in 0 .. n {
+      let corner = |k: usize, dx: f64, dy: f64| {
+        let g = GRADIENTS_2[gradients[i][k]];
+        g[0] * (xf[i] - dx) + g[1] * (yf - dy)
+      };
+      out[i] =
+        lerp(
+          v,
+          lerp(u[i], corner(0, 0.0, 0.0), corner(1, 1.0, 0.0)),
+          lerp(u[i], corner(2, 0.0, 1.0), corner(3, 1.0, 1.0)),
+        );
+    }
+  }
+
+  /// 3D Perlin noise, roughly in [-1, 1].
+  pub fn perlin3(&self, p: [f64; 3]) -> f64 {
+    let x0 = p[0].floor();
+    let y0 = p[1].floor();
+    let z0 = p[2].floor();
+    let (xi, yi, zi) = (x0 as i32, y0 as i32, z0 as i32);
+    let (xf, yf, zf) = (p[0] - x0, p[1] - y0, p[2] - z0);
+
+    let corner = |dx: i32, dy: i32, dz: i32| {
+      let g = GRADIENTS_3[self.hash3(xi + dx, yi + dy, zi + dz) % 12];
+      g[0] * (xf - dx as f64) + g[1] * (yf - dy as f64) + g[2] * (zf - dz as f64)
+    };
+
+    let u = fade(xf);
+    let v = fade(yf);
+    let w = fade(zf);
+    lerp(
+      w,
+      lerp(
+        v,
+        lerp(u, corner(0, 0, 0), corner(1, 0, 0)),
+        lerp(u, corner(0, 1, 0), corner(1, 1, 0)),
+      ),
+      lerp(
+        v,
+        lerp(u, corner(0, 0, 1), corner(1, 0, 1)),
+        lerp(u, corner(0, 1, 1), corner(1, 1, 1)),
+      ),
+    )
+  }
+
+  /// Sum several octaves of 2D Perlin noise.
+  pub fn fractal2(&self, fractal: &Fractal, p: [f64; 2]) -> f64 {
+    let mut frequency = fractal.frequency;
+    let mut amplitude = fractal.amplitude;
+    let mut r = 0.0;
+    for _ in 0 .. fractal.octaves {
+      r += amplitude * self.perlin2([p[0] * frequency, p[1] * frequency]);
+      amplitude *= fractal.persistence;
+      frequency *= fractal.lacunarity;
+    }
+    r
+  }
+
+  /// `fractal2` at every point along a row, `[x, y]` for each `x` in `xs`. The results are exactly the same, but
+  /// the points are sampled a batch at a time.
+  pub fn fractal2_row(&self, fractal: &Fractal, xs: &[f64], y: f64, out: &mut [f64]) {
+    assert!(xs.len() == out.len());
+    for r in out.iter_mut() {
+      *r = 0.0;
+    }
+
+    let mut scaled = [0.0; BATCH];
+    let mut noise = [0.0; BATCH];
+    let mut frequency = fractal.frequency;
+    let mut amplitude = fractal.amplitude;
+    for _ in 0 .. fractal.octaves {
+      for (xs, out) in xs.chunks(BATCH).zip(out.chunks_mut(BATCH)) {
+        let n = xs.len();
+        for i in 0 .. n {
+          scaled[i] = xs[i] * frequency;
+        }
+        self.perlin2_batch(&scaled[.. n], y * frequency, &mut noise[.. n]);
+        for i in 0 .. n {
+          out[i] += amplitude * noise[i];
+        }
+      }
+      amplitude *= fractal.persistence;
+      frequency *= fractal.lacunarity;
+    }
+  }
+
+  /// Sum several octaves of 3D Perlin noise.
+  pub fn fractal3(&self, fractal: &Fractal, p: [f64; 3]) -> f64 {
+    let mut frequency = fractal.frequency;
+    let mut amplitude = fractal.amplitude;
+    let mut r = 0.0;
+    for _ in 0 .. fractal.octaves {
+      r += amplitude * self.perlin3([p[0] * frequency, p[1] * frequency, p[2] * frequency]);
+      amplitude *= fractal.persistence;
+      frequency *= fractal.lacunarity;
+    }
+    r
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  // These were computed independently of this implementation; if they change, every world changes.
+
+  #[test]
+  fn test_permutation() {
+    assert_eq!(&new(Seed::new(0)).perm[.. 8], &[143, 109, 233, 158, 23, 188, 133, 116]);
+    assert_eq!(&new(Seed::new(1234)).perm[.. 8], &[208, 95, 203, 199, 3, 217, 77, 235]);
+  }
+
+  #[test]
+  fn test_perlin2() {
+    let n0 = new(Seed::new(0));
+    let n1 = new(Seed::new(1234));
+    assert_eq!(n0.perlin2([0.5, 0.5]), -0.375);
+    assert_eq!(n1.perlin2([0.5, 0.5]), -0.375);
+    assert_eq!(n0.perlin2([-3.25, 7.75]), -0.3634786605834961);
+    assert_eq!(n1.perlin2([-3.25, 7.75]), 0.3472480773925781);
+    assert_eq!(n0.perlin2([100.1, -42.9]), 0.2058601075199947);
+    assert_eq!(n1.perlin2([100.1, -42.9]), -0.0016313990400068546);
+  }
+
+  #[test]
+  fn test_fractal2_row() {
+    let noise = new(Seed::new(1234));
+    let fractal = Fractal { octaves: 5, amplitude: 1.0, frequency: 1.0 / 4.0, persistence: 2.0, lacunarity: 1.0 / 2.0 };
+    // More than one batch, and a partial one.
+    let xs: Vec<f64> = (0 .. 2 * BATCH + 3).map(|i| -17.3 + 2.25 * i as f64).collect();
+    let mut row = vec!(0.0; xs.len());
+    noise.fractal2_row(&fractal, &xs, 42.7, &mut row);
+    for (&x, &r) in xs.iter().zip(row.iter()) {
+      assert_eq!(r, noise.fractal2(&fractal, [x, 42.7]));
+    }
+  }
+
+  #[test]
+  fn test_at_lg_size() {
+    // Wavelengths 4, 8, 16, 32 and 64.
+    let fractal = Fractal { octaves: 5, amplitude: 1.0, frequency: 1.0 / 4.0, persistence: 2.0, lacunarity: 1.0 / 2.0 };
+    let summary = |f: Fractal| (f.octaves, f.amplitude, f.frequency);
+    assert_eq!(summary(fractal.at_lg_size(0)), (5, 1.0, 1.0 / 4.0));
+    assert_eq!(summary(fractal.at_lg_size(1)), (5, 1.0, 1.0 / 4.0));
+    assert_eq!(summary(fractal.at_lg_size(2)), (4, 2.0, 1.0 / 8.0));
+    assert_eq!(summary(fractal.at_lg_size(4)), (2, 8.0, 1.0 / 32.0));
+    assert_eq!(summary(fractal.at_lg_size(10)), (1, 16.0, 1.0 / 64.0));
+
+    // Octaves that get finer instead.
+    let fractal = Fractal { lacunarity: 2.0, persistence: 0.5, frequency: 1.0 / 64.0, ..fractal };
+    assert_eq!(summary(fractal.at_lg_size(3)), (3, 1.0, 1.0 / 64.0));
+  }
+
+  #[test]
+  fn test_perlin3() {
+    let n0 = new(Seed::new(0));
+    let n1 = new(Seed::new(1234));
+    assert_eq!(n0.perlin3([0.5, 0.5, 0.5]), -0.25);
+    assert_eq!(n1.perlin3([0.5, 0.5, 0.5]), 0.125);
+    assert_eq!(n0.perlin3([-3.25, 7.75, 1.125]), -0.2783765353669878);
+    assert_eq!(n1.perlin3([-3.25, 7.75, 1.125]), 0.19332816690439358);
+    assert_eq!(n0.perlin3([100.1, -42.9, 13.3]), 0.167589699500849);
+    assert_eq!(n1.perlin3([100.1, -42.9, 13.3]), 0.4216455310304283);
+  }
+}
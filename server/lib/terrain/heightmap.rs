@@ -0,0 +1,227 @@
+//! Heightmaps, and a pre-pass over the terrain's heightmaps. Each column of voxels is summarized by how high and low its ground can
+//! be, so voxels that are clearly in the sky can be classified without sampling any 3D noise.
+//! Columns are cached, and can be saved to and loaded from a compact sidecar file, so the classification survives
+//! server restarts even when the voxels themselves aren't saved.
+
+use cgmath::{Point3, Vector3, InnerSpace};
+use std;
+use std::io::{Read, Write};
+
+use common::fnv_map;
+use common::voxel;
+
+use gradient_noise::Seed;
+
+/// Identifies a sidecar file (and the version of its format).
+const MAGIC: &'static [u8; 4] = b"phm1";
+/// x, z, lg_size, min, max, biome.
+const RECORD_BYTES: usize = 4 + 4 + 2 + 4 + 4 + 1;
+
+/// The surface of the ground, as a height at each point.
+pub trait HeightMap {
+  /// The height of the ground at a point.
+  fn height_at(&self, x: f32, z: f32) -> f32;
+
+  /// The point on the ground directly above or below a point.
+  fn point_at(&self, x: f32, z: f32) -> Point3<f32> {
+    Point3::new(x, self.height_at(x, z), z)
+  }
+
+  /// The upward-facing normal of the ground at a point, from its slope.
+  fn normal_at(&self, x: f32, z: f32) -> Vector3<f32> {
+    let delta = 0.5;
+    let dx = self.height_at(x + delta, z) - self.height_at(x - delta, z);
+    let dz = self.height_at(x, z + delta) - self.height_at(x, z - delta);
+    Vector3::new(-dx, 2.0 * delta, -dz).normalize()
+  }
+}
+
+/// Which heightmap is on top in a column.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(missing_docs)]
+pub enum Biome {
+  Hills,
+  Mountains,
+}
+
+/// A summary of the ground in a column of voxels.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Column {
+  /// The lowest the heightmaps get in this column, before 3D features or caves are added.
+  pub min   : f32,
+  /// Nothing in this column above this height is solid.
+  pub max   : f32,
+  #[allow(missing_docs)]
+  pub biome : Biome,
+}
+
+/// Identifies a column of voxels: the x and z of its voxels, and their lg_size.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct Key {
+  x       : i32,
+  z       : i32,
+  lg_size : i16,
+}
+
+fn key(bounds: &voxel::bounds::T) -> Key {
+  Key {
+    x       : bounds.x,
+    z       : bounds.z,
+    lg_size : bounds.lg_size,
+  }
+}
+
+#[allow(missing_docs)]
+pub struct T {
+  columns : fnv_map::T<Key, Column>,
+  /// Whether any columns have been added since this was last loaded or saved.
+  dirty   : bool,
+}
+
+#[allow(missing_docs)]
+pub fn new() -> T {
+  T {
+    columns : fnv_map::new(),
+    dirty   : false,
+  }
+}
+
+fn put_u32(bytes: &mut Vec<u8>, x: u32) {
+  for i in 0 .. 4 {
+    bytes.push((x >> (8 * i)) as u8);
+  }
+}
+
+fn get_u32(bytes: &[u8]) -> u32 {
+  (0 .. 4).fold(0, |x, i| x | ((bytes[i] as u32) << (8 * i)))
+}
+
+impl T {
+  #[allow(missing_docs)]
+  pub fn len(&self) -> usize {
+    self.columns.len()
+  }
+
+  /// The column containing a voxel, summarizing it with `summarize` if it hasn't been already.
+  pub fn column<Summarize>(&mut self, bounds: &voxel::bounds::T, summarize: Summarize) -> Column where
+    Summarize: FnOnce() -> Column,
+  {
+    let key = key(bounds);
+    if let Some(column) = self.columns.get(&key) {
+      return *column
+    }
+
+    let column = summarize();
+    self.columns.insert(key, column);
+    self.dirty = true;
+    column
+  }
+
+  /// Write the columns out to a sidecar file for terrain generated from `seed`, if there's anything new to write.
+  pub fn save(&mut self, path: &std::path::Path, seed: Seed) -> std::io::Result<()> {
+    if !self.dirty {
+      return Ok(())
+    }
+
+    let mut bytes = Vec::with_capacity(MAGIC.len() + 4 + self.columns.len() * RECORD_BYTES);
+    bytes.extend_from_slice(MAGIC);
+    put_u32(&mut bytes, seed.to_u32());
+    for (key, column) in &self.columns {
+      put_u32(&mut bytes, key.x as u32);
+      put_u32(&mut bytes, key.z as u32);
+      bytes.push(key.lg_size as u16 as u8);
+      bytes.push((key.lg_size as u16 >> 8) as u8);
+      put_u32(&mut bytes, column.min.to_bits());
+      put_u32(&mut bytes, column.max.to_bits());
+      bytes.push(
+        match column.biome {
+          Biome::Hills => 0,
+          Biome::Mountains => 1,
+        }
+      );
+    }
+
+    let mut file = try!(std::fs::File::create(path));
+    try!(file.write_all(&bytes));
+    self.dirty = false;
+    Ok(())
+  }
+}
+
+/// Load columns from a sidecar file. Files that are missing, corrupt or for a different seed are ignored.
+pub fn load(path: &std::path::Path, seed: Seed) -> T {
+  let mut heightmap = new();
+
+  let mut bytes = Vec::new();
+  if let Err(err) = std::fs::File::open(path).and_then(|mut file| file.read_to_end(&mut bytes)) {
+    info!("Not loading heightmap cache from {:?}: {:?}", path, err);
+    return heightmap
+  }
+
+  let header = MAGIC.len() + 4;
+  if bytes.len() < header || &bytes[.. MAGIC.len()] != MAGIC || (bytes.len() - header) % RECORD_BYTES != 0 {
+    warn!("Ignoring malformed heightmap cache {:?}", path);
+    return heightmap
+  }
+  if get_u32(&bytes[MAGIC.len() ..]) != seed.to_u32() {
+    info!("Ignoring heightmap cache {:?} from another seed", path);
+    return heightmap
+  }
+
+  for record in bytes[header ..].chunks(RECORD_BYTES) {
+    let key =
+      Key {
+        x       : get_u32(&record[0 ..]) as i32,
+        z       : get_u32(&record[4 ..]) as i32,
+        lg_size : (record[8] as u16 | (record[9] as u16) << 8) as i16,
+      };
+    let biome =
+      match record[18] {
+        0 => Biome::Hills,
+        1 => Biome::Mountains,
+        b => {
+          warn!("Ignoring heightmap cache {:?} with unknown biome {}", path, b);
+          return new()
+        },
+      };
+    let column =
+      Column {
+        min   : f32::from_bits(get_u32(&record[10 ..])),
+        max   : f32::from_bits(get_u32(&record[14 ..])),
+        biome : biome,
+      };
+    heightmap.columns.insert(key, column);
+  }
+
+  info!("Loaded {} heightmap columns from {:?}", heightmap.columns.len(), path);
+  heightmap
+}
+
+#[cfg(test)]
+mod test {
+  use std;
+
+  use common::voxel;
+
+  use gradient_noise::Seed;
+
+  use super::*;
+
+  #[test]
+  fn test_save_load() {
+    let path = std::env::temp_dir().join("playform_heightmap_test");
+    let column = Column { min: -3.5, max: 100.25, biome: Biome::Mountains };
+
+    let mut heightmap = new();
+    heightmap.column(&voxel::bounds::new(-7, 3, 12, -2), || column);
+    heightmap.save(&path, Seed::new(5)).unwrap();
+
+    let mut loaded = load(&path, Seed::new(5));
+    assert_eq!(loaded.len(), 1);
+    // Other voxels in the same column share its summary.
+    assert_eq!(loaded.column(&voxel::bounds::new(-7, -40, 12, -2), || panic!("not cached")), column);
+    assert_eq!(load(&path, Seed::new(6)).len(), 0);
+
+    std::fs::remove_file(&path).unwrap();
+  }
+}
@@ -7,6 +7,8 @@ extern crate cgmath;
 extern crate collision;
 extern crate common;
 extern crate fnv;
+extern crate image;
+#[macro_use]
 extern crate log;
 extern crate lru_cache;
 extern crate noise;
@@ -18,72 +20,457 @@ extern crate num;
 
 mod cache_mosaic;
 
+pub mod gradient_noise;
+
 pub mod biome;
+pub mod compaction;
+pub mod edits;
+pub mod heightmap;
+pub mod reinforcement;
+pub mod rock;
 pub mod tree;
+pub mod world;
 
-pub use noise::Seed;
+pub use gradient_noise::Seed;
 
-use std::sync::Mutex;
+use cgmath::Point2;
+use collision::Aabb3;
+use std::sync::{Arc, Mutex};
 
+use common::{fnv_map, fnv_set};
 use common::voxel;
 
+/// Voxels with lg_size up to this get terrain generated with only as much detail as they can show. Bigger voxels get
+/// the same detail as these.
+const MAX_DETAIL_LG_SIZE: i16 = compaction::MAX_LG_SIZE;
+
 /// This struct contains and lazily generates the world's terrain.
 #[allow(missing_docs)]
 pub struct T {
-  pub mosaic: Mutex<cache_mosaic::T<voxel::Material>>,
+  /// The terrain generator for voxels of each lg_size from 0 to `MAX_DETAIL_LG_SIZE`. Voxels smaller than that use the
+  /// first, which has full detail.
+  pub mosaics: Vec<Mutex<cache_mosaic::T<voxel::Material>>>,
   pub voxels: Mutex<voxel::tree::T>,
+  /// Voxels that edits have collapsed. This is always locked after `voxels`.
+  pub compaction: Mutex<compaction::T>,
+  /// Blocks that have been edited. This is always locked after `compaction`.
+  pub edits: Mutex<edits::T>,
+  /// Extra blast resistance. This is always locked after `edits`.
+  pub reinforcement: Mutex<reinforcement::T>,
+  pub heightmap: Mutex<heightmap::T>,
+  seed: Seed,
+  // The same terrain as `mosaic`, for summarizing its heightmaps.
+  surface: Surface,
+}
+
+/// The surface the heightmap pre-pass summarizes.
+enum Surface {
+  Noise(biome::demo::T),
+  Image(Arc<biome::image::ImageHeightMap>),
+}
+
+impl Surface {
+  fn column(&self, low: Point2<f32>, high: Point2<f32>) -> heightmap::Column {
+    match self {
+      &Surface::Noise(ref demo) => demo.column(low, high),
+      &Surface::Image(ref height_map) => height_map.column(low, high),
+    }
+  }
 }
 
 impl T {
   #[allow(missing_docs)]
   pub fn new(terrain_seed: Seed) -> T {
     T {
-      mosaic: Mutex::new(cache_mosaic::new(Box::new(biome::demo::new(terrain_seed)))),
+      mosaics:
+        (0 .. MAX_DETAIL_LG_SIZE + 1)
+        .map(|lg_size| Mutex::new(cache_mosaic::new(Box::new(biome::demo::at_lg_size(terrain_seed, lg_size)))))
+        .collect(),
       voxels: Mutex::new(voxel::tree::new()),
+      compaction: Mutex::new(compaction::new()),
+      edits: Mutex::new(edits::new()),
+      reinforcement: Mutex::new(reinforcement::new()),
+      heightmap: Mutex::new(heightmap::new()),
+      seed: terrain_seed,
+      surface: Surface::Noise(biome::demo::new(terrain_seed)),
+    }
+  }
+
+  /// Set up the terrain a world is configured with. This fails if an image heightmap can't be loaded.
+  pub fn of_config(config: &world::WorldConfig) -> Result<T, String> {
+    match config.base {
+      world::Base::Noise => Ok(T::new(config.seed)),
+      world::Base::Image { ref path, vertical_scale } => {
+        let height_map = Arc::new(try!(biome::image::load(path, vertical_scale)));
+        Ok(T {
+          // Image heightmaps don't have any detail to leave out.
+          mosaics: vec!(Mutex::new(cache_mosaic::new(Box::new(biome::image::new(height_map.clone()))))),
+          voxels: Mutex::new(voxel::tree::new()),
+          compaction: Mutex::new(compaction::new()),
+          edits: Mutex::new(edits::new()),
+          reinforcement: Mutex::new(reinforcement::new()),
+          heightmap: Mutex::new(heightmap::new()),
+          seed: config.seed,
+          surface: Surface::Image(height_map),
+        })
+      },
+    }
+  }
+
+  /// Load cached heightmap columns from a sidecar file, in place of any there are now.
+  pub fn load_heightmap(&self, path: &std::path::Path) {
+    // The sidecar is only tagged with the seed, so it can't tell images apart. Image columns are cheap to summarize
+    // anyway.
+    if let Surface::Image(_) = self.surface {
+      return
+    }
+    *self.heightmap.lock().unwrap() = heightmap::load(path, self.seed);
+  }
+
+  /// Save the heightmap columns summarized so far to a sidecar file.
+  pub fn save_heightmap(&self, path: &std::path::Path) -> std::io::Result<()> {
+    if let Surface::Image(_) = self.surface {
+      return Ok(())
+    }
+    self.heightmap.lock().unwrap().save(path, self.seed)
+  }
+
+  /// Whether a voxel is definitely all sky, according to the heightmap pre-pass.
+  fn is_above_ground(&self, bounds: &voxel::bounds::T) -> bool {
+    let (low, high) = bounds.corners();
+    let column =
+      self.heightmap.lock().unwrap().column(bounds, || {
+        self.surface.column(Point2::new(low.x, low.z), Point2::new(high.x, high.z))
+      });
+    low.y > column.max
+  }
+
+  /// Generate a voxel from the terrain generator for its size.
+  fn generate_block(&self, bounds: &voxel::bounds::T) -> voxel::T {
+    let detail = std::cmp::max(0, std::cmp::min(bounds.lg_size, MAX_DETAIL_LG_SIZE)) as usize;
+    let detail = std::cmp::min(detail, self.mosaics.len() - 1);
+    let mut mosaic = self.mosaics[detail].lock().unwrap();
+    voxel::unwrap(voxel::of_field(&mut *mosaic, bounds))
+  }
+
+  /// What a voxel that isn't stored would be: whatever it inherits from a compacted voxel, or else what's generated.
+  /// `cancelled` is checked between the heightmap pre-pass and generating the voxel; if it's set by then, this gives up
+  /// and returns None.
+  fn unstored<Cancelled>(
+    &self,
+    bounds: &voxel::bounds::T,
+    inherited: Option<voxel::T>,
+    cancelled: &Cancelled,
+  ) -> Option<voxel::T> where
+    Cancelled: Fn() -> bool,
+  {
+    if let Some(voxel) = inherited {
+      return Some(voxel)
+    }
+    if self.is_above_ground(bounds) {
+      return Some(voxel::Volume(voxel::Material::Empty))
+    }
+    if cancelled() {
+      return None
     }
+    Some(stopwatch::time("terrain.generate", || self.generate_block(bounds)))
+  }
+
+  /// Look up a voxel if it's stored, or find out what it would be if it isn't, without storing it.
+  fn peek(&self, bounds: &voxel::bounds::T) -> voxel::T {
+    let inherited = {
+      let voxels = self.voxels.lock().unwrap();
+      if let Some(data) = voxels.get(bounds) {
+        return *data
+      }
+      self.compaction.lock().unwrap().inherited(bounds)
+    };
+    self.unstored(bounds, inherited, &|| false).unwrap()
   }
 
   /// Load the block of terrain at a given position.
-  // TODO: Allow this to be performed in such a way that self is only briefly locked.
+  /// The voxels are only locked to look the block up and to store it, not while it's generated, so generating doesn't
+  /// hold up edits or other loads. Concurrent loads of the same block can both generate it; the first one stored wins.
+  /// If an edit compacts the block away while it's generating, what it inherits has changed, so it's generated again.
   pub fn load(&self, bounds: &voxel::bounds::T) -> voxel::T {
-    let mut voxels = self.voxels.lock().unwrap();
-    let node = voxels.get_mut_or_create(bounds);
-    match node.data {
-      None => {
-        let mut mosaic = self.mosaic.lock().unwrap();
-        let voxel = voxel::unwrap(voxel::of_field(&mut *mosaic, bounds));
-        let r = voxel;
-        node.data = Some(voxel);
-        r
-      },
-      Some(data) => {
-        data
-      },
+    self.load_unless(bounds, &|| false).unwrap()
+  }
+
+  /// Like `load`, but give up if `cancelled` is set by the time the block's looked up, between the steps of generating
+  /// it, or before it's stored. A block that's given up on isn't stored, and None is returned instead.
+  pub fn load_unless<Cancelled>(&self, bounds: &voxel::bounds::T, cancelled: &Cancelled) -> Option<voxel::T> where
+    Cancelled: Fn() -> bool,
+  {
+    loop {
+      if cancelled() {
+        return None
+      }
+      let (inherited, generation) = {
+        let voxels = self.voxels.lock().unwrap();
+        if let Some(data) = voxels.get(bounds) {
+          return Some(*data)
+        }
+        let inherited = self.compaction.lock().unwrap().inherited(bounds);
+        (inherited, self.edits.lock().unwrap().generation())
+      };
+
+      let voxel =
+        match self.unstored(bounds, inherited, cancelled) {
+          None => return None,
+          Some(voxel) => voxel,
+        };
+
+      let mut voxels = self.voxels.lock().unwrap();
+      if let Some(data) = voxels.get(bounds) {
+        // It was loaded, or edited, while this was generating.
+        return Some(*data)
+      }
+      if cancelled() {
+        return None
+      }
+      {
+        let compaction = self.compaction.lock().unwrap();
+        let edited = self.edits.lock().unwrap().generation() != generation;
+        if edited && compaction.inherited(bounds) != inherited {
+          debug!("{:?} was compacted while it was generating", bounds);
+          continue
+        }
+      }
+      voxels.get_mut_or_create(bounds).data = Some(voxel);
+      return Some(voxel)
     }
   }
 
-  /// Apply a voxel brush to the terrain.
+  /// Extract the smallest voxels in a region, whether or not they've been loaded, in x, then y, then z order (z varies
+  /// fastest). The region includes its min corner but not its max. Nothing's stored for the voxels that aren't
+  /// already, so copying unedited terrain doesn't bloat the tree.
+  pub fn extract(&self, region: &Aabb3<i32>) -> Vec<voxel::T> {
+    let size = region.max - region.min;
+    let mut voxels = Vec::with_capacity(std::cmp::max(0, size.x * size.y * size.z) as usize);
+    for x in region.min.x .. region.max.x {
+    for y in region.min.y .. region.max.y {
+    for z in region.min.z .. region.max.z {
+      voxels.push(self.peek(&voxel::bounds::new(x, y, z, 0)));
+    }}}
+    voxels
+  }
+
+  /// Apply a voxel brush to the terrain, then compact what it touched and record that it's been edited.
+  /// `voxel_changed` is only called for voxels that actually changed (or weren't loaded before). Returns the blocks
+  /// (as in `edits`) that had voxels change; nothing else needs remeshing or resending.
   pub fn brush<VoxelChanged, Mosaic>(
     &self,
     brush: &mut voxel::brush::T<Mosaic>,
     mut voxel_changed: VoxelChanged,
-  ) where
+  ) -> Vec<voxel::bounds::T> where
     VoxelChanged: FnMut(&voxel::T, &voxel::bounds::T),
     Mosaic: voxel::mosaic::T<voxel::Material>,
   {
     let mut voxels = self.voxels.lock().unwrap();
-    voxels.brush(
-      brush,
-      // TODO: Put a max size on this
-      &mut |bounds| {
-        if bounds.lg_size > 3 {
-          None
-        } else {
-          let mut mosaic = self.mosaic.lock().unwrap();
-          Some(voxel::unwrap(voxel::of_field(&mut *mosaic, bounds)))
+    let mut compaction = self.compaction.lock().unwrap();
+
+    // The brush reports every voxel it visits, so remember what was there to tell which ones it really changed.
+    let mut before = fnv_map::new();
+    for block in edits::blocks_in(&brush.bounds) {
+      for bounds in edits::voxels_in(&block) {
+        if let Some(voxel) = voxels.get(&bounds) {
+          before.insert(bounds, *voxel);
+        }
+      }
+    }
+
+    let mut dirty = fnv_set::new();
+    {
+      let compaction = &*compaction;
+      voxels.brush(
+        brush,
+        // TODO: Put a max size on this
+        &mut |bounds| {
+          if bounds.lg_size > compaction::MAX_LG_SIZE {
+            None
+          } else if let Some(voxel) = compaction.inherited(bounds) {
+            Some(voxel)
+          } else {
+            Some(self.generate_block(bounds))
+          }
+        },
+        &mut |voxel: &voxel::T, bounds: &voxel::bounds::T| {
+          if before.get(bounds) == Some(voxel) {
+            return
+          }
+          dirty.extend(edits::blocks_of(bounds));
+          voxel_changed(voxel, bounds);
+        },
+      );
+    }
+    if dirty.is_empty() {
+      return Vec::new()
+    }
+
+    let collapsed = compaction.compact(&mut voxels, &brush.bounds);
+    if collapsed > 0 {
+      debug!("Compacted {} voxels", collapsed);
+    }
+    self.edits.lock().unwrap().record_blocks(dirty.iter().cloned());
+    dirty.into_iter().collect()
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use fnv::FnvHasher;
+  use std;
+  use std::hash::Hasher;
+  use std::io::{Read, Write};
+
+  use cgmath::Point3;
+  use collision::Aabb3;
+
+  use common::voxel;
+
+  use super::*;
+
+  /// Golden block hashes, one `seed x y z lg_size hash` per line, checked in next to this crate's manifest.
+  /// Set `PLAYFORM_BLESS=1` to regenerate this after an intentional change to terrain generation.
+  const GOLDEN_PATH: &'static str = concat!(env!("CARGO_MANIFEST_DIR"), "/golden_hashes.txt");
+
+  const SEEDS: [u32; 3] = [0, 1, 1234];
+
+  // A spread of blocks above, below and around the surface, at a few different LODs.
+  fn blocks() -> Vec<voxel::bounds::T> {
+    let mut blocks = Vec::new();
+    for &lg_size in &[0, 2, 4] {
+      for &y in &[-4, 0, 4, 16] {
+        for &(x, z) in &[(0, 0), (3, -5), (-17, 8), (64, 64)] {
+          blocks.push(voxel::bounds::new(x, y >> lg_size, z, lg_size));
         }
+      }
+    }
+    blocks
+  }
+
+  fn push_u32(bytes: &mut Vec<u8>, x: u32) {
+    for i in 0 .. 4 {
+      bytes.push((x >> (8 * i)) as u8);
+    }
+  }
+
+  fn push_f32s(bytes: &mut Vec<u8>, xs: &[f32]) {
+    for x in xs {
+      push_u32(bytes, x.to_bits());
+    }
+  }
+
+  /// A byte encoding of a voxel that only changes when the voxel does, unlike its `Debug` output (which changes with
+  /// field names and float formatting).
+  fn encode(bounds: &voxel::bounds::T, voxel: &voxel::T) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    match *voxel {
+      voxel::Volume(material) => {
+        bytes.push(0);
+        bytes.push(material as u8);
       },
-      &mut voxel_changed,
-    );
+      voxel::Surface(ref vertex) => {
+        bytes.push(1);
+        bytes.push(vertex.corner as u8);
+        let position = vertex.surface_vertex.to_world_vertex(bounds);
+        let normal = vertex.normal.to_float_normal();
+        push_f32s(&mut bytes, &[position.x, position.y, position.z, normal.x, normal.y, normal.z]);
+      },
+    }
+    bytes
+  }
+
+  fn hash(bounds: &voxel::bounds::T, voxel: &voxel::T) -> u64 {
+    let mut hasher = FnvHasher::default();
+    hasher.write(&encode(bounds, voxel));
+    hasher.finish()
+  }
+
+  fn hashes() -> Vec<String> {
+    let mut lines = Vec::new();
+    for &seed in &SEEDS {
+      let terrain = T::new(Seed::new(seed));
+      for bounds in blocks() {
+        lines.push(
+          format!(
+            "{} {} {} {} {} {:016x}",
+            seed, bounds.x, bounds.y, bounds.z, bounds.lg_size, hash(&bounds, &terrain.load(&bounds)),
+          )
+        );
+      }
+    }
+    lines
+  }
+
+  #[test]
+  // The same seed should always give the same terrain, regardless of load order.
+  fn test_repeatable() {
+    let bounds = blocks();
+    let forward = T::new(Seed::new(7));
+    let backward = T::new(Seed::new(7));
+    let forward: Vec<u64> = bounds.iter().map(|b| hash(b, &forward.load(b))).collect();
+    let mut backward: Vec<u64> = bounds.iter().rev().map(|b| hash(b, &backward.load(b))).collect();
+    backward.reverse();
+    assert_eq!(forward, backward);
+  }
+
+  #[test]
+  // Extracting sees stored voxels, generates the rest, and doesn't store what it generates.
+  fn test_extract() {
+    let terrain = T::new(Seed::new(7));
+    let stored = voxel::bounds::new(0, -1, 0, 0);
+    let unstored = voxel::bounds::new(0, -1, 1, 0);
+    terrain.voxels.lock().unwrap().get_mut_or_create(&stored).data = Some(voxel::Volume(voxel::Material::Marble));
+
+    let region = Aabb3::new(Point3::new(0, -1, 0), Point3::new(1, 0, 2));
+    let extracted = terrain.extract(&region);
+    assert!(terrain.voxels.lock().unwrap().get(&unstored).is_none());
+    assert_eq!(extracted, vec!(voxel::Volume(voxel::Material::Marble), terrain.load(&unstored)));
+  }
+
+  #[test]
+  // A cancelled load doesn't store anything, and the block still loads normally afterwards.
+  fn test_cancelled_load() {
+    let terrain = T::new(Seed::new(7));
+    let bounds = voxel::bounds::new(0, -1, 0, 0);
+    assert_eq!(terrain.load_unless(&bounds, &|| true), None);
+    assert!(terrain.voxels.lock().unwrap().get(&bounds).is_none());
+
+    // Cancelled partway through, after the block's been looked up.
+    let checks = std::cell::Cell::new(0);
+    let cancelled = || { checks.set(checks.get() + 1); checks.get() > 1 };
+    assert_eq!(terrain.load_unless(&bounds, &cancelled), None);
+    assert!(terrain.voxels.lock().unwrap().get(&bounds).is_none());
+
+    let voxel = terrain.load(&bounds);
+    assert_eq!(terrain.voxels.lock().unwrap().get(&bounds), Some(&voxel));
+  }
+
+  #[test]
+  // Catch accidental world-breaking changes to terrain generation.
+  fn test_golden_hashes() {
+    let actual = hashes();
+
+    if std::env::var("PLAYFORM_BLESS").is_ok() {
+      println!("Recording terrain hashes in {}", GOLDEN_PATH);
+      let mut file = std::fs::File::create(GOLDEN_PATH).unwrap();
+      for line in &actual {
+        writeln!(file, "{}", line).unwrap();
+      }
+      return
+    }
+
+    // A missing file is a failure, not a fresh start: otherwise a checkout without it would quietly pass.
+    let mut golden = String::new();
+    std::fs::File::open(GOLDEN_PATH)
+      .and_then(|mut file| file.read_to_string(&mut golden))
+      .unwrap_or_else(|err| panic!("Couldn't read {}: {:?}; run with PLAYFORM_BLESS=1 to record it", GOLDEN_PATH, err));
+
+    let golden: Vec<&str> = golden.lines().collect();
+    assert_eq!(golden.len(), actual.len(), "wrong number of hashes in {}", GOLDEN_PATH);
+    for (expected, actual) in golden.iter().zip(actual.iter()) {
+      assert_eq!(expected, actual, "terrain changed; rerun with PLAYFORM_BLESS=1 if this was intentional");
+    }
   }
 }
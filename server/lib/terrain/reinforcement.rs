@@ -0,0 +1,65 @@
+//! Reinforcement: extra blast resistance given to places in the terrain, whatever they're made of. It's kept per
+//! voxel at lg_size 0, so it stays put when what's there is dug out or built over.
+
+use common::fnv_map;
+use common::voxel;
+
+/// The most times a voxel can be reinforced.
+pub const MAX_LEVEL: u8 = 3;
+/// How much blast resistance each level of reinforcement adds.
+pub const RESISTANCE_PER_LEVEL: f32 = 4.0;
+
+#[allow(missing_docs)]
+pub struct T {
+  /// How many times each reinforced voxel has been reinforced.
+  pub levels: fnv_map::T<voxel::bounds::T, u8>,
+}
+
+#[allow(missing_docs)]
+pub fn new() -> T {
+  T {
+    levels: fnv_map::new(),
+  }
+}
+
+impl T {
+  /// Reinforce some voxels at lg_size 0 one more level, up to `MAX_LEVEL`. Returns how many got stronger.
+  pub fn reinforce(&mut self, voxels: &[voxel::bounds::T]) -> usize {
+    let mut strengthened = 0;
+    for bounds in voxels {
+      assert!(bounds.lg_size == 0);
+      let level = self.levels.entry(*bounds).or_insert(0);
+      if *level < MAX_LEVEL {
+        *level += 1;
+        strengthened += 1;
+      }
+    }
+    strengthened
+  }
+
+  /// The blast resistance a voxel at lg_size 0 has on top of its material's.
+  pub fn resistance(&self, bounds: &voxel::bounds::T) -> f32 {
+    self.levels.get(bounds).map(|&level| level as f32 * RESISTANCE_PER_LEVEL).unwrap_or(0.0)
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use common::voxel;
+
+  use super::*;
+
+  #[test]
+  fn test_reinforce() {
+    let mut reinforcement = new();
+    let a = voxel::bounds::new(1, 2, 3, 0);
+    let b = voxel::bounds::new(-1, 2, 3, 0);
+    assert_eq!(reinforcement.reinforce(&[a]), 1);
+    for _ in 0 .. MAX_LEVEL {
+      reinforcement.reinforce(&[a, b]);
+    }
+    assert_eq!(reinforcement.reinforce(&[a, b]), 0);
+    assert_eq!(reinforcement.resistance(&a), MAX_LEVEL as f32 * RESISTANCE_PER_LEVEL);
+    assert_eq!(reinforcement.resistance(&voxel::bounds::new(0, 2, 3, 0)), 0.0);
+  }
+}
@@ -0,0 +1,75 @@
+//! A rock is a lumpy boulder of stone: a few overlapping spheres, jittered around its center.
+
+use cgmath::{Point3, Vector3, EuclideanSpace};
+use rand;
+use voxel_data;
+use voxel_data::field;
+use voxel_data::mosaic;
+
+use common::voxel;
+
+/// The fewest and most spheres a rock is made of.
+const MIN_LUMPS: u32 = 3;
+const MAX_LUMPS: u32 = 6;
+
+#[allow(missing_docs)]
+pub struct T {
+  union: voxel_data::mosaic::union::T<voxel::Material>,
+}
+
+/// Make a rock about `radius` wide, centered at the origin.
+/// Everything in it is within `reach(radius)` of the origin along each axis.
+pub fn new<Rng>(rng: &mut Rng, radius: f32) -> T
+  where Rng: rand::Rng,
+{
+  let mut union = mosaic::union::new();
+
+  // The first lump is centered, so the rock is never hollow in the middle.
+  let mut center = Point3::new(0.0, 0.0, 0.0);
+  for _ in 0 .. rng.gen_range(MIN_LUMPS, MAX_LUMPS + 1) {
+    let lump_radius = radius * rng.gen_range(0.5, 1.0);
+    union.push(
+      voxel::Material::Stone,
+      field::translation::T {
+        translation: center.to_vec(),
+        field: field::sphere::T {
+          radius: lump_radius,
+        },
+      },
+    );
+
+    let jitter = radius / 2.0;
+    center =
+      Point3::new(
+        rng.gen_range(-jitter, jitter),
+        // Flatter than it is wide, so it sits on the ground.
+        rng.gen_range(-jitter, jitter) / 2.0,
+        rng.gen_range(-jitter, jitter),
+      );
+  }
+
+  T {
+    union: union,
+  }
+}
+
+/// How far from its center a rock of some radius can reach.
+pub fn reach(radius: f32) -> f32 {
+  1.5 * radius
+}
+
+impl field::T for T {
+  fn density(&mut self, p: &Point3<f32>) -> f32 {
+    field::T::density(&mut self.union, p)
+  }
+
+  fn normal(&mut self, p: &Point3<f32>) -> Vector3<f32> {
+    field::T::normal(&mut self.union, p)
+  }
+}
+
+impl mosaic::T<voxel::Material> for T {
+  fn material(&mut self, p: &Point3<f32>) -> Option<voxel::Material> {
+    mosaic::T::material(&mut self.union, p)
+  }
+}
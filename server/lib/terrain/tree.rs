@@ -1,5 +1,6 @@
 //! A tree is comprised of a cylindrical trunk, a spherical bunch of leaves, and a spherical
-//! rounding to the bottom of the trunk.
+//! rounding to the bottom of the trunk. The trunk can extend below its base as roots, so a tree
+//! planted on uneven ground doesn't float.
 
 use cgmath::{Point3, Vector3, Basis3, Rotation, EuclideanSpace, InnerSpace};
 use rand;
@@ -57,18 +58,19 @@ fn inside_sphere<Rng>(
   }
 }
 
-#[allow(missing_docs)]
+/// Make a tree whose base is at the origin. The trunk extends `root_depth` below the base.
 pub fn new<Rng>(
   rng: &mut Rng,
   trunk_height: f32,
   trunk_radius: f32,
   leaf_radius: f32,
+  root_depth: f32,
 ) -> T
   where Rng: rand::Rng,
 {
   let trunk_top = Point3::new(0.0, trunk_height, 0.0);
   let leaf_center = trunk_top + Vector3::new(0.0, leaf_radius / 2.0, 0.0);
-  let trunk_center = Point3::new(0.0, trunk_height / 2.0, 0.0);
+  let trunk_center = Point3::new(0.0, (trunk_height - root_depth) / 2.0, 0.0);
 
   let mut union = mosaic::union::new();
 
@@ -80,7 +82,7 @@ pub fn new<Rng>(
           radius: trunk_radius,
         },
         field::sphere::T {
-          radius: trunk_height / 2.0,
+          radius: (trunk_height + root_depth) / 2.0,
         },
       ),
     };
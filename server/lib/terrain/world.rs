@@ -0,0 +1,37 @@
+//! Settings for what terrain a world is generated with.
+
+use std;
+
+use gradient_noise::Seed;
+
+/// How tall white is in an image heightmap, if it isn't specified.
+pub const DEFAULT_VERTICAL_SCALE: f32 = 64.0;
+
+/// What the terrain is built on.
+#[derive(Debug, Clone)]
+pub enum Base {
+  /// Hills, mountains and caves from noise.
+  Noise,
+  /// A grayscale image, where black is at height 0 and white is at `vertical_scale`.
+  Image {
+    #[allow(missing_docs)]
+    path           : std::path::PathBuf,
+    #[allow(missing_docs)]
+    vertical_scale : f32,
+  },
+}
+
+#[allow(missing_docs)]
+#[derive(Debug, Clone)]
+pub struct WorldConfig {
+  pub seed : Seed,
+  pub base : Base,
+}
+
+/// Noise terrain from seed 0.
+pub fn default() -> WorldConfig {
+  WorldConfig {
+    seed : Seed::new(0),
+    base : Base::Noise,
+  }
+}
@@ -14,6 +14,7 @@ extern crate dummy_client_lib;
 extern crate server_lib;
 
 use std::borrow::Borrow;
+use std::collections::VecDeque;
 use std::sync::Mutex;
 
 fn main() {
@@ -23,17 +24,22 @@ fn main() {
   let server_url = String::from("ipc:///tmp/server.ipc");
 
   let quit_signal = Mutex::new(false);
+  // There's no server console here.
+  let commands = Mutex::new(VecDeque::new());
+  let world = server_lib::world::default();
 
   unsafe {
     let server_thread =
       thread_scoped::scoped(|| {
-        server_lib::run(server_url.borrow(), &quit_signal);
+        let distances = server_lib::server::default_distances();
+        let autosave = Some(server_lib::autosave::default_config());
+        server_lib::run(server_url.borrow(), &world, distances, None, autosave, &commands, &quit_signal);
       });
 
     #[cfg(feature = "dummy-client")]
     dummy_client_lib::run(listen_url.borrow(), server_url.borrow());
     #[cfg(not(feature = "dummy-client"))]
-    client_lib::run(listen_url.borrow(), server_url.borrow());
+    client_lib::run(listen_url.borrow(), server_url.borrow(), &client_lib::run::default_options());
     *quit_signal.lock().unwrap() = true;
     server_thread.join();
 